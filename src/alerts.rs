@@ -0,0 +1,103 @@
+use crate::config::EventsConfig;
+use crate::events::{Event, NicSummary};
+use crate::model::NicName;
+use std::collections::{HashMap, VecDeque};
+
+/// Turns per-cycle traffic and switch counts into the conditions worth telling a person
+/// about: NICs overloaded for longer than `overload_secs`, and switch storms. Each is
+/// reported when it starts and when it ends.
+pub struct Alerts {
+    overload_secs: u64,
+    storm_switches: usize,
+    storm_window_secs: u64,
+    /// NIC → since when it is above its estimate, and whether that was reported.
+    overloaded: HashMap<NicName, (u64, bool)>,
+    /// When each switch in the storm window was made.
+    switches: VecDeque<u64>,
+    /// The switch total at the last cycle.
+    last_total: u64,
+    storm: bool,
+}
+
+impl Alerts {
+    pub fn new(config: &EventsConfig) -> Self {
+        Self {
+            overload_secs: config.overload_secs,
+            storm_switches: config.storm_switches,
+            storm_window_secs: config.storm_window_secs,
+            overloaded: HashMap::new(),
+            switches: VecDeque::new(),
+            last_total: 0,
+            storm: false,
+        }
+    }
+
+    /// Overloads that became sustained or ended with this cycle's `summaries`.
+    pub fn overloads(&mut self, now: u64, summaries: &[NicSummary]) -> Vec<Event> {
+        let mut events = Vec::new();
+        for summary in summaries {
+            let traffic_bps = summary.tx_bps + summary.rx_bps;
+            let over = summary.tcp_bandwidth_bps > 0.0 && traffic_bps > summary.tcp_bandwidth_bps;
+            let event = |since, sustained| Event::Overload {
+                timestamp: now,
+                nic: summary.nic.clone(),
+                wan: summary.wan.clone(),
+                since,
+                sustained,
+                tcp_bandwidth_bps: summary.tcp_bandwidth_bps,
+                traffic_bps,
+            };
+            if !over {
+                if let Some((since, true)) = self.overloaded.remove(&summary.nic) {
+                    events.push(event(since, false));
+                }
+                continue;
+            }
+            let (since, reported) = self
+                .overloaded
+                .entry(summary.nic.clone())
+                .or_insert((now, false));
+            if !*reported && now.saturating_sub(*since) >= self.overload_secs {
+                *reported = true;
+                events.push(event(*since, true));
+            }
+        }
+        events
+    }
+
+    /// A switch storm starting or ending, given the switches made so far (`total`). A
+    /// storm ends once the window holds fewer than half of `storm_switches`; 0 disables.
+    pub fn switches(&mut self, now: u64, total: u64) -> Option<Event> {
+        if self.storm_switches == 0 {
+            return None;
+        }
+        for _ in self.last_total..total {
+            self.switches.push_back(now);
+        }
+        self.last_total = total;
+        while self
+            .switches
+            .front()
+            .is_some_and(|at| now.saturating_sub(*at) >= self.storm_window_secs)
+        {
+            self.switches.pop_front();
+        }
+
+        let count = self.switches.len();
+        let active = if self.storm {
+            count * 2 >= self.storm_switches
+        } else {
+            count >= self.storm_switches
+        };
+        if active == self.storm {
+            return None;
+        }
+        self.storm = active;
+        Some(Event::SwitchStorm {
+            timestamp: now,
+            switches: count,
+            window_secs: self.storm_window_secs,
+            active,
+        })
+    }
+}
@@ -0,0 +1,200 @@
+use crate::config::AnomalyConfig;
+use crate::error::ConfigError;
+use crate::history_db::StoredProfile;
+use crate::model::{ClientIp, WanId};
+use crate::policy::{PolicyInput, SwitchDecision};
+use std::collections::{HashMap, VecDeque};
+
+/// Spread assumed for a baseline flatter than this, so a client that was idle at a steady
+/// rate is not flagged for the first few Mbps it moves.
+const MIN_STDDEV_BPS: f64 = 1_000_000.0;
+
+/// A client flagged as anomalous this cycle, or cleared.
+#[derive(Debug, Clone)]
+pub struct AnomalyChange {
+    pub ip: ClientIp,
+    pub anomalous: bool,
+    pub traffic_bps: f64,
+    /// Mean of the client's baseline.
+    pub baseline_bps: f64,
+    pub z_score: f64,
+}
+
+#[derive(Debug, Default)]
+pub struct AnomalyPlan {
+    /// Client IP → quarantine WAN it must stay on; other moves of these clients are dropped.
+    pub quarantined: HashMap<ClientIp, WanId>,
+    /// Moves of quarantined clients that are not on the quarantine WAN yet.
+    pub switches: Vec<SwitchDecision>,
+    pub changes: Vec<AnomalyChange>,
+}
+
+/// One client's recent cycles.
+#[derive(Debug)]
+struct Baseline {
+    first_seen: u64,
+    /// `(timestamp, traffic_bps)` within the window, oldest first.
+    samples: VecDeque<(u64, f64)>,
+    anomalous: bool,
+}
+
+/// Flags clients whose traffic is far above their own rolling baseline, by z-score, since
+/// a single runaway client is usually what saturates a WAN. Every cycle joins the baseline,
+/// so a lasting change of habit becomes the new normal within the window.
+pub struct AnomalyDetector {
+    config: AnomalyConfig,
+    baselines: HashMap<ClientIp, Baseline>,
+}
+
+impl AnomalyDetector {
+    pub fn new(config: AnomalyConfig) -> Result<Self, ConfigError> {
+        if config.window_secs == 0 || config.z_score <= 0.0 {
+            return Err(ConfigError::Invalid(
+                "[anomaly] needs a positive window_secs and z_score".to_string(),
+            ));
+        }
+        if config.min_baseline_secs > config.window_secs {
+            return Err(ConfigError::Invalid(
+                "anomaly.min_baseline_secs cannot exceed window_secs".to_string(),
+            ));
+        }
+        Ok(Self {
+            config,
+            baselines: HashMap::new(),
+        })
+    }
+
+    pub fn export(&self) -> Vec<StoredProfile> {
+        self.baselines
+            .iter()
+            .map(|(ip, baseline)| StoredProfile {
+                ip: ip.to_string(),
+                first_seen: baseline.first_seen,
+                samples: baseline.samples.iter().copied().collect(),
+                anomalous: baseline.anomalous,
+            })
+            .collect()
+    }
+
+    /// Takes back the baselines learned before a restart, without the samples that fell out
+    /// of the window by `now`.
+    pub fn restore(&mut self, rows: Vec<StoredProfile>, now: u64) {
+        for row in rows {
+            let Ok(ip) = row.ip.parse() else {
+                continue;
+            };
+            let samples: VecDeque<(u64, f64)> = row
+                .samples
+                .into_iter()
+                .filter(|(at, _)| at + self.config.window_secs > now)
+                .collect();
+            if samples.is_empty() {
+                continue;
+            }
+            self.baselines.insert(
+                ip,
+                Baseline {
+                    first_seen: row.first_seen,
+                    samples,
+                    anomalous: row.anomalous,
+                },
+            );
+        }
+    }
+
+    /// Judges this cycle's traffic against each client's baseline, then adds it.
+    pub fn plan(&mut self, input: &PolicyInput, now: u64) -> AnomalyPlan {
+        let traffic: HashMap<ClientIp, (f64, f64)> = input
+            .ip_traffic
+            .iter()
+            .map(|traffic| (traffic.ip, (traffic.rx_bps, traffic.tx_bps)))
+            .collect();
+        // Clients that went away start over when they come back
+        self.baselines
+            .retain(|ip, _| input.mappings.contains_key(ip));
+
+        let mut clients: Vec<ClientIp> = input.mappings.keys().copied().collect();
+        clients.sort();
+        let mut plan = AnomalyPlan::default();
+        for ip in clients {
+            let (rx_bps, tx_bps) = traffic.get(&ip).copied().unwrap_or_default();
+            let bps = rx_bps + tx_bps;
+            let baseline = self.baselines.entry(ip).or_insert_with(|| Baseline {
+                first_seen: now,
+                samples: VecDeque::new(),
+                anomalous: false,
+            });
+            while baseline
+                .samples
+                .front()
+                .is_some_and(|(at, _)| at + self.config.window_secs <= now)
+            {
+                baseline.samples.pop_front();
+            }
+
+            let count = baseline.samples.len() as f64;
+            let (mean, z_score) = if count == 0.0 {
+                (0.0, 0.0)
+            } else {
+                let mean = baseline.samples.iter().map(|(_, bps)| bps).sum::<f64>() / count;
+                let variance = baseline
+                    .samples
+                    .iter()
+                    .map(|(_, bps)| (bps - mean).powi(2))
+                    .sum::<f64>()
+                    / count;
+                (mean, (bps - mean) / variance.sqrt().max(MIN_STDDEV_BPS))
+            };
+            baseline.samples.push_back((now, bps));
+
+            let was_anomalous = baseline.anomalous;
+            baseline.anomalous = if was_anomalous {
+                z_score >= self.config.z_score / 2.0
+            } else {
+                now.saturating_sub(baseline.first_seen) >= self.config.min_baseline_secs
+                    && bps >= self.config.min_mbps * 1_000_000.0
+                    && z_score >= self.config.z_score
+            };
+            if baseline.anomalous != was_anomalous {
+                plan.changes.push(AnomalyChange {
+                    ip,
+                    anomalous: baseline.anomalous,
+                    traffic_bps: bps,
+                    baseline_bps: mean,
+                    z_score,
+                });
+            }
+
+            let Some(quarantine_wan) = self
+                .config
+                .quarantine_wan
+                .as_ref()
+                .filter(|_| baseline.anomalous)
+            else {
+                continue;
+            };
+            plan.quarantined.insert(ip, quarantine_wan.clone());
+            let Some(current_wan) = input.mappings.get(&ip) else {
+                continue;
+            };
+            if current_wan == quarantine_wan || !input.wan_to_nic.contains_key(quarantine_wan) {
+                continue;
+            }
+            let Some(from_nic) = input.wan_to_nic.get(current_wan) else {
+                continue;
+            };
+            plan.switches.push(SwitchDecision {
+                ip,
+                from_nic: from_nic.clone(),
+                target_wan: quarantine_wan.clone(),
+                rx_bps,
+                reason: format!(
+                    "anomalous traffic ({:.2} Mbps against a baseline of {:.2} Mbps); quarantined",
+                    bps / 1_000_000.0,
+                    mean / 1_000_000.0
+                ),
+            });
+        }
+        plan
+    }
+}
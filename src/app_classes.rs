@@ -0,0 +1,89 @@
+use crate::config::{AppClassConfig, AppClassesConfig};
+use crate::error::ConfigError;
+use crate::model::ClientIp;
+use crate::prometheus::PrometheusResult;
+use std::collections::HashMap;
+
+/// Sorts each client's per-port traffic into the configured application classes, so rules
+/// can act on what the traffic is ("backups always via wan1") rather than how fast it is.
+pub struct AppClassifier {
+    config: AppClassesConfig,
+}
+
+impl AppClassifier {
+    pub fn new(config: AppClassesConfig) -> Result<Self, ConfigError> {
+        let mut names: Vec<&str> = Vec::new();
+        for class in &config.classes {
+            if class.ports.is_empty() {
+                return Err(ConfigError::Invalid(format!(
+                    "Application class {} needs at least one port",
+                    class.name
+                )));
+            }
+            if names.contains(&class.name.as_str()) {
+                return Err(ConfigError::Invalid(format!(
+                    "Application class {} is defined twice",
+                    class.name
+                )));
+            }
+            names.push(&class.name);
+        }
+        Ok(Self { config })
+    }
+
+    /// Query for the per-port rates.
+    pub fn query(&self) -> String {
+        self.config.rx_metric.clone()
+    }
+
+    /// Classes per client with at least `min_bps` on their ports, busiest first.
+    pub fn classify(&self, results: &[PrometheusResult]) -> HashMap<ClientIp, Vec<String>> {
+        let mut rates: HashMap<ClientIp, HashMap<&str, f64>> = HashMap::new();
+        for result in results {
+            let (Some(ip), Some(port), Ok(rx_bps)) = (
+                result.label::<ClientIp>(&self.config.ip_label),
+                result.label::<u16>(&self.config.port_label),
+                result.value.1.parse::<f64>(),
+            ) else {
+                continue;
+            };
+            if !rx_bps.is_finite() {
+                continue;
+            }
+            let proto = result.metric.get(&self.config.proto_label);
+            let Some(class) = self
+                .config
+                .classes
+                .iter()
+                .find(|class| class_matches(class, port, proto))
+            else {
+                continue;
+            };
+            *rates.entry(ip).or_default().entry(&class.name).or_default() += rx_bps;
+        }
+
+        rates
+            .into_iter()
+            .filter_map(|(ip, classes)| {
+                let mut classes: Vec<_> = classes
+                    .into_iter()
+                    .filter(|(_, rx_bps)| *rx_bps >= self.config.min_bps)
+                    .collect();
+                classes.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+                let classes: Vec<String> = classes
+                    .into_iter()
+                    .map(|(name, _)| name.to_string())
+                    .collect();
+                (!classes.is_empty()).then_some((ip, classes))
+            })
+            .collect()
+    }
+}
+
+fn class_matches(class: &AppClassConfig, port: u16, proto: Option<&String>) -> bool {
+    class.ports.iter().any(|range| range.contains(port))
+        && class
+            .proto
+            .as_ref()
+            .is_none_or(|wanted| proto.is_some_and(|proto| proto.eq_ignore_ascii_case(wanted)))
+}
@@ -0,0 +1,29 @@
+use crate::model::ClientIp;
+use anyhow::{Context, Result};
+use std::collections::HashSet;
+
+const ARP_TABLE_PATH: &str = "/proc/net/arp";
+
+/// ATF_COM: the neighbour entry is complete (the host answered ARP).
+const ATF_COMPLETE: u32 = 0x2;
+
+/// IPs with a complete entry in the kernel ARP table.
+pub fn read_present_ips() -> Result<HashSet<ClientIp>> {
+    let contents = std::fs::read_to_string(ARP_TABLE_PATH)
+        .with_context(|| format!("Failed to read {}", ARP_TABLE_PATH))?;
+
+    Ok(parse_arp_table(&contents))
+}
+
+fn parse_arp_table(contents: &str) -> HashSet<ClientIp> {
+    contents
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let ip = fields.first()?.parse().ok()?;
+            let flags = u32::from_str_radix(fields.get(2)?.trim_start_matches("0x"), 16).ok()?;
+            (flags & ATF_COMPLETE != 0).then_some(ip)
+        })
+        .collect()
+}
@@ -0,0 +1,240 @@
+use crate::config::{AuditConfig, HysteresisConfig};
+use crate::cooldown::Cooldowns;
+use crate::history::SwitchHistory;
+use crate::model::{ClientIp, IpTraffic, NicName, WanId};
+use crate::policy::{PolicyInput, SwitchPolicy};
+use crate::report::{DecisionOutcome, DecisionReport};
+use crate::reverse_dns::Hostnames;
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Write};
+use tracing::warn;
+
+/// Date of the audit files' names; sorts in time order.
+const FILE_DAY_FORMAT: &str = "%Y%m%d";
+
+/// A WAN as the policy saw it when deciding.
+#[derive(Debug, Serialize)]
+struct Candidate {
+    wan: WanId,
+    nic: NicName,
+    tcp_bandwidth_bps: f64,
+    traffic_bps: f64,
+    headroom_bps: f64,
+    clients: usize,
+    /// The policy's own rating of the WAN, higher is better.
+    score: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+struct CooldownState {
+    remaining_secs: u64,
+    reason: String,
+}
+
+#[derive(Debug, Serialize)]
+struct Thresholds {
+    min_delta_percent: Option<f64>,
+    min_delta_mbps: Option<f64>,
+    consecutive_scans: Option<u32>,
+    cooldown_secs: u64,
+}
+
+/// What a cycle's decisions were made on, taken before its switches change it.
+pub struct AuditSnapshot {
+    policy: String,
+    candidates: Vec<Candidate>,
+    /// Clients held by a cooldown or class residency when the cycle started.
+    holds: HashMap<ClientIp, CooldownState>,
+}
+
+/// One decision, as a line of the audit log.
+#[derive(Serialize)]
+struct AuditRecord<'a> {
+    timestamp: u64,
+    time: String,
+    policy: &'a str,
+    ip: ClientIp,
+    hostname: Option<&'a str>,
+    from_wan: Option<&'a WanId>,
+    from_nic: &'a NicName,
+    target_wan: Option<&'a WanId>,
+    reason: &'a str,
+    #[serde(flatten)]
+    outcome: &'a DecisionOutcome,
+    rx_bps: f64,
+    tx_bps: f64,
+    cooldown: Option<&'a CooldownState>,
+    thresholds: Thresholds,
+    candidates: &'a [Candidate],
+}
+
+/// Writes every decision of a cycle (switches, failures, held-back and skipped candidates)
+/// with the WAN figures, thresholds and cooldown it was made against, so "why did the NAS
+/// move to wan1 at 3am?" has an answer after the logs have rotated.
+pub struct AuditLog {
+    config: AuditConfig,
+    hysteresis: Option<HysteresisConfig>,
+    file: Option<(String, BufWriter<File>)>,
+}
+
+impl AuditLog {
+    pub fn new(config: AuditConfig, hysteresis: Option<HysteresisConfig>) -> Self {
+        Self {
+            config,
+            hysteresis,
+            file: None,
+        }
+    }
+
+    /// Captures the WANs and the holds of `clients` as `policy` sees them now.
+    pub fn snapshot(
+        &self,
+        policy: &dyn SwitchPolicy,
+        input: &PolicyInput,
+        clients: impl IntoIterator<Item = ClientIp>,
+        cooldowns: &Cooldowns,
+        history: &SwitchHistory,
+        now: u64,
+    ) -> AuditSnapshot {
+        let scores = policy.wan_scores(input);
+        let mut candidates: Vec<Candidate> = input
+            .wan_to_nic
+            .iter()
+            .map(|(wan, nic)| {
+                let stats = input.nic_stats.get(nic).cloned().unwrap_or_default();
+                let traffic_bps = stats.tx_bps + stats.rx_bps;
+                Candidate {
+                    wan: wan.clone(),
+                    nic: nic.clone(),
+                    tcp_bandwidth_bps: stats.tcp_bandwidth,
+                    traffic_bps,
+                    headroom_bps: stats.tcp_bandwidth - traffic_bps,
+                    clients: input.clients_per_wan.get(wan).copied().unwrap_or(0),
+                    score: scores.get(wan).copied(),
+                }
+            })
+            .collect();
+        candidates.sort_by(|a, b| a.wan.cmp(&b.wan));
+        let holds = clients
+            .into_iter()
+            .filter_map(|ip| {
+                let hold = cooldowns.remaining(history, ip, now)?;
+                Some((
+                    ip,
+                    CooldownState {
+                        remaining_secs: hold.remaining_secs,
+                        reason: hold.reason.to_string(),
+                    },
+                ))
+            })
+            .collect();
+        AuditSnapshot {
+            policy: policy.name().to_string(),
+            candidates,
+            holds,
+        }
+    }
+
+    /// Appends one line per decision to the day's file.
+    #[allow(clippy::too_many_arguments)]
+    pub fn record(
+        &mut self,
+        now: u64,
+        snapshot: &AuditSnapshot,
+        decisions: &[DecisionReport],
+        cooldowns: &Cooldowns,
+        mappings: &HashMap<ClientIp, WanId>,
+        ip_traffic: &[IpTraffic],
+        hostnames: &Hostnames,
+    ) -> Result<()> {
+        if decisions.is_empty() {
+            return Ok(());
+        }
+        let day = file_day(now);
+        if self.file.as_ref().is_none_or(|(open, _)| *open != day) {
+            fs::create_dir_all(&self.config.dir)
+                .with_context(|| format!("Failed to create {}", self.config.dir.display()))?;
+            let path = self.config.dir.join(format!("decisions-{}.jsonl", day));
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .with_context(|| format!("Failed to open {}", path.display()))?;
+            self.file = Some((day, BufWriter::new(file)));
+            self.prune(now);
+        }
+        let Some((_, file)) = self.file.as_mut() else {
+            return Ok(());
+        };
+
+        let time = chrono::DateTime::from_timestamp(now as i64, 0)
+            .map(|time| time.to_rfc3339())
+            .unwrap_or_default();
+        for decision in decisions {
+            let traffic = ip_traffic.iter().find(|traffic| traffic.ip == decision.ip);
+            let record = AuditRecord {
+                timestamp: now,
+                time: time.clone(),
+                policy: &snapshot.policy,
+                ip: decision.ip,
+                hostname: hostnames.get(decision.ip),
+                from_wan: mappings.get(&decision.ip),
+                from_nic: &decision.nic,
+                target_wan: decision.target_wan.as_ref(),
+                reason: &decision.reason,
+                outcome: &decision.outcome,
+                rx_bps: traffic.map_or(0.0, |traffic| traffic.rx_bps),
+                tx_bps: traffic.map_or(0.0, |traffic| traffic.tx_bps),
+                cooldown: snapshot.holds.get(&decision.ip),
+                thresholds: Thresholds {
+                    min_delta_percent: self
+                        .hysteresis
+                        .as_ref()
+                        .and_then(|hysteresis| hysteresis.min_delta_percent),
+                    min_delta_mbps: self
+                        .hysteresis
+                        .as_ref()
+                        .and_then(|hysteresis| hysteresis.min_delta_mbps),
+                    consecutive_scans: self
+                        .hysteresis
+                        .as_ref()
+                        .map(|hysteresis| hysteresis.consecutive_scans),
+                    cooldown_secs: cooldowns.window_for(decision.ip),
+                },
+                candidates: &snapshot.candidates,
+            };
+            serde_json::to_writer(&mut *file, &record)?;
+            file.write_all(b"\n")?;
+        }
+        file.flush()?;
+        Ok(())
+    }
+
+    fn prune(&self, now: u64) {
+        let cutoff = format!(
+            "decisions-{}.jsonl",
+            file_day(now.saturating_sub(self.config.retain_days * 24 * 3600))
+        );
+        let Ok(entries) = fs::read_dir(&self.config.dir) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name.starts_with("decisions-") && name.ends_with(".jsonl") && name < cutoff {
+                if let Err(e) = fs::remove_file(entry.path()) {
+                    warn!("Failed to delete old audit log {}: {}", name, e);
+                }
+            }
+        }
+    }
+}
+
+fn file_day(timestamp: u64) -> String {
+    chrono::DateTime::from_timestamp(timestamp as i64, 0)
+        .unwrap_or_default()
+        .format(FILE_DAY_FORMAT)
+        .to_string()
+}
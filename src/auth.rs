@@ -0,0 +1,275 @@
+use crate::clock::Clock;
+use crate::config::{AuthConfig, OidcConfig};
+use anyhow::{bail, Context, Result};
+use axum::http::{header, HeaderMap};
+use reqwest::Client;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use subtle::ConstantTimeEq;
+use tracing::warn;
+
+/// How long a successful OIDC token introspection is trusted at most; never past the
+/// token's own expiry.
+const INTROSPECTION_CACHE_SECS: u64 = 60;
+
+/// Permission levels of the HTTP API, ordered from least to most privileged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Read-only access to state, history and metrics.
+    Viewer,
+    /// May additionally issue switches and pause/resume the balancer.
+    Operator,
+    /// May additionally change policies and configuration.
+    Admin,
+}
+
+impl Role {
+    fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "viewer" => Some(Role::Viewer),
+            "operator" => Some(Role::Operator),
+            "admin" => Some(Role::Admin),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Role::Viewer => "viewer",
+            Role::Operator => "operator",
+            Role::Admin => "admin",
+        };
+        f.write_str(name)
+    }
+}
+
+/// The authenticated caller of an API request.
+#[derive(Debug, Clone)]
+pub struct Principal {
+    pub name: String,
+    pub role: Role,
+}
+
+#[derive(Debug)]
+pub enum AuthError {
+    /// No credentials, or credentials that are not recognised.
+    Unauthenticated,
+    /// Valid credentials whose role is too low.
+    Forbidden {
+        principal: Principal,
+        required: Role,
+    },
+}
+
+/// Authenticates API requests by static API key or OIDC bearer token.
+pub struct Authenticator {
+    api_keys: Vec<(String, Principal)>,
+    oidc: Option<OidcIntrospector>,
+}
+
+impl Authenticator {
+    pub fn new(config: &AuthConfig, clock: Arc<dyn Clock>) -> Self {
+        let api_keys = config
+            .api_keys
+            .iter()
+            .map(|key| {
+                (
+                    key.key.clone(),
+                    Principal {
+                        name: key.name.clone(),
+                        role: key.role,
+                    },
+                )
+            })
+            .collect();
+
+        Self {
+            api_keys,
+            oidc: config
+                .oidc
+                .clone()
+                .map(|oidc| OidcIntrospector::new(oidc, clock)),
+        }
+    }
+
+    /// With no keys and no OIDC provider configured everyone may read, but nobody may
+    /// switch, pause or change anything.
+    pub fn is_enabled(&self) -> bool {
+        !self.api_keys.is_empty() || self.oidc.is_some()
+    }
+
+    pub async fn authorize(
+        &self,
+        headers: &HeaderMap,
+        required: Role,
+    ) -> Result<Principal, AuthError> {
+        let principal = if self.is_enabled() {
+            self.authenticate(headers).await?
+        } else {
+            Principal {
+                name: "anonymous".to_string(),
+                role: Role::Viewer,
+            }
+        };
+
+        if principal.role < required {
+            return Err(AuthError::Forbidden {
+                principal,
+                required,
+            });
+        }
+
+        Ok(principal)
+    }
+
+    async fn authenticate(&self, headers: &HeaderMap) -> Result<Principal, AuthError> {
+        let token = credentials(headers).ok_or(AuthError::Unauthenticated)?;
+
+        match self.api_key(token) {
+            Some(principal) => Ok(principal.clone()),
+            None => match &self.oidc {
+                Some(oidc) => oidc.introspect(token).await.map_err(|e| {
+                    warn!("OIDC token rejected: {:#}", e);
+                    AuthError::Unauthenticated
+                }),
+                None => Err(AuthError::Unauthenticated),
+            },
+        }
+    }
+
+    /// The owner of API key `token`, comparing against every key in constant time so the
+    /// response time does not tell how much of a key was guessed right.
+    fn api_key(&self, token: &str) -> Option<&Principal> {
+        let mut found = None;
+        for (key, principal) in &self.api_keys {
+            if bool::from(key.as_bytes().ct_eq(token.as_bytes())) {
+                found = Some(principal);
+            }
+        }
+        found
+    }
+}
+
+/// Token from `Authorization: Bearer <token>` or `X-API-Key: <token>`.
+fn credentials(headers: &HeaderMap) -> Option<&str> {
+    if let Some(bearer) = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+    {
+        return Some(bearer.trim());
+    }
+
+    headers
+        .get("x-api-key")
+        .and_then(|value| value.to_str().ok())
+        .map(str::trim)
+}
+
+#[derive(Debug, Deserialize)]
+struct IntrospectionResponse {
+    active: bool,
+    sub: Option<String>,
+    /// Unix time the token expires.
+    exp: Option<u64>,
+    #[serde(flatten)]
+    claims: HashMap<String, serde_json::Value>,
+}
+
+/// Validates opaque or JWT access tokens with an OAuth2 token introspection endpoint (RFC 7662).
+struct OidcIntrospector {
+    client: Client,
+    config: OidcConfig,
+    /// Token → its principal and the Unix time to introspect it again.
+    cache: Mutex<HashMap<String, (Principal, u64)>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl OidcIntrospector {
+    fn new(config: OidcConfig, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            client: Client::new(),
+            config,
+            cache: Mutex::new(HashMap::new()),
+            clock,
+        }
+    }
+
+    async fn introspect(&self, token: &str) -> Result<Principal> {
+        if let Some((principal, expires)) = self.cache.lock().unwrap().get(token) {
+            if self.clock.unix_secs() < *expires {
+                return Ok(principal.clone());
+            }
+        }
+
+        let response = self
+            .client
+            .post(&self.config.introspection_url)
+            .basic_auth(&self.config.client_id, self.config.client_secret.as_deref())
+            .form(&[("token", token)])
+            .send()
+            .await
+            .context("Failed to reach token introspection endpoint")?;
+        if !response.status().is_success() {
+            bail!("Introspection endpoint returned {}", response.status());
+        }
+
+        let introspection: IntrospectionResponse = response
+            .json()
+            .await
+            .context("Failed to parse introspection response")?;
+        if !introspection.active {
+            bail!("Token is not active");
+        }
+
+        let role = self
+            .roles_from_claim(introspection.claims.get(&self.config.role_claim))
+            .max()
+            .with_context(|| {
+                format!(
+                    "Token has no recognised role in claim {}",
+                    self.config.role_claim
+                )
+            })?;
+        let principal = Principal {
+            name: introspection.sub.unwrap_or_else(|| "oidc".to_string()),
+            role,
+        };
+
+        let now = self.clock.unix_secs();
+        let expires = (now + INTROSPECTION_CACHE_SECS).min(introspection.exp.unwrap_or(u64::MAX));
+        let mut cache = self.cache.lock().unwrap();
+        cache.retain(|_, (_, expires)| now < *expires);
+        cache.insert(token.to_string(), (principal.clone(), expires));
+
+        Ok(principal)
+    }
+
+    /// Maps claim values (a string or an array of strings) to roles, using
+    /// `role_mapping` first and the role names themselves as a fallback.
+    fn roles_from_claim<'a>(
+        &'a self,
+        claim: Option<&'a serde_json::Value>,
+    ) -> impl Iterator<Item = Role> + 'a {
+        let values: Vec<&str> = match claim {
+            Some(serde_json::Value::String(value)) => vec![value.as_str()],
+            Some(serde_json::Value::Array(values)) => {
+                values.iter().filter_map(|value| value.as_str()).collect()
+            }
+            _ => Vec::new(),
+        };
+
+        values.into_iter().filter_map(|value| {
+            self.config
+                .role_mapping
+                .get(value)
+                .copied()
+                .or_else(|| Role::from_name(value))
+        })
+    }
+}
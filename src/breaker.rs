@@ -0,0 +1,121 @@
+use crate::config::CircuitBreakerConfig;
+use serde::{Deserialize, Serialize};
+
+/// Where the breaker stands; switching only happens while it is not open.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum BreakerState {
+    Closed,
+    /// Switching is paused after repeated failures.
+    Open {
+        remaining_secs: u64,
+    },
+    /// The pause is over; one trial switch decides whether to close again.
+    HalfOpen,
+}
+
+impl BreakerState {
+    /// Numeric value for the state gauge.
+    pub fn gauge(&self) -> u8 {
+        match self {
+            BreakerState::Closed => 0,
+            BreakerState::HalfOpen => 1,
+            BreakerState::Open { .. } => 2,
+        }
+    }
+}
+
+/// What a breaker counts, as saved across restarts.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct BreakerCounters {
+    pub consecutive_failures: u32,
+    pub opened_at: Option<u64>,
+}
+
+/// Stops calling the switch API after `failure_threshold` consecutive failures and
+/// pauses switching for `open_secs`, so a broken routing service is not hammered
+/// every cycle.
+pub struct CircuitBreaker {
+    config: CircuitBreakerConfig,
+    consecutive_failures: u32,
+    opened_at: Option<u64>,
+    /// A half-open trial switch has been let through and not yet reported.
+    trial_pending: bool,
+}
+
+impl CircuitBreaker {
+    pub fn new(config: CircuitBreakerConfig) -> Self {
+        Self {
+            config,
+            consecutive_failures: 0,
+            opened_at: None,
+            trial_pending: false,
+        }
+    }
+
+    pub fn state(&self, now: u64) -> BreakerState {
+        match self.opened_at {
+            None => BreakerState::Closed,
+            Some(opened_at) => match (opened_at + self.config.open_secs).checked_sub(now) {
+                Some(remaining_secs) if remaining_secs > 0 => BreakerState::Open { remaining_secs },
+                _ => BreakerState::HalfOpen,
+            },
+        }
+    }
+
+    pub fn export(&self) -> BreakerCounters {
+        BreakerCounters {
+            consecutive_failures: self.consecutive_failures,
+            opened_at: self.opened_at,
+        }
+    }
+
+    /// Continues from saved counters; a trial switch in flight when they were saved is not.
+    pub fn restore(&mut self, counters: BreakerCounters) {
+        self.consecutive_failures = counters.consecutive_failures;
+        self.opened_at = counters.opened_at;
+    }
+
+    pub fn consecutive_failures(&self) -> u32 {
+        self.consecutive_failures
+    }
+
+    /// Whether a switch may be attempted now; in the half-open state only one is.
+    pub fn allow(&mut self, now: u64) -> bool {
+        match self.state(now) {
+            BreakerState::Closed => true,
+            BreakerState::Open { .. } => false,
+            BreakerState::HalfOpen if self.trial_pending => false,
+            BreakerState::HalfOpen => {
+                self.trial_pending = true;
+                true
+            }
+        }
+    }
+
+    /// Records a successful switch; returns whether this closed an open breaker.
+    pub fn record_success(&mut self) -> bool {
+        self.consecutive_failures = 0;
+        self.trial_pending = false;
+        self.opened_at.take().is_some()
+    }
+
+    /// Records a failed switch; returns whether this opened the breaker.
+    pub fn record_failure(&mut self, now: u64) -> bool {
+        self.consecutive_failures += 1;
+        if self.trial_pending {
+            // The trial failed: back to a full pause
+            self.trial_pending = false;
+            self.opened_at = Some(now);
+            return true;
+        }
+        if self.opened_at.is_none()
+            && self.config.failure_threshold > 0
+            && self.consecutive_failures >= self.config.failure_threshold
+        {
+            self.opened_at = Some(now);
+            return true;
+        }
+        false
+    }
+}
@@ -0,0 +1,164 @@
+use crate::cli::{ExportBundleArgs, ImportBundleArgs};
+use crate::config::Config;
+use crate::history_db::{HistoryDb, HistoryQuery, StoredSwitch};
+use crate::redact::Redactor;
+use crate::store::{self, StateStore};
+use anyhow::{bail, Context, Result};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const MANIFEST: &str = "manifest.json";
+const CONFIG: &str = "routingflow.toml";
+const HISTORY: &str = "history.json";
+
+/// Describes what a bundle holds and where it came from.
+#[derive(Debug, Serialize, Deserialize)]
+struct Manifest {
+    version: String,
+    created_at: u64,
+    /// IP addresses were replaced by pseudonyms; such a bundle is meant for bug reports.
+    redacted_ips: bool,
+    history_records: usize,
+}
+
+/// Implements the `export-bundle` subcommand: config and switch history as one `.tar.gz`.
+pub fn export_bundle(config: &Config, config_path: &Path, args: &ExportBundleArgs) -> Result<()> {
+    let mut config_text = if config_path.exists() {
+        std::fs::read_to_string(config_path)
+            .with_context(|| format!("Failed to read config file {}", config_path.display()))?
+    } else {
+        String::new()
+    };
+
+    let mut history = match store::open_database(&config.history)? {
+        Some(db) => db.query_switches(&HistoryQuery::default())?,
+        None => Vec::new(),
+    };
+
+    if args.redact_ips {
+        let mut redactor = Redactor::default();
+        let mut document: toml::Value = toml::from_str(&config_text)
+            .with_context(|| format!("Failed to parse config file {}", config_path.display()))?;
+        redactor.toml(&mut document);
+        config_text = toml::to_string_pretty(&document)?;
+        for record in &mut history {
+            record.ip = redactor.text(&record.ip);
+            record.reason = redactor.text(&record.reason);
+            record.error = record.error.as_deref().map(|error| redactor.text(error));
+        }
+    }
+
+    let manifest = Manifest {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        created_at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs(),
+        redacted_ips: args.redact_ips,
+        history_records: history.len(),
+    };
+
+    let file = File::create(&args.output)
+        .with_context(|| format!("Failed to create {}", args.output.display()))?;
+    let mut archive = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+    append(
+        &mut archive,
+        MANIFEST,
+        &serde_json::to_vec_pretty(&manifest)?,
+    )?;
+    append(&mut archive, CONFIG, config_text.as_bytes())?;
+    append(&mut archive, HISTORY, &serde_json::to_vec(&history)?)?;
+    archive.into_inner()?.finish()?;
+
+    println!(
+        "Wrote {} ({} history records{})",
+        args.output.display(),
+        history.len(),
+        if args.redact_ips {
+            ", IPs redacted"
+        } else {
+            ""
+        }
+    );
+    Ok(())
+}
+
+/// Implements the `import-bundle` subcommand: restores the config to `config_path` and
+/// appends the bundled history to the database that config points at.
+pub fn import_bundle(config_path: &Path, args: &ImportBundleArgs) -> Result<()> {
+    let file = File::open(&args.input)
+        .with_context(|| format!("Failed to open {}", args.input.display()))?;
+    let mut archive = tar::Archive::new(GzDecoder::new(file));
+
+    let (mut manifest, mut config_text, mut history) = (None, None, None);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let name = entry.path()?.to_string_lossy().into_owned();
+        let mut contents = Vec::new();
+        entry.read_to_end(&mut contents)?;
+        match name.as_str() {
+            MANIFEST => manifest = Some(serde_json::from_slice::<Manifest>(&contents)?),
+            CONFIG => config_text = Some(String::from_utf8(contents)?),
+            HISTORY => history = Some(serde_json::from_slice::<Vec<StoredSwitch>>(&contents)?),
+            _ => {}
+        }
+    }
+    let (Some(manifest), Some(config_text), Some(history)) = (manifest, config_text, history)
+    else {
+        bail!("{} is not a routingFlow bundle", args.input.display());
+    };
+    if manifest.redacted_ips {
+        eprintln!("Note: this bundle has redacted IP addresses");
+    }
+
+    if config_path.exists() && !args.force {
+        bail!(
+            "{} already exists; pass --force to overwrite it",
+            config_path.display()
+        );
+    }
+    let config: Config = toml::from_str(&config_text).context("Bundled config is invalid")?;
+    std::fs::write(config_path, &config_text)
+        .with_context(|| format!("Failed to write {}", config_path.display()))?;
+
+    let db = HistoryDb::open(&config.history.db_path)?;
+    // Oldest first, so the restored rows keep their order
+    for record in history.iter().rev() {
+        db.insert_switch(record)?;
+    }
+
+    println!(
+        "Restored {} and {} history records into {} (bundle of routingFlow {})",
+        config_path.display(),
+        history.len(),
+        config.history.db_path.display(),
+        manifest.version
+    );
+    Ok(())
+}
+
+pub fn append<W: std::io::Write>(
+    archive: &mut tar::Builder<W>,
+    name: &str,
+    data: &[u8],
+) -> Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o600);
+    header.set_mtime(
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs(),
+    );
+    header.set_cksum();
+    archive
+        .append_data(&mut header, name, data)
+        .with_context(|| format!("Failed to add {} to the bundle", name))
+}
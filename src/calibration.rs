@@ -0,0 +1,142 @@
+use crate::clock::Clock;
+use crate::config::CalibrationConfig;
+use crate::model::{NicName, WanId};
+use crate::probe::{self, Egress};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use tracing::{debug, info, warn};
+
+/// Time to connect and get the response head on top of the test itself.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A WAN's capacity as a throughput test measured it.
+#[derive(Debug, Clone, Copy)]
+struct Measurement {
+    bps: f64,
+    measured_at: SystemTime,
+}
+
+/// Downloads a test file out of every WAN interface on a schedule and keeps the throughput
+/// as the WAN's capacity, in place of tcp-traffic-scan's estimate, which drifts on links
+/// whose speed varies with the time of day (cable, LTE). Tests stay within a daily data
+/// budget per WAN.
+pub struct Calibrator {
+    interfaces: Arc<Mutex<HashMap<WanId, NicName>>>,
+    measurements: Arc<Mutex<HashMap<NicName, Measurement>>>,
+    max_age: Duration,
+    clock: Arc<dyn Clock>,
+}
+
+impl Calibrator {
+    pub fn spawn(config: CalibrationConfig, clock: Arc<dyn Clock>) -> Self {
+        let interfaces = Arc::new(Mutex::new(HashMap::new()));
+        let measurements = Arc::new(Mutex::new(HashMap::new()));
+        // A missed or skipped test leaves the previous one standing for another round
+        let max_age = Duration::from_secs(config.interval_secs.max(1) * 2);
+        tokio::spawn(run(
+            config,
+            interfaces.clone(),
+            measurements.clone(),
+            clock.clone(),
+        ));
+        Self {
+            interfaces,
+            measurements,
+            max_age,
+            clock,
+        }
+    }
+
+    /// WAN → interface assignment to test out of; refreshed from the routing service each cycle.
+    pub fn set_interfaces(&self, wan_to_nic: &HashMap<WanId, NicName>) {
+        *self.interfaces.lock().unwrap() = wan_to_nic.clone();
+    }
+
+    /// The measured capacity of every NIC tested recently, in bits per second.
+    pub fn capacities(&self) -> HashMap<NicName, f64> {
+        self.measurements
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, measurement)| self.clock.since(measurement.measured_at) <= self.max_age)
+            .map(|(nic, measurement)| (nic.clone(), measurement.bps))
+            .collect()
+    }
+}
+
+async fn run(
+    config: CalibrationConfig,
+    interfaces: Arc<Mutex<HashMap<WanId, NicName>>>,
+    measurements: Arc<Mutex<HashMap<NicName, Measurement>>>,
+    clock: Arc<dyn Clock>,
+) {
+    let max_bytes = config.max_mb * 1_000_000;
+    let max_duration = Duration::from_secs(config.max_secs.max(1));
+    // Per WAN: the UTC day and the bytes spent on tests during it
+    let mut spent: HashMap<WanId, (u64, u64)> = HashMap::new();
+    let interval = Duration::from_secs(config.interval_secs.max(1));
+    let mut next_round = clock.now();
+
+    loop {
+        clock.sleep_until(next_round).await;
+        next_round = clock.now() + interval;
+        // The first round starts at once, before the monitor has read the interfaces
+        let mut wans: Vec<_> = interfaces.lock().unwrap().clone().into_iter().collect();
+        if wans.is_empty() {
+            next_round = clock.now() + Duration::from_secs(1);
+            continue;
+        }
+        wans.sort();
+
+        let today = clock.unix_secs() / 86_400;
+        // One WAN at a time, so the tests do not compete for the router's CPU
+        for (wan, nic) in wans {
+            let (day, bytes) = spent.entry(wan.clone()).or_insert((today, 0));
+            if *day != today {
+                *day = today;
+                *bytes = 0;
+            }
+            if let Some(budget_mb) = config.daily_budget_mb {
+                if *bytes + max_bytes > budget_mb * 1_000_000 {
+                    debug!(wan = %wan, spent_mb = *bytes / 1_000_000, "Daily calibration budget used up");
+                    continue;
+                }
+            }
+
+            let egress = Egress::new(nic.clone(), config.fwmark);
+            let result = tokio::time::timeout(
+                max_duration + CONNECT_TIMEOUT,
+                probe::http_download(&config.url, &egress, max_bytes, max_duration),
+            )
+            .await
+            .unwrap_or_else(|_| Err(anyhow::anyhow!("timed out")));
+            match result {
+                Ok((received, elapsed)) if received > 0 && !elapsed.is_zero() => {
+                    *bytes += received;
+                    let bps = received as f64 * 8.0 / elapsed.as_secs_f64();
+                    info!(
+                        wan = %wan,
+                        nic = %nic,
+                        mbps = bps / 1_000_000.0,
+                        received_mb = received as f64 / 1_000_000.0,
+                        secs = elapsed.as_secs_f64(),
+                        "Calibrated WAN capacity"
+                    );
+                    measurements.lock().unwrap().insert(
+                        nic,
+                        Measurement {
+                            bps,
+                            measured_at: clock.now(),
+                        },
+                    );
+                }
+                Ok((received, _)) => {
+                    *bytes += received;
+                    warn!(wan = %wan, nic = %nic, "Calibration download was too short to measure");
+                }
+                Err(e) => warn!(wan = %wan, nic = %nic, "Calibration failed: {:#}", e),
+            }
+        }
+    }
+}
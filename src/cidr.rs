@@ -0,0 +1,90 @@
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Deserializer};
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// An IPv4 or IPv6 prefix such as `192.168.1.0/24`. A bare address is a host prefix.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix_len: u8,
+}
+
+impl Cidr {
+    /// The prefix holding just `ip`.
+    pub fn host(ip: IpAddr) -> Self {
+        Self {
+            network: ip,
+            prefix_len: if ip.is_ipv4() { 32 } else { 128 },
+        }
+    }
+
+    pub fn prefix_len(&self) -> u8 {
+        self.prefix_len
+    }
+
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        match (self.network, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - u32::from(self.prefix_len))
+                    .unwrap_or(0);
+                u32::from(network) & mask == u32::from(*ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - u32::from(self.prefix_len))
+                    .unwrap_or(0);
+                u128::from(network) & mask == u128::from(*ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (address, prefix_len) = match s.split_once('/') {
+            Some((address, prefix_len)) => (address, Some(prefix_len)),
+            None => (s, None),
+        };
+
+        let network: IpAddr = address
+            .trim()
+            .parse()
+            .with_context(|| format!("Invalid address in prefix {}", s))?;
+        let max_len = if network.is_ipv4() { 32 } else { 128 };
+
+        let prefix_len = match prefix_len {
+            Some(len) => len
+                .trim()
+                .parse::<u8>()
+                .map_err(|_| anyhow!("Invalid prefix length in {}", s))?,
+            None => max_len,
+        };
+        if prefix_len > max_len {
+            bail!("Prefix length {} out of range in {}", prefix_len, s);
+        }
+
+        Ok(Self {
+            network,
+            prefix_len,
+        })
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix_len)
+    }
+}
+
+impl<'de> Deserialize<'de> for Cidr {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
@@ -0,0 +1,37 @@
+use crate::config::TrafficClassConfig;
+use crate::model::IpTraffic;
+
+/// Assigns clients to the configured traffic classes based on their current rates.
+pub struct TrafficClassifier {
+    classes: Vec<TrafficClassConfig>,
+}
+
+impl TrafficClassifier {
+    pub fn new(classes: &[TrafficClassConfig]) -> Self {
+        Self {
+            classes: classes.to_vec(),
+        }
+    }
+
+    /// The first configured class whose criteria all match `traffic`.
+    pub fn classify(&self, traffic: &IpTraffic) -> Option<&TrafficClassConfig> {
+        self.classes.iter().find(|class| matches(class, traffic))
+    }
+}
+
+fn matches(class: &TrafficClassConfig, traffic: &IpTraffic) -> bool {
+    let rx_mbps = traffic.rx_bps / 1_000_000.0;
+    let tx_mbps = traffic.tx_bps / 1_000_000.0;
+
+    let in_range = |value: f64, min: Option<f64>, max: Option<f64>| {
+        min.is_none_or(|min| value >= min) && max.is_none_or(|max| value <= max)
+    };
+
+    (class.prefixes.is_empty()
+        || class
+            .prefixes
+            .iter()
+            .any(|p| p.contains(&traffic.ip.addr())))
+        && in_range(rx_mbps, class.min_rx_mbps, class.max_rx_mbps)
+        && in_range(tx_mbps, class.min_tx_mbps, class.max_tx_mbps)
+}
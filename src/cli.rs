@@ -0,0 +1,301 @@
+use crate::export::ExportFormat;
+use crate::model::{ClientIp, WanId};
+use crate::monitor::OutputFormat;
+use crate::summary::ReportPeriod;
+use anyhow::{bail, Result};
+use clap::{Args, Parser, Subcommand};
+use std::path::PathBuf;
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "routingFlow",
+    version,
+    about = "Per-NIC traffic monitor and WAN load balancer"
+)]
+pub struct Cli {
+    /// Path to the TOML config file (defaults to $ROUTINGFLOW_CONFIG or ./routingflow.toml)
+    #[arg(long, global = true)]
+    pub config: Option<PathBuf>,
+
+    /// Do not print the per-cycle report; only log output is written (to stderr)
+    #[arg(long, short, global = true)]
+    pub quiet: bool,
+
+    /// Format of the per-cycle report on stdout
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    pub output: OutputFormat,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Run the monitor and balancing loop (default)
+    Run(RunArgs),
+    /// Show persisted switch history
+    History(HistoryArgs),
+    /// Write the stored NIC stats and switches, e.g. as CSV for a spreadsheet
+    Export(ExportArgs),
+    /// Sum up the stored history of the last day or week: WAN utilization, switches per
+    /// client, time saturated and top talkers
+    Report(ReportArgs),
+    /// Run recorded traffic through the balancing at simulated speed and list the switches
+    /// it would make, e.g. to tune thresholds offline
+    Replay(ReplayArgs),
+    /// Show the active policy, or switch a running instance to another one
+    Policy(PolicyArgs),
+    /// Pin a client to a WAN for a while (switching it now), or list the active pins
+    Pin(PinArgs),
+    /// Stop a running instance from moving clients, for a while or until resumed
+    Pause(PauseArgs),
+    /// Let a paused instance move clients again
+    Resume(ResumeArgs),
+    /// Move a client to a WAN once; the policies may move it again later
+    Switch(SwitchArgs),
+    /// Pack the config and switch history into one archive (e.g. to move to new hardware)
+    ExportBundle(ExportBundleArgs),
+    /// Restore the config and switch history from an archive written by export-bundle
+    ImportBundle(ImportBundleArgs),
+    /// Collect logs, recent cycles, the config without secrets and version info for a bug report
+    Diag(DiagArgs),
+    /// Check the connections to Prometheus and the routing service, the interfaces and the
+    /// metric series before running, with a fix for every problem found
+    Doctor,
+}
+
+#[derive(Debug, Default, Args)]
+pub struct RunArgs {
+    /// Run against a simulated clock starting at this time (RFC 3339) that skips the waits
+    /// between cycles, e.g. to exercise schedules against test backends
+    #[arg(long, hide = true)]
+    pub simulated_start: Option<chrono::DateTime<chrono::FixedOffset>>,
+
+    /// Detach from the terminal and keep running in the background
+    #[arg(long)]
+    pub daemon: bool,
+
+    /// PID file, locked while this instance runs so a second one refuses to start
+    /// [default with --daemon: /run/routingflow.pid]
+    #[arg(long)]
+    pub pid_file: Option<PathBuf>,
+
+    /// With --daemon, append the report and logs here instead of discarding them
+    #[arg(long, requires = "daemon")]
+    pub log_file: Option<PathBuf>,
+
+    /// Take over from the instance listening on the handoff socket: carry over its state,
+    /// wait for it to exit and continue in its place (e.g. after an upgrade)
+    #[arg(long)]
+    pub take_over: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct ExportBundleArgs {
+    /// Archive to write
+    #[arg(default_value = "routingflow-bundle.tar.gz")]
+    pub output: PathBuf,
+
+    /// Replace IP addresses with stable pseudonyms, e.g. before attaching to a bug report
+    #[arg(long)]
+    pub redact_ips: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct ImportBundleArgs {
+    /// Archive written by export-bundle
+    pub input: PathBuf,
+
+    /// Overwrite an existing config file
+    #[arg(long)]
+    pub force: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct DiagArgs {
+    /// Archive to write
+    #[arg(default_value = "routingflow-diag.tar.gz")]
+    pub output: PathBuf,
+
+    /// Replace IP and MAC addresses with stable pseudonyms
+    #[arg(long)]
+    pub anonymize: bool,
+
+    /// Include only the last N cycle reports (default: all the instance kept)
+    #[arg(long)]
+    pub cycles: Option<usize>,
+
+    /// API key of an admin, if the API requires authentication
+    #[arg(long, env = "ROUTINGFLOW_API_KEY")]
+    pub api_key: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct PolicyArgs {
+    /// Policy to switch to (`top_rx` or `weighted`); shows the current state when omitted
+    pub name: Option<String>,
+
+    /// Weight of a WAN for the weighted policy, e.g. `--weight wan0=70` (repeatable)
+    #[arg(long = "weight", value_parser = parse_weight)]
+    pub weights: Vec<(WanId, f64)>,
+
+    /// API key of an admin, if the API requires authentication
+    #[arg(long, env = "ROUTINGFLOW_API_KEY")]
+    pub api_key: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct PinArgs {
+    /// Client to pin; lists the active pins when omitted
+    pub ip: Option<ClientIp>,
+
+    /// WAN to pin the client to
+    pub wan: Option<WanId>,
+
+    /// How long the pin lasts (e.g. 30m, 2h, 1d)
+    #[arg(long = "for", value_parser = parse_duration_secs)]
+    pub duration_secs: Option<u64>,
+
+    /// End the client's pin early instead
+    #[arg(long, conflicts_with_all = ["wan", "duration_secs"])]
+    pub release: bool,
+
+    /// API key of an admin, if the API requires authentication
+    #[arg(long, env = "ROUTINGFLOW_API_KEY")]
+    pub api_key: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct PauseArgs {
+    /// How long the pause lasts (e.g. 30m, 2h); until resumed when omitted
+    #[arg(long = "for", value_parser = parse_duration_secs)]
+    pub duration_secs: Option<u64>,
+
+    /// Why the balancing is paused, shown in the state and logs
+    #[arg(long)]
+    pub reason: Option<String>,
+
+    /// API key of an operator, if the API requires authentication
+    #[arg(long, env = "ROUTINGFLOW_API_KEY")]
+    pub api_key: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct ResumeArgs {
+    /// API key of an operator, if the API requires authentication
+    #[arg(long, env = "ROUTINGFLOW_API_KEY")]
+    pub api_key: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct SwitchArgs {
+    /// Client to move
+    pub ip: ClientIp,
+
+    /// WAN to move the client to
+    pub wan: WanId,
+
+    /// API key of an operator, if the API requires authentication
+    #[arg(long, env = "ROUTINGFLOW_API_KEY")]
+    pub api_key: Option<String>,
+}
+
+fn parse_weight(value: &str) -> Result<(WanId, f64)> {
+    let Some((wan, weight)) = value.split_once('=') else {
+        bail!("Invalid weight {} (use wan=weight)", value);
+    };
+    let wan = wan.parse().map_err(anyhow::Error::msg)?;
+    let weight = weight
+        .parse()
+        .map_err(|_| anyhow::anyhow!("Invalid weight {}", value))?;
+    Ok((wan, weight))
+}
+
+#[derive(Debug, Args)]
+pub struct HistoryArgs {
+    /// Only show switches of this IP
+    #[arg(long)]
+    pub ip: Option<ClientIp>,
+
+    /// Only show switches newer than this (e.g. 90s, 30m, 24h, 7d)
+    #[arg(long, value_parser = parse_duration_secs)]
+    pub since: Option<u64>,
+
+    /// Maximum number of records to show (newest first)
+    #[arg(long, default_value_t = 50)]
+    pub limit: usize,
+}
+
+#[derive(Debug, Args)]
+pub struct ExportArgs {
+    #[arg(long, value_enum, default_value_t = ExportFormat::Csv)]
+    pub format: ExportFormat,
+
+    /// Only export records newer than this (e.g. 30m, 24h, 7d)
+    #[arg(long, value_parser = parse_duration_secs)]
+    pub since: Option<u64>,
+
+    /// File to write instead of stdout
+    #[arg(long, short)]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct ReportArgs {
+    #[arg(long, value_enum, default_value_t = ReportPeriod::Daily)]
+    pub period: ReportPeriod,
+
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    pub format: OutputFormat,
+
+    /// End of the period (RFC 3339) [default: now]
+    #[arg(long)]
+    pub until: Option<chrono::DateTime<chrono::FixedOffset>>,
+
+    /// Number of top talkers to list
+    #[arg(long, default_value_t = 10)]
+    pub top: usize,
+}
+
+#[derive(Debug, Args)]
+pub struct ReplayArgs {
+    /// Directory of cycles recorded by a running instance (see [recording])
+    #[arg(long, required_unless_present = "since", conflicts_with = "since")]
+    pub dir: Option<PathBuf>,
+
+    /// Replay Prometheus's own history over this period instead (e.g. 6h), starting from
+    /// the current mappings
+    #[arg(long, value_parser = parse_duration_secs)]
+    pub since: Option<u64>,
+
+    /// Resolution of the history queries with --since
+    #[arg(long, value_parser = parse_duration_secs, default_value = "10s")]
+    pub step: u64,
+
+    /// Also print the report of every replayed cycle
+    #[arg(long)]
+    pub reports: bool,
+}
+
+/// Parses durations like `45`, `90s`, `30m`, `24h` or `7d` into seconds.
+pub fn parse_duration_secs(value: &str) -> Result<u64> {
+    let value = value.trim();
+    let (number, unit) = match value.find(|c: char| !c.is_ascii_digit()) {
+        Some(index) => value.split_at(index),
+        None => (value, "s"),
+    };
+
+    let number: u64 = match number.parse() {
+        Ok(number) => number,
+        Err(_) => bail!("Invalid duration: {}", value),
+    };
+    let multiplier = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => bail!("Invalid duration unit in {} (use s, m, h or d)", value),
+    };
+
+    Ok(number * multiplier)
+}
@@ -0,0 +1,575 @@
+use crate::cidr::Cidr;
+use crate::config::{ClientRuleAction, Config};
+use crate::error::ConfigError;
+use crate::model::{ClientIp, WanId};
+use crate::neighbors::{is_mac, normalize_mac};
+use crate::policy::{PolicyInput, PolicyPlan, SkippedCandidate, SwitchDecision};
+use crate::schedule::TimeWindow;
+use chrono::{DateTime, TimeZone};
+use std::collections::{HashMap, HashSet};
+
+/// How much lower another WAN's RTT must be for a realtime client to be moved there, so
+/// WANs with about the same RTT do not swap places every round of probes.
+const REALTIME_RTT_MARGIN_MS: f64 = 5.0;
+
+/// What a rule does to the clients in its prefix.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ClientRule {
+    /// Always on this WAN: brought back to it and never moved off it.
+    Pin(WanId),
+    /// Never moved by the balancing.
+    Exclude,
+    /// Left on this WAN by the policies and moved to it first when it has room.
+    PreferWan(WanId),
+    /// Counts this much toward the weighted policy's shares (a plain client counts 1).
+    Weight(f64),
+    /// On the healthy WAN with the lowest RTT, and moved for nothing else.
+    Realtime,
+}
+
+/// A configured rule with the window it applies in.
+#[derive(Debug)]
+struct Scheduled<M> {
+    clients: M,
+    rule: ClientRule,
+    schedule: Option<TimeWindow>,
+    /// Whether the schedule was open at the last `set_time`.
+    in_force: bool,
+}
+
+/// Per-client rules from `client_rules`, `pinned_ips` and `excluded_ips`. A rule for a
+/// client's MAC goes first, whatever address the client has; otherwise the client
+/// follows the rule with the longest prefix containing it, so a host entry overrides
+/// the rule of its subnet; clients no prefix rule covers follow the rule of the busiest
+/// application class their traffic is in, and then the first device type or vendor rule
+/// matching what the controller reports for them. Rules with a schedule only
+/// count while it is open and then go before unscheduled rules of the same prefix. A
+/// manual pin made over the API overrides them all.
+#[derive(Debug, Default)]
+pub struct ClientRules {
+    /// Rules by normalized MAC, scheduled ones first.
+    mac_rules: Vec<Scheduled<String>>,
+    /// Most specific first.
+    rules: Vec<Scheduled<Cidr>>,
+    /// Rules by application class, scheduled ones first.
+    app_rules: Vec<Scheduled<String>>,
+    /// Rules by controller metadata, in configuration order.
+    metadata_rules: Vec<Scheduled<MetadataMatch>>,
+    /// Application classes each client's traffic is in, busiest first.
+    app_classes: HashMap<ClientIp, Vec<String>>,
+    /// Device type and vendor of the clients the controller knows, lowercased.
+    metadata: HashMap<ClientIp, (Option<String>, Option<String>)>,
+    /// MAC of each client address that one is known for.
+    macs: HashMap<ClientIp, String>,
+    /// Manual pins and the reason reported for them.
+    manual: HashMap<ClientIp, (ClientRule, String)>,
+}
+
+/// Which clients a metadata rule covers, lowercased.
+#[derive(Debug)]
+enum MetadataMatch {
+    /// The controller's device type equals this.
+    DeviceType(String),
+    /// The controller's vendor name contains this.
+    Vendor(String),
+}
+
+impl ClientRules {
+    pub fn new(config: &Config) -> Result<Self, ConfigError> {
+        let mut mac_rules = Vec::new();
+        let mut rules = Vec::new();
+        let mut app_rules = Vec::new();
+        let mut metadata_rules = Vec::new();
+        for rule in &config.client_rules {
+            let clients = match (
+                &rule.prefix,
+                &rule.mac,
+                &rule.device_type,
+                &rule.vendor,
+                &rule.app_class,
+            ) {
+                (Some(prefix), None, None, None, None) => prefix.to_string(),
+                (None, Some(mac), None, None, None) => format!("MAC {}", mac),
+                (None, None, Some(device_type), None, None) => {
+                    format!("device type {:?}", device_type)
+                }
+                (None, None, None, Some(vendor), None) => format!("vendor {:?}", vendor),
+                (None, None, None, None, Some(app_class)) => {
+                    format!("application class {:?}", app_class)
+                }
+                _ => {
+                    return Err(ConfigError::Invalid(
+                        "A client rule needs exactly one of prefix, mac, device_type, vendor \
+                         and app_class"
+                            .to_string(),
+                    ))
+                }
+            };
+            if let Some(mac) = &rule.mac {
+                if !is_mac(mac) {
+                    return Err(ConfigError::Invalid(format!(
+                        "Client rule for {}: not a MAC address",
+                        clients
+                    )));
+                }
+                if config.controller.is_none()
+                    && config.dhcp_leases.is_empty()
+                    && config.dual_stack.is_none()
+                {
+                    return Err(ConfigError::Invalid(format!(
+                        "Client rule for {} needs a [controller], [[dhcp_leases]] or \
+                         [dual_stack] to learn MACs from",
+                        clients
+                    )));
+                }
+            } else if let Some(app_class) = &rule.app_class {
+                if !config.app_classes.as_ref().is_some_and(|app_classes| {
+                    app_classes
+                        .classes
+                        .iter()
+                        .any(|class| class.name == *app_class)
+                }) {
+                    return Err(ConfigError::Invalid(format!(
+                        "Client rule for {} needs the class in [app_classes]",
+                        clients
+                    )));
+                }
+            } else if rule.prefix.is_none() && config.controller.is_none() {
+                return Err(ConfigError::Invalid(format!(
+                    "Client rule for {} needs a [controller] to read device metadata from",
+                    clients
+                )));
+            }
+            let wan = || {
+                rule.wan.clone().ok_or_else(|| {
+                    ConfigError::Invalid(format!("Client rule for {} needs a wan", clients))
+                })
+            };
+            let action = match rule.action {
+                ClientRuleAction::Pin => ClientRule::Pin(wan()?),
+                ClientRuleAction::PreferWan => ClientRule::PreferWan(wan()?),
+                ClientRuleAction::Exclude => ClientRule::Exclude,
+                ClientRuleAction::Realtime if config.probes.is_none() => {
+                    return Err(ConfigError::Invalid(format!(
+                        "Realtime client rule for {} needs [probes] to measure WAN latency",
+                        clients
+                    )))
+                }
+                ClientRuleAction::Realtime => ClientRule::Realtime,
+                ClientRuleAction::Weight => match rule.weight {
+                    Some(weight) if weight >= 0.0 => ClientRule::Weight(weight),
+                    _ => {
+                        return Err(ConfigError::Invalid(format!(
+                            "Client rule for {} needs a non-negative weight",
+                            clients
+                        )))
+                    }
+                },
+            };
+            let schedule = rule.schedule.clone();
+            if let Some(mac) = &rule.mac {
+                mac_rules.push(Scheduled::new(normalize_mac(mac), action, schedule));
+                continue;
+            }
+            if let Some(app_class) = &rule.app_class {
+                app_rules.push(Scheduled::new(app_class.clone(), action, schedule));
+                continue;
+            }
+            match (rule.prefix, &rule.device_type, &rule.vendor) {
+                (Some(prefix), _, _) => rules.push(Scheduled::new(prefix, action, schedule)),
+                (None, Some(device_type), _) => metadata_rules.push(Scheduled::new(
+                    MetadataMatch::DeviceType(device_type.to_lowercase()),
+                    action,
+                    schedule,
+                )),
+                (None, None, Some(vendor)) => metadata_rules.push(Scheduled::new(
+                    MetadataMatch::Vendor(vendor.to_lowercase()),
+                    action,
+                    schedule,
+                )),
+                (None, None, None) => unreachable!("checked above"),
+            }
+        }
+        for (ip, wan) in &config.pinned_ips {
+            rules.push(Scheduled::new(
+                Cidr::host(ip.addr()),
+                ClientRule::Pin(wan.clone()),
+                None,
+            ));
+        }
+        for ip in &config.excluded_ips {
+            rules.push(Scheduled::new(
+                Cidr::host(ip.addr()),
+                ClientRule::Exclude,
+                None,
+            ));
+        }
+
+        rules.sort_by_key(|rule| {
+            (
+                std::cmp::Reverse(rule.clients.prefix_len()),
+                rule.schedule.is_none(),
+            )
+        });
+        for (index, rule) in rules.iter().enumerate() {
+            if rule.schedule.is_none()
+                && rules[..index]
+                    .iter()
+                    .any(|other| other.schedule.is_none() && other.clients == rule.clients)
+            {
+                return Err(ConfigError::Invalid(format!(
+                    "{} has more than one unscheduled client rule",
+                    rule.clients
+                )));
+            }
+        }
+
+        mac_rules.sort_by_key(|rule| rule.schedule.is_none());
+        for (index, rule) in mac_rules.iter().enumerate() {
+            if rule.schedule.is_none()
+                && mac_rules[..index]
+                    .iter()
+                    .any(|other| other.schedule.is_none() && other.clients == rule.clients)
+            {
+                return Err(ConfigError::Invalid(format!(
+                    "MAC {} has more than one unscheduled client rule",
+                    rule.clients
+                )));
+            }
+        }
+
+        app_rules.sort_by_key(|rule| rule.schedule.is_none());
+        for (index, rule) in app_rules.iter().enumerate() {
+            if rule.schedule.is_none()
+                && app_rules[..index]
+                    .iter()
+                    .any(|other| other.schedule.is_none() && other.clients == rule.clients)
+            {
+                return Err(ConfigError::Invalid(format!(
+                    "Application class {} has more than one unscheduled client rule",
+                    rule.clients
+                )));
+            }
+        }
+
+        Ok(Self {
+            mac_rules,
+            rules,
+            app_rules,
+            metadata_rules,
+            app_classes: HashMap::new(),
+            metadata: HashMap::new(),
+            macs: HashMap::new(),
+            manual: HashMap::new(),
+        })
+    }
+
+    /// Opens and closes the scheduled rules for `at`, returning a description of each rule
+    /// that came into force (`true`) or stopped being in force.
+    pub fn set_time<Tz: TimeZone>(&mut self, at: &DateTime<Tz>) -> Vec<(String, bool)> {
+        let mut changes = Vec::new();
+        for rule in &mut self.mac_rules {
+            if let Some(change) = rule.update(at) {
+                changes.push((format!("MAC {} {}", rule.clients, rule.describe()), change));
+            }
+        }
+        for rule in &mut self.rules {
+            if let Some(change) = rule.update(at) {
+                changes.push((format!("{} {}", rule.clients, rule.describe()), change));
+            }
+        }
+        for rule in &mut self.app_rules {
+            if let Some(change) = rule.update(at) {
+                changes.push((
+                    format!("application class {} {}", rule.clients, rule.describe()),
+                    change,
+                ));
+            }
+        }
+        for rule in &mut self.metadata_rules {
+            if let Some(change) = rule.update(at) {
+                changes.push((format!("{} {}", rule.clients, rule.describe()), change));
+            }
+        }
+        changes
+    }
+
+    /// Replaces the controller metadata with `clients` of `(client, device type, vendor)`.
+    pub fn set_metadata<'a>(
+        &mut self,
+        clients: impl IntoIterator<Item = (ClientIp, Option<&'a str>, Option<&'a str>)>,
+    ) {
+        self.metadata = clients
+            .into_iter()
+            .map(|(ip, device_type, vendor)| {
+                (
+                    ip,
+                    (
+                        device_type.map(str::to_lowercase),
+                        vendor.map(str::to_lowercase),
+                    ),
+                )
+            })
+            .collect();
+    }
+
+    /// Replaces the known MACs with `clients` of `(client, MAC)`.
+    pub fn set_macs(&mut self, clients: impl IntoIterator<Item = (ClientIp, String)>) {
+        self.macs = clients
+            .into_iter()
+            .map(|(ip, mac)| (ip, normalize_mac(&mac)))
+            .collect();
+    }
+
+    /// Replaces the application classes with `clients` of `(client, classes busiest first)`.
+    pub fn set_app_classes(&mut self, clients: HashMap<ClientIp, Vec<String>>) {
+        self.app_classes = clients;
+    }
+
+    /// Replaces the manual pins with `pins` of `(client, WAN, requested by)`.
+    pub fn set_manual_pins(&mut self, pins: impl IntoIterator<Item = (ClientIp, WanId, String)>) {
+        self.manual = pins
+            .into_iter()
+            .map(|(ip, wan, requested_by)| {
+                let reason = format!("manually pinned to {} by {}", wan, requested_by);
+                (ip, (ClientRule::Pin(wan), reason))
+            })
+            .collect();
+    }
+
+    pub fn is_manually_pinned(&self, ip: ClientIp) -> bool {
+        self.manual.contains_key(&ip)
+    }
+
+    /// The manual pin of `ip`, or else the rule for its MAC, or else the longest-prefix
+    /// match for it, or else the rule of its busiest application class that has one, or
+    /// else the first metadata rule matching it.
+    pub fn rule(&self, ip: ClientIp) -> Option<&ClientRule> {
+        if let Some((rule, _)) = self.manual.get(&ip) {
+            return Some(rule);
+        }
+        if let Some(rule) = self.macs.get(&ip).and_then(|mac| {
+            self.mac_rules
+                .iter()
+                .find(|rule| rule.in_force && rule.clients == *mac)
+        }) {
+            return Some(&rule.rule);
+        }
+        let addr = ip.addr();
+        if let Some(rule) = self
+            .rules
+            .iter()
+            .find(|rule| rule.in_force && rule.clients.contains(&addr))
+        {
+            return Some(&rule.rule);
+        }
+        if let Some(rule) = self.app_classes.get(&ip).and_then(|classes| {
+            classes.iter().find_map(|class| {
+                self.app_rules
+                    .iter()
+                    .find(|rule| rule.in_force && rule.clients == *class)
+            })
+        }) {
+            return Some(&rule.rule);
+        }
+        let (device_type, vendor) = self.metadata.get(&ip)?;
+        self.metadata_rules
+            .iter()
+            .filter(|rule| rule.in_force)
+            .find(|rule| match &rule.clients {
+                MetadataMatch::DeviceType(wanted) => device_type.as_ref() == Some(wanted),
+                MetadataMatch::Vendor(wanted) => vendor
+                    .as_ref()
+                    .is_some_and(|vendor| vendor.contains(wanted)),
+            })
+            .map(|rule| &rule.rule)
+    }
+
+    /// Why `ip` may not be moved by the balancing, if it may not.
+    pub fn hold_reason(&self, ip: ClientIp) -> Option<String> {
+        if let Some((_, reason)) = self.manual.get(&ip) {
+            return Some(reason.clone());
+        }
+        match self.rule(ip)? {
+            ClientRule::Pin(wan) => Some(format!("pinned to {}", wan)),
+            ClientRule::Exclude => Some("excluded from switching".to_string()),
+            ClientRule::Realtime => Some("realtime client".to_string()),
+            ClientRule::PreferWan(_) | ClientRule::Weight(_) => None,
+        }
+    }
+
+    /// Why a policy should leave `ip` on `current_wan`, if it should: on top of pins and
+    /// exclusions, clients already on their preferred WAN stay there.
+    pub fn policy_hold_reason(&self, ip: ClientIp, current_wan: Option<&WanId>) -> Option<String> {
+        match self.rule(ip)? {
+            ClientRule::PreferWan(wan) if Some(wan) == current_wan => {
+                Some(format!("on its preferred {}", wan))
+            }
+            _ => self.hold_reason(ip),
+        }
+    }
+
+    pub fn preferred_wan(&self, ip: ClientIp) -> Option<&WanId> {
+        match self.rule(ip)? {
+            ClientRule::PreferWan(wan) => Some(wan),
+            _ => None,
+        }
+    }
+
+    /// Share weight of `ip` for the weighted policy.
+    pub fn weight(&self, ip: ClientIp) -> f64 {
+        match self.rule(ip) {
+            Some(ClientRule::Weight(weight)) => *weight,
+            _ => 1.0,
+        }
+    }
+
+    /// The healthy WAN with the lowest probed RTT and that RTT, for realtime clients.
+    pub fn realtime_wan<'a>(input: &PolicyInput<'a>) -> Option<(&'a WanId, f64)> {
+        let mut wans: Vec<_> = input
+            .wan_to_nic
+            .keys()
+            .filter_map(|wan| {
+                let stats = input.wan_probes.get(wan)?;
+                if stats.is_down() || stats.is_degraded() {
+                    return None;
+                }
+                Some((wan, stats.rtt_ms?))
+            })
+            .collect();
+        wans.sort_by(|a, b| a.1.total_cmp(&b.1).then_with(|| a.0.cmp(b.0)));
+        wans.into_iter().next()
+    }
+
+    /// Moves of pinned clients that are currently mapped to another WAN, and of realtime
+    /// clients onto the WAN with the lowest RTT.
+    pub fn plan(&self, input: &PolicyInput) -> Vec<SwitchDecision> {
+        let realtime_wan = Self::realtime_wan(input);
+        let mut mappings: Vec<_> = input.mappings.iter().collect();
+        mappings.sort();
+        mappings
+            .into_iter()
+            .filter_map(|(ip, current_wan)| {
+                let (wan, reason) = match self.rule(*ip) {
+                    Some(ClientRule::Pin(wan)) => (wan, self.hold_reason(*ip).unwrap_or_default()),
+                    Some(ClientRule::Realtime) => {
+                        let (wan, rtt_ms) = realtime_wan?;
+                        let current_rtt_ms = input
+                            .wan_probes
+                            .get(current_wan)
+                            .filter(|stats| !stats.is_down() && !stats.is_degraded())
+                            .and_then(|stats| stats.rtt_ms);
+                        if current_rtt_ms
+                            .is_some_and(|current| current - rtt_ms <= REALTIME_RTT_MARGIN_MS)
+                        {
+                            return None;
+                        }
+                        let reason = format!(
+                            "realtime client; {} has the lowest RTT ({:.1} ms)",
+                            wan, rtt_ms
+                        );
+                        (wan, reason)
+                    }
+                    _ => return None,
+                };
+                if current_wan == wan || !input.wan_to_nic.contains_key(wan) {
+                    return None;
+                }
+                Some(SwitchDecision {
+                    ip: *ip,
+                    from_nic: input.wan_to_nic.get(current_wan)?.clone(),
+                    target_wan: wan.clone(),
+                    rx_bps: input
+                        .ip_traffic
+                        .iter()
+                        .find(|traffic| traffic.ip == *ip)
+                        .map_or(0.0, |traffic| traffic.rx_bps),
+                    reason,
+                })
+            })
+            .collect()
+    }
+
+    /// Drops every move of a pinned or excluded client except the one onto its pin, and of
+    /// a realtime client except onto the WAN with the lowest RTT or off a dead WAN
+    /// (`evacuating`), reporting the dropped ones as skipped.
+    pub fn filter(
+        &self,
+        mut plan: PolicyPlan,
+        input: &PolicyInput,
+        evacuating: &HashSet<ClientIp>,
+    ) -> PolicyPlan {
+        let realtime_wan = Self::realtime_wan(input).map(|(wan, _)| wan);
+        let (kept, suppressed): (Vec<_>, Vec<_>) =
+            plan.switches
+                .into_iter()
+                .partition(|decision| match self.rule(decision.ip) {
+                    Some(ClientRule::Pin(wan)) => *wan == decision.target_wan,
+                    Some(ClientRule::Exclude) => false,
+                    Some(ClientRule::Realtime) => {
+                        evacuating.contains(&decision.ip)
+                            || realtime_wan == Some(&decision.target_wan)
+                    }
+                    _ => true,
+                });
+        plan.switches = kept;
+        plan.skipped
+            .extend(suppressed.into_iter().map(|decision| SkippedCandidate {
+                reason: format!(
+                    "{}; not moving it to {}",
+                    self.hold_reason(decision.ip).unwrap_or_default(),
+                    decision.target_wan
+                ),
+                ip: decision.ip,
+                nic: decision.from_nic,
+            }));
+        plan
+    }
+}
+
+impl<M> Scheduled<M> {
+    fn new(clients: M, rule: ClientRule, schedule: Option<TimeWindow>) -> Self {
+        Self {
+            clients,
+            rule,
+            // Scheduled rules wait for the first `set_time`
+            in_force: schedule.is_none(),
+            schedule,
+        }
+    }
+
+    /// The new state when it changed.
+    fn update<Tz: TimeZone>(&mut self, at: &DateTime<Tz>) -> Option<bool> {
+        let in_force = self
+            .schedule
+            .as_ref()
+            .is_none_or(|schedule| schedule.contains(at));
+        (in_force != self.in_force).then(|| {
+            self.in_force = in_force;
+            in_force
+        })
+    }
+
+    fn describe(&self) -> String {
+        let action = match &self.rule {
+            ClientRule::Pin(wan) => format!("pin to {}", wan),
+            ClientRule::Exclude => "exclude".to_string(),
+            ClientRule::PreferWan(wan) => format!("prefer {}", wan),
+            ClientRule::Weight(weight) => format!("weight {}", weight),
+            ClientRule::Realtime => "realtime".to_string(),
+        };
+        match &self.schedule {
+            Some(schedule) => format!("{} during {}", action, schedule),
+            None => action,
+        }
+    }
+}
+
+impl std::fmt::Display for MetadataMatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MetadataMatch::DeviceType(device_type) => write!(f, "device type {:?}", device_type),
+            MetadataMatch::Vendor(vendor) => write!(f, "vendor {:?}", vendor),
+        }
+    }
+}
@@ -0,0 +1,110 @@
+use chrono::{DateTime, Local};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::Notify;
+
+/// Source of the current time for the balancing loop and everything it times:
+/// cooldowns, schedules, history ages, rate windows and the wait between cycles.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> SystemTime;
+
+    /// Waits `duration` of this clock's time.
+    fn sleep(&self, duration: Duration) -> Pin<Box<dyn Future<Output = ()> + Send + '_>>;
+
+    /// Waits until the clock reads `deadline`, without moving it, for the background tasks
+    /// that run alongside the balancing loop.
+    fn sleep_until(&self, deadline: SystemTime) -> Pin<Box<dyn Future<Output = ()> + Send + '_>>;
+
+    /// Time passed since `earlier`, a reading of this clock.
+    fn since(&self, earlier: SystemTime) -> Duration {
+        self.now().duration_since(earlier).unwrap_or_default()
+    }
+
+    /// Seconds since the Unix epoch.
+    fn unix_secs(&self) -> u64 {
+        self.now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+    }
+
+    fn unix_millis(&self) -> i64 {
+        self.now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as i64
+    }
+
+    /// Wall-clock time in the local timezone, for schedules.
+    fn local(&self) -> DateTime<Local> {
+        DateTime::from(self.now())
+    }
+}
+
+/// The real time.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    fn sleep(&self, duration: Duration) -> Pin<Box<dyn Future<Output = ()> + Send + '_>> {
+        Box::pin(tokio::time::sleep(duration))
+    }
+
+    fn sleep_until(&self, deadline: SystemTime) -> Pin<Box<dyn Future<Output = ()> + Send + '_>> {
+        let duration = deadline
+            .duration_since(SystemTime::now())
+            .unwrap_or_default();
+        Box::pin(tokio::time::sleep(duration))
+    }
+}
+
+/// Simulated time that only moves when advanced: sleeping advances it at once instead of
+/// waiting, so runs against it go as fast as the backends answer.
+pub struct ManualClock {
+    now: Mutex<SystemTime>,
+    advanced: Notify,
+}
+
+impl ManualClock {
+    pub fn new(start: SystemTime) -> Self {
+        Self {
+            now: Mutex::new(start),
+            advanced: Notify::new(),
+        }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock().unwrap() += duration;
+        self.advanced.notify_waiters();
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> SystemTime {
+        *self.now.lock().unwrap()
+    }
+
+    fn sleep(&self, duration: Duration) -> Pin<Box<dyn Future<Output = ()> + Send + '_>> {
+        self.advance(duration);
+        // Still yield, so other tasks get to run between simulated cycles
+        Box::pin(tokio::task::yield_now())
+    }
+
+    fn sleep_until(&self, deadline: SystemTime) -> Pin<Box<dyn Future<Output = ()> + Send + '_>> {
+        Box::pin(async move {
+            loop {
+                // Registered before reading the time, so an advance in between still wakes it
+                let advanced = self.advanced.notified();
+                if self.now() >= deadline {
+                    return;
+                }
+                advanced.await;
+            }
+        })
+    }
+}
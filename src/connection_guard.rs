@@ -0,0 +1,122 @@
+use crate::config::ConnectionProtectConfig;
+use crate::model::ClientIp;
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+/// A client's connections that a switch would break.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BusyConnections {
+    pub count: usize,
+    pub bytes: u64,
+}
+
+/// Why a switch waits for a client's connections.
+#[derive(Debug, Clone, Copy)]
+pub struct Deferral {
+    pub connections: BusyConnections,
+    /// Until the switch is made anyway; `None` without a limit.
+    pub remaining_secs: Option<u64>,
+}
+
+/// Defers switches of clients with long-lived established TCP connections that have carried
+/// enough data to matter: NAT on the new WAN gives them a different public address, so
+/// they break mid-transfer. The connection table is read at most once per scan.
+pub struct ConnectionGuard {
+    config: ConnectionProtectConfig,
+    /// The scan the table was read in, and the busy clients in it.
+    read: Option<(u64, HashMap<IpAddr, BusyConnections>)>,
+    /// Client → when its switch was first deferred.
+    deferred_since: HashMap<ClientIp, u64>,
+}
+
+impl ConnectionGuard {
+    pub fn new(config: ConnectionProtectConfig) -> Self {
+        Self {
+            config,
+            read: None,
+            deferred_since: HashMap::new(),
+        }
+    }
+
+    /// Whether switching `ip` now should wait. A table that cannot be read defers nothing.
+    pub fn check(&mut self, ip: ClientIp, now: u64) -> Result<Option<Deferral>> {
+        if self.read.as_ref().map(|(at, _)| *at) != Some(now) {
+            let text = std::fs::read_to_string(&self.config.path)
+                .with_context(|| format!("Failed to read {}", self.config.path.display()))?;
+            let busy = busy_connections(&text, self.config.min_bytes, self.config.min_age_secs);
+            self.deferred_since
+                .retain(|ip, _| busy.contains_key(&ip.addr()));
+            self.read = Some((now, busy));
+        }
+        let Some((_, busy)) = &self.read else {
+            return Ok(None);
+        };
+        let Some(connections) = busy.get(&ip.addr()).copied() else {
+            self.deferred_since.remove(&ip);
+            return Ok(None);
+        };
+
+        let since = *self.deferred_since.entry(ip).or_insert(now);
+        let remaining_secs = match self.config.max_defer_secs {
+            0 => None,
+            max_defer_secs => {
+                let remaining_secs = (since + max_defer_secs).saturating_sub(now);
+                if remaining_secs == 0 {
+                    // Deferred long enough; the switch goes ahead
+                    self.deferred_since.remove(&ip);
+                    return Ok(None);
+                }
+                Some(remaining_secs)
+            }
+        };
+        Ok(Some(Deferral {
+            connections,
+            remaining_secs,
+        }))
+    }
+}
+
+/// Established TCP connections per originating address in `/proc/net/nf_conntrack` with at
+/// least `min_bytes` in both directions together and, where the kernel reports their age
+/// (`delta-time=`), at least `min_age_secs` old.
+fn busy_connections(
+    text: &str,
+    min_bytes: u64,
+    min_age_secs: u64,
+) -> HashMap<IpAddr, BusyConnections> {
+    let mut busy: HashMap<IpAddr, BusyConnections> = HashMap::new();
+    for line in text.lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.get(2) != Some(&"tcp") || !fields.contains(&"ESTABLISHED") {
+            continue;
+        }
+        let values = |key: &str| {
+            fields
+                .iter()
+                .filter_map(|field| field.strip_prefix(key))
+                .collect::<Vec<_>>()
+        };
+        // The first tuple is the original direction, whose source is the client
+        let Some(source) = values("src=")
+            .first()
+            .and_then(|source| source.parse::<IpAddr>().ok())
+        else {
+            continue;
+        };
+        let bytes: u64 = values("bytes=")
+            .iter()
+            .filter_map(|bytes| bytes.parse::<u64>().ok())
+            .sum();
+        let old_enough = values("delta-time=")
+            .first()
+            .and_then(|age| age.parse::<u64>().ok())
+            .is_none_or(|age_secs| age_secs >= min_age_secs);
+        if bytes >= min_bytes && old_enough {
+            let entry = busy.entry(source).or_default();
+            entry.count += 1;
+            entry.bytes += bytes;
+        }
+    }
+    busy
+}
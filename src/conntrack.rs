@@ -0,0 +1,88 @@
+use crate::netlink::{self, NetlinkError, NetlinkSocket};
+use anyhow::{bail, Result};
+use std::net::IpAddr;
+
+const NETLINK_NETFILTER: i32 = 12;
+
+const NFNL_SUBSYS_CTNETLINK: u16 = 1;
+const IPCTNL_MSG_CT_GET: u16 = 1;
+const IPCTNL_MSG_CT_DELETE: u16 = 2;
+
+const CTA_TUPLE_ORIG: u16 = 1;
+const CTA_ZONE: u16 = 18;
+const CTA_TUPLE_IP: u16 = 1;
+const CTA_IP_V4_SRC: u16 = 1;
+const CTA_IP_V6_SRC: u16 = 3;
+
+const NFGENMSG_LEN: usize = 4;
+
+/// Deletes every conntrack entry originated by `ip` over ctnetlink, so its existing flows
+/// re-establish over the new WAN instead of lingering on the old path until they time out.
+/// Returns the number of entries removed. Needs `CAP_NET_ADMIN`.
+pub fn flush_client(ip: IpAddr) -> Result<usize> {
+    let mut socket = NetlinkSocket::open(NETLINK_NETFILTER)?;
+
+    let family = match ip {
+        IpAddr::V4(_) => 2,
+        IpAddr::V6(_) => 10,
+    };
+    let entries: Vec<Vec<u8>> = socket
+        .dump(
+            (NFNL_SUBSYS_CTNETLINK << 8) | IPCTNL_MSG_CT_GET,
+            &nfgenmsg(family),
+        )?
+        .iter()
+        .filter_map(|payload| payload.get(NFGENMSG_LEN..))
+        .filter(|attributes| originated_by(attributes, ip))
+        .map(identifying_attributes)
+        .collect();
+
+    let mut flushed = 0;
+    for attributes in entries {
+        // Entries may disappear between dump and delete
+        let mut request = nfgenmsg(family).to_vec();
+        request.extend_from_slice(&attributes);
+        match socket.request(
+            (NFNL_SUBSYS_CTNETLINK << 8) | IPCTNL_MSG_CT_DELETE,
+            &request,
+        ) {
+            Ok(()) => flushed += 1,
+            Err(NetlinkError::Errno(2)) => {}
+            Err(NetlinkError::Errno(errno)) => {
+                bail!("Failed to delete conntrack entry: errno {}", errno)
+            }
+            Err(NetlinkError::Io(e)) => return Err(e),
+        }
+    }
+    Ok(flushed)
+}
+
+/// The nfnetlink header of a request for address `family`.
+fn nfgenmsg(family: u8) -> [u8; NFGENMSG_LEN] {
+    [family, 0, 0, 0]
+}
+
+fn originated_by(attributes: &[u8], ip: IpAddr) -> bool {
+    let source = netlink::find(attributes, CTA_TUPLE_ORIG)
+        .and_then(|tuple| netlink::find(tuple, CTA_TUPLE_IP))
+        .and_then(|addresses| match ip {
+            IpAddr::V4(_) => netlink::find(addresses, CTA_IP_V4_SRC),
+            IpAddr::V6(_) => netlink::find(addresses, CTA_IP_V6_SRC),
+        });
+    match (source, ip) {
+        (Some(source), IpAddr::V4(ip)) => source == ip.octets(),
+        (Some(source), IpAddr::V6(ip)) => source == ip.octets(),
+        _ => false,
+    }
+}
+
+fn identifying_attributes(attributes: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    for (attribute_type, attribute, _) in netlink::attributes(attributes) {
+        if attribute_type == CTA_TUPLE_ORIG || attribute_type == CTA_ZONE {
+            out.extend_from_slice(attribute);
+            out.resize(netlink::align(out.len()), 0);
+        }
+    }
+    out
+}
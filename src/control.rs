@@ -0,0 +1,379 @@
+use crate::cli::{PauseArgs, PinArgs, PolicyArgs, ResumeArgs, SwitchArgs};
+use crate::config::{Config, WeightedPolicyConfig};
+use crate::error::ConfigError;
+use crate::maintenance::MaintenanceStatus;
+use crate::mode::ModeStatus;
+use crate::model::{ClientIp, WanId};
+use crate::policy::{self, SwitchPolicy};
+use crate::report::CycleReport;
+use anyhow::{bail, Context, Result};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Longest pin or pause the API accepts: a year.
+pub const MAX_DURATION_SECS: u64 = 365 * 24 * 60 * 60;
+
+/// A policy change as submitted over the API; parameters default to the configured ones.
+#[derive(Debug, Clone, Deserialize)]
+pub struct PolicyChangeRequest {
+    pub policy: String,
+    #[serde(default)]
+    pub weighted: Option<WeightedPolicyConfig>,
+}
+
+/// A validated policy change waiting to be picked up by the balancing loop.
+pub struct PendingPolicyChange {
+    pub policy: Box<dyn SwitchPolicy>,
+    pub requested_by: String,
+}
+
+/// The active policy and the one being warmed up, as published by the balancing loop.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PolicyStatus {
+    pub active: String,
+    pub shadow: Option<ShadowStatus>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ShadowStatus {
+    pub policy: String,
+    pub requested_by: String,
+    pub remaining_secs: u64,
+}
+
+/// A temporary manual placement as submitted over the API; `wan: None` releases the pin.
+#[derive(Debug, Clone, Deserialize)]
+pub struct PinRequest {
+    pub ip: ClientIp,
+    #[serde(default)]
+    pub wan: Option<WanId>,
+    #[serde(default)]
+    pub duration_secs: u64,
+}
+
+pub struct PendingPin {
+    pub request: PinRequest,
+    pub requested_by: String,
+}
+
+/// An active manual pin, as published by the balancing loop.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManualPin {
+    pub ip: ClientIp,
+    pub wan: WanId,
+    pub requested_by: String,
+    pub expires_at: u64,
+}
+
+/// A pause of the balancing as submitted over the API; without a duration it lasts until
+/// resumed.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct PauseRequest {
+    #[serde(default)]
+    pub duration_secs: Option<u64>,
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
+pub enum PauseChange {
+    Pause(PauseRequest),
+    Resume,
+}
+
+pub struct PendingPause {
+    pub change: PauseChange,
+    pub requested_by: String,
+}
+
+/// The active pause, as published by the balancing loop.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PauseStatus {
+    pub requested_by: String,
+    pub reason: Option<String>,
+    pub since: u64,
+    pub until: Option<u64>,
+}
+
+/// A one-off move as submitted over the API. Unlike a pin, the policies may move the
+/// client again later.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SwitchRequest {
+    pub ip: ClientIp,
+    pub wan: WanId,
+}
+
+pub struct PendingSwitch {
+    pub request: SwitchRequest,
+    pub requested_by: String,
+}
+
+/// What `GET /state` returns.
+#[derive(Debug, Serialize)]
+pub struct BalancerState {
+    pub policy: PolicyStatus,
+    pub paused: Option<PauseStatus>,
+    pub manual_pins: Vec<ManualPin>,
+    /// The last cycle's report as printed with `--output json`; `None` before the first.
+    pub cycle: Option<serde_json::Value>,
+    /// `None` before the first cycle.
+    pub mode: Option<ModeStatus>,
+    /// The maintenance window switching is frozen for; `None` outside of one.
+    pub maintenance: Option<MaintenanceStatus>,
+}
+
+/// Runtime requests from the API to the balancing loop.
+pub struct Control {
+    /// How long a requested policy runs in shadow mode before it takes control.
+    pub shadow_secs: u64,
+    default_weighted: WeightedPolicyConfig,
+    pending_policy: Mutex<Option<PendingPolicyChange>>,
+    policy_status: Mutex<PolicyStatus>,
+    pending_pins: Mutex<Vec<PendingPin>>,
+    manual_pins: Mutex<Vec<ManualPin>>,
+    pending_pause: Mutex<Option<PendingPause>>,
+    pause_status: Mutex<Option<PauseStatus>>,
+    pending_switches: Mutex<Vec<PendingSwitch>>,
+    latest_cycle: Mutex<Option<serde_json::Value>>,
+    mode: Mutex<Option<ModeStatus>>,
+    maintenance: Mutex<Option<MaintenanceStatus>>,
+}
+
+impl Control {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            shadow_secs: config.policy_shadow_secs,
+            default_weighted: config.weighted.clone(),
+            pending_policy: Mutex::new(None),
+            policy_status: Mutex::new(PolicyStatus {
+                active: config.policy.clone(),
+                shadow: None,
+            }),
+            pending_pins: Mutex::new(Vec::new()),
+            manual_pins: Mutex::new(Vec::new()),
+            pending_pause: Mutex::new(None),
+            pause_status: Mutex::new(None),
+            pending_switches: Mutex::new(Vec::new()),
+            latest_cycle: Mutex::new(None),
+            mode: Mutex::new(None),
+            maintenance: Mutex::new(None),
+        }
+    }
+
+    /// Validates `request` and queues it, replacing any change not yet picked up.
+    pub fn request_policy(
+        &self,
+        request: &PolicyChangeRequest,
+        requested_by: &str,
+    ) -> Result<(), ConfigError> {
+        let weighted = request.weighted.as_ref().unwrap_or(&self.default_weighted);
+        let policy = policy::build(&request.policy, weighted)?;
+        *self.pending_policy.lock().unwrap() = Some(PendingPolicyChange {
+            policy,
+            requested_by: requested_by.to_string(),
+        });
+        Ok(())
+    }
+
+    pub fn take_policy_request(&self) -> Option<PendingPolicyChange> {
+        self.pending_policy.lock().unwrap().take()
+    }
+
+    pub fn set_policy_status(&self, status: PolicyStatus) {
+        *self.policy_status.lock().unwrap() = status;
+    }
+
+    pub fn policy_status(&self) -> PolicyStatus {
+        self.policy_status.lock().unwrap().clone()
+    }
+
+    /// Validates `request` and queues it for the next cycle.
+    pub fn request_pin(&self, request: PinRequest, requested_by: &str) -> Result<(), ConfigError> {
+        if request.wan.is_some() && request.duration_secs == 0 {
+            return Err(ConfigError::Invalid(
+                "A manual pin needs a duration".to_string(),
+            ));
+        }
+        if request.duration_secs > MAX_DURATION_SECS {
+            return Err(ConfigError::Invalid(format!(
+                "A manual pin lasts at most {}s",
+                MAX_DURATION_SECS
+            )));
+        }
+        self.pending_pins.lock().unwrap().push(PendingPin {
+            request,
+            requested_by: requested_by.to_string(),
+        });
+        Ok(())
+    }
+
+    pub fn take_pin_requests(&self) -> Vec<PendingPin> {
+        std::mem::take(&mut *self.pending_pins.lock().unwrap())
+    }
+
+    pub fn set_manual_pins(&self, pins: Vec<ManualPin>) {
+        *self.manual_pins.lock().unwrap() = pins;
+    }
+
+    pub fn manual_pins(&self) -> Vec<ManualPin> {
+        self.manual_pins.lock().unwrap().clone()
+    }
+
+    /// Queues a pause or resume, replacing any not yet picked up.
+    pub fn request_pause(&self, change: PauseChange, requested_by: &str) {
+        *self.pending_pause.lock().unwrap() = Some(PendingPause {
+            change,
+            requested_by: requested_by.to_string(),
+        });
+    }
+
+    pub fn take_pause_request(&self) -> Option<PendingPause> {
+        self.pending_pause.lock().unwrap().take()
+    }
+
+    pub fn set_pause_status(&self, status: Option<PauseStatus>) {
+        *self.pause_status.lock().unwrap() = status;
+    }
+
+    /// Queues a one-off switch for the next cycle.
+    pub fn request_switch(&self, request: SwitchRequest, requested_by: &str) {
+        self.pending_switches.lock().unwrap().push(PendingSwitch {
+            request,
+            requested_by: requested_by.to_string(),
+        });
+    }
+
+    pub fn take_switch_requests(&self) -> Vec<PendingSwitch> {
+        std::mem::take(&mut *self.pending_switches.lock().unwrap())
+    }
+
+    pub fn set_mode(&self, status: ModeStatus) {
+        *self.mode.lock().unwrap() = Some(status);
+    }
+
+    pub fn set_maintenance(&self, status: Option<MaintenanceStatus>) {
+        *self.maintenance.lock().unwrap() = status;
+    }
+
+    pub fn record_cycle(&self, report: &CycleReport) {
+        if let Ok(report) = serde_json::to_value(report) {
+            *self.latest_cycle.lock().unwrap() = Some(report);
+        }
+    }
+
+    pub fn state(&self) -> BalancerState {
+        BalancerState {
+            policy: self.policy_status(),
+            paused: self.pause_status.lock().unwrap().clone(),
+            manual_pins: self.manual_pins(),
+            cycle: self.latest_cycle.lock().unwrap().clone(),
+            mode: self.mode.lock().unwrap().clone(),
+            maintenance: self.maintenance.lock().unwrap().clone(),
+        }
+    }
+}
+
+/// Base URL of the running instance's API.
+fn api_url(config: &Config, path: &str) -> Result<String> {
+    let Some(listen) = config.server.listen else {
+        bail!("The HTTP API is disabled ([server] listen is not set)");
+    };
+    Ok(format!("http://{}{}", listen, path))
+}
+
+/// Sends `request` and prints the response body.
+async fn send_api_request(
+    url: &str,
+    request: reqwest::RequestBuilder,
+    api_key: Option<&str>,
+) -> Result<()> {
+    let request = match api_key {
+        Some(api_key) => request.bearer_auth(api_key),
+        None => request,
+    };
+
+    let response = request
+        .send()
+        .await
+        .with_context(|| format!("Failed to reach {}", url))?;
+    let status = response.status();
+    let body = response.text().await.unwrap_or_default();
+    if !status.is_success() {
+        bail!("{} ({})", body.trim(), status);
+    }
+    println!("{}", body.trim_end());
+    Ok(())
+}
+
+/// Implements the `policy` subcommand against the running instance's API.
+pub async fn run_policy_command(config: &Config, args: &PolicyArgs) -> Result<()> {
+    let url = api_url(config, "/policy")?;
+    let client = Client::new();
+
+    let request = match &args.name {
+        Some(name) => {
+            let mut body = json!({ "policy": name });
+            if !args.weights.is_empty() {
+                let weights: HashMap<&str, f64> = args
+                    .weights
+                    .iter()
+                    .map(|(wan, weight)| (wan.as_str(), *weight))
+                    .collect();
+                body["weighted"] = json!({ "weights": weights });
+            }
+            client.post(&url).json(&body)
+        }
+        None => client.get(&url),
+    };
+    send_api_request(&url, request, args.api_key.as_deref()).await
+}
+
+/// Implements the `pin` subcommand against the running instance's API.
+pub async fn run_pin_command(config: &Config, args: &PinArgs) -> Result<()> {
+    let url = api_url(config, "/pins")?;
+    let client = Client::new();
+
+    let request = match (&args.ip, &args.wan) {
+        (None, _) => client.get(&url),
+        (Some(ip), None) if args.release => client.post(&url).json(&json!({ "ip": ip })),
+        (Some(_), None) => bail!("Give the WAN to pin to, or --release to end the pin"),
+        (Some(ip), Some(wan)) => {
+            let Some(duration_secs) = args.duration_secs else {
+                bail!("Give the pin's duration with --for (e.g. --for 2h)");
+            };
+            client.post(&url).json(&json!({
+                "ip": ip,
+                "wan": wan,
+                "duration_secs": duration_secs,
+            }))
+        }
+    };
+    send_api_request(&url, request, args.api_key.as_deref()).await
+}
+
+/// Implements the `pause` subcommand against the running instance's API.
+pub async fn run_pause_command(config: &Config, args: &PauseArgs) -> Result<()> {
+    let url = api_url(config, "/pause")?;
+    let request = Client::new().post(&url).json(&json!({
+        "duration_secs": args.duration_secs,
+        "reason": args.reason,
+    }));
+    send_api_request(&url, request, args.api_key.as_deref()).await
+}
+
+/// Implements the `resume` subcommand against the running instance's API.
+pub async fn run_resume_command(config: &Config, args: &ResumeArgs) -> Result<()> {
+    let url = api_url(config, "/resume")?;
+    send_api_request(&url, Client::new().post(&url), args.api_key.as_deref()).await
+}
+
+/// Implements the `switch` subcommand against the running instance's API.
+pub async fn run_switch_command(config: &Config, args: &SwitchArgs) -> Result<()> {
+    let url = api_url(config, "/switch")?;
+    let request = Client::new()
+        .post(&url)
+        .json(&json!({ "ip": args.ip, "wan": args.wan }));
+    send_api_request(&url, request, args.api_key.as_deref()).await
+}
@@ -0,0 +1,402 @@
+use crate::config::{ControllerConfig, ControllerKind};
+use crate::error::ConfigError;
+use crate::model::ClientIp;
+use anyhow::{bail, Context, Result};
+use reqwest::header::{HeaderMap, COOKIE, SET_COOKIE};
+use reqwest::{Certificate, Client, RequestBuilder};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{debug, warn};
+
+/// Clients requested per page from Omada.
+const OMADA_PAGE_SIZE: usize = 1000;
+
+/// What the network controller knows about a client.
+#[derive(Debug, Clone, Serialize)]
+pub struct ClientInfo {
+    pub mac: String,
+    /// Every address the controller has seen the client use.
+    pub addresses: Vec<ClientIp>,
+    /// The alias set on the controller, or else the client's hostname.
+    pub name: Option<String>,
+    /// Omada's device type (e.g. `camera`); UniFi reports none.
+    pub device_type: Option<String>,
+    /// Manufacturer, from the MAC's OUI.
+    pub vendor: Option<String>,
+    pub wired: bool,
+}
+
+/// Reads the client list of a UniFi or Omada controller every `refresh_secs` in the
+/// background, so the balancing can use the names and device types set up there.
+pub struct Controller {
+    clients: Arc<Mutex<HashMap<ClientIp, ClientInfo>>>,
+}
+
+impl Controller {
+    pub fn spawn(config: ControllerConfig) -> Result<Self, ConfigError> {
+        let mut builder = Client::builder()
+            .danger_accept_invalid_certs(config.insecure_skip_verify)
+            .timeout(Duration::from_secs(30));
+        if let Some(ca_file) = &config.ca_file {
+            let certificate = std::fs::read(ca_file)
+                .map_err(|e| e.to_string())
+                .and_then(|pem| Certificate::from_pem(&pem).map_err(|e| e.to_string()))
+                .map_err(|e| {
+                    ConfigError::Invalid(format!(
+                        "Invalid controller CA file {}: {}",
+                        ca_file.display(),
+                        e
+                    ))
+                })?;
+            builder = builder.add_root_certificate(certificate);
+        }
+        let http = builder.build().map_err(|e| {
+            ConfigError::Invalid(format!("Failed to set up controller client: {}", e))
+        })?;
+
+        let clients = Arc::new(Mutex::new(HashMap::new()));
+        let session = Session {
+            http,
+            config,
+            cookie: None,
+            omada: None,
+        };
+        tokio::spawn(run(session, clients.clone()));
+        Ok(Self { clients })
+    }
+
+    /// Client address → what the controller knows about the client, as of the last
+    /// successful read.
+    pub fn snapshot(&self) -> HashMap<ClientIp, ClientInfo> {
+        self.clients.lock().unwrap().clone()
+    }
+}
+
+async fn run(mut session: Session, clients: Arc<Mutex<HashMap<ClientIp, ClientInfo>>>) {
+    let mut interval =
+        tokio::time::interval(Duration::from_secs(session.config.refresh_secs.max(1)));
+    loop {
+        interval.tick().await;
+        match session.fetch().await {
+            Ok(list) => {
+                debug!(clients = list.len(), "Read clients from the controller");
+                let mut by_address = HashMap::new();
+                for client in list {
+                    for ip in &client.addresses {
+                        by_address.insert(*ip, client.clone());
+                    }
+                }
+                *clients.lock().unwrap() = by_address;
+            }
+            Err(e) => {
+                // Log in again next time, in case the session expired
+                session.cookie = None;
+                session.omada = None;
+                warn!("Failed to read clients from the controller: {:#}", e);
+            }
+        }
+    }
+}
+
+/// A logged-in session with the controller.
+struct Session {
+    http: Client,
+    config: ControllerConfig,
+    /// `Cookie` header carrying the session.
+    cookie: Option<String>,
+    omada: Option<OmadaSession>,
+}
+
+struct OmadaSession {
+    controller_id: String,
+    csrf_token: String,
+    site_id: Option<String>,
+}
+
+impl Session {
+    async fn fetch(&mut self) -> Result<Vec<ClientInfo>> {
+        match self.config.kind {
+            ControllerKind::Unifi => self.fetch_unifi().await,
+            ControllerKind::Omada => self.fetch_omada().await,
+        }
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.config.url.trim_end_matches('/'), path)
+    }
+
+    fn credentials(&self) -> serde_json::Value {
+        serde_json::json!({
+            "username": self.config.username,
+            "password": self.config.password,
+        })
+    }
+
+    fn authorized(&self, request: RequestBuilder) -> RequestBuilder {
+        let request = match &self.cookie {
+            Some(cookie) => request.header(COOKIE, cookie),
+            None => request,
+        };
+        match &self.omada {
+            Some(omada) => request.header("Csrf-Token", &omada.csrf_token),
+            None => request,
+        }
+    }
+
+    async fn get<T: DeserializeOwned>(&self, url: &str) -> Result<T> {
+        let response = self
+            .authorized(self.http.get(url))
+            .send()
+            .await
+            .with_context(|| format!("Failed to reach {}", url))?;
+        if !response.status().is_success() {
+            bail!("{} answered {}", url, response.status());
+        }
+        response
+            .json()
+            .await
+            .with_context(|| format!("Unexpected response from {}", url))
+    }
+
+    /// Logs in with the configured account, keeping the session cookie.
+    async fn login(&mut self, url: &str) -> Result<serde_json::Value> {
+        let response = self
+            .http
+            .post(url)
+            .json(&self.credentials())
+            .send()
+            .await
+            .with_context(|| format!("Failed to reach {}", url))?;
+        if !response.status().is_success() {
+            bail!("Login at {} failed: {}", url, response.status());
+        }
+        self.cookie = session_cookie(response.headers());
+        response
+            .json()
+            .await
+            .with_context(|| format!("Unexpected login response from {}", url))
+    }
+
+    async fn fetch_unifi(&mut self) -> Result<Vec<ClientInfo>> {
+        let (login_path, api_prefix) = if self.config.unifi_os {
+            ("/api/auth/login", "/proxy/network")
+        } else {
+            ("/api/login", "")
+        };
+        if self.cookie.is_none() {
+            self.login(&self.url(login_path)).await?;
+        }
+        let site = self.config.site.as_deref().unwrap_or("default");
+        let url = self.url(&format!(
+            "{}/api/s/{}/stat/sta",
+            api_prefix,
+            urlencoding::encode(site)
+        ));
+        let response: UnifiResponse = self.get(&url).await?;
+        Ok(response
+            .data
+            .into_iter()
+            .filter_map(UnifiClient::into_info)
+            .collect())
+    }
+
+    async fn fetch_omada(&mut self) -> Result<Vec<ClientInfo>> {
+        if self.cookie.is_none() || self.omada.is_none() {
+            let info: OmadaResponse<OmadaInfo> = self.get(&self.url("/api/info")).await?;
+            let controller_id = info.result()?.omadac_id;
+            let login: OmadaResponse<OmadaLogin> = serde_json::from_value(
+                self.login(&self.url(&format!("/{}/api/v2/login", controller_id)))
+                    .await?,
+            )
+            .context("Unexpected Omada login response")?;
+            self.omada = Some(OmadaSession {
+                controller_id,
+                csrf_token: login.result()?.token,
+                site_id: None,
+            });
+        }
+        let (controller_id, site_id) = match &self.omada {
+            Some(omada) => (omada.controller_id.clone(), omada.site_id.clone()),
+            None => bail!("Not logged in to the Omada controller"),
+        };
+
+        let site_id = match site_id {
+            Some(site_id) => site_id,
+            None => {
+                let site = self.config.site.as_deref().unwrap_or("Default");
+                let sites: OmadaResponse<OmadaPage<OmadaSite>> = self
+                    .get(&self.url(&format!(
+                        "/{}/api/v2/sites?currentPage=1&currentPageSize={}",
+                        controller_id, OMADA_PAGE_SIZE
+                    )))
+                    .await?;
+                let site_id = sites
+                    .result()?
+                    .data
+                    .into_iter()
+                    .find(|candidate| candidate.name == site)
+                    .map(|site| site.id)
+                    .with_context(|| format!("The Omada controller has no site {:?}", site))?;
+                if let Some(omada) = self.omada.as_mut() {
+                    omada.site_id = Some(site_id.clone());
+                }
+                site_id
+            }
+        };
+
+        let mut clients = Vec::new();
+        for page in 1.. {
+            let response: OmadaResponse<OmadaPage<OmadaClient>> = self
+                .get(&self.url(&format!(
+                    "/{}/api/v2/sites/{}/clients?currentPage={}&currentPageSize={}",
+                    controller_id, site_id, page, OMADA_PAGE_SIZE
+                )))
+                .await?;
+            let page = response.result()?;
+            let last = page.data.len() < OMADA_PAGE_SIZE
+                || clients.len() + page.data.len() >= page.total_rows;
+            clients.extend(page.data.into_iter().filter_map(OmadaClient::into_info));
+            if last {
+                break;
+            }
+        }
+        Ok(clients)
+    }
+}
+
+/// The `name=value` pairs of the response's cookies, as a `Cookie` header.
+fn session_cookie(headers: &HeaderMap) -> Option<String> {
+    let cookies: Vec<&str> = headers
+        .get_all(SET_COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok()?.split(';').next())
+        .collect();
+    (!cookies.is_empty()).then(|| cookies.join("; "))
+}
+
+fn parse_addresses(ip: Option<&str>, ipv6: &[String]) -> Vec<ClientIp> {
+    let mut addresses: Vec<ClientIp> = ip
+        .into_iter()
+        .chain(ipv6.iter().map(String::as_str))
+        .filter_map(|address| address.parse().ok())
+        .collect();
+    addresses.sort();
+    addresses.dedup();
+    addresses
+}
+
+fn non_empty(value: Option<String>) -> Option<String> {
+    value.filter(|value| !value.trim().is_empty())
+}
+
+#[derive(Deserialize)]
+struct UnifiResponse {
+    data: Vec<UnifiClient>,
+}
+
+#[derive(Deserialize)]
+struct UnifiClient {
+    mac: String,
+    ip: Option<String>,
+    #[serde(default)]
+    ipv6_addresses: Vec<String>,
+    name: Option<String>,
+    hostname: Option<String>,
+    oui: Option<String>,
+    #[serde(default)]
+    is_wired: bool,
+}
+
+impl UnifiClient {
+    fn into_info(self) -> Option<ClientInfo> {
+        let addresses = parse_addresses(self.ip.as_deref(), &self.ipv6_addresses);
+        (!addresses.is_empty()).then(|| ClientInfo {
+            mac: self.mac.to_lowercase(),
+            addresses,
+            name: non_empty(self.name).or(non_empty(self.hostname)),
+            device_type: None,
+            vendor: non_empty(self.oui),
+            wired: self.is_wired,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct OmadaResponse<T> {
+    error_code: i64,
+    msg: Option<String>,
+    result: Option<T>,
+}
+
+impl<T> OmadaResponse<T> {
+    fn result(self) -> Result<T> {
+        if self.error_code != 0 {
+            bail!(
+                "Omada error {}: {}",
+                self.error_code,
+                self.msg.unwrap_or_default()
+            );
+        }
+        self.result.context("Omada response without a result")
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct OmadaInfo {
+    omadac_id: String,
+}
+
+#[derive(Deserialize)]
+struct OmadaLogin {
+    token: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct OmadaPage<T> {
+    #[serde(default)]
+    total_rows: usize,
+    data: Vec<T>,
+}
+
+#[derive(Deserialize)]
+struct OmadaSite {
+    id: String,
+    name: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct OmadaClient {
+    mac: String,
+    ip: Option<String>,
+    #[serde(default)]
+    ipv6_list: Vec<String>,
+    name: Option<String>,
+    host_name: Option<String>,
+    device_type: Option<String>,
+    vendor: Option<String>,
+    #[serde(default)]
+    wireless: bool,
+}
+
+impl OmadaClient {
+    fn into_info(self) -> Option<ClientInfo> {
+        let addresses = parse_addresses(self.ip.as_deref(), &self.ipv6_list);
+        // Omada shows the MAC as the name of clients nobody named
+        let name = non_empty(self.name).filter(|name| !name.eq_ignore_ascii_case(&self.mac));
+        (!addresses.is_empty()).then(|| ClientInfo {
+            mac: self.mac.to_lowercase().replace('-', ":"),
+            addresses,
+            name: name.or(non_empty(self.host_name)),
+            device_type: non_empty(self.device_type),
+            vendor: non_empty(self.vendor),
+            wired: !self.wireless,
+        })
+    }
+}
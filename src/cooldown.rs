@@ -0,0 +1,176 @@
+use crate::cidr::Cidr;
+use crate::classify::TrafficClassifier;
+use crate::config::Config;
+use crate::history::SwitchHistory;
+use crate::history_db::StoredResidency;
+use crate::model::{ClientIp, IpTraffic};
+use std::collections::HashMap;
+use std::fmt;
+
+/// Why an IP is currently held in place.
+#[derive(Debug, Clone, PartialEq)]
+pub enum HoldReason {
+    /// The IP was switched recently and the cooldown window has not elapsed.
+    SwitchCooldown { window_secs: u64 },
+    /// The IP entered a sticky traffic class and must stay for its residency time.
+    ClassResidency { class: String },
+}
+
+impl fmt::Display for HoldReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HoldReason::SwitchCooldown { window_secs } => write!(f, "cooldown {}s", window_secs),
+            HoldReason::ClassResidency { class } => write!(f, "{} residency", class),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Hold {
+    pub remaining_secs: u64,
+    pub reason: HoldReason,
+}
+
+#[derive(Debug, Clone)]
+struct Residency {
+    class: String,
+    until: u64,
+}
+
+/// Decides how long an IP must stay put, from its last switch and its traffic class.
+pub struct Cooldowns {
+    default_secs: u64,
+    /// Per-IP / per-subnet windows, most specific prefix first.
+    overrides: Vec<(Cidr, u64)>,
+    classifier: TrafficClassifier,
+    residencies: HashMap<ClientIp, Residency>,
+}
+
+impl Cooldowns {
+    pub fn new(config: &Config) -> Self {
+        let mut overrides: Vec<(Cidr, u64)> = config
+            .cooldown
+            .overrides
+            .iter()
+            .map(|rule| (rule.prefix, rule.secs))
+            .collect();
+        overrides.sort_by_key(|(prefix, _)| std::cmp::Reverse(prefix.prefix_len()));
+
+        Self {
+            default_secs: config.cooldown.default_secs,
+            overrides,
+            classifier: TrafficClassifier::new(&config.traffic_classes),
+            residencies: HashMap::new(),
+        }
+    }
+
+    /// Starts residency timers for IPs that just entered a sticky traffic class.
+    pub fn observe(&mut self, ip_traffic: &[IpTraffic], now: u64) {
+        let classes: HashMap<ClientIp, (String, u64)> = ip_traffic
+            .iter()
+            .filter_map(|traffic| {
+                let class = self.classifier.classify(traffic)?;
+                (class.min_residency_secs > 0)
+                    .then(|| (traffic.ip, (class.name.clone(), class.min_residency_secs)))
+            })
+            .collect();
+        // A residency that has run out stays on record while the IP remains in its class, so
+        // that staying in the class does not start it over
+        self.residencies.retain(|ip, residency| {
+            residency.until > now
+                || classes
+                    .get(ip)
+                    .is_some_and(|(class, _)| *class == residency.class)
+        });
+
+        for (ip, (class, min_residency_secs)) in classes {
+            let until = now + min_residency_secs;
+            let extends = self
+                .residencies
+                .get(&ip)
+                .is_none_or(|existing| existing.class != class && existing.until < until);
+
+            if extends {
+                self.residencies.insert(ip, Residency { class, until });
+            }
+        }
+    }
+
+    pub fn export(&self) -> Vec<StoredResidency> {
+        self.residencies
+            .iter()
+            .map(|(ip, residency)| StoredResidency {
+                ip: ip.to_string(),
+                class: residency.class.clone(),
+                until: residency.until,
+            })
+            .collect()
+    }
+
+    /// Takes back the residencies that have not run out by `now`.
+    pub fn restore(&mut self, rows: Vec<StoredResidency>, now: u64) {
+        for row in rows.into_iter().filter(|row| row.until > now) {
+            let Ok(ip) = row.ip.parse() else {
+                continue;
+            };
+            self.residencies.insert(
+                ip,
+                Residency {
+                    class: row.class,
+                    until: row.until,
+                },
+            );
+        }
+    }
+
+    /// Cooldown window for `ip`, using the longest matching override.
+    pub fn window_for(&self, ip: ClientIp) -> u64 {
+        self.overrides
+            .iter()
+            .find(|(prefix, _)| prefix.contains(&ip.addr()))
+            .map(|(_, secs)| *secs)
+            .unwrap_or(self.default_secs)
+    }
+
+    /// The longest hold currently preventing `ip` from being switched, if any.
+    pub fn remaining(&self, history: &SwitchHistory, ip: ClientIp, now: u64) -> Option<Hold> {
+        let cooldown = history.last_switch(ip).and_then(|record| {
+            let elapsed = now.saturating_sub(record.timestamp);
+            let window_secs = self.window_for(ip);
+            (elapsed < window_secs).then(|| Hold {
+                remaining_secs: window_secs - elapsed,
+                reason: HoldReason::SwitchCooldown { window_secs },
+            })
+        });
+
+        let residency = self
+            .residencies
+            .get(&ip)
+            .filter(|residency| residency.until > now)
+            .map(|residency| Hold {
+                remaining_secs: residency.until - now,
+                reason: HoldReason::ClassResidency {
+                    class: residency.class.clone(),
+                },
+            });
+
+        match (cooldown, residency) {
+            (Some(a), Some(b)) => Some(if a.remaining_secs >= b.remaining_secs {
+                a
+            } else {
+                b
+            }),
+            (a, b) => a.or(b),
+        }
+    }
+
+    /// How long history must be kept for every cooldown to be enforceable.
+    pub fn max_window(&self) -> u64 {
+        self.overrides
+            .iter()
+            .map(|(_, secs)| *secs)
+            .chain(std::iter::once(self.default_secs))
+            .max()
+            .unwrap_or(self.default_secs)
+    }
+}
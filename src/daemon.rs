@@ -0,0 +1,111 @@
+use anyhow::{bail, Context, Result};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+/// PID file holding an exclusive lock for as long as this instance runs, so a second
+/// instance cannot start and issue switches that fight this one's. The lock goes away
+/// with the process, so a stale file left by a crash does not block the next start.
+pub struct PidFile {
+    file: File,
+    path: PathBuf,
+}
+
+impl PidFile {
+    /// Locks `path`, failing if another instance holds it.
+    pub fn lock(path: &Path) -> Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .with_context(|| format!("Failed to open PID file {}", path.display()))?;
+        // SAFETY: flock only operates on the descriptor, which `file` keeps open
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+            let error = std::io::Error::last_os_error();
+            if error.raw_os_error() == Some(libc::EWOULDBLOCK) {
+                let mut pid = String::new();
+                let _ = file.read_to_string(&mut pid);
+                bail!(
+                    "Another routingFlow instance is running (PID {}, holding {})",
+                    pid.trim(),
+                    path.display()
+                );
+            }
+            return Err(error).with_context(|| format!("Failed to lock {}", path.display()));
+        }
+        Ok(Self {
+            file,
+            path: path.to_path_buf(),
+        })
+    }
+
+    /// Records the current process's PID; called again after detaching, since that
+    /// changes it.
+    pub fn write_pid(&mut self) -> Result<()> {
+        self.file.set_len(0)?;
+        self.file.seek(SeekFrom::Start(0))?;
+        writeln!(self.file, "{}", std::process::id())
+            .and_then(|_| self.file.sync_data())
+            .with_context(|| format!("Failed to write PID file {}", self.path.display()))
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            warn!("Failed to remove PID file {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// Detaches from the terminal: forks twice around `setsid` so the daemon is neither a
+/// session leader nor a child of the shell, and points stdin at `/dev/null` and stdout
+/// and stderr at `log_file` (or `/dev/null`). The working directory is kept, so relative
+/// paths in the config still resolve. Must run before any threads (the async runtime)
+/// are started.
+pub fn detach(log_file: Option<&Path>) -> Result<()> {
+    let null = File::open("/dev/null").context("Failed to open /dev/null")?;
+    let output = match log_file {
+        Some(path) => OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open log file {}", path.display()))?,
+        None => OpenOptions::new()
+            .write(true)
+            .open("/dev/null")
+            .context("Failed to open /dev/null")?,
+    };
+
+    fork_and_exit_parent()?;
+    // SAFETY: no threads have been started, so the forked child is a full copy
+    if unsafe { libc::setsid() } < 0 {
+        return Err(std::io::Error::last_os_error()).context("setsid failed");
+    }
+    fork_and_exit_parent()?;
+
+    for (source, target) in [
+        (null.as_raw_fd(), libc::STDIN_FILENO),
+        (output.as_raw_fd(), libc::STDOUT_FILENO),
+        (output.as_raw_fd(), libc::STDERR_FILENO),
+    ] {
+        // SAFETY: both descriptors are open for the duration of the call
+        if unsafe { libc::dup2(source, target) } < 0 {
+            return Err(std::io::Error::last_os_error()).context("Failed to redirect stdio");
+        }
+    }
+    Ok(())
+}
+
+fn fork_and_exit_parent() -> Result<()> {
+    // SAFETY: called single-threaded, see `detach`
+    match unsafe { libc::fork() } {
+        -1 => Err(std::io::Error::last_os_error()).context("fork failed"),
+        0 => Ok(()),
+        _ => std::process::exit(0),
+    }
+}
@@ -0,0 +1,222 @@
+use crate::config::DashboardConfig;
+use crate::model::{ClientIp, IpTraffic, WanId};
+use crate::report::{CycleReport, RecentSwitch};
+use crate::status_page::WanStatus;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::Mutex;
+
+/// What the dashboard shows, kept in memory by the monitor loop: per-WAN traffic of the
+/// last cycles, the current IP→WAN mapping and the recent switches.
+pub struct Dashboard {
+    samples: usize,
+    state: Mutex<DashboardSnapshot>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DashboardSnapshot {
+    pub updated_at: u64,
+    pub policy: String,
+    /// Oldest first.
+    pub samples: VecDeque<Sample>,
+    pub mappings: Vec<MappingRow>,
+    pub recent_switches: Vec<RecentSwitch>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Sample {
+    pub timestamp: u64,
+    pub wans: BTreeMap<WanId, WanSample>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WanSample {
+    pub traffic_bps: f64,
+    pub tcp_bandwidth_bps: f64,
+    pub utilization: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MappingRow {
+    pub ip: ClientIp,
+    pub wan: WanId,
+    /// From the network controller, if one is configured.
+    pub name: Option<String>,
+    pub rx_bps: f64,
+    pub tx_bps: f64,
+}
+
+impl Dashboard {
+    pub fn new(config: &DashboardConfig) -> Self {
+        Self {
+            samples: config.samples.max(1),
+            state: Mutex::new(DashboardSnapshot::default()),
+        }
+    }
+
+    pub fn record(
+        &self,
+        report: &CycleReport,
+        wans: &[WanStatus],
+        mappings: &HashMap<ClientIp, WanId>,
+        ip_traffic: &[IpTraffic],
+    ) {
+        let traffic: HashMap<&ClientIp, &IpTraffic> = ip_traffic
+            .iter()
+            .map(|traffic| (&traffic.ip, traffic))
+            .collect();
+        let mut rows: Vec<MappingRow> = mappings
+            .iter()
+            .map(|(ip, wan)| {
+                let traffic = traffic.get(ip);
+                MappingRow {
+                    ip: *ip,
+                    wan: wan.clone(),
+                    name: report
+                        .clients
+                        .get(ip)
+                        .and_then(|client| client.name.clone()),
+                    rx_bps: traffic.map_or(0.0, |traffic| traffic.rx_bps),
+                    tx_bps: traffic.map_or(0.0, |traffic| traffic.tx_bps),
+                }
+            })
+            .collect();
+        rows.sort_by_key(|row| row.ip);
+
+        let mut state = self.state.lock().unwrap();
+        state.updated_at = report.timestamp;
+        state.policy = report.policy.clone();
+        state.samples.push_back(Sample {
+            timestamp: report.timestamp,
+            wans: wans
+                .iter()
+                .map(|status| {
+                    (
+                        status.wan.clone(),
+                        WanSample {
+                            traffic_bps: status.traffic_bps,
+                            tcp_bandwidth_bps: status.tcp_bandwidth_bps,
+                            utilization: status.utilization,
+                        },
+                    )
+                })
+                .collect(),
+        });
+        while state.samples.len() > self.samples {
+            state.samples.pop_front();
+        }
+        state.mappings = rows;
+        state.recent_switches = report.recent_switches.clone();
+    }
+
+    pub fn snapshot(&self) -> DashboardSnapshot {
+        self.state.lock().unwrap().clone()
+    }
+}
+
+/// The dashboard page. It polls `/api/dashboard` and draws everything client-side, so it
+/// needs nothing beyond the daemon itself.
+pub const PAGE: &str = r##"<!DOCTYPE html>
+<html><head><meta charset="utf-8"><title>routingFlow</title>
+<style>
+body { font-family: sans-serif; margin: 1.5em; color: #222; }
+h2 { margin-top: 1.5em; font-size: 1.1em; }
+.graphs { display: flex; flex-wrap: wrap; gap: 1.5em; }
+.graph { border: 1px solid #ccc; padding: .5em; }
+.graph h3 { margin: 0 0 .3em; font-size: 1em; }
+svg { display: block; }
+table { border-collapse: collapse; }
+th, td { padding: .2em .8em; text-align: left; border-bottom: 1px solid #eee; }
+td.num { text-align: right; font-variant-numeric: tabular-nums; }
+.legend span { margin-right: 1em; }
+#status { color: #888; }
+</style></head><body>
+<h1>routingFlow</h1>
+<p id="status">Loading…</p>
+<p class="legend"><span style="color:#1f77b4">■ traffic</span><span style="color:#d62728">■ bandwidth estimate</span></p>
+<div class="graphs" id="graphs"></div>
+<h2>Mappings</h2>
+<table><thead><tr><th>Client</th><th>Name</th><th>WAN</th><th>Down</th><th>Up</th></tr></thead><tbody id="mappings"></tbody></table>
+<h2>Recent switches</h2>
+<table><thead><tr><th>Client</th><th>Moved to</th><th>Ago</th><th>Held</th></tr></thead><tbody id="switches"></tbody></table>
+<script>
+const W = 480, H = 140;
+function mbps(bps) { return (bps / 1e6).toFixed(2) + " Mbps"; }
+function cell(text, cls) {
+  const td = document.createElement("td");
+  td.textContent = text;
+  if (cls) td.className = cls;
+  return td;
+}
+function row(cells) {
+  const tr = document.createElement("tr");
+  cells.forEach(c => tr.appendChild(c));
+  return tr;
+}
+function line(points, color) {
+  const path = document.createElementNS("http://www.w3.org/2000/svg", "polyline");
+  path.setAttribute("points", points.join(" "));
+  path.setAttribute("fill", "none");
+  path.setAttribute("stroke", color);
+  path.setAttribute("stroke-width", "1.5");
+  return path;
+}
+function graph(wan, samples) {
+  const div = document.createElement("div");
+  div.className = "graph";
+  const series = samples.map(s => s.wans[wan]).filter(Boolean);
+  const last = series[series.length - 1];
+  const title = document.createElement("h3");
+  title.textContent = wan + (last ? " — " + mbps(last.traffic_bps) +
+    (last.utilization != null ? " (" + (last.utilization * 100).toFixed(1) + "%)" : "") : "");
+  div.appendChild(title);
+  const svg = document.createElementNS("http://www.w3.org/2000/svg", "svg");
+  svg.setAttribute("width", W);
+  svg.setAttribute("height", H);
+  const max = Math.max(1, ...series.map(s => Math.max(s.traffic_bps, s.tcp_bandwidth_bps)));
+  const x = i => series.length < 2 ? W : (i * W / (series.length - 1)).toFixed(1);
+  const y = v => (H - v * (H - 4) / max).toFixed(1);
+  svg.appendChild(line(series.map((s, i) => x(i) + "," + y(s.tcp_bandwidth_bps)), "#d62728"));
+  svg.appendChild(line(series.map((s, i) => x(i) + "," + y(s.traffic_bps)), "#1f77b4"));
+  div.appendChild(svg);
+  const scale = document.createElement("small");
+  scale.textContent = "max " + mbps(max);
+  div.appendChild(scale);
+  return div;
+}
+function render(data) {
+  document.getElementById("status").textContent = "Policy " + data.policy +
+    ", updated " + new Date(data.updated_at * 1000).toLocaleTimeString();
+  const wans = [...new Set(data.samples.flatMap(s => Object.keys(s.wans)))].sort();
+  document.getElementById("graphs").replaceChildren(...wans.map(wan => graph(wan, data.samples)));
+  document.getElementById("mappings").replaceChildren(...data.mappings.map(m => row([
+    cell(m.ip), cell(m.name || ""), cell(m.wan), cell(mbps(m.rx_bps), "num"), cell(mbps(m.tx_bps), "num")
+  ])));
+  document.getElementById("switches").replaceChildren(...data.recent_switches.map(s => row([
+    cell(s.ip), cell(s.target_wan), cell(s.age_secs + "s", "num"),
+    cell(s.hold ? s.hold.reason + " (" + s.hold.remaining_secs + "s left)" : "")
+  ])));
+}
+async function refresh() {
+  const headers = {};
+  const key = localStorage.getItem("routingflow-api-key");
+  if (key) headers["Authorization"] = "Bearer " + key;
+  try {
+    const response = await fetch("api/dashboard", { headers });
+    if (response.status === 401 || response.status === 403) {
+      const entered = prompt("API key for the dashboard");
+      if (entered) localStorage.setItem("routingflow-api-key", entered);
+    } else if (!response.ok) {
+      document.getElementById("status").textContent = "Error: " + response.status;
+    } else {
+      render(await response.json());
+    }
+  } catch (e) {
+    document.getElementById("status").textContent = "Cannot reach routingFlow: " + e;
+  }
+  setTimeout(refresh, 2000);
+}
+refresh();
+</script>
+</body></html>
+"##;
@@ -0,0 +1,261 @@
+use crate::config::{DataCapWanConfig, DataCapsConfig};
+use crate::error::{ConfigError, MetricsError};
+use crate::model::{IpTraffic, NicName, NicStats, WanId};
+use crate::policy::{PolicyPlan, SkippedCandidate};
+use crate::prometheus::PrometheusResult;
+use chrono::{DateTime, Datelike, Local, Months, NaiveTime, TimeZone};
+use serde::Serialize;
+use std::collections::HashMap;
+use tracing::{info, warn};
+
+const BYTES_PER_GB: f64 = 1_000_000_000.0;
+
+/// A metered WAN's use of its cap in the current billing period.
+#[derive(Debug, Clone, Serialize)]
+pub struct DataCapUsage {
+    pub used_bytes: f64,
+    pub cap_bytes: f64,
+    pub remaining_bytes: f64,
+    pub used_percent: f64,
+    pub period_start: u64,
+    pub resets_at: u64,
+}
+
+/// What is known of one WAN's billing period.
+#[derive(Debug)]
+struct Period {
+    start: u64,
+    /// Usage as Prometheus last reported it.
+    queried_bytes: f64,
+    /// Observed traffic of the WAN since then.
+    observed_bytes: f64,
+}
+
+/// Tracks how much of its monthly cap each metered WAN has used, from the `increase` of
+/// its byte counters since the billing period started plus what was observed since the
+/// last reading, and holds back policy moves onto a WAN that is close to its cap: bulk
+/// traffic from `deprioritize_percent`, everything from `stop_percent`.
+pub struct DataCaps {
+    config: DataCapsConfig,
+    periods: HashMap<WanId, Period>,
+    last_refresh: Option<u64>,
+    last_observed: Option<u64>,
+}
+
+impl DataCaps {
+    pub fn new(config: DataCapsConfig) -> Result<Self, ConfigError> {
+        for cap in &config.wans {
+            if cap.cap_gb <= 0.0 || !(1..=28).contains(&cap.reset_day) {
+                return Err(ConfigError::Invalid(format!(
+                    "data cap of {} needs a positive cap_gb and a reset_day of 1 to 28",
+                    cap.wan
+                )));
+            }
+            if cap.deprioritize_percent > cap.stop_percent {
+                return Err(ConfigError::Invalid(format!(
+                    "deprioritize_percent of {}'s data cap cannot exceed its stop_percent",
+                    cap.wan
+                )));
+            }
+        }
+        Ok(Self {
+            config,
+            periods: HashMap::new(),
+            last_refresh: None,
+            last_observed: None,
+        })
+    }
+
+    /// Usage queries of the capped WANs, when a reading is due at `now` or a billing
+    /// period has started since the last one.
+    pub fn due_queries(&self, now: &DateTime<Local>) -> Vec<(WanId, String)> {
+        let now_secs = now.timestamp().max(0) as u64;
+        let new_period = self.config.wans.iter().any(|cap| {
+            self.periods
+                .get(&cap.wan)
+                .is_none_or(|period| period.start != period_start(now, cap.reset_day).0)
+        });
+        let due = self
+            .last_refresh
+            .is_none_or(|at| now_secs.saturating_sub(at) >= self.config.refresh_secs);
+        if !due && !new_period {
+            return Vec::new();
+        }
+        self.config
+            .wans
+            .iter()
+            .map(|cap| {
+                let (start, _) = period_start(now, cap.reset_day);
+                // At least a minute, so a period that just started still has a window
+                let range_secs = now_secs.saturating_sub(start).max(60);
+                let query = format!(
+                    "sum by ({}) (increase({}[{}s]))",
+                    self.config.interface_label, self.config.counter_selector, range_secs
+                );
+                (cap.wan.clone(), query)
+            })
+            .collect()
+    }
+
+    /// Takes this cycle's readings, if any were due, and adds the traffic observed on each
+    /// capped WAN since the last cycle.
+    pub fn update(
+        &mut self,
+        now: &DateTime<Local>,
+        results: Vec<(WanId, Result<Vec<PrometheusResult>, MetricsError>)>,
+        wan_to_nic: &HashMap<WanId, NicName>,
+        nic_stats: &HashMap<NicName, NicStats>,
+    ) -> Result<(), MetricsError> {
+        let now_secs = now.timestamp().max(0) as u64;
+        let elapsed_secs = self
+            .last_observed
+            .map_or(0, |at| now_secs.saturating_sub(at));
+        self.last_observed = Some(now_secs);
+
+        for cap in &self.config.wans {
+            let (start, _) = period_start(now, cap.reset_day);
+            let period = self.periods.entry(cap.wan.clone()).or_insert(Period {
+                start,
+                queried_bytes: 0.0,
+                observed_bytes: 0.0,
+            });
+            if period.start != start {
+                info!(wan = %cap.wan, "Data cap billing period started");
+                *period = Period {
+                    start,
+                    queried_bytes: 0.0,
+                    observed_bytes: 0.0,
+                };
+            }
+            if let Some(stats) = wan_to_nic.get(&cap.wan).and_then(|nic| nic_stats.get(nic)) {
+                period.observed_bytes += (stats.rx_bps + stats.tx_bps) * elapsed_secs as f64 / 8.0;
+            }
+        }
+
+        if results.is_empty() {
+            return Ok(());
+        }
+        self.last_refresh = Some(now_secs);
+        let mut failure = None;
+        for (wan, result) in results {
+            let results = match result {
+                Ok(results) => results,
+                Err(e) => {
+                    failure = Some(e);
+                    continue;
+                }
+            };
+            let (Some(nic), Some(period)) = (wan_to_nic.get(&wan), self.periods.get_mut(&wan))
+            else {
+                continue;
+            };
+            let reading = results
+                .iter()
+                .find(|result| {
+                    result
+                        .label::<NicName>(&self.config.interface_label)
+                        .as_ref()
+                        == Some(nic)
+                })
+                .and_then(|result| result.value.1.parse::<f64>().ok());
+            match reading {
+                Some(bytes) if bytes.is_finite() => {
+                    period.queried_bytes = bytes;
+                    period.observed_bytes = 0.0;
+                }
+                _ => warn!(wan = %wan, nic = %nic, "No byte counters for the data cap"),
+            }
+        }
+        match failure {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    /// Usage of every capped WAN this billing period.
+    pub fn usage(&self, now: &DateTime<Local>) -> HashMap<WanId, DataCapUsage> {
+        self.config
+            .wans
+            .iter()
+            .filter_map(|cap| {
+                let period = self.periods.get(&cap.wan)?;
+                let (_, resets_at) = period_start(now, cap.reset_day);
+                let used_bytes = period.queried_bytes + period.observed_bytes;
+                let cap_bytes = cap.cap_gb * BYTES_PER_GB;
+                Some((
+                    cap.wan.clone(),
+                    DataCapUsage {
+                        used_bytes,
+                        cap_bytes,
+                        remaining_bytes: (cap_bytes - used_bytes).max(0.0),
+                        used_percent: used_bytes / cap_bytes * 100.0,
+                        period_start: period.start,
+                        resets_at,
+                    },
+                ))
+            })
+            .collect()
+    }
+
+    /// `plan` without the moves onto WANs too close to their caps for them.
+    pub fn filter(
+        &self,
+        mut plan: PolicyPlan,
+        usage: &HashMap<WanId, DataCapUsage>,
+        ip_traffic: &[IpTraffic],
+    ) -> PolicyPlan {
+        let bulk_bps = self.config.bulk_mbps * 1_000_000.0;
+        let (kept, held): (Vec<_>, Vec<_>) = plan.switches.into_iter().partition(|decision| {
+            let Some((cap, usage)) = self.cap(&decision.target_wan, usage) else {
+                return true;
+            };
+            let moving_bps = ip_traffic
+                .iter()
+                .find(|traffic| traffic.ip == decision.ip)
+                .map_or(decision.rx_bps, |traffic| traffic.rx_bps + traffic.tx_bps);
+            usage.used_percent < cap.stop_percent
+                && (usage.used_percent < cap.deprioritize_percent || moving_bps < bulk_bps)
+        });
+        plan.switches = kept;
+        plan.skipped.extend(held.into_iter().map(|decision| {
+            let used_percent = self
+                .cap(&decision.target_wan, usage)
+                .map_or(0.0, |(_, usage)| usage.used_percent);
+            SkippedCandidate {
+                reason: format!(
+                    "{} has used {:.0}% of its data cap",
+                    decision.target_wan, used_percent
+                ),
+                ip: decision.ip,
+                nic: decision.from_nic,
+            }
+        }));
+        plan
+    }
+
+    fn cap<'a>(
+        &self,
+        wan: &WanId,
+        usage: &'a HashMap<WanId, DataCapUsage>,
+    ) -> Option<(&DataCapWanConfig, &'a DataCapUsage)> {
+        let cap = self.config.wans.iter().find(|cap| cap.wan == *wan)?;
+        Some((cap, usage.get(wan)?))
+    }
+}
+
+/// Start of the billing period `now` is in and start of the next one, as Unix seconds.
+fn period_start(now: &DateTime<Local>, reset_day: u32) -> (u64, u64) {
+    let today = now.date_naive();
+    let mut start = today.with_day(reset_day).unwrap_or(today);
+    if start > today {
+        start = start.checked_sub_months(Months::new(1)).unwrap_or(start);
+    }
+    let next = start.checked_add_months(Months::new(1)).unwrap_or(start);
+    let midnight = |date: chrono::NaiveDate| {
+        Local
+            .from_local_datetime(&date.and_time(NaiveTime::MIN))
+            .earliest()
+            .map_or(0, |time| time.timestamp().max(0) as u64)
+    };
+    (midnight(start), midnight(next))
+}
@@ -0,0 +1,205 @@
+use crate::config::{DestinationRuleConfig, DestinationsConfig};
+use crate::error::ConfigError;
+use crate::model::{ClientIp, NicName, WanId};
+use crate::policy::{PolicyInput, SwitchDecision};
+use anyhow::{Context, Result};
+use maxminddb::{geoip2, Reader};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::Path;
+
+/// ASN and country of a destination address, as far as the databases know them.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize)]
+pub struct DestinationInfo {
+    pub asn: Option<u32>,
+    pub as_org: Option<String>,
+    pub country: Option<String>,
+}
+
+/// One client's traffic towards one destination address.
+#[derive(Debug, Clone)]
+pub struct DestinationTraffic {
+    pub client_ip: ClientIp,
+    pub nic: NicName,
+    pub destination: IpAddr,
+    pub info: DestinationInfo,
+    pub rx_bps: f64,
+    pub tx_bps: f64,
+}
+
+/// Traffic to one destination network over one WAN.
+#[derive(Debug, Clone, Serialize)]
+pub struct DestinationUsage {
+    pub wan: WanId,
+    #[serde(flatten)]
+    pub network: DestinationInfo,
+    pub rx_bps: f64,
+    pub tx_bps: f64,
+}
+
+/// Looks destination addresses up in MaxMind ASN / country databases (`.mmdb`).
+pub struct DestinationEnricher {
+    asn_db: Option<Reader<Vec<u8>>>,
+    country_db: Option<Reader<Vec<u8>>>,
+    cache: HashMap<IpAddr, DestinationInfo>,
+}
+
+impl DestinationEnricher {
+    pub fn new(config: &DestinationsConfig) -> Result<Self> {
+        Ok(Self {
+            asn_db: config.asn_db.as_deref().map(open_db).transpose()?,
+            country_db: config.country_db.as_deref().map(open_db).transpose()?,
+            cache: HashMap::new(),
+        })
+    }
+
+    pub fn lookup(&mut self, address: IpAddr) -> DestinationInfo {
+        if let Some(info) = self.cache.get(&address) {
+            return info.clone();
+        }
+
+        let mut info = DestinationInfo::default();
+        if let Some(Ok(Some(asn))) = self.asn_db.as_ref().map(|db| {
+            db.lookup(address)
+                .and_then(|result| result.decode::<geoip2::Asn>())
+        }) {
+            info.asn = asn.autonomous_system_number;
+            info.as_org = asn.autonomous_system_organization.map(str::to_string);
+        }
+        if let Some(Ok(Some(country))) = self.country_db.as_ref().map(|db| {
+            db.lookup(address)
+                .and_then(|result| result.decode::<geoip2::Country>())
+        }) {
+            info.country = country.country.iso_code.map(str::to_string);
+        }
+
+        self.cache.insert(address, info.clone());
+        info
+    }
+}
+
+fn open_db(path: &Path) -> Result<Reader<Vec<u8>>> {
+    Reader::open_readfile(path)
+        .with_context(|| format!("Failed to open MaxMind database {}", path.display()))
+}
+
+/// Sums destination traffic per WAN and destination network, largest first.
+pub fn aggregate_by_network(
+    traffic: &[DestinationTraffic],
+    nic_to_wan: &HashMap<NicName, WanId>,
+) -> Vec<DestinationUsage> {
+    let mut usage: HashMap<(WanId, DestinationInfo), (f64, f64)> = HashMap::new();
+    for flow in traffic {
+        let Some(wan) = nic_to_wan.get(&flow.nic) else {
+            continue;
+        };
+        let entry = usage
+            .entry((wan.clone(), flow.info.clone()))
+            .or_insert((0.0, 0.0));
+        entry.0 += flow.rx_bps;
+        entry.1 += flow.tx_bps;
+    }
+
+    let mut usage: Vec<DestinationUsage> = usage
+        .into_iter()
+        .map(|((wan, network), (rx_bps, tx_bps))| DestinationUsage {
+            wan,
+            network,
+            rx_bps,
+            tx_bps,
+        })
+        .collect();
+    usage.sort_by(|a, b| {
+        (b.rx_bps + b.tx_bps)
+            .partial_cmp(&(a.rx_bps + a.tx_bps))
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+    usage
+}
+
+/// Destination rules' verdict for one cycle.
+#[derive(Debug, Default)]
+pub struct DestinationPlan {
+    /// Client IP → WAN it must stay on; other moves of these clients are dropped.
+    pub pinned: HashMap<ClientIp, WanId>,
+    /// Moves of pinned clients that are currently on another WAN.
+    pub switches: Vec<SwitchDecision>,
+}
+
+/// Pins clients to a WAN while they exchange traffic with configured destination networks.
+pub struct DestinationRules {
+    rules: Vec<DestinationRuleConfig>,
+}
+
+impl DestinationRules {
+    pub fn new(rules: &[DestinationRuleConfig]) -> Result<Self, ConfigError> {
+        if let Some(rule) = rules
+            .iter()
+            .find(|rule| rule.prefixes.is_empty() && rule.asns.is_empty())
+        {
+            return Err(ConfigError::Invalid(format!(
+                "Destination rule {} needs at least one prefix or ASN",
+                rule.name
+            )));
+        }
+
+        Ok(Self {
+            rules: rules.to_vec(),
+        })
+    }
+
+    pub fn plan(&self, input: &PolicyInput) -> DestinationPlan {
+        let mut plan = DestinationPlan::default();
+
+        // Per client, the earliest matching rule wins and its busiest flow is reported
+        let mut matches: HashMap<ClientIp, (usize, &DestinationTraffic)> = HashMap::new();
+        for flow in input.destinations {
+            let Some(index) = self.rules.iter().position(|rule| {
+                rule_matches(rule, flow) && flow.rx_bps + flow.tx_bps >= rule.min_mbps * 1_000_000.0
+            }) else {
+                continue;
+            };
+            let best = matches.entry(flow.client_ip).or_insert((index, flow));
+            if (index, -(flow.rx_bps + flow.tx_bps)) < (best.0, -(best.1.rx_bps + best.1.tx_bps)) {
+                *best = (index, flow);
+            }
+        }
+
+        let mut matches: Vec<_> = matches.into_iter().collect();
+        matches.sort_by_key(|(client_ip, _)| *client_ip);
+        for (client_ip, (index, flow)) in matches {
+            let rule = &self.rules[index];
+            plan.pinned.insert(client_ip, rule.wan.clone());
+            if input.mappings.get(&client_ip) == Some(&rule.wan) {
+                continue;
+            }
+
+            plan.switches.push(SwitchDecision {
+                ip: client_ip,
+                from_nic: flow.nic.clone(),
+                target_wan: rule.wan.clone(),
+                rx_bps: flow.rx_bps,
+                reason: match flow.info.asn {
+                    Some(asn) => format!(
+                        "traffic to {} (AS{}) matches destination rule {}",
+                        flow.destination, asn, rule.name
+                    ),
+                    None => format!(
+                        "traffic to {} matches destination rule {}",
+                        flow.destination, rule.name
+                    ),
+                },
+            });
+        }
+
+        plan
+    }
+}
+
+fn rule_matches(rule: &DestinationRuleConfig, flow: &DestinationTraffic) -> bool {
+    rule.prefixes
+        .iter()
+        .any(|prefix| prefix.contains(&flow.destination))
+        || flow.info.asn.is_some_and(|asn| rule.asns.contains(&asn))
+}
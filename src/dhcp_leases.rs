@@ -0,0 +1,174 @@
+use crate::config::{DhcpLeasesConfig, LeaseFormat};
+use crate::model::ClientIp;
+use crate::neighbors::{is_mac, normalize_mac};
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::Path;
+use tracing::{debug, warn};
+
+/// Kea's lease state for a lease in use (the others are declined and reclaimed ones).
+const KEA_STATE_DEFAULT: &str = "0";
+
+/// What the DHCP server handed out to one address.
+#[derive(Debug, Clone, Serialize)]
+pub struct Lease {
+    /// Absent for DHCPv6 leases that only carry a DUID.
+    pub mac: Option<String>,
+    pub hostname: Option<String>,
+    /// Unix time the lease runs out; `None` for infinite leases.
+    pub expires: Option<u64>,
+}
+
+/// The lease files, each re-read when its `refresh_secs` have passed. A file that cannot be
+/// read keeps its last good leases.
+pub struct DhcpLeases {
+    files: Vec<LeaseFile>,
+}
+
+struct LeaseFile {
+    config: DhcpLeasesConfig,
+    read_at: Option<u64>,
+    leases: HashMap<ClientIp, Lease>,
+}
+
+impl DhcpLeases {
+    pub fn new(files: &[DhcpLeasesConfig]) -> Self {
+        Self {
+            files: files
+                .iter()
+                .map(|config| LeaseFile {
+                    config: config.clone(),
+                    read_at: None,
+                    leases: HashMap::new(),
+                })
+                .collect(),
+        }
+    }
+
+    pub async fn refresh(&mut self, now: u64) {
+        for file in &mut self.files {
+            if file
+                .read_at
+                .is_some_and(|read_at| now.saturating_sub(read_at) < file.config.refresh_secs)
+            {
+                continue;
+            }
+            file.read_at = Some(now);
+            let config = file.config.clone();
+            let read =
+                tokio::task::spawn_blocking(move || read_leases(&config.path, config.format)).await;
+            match read {
+                Ok(Ok(leases)) => {
+                    debug!(path = %file.config.path.display(), leases = leases.len(), "Read DHCP leases");
+                    file.leases = leases;
+                }
+                Ok(Err(e)) => warn!("{:#}; keeping the last leases read", e),
+                Err(e) => warn!("Reading DHCP leases panicked: {}", e),
+            }
+        }
+    }
+
+    /// Leases in force at `now`; the first file listed wins for an address in several.
+    pub fn active(&self, now: u64) -> HashMap<ClientIp, Lease> {
+        let mut active = HashMap::new();
+        for file in &self.files {
+            for (ip, lease) in &file.leases {
+                if lease.expires.is_none_or(|expires| expires > now) {
+                    active.entry(*ip).or_insert_with(|| lease.clone());
+                }
+            }
+        }
+        active
+    }
+}
+
+pub fn read_leases(path: &Path, format: LeaseFormat) -> Result<HashMap<ClientIp, Lease>> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read DHCP leases from {}", path.display()))?;
+    Ok(match format {
+        LeaseFormat::Dnsmasq => parse_dnsmasq(&text),
+        LeaseFormat::Kea => parse_kea(&text),
+    })
+}
+
+/// `<expiry> <MAC> <address> <hostname> <client id>` per lease, `*` for a missing field and
+/// expiry 0 for an infinite lease. DHCPv6 leases follow a `duid` line with an IAID where the
+/// MAC would be.
+fn parse_dnsmasq(text: &str) -> HashMap<ClientIp, Lease> {
+    let mut leases = HashMap::new();
+    for line in text.lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let [expiry, mac, address, hostname, ..] = fields[..] else {
+            continue;
+        };
+        let (Ok(expiry), Ok(ip)) = (expiry.parse::<u64>(), address.parse::<ClientIp>()) else {
+            continue;
+        };
+        leases.insert(
+            ip,
+            Lease {
+                mac: is_mac(mac).then(|| normalize_mac(mac)),
+                hostname: (hostname != "*").then(|| hostname.to_string()),
+                expires: (expiry != 0).then_some(expiry),
+            },
+        );
+    }
+    leases
+}
+
+/// Kea's memfile appends every change to a lease, so the last line of an address is the one
+/// that counts.
+fn parse_kea(text: &str) -> HashMap<ClientIp, Lease> {
+    let mut lines = text.lines();
+    let Some(header) = lines.next() else {
+        return HashMap::new();
+    };
+    let columns: Vec<&str> = header.split(',').map(str::trim).collect();
+    let column = |name: &str| columns.iter().position(|column| *column == name);
+    let (Some(address), Some(expire)) = (column("address"), column("expire")) else {
+        return HashMap::new();
+    };
+    let (hwaddr, hostname, valid_lifetime, state) = (
+        column("hwaddr"),
+        column("hostname"),
+        column("valid_lifetime"),
+        column("state"),
+    );
+
+    let mut leases = HashMap::new();
+    for line in lines {
+        let fields: Vec<&str> = line.split(',').collect();
+        let field = |index: Option<usize>| {
+            index
+                .and_then(|index| fields.get(index))
+                .map(|field| field.trim())
+                .filter(|field| !field.is_empty())
+        };
+        let Some(ip) = field(Some(address)).and_then(|address| address.parse::<ClientIp>().ok())
+        else {
+            continue;
+        };
+        if field(state).is_some_and(|state| state != KEA_STATE_DEFAULT) {
+            leases.remove(&ip);
+            continue;
+        }
+        let Some(expires) = field(Some(expire)).and_then(|expire| expire.parse::<u64>().ok())
+        else {
+            continue;
+        };
+        let infinite = field(valid_lifetime) == Some("4294967295");
+        leases.insert(
+            ip,
+            Lease {
+                mac: field(hwaddr).filter(|mac| is_mac(mac)).map(normalize_mac),
+                hostname: field(hostname)
+                    // Kea escapes commas inside a field
+                    .map(|hostname| hostname.replace("&#x2c", ","))
+                    .map(|hostname| hostname.trim_end_matches('.').to_string()),
+                expires: (!infinite).then_some(expires),
+            },
+        );
+    }
+    leases
+}
@@ -0,0 +1,284 @@
+use crate::bundle;
+use crate::cli::DiagArgs;
+use crate::config::{Config, DiagnosticsConfig};
+use crate::redact::Redactor;
+use crate::report::CycleReport;
+use anyhow::{bail, Context, Result};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing_subscriber::fmt::MakeWriter;
+
+/// Config keys whose values never leave the host.
+const SECRET_KEYS: [&str; 5] = ["password", "secret", "token", "authorization", "key"];
+
+/// The running instance's most recent log lines and cycle reports.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct DiagSnapshot {
+    pub logs: Vec<String>,
+    /// Cycle reports as printed with `--output json`, oldest first.
+    pub cycles: Vec<serde_json::Value>,
+}
+
+/// Keeps the last log lines and cycle reports in memory so `diag` can collect them
+/// from the running instance.
+pub struct DiagRecorder {
+    config: DiagnosticsConfig,
+    logs: Mutex<VecDeque<String>>,
+    cycles: Mutex<VecDeque<serde_json::Value>>,
+}
+
+impl DiagRecorder {
+    pub fn new(config: DiagnosticsConfig) -> Self {
+        Self {
+            config,
+            logs: Mutex::new(VecDeque::new()),
+            cycles: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    pub fn record_cycle(&self, report: &CycleReport) {
+        let Ok(report) = serde_json::to_value(report) else {
+            return;
+        };
+        let mut cycles = self.cycles.lock().unwrap();
+        cycles.push_back(report);
+        while cycles.len() > self.config.cycles {
+            cycles.pop_front();
+        }
+    }
+
+    fn record_log(&self, line: &str) {
+        let mut logs = self.logs.lock().unwrap();
+        logs.push_back(strip_ansi(line));
+        while logs.len() > self.config.log_lines {
+            logs.pop_front();
+        }
+    }
+
+    pub fn snapshot(&self) -> DiagSnapshot {
+        DiagSnapshot {
+            logs: self.logs.lock().unwrap().iter().cloned().collect(),
+            cycles: self.cycles.lock().unwrap().iter().cloned().collect(),
+        }
+    }
+
+    /// A log writer that copies every formatted line into the recorder.
+    pub fn log_tap(self: &Arc<Self>) -> LogTap {
+        LogTap(self.clone())
+    }
+}
+
+/// [`MakeWriter`] feeding [`DiagRecorder`]; one writer per log event.
+pub struct LogTap(Arc<DiagRecorder>);
+
+impl<'a> MakeWriter<'a> for LogTap {
+    type Writer = LogTapWriter;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        LogTapWriter {
+            recorder: self.0.clone(),
+            buffer: Vec::new(),
+        }
+    }
+}
+
+pub struct LogTapWriter {
+    recorder: Arc<DiagRecorder>,
+    buffer: Vec<u8>,
+}
+
+impl Write for LogTapWriter {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        self.buffer.extend_from_slice(data);
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for LogTapWriter {
+    fn drop(&mut self) {
+        for line in String::from_utf8_lossy(&self.buffer).lines() {
+            self.recorder.record_log(line);
+        }
+    }
+}
+
+fn strip_ansi(line: &str) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c == '\u{1b}' {
+            // Skip the control sequence up to its final letter
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// Version and environment of the bundle's source.
+#[derive(Debug, Serialize)]
+struct VersionInfo {
+    version: &'static str,
+    os: &'static str,
+    arch: &'static str,
+    created_at: u64,
+    anonymized: bool,
+    /// Why the running instance's logs and cycles are missing, if they are.
+    instance_error: Option<String>,
+}
+
+/// Implements the `diag` subcommand: logs, recent cycles, the config without secrets and
+/// version info in one `.tar.gz` to attach to a bug report.
+pub async fn run_diag_command(config: &Config, config_path: &Path, args: &DiagArgs) -> Result<()> {
+    let (mut snapshot, instance_error) = match fetch_snapshot(config, args).await {
+        Ok(snapshot) => (snapshot, None),
+        Err(e) => {
+            eprintln!("Warning: {:#}; the bundle has no logs or cycle reports", e);
+            (DiagSnapshot::default(), Some(format!("{:#}", e)))
+        }
+    };
+    if let Some(limit) = args.cycles {
+        let skip = snapshot.cycles.len().saturating_sub(limit);
+        snapshot.cycles.drain(..skip);
+    }
+
+    let mut config_document: toml::Value = if config_path.exists() {
+        let text = std::fs::read_to_string(config_path)
+            .with_context(|| format!("Failed to read config file {}", config_path.display()))?;
+        toml::from_str(&text)
+            .with_context(|| format!("Failed to parse config file {}", config_path.display()))?
+    } else {
+        toml::Value::Table(Default::default())
+    };
+    strip_secrets(&mut config_document);
+
+    if args.anonymize {
+        let mut redactor = Redactor::with_macs();
+        redactor.toml(&mut config_document);
+        snapshot.logs = snapshot
+            .logs
+            .iter()
+            .map(|line| redactor.text(line))
+            .collect();
+        snapshot
+            .cycles
+            .iter_mut()
+            .for_each(|cycle| redactor.json(cycle));
+    }
+
+    let version = VersionInfo {
+        version: env!("CARGO_PKG_VERSION"),
+        os: std::env::consts::OS,
+        arch: std::env::consts::ARCH,
+        created_at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs(),
+        anonymized: args.anonymize,
+        instance_error,
+    };
+
+    let file = File::create(&args.output)
+        .with_context(|| format!("Failed to create {}", args.output.display()))?;
+    let mut archive = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+    bundle::append(
+        &mut archive,
+        "version.json",
+        &serde_json::to_vec_pretty(&version)?,
+    )?;
+    bundle::append(
+        &mut archive,
+        "routingflow.toml",
+        toml::to_string_pretty(&config_document)?.as_bytes(),
+    )?;
+    bundle::append(
+        &mut archive,
+        "cycles.json",
+        &serde_json::to_vec_pretty(&snapshot.cycles)?,
+    )?;
+    let mut logs = snapshot.logs.join("\n");
+    logs.push('\n');
+    bundle::append(&mut archive, "logs.txt", logs.as_bytes())?;
+    archive.into_inner()?.finish()?;
+
+    println!(
+        "Wrote {} ({} log lines, {} cycles{})",
+        args.output.display(),
+        snapshot.logs.len(),
+        snapshot.cycles.len(),
+        if args.anonymize { ", anonymized" } else { "" }
+    );
+    Ok(())
+}
+
+async fn fetch_snapshot(config: &Config, args: &DiagArgs) -> Result<DiagSnapshot> {
+    let Some(listen) = config.server.listen else {
+        bail!("The HTTP API is disabled ([server] listen is not set)");
+    };
+    let url = format!("http://{}/diag", listen);
+    let request = Client::new().get(&url);
+    let request = match &args.api_key {
+        Some(api_key) => request.bearer_auth(api_key),
+        None => request,
+    };
+
+    let response = request
+        .send()
+        .await
+        .with_context(|| format!("Failed to reach {}", url))?;
+    let status = response.status();
+    if !status.is_success() {
+        let body = response.text().await.unwrap_or_default();
+        bail!("{} ({})", body.trim(), status);
+    }
+    response
+        .json()
+        .await
+        .with_context(|| format!("Invalid response from {}", url))
+}
+
+/// Replaces the values of credential-like keys (passwords, tokens, API keys) and of all
+/// request headers throughout the document.
+fn strip_secrets(value: &mut toml::Value) {
+    match value {
+        toml::Value::Table(table) => {
+            for (key, value) in table.iter_mut() {
+                let key = key.to_ascii_lowercase();
+                if key == "headers" {
+                    if let toml::Value::Table(headers) = value {
+                        for (_, header) in headers.iter_mut() {
+                            *header = toml::Value::String("<redacted>".to_string());
+                        }
+                    }
+                } else if value.is_str()
+                    && SECRET_KEYS
+                        .iter()
+                        .any(|secret| key == *secret || (*secret != "key" && key.contains(secret)))
+                {
+                    *value = toml::Value::String("<redacted>".to_string());
+                } else {
+                    strip_secrets(value);
+                }
+            }
+        }
+        toml::Value::Array(values) => values.iter_mut().for_each(strip_secrets),
+        _ => {}
+    }
+}
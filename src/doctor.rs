@@ -0,0 +1,512 @@
+use crate::clock::{Clock, SystemClock};
+use crate::config::{Config, FailoverConfig, InfluxDbConfig};
+use crate::dhcp_leases;
+use crate::error::{BackendError, MetricsError};
+use crate::metric_source;
+use crate::model::NicName;
+use crate::monitor;
+use crate::passive_rtt::PassiveRtt;
+use crate::prometheus::PrometheusClient;
+use crate::routing::{ConfigInfo, RoutingService};
+use anyhow::{bail, Result};
+use reqwest::StatusCode;
+use std::collections::HashSet;
+use std::path::Path;
+
+/// Results of the checks, printed as they come in.
+#[derive(Default)]
+struct Findings {
+    problems: usize,
+    warnings: usize,
+}
+
+impl Findings {
+    fn ok(&self, message: impl AsRef<str>) {
+        println!("  ✓ {}", message.as_ref());
+    }
+
+    fn warn(&mut self, message: impl AsRef<str>, fix: impl AsRef<str>) {
+        self.warnings += 1;
+        println!("  ⚠ {}", message.as_ref());
+        println!("    → {}", fix.as_ref());
+    }
+
+    fn fail(&mut self, message: impl AsRef<str>, fix: impl AsRef<str>) {
+        self.problems += 1;
+        println!("  ✗ {}", message.as_ref());
+        println!("    → {}", fix.as_ref());
+    }
+}
+
+/// Checks that the routing service and Prometheus can be reached and answer in the
+/// expected format, that the interfaces they name exist here and that the metric series
+/// the balancing reads are present, suggesting a fix for every problem found. Fails if
+/// there is one.
+pub async fn run_doctor_command(config: &Config) -> Result<()> {
+    let mut findings = Findings::default();
+
+    println!("=== Routing service ({}) ===", config.routing_service.url);
+    let routing = RoutingService::new(&config.routing_service, config.retry.clone())?;
+    let interfaces = match routing.status().await {
+        Ok(status) => {
+            findings.ok(format!(
+                "/status answers: LAN {}, {}, {} mapped clients",
+                status.config.lan,
+                sorted(
+                    status
+                        .config
+                        .wans
+                        .iter()
+                        .map(|(wan, nic)| format!("{} {}", wan, nic))
+                ),
+                status.mappings.len()
+            ));
+            Some(status.config)
+        }
+        Err(e) => {
+            let fix = routing_fix(&e);
+            findings.fail(format!("/status failed: {}", e), fix);
+            None
+        }
+    };
+    if let Some(qos) = &config.qos {
+        match routing.qos(&qos.path).await {
+            Ok(response) => findings.ok(format!(
+                "{} answers with queue counters of {} interfaces",
+                qos.path,
+                response.interfaces.len()
+            )),
+            Err(e) => findings.fail(
+                format!("{} failed: {}", qos.path, e),
+                format!(
+                    "Check that the routing service serves queue counters at qos.path, or remove [qos]. {}",
+                    routing_fix(&e)
+                ),
+            ),
+        }
+    }
+    println!();
+
+    println!("=== Interfaces ===");
+    match &interfaces {
+        Some(interfaces) => {
+            let wans = interfaces.wans.iter().map(|(wan, nic)| (wan.as_str(), nic));
+            for (role, nic) in std::iter::once(("LAN", &interfaces.lan)).chain(wans) {
+                if interface_exists(nic) {
+                    findings.ok(format!("{} interface {} exists", role, nic));
+                } else {
+                    findings.fail(
+                        format!("{} interface {} does not exist on this host", role, nic),
+                        "Run routingFlow on the router itself, or correct the interface names in the routing service's config",
+                    );
+                }
+            }
+        }
+        None => println!("  (skipped: interface names come from the routing service)"),
+    }
+    println!();
+
+    match &config.influxdb {
+        Some(influxdb) => {
+            check_influxdb(config, influxdb, interfaces.as_ref(), &mut findings).await?
+        }
+        None => check_prometheus(config, interfaces.as_ref(), &mut findings).await?,
+    }
+    if !config.dhcp_leases.is_empty() {
+        println!("=== DHCP leases ===");
+        for file in &config.dhcp_leases {
+            match dhcp_leases::read_leases(&file.path, file.format) {
+                Ok(leases) if leases.is_empty() => findings.warn(
+                    format!("No leases in {}", file.path.display()),
+                    "Check that dhcp_leases.format matches the DHCP server writing the file",
+                ),
+                Ok(leases) => findings.ok(format!(
+                    "{} DHCP leases in {}",
+                    leases.len(),
+                    file.path.display()
+                )),
+                Err(e) => findings.fail(
+                    format!("{:#}", e),
+                    "Check dhcp_leases.path and that routingFlow may read it",
+                ),
+            }
+        }
+        println!();
+    }
+
+    summarize(&findings)
+}
+
+async fn check_prometheus(
+    config: &Config,
+    interfaces: Option<&ConfigInfo>,
+    findings: &mut Findings,
+) -> Result<()> {
+    println!("=== Prometheus ({}) ===", config.prometheus.url);
+    let prometheus = PrometheusClient::new(&config.prometheus)?;
+    match prometheus.query("vector(1)").await {
+        Ok(_) => findings.ok("The query API answers"),
+        Err(e) => {
+            let fix = metrics_fix(&e);
+            findings.fail(format!("Query failed: {}", e), fix);
+            return Ok(());
+        }
+    }
+
+    let schema = &config.metric_schema;
+    let bandwidth_query = metric_source::bandwidth_query(schema);
+    match prometheus.query(&bandwidth_query).await {
+        Ok(results) => {
+            let reported: HashSet<NicName> = results
+                .iter()
+                .filter_map(|result| result.label(&schema.interface_label))
+                .collect();
+            if reported.is_empty() {
+                findings.fail(
+                    format!("No series for {} with an {} label", bandwidth_query, schema.interface_label),
+                    "Check that Prometheus scrapes tcp-traffic-scan under metric_schema.bandwidth_job, or the metric_schema names",
+                );
+            } else {
+                findings.ok(format!(
+                    "Bandwidth estimates for {}",
+                    sorted(reported.iter().map(ToString::to_string))
+                ));
+            }
+            if let Some(interfaces) = interfaces {
+                for (wan, nic) in monitor::build_wan_to_nic_map(interfaces) {
+                    if !reported.is_empty() && !reported.contains(&nic) {
+                        findings.fail(
+                            format!("No bandwidth estimate for {} ({})", nic, wan),
+                            format!(
+                                "Check that tcp-traffic-scan measures {} and labels it {}=\"{}\"",
+                                nic, schema.interface_label, nic
+                            ),
+                        );
+                    }
+                }
+            }
+        }
+        Err(e) => {
+            let fix = metrics_fix(&e);
+            findings.fail(format!("Bandwidth query failed: {}", e), fix);
+        }
+    }
+
+    let max_age_secs = config
+        .failover
+        .as_ref()
+        .map_or(FailoverConfig::default().stale_after_secs, |failover| {
+            failover.stale_after_secs
+        });
+    match prometheus
+        .query(&format!("timestamp({})", bandwidth_query))
+        .await
+    {
+        Ok(results) => {
+            let now = SystemClock.unix_secs();
+            for result in results {
+                let (Some(nic), Ok(sampled_at)) = (
+                    result.label::<NicName>(&schema.interface_label),
+                    result.value.1.parse::<f64>(),
+                ) else {
+                    continue;
+                };
+                let age_secs = now.saturating_sub(sampled_at.max(0.0) as u64);
+                if age_secs > max_age_secs {
+                    findings.warn(
+                        format!("The bandwidth estimate for {} is {}s old", nic, age_secs),
+                        "Check that tcp-traffic-scan is still running and being scraped",
+                    );
+                }
+            }
+        }
+        Err(e) => {
+            let fix = metrics_fix(&e);
+            findings.fail(format!("Sample time query failed: {}", e), fix);
+        }
+    }
+
+    let traffic_query = metric_source::traffic_query(schema);
+    match prometheus.query(&traffic_query).await {
+        Ok(results) => {
+            let clients: HashSet<String> = results
+                .iter()
+                .filter_map(|result| result.metric.get(&schema.ip_label).cloned())
+                .collect();
+            if clients.is_empty() {
+                findings.fail(
+                    format!("No series for {} with an {} label", traffic_query, schema.ip_label),
+                    "Check that Prometheus scrapes localpacketdump under metric_schema.traffic_job, or the metric_schema names",
+                );
+            } else {
+                findings.ok(format!("Traffic of {} client addresses", clients.len()));
+            }
+        }
+        Err(e) => {
+            let fix = metrics_fix(&e);
+            findings.fail(format!("Client traffic query failed: {}", e), fix);
+        }
+    }
+
+    if let Some(passive_rtt) = config.passive_rtt.clone().map(PassiveRtt::new) {
+        match prometheus.query(&passive_rtt.query()).await {
+            Ok(results) if results.is_empty() => findings.warn(
+                "No passive RTT series",
+                "Check passive_rtt.rtt_metric against the exporter's metric names, or remove [passive_rtt]",
+            ),
+            Ok(results) => findings.ok(format!("{} passive RTT series", results.len())),
+            Err(e) => {
+                let fix = metrics_fix(&e);
+                findings.fail(format!("Passive RTT query failed: {}", e), fix);
+            }
+        }
+    }
+    if let Some(link_quality) = &config.link_quality {
+        let queries = [
+            ("link_quality.loss_query", &link_quality.loss_query),
+            ("link_quality.jitter_query", &link_quality.jitter_query),
+        ];
+        for (name, query) in queries {
+            let Some(query) = query else {
+                continue;
+            };
+            match prometheus.query(query).await {
+                Ok(results) if results.is_empty() => findings.warn(
+                    format!("No series for {}", name),
+                    "Check that Prometheus scrapes ping_exporter (or whatever the query reads); the probes' readings are used meanwhile",
+                ),
+                Ok(results) => findings.ok(format!("{} series for {}", results.len(), name)),
+                Err(e) => findings.fail(
+                    format!("{} failed: {}", name, e),
+                    format!("Check the PromQL of {}", name),
+                ),
+            }
+        }
+    }
+    if let Some(data_caps) = &config.data_caps {
+        match prometheus.query(&data_caps.counter_selector).await {
+            Ok(results) => {
+                let interfaces: HashSet<String> = results
+                    .iter()
+                    .filter_map(|result| result.metric.get(&data_caps.interface_label).cloned())
+                    .collect();
+                if interfaces.is_empty() {
+                    findings.warn(
+                        format!("No byte counters for {} with a {} label", data_caps.counter_selector, data_caps.interface_label),
+                        "Check that Prometheus scrapes node_exporter, or data_caps.counter_selector and interface_label",
+                    );
+                } else {
+                    findings.ok(format!(
+                        "Byte counters of {} interfaces for the data caps",
+                        interfaces.len()
+                    ));
+                }
+            }
+            Err(e) => {
+                let fix = metrics_fix(&e);
+                findings.fail(format!("Data cap counter query failed: {}", e), fix);
+            }
+        }
+    }
+    if let Some(app_classes) = &config.app_classes {
+        match prometheus.query(&app_classes.rx_metric).await {
+            Ok(results) if results.is_empty() => findings.warn(
+                "No per-port traffic series for the application classes",
+                "Check app_classes.rx_metric against the exporter's metric names, or remove [app_classes]",
+            ),
+            Ok(results) => findings.ok(format!("{} per-port traffic series", results.len())),
+            Err(e) => {
+                let fix = metrics_fix(&e);
+                findings.fail(format!("Per-port traffic query failed: {}", e), fix);
+            }
+        }
+    }
+    if let Some(pause_query) = config
+        .probes
+        .as_ref()
+        .and_then(|probes| probes.pause_query.as_ref())
+    {
+        match prometheus.query(pause_query).await {
+            Ok(_) => findings.ok("probes.pause_query runs"),
+            Err(e) => findings.fail(
+                format!("probes.pause_query failed: {}", e),
+                "Check the PromQL of probes.pause_query",
+            ),
+        }
+    }
+    println!();
+    Ok(())
+}
+
+/// The bandwidth and traffic series in InfluxDB, read the way the balancing reads them.
+async fn check_influxdb(
+    config: &Config,
+    influxdb: &InfluxDbConfig,
+    interfaces: Option<&ConfigInfo>,
+    findings: &mut Findings,
+) -> Result<()> {
+    println!(
+        "=== InfluxDB ({}, bucket {}) ===",
+        influxdb.url, influxdb.bucket
+    );
+    let source = metric_source::from_config(config)?;
+    let now = SystemClock.unix_secs();
+    match source.tcp_bandwidth(now).await {
+        Ok(results) => {
+            let reported: HashSet<NicName> = results
+                .iter()
+                .filter_map(|result| result.label("interface"))
+                .collect();
+            if reported.is_empty() {
+                findings.fail(
+                    format!(
+                        "No {} points of {} with an {} tag in the last {}s",
+                        influxdb.bandwidth_field,
+                        influxdb.bandwidth_measurement,
+                        influxdb.interface_tag,
+                        influxdb.lookback_secs
+                    ),
+                    "Check influxdb.bandwidth_measurement, bandwidth_field and interface_tag against what tcp-traffic-scan writes",
+                );
+            } else {
+                findings.ok(format!(
+                    "Bandwidth estimates for {}",
+                    sorted(reported.iter().map(ToString::to_string))
+                ));
+            }
+            if let Some(interfaces) = interfaces {
+                for (wan, nic) in monitor::build_wan_to_nic_map(interfaces) {
+                    if !reported.is_empty() && !reported.contains(&nic) {
+                        findings.fail(
+                            format!("No bandwidth estimate for {} ({})", nic, wan),
+                            format!(
+                                "Check that tcp-traffic-scan measures {} and tags it {}={}",
+                                nic, influxdb.interface_tag, nic
+                            ),
+                        );
+                    }
+                }
+            }
+        }
+        Err(e) => {
+            let fix = metrics_fix(&e);
+            findings.fail(format!("Bandwidth query failed: {}", e), fix);
+            println!();
+            return Ok(());
+        }
+    }
+
+    match source.network_by_ip(now).await {
+        Ok(results) => {
+            let clients: HashSet<String> = results
+                .iter()
+                .filter_map(|result| result.metric.get("ip_address").cloned())
+                .collect();
+            if clients.is_empty() {
+                findings.fail(
+                    format!(
+                        "No {} / {} points of {} with an {} tag in the last {}s",
+                        influxdb.rx_field,
+                        influxdb.tx_field,
+                        influxdb.traffic_measurement,
+                        influxdb.ip_tag,
+                        influxdb.lookback_secs
+                    ),
+                    "Check influxdb.traffic_measurement, rx_field, tx_field and ip_tag against what localpacketdump writes",
+                );
+            } else {
+                findings.ok(format!("Traffic of {} client addresses", clients.len()));
+            }
+        }
+        Err(e) => {
+            let fix = metrics_fix(&e);
+            findings.fail(format!("Client traffic query failed: {}", e), fix);
+        }
+    }
+    println!();
+    Ok(())
+}
+
+fn summarize(findings: &Findings) -> Result<()> {
+    if findings.problems > 0 {
+        bail!(
+            "{} problem(s) and {} warning(s) found",
+            findings.problems,
+            findings.warnings
+        );
+    }
+    if findings.warnings > 0 {
+        println!("No problems found, {} warning(s)", findings.warnings);
+    } else {
+        println!("All checks passed");
+    }
+    Ok(())
+}
+
+fn interface_exists(nic: &NicName) -> bool {
+    Path::new("/sys/class/net").join(nic.as_str()).exists()
+}
+
+fn sorted(items: impl Iterator<Item = String>) -> String {
+    let mut items: Vec<String> = items.collect();
+    items.sort();
+    items.join(", ")
+}
+
+fn routing_fix(error: &BackendError) -> String {
+    match error {
+        BackendError::Unreachable(_) => {
+            "Check routing_service.url and that the routing service is running".to_string()
+        }
+        BackendError::Rejected { status }
+            if *status == StatusCode::UNAUTHORIZED || *status == StatusCode::FORBIDDEN =>
+        {
+            "Set the credentials the service expects with routing_service.bearer_token(_file) or routing_service.headers".to_string()
+        }
+        BackendError::Rejected { status } if *status == StatusCode::NOT_FOUND => {
+            "Check that routing_service.url points at the routing service itself, without a path".to_string()
+        }
+        BackendError::Rejected { .. } | BackendError::RateLimited { .. } => {
+            "Check the routing service's logs".to_string()
+        }
+        BackendError::Malformed(_) => {
+            "The service does not answer in the format routingFlow expects; check that the routing service is up to date".to_string()
+        }
+    }
+}
+
+fn metrics_fix(error: &MetricsError) -> String {
+    match error {
+        MetricsError::Unreachable(e)
+            if e.status() == Some(StatusCode::UNAUTHORIZED)
+                || e.status() == Some(StatusCode::FORBIDDEN) =>
+        {
+            "Set prometheus.username/password or prometheus.bearer_token(_file)".to_string()
+        }
+        MetricsError::Unreachable(e) if e.status().is_some() => {
+            "Check that prometheus.url points at Prometheus itself, without /api/v1".to_string()
+        }
+        MetricsError::Unreachable(_) => {
+            "Check prometheus.url, the TLS settings (ca_file) and that Prometheus is running"
+                .to_string()
+        }
+        MetricsError::Malformed(_) => {
+            "prometheus.url does not seem to point at a Prometheus-compatible query API".to_string()
+        }
+        MetricsError::Stale { .. } => "Check that the exporters are being scraped".to_string(),
+        MetricsError::InfluxDbUnreachable(_) => {
+            "Check influxdb.url and that InfluxDB is running".to_string()
+        }
+        MetricsError::InfluxDbRejected { status, .. }
+            if *status == StatusCode::UNAUTHORIZED || *status == StatusCode::FORBIDDEN =>
+        {
+            "Set influxdb.token(_file) to a token that may read the bucket".to_string()
+        }
+        MetricsError::InfluxDbRejected { .. } => {
+            "Check influxdb.org and influxdb.bucket".to_string()
+        }
+        MetricsError::InfluxDbMalformed(_) => {
+            "influxdb.url does not seem to point at an InfluxDB 2 query API".to_string()
+        }
+    }
+}
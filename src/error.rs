@@ -0,0 +1,68 @@
+use reqwest::StatusCode;
+use std::path::PathBuf;
+use std::time::Duration;
+use thiserror::Error;
+
+/// Why the configuration could not be loaded or does not make sense.
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("Config file {} does not exist", path.display())]
+    Missing { path: PathBuf },
+    #[error("Failed to read config file {}: {error}", path.display())]
+    Read {
+        path: PathBuf,
+        error: std::io::Error,
+    },
+    #[error("Failed to parse config file {}: {error}", path.display())]
+    Parse {
+        path: PathBuf,
+        error: toml::de::Error,
+    },
+    #[error("{0}")]
+    Invalid(String),
+}
+
+/// Failure to get usable samples out of Prometheus or InfluxDB.
+#[derive(Debug, Error)]
+pub enum MetricsError {
+    /// Prometheus could not be reached or answered with an HTTP error.
+    #[error("Failed to query Prometheus: {0}")]
+    Unreachable(reqwest::Error),
+    /// The newest sample is older than the caller accepts.
+    #[error("data stale for {age_secs}s")]
+    Stale { age_secs: u64 },
+    /// The response was not a Prometheus query result.
+    #[error("Failed to parse Prometheus response: {0}")]
+    Malformed(String),
+    /// InfluxDB could not be reached or answered with a server error.
+    #[error("Failed to query InfluxDB: {0}")]
+    InfluxDbUnreachable(reqwest::Error),
+    /// InfluxDB refused the Flux query, e.g. for a missing bucket or a bad token.
+    #[error("InfluxDB rejected the query ({status}): {message}")]
+    InfluxDbRejected { status: StatusCode, message: String },
+    /// The response was not the CSV of a Flux result.
+    #[error("Failed to parse InfluxDB response: {0}")]
+    InfluxDbMalformed(String),
+}
+
+/// Failure of a call to the routing service.
+#[derive(Debug, Error)]
+pub enum BackendError {
+    /// The service answered with a non-success status.
+    #[error("API returned error status: {status}")]
+    Rejected { status: StatusCode },
+    /// The service asked us to slow down (HTTP 429).
+    #[error("API is rate limiting requests{}", retry_hint(*retry_after))]
+    RateLimited { retry_after: Option<Duration> },
+    #[error("Failed to reach API: {0}")]
+    Unreachable(reqwest::Error),
+    /// The response body was not what the service is expected to return.
+    #[error("Failed to parse routing service response: {0}")]
+    Malformed(reqwest::Error),
+}
+
+fn retry_hint(retry_after: Option<Duration>) -> String {
+    retry_after
+        .map(|after| format!("; retry after {}s", after.as_secs()))
+        .unwrap_or_default()
+}
@@ -0,0 +1,137 @@
+use crate::config::{EventStreamConfig, LagPolicy};
+use crate::events::{Event, EventBus};
+use crate::metrics::Metrics;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::Notify;
+
+/// Hands out live event streams for HTTP clients. Every client gets its own bounded queue,
+/// so a slow one loses events (or its stream, with `on_lag = "disconnect"`) instead of
+/// making the daemon hold everything it has not read.
+pub struct EventStream {
+    config: EventStreamConfig,
+    bus: EventBus,
+    metrics: Arc<Metrics>,
+}
+
+/// What a client reads next.
+pub enum Delivery {
+    Event(Arc<Event>),
+    /// This many events were dropped since the last delivery.
+    Lagged(u64),
+}
+
+struct Queue {
+    events: VecDeque<Arc<Event>>,
+    dropped: u64,
+    closed: bool,
+}
+
+struct Shared {
+    queue: Mutex<Queue>,
+    ready: Notify,
+}
+
+pub struct Subscription {
+    shared: Arc<Shared>,
+    metrics: Arc<Metrics>,
+}
+
+impl EventStream {
+    pub fn new(config: EventStreamConfig, bus: EventBus, metrics: Arc<Metrics>) -> Self {
+        Self {
+            config,
+            bus,
+            metrics,
+        }
+    }
+
+    /// Starts queueing events for a new client.
+    pub fn subscribe(&self) -> Subscription {
+        let shared = Arc::new(Shared {
+            queue: Mutex::new(Queue {
+                events: VecDeque::new(),
+                dropped: 0,
+                closed: false,
+            }),
+            ready: Notify::new(),
+        });
+        self.metrics.record_stream_client(true);
+
+        let mut events = self.bus.subscribe();
+        let (capacity, on_lag) = (self.config.buffer.max(1), self.config.on_lag);
+        let (queue, metrics) = (shared.clone(), self.metrics.clone());
+        tokio::spawn(async move {
+            loop {
+                let received = events.recv().await;
+                let mut state = queue.queue.lock().unwrap();
+                if state.closed {
+                    return;
+                }
+                let mut dropped = 0;
+                match received {
+                    Ok(event) => state.events.push_back(event),
+                    // The bus itself overran this client's forwarding
+                    Err(RecvError::Lagged(missed)) => dropped = missed,
+                    Err(RecvError::Closed) => state.closed = true,
+                }
+                while state.events.len() > capacity {
+                    state.events.pop_front();
+                    dropped += 1;
+                }
+                if dropped > 0 {
+                    metrics.record_stream_drops(dropped);
+                    match on_lag {
+                        LagPolicy::DropOldest => state.dropped += dropped,
+                        LagPolicy::Disconnect => {
+                            metrics.record_stream_disconnect();
+                            state.events.clear();
+                            state.closed = true;
+                        }
+                    }
+                }
+                let closed = state.closed;
+                drop(state);
+                queue.ready.notify_one();
+                if closed {
+                    return;
+                }
+            }
+        });
+
+        Subscription {
+            shared,
+            metrics: self.metrics.clone(),
+        }
+    }
+}
+
+impl Subscription {
+    /// The next delivery, or `None` once the stream is closed.
+    pub async fn next(&mut self) -> Option<Delivery> {
+        loop {
+            {
+                let mut state = self.shared.queue.lock().unwrap();
+                if state.dropped > 0 {
+                    return Some(Delivery::Lagged(std::mem::take(&mut state.dropped)));
+                }
+                if let Some(event) = state.events.pop_front() {
+                    return Some(Delivery::Event(event));
+                }
+                if state.closed {
+                    return None;
+                }
+            }
+            self.shared.ready.notified().await;
+        }
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        // The forwarder notices on its next event
+        self.shared.queue.lock().unwrap().closed = true;
+        self.metrics.record_stream_client(false);
+    }
+}
@@ -0,0 +1,219 @@
+use crate::model::{ClientIp, NicName, WanId};
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use std::sync::Arc;
+use tokio::sync::broadcast;
+
+/// Capacity of the in-process event channel; slow sinks lose the oldest events.
+const EVENT_CHANNEL_CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct NicSummary {
+    pub nic: NicName,
+    pub wan: Option<WanId>,
+    pub tcp_bandwidth_bps: f64,
+    pub tx_bps: f64,
+    pub rx_bps: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    /// A switch was attempted; `error` is set when it failed.
+    Switch {
+        timestamp: u64,
+        ip: ClientIp,
+        from_nic: NicName,
+        target_wan: WanId,
+        reason: String,
+        success: bool,
+        error: Option<String>,
+    },
+    /// A candidate was considered but not switched.
+    SwitchSkipped {
+        timestamp: u64,
+        ip: ClientIp,
+        reason: String,
+    },
+    /// Observed traffic on a NIC rose above its TCP bandwidth estimate (edge-triggered).
+    BandwidthExceeded {
+        timestamp: u64,
+        nic: NicName,
+        wan: Option<WanId>,
+        tcp_bandwidth_bps: f64,
+        traffic_bps: f64,
+    },
+    /// A WAN was detected as down, or came back up (edge-triggered).
+    WanHealth {
+        timestamp: u64,
+        wan: WanId,
+        nic: NicName,
+        up: bool,
+        reason: String,
+    },
+    /// A WAN's public address changed; `previous` is `None` the first time one is found.
+    PublicIpChange {
+        timestamp: u64,
+        wan: WanId,
+        nic: NicName,
+        previous: Option<IpAddr>,
+        current: IpAddr,
+    },
+    /// A client (`ip`) or a whole group became routinely over its quota and is sent to
+    /// `bulk_wan`, or is back under it (edge-triggered).
+    Quota {
+        timestamp: u64,
+        quota: String,
+        ip: Option<ClientIp>,
+        exceeded: bool,
+        traffic_bps: f64,
+        quota_bps: f64,
+        bulk_wan: WanId,
+    },
+    /// A client's traffic jumped far above its baseline and it is sent to `quarantine_wan`
+    /// if one is set, or it is back to normal (edge-triggered).
+    Anomaly {
+        timestamp: u64,
+        ip: ClientIp,
+        anomalous: bool,
+        traffic_bps: f64,
+        baseline_bps: f64,
+        z_score: f64,
+        quarantine_wan: Option<WanId>,
+    },
+    /// A policy selected at runtime started shadowing (`active = false`) or took control.
+    PolicyChange {
+        timestamp: u64,
+        policy: String,
+        previous: String,
+        requested_by: String,
+        active: bool,
+    },
+    /// A NIC stayed above its TCP bandwidth estimate for `events.overload_secs` since
+    /// `since`, or is back below it (`sustained = false`; edge-triggered).
+    Overload {
+        timestamp: u64,
+        nic: NicName,
+        wan: Option<WanId>,
+        since: u64,
+        sustained: bool,
+        tcp_bandwidth_bps: f64,
+        traffic_bps: f64,
+    },
+    /// `switches` switches were made within `window_secs`, or the rate is back under the
+    /// limit (`active = false`; edge-triggered).
+    SwitchStorm {
+        timestamp: u64,
+        switches: usize,
+        window_secs: u64,
+        active: bool,
+    },
+    /// Per-cycle traffic overview.
+    TrafficSummary {
+        timestamp: u64,
+        nics: Vec<NicSummary>,
+        jain_index: Option<f64>,
+    },
+}
+
+impl Event {
+    /// Every value of [`Event::kind`].
+    pub const KINDS: [&'static str; 11] = [
+        "switch",
+        "switch_skipped",
+        "bandwidth_exceeded",
+        "wan_health",
+        "public_ip_change",
+        "quota",
+        "anomaly",
+        "policy_change",
+        "overload",
+        "switch_storm",
+        "traffic_summary",
+    ];
+
+    /// Short name used as topic/subject suffix by sinks.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Event::Switch { .. } => "switch",
+            Event::SwitchSkipped { .. } => "switch_skipped",
+            Event::BandwidthExceeded { .. } => "bandwidth_exceeded",
+            Event::WanHealth { .. } => "wan_health",
+            Event::PublicIpChange { .. } => "public_ip_change",
+            Event::Quota { .. } => "quota",
+            Event::Anomaly { .. } => "anomaly",
+            Event::PolicyChange { .. } => "policy_change",
+            Event::Overload { .. } => "overload",
+            Event::SwitchStorm { .. } => "switch_storm",
+            Event::TrafficSummary { .. } => "traffic_summary",
+        }
+    }
+
+    pub fn timestamp(&self) -> u64 {
+        match self {
+            Event::Switch { timestamp, .. }
+            | Event::SwitchSkipped { timestamp, .. }
+            | Event::BandwidthExceeded { timestamp, .. }
+            | Event::WanHealth { timestamp, .. }
+            | Event::PublicIpChange { timestamp, .. }
+            | Event::Quota { timestamp, .. }
+            | Event::Anomaly { timestamp, .. }
+            | Event::PolicyChange { timestamp, .. }
+            | Event::Overload { timestamp, .. }
+            | Event::SwitchStorm { timestamp, .. }
+            | Event::TrafficSummary { timestamp, .. } => *timestamp,
+        }
+    }
+
+    /// How urgently a person should hear about the event.
+    pub fn severity(&self) -> Severity {
+        match self {
+            Event::WanHealth { up: false, .. } => Severity::Critical,
+            Event::Switch { success: false, .. }
+            | Event::BandwidthExceeded { .. }
+            | Event::Quota { exceeded: true, .. }
+            | Event::Anomaly {
+                anomalous: true, ..
+            }
+            | Event::Overload {
+                sustained: true, ..
+            }
+            | Event::SwitchStorm { active: true, .. } => Severity::Warning,
+            Event::SwitchSkipped { .. } | Event::TrafficSummary { .. } => Severity::Debug,
+            _ => Severity::Info,
+        }
+    }
+}
+
+/// Event severities, least urgent first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// Routine output of every cycle.
+    Debug,
+    Info,
+    Warning,
+    Critical,
+}
+
+/// Fans events out to every subscribed sink without blocking the scan loop.
+#[derive(Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<Arc<Event>>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(EVENT_CHANNEL_CAPACITY);
+        Self { sender }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<Event>> {
+        self.sender.subscribe()
+    }
+
+    pub fn emit(&self, event: Event) {
+        // No subscribers is not an error: sinks are optional
+        let _ = self.sender.send(Arc::new(event));
+    }
+}
@@ -0,0 +1,161 @@
+use crate::cli::ExportArgs;
+use crate::config::Config;
+use crate::history_db::{HistoryQuery, StoredNicStats, StoredSwitch};
+use crate::store;
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ExportFormat {
+    /// One table of NIC stats and switches, ordered by time, `kind` telling them apart
+    Csv,
+}
+
+const CSV_HEADER: [&str; 16] = [
+    "timestamp",
+    "time",
+    "kind",
+    "nic",
+    "wan",
+    "interval_secs",
+    "tcp_bandwidth_bps",
+    "tx_bps",
+    "rx_bps",
+    "clients",
+    "ip",
+    "from_wan",
+    "to_wan",
+    "result",
+    "reason",
+    "error",
+];
+
+enum Row {
+    NicStats(StoredNicStats),
+    Switch(StoredSwitch),
+}
+
+impl Row {
+    fn timestamp(&self) -> u64 {
+        match self {
+            Row::NicStats(stats) => stats.timestamp,
+            Row::Switch(switch) => switch.timestamp,
+        }
+    }
+
+    fn fields(&self) -> [String; 16] {
+        let time = chrono::DateTime::from_timestamp(self.timestamp() as i64, 0)
+            .map(|time| time.to_rfc3339())
+            .unwrap_or_default();
+        match self {
+            Row::NicStats(stats) => [
+                stats.timestamp.to_string(),
+                time,
+                "nic_stats".to_string(),
+                stats.nic.clone(),
+                stats.wan.clone().unwrap_or_default(),
+                stats.interval_secs.to_string(),
+                format!("{:.0}", stats.tcp_bandwidth_bps),
+                format!("{:.0}", stats.tx_bps),
+                format!("{:.0}", stats.rx_bps),
+                stats.clients.to_string(),
+                String::new(),
+                String::new(),
+                String::new(),
+                String::new(),
+                String::new(),
+                String::new(),
+            ],
+            Row::Switch(switch) => [
+                switch.timestamp.to_string(),
+                time,
+                "switch".to_string(),
+                String::new(),
+                String::new(),
+                String::new(),
+                String::new(),
+                String::new(),
+                String::new(),
+                String::new(),
+                switch.ip.clone(),
+                switch.from_wan.clone().unwrap_or_default(),
+                switch.to_wan.clone(),
+                match &switch.verification {
+                    Some(verification) => format!("{} ({})", switch.result, verification),
+                    None => switch.result.clone(),
+                },
+                switch.reason.clone(),
+                switch.error.clone().unwrap_or_default(),
+            ],
+        }
+    }
+}
+
+/// Implements the `export` subcommand at `now`: the stored NIC stats and switches, for
+/// analysis in a spreadsheet.
+pub fn run_export_command(config: &Config, args: &ExportArgs, now: u64) -> Result<()> {
+    let Some(db) = store::open_database(&config.history)? else {
+        bail!(
+            "No switch history database at {}",
+            config.history.db_path.display()
+        );
+    };
+    let since = args.since.map(|since| now.saturating_sub(since));
+
+    let mut rows: Vec<Row> = db
+        .query_nic_stats(since)?
+        .into_iter()
+        .map(Row::NicStats)
+        .collect();
+    rows.extend(
+        db.query_switches(&HistoryQuery {
+            since,
+            ..HistoryQuery::default()
+        })?
+        .into_iter()
+        .map(Row::Switch),
+    );
+    // Stable, so switches within one interval stay after its NIC stats
+    rows.sort_by_key(Row::timestamp);
+
+    let mut out: Box<dyn Write> = match &args.output {
+        Some(path) => {
+            Box::new(BufWriter::new(File::create(path).with_context(|| {
+                format!("Failed to create {}", path.display())
+            })?))
+        }
+        None => Box::new(BufWriter::new(io::stdout().lock())),
+    };
+    match args.format {
+        ExportFormat::Csv => {
+            write_csv_line(&mut out, &CSV_HEADER)?;
+            for row in &rows {
+                write_csv_line(&mut out, &row.fields())?;
+            }
+        }
+    }
+    out.flush()?;
+
+    if rows.is_empty() {
+        eprintln!("(No NIC stats or switches recorded in that period)");
+    }
+    Ok(())
+}
+
+fn write_csv_line<S: AsRef<str>>(out: &mut dyn Write, fields: &[S]) -> io::Result<()> {
+    let line: Vec<String> = fields
+        .iter()
+        .map(|field| {
+            let field = field.as_ref();
+            // RFC 4180 quoting
+            if field.contains([',', '"', '\n', '\r']) {
+                format!("\"{}\"", field.replace('"', "\"\""))
+            } else {
+                field.to_string()
+            }
+        })
+        .collect();
+    writeln!(out, "{}", line.join(","))
+}
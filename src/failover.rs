@@ -0,0 +1,202 @@
+use crate::config::FailoverConfig;
+use crate::model::{ClientIp, NicName, WanId};
+use crate::policy::{PolicyInput, SwitchDecision};
+use crate::probe::WanProbeStats;
+use crate::prometheus;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Failover moves for one cycle.
+#[derive(Debug, Default)]
+pub struct FailoverPlan {
+    /// Clients leaving a dead WAN; these bypass cooldowns.
+    pub evacuations: Vec<SwitchDecision>,
+    /// Evacuated clients returning to their recovered WAN.
+    pub failbacks: Vec<SwitchDecision>,
+}
+
+/// A WAN going down or coming back up.
+#[derive(Debug, Clone)]
+pub struct HealthChange {
+    pub wan: WanId,
+    pub nic: NicName,
+    pub up: bool,
+    pub reason: String,
+}
+
+/// What a failover tracks, as handed to the instance taking over.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct FailoverState {
+    pub down: HashMap<WanId, (u64, String)>,
+    pub recovered_at: HashMap<WanId, u64>,
+    pub evacuated: HashMap<ClientIp, WanId>,
+}
+
+/// Tracks WAN health from sample staleness (and probes), moves clients off dead WANs and,
+/// once a WAN has been healthy for `recovery_secs`, back onto it.
+pub struct Failover {
+    config: FailoverConfig,
+    /// WAN → (down since, why)
+    down: HashMap<WanId, (u64, String)>,
+    /// WAN → time it was last seen recovering
+    recovered_at: HashMap<WanId, u64>,
+    /// Evacuated client IP → WAN it was moved away from
+    evacuated: HashMap<ClientIp, WanId>,
+}
+
+impl Failover {
+    pub fn new(config: FailoverConfig) -> Self {
+        Self {
+            config,
+            down: HashMap::new(),
+            recovered_at: HashMap::new(),
+            evacuated: HashMap::new(),
+        }
+    }
+
+    /// Re-evaluates every WAN. `sample_times` maps NICs to the Unix time of their newest
+    /// TCP bandwidth sample.
+    pub fn update(
+        &mut self,
+        wan_to_nic: &HashMap<WanId, NicName>,
+        sample_times: &HashMap<NicName, f64>,
+        wan_probes: &HashMap<WanId, WanProbeStats>,
+        now: u64,
+    ) -> Vec<HealthChange> {
+        let mut wans: Vec<_> = wan_to_nic.iter().collect();
+        wans.sort();
+
+        let mut changes = Vec::new();
+        for (wan, nic) in wans {
+            let failure = match sample_times.get(nic) {
+                None => Some("no TCP bandwidth samples".to_string()),
+                Some(sampled_at) => {
+                    prometheus::check_fresh(*sampled_at, now, self.config.stale_after_secs)
+                        .err()
+                        .map(|e| format!("TCP bandwidth {}", e))
+                }
+            }
+            .or_else(|| {
+                wan_probes
+                    .get(wan)
+                    .is_some_and(WanProbeStats::is_down)
+                    .then(|| "all latency probes failing".to_string())
+            });
+
+            match (failure, self.down.contains_key(wan)) {
+                (Some(reason), false) => {
+                    self.down.insert(wan.clone(), (now, reason.clone()));
+                    self.recovered_at.remove(wan);
+                    changes.push(HealthChange {
+                        wan: wan.clone(),
+                        nic: nic.clone(),
+                        up: false,
+                        reason,
+                    });
+                }
+                (None, true) => {
+                    let (since, _) = self.down.remove(wan).unwrap();
+                    self.recovered_at.insert(wan.clone(), now);
+                    changes.push(HealthChange {
+                        wan: wan.clone(),
+                        nic: nic.clone(),
+                        up: true,
+                        reason: format!("healthy again after {}s", now.saturating_sub(since)),
+                    });
+                }
+                _ => {}
+            }
+        }
+
+        changes
+    }
+
+    pub fn export(&self) -> FailoverState {
+        FailoverState {
+            down: self.down.clone(),
+            recovered_at: self.recovered_at.clone(),
+            evacuated: self.evacuated.clone(),
+        }
+    }
+
+    pub fn restore(&mut self, state: FailoverState) {
+        self.down = state.down;
+        self.recovered_at = state.recovered_at;
+        self.evacuated = state.evacuated;
+    }
+
+    pub fn is_down(&self, wan: &WanId) -> bool {
+        self.down.contains_key(wan)
+    }
+
+    /// Evacuations of clients on dead WANs and fail-backs to recovered ones.
+    pub fn plan(&mut self, input: &PolicyInput, now: u64) -> FailoverPlan {
+        let mut healthy: Vec<&WanId> = input
+            .wan_to_nic
+            .keys()
+            .filter(|wan| !self.is_down(wan))
+            .collect();
+        healthy.sort();
+
+        let mut mappings: Vec<(&ClientIp, &WanId)> = input.mappings.iter().collect();
+        mappings.sort();
+
+        let mut plan = FailoverPlan::default();
+        for (ip, wan) in mappings {
+            let Some(nic) = input.wan_to_nic.get(wan) else {
+                continue;
+            };
+
+            if let Some((_, reason)) = self.down.get(wan) {
+                let Some(target_wan) = healthy.first() else {
+                    continue;
+                };
+                self.evacuated.entry(*ip).or_insert_with(|| wan.clone());
+                plan.evacuations.push(SwitchDecision {
+                    ip: *ip,
+                    from_nic: nic.clone(),
+                    target_wan: (*target_wan).clone(),
+                    rx_bps: rx_bps(input, *ip),
+                    reason: format!("{} is down ({}); failing over", wan, reason),
+                });
+                continue;
+            }
+
+            let Some(original_wan) = self.evacuated.get(ip) else {
+                continue;
+            };
+            if original_wan == wan {
+                self.evacuated.remove(ip);
+                continue;
+            }
+            let recovered = !self.is_down(original_wan)
+                && self
+                    .recovered_at
+                    .get(original_wan)
+                    .is_some_and(|at| now.saturating_sub(*at) >= self.config.recovery_secs);
+            if self.config.failback && recovered {
+                plan.failbacks.push(SwitchDecision {
+                    ip: *ip,
+                    from_nic: nic.clone(),
+                    target_wan: original_wan.clone(),
+                    rx_bps: rx_bps(input, *ip),
+                    reason: format!("{} has recovered; failing back", original_wan),
+                });
+            }
+        }
+
+        // Clients that disappeared while evacuated need no fail-back
+        self.evacuated
+            .retain(|ip, _| input.mappings.contains_key(ip));
+
+        plan
+    }
+}
+
+fn rx_bps(input: &PolicyInput, ip: ClientIp) -> f64 {
+    input
+        .ip_traffic
+        .iter()
+        .find(|traffic| traffic.ip == ip)
+        .map_or(0.0, |traffic| traffic.rx_bps)
+}
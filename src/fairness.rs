@@ -0,0 +1,55 @@
+use crate::model::WanId;
+use std::collections::HashMap;
+
+/// Per-cycle indicators of how evenly load is spread across the WANs.
+#[derive(Debug, Clone)]
+pub struct FairnessMetrics {
+    /// Utilization (total traffic / estimated TCP bandwidth) per WAN, sorted by WAN id.
+    pub utilizations: Vec<(WanId, f64)>,
+    /// Jain's fairness index: 1.0 when all WANs are equally utilized, 1/n in the worst case.
+    pub jain_index: f64,
+    /// Ratio between the most and least utilized WAN, `None` when the least utilized WAN is idle.
+    pub max_min_ratio: Option<f64>,
+}
+
+/// Computes fairness metrics from per-WAN `(traffic_bps, capacity_bps)` samples.
+/// WANs with no capacity estimate are ignored; returns `None` if none remain.
+pub fn compute(samples: &HashMap<WanId, (f64, f64)>) -> Option<FairnessMetrics> {
+    let mut utilizations: Vec<(WanId, f64)> = samples
+        .iter()
+        .filter(|(_, (_, capacity))| *capacity > 0.0)
+        .map(|(wan, (traffic, capacity))| (wan.clone(), traffic / capacity))
+        .collect();
+
+    if utilizations.is_empty() {
+        return None;
+    }
+    utilizations.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let n = utilizations.len() as f64;
+    let sum: f64 = utilizations.iter().map(|(_, u)| u).sum();
+    let sum_sq: f64 = utilizations.iter().map(|(_, u)| u * u).sum();
+
+    // An idle network is perfectly (if trivially) fair
+    let jain_index = if sum_sq > 0.0 {
+        (sum * sum) / (n * sum_sq)
+    } else {
+        1.0
+    };
+
+    let max = utilizations
+        .iter()
+        .map(|(_, u)| *u)
+        .fold(f64::MIN, f64::max);
+    let min = utilizations
+        .iter()
+        .map(|(_, u)| *u)
+        .fold(f64::MAX, f64::min);
+    let max_min_ratio = if min > 0.0 { Some(max / min) } else { None };
+
+    Some(FairnessMetrics {
+        utilizations,
+        jain_index,
+        max_min_ratio,
+    })
+}
@@ -0,0 +1,77 @@
+use crate::config::FlowSteeringConfig;
+use crate::model::ClientIp;
+use crate::prometheus::PrometheusResult;
+use std::collections::HashMap;
+use std::fmt;
+
+/// One flow of a client, by the client's end of it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Flow {
+    pub port: u16,
+    pub proto: String,
+    pub rx_bps: f64,
+}
+
+impl fmt::Display for Flow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.port, self.proto)
+    }
+}
+
+/// Per-flow rates from the exporters, so a busy client can be relieved by moving only its
+/// heaviest flows while the rest of its traffic stays put.
+pub struct FlowSteering {
+    config: FlowSteeringConfig,
+    /// Client → its flows worth moving, heaviest first.
+    flows: HashMap<ClientIp, Vec<Flow>>,
+}
+
+impl FlowSteering {
+    pub fn new(config: FlowSteeringConfig) -> Self {
+        Self {
+            config,
+            flows: HashMap::new(),
+        }
+    }
+
+    pub fn switch_path(&self) -> &str {
+        &self.config.switch_path
+    }
+
+    /// Query for the flows fast enough to move.
+    pub fn query(&self) -> String {
+        format!("{} >= {}", self.config.rx_metric, self.config.min_bps)
+    }
+
+    /// Replaces the flows with those in `results`; `None` (no answer) leaves no flows, so
+    /// whole clients are moved.
+    pub fn update(&mut self, results: Option<&[PrometheusResult]>) {
+        self.flows.clear();
+        for result in results.unwrap_or_default() {
+            let (Some(ip), Some(port), Some(proto), Ok(rx_bps)) = (
+                result.label::<ClientIp>(&self.config.ip_label),
+                result.label::<u16>(&self.config.port_label),
+                result.metric.get(&self.config.proto_label),
+                result.value.1.parse::<f64>(),
+            ) else {
+                continue;
+            };
+            if rx_bps.is_finite() && rx_bps >= self.config.min_bps {
+                self.flows.entry(ip).or_default().push(Flow {
+                    port,
+                    proto: proto.clone(),
+                    rx_bps,
+                });
+            }
+        }
+        for flows in self.flows.values_mut() {
+            flows.sort_by(|a, b| b.rx_bps.total_cmp(&a.rx_bps));
+            flows.truncate(self.config.max_flows);
+        }
+    }
+
+    /// The flows of `ip` to move instead of the whole client; empty to move the client.
+    pub fn heaviest(&self, ip: ClientIp) -> &[Flow] {
+        self.flows.get(&ip).map(Vec::as_slice).unwrap_or_default()
+    }
+}
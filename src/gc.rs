@@ -0,0 +1,71 @@
+use crate::config::MappingGcConfig;
+use crate::model::{ClientIp, IpTraffic, WanId};
+use std::collections::{HashMap, HashSet};
+
+/// Tracks when each mapped IP was last active (traffic or ARP presence) and
+/// reports mappings that have been idle for longer than the configured period.
+pub struct MappingGc {
+    config: MappingGcConfig,
+    last_seen: HashMap<ClientIp, u64>,
+    reported: HashSet<ClientIp>,
+}
+
+impl MappingGc {
+    pub fn new(config: MappingGcConfig) -> Self {
+        Self {
+            config,
+            last_seen: HashMap::new(),
+            reported: HashSet::new(),
+        }
+    }
+
+    pub fn config(&self) -> &MappingGcConfig {
+        &self.config
+    }
+
+    /// Returns `(ip, idle_secs)` for mappings that newly crossed the idle threshold.
+    pub fn sweep(
+        &mut self,
+        mappings: &HashMap<ClientIp, WanId>,
+        ip_traffic: &[IpTraffic],
+        arp_present: &HashSet<ClientIp>,
+        now: u64,
+    ) -> Vec<(ClientIp, u64)> {
+        // Forget IPs the backend no longer maps
+        self.last_seen.retain(|ip, _| mappings.contains_key(ip));
+        self.reported.retain(|ip| mappings.contains_key(ip));
+
+        let active: HashSet<ClientIp> = ip_traffic
+            .iter()
+            .filter(|traffic| traffic.rx_bps > 0.0 || traffic.tx_bps > 0.0)
+            .map(|traffic| traffic.ip)
+            .chain(arp_present.iter().copied())
+            .collect();
+
+        let mut stale = Vec::new();
+
+        for ip in mappings.keys() {
+            // First sighting counts as activity so a restart does not flag everything
+            let last_seen = self.last_seen.entry(*ip).or_insert(now);
+            if active.contains(ip) {
+                *last_seen = now;
+                self.reported.remove(ip);
+                continue;
+            }
+
+            let idle = now.saturating_sub(*last_seen);
+            if idle >= self.config.idle_secs && self.reported.insert(*ip) {
+                stale.push((*ip, idle));
+            }
+        }
+
+        stale.sort();
+        stale
+    }
+
+    /// Forgets a mapping after the backend removed it.
+    pub fn forget(&mut self, ip: ClientIp) {
+        self.last_seen.remove(&ip);
+        self.reported.remove(&ip);
+    }
+}
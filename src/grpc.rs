@@ -0,0 +1,214 @@
+use crate::auth::{AuthError, Role};
+use crate::event_stream::Delivery;
+use crate::events::Event;
+use crate::model::{ClientIp, IpTraffic, NicName, NicStats, WanId};
+use crate::server::AppState;
+use anyhow::{Context, Result};
+use futures_util::stream::{self, Stream};
+use pb::routing_flow_server::{RoutingFlow, RoutingFlowServer};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use tonic::service::Routes;
+use tonic::{Request, Response, Status};
+use tracing::{debug, error, info};
+
+/// Messages and service of proto/routingflow.proto, generated by build.rs.
+pub mod pb {
+    tonic::include_proto!("routingflow.v1");
+}
+
+/// The latest NIC stats and mappings for the unary RPCs, published by the monitor loop.
+#[derive(Default)]
+pub struct GrpcView {
+    latest: Mutex<(pb::GetNicStatsResponse, pb::GetMappingsResponse)>,
+}
+
+impl GrpcView {
+    pub fn record(
+        &self,
+        timestamp: u64,
+        nic_stats: &HashMap<NicName, NicStats>,
+        wan_to_nic: &HashMap<WanId, NicName>,
+        mappings: &HashMap<ClientIp, WanId>,
+        ip_traffic: &[IpTraffic],
+    ) {
+        let nic_to_wan: HashMap<&NicName, &WanId> =
+            wan_to_nic.iter().map(|(wan, nic)| (nic, wan)).collect();
+        let mut clients: HashMap<&WanId, u32> = HashMap::new();
+        for wan in mappings.values() {
+            *clients.entry(wan).or_default() += 1;
+        }
+        let mut nics: Vec<pb::NicStats> = nic_stats
+            .iter()
+            .map(|(nic, stats)| {
+                let wan = nic_to_wan.get(nic);
+                pb::NicStats {
+                    nic: nic.to_string(),
+                    wan: wan.map(|wan| wan.to_string()).unwrap_or_default(),
+                    tcp_bandwidth_bps: stats.tcp_bandwidth,
+                    tx_bps: stats.tx_bps,
+                    rx_bps: stats.rx_bps,
+                    clients: wan.and_then(|wan| clients.get(wan)).copied().unwrap_or(0),
+                    queue_congested: stats.queue_congested,
+                }
+            })
+            .collect();
+        nics.sort_by(|a, b| a.nic.cmp(&b.nic));
+
+        let traffic: HashMap<&ClientIp, &IpTraffic> = ip_traffic
+            .iter()
+            .map(|traffic| (&traffic.ip, traffic))
+            .collect();
+        let mut rows: Vec<(ClientIp, pb::Mapping)> = mappings
+            .iter()
+            .map(|(ip, wan)| {
+                let traffic = traffic.get(ip);
+                let row = pb::Mapping {
+                    ip: ip.to_string(),
+                    wan: wan.to_string(),
+                    rx_bps: traffic.map_or(0.0, |traffic| traffic.rx_bps),
+                    tx_bps: traffic.map_or(0.0, |traffic| traffic.tx_bps),
+                };
+                (*ip, row)
+            })
+            .collect();
+        rows.sort_by_key(|(ip, _)| *ip);
+
+        *self.latest.lock().unwrap() = (
+            pb::GetNicStatsResponse { timestamp, nics },
+            pb::GetMappingsResponse {
+                timestamp,
+                mappings: rows.into_iter().map(|(_, row)| row).collect(),
+            },
+        );
+    }
+}
+
+/// Binds the gRPC server and serves it in the background, on axum like the HTTP API
+/// (prior-knowledge HTTP/2 is accepted alongside HTTP/1).
+pub async fn spawn(listen: SocketAddr, state: AppState, view: Arc<GrpcView>) -> Result<()> {
+    let listener = tokio::net::TcpListener::bind(listen)
+        .await
+        .with_context(|| format!("Failed to bind gRPC server to {}", listen))?;
+    info!("Serving gRPC API on {}", listen);
+
+    let app = Routes::new(RoutingFlowServer::new(Service { state, view })).into_axum_router();
+    tokio::spawn(async move {
+        if let Err(e) = axum::serve(listener, app).await {
+            error!("gRPC server stopped: {}", e);
+        }
+    });
+
+    Ok(())
+}
+
+struct Service {
+    state: AppState,
+    view: Arc<GrpcView>,
+}
+
+impl Service {
+    /// Every RPC needs the viewer role.
+    async fn authorize<T>(&self, request: &Request<T>) -> Result<(), Status> {
+        let headers = request.metadata().clone().into_headers();
+        match self.state.auth.authorize(&headers, Role::Viewer).await {
+            Ok(_) => Ok(()),
+            Err(AuthError::Unauthenticated) => {
+                Err(Status::unauthenticated("missing or unknown credentials"))
+            }
+            Err(AuthError::Forbidden { required, .. }) => Err(Status::permission_denied(format!(
+                "needs the {} role",
+                required
+            ))),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl RoutingFlow for Service {
+    async fn get_nic_stats(
+        &self,
+        request: Request<pb::GetNicStatsRequest>,
+    ) -> Result<Response<pb::GetNicStatsResponse>, Status> {
+        self.authorize(&request).await?;
+        Ok(Response::new(self.view.latest.lock().unwrap().0.clone()))
+    }
+
+    async fn get_mappings(
+        &self,
+        request: Request<pb::GetMappingsRequest>,
+    ) -> Result<Response<pb::GetMappingsResponse>, Status> {
+        self.authorize(&request).await?;
+        Ok(Response::new(self.view.latest.lock().unwrap().1.clone()))
+    }
+
+    type StreamDecisionsStream = Pin<Box<dyn Stream<Item = Result<pb::Decision, Status>> + Send>>;
+
+    /// Streams switch decisions from the event stream until the client goes away. Like the
+    /// `/events` clients it has a bounded queue, so a slow reader loses decisions rather
+    /// than holding them in the daemon.
+    async fn stream_decisions(
+        &self,
+        request: Request<pb::StreamDecisionsRequest>,
+    ) -> Result<Response<Self::StreamDecisionsStream>, Status> {
+        self.authorize(&request).await?;
+        let include_skipped = request.into_inner().include_skipped;
+        let subscription = self.state.event_stream.subscribe();
+        let decisions = stream::unfold(Some(subscription), move |subscription| async move {
+            let mut subscription = subscription?;
+            while let Some(delivery) = subscription.next().await {
+                if let Some(decision) = decision(delivery, include_skipped) {
+                    return Some((Ok(decision), Some(subscription)));
+                }
+            }
+            // Only when a lagging client was disconnected or the daemon is stopping
+            Some((Err(Status::unavailable("decision stream closed")), None))
+        });
+        Ok(Response::new(Box::pin(decisions)))
+    }
+}
+
+/// The switch decision an event stream delivery carries, if any.
+fn decision(delivery: Delivery, include_skipped: bool) -> Option<pb::Decision> {
+    let event = match delivery {
+        Delivery::Event(event) => event,
+        Delivery::Lagged(dropped) => {
+            debug!(dropped, "gRPC decision stream fell behind");
+            return None;
+        }
+    };
+    match event.as_ref() {
+        Event::Switch {
+            timestamp,
+            ip,
+            from_nic,
+            target_wan,
+            reason,
+            success,
+            error,
+        } => Some(pb::Decision {
+            timestamp: *timestamp,
+            ip: ip.to_string(),
+            from_nic: from_nic.to_string(),
+            target_wan: target_wan.to_string(),
+            reason: reason.clone(),
+            skipped: false,
+            success: *success,
+            error: error.clone().unwrap_or_default(),
+        }),
+        Event::SwitchSkipped {
+            timestamp,
+            ip,
+            reason,
+        } if include_skipped => Some(pb::Decision {
+            timestamp: *timestamp,
+            ip: ip.to_string(),
+            reason: reason.clone(),
+            skipped: true,
+            ..Default::default()
+        }),
+        _ => None,
+    }
+}
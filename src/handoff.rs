@@ -0,0 +1,206 @@
+use crate::breaker::BreakerCounters;
+use crate::config::HandoffConfig;
+use crate::control::{ManualPin, PauseStatus};
+use crate::failover::FailoverState;
+use crate::history::SwitchRecord;
+use crate::routing::StatusResponse;
+use crate::saved_state::SavedState;
+use crate::smoothing::SmootherState;
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::mpsc;
+use tracing::{debug, info, warn};
+
+/// The one request the control socket understands.
+const TAKE_OVER: &str = "take_over";
+
+/// What a running instance hands to the one replacing it, so the new one continues with
+/// the same holds, pins and view of the WANs instead of starting from scratch.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HandoffState {
+    /// Of the old instance, which exits once it has sent this.
+    pub pid: u32,
+    pub version: String,
+    pub switch_history: Vec<SwitchRecord>,
+    pub manual_pins: Vec<ManualPin>,
+    pub pause: Option<PauseStatus>,
+    pub failover: Option<FailoverState>,
+    /// When the old instance's soft-start ramp last started.
+    pub soft_start_since: Option<u64>,
+    /// Missing from the state of versions that did not hand these over.
+    #[serde(default)]
+    pub smoothing: Option<SmootherState>,
+    #[serde(default)]
+    pub breaker: Option<BreakerCounters>,
+    #[serde(default)]
+    pub last_status: Option<(StatusResponse, u64)>,
+}
+
+impl HandoffState {
+    /// The state to continue with at `now`: this one over the last snapshot, which fills
+    /// in only what the old instance did not hand over.
+    pub fn over(self, snapshot: Option<SavedState>, now: u64) -> SavedState {
+        let snapshot = snapshot.unwrap_or_else(|| SavedState {
+            saved_at: now,
+            version: self.version.clone(),
+            switch_history: Vec::new(),
+            manual_pins: Vec::new(),
+            pause: None,
+            failover: None,
+            soft_start_since: None,
+            smoothing: None,
+            breaker: BreakerCounters::default(),
+            last_status: None,
+        });
+        SavedState {
+            saved_at: now,
+            version: self.version,
+            switch_history: self.switch_history,
+            manual_pins: self.manual_pins,
+            pause: self.pause,
+            failover: self.failover.or(snapshot.failover),
+            soft_start_since: self.soft_start_since.or(snapshot.soft_start_since),
+            smoothing: self.smoothing.or(snapshot.smoothing),
+            breaker: self.breaker.unwrap_or(snapshot.breaker),
+            last_status: self.last_status.or(snapshot.last_status),
+        }
+    }
+}
+
+/// A new instance waiting on the control socket for the state.
+pub struct HandoffRequest {
+    stream: UnixStream,
+}
+
+/// The control socket of a running instance.
+pub struct ControlSocket {
+    path: PathBuf,
+    requests: mpsc::Receiver<HandoffRequest>,
+}
+
+impl ControlSocket {
+    /// Binds the socket, replacing one left behind by an instance that is gone.
+    pub fn bind(config: &HandoffConfig) -> Result<Self> {
+        if config.socket.exists() {
+            std::fs::remove_file(&config.socket).with_context(|| {
+                format!(
+                    "Failed to remove old control socket {}",
+                    config.socket.display()
+                )
+            })?;
+        }
+        let listener = UnixListener::bind(&config.socket).with_context(|| {
+            format!("Failed to bind control socket {}", config.socket.display())
+        })?;
+        debug!(socket = %config.socket.display(), "Listening for handoff requests");
+
+        let (sender, requests) = mpsc::channel(1);
+        tokio::spawn(async move {
+            loop {
+                let stream = match listener.accept().await {
+                    Ok((stream, _)) => stream,
+                    Err(e) => {
+                        warn!("Control socket stopped accepting: {}", e);
+                        return;
+                    }
+                };
+                let sender = sender.clone();
+                tokio::spawn(async move {
+                    let mut stream = tokio::io::BufReader::new(stream);
+                    let mut line = String::new();
+                    if stream.read_line(&mut line).await.is_err() {
+                        return;
+                    }
+                    let mut stream = stream.into_inner();
+                    if line.trim() != TAKE_OVER {
+                        let _ = stream.write_all(b"unknown request\n").await;
+                        return;
+                    }
+                    let _ = sender.send(HandoffRequest { stream }).await;
+                });
+            }
+        });
+
+        Ok(Self {
+            path: config.socket.clone(),
+            requests,
+        })
+    }
+
+    /// A handoff request that came in since the last call.
+    pub fn take_request(&mut self) -> Option<HandoffRequest> {
+        self.requests.try_recv().ok()
+    }
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            warn!(
+                "Failed to remove control socket {}: {}",
+                self.path.display(),
+                e
+            );
+        }
+    }
+}
+
+impl HandoffRequest {
+    pub async fn complete(mut self, state: &HandoffState) -> Result<()> {
+        let mut message = serde_json::to_vec(state)?;
+        message.push(b'\n');
+        self.stream
+            .write_all(&message)
+            .await
+            .context("Failed to send the state to the new instance")?;
+        self.stream.flush().await?;
+        Ok(())
+    }
+}
+
+/// Asks the instance running on the control socket for its state and waits for it to
+/// exit. Blocking, since it runs before the runtime starts and before the PID file is
+/// taken over.
+pub fn take_over(config: &HandoffConfig) -> Result<HandoffState> {
+    let timeout = Duration::from_secs(config.timeout_secs);
+    let mut stream =
+        std::os::unix::net::UnixStream::connect(&config.socket).with_context(|| {
+            format!(
+                "No running instance to take over from at {}",
+                config.socket.display()
+            )
+        })?;
+    stream.set_read_timeout(Some(timeout))?;
+    writeln!(stream, "{}", TAKE_OVER)?;
+
+    let mut line = String::new();
+    BufReader::new(stream)
+        .read_line(&mut line)
+        .context("The running instance did not hand over its state")?;
+    let state: HandoffState = serde_json::from_str(&line).with_context(|| {
+        format!(
+            "Unexpected answer from the running instance: {:?}",
+            line.trim()
+        )
+    })?;
+    info!(pid = state.pid, version = %state.version, "Running instance handed over; waiting for it to exit");
+
+    let deadline = Instant::now() + timeout;
+    // SAFETY: signal 0 only checks whether the process still exists
+    while unsafe { libc::kill(state.pid as libc::pid_t, 0) } == 0 {
+        if Instant::now() >= deadline {
+            bail!(
+                "The previous instance (PID {}) did not exit within {}s",
+                state.pid,
+                config.timeout_secs
+            );
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    Ok(state)
+}
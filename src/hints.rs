@@ -0,0 +1,182 @@
+use crate::config::{CapacityHintsConfig, HintFormat};
+use crate::model::{NicName, NicStats, WanId};
+use crate::status_page::WanStatus;
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::sync::Mutex;
+
+/// Per-WAN load and the WAN a brand-new client should start on, published every cycle for
+/// DHCP/DNS hooks (dnsmasq `--dhcp-script`, Kea `run_script`) so a new lease lands where
+/// the balancer would put it instead of always on wan0.
+pub struct CapacityHints {
+    config: CapacityHintsConfig,
+    latest: Mutex<Option<HintSnapshot>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HintSnapshot {
+    pub updated_at: u64,
+    /// The healthy WAN with the most headroom that is below its client cap; `None` when no
+    /// WAN qualifies.
+    pub preferred_wan: Option<WanId>,
+    pub preferred_nic: Option<NicName>,
+    pub wans: Vec<WanHint>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WanHint {
+    pub wan: WanId,
+    pub nic: NicName,
+    pub health: &'static str,
+    pub utilization: Option<f64>,
+    pub headroom_bps: f64,
+    pub clients: usize,
+    pub client_cap: Option<usize>,
+    /// Whether new clients may be sent here.
+    pub accepting: bool,
+}
+
+impl CapacityHints {
+    pub fn new(config: CapacityHintsConfig) -> Self {
+        Self {
+            config,
+            latest: Mutex::new(None),
+        }
+    }
+
+    pub fn update(
+        &self,
+        timestamp: u64,
+        wans: &[WanStatus],
+        nic_stats: &HashMap<NicName, NicStats>,
+        client_caps: &HashMap<WanId, usize>,
+    ) -> Result<()> {
+        let wans: Vec<WanHint> = wans
+            .iter()
+            .map(|status| {
+                let client_cap = client_caps.get(&status.wan).copied();
+                let headroom_bps = nic_stats
+                    .get(&status.nic)
+                    .map_or(0.0, |stats| stats.headroom().max(0.0));
+                WanHint {
+                    wan: status.wan.clone(),
+                    nic: status.nic.clone(),
+                    health: status.health,
+                    utilization: status.utilization,
+                    headroom_bps,
+                    clients: status.clients,
+                    client_cap,
+                    accepting: status.health == "ok"
+                        && client_cap.is_none_or(|cap| status.clients < cap),
+                }
+            })
+            .collect();
+        let preferred = wans
+            .iter()
+            .filter(|hint| hint.accepting)
+            .max_by(|a, b| a.headroom_bps.total_cmp(&b.headroom_bps));
+        let snapshot = HintSnapshot {
+            updated_at: timestamp,
+            preferred_wan: preferred.map(|hint| hint.wan.clone()),
+            preferred_nic: preferred.map(|hint| hint.nic.clone()),
+            wans,
+        };
+
+        if let Some(path) = &self.config.path {
+            // Written beside the target and renamed over it, so a hook never reads half a file
+            let partial = path.with_extension("partial");
+            std::fs::write(&partial, snapshot.render(self.config.format))
+                .and_then(|()| std::fs::rename(&partial, path))
+                .with_context(|| format!("Failed to write capacity hints to {}", path.display()))?;
+        }
+        *self.latest.lock().unwrap() = Some(snapshot);
+        Ok(())
+    }
+
+    /// The latest hints in the configured format, with its content type.
+    pub fn rendered(&self) -> Option<(&'static str, String)> {
+        let latest = self.latest.lock().unwrap();
+        let snapshot = latest.as_ref()?;
+        let content_type = match self.config.format {
+            HintFormat::Json => "application/json",
+            HintFormat::Env => "text/plain; charset=utf-8",
+        };
+        Some((content_type, snapshot.render(self.config.format)))
+    }
+}
+
+impl HintSnapshot {
+    fn render(&self, format: HintFormat) -> String {
+        match format {
+            HintFormat::Json => {
+                let mut out = serde_json::to_string_pretty(self).unwrap_or_default();
+                out.push('\n');
+                out
+            }
+            HintFormat::Env => self.render_env(),
+        }
+    }
+
+    /// `ROUTINGFLOW_PREFERRED_WAN=wan1`, then `ROUTINGFLOW_WAN0_HEADROOM_BPS=...` and so on
+    /// per WAN. Values never contain spaces or quotes, so the file can be sourced as is.
+    fn render_env(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "ROUTINGFLOW_UPDATED_AT={}", self.updated_at);
+        let _ = writeln!(
+            out,
+            "ROUTINGFLOW_PREFERRED_WAN={}",
+            self.preferred_wan
+                .as_ref()
+                .map(ToString::to_string)
+                .unwrap_or_default()
+        );
+        let _ = writeln!(
+            out,
+            "ROUTINGFLOW_PREFERRED_NIC={}",
+            self.preferred_nic
+                .as_ref()
+                .map(ToString::to_string)
+                .unwrap_or_default()
+        );
+        let names: Vec<String> = self.wans.iter().map(|hint| env_name(&hint.wan)).collect();
+        let _ = writeln!(out, "ROUTINGFLOW_WANS={}", names.join(","));
+        for (hint, name) in self.wans.iter().zip(&names) {
+            let prefix = format!("ROUTINGFLOW_{}", name);
+            let _ = writeln!(out, "{}_NIC={}", prefix, hint.nic);
+            let _ = writeln!(out, "{}_HEALTH={}", prefix, hint.health);
+            let _ = writeln!(
+                out,
+                "{}_UTILIZATION={}",
+                prefix,
+                hint.utilization
+                    .map(|utilization| format!("{:.3}", utilization))
+                    .unwrap_or_default()
+            );
+            let _ = writeln!(out, "{}_HEADROOM_BPS={:.0}", prefix, hint.headroom_bps);
+            let _ = writeln!(out, "{}_CLIENTS={}", prefix, hint.clients);
+            let _ = writeln!(
+                out,
+                "{}_ACCEPTING={}",
+                prefix,
+                if hint.accepting { 1 } else { 0 }
+            );
+        }
+        out
+    }
+}
+
+/// A WAN id as a variable name part: `wan-lte` becomes `WAN_LTE`.
+fn env_name(wan: &WanId) -> String {
+    wan.to_string()
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,36 @@
+use crate::model::{ClientIp, WanId};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SwitchRecord {
+    pub ip: ClientIp,
+    pub target_wan: WanId,
+    pub timestamp: u64,
+}
+
+/// Recent switches, kept in memory for cooldown checks and reporting.
+#[derive(Debug, Default)]
+pub struct SwitchHistory {
+    records: Vec<SwitchRecord>,
+}
+
+impl SwitchHistory {
+    pub fn record(&mut self, record: SwitchRecord) {
+        self.records.push(record);
+    }
+
+    pub fn records(&self) -> &[SwitchRecord] {
+        &self.records
+    }
+
+    /// The most recent switch of `ip`, if it is still retained.
+    pub fn last_switch(&self, ip: ClientIp) -> Option<&SwitchRecord> {
+        self.records.iter().rev().find(|record| record.ip == ip)
+    }
+
+    /// Drops records older than `retention_secs`.
+    pub fn prune(&mut self, now: u64, retention_secs: u64) {
+        self.records
+            .retain(|record| now.saturating_sub(record.timestamp) <= retention_secs);
+    }
+}
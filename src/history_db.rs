@@ -0,0 +1,750 @@
+use crate::cli::HistoryArgs;
+use crate::config::Config;
+use crate::model::{ClientIp, IpTraffic, NicName, NicStats, WanId};
+use crate::store::{self, StateStore};
+use anyhow::{bail, Context, Result};
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Mutex;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS switch_history (
+    id        INTEGER PRIMARY KEY AUTOINCREMENT,
+    timestamp INTEGER NOT NULL,
+    ip        TEXT NOT NULL,
+    from_wan  TEXT,
+    to_wan    TEXT NOT NULL,
+    reason    TEXT NOT NULL,
+    result    TEXT NOT NULL,
+    error     TEXT,
+    verification TEXT
+);
+CREATE INDEX IF NOT EXISTS switch_history_ip_timestamp ON switch_history (ip, timestamp);
+CREATE INDEX IF NOT EXISTS switch_history_timestamp ON switch_history (timestamp);
+CREATE TABLE IF NOT EXISTS public_ip_history (
+    id        INTEGER PRIMARY KEY AUTOINCREMENT,
+    timestamp INTEGER NOT NULL,
+    wan       TEXT NOT NULL,
+    previous  TEXT,
+    current   TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS public_ip_history_timestamp ON public_ip_history (timestamp);
+CREATE TABLE IF NOT EXISTS nic_stats_history (
+    timestamp INTEGER NOT NULL,
+    interval_secs INTEGER NOT NULL,
+    nic       TEXT NOT NULL,
+    wan       TEXT,
+    tcp_bandwidth_bps REAL NOT NULL,
+    tx_bps    REAL NOT NULL,
+    rx_bps    REAL NOT NULL,
+    clients   INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS nic_stats_history_timestamp ON nic_stats_history (timestamp);
+CREATE TABLE IF NOT EXISTS client_traffic_history (
+    timestamp INTEGER NOT NULL,
+    interval_secs INTEGER NOT NULL,
+    ip        TEXT NOT NULL,
+    rx_bps    REAL NOT NULL,
+    tx_bps    REAL NOT NULL
+);
+CREATE INDEX IF NOT EXISTS client_traffic_history_timestamp ON client_traffic_history (timestamp);
+CREATE TABLE IF NOT EXISTS cooldown_residencies (
+    ip        TEXT NOT NULL,
+    class     TEXT NOT NULL,
+    until     INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS quota_usage (
+    quota     TEXT NOT NULL,
+    ip        TEXT,
+    first_seen INTEGER NOT NULL,
+    samples   TEXT NOT NULL,
+    over      INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS traffic_profiles (
+    ip        TEXT NOT NULL,
+    first_seen INTEGER NOT NULL,
+    samples   TEXT NOT NULL,
+    anomalous INTEGER NOT NULL
+);
+";
+
+/// A switch attempt as persisted in the history database.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredSwitch {
+    pub timestamp: u64,
+    pub ip: String,
+    pub from_wan: Option<String>,
+    pub to_wan: String,
+    pub reason: String,
+    /// `success` or `failed`.
+    pub result: String,
+    pub error: Option<String>,
+    /// `verified`, `not_in_effect` or `unverified` when the switch was checked against the
+    /// routing service's status afterwards.
+    #[serde(default)]
+    pub verification: Option<String>,
+}
+
+/// A change of a WAN's public address, kept next to the switches it may explain.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredPublicIpChange {
+    pub timestamp: u64,
+    pub wan: String,
+    pub previous: Option<String>,
+    pub current: String,
+}
+
+/// A NIC's readings averaged over one interval, for exports.
+#[derive(Debug, Clone, Serialize)]
+pub struct StoredNicStats {
+    /// Start of the interval.
+    pub timestamp: u64,
+    pub interval_secs: u64,
+    pub nic: String,
+    pub wan: Option<String>,
+    pub tcp_bandwidth_bps: f64,
+    pub tx_bps: f64,
+    pub rx_bps: f64,
+    /// Clients mapped to the NIC's WAN at the end of the interval.
+    pub clients: usize,
+}
+
+/// A client's traffic averaged over one interval, for the top talkers of `report`.
+#[derive(Debug, Clone, Serialize)]
+pub struct StoredClientTraffic {
+    /// Start of the interval.
+    pub timestamp: u64,
+    pub interval_secs: u64,
+    pub ip: String,
+    pub rx_bps: f64,
+    pub tx_bps: f64,
+}
+
+/// A sticky traffic class hold, which unlike the switch cooldowns cannot be derived from
+/// the switch history.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoredResidency {
+    pub ip: String,
+    pub class: String,
+    pub until: u64,
+}
+
+/// A client's or group's recent traffic against a quota.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoredQuotaUsage {
+    pub quota: String,
+    /// `None` for a group quota.
+    pub ip: Option<String>,
+    pub first_seen: u64,
+    /// `(timestamp, traffic_bps)`, oldest first.
+    pub samples: Vec<(u64, f64)>,
+    pub over: bool,
+}
+
+/// A client's traffic baseline as the anomaly detector learned it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoredProfile {
+    pub ip: String,
+    pub first_seen: u64,
+    /// `(timestamp, traffic_bps)`, oldest first.
+    pub samples: Vec<(u64, f64)>,
+    pub anomalous: bool,
+}
+
+/// What [`NicStatsIntervals`] stores at the end of an interval.
+#[derive(Debug, Default)]
+pub struct IntervalStats {
+    pub nics: Vec<StoredNicStats>,
+    pub clients: Vec<StoredClientTraffic>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct HistoryQuery {
+    pub ip: Option<String>,
+    /// Only records with `timestamp >= since`.
+    pub since: Option<u64>,
+    pub limit: Option<usize>,
+}
+
+/// SQLite-backed switch history that survives restarts.
+pub struct HistoryDb {
+    /// Taken out by `close`.
+    conn: Mutex<Option<Connection>>,
+}
+
+impl HistoryDb {
+    pub fn open(path: &Path) -> Result<Self> {
+        let conn = Connection::open(path)
+            .with_context(|| format!("Failed to open history database {}", path.display()))?;
+        conn.execute_batch(SCHEMA)
+            .context("Failed to initialize history database schema")?;
+        // Databases created before switches were verified lack the column
+        let has_verification = conn
+            .prepare(
+                "SELECT 1 FROM pragma_table_info('switch_history') WHERE name = 'verification'",
+            )?
+            .exists([])?;
+        if !has_verification {
+            conn.execute_batch("ALTER TABLE switch_history ADD COLUMN verification TEXT")
+                .context("Failed to migrate history database schema")?;
+        }
+
+        Ok(Self {
+            conn: Mutex::new(Some(conn)),
+        })
+    }
+
+    fn with_conn<T>(&self, f: impl FnOnce(&Connection) -> Result<T>) -> Result<T> {
+        match self.conn.lock().unwrap().as_ref() {
+            Some(conn) => f(conn),
+            None => bail!("The history database is closed"),
+        }
+    }
+}
+
+impl StateStore for HistoryDb {
+    fn insert_switch(&self, record: &StoredSwitch) -> Result<i64> {
+        self.with_conn(|conn| {
+            conn.execute(
+                "INSERT INTO switch_history
+                     (timestamp, ip, from_wan, to_wan, reason, result, error, verification)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                params![
+                    record.timestamp as i64,
+                    record.ip,
+                    record.from_wan,
+                    record.to_wan,
+                    record.reason,
+                    record.result,
+                    record.error,
+                    record.verification,
+                ],
+            )
+            .context("Failed to insert switch history record")?;
+
+            Ok(conn.last_insert_rowid())
+        })
+    }
+
+    fn set_verification(&self, id: i64, verification: &str) -> Result<()> {
+        self.with_conn(|conn| {
+            conn.execute(
+                "UPDATE switch_history SET verification = ?1 WHERE id = ?2",
+                params![verification, id],
+            )
+            .context("Failed to update switch history record")?;
+
+            Ok(())
+        })
+    }
+
+    fn query_switches(&self, query: &HistoryQuery) -> Result<Vec<StoredSwitch>> {
+        self.with_conn(|conn| {
+            let mut statement = conn.prepare(
+                "SELECT timestamp, ip, from_wan, to_wan, reason, result, error, verification
+                 FROM switch_history
+                 WHERE (?1 IS NULL OR ip = ?1) AND (?2 IS NULL OR timestamp >= ?2)
+                 ORDER BY timestamp DESC, id DESC
+                 LIMIT ?3",
+            )?;
+
+            let limit = query.limit.map(|limit| limit as i64).unwrap_or(-1);
+            let rows = statement.query_map(
+                params![query.ip, query.since.map(|since| since as i64), limit],
+                |row| {
+                    Ok(StoredSwitch {
+                        timestamp: row.get::<_, i64>(0)? as u64,
+                        ip: row.get(1)?,
+                        from_wan: row.get(2)?,
+                        to_wan: row.get(3)?,
+                        reason: row.get(4)?,
+                        result: row.get(5)?,
+                        error: row.get(6)?,
+                        verification: row.get(7)?,
+                    })
+                },
+            )?;
+
+            rows.collect::<rusqlite::Result<Vec<_>>>()
+                .context("Failed to read switch history")
+        })
+    }
+
+    fn insert_public_ip_change(&self, change: &StoredPublicIpChange) -> Result<()> {
+        self.with_conn(|conn| {
+            conn.execute(
+                "INSERT INTO public_ip_history (timestamp, wan, previous, current)
+                 VALUES (?1, ?2, ?3, ?4)",
+                params![
+                    change.timestamp as i64,
+                    change.wan,
+                    change.previous,
+                    change.current,
+                ],
+            )
+            .context("Failed to insert public IP change")?;
+
+            Ok(())
+        })
+    }
+
+    fn latest_public_ips(&self) -> Result<HashMap<String, String>> {
+        self.with_conn(|conn| {
+            let mut statement = conn.prepare(
+                "SELECT wan, current FROM public_ip_history
+                 WHERE id IN (SELECT MAX(id) FROM public_ip_history GROUP BY wan)",
+            )?;
+            let rows = statement.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+            rows.collect::<rusqlite::Result<HashMap<_, _>>>()
+                .context("Failed to read public IP history")
+        })
+    }
+
+    fn query_public_ip_changes(&self, query: &HistoryQuery) -> Result<Vec<StoredPublicIpChange>> {
+        self.with_conn(|conn| {
+            let mut statement = conn.prepare(
+                "SELECT timestamp, wan, previous, current
+                 FROM public_ip_history
+                 WHERE ?1 IS NULL OR timestamp >= ?1
+                 ORDER BY timestamp DESC, id DESC
+                 LIMIT ?2",
+            )?;
+
+            let limit = query.limit.map(|limit| limit as i64).unwrap_or(-1);
+            let rows = statement.query_map(
+                params![query.since.map(|since| since as i64), limit],
+                |row| {
+                    Ok(StoredPublicIpChange {
+                        timestamp: row.get::<_, i64>(0)? as u64,
+                        wan: row.get(1)?,
+                        previous: row.get(2)?,
+                        current: row.get(3)?,
+                    })
+                },
+            )?;
+
+            rows.collect::<rusqlite::Result<Vec<_>>>()
+                .context("Failed to read public IP history")
+        })
+    }
+
+    fn insert_nic_stats(&self, rows: &[StoredNicStats], retain_since: u64) -> Result<()> {
+        self.with_conn(|conn| {
+            let transaction = conn.unchecked_transaction()?;
+            for row in rows {
+                transaction
+                    .execute(
+                        "INSERT INTO nic_stats_history
+                             (timestamp, interval_secs, nic, wan, tcp_bandwidth_bps, tx_bps, rx_bps, clients)
+                         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                        params![
+                            row.timestamp as i64,
+                            row.interval_secs as i64,
+                            row.nic,
+                            row.wan,
+                            row.tcp_bandwidth_bps,
+                            row.tx_bps,
+                            row.rx_bps,
+                            row.clients as i64,
+                        ],
+                    )
+                    .context("Failed to insert NIC stats")?;
+            }
+            transaction.execute(
+                "DELETE FROM nic_stats_history WHERE timestamp < ?1",
+                params![retain_since as i64],
+            )?;
+            transaction.commit().context("Failed to store NIC stats")
+        })
+    }
+
+    fn query_nic_stats(&self, since: Option<u64>) -> Result<Vec<StoredNicStats>> {
+        self.with_conn(|conn| {
+            let mut statement = conn.prepare(
+                "SELECT timestamp, interval_secs, nic, wan, tcp_bandwidth_bps, tx_bps, rx_bps, clients
+                 FROM nic_stats_history
+                 WHERE ?1 IS NULL OR timestamp >= ?1
+                 ORDER BY timestamp, nic",
+            )?;
+            let rows = statement.query_map(params![since.map(|since| since as i64)], |row| {
+                Ok(StoredNicStats {
+                    timestamp: row.get::<_, i64>(0)? as u64,
+                    interval_secs: row.get::<_, i64>(1)? as u64,
+                    nic: row.get(2)?,
+                    wan: row.get(3)?,
+                    tcp_bandwidth_bps: row.get(4)?,
+                    tx_bps: row.get(5)?,
+                    rx_bps: row.get(6)?,
+                    clients: row.get::<_, i64>(7)? as usize,
+                })
+            })?;
+
+            rows.collect::<rusqlite::Result<Vec<_>>>()
+                .context("Failed to read NIC stats history")
+        })
+    }
+
+    fn insert_client_traffic(&self, rows: &[StoredClientTraffic], retain_since: u64) -> Result<()> {
+        self.with_conn(|conn| {
+            let transaction = conn.unchecked_transaction()?;
+            for row in rows {
+                transaction
+                    .execute(
+                        "INSERT INTO client_traffic_history (timestamp, interval_secs, ip, rx_bps, tx_bps)
+                         VALUES (?1, ?2, ?3, ?4, ?5)",
+                        params![
+                            row.timestamp as i64,
+                            row.interval_secs as i64,
+                            row.ip,
+                            row.rx_bps,
+                            row.tx_bps,
+                        ],
+                    )
+                    .context("Failed to insert client traffic")?;
+            }
+            transaction.execute(
+                "DELETE FROM client_traffic_history WHERE timestamp < ?1",
+                params![retain_since as i64],
+            )?;
+            transaction
+                .commit()
+                .context("Failed to store client traffic")
+        })
+    }
+
+    fn query_client_traffic(&self, since: Option<u64>) -> Result<Vec<StoredClientTraffic>> {
+        self.with_conn(|conn| {
+            let mut statement = conn.prepare(
+                "SELECT timestamp, interval_secs, ip, rx_bps, tx_bps
+                 FROM client_traffic_history
+                 WHERE ?1 IS NULL OR timestamp >= ?1
+                 ORDER BY timestamp, ip",
+            )?;
+            let rows = statement.query_map(params![since.map(|since| since as i64)], |row| {
+                Ok(StoredClientTraffic {
+                    timestamp: row.get::<_, i64>(0)? as u64,
+                    interval_secs: row.get::<_, i64>(1)? as u64,
+                    ip: row.get(2)?,
+                    rx_bps: row.get(3)?,
+                    tx_bps: row.get(4)?,
+                })
+            })?;
+
+            rows.collect::<rusqlite::Result<Vec<_>>>()
+                .context("Failed to read client traffic history")
+        })
+    }
+
+    fn save_residencies(&self, rows: &[StoredResidency]) -> Result<()> {
+        self.with_conn(|conn| {
+            let transaction = conn.unchecked_transaction()?;
+            transaction.execute("DELETE FROM cooldown_residencies", [])?;
+            for row in rows {
+                transaction
+                    .execute(
+                        "INSERT INTO cooldown_residencies (ip, class, until) VALUES (?1, ?2, ?3)",
+                        params![row.ip, row.class, row.until as i64],
+                    )
+                    .context("Failed to insert cooldown residency")?;
+            }
+            transaction
+                .commit()
+                .context("Failed to store cooldown residencies")
+        })
+    }
+
+    fn load_residencies(&self) -> Result<Vec<StoredResidency>> {
+        self.with_conn(|conn| {
+            let mut statement =
+                conn.prepare("SELECT ip, class, until FROM cooldown_residencies")?;
+            let rows = statement.query_map([], |row| {
+                Ok(StoredResidency {
+                    ip: row.get(0)?,
+                    class: row.get(1)?,
+                    until: row.get::<_, i64>(2)? as u64,
+                })
+            })?;
+
+            rows.collect::<rusqlite::Result<Vec<_>>>()
+                .context("Failed to read cooldown residencies")
+        })
+    }
+
+    fn save_quota_usage(&self, rows: &[StoredQuotaUsage]) -> Result<()> {
+        self.with_conn(|conn| {
+            let transaction = conn.unchecked_transaction()?;
+            transaction.execute("DELETE FROM quota_usage", [])?;
+            for row in rows {
+                transaction
+                    .execute(
+                        "INSERT INTO quota_usage (quota, ip, first_seen, samples, over)
+                         VALUES (?1, ?2, ?3, ?4, ?5)",
+                        params![
+                            row.quota,
+                            row.ip,
+                            row.first_seen as i64,
+                            serde_json::to_string(&row.samples)?,
+                            row.over,
+                        ],
+                    )
+                    .context("Failed to insert quota usage")?;
+            }
+            transaction.commit().context("Failed to store quota usage")
+        })
+    }
+
+    fn load_quota_usage(&self) -> Result<Vec<StoredQuotaUsage>> {
+        self.with_conn(|conn| {
+            let mut statement =
+                conn.prepare("SELECT quota, ip, first_seen, samples, over FROM quota_usage")?;
+            let rows = statement.query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, Option<String>>(1)?,
+                    row.get::<_, i64>(2)?,
+                    row.get::<_, String>(3)?,
+                    row.get::<_, bool>(4)?,
+                ))
+            })?;
+
+            let mut usage = Vec::new();
+            for row in rows {
+                let (quota, ip, first_seen, samples, over) =
+                    row.context("Failed to read quota usage")?;
+                usage.push(StoredQuotaUsage {
+                    quota,
+                    ip,
+                    first_seen: first_seen as u64,
+                    samples: serde_json::from_str(&samples)
+                        .context("Unreadable quota usage samples")?,
+                    over,
+                });
+            }
+            Ok(usage)
+        })
+    }
+
+    fn save_profiles(&self, rows: &[StoredProfile]) -> Result<()> {
+        self.with_conn(|conn| {
+            let transaction = conn.unchecked_transaction()?;
+            transaction.execute("DELETE FROM traffic_profiles", [])?;
+            for row in rows {
+                transaction
+                    .execute(
+                        "INSERT INTO traffic_profiles (ip, first_seen, samples, anomalous)
+                         VALUES (?1, ?2, ?3, ?4)",
+                        params![
+                            row.ip,
+                            row.first_seen as i64,
+                            serde_json::to_string(&row.samples)?,
+                            row.anomalous,
+                        ],
+                    )
+                    .context("Failed to insert traffic profile")?;
+            }
+            transaction
+                .commit()
+                .context("Failed to store traffic profiles")
+        })
+    }
+
+    fn load_profiles(&self) -> Result<Vec<StoredProfile>> {
+        self.with_conn(|conn| {
+            let mut statement =
+                conn.prepare("SELECT ip, first_seen, samples, anomalous FROM traffic_profiles")?;
+            let rows = statement.query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, i64>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, bool>(3)?,
+                ))
+            })?;
+
+            let mut profiles = Vec::new();
+            for row in rows {
+                let (ip, first_seen, samples, anomalous) =
+                    row.context("Failed to read traffic profiles")?;
+                profiles.push(StoredProfile {
+                    ip,
+                    first_seen: first_seen as u64,
+                    samples: serde_json::from_str(&samples)
+                        .context("Unreadable traffic profile samples")?,
+                    anomalous,
+                });
+            }
+            Ok(profiles)
+        })
+    }
+
+    /// Closes the database, reporting what SQLite could not finish writing.
+    fn close(&self) -> Result<()> {
+        let Some(conn) = self.conn.lock().unwrap().take() else {
+            return Ok(());
+        };
+        conn.close()
+            .map_err(|(_, e)| e)
+            .context("Failed to close history database")
+    }
+}
+
+/// Averages each NIC's readings and each client's traffic over fixed intervals, so the
+/// history keeps one row per NIC or client and interval however short the scan interval is.
+pub struct NicStatsIntervals {
+    interval_secs: u64,
+    started_at: Option<u64>,
+    cycles: u32,
+    sums: HashMap<NicName, NicStats>,
+    /// Client → summed `(rx_bps, tx_bps)`; cycles a client was idle in add nothing.
+    client_sums: HashMap<ClientIp, (f64, f64)>,
+}
+
+impl NicStatsIntervals {
+    pub fn new(interval_secs: u64) -> Self {
+        Self {
+            interval_secs,
+            started_at: None,
+            cycles: 0,
+            sums: HashMap::new(),
+            client_sums: HashMap::new(),
+        }
+    }
+
+    /// Adds one cycle's readings; returns the averages of the interval this cycle closed.
+    pub fn add(
+        &mut self,
+        now: u64,
+        nic_stats: &HashMap<NicName, NicStats>,
+        ip_traffic: &[IpTraffic],
+        wan_to_nic: &HashMap<WanId, NicName>,
+        clients_per_wan: &HashMap<WanId, usize>,
+    ) -> Option<IntervalStats> {
+        let started_at = *self.started_at.get_or_insert(now);
+        for (nic, stats) in nic_stats {
+            let sum = self.sums.entry(nic.clone()).or_default();
+            sum.tcp_bandwidth += stats.tcp_bandwidth;
+            sum.tx_bps += stats.tx_bps;
+            sum.rx_bps += stats.rx_bps;
+        }
+        for traffic in ip_traffic {
+            let sum = self.client_sums.entry(traffic.ip).or_default();
+            sum.0 += traffic.rx_bps;
+            sum.1 += traffic.tx_bps;
+        }
+        self.cycles += 1;
+        if now.saturating_sub(started_at) < self.interval_secs {
+            return None;
+        }
+
+        let cycles = f64::from(self.cycles);
+        let mut rows: Vec<StoredNicStats> = self
+            .sums
+            .drain()
+            .map(|(nic, sum)| {
+                let wan = wan_to_nic
+                    .iter()
+                    .find(|(_, wan_nic)| **wan_nic == nic)
+                    .map(|(wan, _)| wan);
+                StoredNicStats {
+                    timestamp: started_at,
+                    interval_secs: now - started_at,
+                    nic: nic.to_string(),
+                    wan: wan.map(ToString::to_string),
+                    tcp_bandwidth_bps: sum.tcp_bandwidth / cycles,
+                    tx_bps: sum.tx_bps / cycles,
+                    rx_bps: sum.rx_bps / cycles,
+                    clients: wan
+                        .and_then(|wan| clients_per_wan.get(wan))
+                        .copied()
+                        .unwrap_or(0),
+                }
+            })
+            .collect();
+        rows.sort_by(|a, b| a.nic.cmp(&b.nic));
+        let mut clients: Vec<StoredClientTraffic> = self
+            .client_sums
+            .drain()
+            .map(|(ip, (rx_bps, tx_bps))| StoredClientTraffic {
+                timestamp: started_at,
+                interval_secs: now - started_at,
+                ip: ip.to_string(),
+                rx_bps: rx_bps / cycles,
+                tx_bps: tx_bps / cycles,
+            })
+            .collect();
+        clients.sort_by(|a, b| a.ip.cmp(&b.ip));
+        self.started_at = Some(now);
+        self.cycles = 0;
+        Some(IntervalStats {
+            nics: rows,
+            clients,
+        })
+    }
+}
+
+/// Implements the `history` subcommand, with ages counted from `now`.
+pub fn print_history(config: &Config, args: &HistoryArgs, now: u64) -> Result<()> {
+    let Some(db) = store::open_database(&config.history)? else {
+        bail!(
+            "No switch history database at {}",
+            config.history.db_path.display()
+        );
+    };
+
+    let query = HistoryQuery {
+        ip: args.ip.map(|ip| ip.to_string()),
+        since: args.since.map(|since| now.saturating_sub(since)),
+        limit: Some(args.limit),
+    };
+    let records = db.query_switches(&query)?;
+    // Shown between the switches, since an address change often explains what followed
+    let changes = db.query_public_ip_changes(&query)?;
+
+    if records.is_empty() && changes.is_empty() {
+        println!("(No switch history recorded)");
+        return Ok(());
+    }
+
+    let mut changes = changes.into_iter().peekable();
+    for record in records {
+        while let Some(change) = changes.next_if(|change| change.timestamp >= record.timestamp) {
+            print_public_ip_change(&change, now);
+        }
+        let age = now.saturating_sub(record.timestamp);
+        let result = match &record.verification {
+            Some(verification) => format!("{}, {}", record.result, verification),
+            None => record.result.clone(),
+        };
+        println!(
+            "{} - {} {} → {} [{}] {}s ago: {}",
+            record.timestamp,
+            record.ip,
+            record.from_wan.as_deref().unwrap_or("?"),
+            record.to_wan,
+            result,
+            age,
+            record.reason
+        );
+        if let Some(error) = record.error {
+            println!("    error: {}", error);
+        }
+    }
+    for change in changes {
+        print_public_ip_change(&change, now);
+    }
+
+    Ok(())
+}
+
+fn print_public_ip_change(change: &StoredPublicIpChange, now: u64) {
+    println!(
+        "{} - {} public IP {} → {} {}s ago",
+        change.timestamp,
+        change.wan,
+        change.previous.as_deref().unwrap_or("?"),
+        change.current,
+        now.saturating_sub(change.timestamp)
+    );
+}
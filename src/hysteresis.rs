@@ -0,0 +1,101 @@
+use crate::config::HysteresisConfig;
+use crate::model::{ClientIp, WanId};
+use crate::policy::{PolicyInput, PolicyPlan, SkippedCandidate};
+use std::collections::HashMap;
+
+/// Holds back switch decisions until the target WAN's headroom advantage over the
+/// current WAN is large enough and has persisted for several consecutive scans.
+pub struct Hysteresis {
+    config: HysteresisConfig,
+    /// Consecutive qualifying scans per (ip, target_wan).
+    streaks: HashMap<(ClientIp, WanId), u32>,
+}
+
+impl Hysteresis {
+    pub fn new(config: HysteresisConfig) -> Self {
+        Self {
+            config,
+            streaks: HashMap::new(),
+        }
+    }
+
+    pub fn filter(&mut self, plan: PolicyPlan, input: &PolicyInput) -> PolicyPlan {
+        let mut filtered = PolicyPlan {
+            switches: Vec::new(),
+            skipped: plan.skipped,
+        };
+        let mut streaks = HashMap::new();
+
+        for decision in plan.switches {
+            let current = input.nic_stats.get(&decision.from_nic);
+            let target = input
+                .wan_to_nic
+                .get(&decision.target_wan)
+                .and_then(|nic| input.nic_stats.get(nic));
+
+            let (Some(current), Some(target)) = (current, target) else {
+                filtered.switches.push(decision);
+                continue;
+            };
+
+            let current_headroom = current.headroom();
+            // Headroom other groups have reserved on the target is not on offer
+            let target_headroom = target.headroom()
+                - input
+                    .reservations
+                    .withheld_bps(&decision.target_wan, decision.ip);
+            let delta = target_headroom - current_headroom;
+
+            if !self.exceeds_threshold(delta, current_headroom) {
+                filtered.skipped.push(SkippedCandidate {
+                    ip: decision.ip,
+                    nic: decision.from_nic,
+                    reason: format!(
+                        "headroom gain on {} ({:.2} Mbps) below hysteresis threshold",
+                        decision.target_wan,
+                        delta / 1_000_000.0
+                    ),
+                });
+                continue;
+            }
+
+            let key = (decision.ip, decision.target_wan.clone());
+            let streak = self.streaks.get(&key).copied().unwrap_or(0) + 1;
+
+            if streak < self.config.consecutive_scans {
+                streaks.insert(key, streak);
+                filtered.skipped.push(SkippedCandidate {
+                    ip: decision.ip,
+                    nic: decision.from_nic,
+                    reason: format!(
+                        "headroom gain on {} held for {}/{} scans",
+                        decision.target_wan, streak, self.config.consecutive_scans
+                    ),
+                });
+                continue;
+            }
+
+            filtered.switches.push(decision);
+        }
+
+        // Streaks that were not extended this scan are broken
+        self.streaks = streaks;
+        filtered
+    }
+
+    fn exceeds_threshold(&self, delta: f64, current_headroom: f64) -> bool {
+        let by_percent = self
+            .config
+            .min_delta_percent
+            .map(|percent| delta >= current_headroom.abs() * percent / 100.0);
+        let by_mbps = self
+            .config
+            .min_delta_mbps
+            .map(|mbps| delta >= mbps * 1_000_000.0);
+
+        match (by_percent, by_mbps) {
+            (None, None) => delta > 0.0,
+            (percent, mbps) => percent.unwrap_or(false) || mbps.unwrap_or(false),
+        }
+    }
+}
@@ -0,0 +1,356 @@
+use crate::config::{InfluxDbConfig, QueryWindowConfig, RetryConfig, WindowFunction};
+use crate::error::{ConfigError, MetricsError};
+use crate::metric_source::{MetricFuture, MetricSource};
+use crate::prometheus::PrometheusResult;
+use crate::retry;
+use reqwest::header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE};
+use reqwest::Client;
+use std::collections::HashMap;
+
+/// The exporters' measurements in InfluxDB 2, read with Flux over `/api/v2/query`.
+pub struct InfluxDbSource {
+    client: Client,
+    query_url: String,
+    token: Option<String>,
+    config: InfluxDbConfig,
+    query_window: Option<QueryWindowConfig>,
+    retry: RetryConfig,
+}
+
+/// One row of a Flux result, by column name.
+type Row = HashMap<String, String>;
+
+impl InfluxDbSource {
+    pub fn new(
+        config: &InfluxDbConfig,
+        query_window: Option<QueryWindowConfig>,
+        retry: RetryConfig,
+    ) -> Result<Self, ConfigError> {
+        if config.org.is_empty() || config.bucket.is_empty() {
+            return Err(ConfigError::Invalid(
+                "[influxdb] needs an org and a bucket".to_string(),
+            ));
+        }
+        let token = match (&config.token, &config.token_file) {
+            (Some(_), Some(_)) => {
+                return Err(ConfigError::Invalid(
+                    "Set either influxdb.token or influxdb.token_file, not both".to_string(),
+                ))
+            }
+            (Some(token), None) => Some(token.clone()),
+            (None, Some(path)) => Some(
+                std::fs::read_to_string(path)
+                    .map_err(|e| {
+                        ConfigError::Invalid(format!(
+                            "Failed to read InfluxDB token file {}: {}",
+                            path.display(),
+                            e
+                        ))
+                    })?
+                    .trim()
+                    .to_string(),
+            ),
+            (None, None) => None,
+        };
+        let client = Client::builder().build().map_err(|e| {
+            ConfigError::Invalid(format!("Failed to set up InfluxDB client: {}", e))
+        })?;
+
+        Ok(Self {
+            client,
+            query_url: format!(
+                "{}/api/v2/query?org={}",
+                config.url.trim_end_matches('/'),
+                urlencoding::encode(&config.org)
+            ),
+            token,
+            config: config.clone(),
+            query_window,
+            retry,
+        })
+    }
+
+    /// `from |> range |> filter` of `measurement` and `fields`, grouped into one table per
+    /// `tags` (and field).
+    fn select(
+        &self,
+        measurement: &str,
+        fields: &[&str],
+        tags: &[&str],
+        range: (u64, u64),
+    ) -> String {
+        let fields = fields
+            .iter()
+            .map(|field| format!("r._field == {}", flux_string(field)))
+            .collect::<Vec<_>>()
+            .join(" or ");
+        let columns = tags
+            .iter()
+            .map(|tag| flux_string(tag))
+            .chain(std::iter::once(flux_string("_field")))
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!(
+            "from(bucket: {})\n  |> range(start: {}, stop: {})\n  |> filter(fn: (r) => r._measurement == {} and ({}))\n  |> group(columns: [{}])",
+            flux_string(&self.config.bucket),
+            range.0,
+            // The stop is exclusive
+            range.1 + 1,
+            flux_string(measurement),
+            fields,
+            columns
+        )
+    }
+
+    /// The latest point of every series, or their window reduced as configured.
+    async fn reduced(
+        &self,
+        measurement: &str,
+        fields: &[&str],
+        tag: &str,
+        now: u64,
+    ) -> Result<Vec<Row>, MetricsError> {
+        let (query, window) = match &self.query_window {
+            None => (
+                format!(
+                    "{}\n  |> last()",
+                    self.select(
+                        measurement,
+                        fields,
+                        &[tag],
+                        (now.saturating_sub(self.config.lookback_secs), now)
+                    )
+                ),
+                false,
+            ),
+            Some(window) => {
+                let select = self.select(
+                    measurement,
+                    fields,
+                    &[tag],
+                    (now.saturating_sub(window.window_secs), now),
+                );
+                let reduce = match window.function {
+                    WindowFunction::AvgOverTime => "mean()",
+                    WindowFunction::MaxOverTime => "max()",
+                    WindowFunction::Rate => "derivative(unit: 1s, nonNegative: true)\n  |> mean()",
+                };
+                (format!("{}\n  |> {}", select, reduce), true)
+            }
+        };
+        let mut rows = self.query(&query).await?;
+        // Aggregates keep no point time; like a Prometheus window, they stand for now
+        if window {
+            for row in &mut rows {
+                row.entry("_time".to_string())
+                    .or_insert_with(|| now.to_string());
+            }
+        }
+        Ok(rows)
+    }
+
+    async fn query(&self, flux: &str) -> Result<Vec<Row>, MetricsError> {
+        retry::with_backoff(&self.retry, "InfluxDB query", || self.query_once(flux)).await
+    }
+
+    async fn query_once(&self, flux: &str) -> Result<Vec<Row>, MetricsError> {
+        let request = self
+            .client
+            .post(&self.query_url)
+            .header(CONTENT_TYPE, "application/vnd.flux")
+            .header(ACCEPT, "application/csv")
+            .body(flux.to_string());
+        let request = match &self.token {
+            Some(token) => request.header(AUTHORIZATION, format!("Token {}", token)),
+            None => request,
+        };
+        let response = request
+            .send()
+            .await
+            .map_err(MetricsError::InfluxDbUnreachable)?;
+        let status = response.status();
+        if status.is_client_error() && status != reqwest::StatusCode::TOO_MANY_REQUESTS {
+            let body = response.text().await.unwrap_or_default();
+            let message = serde_json::from_str::<serde_json::Value>(&body)
+                .ok()
+                .and_then(|body| body.get("message")?.as_str().map(str::to_string))
+                .unwrap_or(body);
+            return Err(MetricsError::InfluxDbRejected { status, message });
+        }
+        let body = response
+            .error_for_status()
+            .map_err(MetricsError::InfluxDbUnreachable)?
+            .text()
+            .await
+            .map_err(MetricsError::InfluxDbUnreachable)?;
+        parse_csv(&body)
+    }
+}
+
+impl MetricSource for InfluxDbSource {
+    fn name(&self) -> &'static str {
+        "influxdb"
+    }
+
+    fn tcp_bandwidth(&self, now: u64) -> MetricFuture<'_> {
+        Box::pin(async move {
+            let config = &self.config;
+            let rows = self
+                .reduced(
+                    &config.bandwidth_measurement,
+                    &[&config.bandwidth_field],
+                    &config.interface_tag,
+                    now,
+                )
+                .await?;
+            Ok(rows
+                .iter()
+                .filter_map(|row| {
+                    let interface = row.get(&config.interface_tag)?;
+                    sample(row, [("interface", interface.as_str())], row.get("_value")?)
+                })
+                .collect())
+        })
+    }
+
+    fn network_by_ip(&self, now: u64) -> MetricFuture<'_> {
+        Box::pin(async move {
+            let config = &self.config;
+            let rows = self
+                .reduced(
+                    &config.traffic_measurement,
+                    &[&config.rx_field, &config.tx_field],
+                    &config.ip_tag,
+                    now,
+                )
+                .await?;
+            Ok(rows
+                .iter()
+                .filter_map(|row| {
+                    let name = match row.get("_field")? {
+                        field if *field == config.rx_field => "network_ip_rx_bps",
+                        field if *field == config.tx_field => "network_ip_tx_bps",
+                        _ => return None,
+                    };
+                    let ip = row.get(&config.ip_tag)?;
+                    sample(
+                        row,
+                        [("__name__", name), ("ip_address", ip.as_str())],
+                        row.get("_value")?,
+                    )
+                })
+                .collect())
+        })
+    }
+
+    fn bandwidth_sample_times(&self, now: u64) -> MetricFuture<'_> {
+        Box::pin(async move {
+            let config = &self.config;
+            let query = format!(
+                "{}\n  |> last()",
+                self.select(
+                    &config.bandwidth_measurement,
+                    &[&config.bandwidth_field],
+                    &[&config.interface_tag],
+                    (now.saturating_sub(config.lookback_secs), now),
+                )
+            );
+            let rows = self.query(&query).await?;
+            Ok(rows
+                .iter()
+                .filter_map(|row| {
+                    let interface = row.get(&config.interface_tag)?;
+                    let at = point_time(row)?;
+                    sample(row, [("interface", interface.as_str())], &at.to_string())
+                })
+                .collect())
+        })
+    }
+}
+
+/// A row as a Prometheus-style sample with `labels`, taken at the row's `_time`.
+fn sample<const N: usize>(
+    row: &Row,
+    labels: [(&str, &str); N],
+    value: &str,
+) -> Option<PrometheusResult> {
+    Some(PrometheusResult {
+        metric: labels
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect(),
+        value: (point_time(row)?, value.to_string()),
+    })
+}
+
+/// `_time` as Unix seconds; RFC 3339 from InfluxDB, or already seconds for aggregates.
+fn point_time(row: &Row) -> Option<f64> {
+    let time = row.get("_time")?;
+    if let Ok(secs) = time.parse::<f64>() {
+        return Some(secs);
+    }
+    let time = chrono::DateTime::parse_from_rfc3339(time).ok()?;
+    Some(time.timestamp() as f64 + f64::from(time.timestamp_subsec_millis()) / 1000.0)
+}
+
+/// A Flux string literal.
+fn flux_string(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// The rows of a Flux result in InfluxDB's CSV dialect: tables separated by blank lines,
+/// each with a header row of its own.
+fn parse_csv(body: &str) -> Result<Vec<Row>, MetricsError> {
+    let mut rows = Vec::new();
+    let mut header: Option<Vec<String>> = None;
+    for line in body.lines() {
+        let line = line.trim_end_matches('\r');
+        if line.is_empty() {
+            header = None;
+            continue;
+        }
+        if line.starts_with('#') {
+            continue;
+        }
+        let fields = split_csv_line(line);
+        let Some(columns) = &header else {
+            header = Some(fields);
+            continue;
+        };
+        if fields.len() != columns.len() {
+            return Err(MetricsError::InfluxDbMalformed(format!(
+                "row with {} fields under a header of {}",
+                fields.len(),
+                columns.len()
+            )));
+        }
+        let row: Row = columns.iter().cloned().zip(fields).collect();
+        // Errors after the response has started come as a table of their own
+        if let Some(error) = row.get("error").filter(|error| !error.is_empty()) {
+            return Err(MetricsError::InfluxDbMalformed(error.clone()));
+        }
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+/// Splits a CSV line, honouring double-quoted fields with `""` escapes.
+fn split_csv_line(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
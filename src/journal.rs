@@ -0,0 +1,282 @@
+use crate::config::JournalConfig;
+use crate::model::{ClientIp, WanId};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+/// One line of the journal.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum JournalRecord {
+    /// Written (and synced) before the switch API is called.
+    Intent(SwitchIntent),
+    /// The switch API accepted the switch and it was recorded in the history.
+    Applied { id: u64 },
+    /// The routing service's mappings show the switch; the action is complete.
+    Verified { id: u64 },
+    /// The switch failed, was never sent, or did not take effect.
+    Failed { id: u64, error: String },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SwitchIntent {
+    pub id: u64,
+    pub timestamp: u64,
+    pub ip: ClientIp,
+    pub from_wan: Option<WanId>,
+    pub target_wan: WanId,
+    pub reason: String,
+    /// The flows (`port/proto`) moved instead of the client, whose mapping stays as it was.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub flows: Vec<String>,
+}
+
+#[derive(Debug)]
+struct Pending {
+    intent: SwitchIntent,
+    applied: bool,
+    /// Left over from a previous run.
+    recovered: bool,
+}
+
+/// Write-ahead log of switch actions. Every switch is journaled before it is issued and
+/// completed once the routing service's mappings confirm it, so after a crash the actions
+/// that were in flight can be checked against the mappings instead of being repeated or
+/// forgotten.
+pub struct Journal {
+    path: PathBuf,
+    file: File,
+    next_id: u64,
+    pending: BTreeMap<u64, Pending>,
+}
+
+impl Journal {
+    /// Opens the journal at the configured path, picking up the actions a previous run
+    /// left unfinished.
+    pub fn open(config: &JournalConfig) -> Result<Self> {
+        let path = config.path.clone();
+        let mut pending = BTreeMap::new();
+        let mut next_id = 1;
+        if path.exists() {
+            let file = File::open(&path)
+                .with_context(|| format!("Failed to open journal {}", path.display()))?;
+            for (number, line) in BufReader::new(file).lines().enumerate() {
+                let line =
+                    line.with_context(|| format!("Failed to read journal {}", path.display()))?;
+                let record = match serde_json::from_str(&line) {
+                    Ok(record) => record,
+                    Err(e) => {
+                        // A crash mid-write leaves a torn last line
+                        warn!(
+                            line = number + 1,
+                            "Ignoring unreadable journal entry: {}", e
+                        );
+                        continue;
+                    }
+                };
+                match record {
+                    JournalRecord::Intent(intent) => {
+                        next_id = next_id.max(intent.id + 1);
+                        pending.insert(
+                            intent.id,
+                            Pending {
+                                intent,
+                                applied: false,
+                                recovered: true,
+                            },
+                        );
+                    }
+                    JournalRecord::Applied { id } => {
+                        if let Some(pending) = pending.get_mut(&id) {
+                            pending.applied = true;
+                        }
+                    }
+                    JournalRecord::Verified { id } | JournalRecord::Failed { id, .. } => {
+                        pending.remove(&id);
+                    }
+                }
+            }
+        }
+        if !pending.is_empty() {
+            info!(
+                actions = pending.len(),
+                "Journal has switches that were in flight at the last shutdown"
+            );
+        }
+
+        let mut journal = Self {
+            file: Self::open_append(&path)?,
+            path,
+            next_id,
+            pending,
+        };
+        // Rewrite it with just the unfinished actions, which also drops a torn last line
+        journal.truncate()?;
+        let records: Vec<JournalRecord> = journal
+            .pending
+            .iter()
+            .flat_map(|(&id, pending)| {
+                let applied = pending.applied.then_some(JournalRecord::Applied { id });
+                std::iter::once(JournalRecord::Intent(pending.intent.clone())).chain(applied)
+            })
+            .collect();
+        for record in &records {
+            journal.write(record)?;
+        }
+        journal.file.sync_data().context("Failed to sync journal")?;
+        Ok(journal)
+    }
+
+    fn open_append(path: &Path) -> Result<File> {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open journal {}", path.display()))
+    }
+
+    /// Journals a switch that is about to be issued and returns its id.
+    pub fn begin(
+        &mut self,
+        timestamp: u64,
+        ip: ClientIp,
+        from_wan: Option<WanId>,
+        target_wan: WanId,
+        reason: String,
+        flows: Vec<String>,
+    ) -> Result<u64> {
+        let intent = SwitchIntent {
+            id: self.next_id,
+            timestamp,
+            ip,
+            from_wan,
+            target_wan,
+            reason,
+            flows,
+        };
+        self.write(&JournalRecord::Intent(intent.clone()))?;
+        // The intent must be durable before the switch is issued
+        self.file.sync_data().context("Failed to sync journal")?;
+        self.next_id += 1;
+        self.pending.insert(
+            intent.id,
+            Pending {
+                intent,
+                applied: false,
+                recovered: false,
+            },
+        );
+        Ok(self.next_id - 1)
+    }
+
+    pub fn applied(&mut self, id: u64) -> Result<()> {
+        if let Some(pending) = self.pending.get_mut(&id) {
+            pending.applied = true;
+        }
+        self.write(&JournalRecord::Applied { id })
+    }
+
+    pub fn failed(&mut self, id: u64, error: String) -> Result<()> {
+        self.pending.remove(&id);
+        self.write(&JournalRecord::Failed { id, error })
+    }
+
+    /// Makes sure everything journaled so far is on disk.
+    pub fn sync(&mut self) -> Result<()> {
+        self.file.sync_data().context("Failed to sync journal")
+    }
+
+    /// Checks the pending actions against the routing service's current mappings and
+    /// completes them. Returns the switches from a previous run that did take effect
+    /// but were never recorded, so the caller can record them now. An action stays pending
+    /// until its outcome is on disk, so a failed write leaves it for the next call.
+    pub fn reconcile(&mut self, mappings: &HashMap<ClientIp, WanId>) -> Result<Vec<SwitchIntent>> {
+        let mut unrecorded = Vec::new();
+        let mut outcomes = Vec::new();
+        for (&id, pending) in &self.pending {
+            let intent = &pending.intent;
+            // The mappings do not show moved flows; the service accepting them has to do
+            let in_effect = if intent.flows.is_empty() {
+                mappings.get(&intent.ip) == Some(&intent.target_wan)
+            } else {
+                pending.applied
+            };
+            let outcome = match (in_effect, pending.applied, pending.recovered) {
+                (true, applied, recovered) => {
+                    if recovered {
+                        info!(
+                            ip = %intent.ip,
+                            target_wan = %intent.target_wan,
+                            "Switch in flight at the last shutdown took effect"
+                        );
+                        if !applied {
+                            unrecorded.push(intent.clone());
+                        }
+                    }
+                    JournalRecord::Verified { id }
+                }
+                (false, true, _) => {
+                    warn!(
+                        ip = %intent.ip,
+                        target_wan = %intent.target_wan,
+                        "Switch was accepted but is not in the routing service's mappings"
+                    );
+                    JournalRecord::Failed {
+                        id,
+                        error: "not reflected in the mappings".to_string(),
+                    }
+                }
+                (false, false, _) => {
+                    // Never sent, or lost on the way: leave the client to the policy
+                    info!(
+                        ip = %intent.ip,
+                        target_wan = %intent.target_wan,
+                        "Switch in flight at the last shutdown did not take effect"
+                    );
+                    JournalRecord::Failed {
+                        id,
+                        error: "interrupted before it took effect".to_string(),
+                    }
+                }
+            };
+            outcomes.push((id, outcome));
+        }
+        if !outcomes.is_empty() {
+            for (_, outcome) in &outcomes {
+                self.write(outcome)?;
+            }
+            self.sync()?;
+            for (id, _) in outcomes {
+                self.pending.remove(&id);
+            }
+        }
+        self.compact()?;
+        Ok(unrecorded)
+    }
+
+    /// Truncates the journal once nothing is in flight, so it does not grow forever.
+    fn compact(&mut self) -> Result<()> {
+        if !self.pending.is_empty() {
+            return Ok(());
+        }
+        self.truncate()
+    }
+
+    fn truncate(&mut self) -> Result<()> {
+        self.file
+            .set_len(0)
+            .with_context(|| format!("Failed to truncate journal {}", self.path.display()))
+    }
+
+    fn write(&mut self, record: &JournalRecord) -> Result<()> {
+        let mut line = serde_json::to_string(record)?;
+        line.push('\n');
+        self.file
+            .write_all(line.as_bytes())
+            .with_context(|| format!("Failed to write journal {}", self.path.display()))
+    }
+}
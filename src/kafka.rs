@@ -0,0 +1,56 @@
+use crate::config::KafkaSinkConfig;
+use crate::events::Event;
+use anyhow::{bail, Context, Result};
+use reqwest::Client;
+use serde_json::json;
+use std::sync::Arc;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::{error, warn};
+
+const KAFKA_JSON_CONTENT_TYPE: &str = "application/vnd.kafka.json.v2+json";
+
+/// Publishes events to a Kafka topic through a Kafka REST Proxy (v2 API).
+/// Events are keyed by their kind so consumers can partition on it.
+pub async fn run(config: KafkaSinkConfig, mut events: broadcast::Receiver<Arc<Event>>) {
+    let client = Client::new();
+
+    loop {
+        let event = match events.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(skipped)) => {
+                warn!("Kafka sink dropped {} events", skipped);
+                continue;
+            }
+            Err(RecvError::Closed) => return,
+        };
+
+        if let Err(e) = publish(&client, &config, &event).await {
+            error!("Kafka sink error: {:#}", e);
+        }
+    }
+}
+
+async fn publish(client: &Client, config: &KafkaSinkConfig, event: &Event) -> Result<()> {
+    let url = format!(
+        "{}/topics/{}",
+        config.rest_proxy_url.trim_end_matches('/'),
+        config.topic
+    );
+    let body = json!({
+        "records": [{ "key": event.kind(), "value": event }],
+    });
+
+    let response = client
+        .post(&url)
+        .header(reqwest::header::CONTENT_TYPE, KAFKA_JSON_CONTENT_TYPE)
+        .json(&body)
+        .send()
+        .await
+        .with_context(|| format!("Failed to reach Kafka REST proxy at {}", url))?;
+
+    if !response.status().is_success() {
+        bail!("Kafka REST proxy returned {}", response.status());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,109 @@
+use crate::config::LinkQualityConfig;
+use crate::error::ConfigError;
+use crate::model::{NicName, WanId};
+use crate::probe::WanProbeStats;
+use crate::prometheus::PrometheusResult;
+use serde::Serialize;
+use std::collections::HashMap;
+
+/// Packet loss and jitter of one WAN.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct LinkQuality {
+    /// Share of packets lost (0–1).
+    pub loss: Option<f64>,
+    pub jitter_ms: Option<f64>,
+}
+
+impl LinkQuality {
+    /// Share of a WAN's headroom that counts when ranking targets: 1 up to the reference
+    /// loss and jitter, and scaled down by how far each goes beyond it.
+    pub fn factor(&self, config: &LinkQualityConfig) -> f64 {
+        let loss = self
+            .loss
+            .filter(|loss| *loss > config.loss_reference)
+            .map_or(1.0, |loss| config.loss_reference / loss);
+        let jitter = self
+            .jitter_ms
+            .filter(|jitter_ms| *jitter_ms > config.jitter_reference_ms)
+            .map_or(1.0, |jitter_ms| config.jitter_reference_ms / jitter_ms);
+        loss * jitter
+    }
+}
+
+/// Reads every WAN's loss and jitter from Prometheus (ping_exporter and the like), falling
+/// back on the probes for what the queries do not cover, so a lossy but fast WAN is not
+/// always preferred over a clean, slower one.
+pub struct LinkQualityReader {
+    config: LinkQualityConfig,
+}
+
+impl LinkQualityReader {
+    pub fn new(config: LinkQualityConfig) -> Result<Self, ConfigError> {
+        if config.loss_reference <= 0.0 || config.jitter_reference_ms <= 0.0 {
+            return Err(ConfigError::Invalid(
+                "[link_quality] needs a positive loss_reference and jitter_reference_ms"
+                    .to_string(),
+            ));
+        }
+        Ok(Self { config })
+    }
+
+    pub fn loss_query(&self) -> Option<&str> {
+        self.config.loss_query.as_deref()
+    }
+
+    pub fn jitter_query(&self) -> Option<&str> {
+        self.config.jitter_query.as_deref()
+    }
+
+    /// Loss and jitter per WAN; a query that failed or has no series for a WAN leaves it
+    /// to the probes.
+    pub fn read(
+        &self,
+        loss_results: Option<&[PrometheusResult]>,
+        jitter_results: Option<&[PrometheusResult]>,
+        wan_to_nic: &HashMap<WanId, NicName>,
+        wan_probes: &HashMap<WanId, WanProbeStats>,
+    ) -> HashMap<WanId, LinkQuality> {
+        let losses = self.per_nic(loss_results.unwrap_or_default(), 1.0);
+        let jitters = self.per_nic(jitter_results.unwrap_or_default(), 1000.0);
+        wan_to_nic
+            .iter()
+            .filter_map(|(wan, nic)| {
+                let probe = wan_probes.get(wan);
+                let quality = LinkQuality {
+                    loss: losses.get(nic).copied().or_else(|| {
+                        probe
+                            .filter(|probe| probe.rtt_ms.is_some())
+                            .map(|probe| probe.loss)
+                    }),
+                    jitter_ms: jitters
+                        .get(nic)
+                        .copied()
+                        .or_else(|| probe.and_then(|probe| probe.jitter_ms)),
+                };
+                (quality.loss.is_some() || quality.jitter_ms.is_some())
+                    .then(|| (wan.clone(), quality))
+            })
+            .collect()
+    }
+
+    /// The worst reading of each interface, times `scale`.
+    fn per_nic(&self, results: &[PrometheusResult], scale: f64) -> HashMap<NicName, f64> {
+        let mut readings: HashMap<NicName, f64> = HashMap::new();
+        for result in results {
+            let (Some(nic), Ok(value)) = (
+                result.label::<NicName>(&self.config.interface_label),
+                result.value.1.parse::<f64>(),
+            ) else {
+                continue;
+            };
+            if !value.is_finite() {
+                continue;
+            }
+            let reading = readings.entry(nic).or_insert(value * scale);
+            *reading = reading.max(value * scale);
+        }
+        readings
+    }
+}
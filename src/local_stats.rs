@@ -0,0 +1,121 @@
+use crate::config::LocalStatsConfig;
+use crate::model::NicName;
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Byte counters of one interface as the kernel reports them.
+#[derive(Debug, Clone, Copy)]
+struct Counters {
+    rx_bytes: u64,
+    tx_bytes: u64,
+}
+
+/// What the kernel says about one interface between two reads.
+#[derive(Debug, Clone, Copy)]
+pub struct InterfaceRates {
+    pub rx_bps: f64,
+    pub tx_bps: f64,
+    /// The link is not reported down.
+    pub up: bool,
+}
+
+/// Per-interface rates from `/proc/net/dev`, standing in for the exporters' NIC totals while
+/// the metric source is unreachable. Client traffic cannot be told apart this way, so only
+/// the NICs' figures and their health are kept going.
+pub struct LocalStats {
+    proc_net_dev: PathBuf,
+    sys_class_net: PathBuf,
+    previous: (SystemTime, HashMap<NicName, Counters>),
+}
+
+impl LocalStats {
+    /// Takes the first reading, so the counters are known to be there and the first scan
+    /// already has rates.
+    pub fn new(config: &LocalStatsConfig, now: SystemTime) -> Result<Self> {
+        let counters = read_counters(&config.proc_net_dev)?;
+        Ok(Self {
+            proc_net_dev: config.proc_net_dev.clone(),
+            sys_class_net: config.sys_class_net.clone(),
+            previous: (now, counters),
+        })
+    }
+
+    /// Reads the counters and returns the rates since the previous read. Counters that went
+    /// backwards (a reset or a re-created interface) start over.
+    pub fn sample(&mut self, now: SystemTime) -> Result<HashMap<NicName, InterfaceRates>> {
+        let counters = read_counters(&self.proc_net_dev)?;
+        let (read_at, previous) = &self.previous;
+        let elapsed = now
+            .duration_since(*read_at)
+            .unwrap_or_default()
+            .as_secs_f64();
+
+        let mut rates = HashMap::new();
+        for (nic, current) in &counters {
+            let Some(before) = previous.get(nic) else {
+                continue;
+            };
+            let (Some(rx_bytes), Some(tx_bytes)) = (
+                current.rx_bytes.checked_sub(before.rx_bytes),
+                current.tx_bytes.checked_sub(before.tx_bytes),
+            ) else {
+                continue;
+            };
+            let per_sec = |bytes: u64| {
+                if elapsed > 0.0 {
+                    bytes as f64 * 8.0 / elapsed
+                } else {
+                    0.0
+                }
+            };
+            rates.insert(
+                nic.clone(),
+                InterfaceRates {
+                    rx_bps: per_sec(rx_bytes),
+                    tx_bps: per_sec(tx_bytes),
+                    up: is_up(&self.sys_class_net, nic),
+                },
+            );
+        }
+        self.previous = (now, counters);
+        Ok(rates)
+    }
+}
+
+fn read_counters(path: &Path) -> Result<HashMap<NicName, Counters>> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    Ok(parse_proc_net_dev(&text))
+}
+
+/// `operstate` other than `down`; `unknown`, as tunnels and PPP links report, counts as up.
+fn is_up(sys_class_net: &Path, nic: &NicName) -> bool {
+    std::fs::read_to_string(sys_class_net.join(nic.as_str()).join("operstate"))
+        .map(|state| state.trim() != "down")
+        .unwrap_or(true)
+}
+
+/// The received and transmitted bytes of every interface in `/proc/net/dev`, whose first two
+/// lines are headers and whose rows are `name: rx_bytes packets ... tx_bytes ...`.
+fn parse_proc_net_dev(text: &str) -> HashMap<NicName, Counters> {
+    text.lines()
+        .skip(2)
+        .filter_map(|line| {
+            let (name, fields) = line.split_once(':')?;
+            let fields: Vec<u64> = fields
+                .split_whitespace()
+                .map(str::parse)
+                .collect::<Result<_, _>>()
+                .ok()?;
+            Some((
+                name.trim().parse().ok()?,
+                Counters {
+                    rx_bytes: *fields.first()?,
+                    tx_bytes: *fields.get(8)?,
+                },
+            ))
+        })
+        .collect()
+}
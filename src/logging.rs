@@ -0,0 +1,38 @@
+use crate::config::{LogFormat, LoggingConfig};
+use crate::diag::DiagRecorder;
+use anyhow::{Context, Result};
+use std::sync::Arc;
+use tracing_subscriber::fmt::writer::MakeWriterExt;
+use tracing_subscriber::EnvFilter;
+
+/// Installs the global tracing subscriber. Logs go to stderr so that stdout only
+/// carries the monitor report; `recorder` keeps a copy of the recent lines.
+pub fn init(config: &LoggingConfig, recorder: &Arc<DiagRecorder>) -> Result<()> {
+    let filter = match std::env::var("RUST_LOG") {
+        Ok(directives) if !directives.is_empty() => EnvFilter::try_new(&directives)
+            .with_context(|| format!("Invalid RUST_LOG filter: {}", directives))?,
+        _ => {
+            let directives = std::iter::once(config.level.clone())
+                .chain(
+                    config
+                        .filters
+                        .iter()
+                        .map(|(module, level)| format!("{}={}", module, level)),
+                )
+                .collect::<Vec<_>>()
+                .join(",");
+            EnvFilter::try_new(&directives)
+                .with_context(|| format!("Invalid logging filter: {}", directives))?
+        }
+    };
+
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr.and(recorder.log_tap()));
+    match config.format {
+        LogFormat::Text => builder.init(),
+        LogFormat::Json => builder.json().flatten_event(true).init(),
+    }
+
+    Ok(())
+}
@@ -0,0 +1,60 @@
+use crate::config::MaintenanceWindowConfig;
+use chrono::{DateTime, TimeZone};
+use serde::Serialize;
+use tracing::{info, warn};
+
+/// A maintenance window that is open, for `GET /state`.
+#[derive(Debug, Clone, Serialize)]
+pub struct MaintenanceStatus {
+    pub name: String,
+    /// The window, as `HH:MM-HH:MM`.
+    pub window: String,
+    /// Unix time the window opened (or the daemon started inside it).
+    pub since: u64,
+}
+
+/// Recurring windows in which the balancer keeps collecting and reporting but issues no
+/// switches of its own, so WAN work does not set off failovers and rebalancing.
+pub struct MaintenanceWindows {
+    windows: Vec<MaintenanceWindowConfig>,
+    open: Option<MaintenanceStatus>,
+}
+
+impl MaintenanceWindows {
+    pub fn new(windows: &[MaintenanceWindowConfig]) -> Self {
+        Self {
+            windows: windows.to_vec(),
+            open: None,
+        }
+    }
+
+    /// The window open at `at`, the first one listed when several are; logs openings and
+    /// closings.
+    pub fn update<Tz: TimeZone>(&mut self, at: &DateTime<Tz>) -> Option<&MaintenanceStatus> {
+        let window = self
+            .windows
+            .iter()
+            .find(|window| window.schedule.contains(at));
+        match (window, &self.open) {
+            (Some(window), Some(open)) if window.name == open.name => {}
+            (Some(window), _) => {
+                warn!(
+                    window = %window.name,
+                    schedule = %window.schedule,
+                    "Maintenance window opened; switching is frozen"
+                );
+                self.open = Some(MaintenanceStatus {
+                    name: window.name.clone(),
+                    window: window.schedule.to_string(),
+                    since: at.timestamp() as u64,
+                });
+            }
+            (None, Some(open)) => {
+                info!(window = %open.name, "Maintenance window closed; switching resumes");
+                self.open = None;
+            }
+            (None, None) => {}
+        }
+        self.open.as_ref()
+    }
+}
@@ -0,0 +1,285 @@
+use crate::config::{Config, MetricSchemaConfig, QueryWindowConfig, RetryConfig};
+use crate::error::{ConfigError, MetricsError};
+use crate::influxdb::InfluxDbSource;
+use crate::model::NicName;
+use crate::prometheus::{PrometheusClient, PrometheusResult};
+use crate::retry;
+use futures_util::future::try_join_all;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Mutex;
+
+pub type MetricFuture<'a> =
+    Pin<Box<dyn Future<Output = Result<Vec<PrometheusResult>, MetricsError>> + Send + 'a>>;
+
+/// Where the bandwidth and traffic figures come from. Whatever the backend, they come back
+/// shaped and labelled like the Prometheus series of the exporters, so the balancing reads
+/// them all the same way.
+pub trait MetricSource: Send + Sync {
+    /// For logs and the scrape error counter.
+    fn name(&self) -> &'static str;
+
+    /// TCP bandwidth estimate per interface (`interface` label), evaluated over the query
+    /// window when there is one.
+    fn tcp_bandwidth(&self, now: u64) -> MetricFuture<'_>;
+
+    /// Traffic per client: `network_ip_rx_bps` and `network_ip_tx_bps` series (`__name__`)
+    /// with an `ip_address` label.
+    fn network_by_ip(&self, now: u64) -> MetricFuture<'_>;
+
+    /// Unix time of each interface's latest bandwidth estimate as the value, for spotting
+    /// scanners that stopped reporting.
+    fn bandwidth_sample_times(&self, now: u64) -> MetricFuture<'_>;
+
+    /// The WAN interfaces the routing service reported last, for queries run per interface.
+    fn set_interfaces(&self, _nics: &[NicName]) {}
+}
+
+/// The source `config` selects: InfluxDB with `[influxdb]`, Prometheus otherwise.
+pub fn from_config(config: &Config) -> Result<Box<dyn MetricSource>, ConfigError> {
+    let Some(influxdb) = &config.influxdb else {
+        return Ok(Box::new(PrometheusSource::new(config)?));
+    };
+    // These run PromQL of their own
+    let promql_only = [
+        (
+            config
+                .probes
+                .as_ref()
+                .is_some_and(|probes| probes.pause_query.is_some()),
+            "probes.pause_query",
+        ),
+        (config.passive_rtt.is_some(), "[passive_rtt]"),
+        (
+            config.link_quality.as_ref().is_some_and(|link_quality| {
+                link_quality.loss_query.is_some() || link_quality.jitter_query.is_some()
+            }),
+            "link_quality.loss_query / jitter_query",
+        ),
+        (config.data_caps.is_some(), "[data_caps]"),
+        (config.flows.is_some(), "[flows]"),
+        (config.app_classes.is_some(), "[app_classes]"),
+        (config.destinations.is_some(), "[destinations]"),
+    ];
+    if let Some((_, feature)) = promql_only.iter().find(|(enabled, _)| *enabled) {
+        return Err(ConfigError::Invalid(format!(
+            "{} needs Prometheus as the metric source; remove it or [influxdb]",
+            feature
+        )));
+    }
+    Ok(Box::new(InfluxDbSource::new(
+        influxdb,
+        config.query_window.clone(),
+        config.retry.clone(),
+    )?))
+}
+
+/// Selector of the bandwidth series `schema` names.
+pub fn bandwidth_query(schema: &MetricSchemaConfig) -> String {
+    selector(
+        &schema.bandwidth_job,
+        &[&schema.bandwidth_metric],
+        &schema.matchers,
+    )
+}
+
+/// Selector of the client traffic series `schema` names.
+pub fn traffic_query(schema: &MetricSchemaConfig) -> String {
+    selector(
+        &schema.traffic_job,
+        &[&schema.tx_metric, &schema.rx_metric],
+        &schema.matchers,
+    )
+}
+
+fn selector(job: &str, names: &[&str], matchers: &str) -> String {
+    let mut labels = Vec::new();
+    if !job.is_empty() {
+        labels.push(format!(r#"job="{}""#, job));
+    }
+    labels.push(format!(r#"__name__=~"{}""#, names.join("|")));
+    if !matchers.is_empty() {
+        labels.push(matchers.to_string());
+    }
+    format!("{{{}}}", labels.join(","))
+}
+
+/// Bandwidth series under `schema`'s names as they would be under the default ones.
+pub fn normalize_bandwidth(
+    schema: &MetricSchemaConfig,
+    mut results: Vec<PrometheusResult>,
+) -> Vec<PrometheusResult> {
+    for result in &mut results {
+        relabel(result, &schema.interface_label, "interface");
+    }
+    results
+}
+
+/// Client traffic series under `schema`'s names as they would be under the default ones.
+pub fn normalize_traffic(
+    schema: &MetricSchemaConfig,
+    mut results: Vec<PrometheusResult>,
+) -> Vec<PrometheusResult> {
+    for result in &mut results {
+        relabel(result, &schema.ip_label, "ip_address");
+        let name = match result.metric.get("__name__") {
+            Some(name) if *name == schema.rx_metric => "network_ip_rx_bps",
+            Some(name) if *name == schema.tx_metric => "network_ip_tx_bps",
+            _ => continue,
+        };
+        result
+            .metric
+            .insert("__name__".to_string(), name.to_string());
+    }
+    results
+}
+
+fn relabel(result: &mut PrometheusResult, from: &str, to: &str) {
+    if from == to {
+        return;
+    }
+    if let Some(value) = result.metric.remove(from) {
+        result.metric.insert(to.to_string(), value);
+    }
+}
+
+/// The exporters' series in Prometheus.
+pub struct PrometheusSource {
+    client: PrometheusClient,
+    schema: MetricSchemaConfig,
+    query_window: Option<QueryWindowConfig>,
+    /// `{window}` of the templates, as a PromQL duration.
+    window: String,
+    retry: RetryConfig,
+    nics: Mutex<Vec<NicName>>,
+}
+
+impl PrometheusSource {
+    pub fn new(config: &Config) -> Result<Self, ConfigError> {
+        let window_secs = config.query_window.clone().unwrap_or_default().window_secs;
+        Ok(Self {
+            client: PrometheusClient::new(&config.prometheus)?,
+            schema: config.metric_schema.clone(),
+            query_window: config.query_window.clone(),
+            window: format!("{}s", window_secs.max(1)),
+            retry: config.retry.clone(),
+            nics: Mutex::new(Vec::new()),
+        })
+    }
+
+    /// Runs a bandwidth or traffic query, over the configured window if there is one.
+    async fn traffic(&self, query: &str, now: u64) -> Result<Vec<PrometheusResult>, MetricsError> {
+        retry::with_backoff(&self.retry, "Prometheus query", || async {
+            match &self.query_window {
+                Some(window) => self.client.query_window(query, window, now).await,
+                None => self.client.query(query).await,
+            }
+        })
+        .await
+    }
+
+    async fn instant(&self, query: &str) -> Result<Vec<PrometheusResult>, MetricsError> {
+        retry::with_backoff(&self.retry, "Prometheus query", || self.client.query(query)).await
+    }
+
+    /// Runs `template` once, or once per WAN interface when it has `{nic}`. Nothing is
+    /// asked per interface before the routing service has named any.
+    async fn per_nic(&self, template: &str) -> Result<Vec<PrometheusResult>, MetricsError> {
+        let query = template.replace("{window}", &self.window);
+        if !query.contains("{nic}") {
+            return self.instant(&query).await;
+        }
+        let nics = self.nics.lock().unwrap().clone();
+        let queries: Vec<String> = nics
+            .iter()
+            .map(|nic| query.replace("{nic}", nic.as_str()))
+            .collect();
+        let answers = try_join_all(queries.iter().map(|query| self.instant(query))).await?;
+        let mut results = Vec::new();
+        for (nic, answer) in nics.iter().zip(answers) {
+            for mut result in answer {
+                result
+                    .metric
+                    .entry(self.schema.interface_label.clone())
+                    .or_insert_with(|| nic.to_string());
+                results.push(result);
+            }
+        }
+        Ok(results)
+    }
+
+    /// Runs `template` once, or once per direction when it has `{direction}`, naming the
+    /// answers after that direction's series.
+    async fn per_direction(&self, template: &str) -> Result<Vec<PrometheusResult>, MetricsError> {
+        let query = template.replace("{window}", &self.window);
+        if !query.contains("{direction}") {
+            return self.instant(&query).await;
+        }
+        let directions = [
+            ("rx", &self.schema.rx_metric),
+            ("tx", &self.schema.tx_metric),
+        ];
+        let queries: Vec<String> = directions
+            .iter()
+            .map(|(direction, _)| query.replace("{direction}", direction))
+            .collect();
+        let answers = try_join_all(queries.iter().map(|query| self.instant(query))).await?;
+        let mut results = Vec::new();
+        for ((_, name), answer) in directions.iter().zip(answers) {
+            for mut result in answer {
+                result
+                    .metric
+                    .insert("__name__".to_string(), (*name).clone());
+                results.push(result);
+            }
+        }
+        Ok(results)
+    }
+}
+
+impl MetricSource for PrometheusSource {
+    fn name(&self) -> &'static str {
+        "prometheus"
+    }
+
+    fn tcp_bandwidth(&self, now: u64) -> MetricFuture<'_> {
+        Box::pin(async move {
+            let results = match &self.schema.bandwidth_template {
+                Some(template) => self.per_nic(template).await?,
+                None => self.traffic(&bandwidth_query(&self.schema), now).await?,
+            };
+            Ok(normalize_bandwidth(&self.schema, results))
+        })
+    }
+
+    fn network_by_ip(&self, now: u64) -> MetricFuture<'_> {
+        Box::pin(async move {
+            let results = match &self.schema.traffic_template {
+                Some(template) => self.per_direction(template).await?,
+                None => self.traffic(&traffic_query(&self.schema), now).await?,
+            };
+            Ok(normalize_traffic(&self.schema, results))
+        })
+    }
+
+    fn bandwidth_sample_times(&self, _now: u64) -> MetricFuture<'_> {
+        Box::pin(async {
+            let template = match &self.schema.sample_time_template {
+                Some(template) => template.clone(),
+                None => format!(
+                    "timestamp({})",
+                    self.schema
+                        .bandwidth_template
+                        .clone()
+                        .unwrap_or_else(|| bandwidth_query(&self.schema))
+                ),
+            };
+            let results = self.per_nic(&template).await?;
+            Ok(normalize_bandwidth(&self.schema, results))
+        })
+    }
+
+    fn set_interfaces(&self, nics: &[NicName]) {
+        *self.nics.lock().unwrap() = nics.to_vec();
+    }
+}
@@ -0,0 +1,419 @@
+use crate::mode::OperatingMode;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Upper bounds (seconds) of the decision latency histogram buckets.
+const LATENCY_BUCKETS: [f64; 8] = [0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1];
+
+#[derive(Debug, Default)]
+struct Histogram {
+    /// Cumulative counts per bucket in `LATENCY_BUCKETS`.
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        for (bucket, bound) in self.buckets.iter_mut().zip(LATENCY_BUCKETS) {
+            if value <= bound {
+                *bucket += 1;
+            }
+        }
+        self.count += 1;
+        self.sum += value;
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct NicGauges {
+    pub tcp_bandwidth_bps: f64,
+    pub rx_bps: f64,
+    pub tx_bps: f64,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct DataCapGauges {
+    pub used_bytes: f64,
+    pub cap_bytes: f64,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    cycles: u64,
+    switches: BTreeMap<&'static str, u64>,
+    verifications: BTreeMap<&'static str, u64>,
+    skipped: BTreeMap<&'static str, u64>,
+    scrape_errors: BTreeMap<&'static str, u64>,
+    nics: BTreeMap<String, NicGauges>,
+    data_caps: BTreeMap<String, DataCapGauges>,
+    jain_index: Option<f64>,
+    decision_latency: Histogram,
+    cycle_duration_secs: f64,
+    circuit_state: u8,
+    circuit_failures: u32,
+    mode: Option<OperatingMode>,
+    mode_changes: u64,
+    stream_clients: u64,
+    stream_dropped: u64,
+    stream_disconnects: u64,
+}
+
+/// Internal counters and gauges of the balancer, rendered in the Prometheus text format.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    inner: Mutex<Inner>,
+}
+
+impl Metrics {
+    /// `result` is `success` or `failed`.
+    pub fn record_switch(&self, result: &'static str) {
+        *self.lock().switches.entry(result).or_insert(0) += 1;
+    }
+
+    /// `result` is `verified`, `not_in_effect` or `unverified`.
+    pub fn record_verification(&self, result: &'static str) {
+        *self.lock().verifications.entry(result).or_insert(0) += 1;
+    }
+
+    /// `reason` is a fixed label such as `cooldown` or `policy`.
+    pub fn record_skip(&self, reason: &'static str) {
+        *self.lock().skipped.entry(reason).or_insert(0) += 1;
+    }
+
+    /// `source` is `prometheus`, `status` or `qos`.
+    pub fn record_scrape_error(&self, source: &'static str) {
+        *self.lock().scrape_errors.entry(source).or_insert(0) += 1;
+    }
+
+    pub fn record_decision_latency(&self, latency: Duration) {
+        self.lock().decision_latency.observe(latency.as_secs_f64());
+    }
+
+    /// `state` is 0 (closed), 1 (half-open) or 2 (open).
+    pub fn record_circuit(&self, state: u8, consecutive_failures: u32) {
+        let mut inner = self.lock();
+        inner.circuit_state = state;
+        inner.circuit_failures = consecutive_failures;
+    }
+
+    pub fn record_mode(&self, mode: OperatingMode) {
+        self.lock().mode = Some(mode);
+    }
+
+    pub fn record_mode_change(&self) {
+        self.lock().mode_changes += 1;
+    }
+
+    /// An event stream client connected (`true`) or went away.
+    pub fn record_stream_client(&self, connected: bool) {
+        let mut inner = self.lock();
+        inner.stream_clients = if connected {
+            inner.stream_clients + 1
+        } else {
+            inner.stream_clients.saturating_sub(1)
+        };
+    }
+
+    pub fn record_stream_drops(&self, dropped: u64) {
+        self.lock().stream_dropped += dropped;
+    }
+
+    pub fn record_stream_disconnect(&self) {
+        self.lock().stream_disconnects += 1;
+    }
+
+    pub fn record_cycle(
+        &self,
+        nics: BTreeMap<String, NicGauges>,
+        jain_index: Option<f64>,
+        duration: Duration,
+    ) {
+        let mut inner = self.lock();
+        inner.cycles += 1;
+        inner.nics = nics;
+        inner.jain_index = jain_index;
+        inner.cycle_duration_secs = duration.as_secs_f64();
+    }
+
+    /// Usage of the metered WANs' caps, by WAN.
+    pub fn record_data_caps(&self, data_caps: BTreeMap<String, DataCapGauges>) {
+        self.lock().data_caps = data_caps;
+    }
+
+    pub fn render(&self) -> String {
+        let inner = self.lock();
+        let mut out = String::new();
+
+        header(
+            &mut out,
+            "routingflow_cycles_total",
+            "counter",
+            "Completed scan cycles.",
+        );
+        let _ = writeln!(out, "routingflow_cycles_total {}", inner.cycles);
+
+        header(
+            &mut out,
+            "routingflow_switches_total",
+            "counter",
+            "Switch attempts by result.",
+        );
+        for (result, count) in &inner.switches {
+            let _ = writeln!(
+                out,
+                "routingflow_switches_total{{result=\"{}\"}} {}",
+                result, count
+            );
+        }
+
+        header(
+            &mut out,
+            "routingflow_switch_verifications_total",
+            "counter",
+            "Switches checked against the routing service's status afterwards, by result.",
+        );
+        for (result, count) in &inner.verifications {
+            let _ = writeln!(
+                out,
+                "routingflow_switch_verifications_total{{result=\"{}\"}} {}",
+                result, count
+            );
+        }
+
+        header(
+            &mut out,
+            "routingflow_switches_skipped_total",
+            "counter",
+            "Switch candidates that were not switched, by reason.",
+        );
+        for (reason, count) in &inner.skipped {
+            let _ = writeln!(
+                out,
+                "routingflow_switches_skipped_total{{reason=\"{}\"}} {}",
+                reason, count
+            );
+        }
+
+        header(
+            &mut out,
+            "routingflow_scrape_errors_total",
+            "counter",
+            "Failed fetches from Prometheus or the routing service.",
+        );
+        for (source, count) in &inner.scrape_errors {
+            let _ = writeln!(
+                out,
+                "routingflow_scrape_errors_total{{source=\"{}\"}} {}",
+                source, count
+            );
+        }
+
+        for (name, help, value) in [
+            (
+                "routingflow_nic_tcp_bandwidth_bps",
+                "Observed TCP bandwidth estimate per NIC.",
+                (|g: &NicGauges| g.tcp_bandwidth_bps) as fn(&NicGauges) -> f64,
+            ),
+            (
+                "routingflow_nic_rx_bps",
+                "Observed RX traffic per NIC.",
+                |g| g.rx_bps,
+            ),
+            (
+                "routingflow_nic_tx_bps",
+                "Observed TX traffic per NIC.",
+                |g| g.tx_bps,
+            ),
+        ] {
+            header(&mut out, name, "gauge", help);
+            for (nic, gauges) in &inner.nics {
+                let _ = writeln!(out, "{}{{nic=\"{}\"}} {}", name, escape(nic), value(gauges));
+            }
+        }
+
+        if !inner.data_caps.is_empty() {
+            for (name, help, value) in [
+                (
+                    "routingflow_wan_data_used_bytes",
+                    "Bytes a metered WAN has moved this billing period.",
+                    (|g: &DataCapGauges| g.used_bytes) as fn(&DataCapGauges) -> f64,
+                ),
+                (
+                    "routingflow_wan_data_cap_bytes",
+                    "Data cap of a metered WAN per billing period.",
+                    |g| g.cap_bytes,
+                ),
+                (
+                    "routingflow_wan_data_remaining_bytes",
+                    "Bytes left of a metered WAN's data cap this billing period.",
+                    |g| (g.cap_bytes - g.used_bytes).max(0.0),
+                ),
+            ] {
+                header(&mut out, name, "gauge", help);
+                for (wan, gauges) in &inner.data_caps {
+                    let _ = writeln!(out, "{}{{wan=\"{}\"}} {}", name, escape(wan), value(gauges));
+                }
+            }
+        }
+
+        if let Some(jain_index) = inner.jain_index {
+            header(
+                &mut out,
+                "routingflow_fairness_jain_index",
+                "gauge",
+                "Jain's fairness index across WAN utilizations.",
+            );
+            let _ = writeln!(out, "routingflow_fairness_jain_index {}", jain_index);
+        }
+
+        header(
+            &mut out,
+            "routingflow_decision_latency_seconds",
+            "histogram",
+            "Time spent planning switches per cycle.",
+        );
+        let histogram = &inner.decision_latency;
+        for (bound, count) in LATENCY_BUCKETS.iter().zip(histogram.buckets) {
+            let _ = writeln!(
+                out,
+                "routingflow_decision_latency_seconds_bucket{{le=\"{}\"}} {}",
+                bound, count
+            );
+        }
+        let _ = writeln!(
+            out,
+            "routingflow_decision_latency_seconds_bucket{{le=\"+Inf\"}} {}",
+            histogram.count
+        );
+        let _ = writeln!(
+            out,
+            "routingflow_decision_latency_seconds_sum {}",
+            histogram.sum
+        );
+        let _ = writeln!(
+            out,
+            "routingflow_decision_latency_seconds_count {}",
+            histogram.count
+        );
+
+        header(
+            &mut out,
+            "routingflow_cycle_duration_seconds",
+            "gauge",
+            "Duration of the last completed scan cycle.",
+        );
+        let _ = writeln!(
+            out,
+            "routingflow_cycle_duration_seconds {}",
+            inner.cycle_duration_secs
+        );
+
+        header(
+            &mut out,
+            "routingflow_switch_circuit_state",
+            "gauge",
+            "Switch API circuit breaker: 0 closed, 1 half-open, 2 open.",
+        );
+        let _ = writeln!(
+            out,
+            "routingflow_switch_circuit_state {}",
+            inner.circuit_state
+        );
+        header(
+            &mut out,
+            "routingflow_switch_consecutive_failures",
+            "gauge",
+            "Switch calls that failed in a row.",
+        );
+        let _ = writeln!(
+            out,
+            "routingflow_switch_consecutive_failures {}",
+            inner.circuit_failures
+        );
+
+        if let Some(current) = inner.mode {
+            header(
+                &mut out,
+                "routingflow_operating_mode",
+                "gauge",
+                "1 for the mode the balancer is operating in, 0 for the others.",
+            );
+            for mode in OperatingMode::ALL {
+                let _ = writeln!(
+                    out,
+                    "routingflow_operating_mode{{mode=\"{}\"}} {}",
+                    mode,
+                    u8::from(mode == current)
+                );
+            }
+        }
+        header(
+            &mut out,
+            "routingflow_operating_mode_changes_total",
+            "counter",
+            "Transitions between operating modes.",
+        );
+        let _ = writeln!(
+            out,
+            "routingflow_operating_mode_changes_total {}",
+            inner.mode_changes
+        );
+
+        header(
+            &mut out,
+            "routingflow_event_stream_clients",
+            "gauge",
+            "Clients connected to the event stream.",
+        );
+        let _ = writeln!(
+            out,
+            "routingflow_event_stream_clients {}",
+            inner.stream_clients
+        );
+        header(
+            &mut out,
+            "routingflow_event_stream_dropped_total",
+            "counter",
+            "Events dropped because an event stream client fell behind.",
+        );
+        let _ = writeln!(
+            out,
+            "routingflow_event_stream_dropped_total {}",
+            inner.stream_dropped
+        );
+        header(
+            &mut out,
+            "routingflow_event_stream_disconnects_total",
+            "counter",
+            "Event stream clients disconnected for falling behind.",
+        );
+        let _ = writeln!(
+            out,
+            "routingflow_event_stream_disconnects_total {}",
+            inner.stream_disconnects
+        );
+
+        out
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Inner> {
+        self.inner
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn escape(label: &str) -> String {
+    label
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
@@ -0,0 +1,223 @@
+use crate::metrics::Metrics;
+use serde::Serialize;
+use std::fmt;
+use tracing::{info, warn};
+
+/// How much of its job the balancer can do with the dependencies it has this cycle.
+/// Each dependency's last good answer stands in for it for a while, so one failed query
+/// no longer costs the whole cycle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OperatingMode {
+    /// Everything answered; all switching.
+    Full,
+    /// Prometheus is behind, the traffic figures are the last good ones: only switches that
+    /// cannot wait (evacuations, manual pins and switches, rollbacks).
+    MetricsDegraded,
+    /// The routing service is not answering or its switch circuit is open, the mappings are
+    /// the last good ones: no switches, everything else carries on.
+    BackendDegraded,
+    /// Both: reports and metrics only.
+    ObserveOnly,
+}
+
+impl OperatingMode {
+    pub const ALL: [OperatingMode; 4] = [
+        OperatingMode::Full,
+        OperatingMode::MetricsDegraded,
+        OperatingMode::BackendDegraded,
+        OperatingMode::ObserveOnly,
+    ];
+
+    pub fn from_health(metrics_healthy: bool, backend_healthy: bool) -> Self {
+        match (metrics_healthy, backend_healthy) {
+            (true, true) => OperatingMode::Full,
+            (false, true) => OperatingMode::MetricsDegraded,
+            (true, false) => OperatingMode::BackendDegraded,
+            (false, false) => OperatingMode::ObserveOnly,
+        }
+    }
+
+    /// Whether a switch may be issued; `urgent` ones are those that cannot wait.
+    pub fn allows_switch(self, urgent: bool) -> bool {
+        match self {
+            OperatingMode::Full => true,
+            OperatingMode::MetricsDegraded => urgent,
+            OperatingMode::BackendDegraded | OperatingMode::ObserveOnly => false,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            OperatingMode::Full => "full",
+            OperatingMode::MetricsDegraded => "metrics_degraded",
+            OperatingMode::BackendDegraded => "backend_degraded",
+            OperatingMode::ObserveOnly => "observe_only",
+        }
+    }
+}
+
+impl fmt::Display for OperatingMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.label())
+    }
+}
+
+/// The current mode with what it is running on, for `GET /state`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ModeStatus {
+    pub mode: OperatingMode,
+    /// Unix time the mode was entered.
+    pub since: u64,
+    /// Age of the traffic figures in use; absent while they are current.
+    pub metrics_age_secs: Option<u64>,
+    /// Age of the mappings in use; absent while they are current.
+    pub mappings_age_secs: Option<u64>,
+    /// A dependency has had no answer for longer than `max_stale_secs`, so cycles are
+    /// skipped altogether.
+    pub skipping_cycles: bool,
+}
+
+/// One dependency's answer this cycle, with the last good one standing in for a failure.
+pub enum Fetched<T, E> {
+    Fresh(T),
+    Stale {
+        value: T,
+        age_secs: u64,
+        error: E,
+    },
+    /// Failed with nothing recent enough to fall back on.
+    Failed(E),
+}
+
+impl<T, E: fmt::Display> Fetched<T, E> {
+    pub fn is_fresh(&self) -> bool {
+        matches!(self, Fetched::Fresh(_))
+    }
+
+    pub fn is_failed(&self) -> bool {
+        matches!(self, Fetched::Failed(_))
+    }
+
+    pub fn age_secs(&self) -> Option<u64> {
+        match self {
+            Fetched::Stale { age_secs, .. } => Some(*age_secs),
+            Fetched::Fresh(_) | Fetched::Failed(_) => None,
+        }
+    }
+
+    /// The value to work with, logging and counting a failure under `source`; `None` when
+    /// the scan has to be skipped.
+    pub fn take(self, metrics: &Metrics, source: &'static str) -> Option<T> {
+        match self {
+            Fetched::Fresh(value) => Some(value),
+            Fetched::Stale {
+                value,
+                age_secs,
+                error,
+            } => {
+                metrics.record_scrape_error(source);
+                warn!(age_secs, "{:#}; using the last good answer", error);
+                Some(value)
+            }
+            Fetched::Failed(error) => {
+                metrics.record_scrape_error(source);
+                warn!("{:#}; skipping this scan", error);
+                None
+            }
+        }
+    }
+}
+
+/// The last good answer of one dependency.
+pub struct LastGood<T> {
+    latest: Option<(T, u64)>,
+}
+
+impl<T> Default for LastGood<T> {
+    fn default() -> Self {
+        Self { latest: None }
+    }
+}
+
+impl<T: Clone> LastGood<T> {
+    /// The last good answer and when it came.
+    pub fn latest(&self) -> Option<&(T, u64)> {
+        self.latest.as_ref()
+    }
+
+    /// Starts from an answer received at `at`, such as one saved before a restart.
+    pub fn restore(&mut self, value: T, at: u64) {
+        self.latest = Some((value, at));
+    }
+
+    /// Keeps a successful `result`; a failure falls back on the last good answer while it is
+    /// at most `max_stale_secs` old.
+    pub fn resolve<E>(
+        &mut self,
+        result: Result<T, E>,
+        now: u64,
+        max_stale_secs: u64,
+    ) -> Fetched<T, E> {
+        match result {
+            Ok(value) => {
+                self.latest = Some((value.clone(), now));
+                Fetched::Fresh(value)
+            }
+            Err(error) => match &self.latest {
+                Some((value, at)) if now.saturating_sub(*at) <= max_stale_secs => Fetched::Stale {
+                    value: value.clone(),
+                    age_secs: now.saturating_sub(*at),
+                    error,
+                },
+                _ => Fetched::Failed(error),
+            },
+        }
+    }
+}
+
+/// The mode over time, logging and counting transitions.
+pub struct ModeTracker {
+    status: ModeStatus,
+}
+
+impl ModeTracker {
+    pub fn new(now: u64) -> Self {
+        Self {
+            status: ModeStatus {
+                mode: OperatingMode::Full,
+                since: now,
+                metrics_age_secs: None,
+                mappings_age_secs: None,
+                skipping_cycles: false,
+            },
+        }
+    }
+
+    pub fn update(
+        &mut self,
+        mode: OperatingMode,
+        metrics_age_secs: Option<u64>,
+        mappings_age_secs: Option<u64>,
+        skipping_cycles: bool,
+        now: u64,
+        metrics: &Metrics,
+    ) -> &ModeStatus {
+        let previous = self.status.mode;
+        if mode != previous {
+            if mode == OperatingMode::Full {
+                info!(previous = %previous, "Dependencies recovered, back to full operation");
+            } else {
+                warn!(mode = %mode, previous = %previous, "Operating mode changed");
+            }
+            metrics.record_mode_change();
+            self.status.since = now;
+        }
+        self.status.mode = mode;
+        self.status.metrics_age_secs = metrics_age_secs;
+        self.status.mappings_age_secs = mappings_age_secs;
+        self.status.skipping_cycles = skipping_cycles;
+        metrics.record_mode(mode);
+        &self.status
+    }
+}
@@ -0,0 +1,176 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::borrow::Borrow;
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// Address of a LAN client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ClientIp(IpAddr);
+
+impl ClientIp {
+    pub fn addr(self) -> IpAddr {
+        self.0
+    }
+}
+
+impl From<IpAddr> for ClientIp {
+    fn from(addr: IpAddr) -> Self {
+        Self(addr)
+    }
+}
+
+impl FromStr for ClientIp {
+    type Err = String;
+
+    /// Also accepts the forms exporters use for IPv6 (`[2001:db8::1]`, `fe80::1%eth0`) and
+    /// treats IPv4-mapped IPv6 addresses as the IPv4 address.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let trimmed = s.trim();
+        let unbracketed = trimmed
+            .strip_prefix('[')
+            .and_then(|rest| rest.strip_suffix(']'))
+            .unwrap_or(trimmed);
+        let address = unbracketed
+            .split_once('%')
+            .map_or(unbracketed, |(address, _zone)| address);
+        address
+            .parse::<IpAddr>()
+            .map(|addr| Self(addr.to_canonical()))
+            .map_err(|_| format!("invalid client IP: {:?}", s))
+    }
+}
+
+impl fmt::Display for ClientIp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// Identifier the routing service uses for an uplink (`wan0`, `wan1`).
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct WanId(String);
+
+impl FromStr for WanId {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let valid = !s.is_empty()
+            && s.chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+        if !valid {
+            return Err(format!("invalid WAN id: {:?}", s));
+        }
+        Ok(Self(s.to_string()))
+    }
+}
+
+/// Name of a network interface (`eth0`), as the kernel would accept it.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NicName(String);
+
+impl FromStr for NicName {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // IFNAMSIZ includes the terminating NUL
+        let valid = !s.is_empty()
+            && s.len() < 16
+            && s != "."
+            && s != ".."
+            && !s.chars().any(|c| c == '/' || c == ':' || c.is_whitespace());
+        if !valid {
+            return Err(format!("invalid interface name: {:?}", s));
+        }
+        Ok(Self(s.to_string()))
+    }
+}
+
+macro_rules! string_newtype {
+    ($name:ident) => {
+        impl $name {
+            pub fn as_str(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl Borrow<str> for $name {
+            fn borrow(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl PartialEq<str> for $name {
+            fn eq(&self, other: &str) -> bool {
+                self.0 == other
+            }
+        }
+
+        impl PartialEq<&str> for $name {
+            fn eq(&self, other: &&str) -> bool {
+                self.0 == *other
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str(&self.0)
+            }
+        }
+    };
+}
+
+string_newtype!(WanId);
+string_newtype!(NicName);
+
+macro_rules! serde_via_str {
+    ($name:ident) => {
+        impl Serialize for $name {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.collect_str(self)
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $name {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                let raw = String::deserialize(deserializer)?;
+                raw.parse().map_err(serde::de::Error::custom)
+            }
+        }
+    };
+}
+
+serde_via_str!(ClientIp);
+serde_via_str!(WanId);
+serde_via_str!(NicName);
+
+#[derive(Debug, Default, Clone)]
+pub struct NicStats {
+    pub tcp_bandwidth: f64,
+    pub tx_bps: f64,
+    pub rx_bps: f64,
+    /// The router reports queue buildup on this NIC.
+    pub queue_congested: bool,
+}
+
+impl NicStats {
+    /// Estimated spare capacity: TCP bandwidth estimate minus current TX+RX traffic. A NIC
+    /// whose queues are building up has none, however low its byte counters are.
+    pub fn headroom(&self) -> f64 {
+        let headroom = self.tcp_bandwidth - (self.tx_bps + self.rx_bps);
+        if self.queue_congested {
+            headroom.min(0.0)
+        } else {
+            headroom
+        }
+    }
+}
+
+/// Traffic observed for a single LAN client, attributed to the NIC it is mapped to.
+#[derive(Debug, Clone)]
+pub struct IpTraffic {
+    pub ip: ClientIp,
+    pub nic: NicName,
+    pub rx_bps: f64,
+    pub tx_bps: f64,
+}
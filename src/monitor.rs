@@ -0,0 +1,2836 @@
+use crate::alerts::Alerts;
+use crate::anomaly::AnomalyDetector;
+use crate::app_classes::AppClassifier;
+use crate::audit::AuditLog;
+use crate::auth::Authenticator;
+use crate::breaker::{BreakerState, CircuitBreaker};
+use crate::calibration::Calibrator;
+use crate::client_rules::ClientRules;
+use crate::clock::Clock;
+use crate::config::{Config, GcAction, SnmpMode};
+use crate::connection_guard::ConnectionGuard;
+use crate::control::{
+    Control, ManualPin, PauseChange, PauseStatus, PendingPolicyChange, PolicyStatus, ShadowStatus,
+};
+use crate::controller::{ClientInfo, Controller};
+use crate::cooldown::Cooldowns;
+use crate::dashboard::Dashboard;
+use crate::data_caps::DataCaps;
+use crate::destinations::{self, DestinationEnricher, DestinationRules, DestinationTraffic};
+use crate::dhcp_leases::{DhcpLeases, Lease};
+use crate::diag::DiagRecorder;
+use crate::error::{BackendError, MetricsError};
+use crate::event_stream::EventStream;
+use crate::events::{Event, EventBus, NicSummary};
+use crate::failover::Failover;
+use crate::flows::{Flow, FlowSteering};
+use crate::gc::MappingGc;
+use crate::grpc::GrpcView;
+use crate::handoff::{ControlSocket, HandoffState};
+use crate::hints::CapacityHints;
+use crate::history::{SwitchHistory, SwitchRecord};
+use crate::history_db::{NicStatsIntervals, StoredPublicIpChange, StoredSwitch};
+use crate::hysteresis::Hysteresis;
+use crate::journal::Journal;
+use crate::link_quality::LinkQualityReader;
+use crate::local_stats::{InterfaceRates, LocalStats};
+use crate::maintenance::MaintenanceWindows;
+use crate::metrics::{DataCapGauges, Metrics, NicGauges};
+use crate::mode::{Fetched, LastGood, ModeTracker, OperatingMode};
+use crate::model::{ClientIp, IpTraffic, NicName, NicStats, WanId};
+use crate::neighbors::{self, Devices};
+use crate::passive_rtt::PassiveRtt;
+use crate::placement::InitialPlacement;
+use crate::policy::{PolicyInput, SkippedCandidate, SwitchDecision};
+use crate::prediction::Forecaster;
+use crate::probe::{Prober, WanProbeStats};
+use crate::prometheus::{PrometheusClient, PrometheusResult};
+use crate::public_ip::PublicIpWatcher;
+use crate::qos::{QueueMonitor, QueueState};
+use crate::quota::Quotas;
+use crate::rate_limit::SwitchRateLimit;
+use crate::remote_write::{DerivedInput, RemoteWriter};
+use crate::replay::Recording;
+use crate::report::{
+    BandwidthComparison, CircuitReport, CycleReport, DecisionOutcome, DecisionReport, RecentHold,
+    RecentSwitch, TopIpReport, WanReport,
+};
+use crate::reservations::Reservations;
+use crate::reverse_dns::{Hostnames, ReverseDns};
+use crate::rollback::Rollbacks;
+use crate::routing::{ConfigInfo, RoutingService, StatusResponse};
+use crate::saved_state::{self, SavedState};
+use crate::scan_interval::ScanInterval;
+use crate::server::AppState;
+use crate::shutdown::Shutdown;
+use crate::smoothing::Smoother;
+use crate::snmp::SnmpPoller;
+use crate::soft_start::SoftStart;
+use crate::speedtest::SpeedtestGuard;
+use crate::status_page::{RateLimiter, StatusBoard, WanStatus};
+use crate::store::{self, StateStore};
+use crate::systemd::Notifier;
+use crate::templates::{ReportTemplate, Templates};
+use crate::verification::{self, AcceptedSwitch, Verification};
+use crate::{
+    arp, conntrack, fairness, grpc, kafka, metric_source, nats, notify, policy, retry, server,
+    webhook,
+};
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use std::collections::BTreeMap;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, error, info, info_span, warn, Instrument};
+
+pub fn build_wan_to_nic_map(config: &ConfigInfo) -> HashMap<WanId, NicName> {
+    config
+        .wans
+        .iter()
+        .map(|(wan, nic)| (wan.clone(), nic.clone()))
+        .collect()
+}
+
+fn build_ip_to_nic_map(
+    status: &StatusResponse,
+    wan_to_nic: &HashMap<WanId, NicName>,
+) -> HashMap<ClientIp, NicName> {
+    let mut ip_to_nic = HashMap::new();
+
+    for (ip, wan) in &status.mappings {
+        if let Some(nic) = wan_to_nic.get(wan) {
+            ip_to_nic.insert(*ip, nic.clone());
+        }
+    }
+
+    ip_to_nic
+}
+
+/// The neighbour table with the addresses the controller and the DHCP leases know for each
+/// MAC added.
+fn with_known_addresses(
+    neighbors: &HashMap<String, Vec<ClientIp>>,
+    controller_clients: &HashMap<ClientIp, ClientInfo>,
+    leases: &HashMap<ClientIp, Lease>,
+) -> HashMap<String, Vec<ClientIp>> {
+    let mut table = neighbors.clone();
+    for client in controller_clients.values() {
+        table
+            .entry(neighbors::normalize_mac(&client.mac))
+            .or_default()
+            .extend(&client.addresses);
+    }
+    for (ip, lease) in leases {
+        if let Some(mac) = &lease.mac {
+            table.entry(mac.clone()).or_default().push(*ip);
+        }
+    }
+    for addresses in table.values_mut() {
+        addresses.sort();
+        addresses.dedup();
+    }
+    table
+}
+
+fn count_clients_per_wan(mappings: &HashMap<ClientIp, WanId>) -> HashMap<WanId, usize> {
+    let mut counts = HashMap::new();
+
+    for wan in mappings.values() {
+        *counts.entry(wan.clone()).or_insert(0) += 1;
+    }
+
+    counts
+}
+
+/// What the loop holds that should survive a restart.
+#[allow(clippy::too_many_arguments)]
+fn save_state(
+    config: &Config,
+    now: u64,
+    switch_history: &SwitchHistory,
+    manual_pins: &BTreeMap<ClientIp, ManualPin>,
+    pause: &Option<PauseStatus>,
+    failover: Option<&Failover>,
+    soft_start: Option<&SoftStart>,
+    smoother: Option<&Smoother>,
+    switch_breaker: &CircuitBreaker,
+    last_status: &LastGood<StatusResponse>,
+) {
+    let Some(saved_state) = &config.saved_state else {
+        return;
+    };
+    let state = SavedState {
+        saved_at: now,
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        switch_history: switch_history.records().to_vec(),
+        manual_pins: manual_pins.values().cloned().collect(),
+        pause: pause.clone(),
+        failover: failover.map(Failover::export),
+        soft_start_since: soft_start.map(SoftStart::ramp_started_at),
+        smoothing: smoother.map(Smoother::export),
+        breaker: switch_breaker.export(),
+        last_status: last_status.latest().cloned(),
+    };
+    if let Err(e) = saved_state::save(saved_state, &state) {
+        warn!("Failed to save the state: {:#}", e);
+    }
+}
+
+/// What the loop learns as it runs, written to the store so a restart does not start the
+/// class residencies, quota windows and traffic baselines over.
+fn store_learned(
+    store: &dyn StateStore,
+    cooldowns: &Cooldowns,
+    quotas: Option<&Quotas>,
+    anomaly_detector: Option<&AnomalyDetector>,
+) {
+    if let Err(e) = store.save_residencies(&cooldowns.export()) {
+        warn!("Failed to store the class residencies: {:#}", e);
+    }
+    if let Some(quotas) = quotas {
+        if let Err(e) = store.save_quota_usage(&quotas.export()) {
+            warn!("Failed to store the quota usage: {:#}", e);
+        }
+    }
+    if let Some(anomaly_detector) = anomaly_detector {
+        if let Err(e) = store.save_profiles(&anomaly_detector.export()) {
+            warn!("Failed to store the traffic profiles: {:#}", e);
+        }
+    }
+}
+
+/// Takes back what `store_learned` wrote. Anything unreadable is learned again from scratch.
+fn restore_learned(
+    store: &dyn StateStore,
+    now: u64,
+    cooldowns: &mut Cooldowns,
+    quotas: Option<&mut Quotas>,
+    anomaly_detector: Option<&mut AnomalyDetector>,
+) {
+    match store.load_residencies() {
+        Ok(rows) => cooldowns.restore(rows, now),
+        Err(e) => warn!(
+            "Failed to load the class residencies: {:#}; starting afresh",
+            e
+        ),
+    }
+    if let Some(quotas) = quotas {
+        match store.load_quota_usage() {
+            Ok(rows) => quotas.restore(rows, now),
+            Err(e) => warn!("Failed to load the quota usage: {:#}; starting afresh", e),
+        }
+    }
+    if let Some(anomaly_detector) = anomaly_detector {
+        match store.load_profiles() {
+            Ok(rows) => anomaly_detector.restore(rows, now),
+            Err(e) => warn!(
+                "Failed to load the traffic profiles: {:#}; starting afresh",
+                e
+            ),
+        }
+    }
+}
+
+/// Sample times of the NICs whose link is up, for WAN health from local counters.
+fn local_sample_times(
+    local_rates: &HashMap<NicName, InterfaceRates>,
+    now: u64,
+) -> HashMap<NicName, f64> {
+    local_rates
+        .iter()
+        .filter(|(_, rates)| rates.up)
+        .map(|(nic, _)| (nic.clone(), now as f64))
+        .collect()
+}
+
+/// Moves each of `flows` of `ip` onto `target_wan`, stopping at the first the routing
+/// service refuses.
+async fn switch_flows(
+    routing: &RoutingService,
+    path: &str,
+    ip: ClientIp,
+    flows: &[Flow],
+    target_wan: &WanId,
+) -> Result<(), BackendError> {
+    for flow in flows {
+        routing
+            .switch_flow(path, ip, flow.port, &flow.proto, target_wan)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Why a planned move is not made this cycle.
+struct Skip {
+    /// Label of the skip metric.
+    metric: &'static str,
+    /// Of the `SwitchSkipped` event, and of the report for a move skipped outright.
+    reason: String,
+    outcome: DecisionOutcome,
+}
+
+impl Skip {
+    fn held(metric: &'static str, remaining_secs: u64, hold_reason: String) -> Self {
+        Self {
+            metric,
+            reason: hold_reason.clone(),
+            outcome: DecisionOutcome::Held {
+                remaining_secs,
+                hold_reason,
+            },
+        }
+    }
+}
+
+/// Counts, announces and reports `decision` as not made this cycle.
+fn skip(
+    decision: &SwitchDecision,
+    skipped: Skip,
+    now: u64,
+    metrics: &Metrics,
+    event_bus: &EventBus,
+    decisions: &mut Vec<DecisionReport>,
+) {
+    metrics.record_skip(skipped.metric);
+    event_bus.emit(Event::SwitchSkipped {
+        timestamp: now,
+        ip: decision.ip,
+        reason: skipped.reason.clone(),
+    });
+    // A held move still stands for the policy's reason
+    let reason = match skipped.outcome {
+        DecisionOutcome::Skipped => skipped.reason,
+        _ => decision.reason.clone(),
+    };
+    decisions.push(DecisionReport {
+        ip: decision.ip,
+        nic: decision.from_nic.clone(),
+        target_wan: Some(decision.target_wan.clone()),
+        rx_bps: Some(decision.rx_bps),
+        reason,
+        outcome: skipped.outcome,
+    });
+}
+
+/// The first of the checks a planned move goes through before it is made that holds it
+/// back this cycle: cooldown, active connections, operating mode, switch API circuit,
+/// soft start, rate limit and the routing service's dry run, in that order. `urgent`
+/// moves skip the holds meant for moves that can wait; a `requested` one, an operator's
+/// one-off switch, the rate limit.
+#[allow(clippy::too_many_arguments)]
+async fn check_gates(
+    decision: &SwitchDecision,
+    urgent: bool,
+    requested: bool,
+    now: u64,
+    cooldowns: &Cooldowns,
+    switch_history: &SwitchHistory,
+    connection_guard: Option<&mut ConnectionGuard>,
+    mode: OperatingMode,
+    switch_breaker: &mut CircuitBreaker,
+    soft_start: Option<&mut SoftStart>,
+    switch_rate_limit: Option<&mut SwitchRateLimit>,
+    routing: &RoutingService,
+    validate_path: Option<&str>,
+    hostnames: &Hostnames,
+) -> Option<Skip> {
+    let ip = decision.ip;
+
+    // Check if this IP is still cooling down from a previous switch
+    if let Some(hold) = cooldowns
+        .remaining(switch_history, ip, now)
+        .filter(|_| !urgent)
+    {
+        info!(
+            ip = %ip,
+            remaining_secs = hold.remaining_secs,
+            reason = %hold.reason,
+            "Skipping switch, IP is held"
+        );
+        return Some(Skip {
+            metric: "cooldown",
+            reason: format!(
+                "held for another {}s ({})",
+                hold.remaining_secs, hold.reason
+            ),
+            outcome: DecisionOutcome::Held {
+                remaining_secs: hold.remaining_secs,
+                hold_reason: hold.reason.to_string(),
+            },
+        });
+    }
+
+    // Moving a client mid-transfer breaks its NATed connections
+    let deferral = match connection_guard.filter(|_| !urgent) {
+        Some(guard) => guard.check(ip, now).unwrap_or_else(|e| {
+            warn!("{:#}; not checking for active connections", e);
+            None
+        }),
+        None => None,
+    };
+    if let Some(deferral) = deferral {
+        info!(
+            ip = %hostnames.label(ip),
+            connections = deferral.connections.count,
+            remaining_secs = deferral.remaining_secs,
+            "Deferring switch, client has active connections"
+        );
+        return Some(Skip::held(
+            "connections",
+            deferral.remaining_secs.unwrap_or_default(),
+            format!(
+                "{} active connection(s), {:.0} MB",
+                deferral.connections.count,
+                deferral.connections.bytes as f64 / 1_000_000.0
+            ),
+        ));
+    }
+
+    // Stale figures or mappings hold back what can wait, or everything
+    if !mode.allows_switch(urgent) {
+        info!(ip = %ip, mode = %mode, "Skipping switch, operating in a degraded mode");
+        return Some(Skip::held("degraded", 0, format!("{} mode", mode)));
+    }
+
+    // A failing switch API gets a pause instead of a call every cycle
+    if !switch_breaker.allow(now) {
+        let remaining_secs = match switch_breaker.state(now) {
+            BreakerState::Open { remaining_secs } => remaining_secs,
+            BreakerState::Closed | BreakerState::HalfOpen => 0,
+        };
+        info!(ip = %ip, remaining_secs, "Skipping switch, switch API circuit is open");
+        return Some(Skip::held(
+            "circuit_open",
+            remaining_secs,
+            "switch API circuit open".to_string(),
+        ));
+    }
+
+    // While ramping up, only a few moves per minute; urgent moves are never held back
+    if let Some(soft_start) = soft_start.filter(|_| !urgent) {
+        if let Err(remaining_secs) = soft_start.check(now) {
+            let budget = soft_start.budget(now).unwrap_or_default();
+            info!(
+                ip = %ip,
+                remaining_secs,
+                moves_per_min = budget,
+                "Skipping switch, soft-start budget used up"
+            );
+            return Some(Skip::held(
+                "soft_start",
+                remaining_secs,
+                format!("soft start ({} moves/min)", budget),
+            ));
+        }
+    }
+
+    // A cap on all moves, so a glitch in the figures cannot set off a storm of switch
+    // calls; only an operator's one-off switch goes past it
+    if let Some(rate_limit) = switch_rate_limit.filter(|_| !requested) {
+        if let Err(remaining_secs) = rate_limit.check(now) {
+            info!(ip = %ip, remaining_secs, "Skipping switch, switch rate limit reached");
+            return Some(Skip::held(
+                "rate_limit",
+                remaining_secs,
+                format!("switch rate limit ({}/min)", rate_limit.max_per_minute()),
+            ));
+        }
+    }
+
+    // A switch the routing service says it would refuse is reported now rather than
+    // failing, and counting against the circuit breaker, when applied
+    if let Some(validate_path) = validate_path {
+        match routing
+            .validate(validate_path, ip, &decision.target_wan)
+            .await
+        {
+            Ok(None) => {}
+            Ok(Some(rejection)) => {
+                let reason = format!(
+                    "the routing service's dry run rejected the move to {}: {}",
+                    decision.target_wan, rejection
+                );
+                warn!(ip = %hostnames.label(ip), reason = %reason, "Skipping switch");
+                return Some(Skip {
+                    metric: "dry_run",
+                    reason,
+                    outcome: DecisionOutcome::Skipped,
+                });
+            }
+            // The switch itself will tell
+            Err(e) => warn!(ip = %ip, "Dry run of switch failed: {}", e),
+        }
+    }
+    None
+}
+
+/// Drops the client's conntrack entries in the background so its flows move to the new WAN.
+fn flush_conntrack(ip: ClientIp) {
+    tokio::task::spawn_blocking(move || match conntrack::flush_client(ip.addr()) {
+        Ok(flushed) => info!(ip = %ip, flushed, "Flushed conntrack entries"),
+        Err(e) => warn!(ip = %ip, "Failed to flush conntrack entries: {:#}", e),
+    });
+}
+
+/// Reports mappings that have been idle too long and, if configured, removes them.
+async fn collect_idle_mappings(
+    routing: &RoutingService,
+    mapping_gc: &mut MappingGc,
+    mappings: &HashMap<ClientIp, WanId>,
+    ip_traffic: &[IpTraffic],
+    now: u64,
+) {
+    let arp_present = arp::read_present_ips().unwrap_or_else(|e| {
+        warn!("ARP table unavailable, using traffic only: {}", e);
+        Default::default()
+    });
+
+    let stale = mapping_gc.sweep(mappings, ip_traffic, &arp_present, now);
+    if stale.is_empty() {
+        return;
+    }
+
+    let action = mapping_gc.config().action;
+    let remove_path = mapping_gc.config().remove_path.clone();
+
+    for (ip, idle) in stale {
+        let wan = mappings.get(&ip).map_or("?", WanId::as_str);
+        info!(
+            ip = %ip,
+            wan,
+            idle_secs = idle,
+            "Idle mapping: no traffic or ARP presence"
+        );
+
+        if action != GcAction::Remove {
+            continue;
+        }
+
+        match routing.remove(&remove_path, ip).await {
+            Ok(()) => {
+                info!(ip = %ip, "Removed idle mapping");
+                mapping_gc.forget(ip);
+            }
+            Err(e) => warn!(ip = %ip, "Failed to remove mapping: {}", e),
+        }
+    }
+}
+
+/// Format of the per-cycle report on stdout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Human-readable report
+    Text,
+    /// One JSON object per cycle (for jq and other tooling)
+    Json,
+}
+
+/// Balances every site of `config` side by side, each on a clock of its own from `clock`,
+/// so a simulated clock advances once per cycle of each. Fails as soon as one site does.
+pub async fn run_sites(
+    config: Config,
+    output: Option<OutputFormat>,
+    recorder: Arc<DiagRecorder>,
+    clock: impl Fn() -> Arc<dyn Clock>,
+    shutdown: Shutdown,
+) -> Result<()> {
+    let loops = config.sites.into_iter().map(|site| {
+        let span = info_span!("site", site = %site.name);
+        let run = run_monitor(
+            site.config,
+            output,
+            recorder.clone(),
+            clock(),
+            shutdown.clone(),
+            None,
+        );
+        async move {
+            run.instrument(span)
+                .await
+                .with_context(|| format!("Site {} stopped", site.name))
+        }
+    });
+    futures_util::future::try_join_all(loops).await?;
+    Ok(())
+}
+
+/// Runs the balancing loop, printing each cycle's report in `output` format
+/// (nothing when `None`); `recorder` keeps the recent reports for `diag`. Every timestamp
+/// and wait of the loop comes from `clock`. `inherited` is the state handed over by the
+/// instance this one replaces. Returns once `shutdown` is requested and the current cycle
+/// is done, or after handing over to a new instance.
+pub async fn run_monitor(
+    config: Config,
+    output: Option<OutputFormat>,
+    recorder: Arc<DiagRecorder>,
+    clock: Arc<dyn Clock>,
+    mut shutdown: Shutdown,
+    inherited: Option<HandoffState>,
+) -> Result<()> {
+    let metric_source = metric_source::from_config(&config)?;
+    // For the PromQL of the probe pauses and passive RTT
+    let prometheus = PrometheusClient::new(&config.prometheus)?;
+    let routing = RoutingService::new(&config.routing_service, config.retry.clone())?;
+    let mut switch_policy = policy::from_config(&config)?;
+    let report_template = ReportTemplate::new(config.report.template.as_deref())?;
+    let mut hysteresis = config.hysteresis.clone().map(Hysteresis::new);
+    let mut initial_placement = config.initial_placement.clone().map(InitialPlacement::new);
+    let mut cooldowns = Cooldowns::new(&config);
+    let mut switch_breaker = CircuitBreaker::new(config.circuit_breaker.clone());
+    let mut soft_start = config.soft_start.clone().map(|soft_start| {
+        let now = clock.unix_secs();
+        SoftStart::new(soft_start, now)
+    });
+    let mut switch_rate_limit = config
+        .switch_rate_limit
+        .as_ref()
+        .map(SwitchRateLimit::new)
+        .transpose()?;
+    let mut switch_history = SwitchHistory::default();
+    let mut mode_tracker = ModeTracker::new(clock.unix_secs());
+    let mut last_status = LastGood::default();
+    let mut last_tcp_results = LastGood::default();
+    let mut last_network_results = LastGood::default();
+    let mut mapping_gc = config.mapping_gc.clone().map(MappingGc::new);
+    let mut destination_enricher = config
+        .destinations
+        .as_ref()
+        .map(DestinationEnricher::new)
+        .transpose()?;
+    let destination_rules = config
+        .destinations
+        .as_ref()
+        .map(|destinations| DestinationRules::new(&destinations.rules))
+        .transpose()?;
+    let mut client_rules = ClientRules::new(&config)?;
+    let mut rollbacks = config.rollback.clone().map(Rollbacks::new);
+    let mut speedtest = config
+        .speedtest
+        .clone()
+        .map(|speedtest| SpeedtestGuard::new(speedtest, config.destinations.is_some()))
+        .transpose()?;
+    // Client whose speed test held back rebalancing last cycle, to log starts and ends
+    let mut speedtest_client: Option<ClientIp> = None;
+    // Temporary pins made over the API, until they expire or are released
+    let mut manual_pins: BTreeMap<ClientIp, ManualPin> = BTreeMap::new();
+    // Set while an operator has paused the balancing over the API
+    let mut pause: Option<PauseStatus> = None;
+    let mut maintenance = MaintenanceWindows::new(&config.maintenance_windows);
+    // MAC → addresses from the neighbour tables, and when they were last read
+    let mut neighbor_table: HashMap<String, Vec<ClientIp>> = HashMap::new();
+    let mut neighbors_read_at: Option<u64> = None;
+    let mut dhcp_leases =
+        (!config.dhcp_leases.is_empty()).then(|| DhcpLeases::new(&config.dhcp_leases));
+    let mut recording = config.recording.clone().map(Recording::new);
+    let scan_interval = ScanInterval::new(config.scan.clone())?;
+    let mut audit_log = config
+        .audit
+        .clone()
+        .map(|audit| AuditLog::new(audit, config.hysteresis.clone()));
+    let reservations = Reservations::new(&config.reservations)?;
+    let mut quotas = (!config.quotas.is_empty())
+        .then(|| Quotas::new(&config.quotas))
+        .transpose()?;
+    let mut anomaly_detector = config
+        .anomaly
+        .clone()
+        .map(AnomalyDetector::new)
+        .transpose()?;
+    // Names of the reservations whose window was open last cycle, to log openings and closings
+    let mut open_reservations: HashSet<String> = HashSet::new();
+    let prober = config
+        .probes
+        .clone()
+        .map(|probes| Prober::spawn(probes, clock.clone()));
+    let controller = config
+        .controller
+        .clone()
+        .map(Controller::spawn)
+        .transpose()?;
+    let reverse_dns = config
+        .reverse_dns
+        .clone()
+        .map(|reverse_dns| ReverseDns::new(reverse_dns, clock.clone()))
+        .transpose()?;
+    let passive_rtt = config.passive_rtt.clone().map(PassiveRtt::new);
+    let experience_query = passive_rtt.as_ref().map(PassiveRtt::query);
+    let link_quality_reader = config
+        .link_quality
+        .clone()
+        .map(LinkQualityReader::new)
+        .transpose()?;
+    let loss_query = link_quality_reader
+        .as_ref()
+        .and_then(LinkQualityReader::loss_query);
+    let jitter_query = link_quality_reader
+        .as_ref()
+        .and_then(LinkQualityReader::jitter_query);
+    let mut data_caps = config.data_caps.clone().map(DataCaps::new).transpose()?;
+    let app_classifier = config
+        .app_classes
+        .clone()
+        .map(AppClassifier::new)
+        .transpose()?;
+    let app_query = app_classifier.as_ref().map(AppClassifier::query);
+    let mut failover = config.failover.clone().map(Failover::new);
+    let mut smoother = config.smoothing.clone().map(Smoother::new);
+    let mut forecaster = config.prediction.clone().map(Forecaster::new).transpose()?;
+    let mut connection_guard = config.conntrack.protect.clone().map(ConnectionGuard::new);
+    let mut flow_steering = config.flows.clone().map(FlowSteering::new);
+    let mut queue_monitor = config.qos.clone().map(QueueMonitor::new);
+    let mut local_stats = config
+        .local_stats
+        .as_ref()
+        .map(|local_stats| LocalStats::new(local_stats, clock.now()))
+        .transpose()?;
+    let snmp = config
+        .snmp
+        .clone()
+        .map(|snmp| SnmpPoller::spawn(snmp, clock.clone()))
+        .transpose()?;
+    let calibrator = config
+        .calibration
+        .clone()
+        .map(|calibration| Calibrator::spawn(calibration, clock.clone()));
+    // Last TCP bandwidth estimate of every NIC, for the cycles run on local counters alone
+    let mut known_bandwidth: HashMap<NicName, f64> = HashMap::new();
+    // NICs whose queues were building up last cycle, so each episode is logged once
+    let mut congested_nics: HashSet<NicName> = HashSet::new();
+    let history_db = store::open(&config.history)?;
+    let mut nic_stats_intervals = (history_db.is_some()
+        && config.history.nic_stats_interval_secs > 0)
+        .then(|| NicStatsIntervals::new(config.history.nic_stats_interval_secs));
+    let public_ip_watcher = match config.public_ip.clone() {
+        Some(public_ip) => Some(PublicIpWatcher::spawn(
+            public_ip,
+            RoutingService::new(&config.routing_service, config.retry.clone())?,
+        )),
+        None => None,
+    };
+    // Last known public address per WAN; across restarts from the history database
+    let mut public_ips: HashMap<WanId, IpAddr> = match (&public_ip_watcher, &history_db) {
+        (Some(_), Some(history_db)) => history_db
+            .latest_public_ips()?
+            .into_iter()
+            .filter_map(|(wan, address)| Some((wan.parse().ok()?, address.parse().ok()?)))
+            .collect(),
+        _ => HashMap::new(),
+    };
+    let mut journal = if config.journal.enabled {
+        Some(Journal::open(&config.journal)?)
+    } else {
+        None
+    };
+
+    let event_bus = EventBus::new();
+    if let Some(nats_config) = config.events.nats.clone() {
+        tokio::spawn(nats::run(nats_config, event_bus.subscribe()));
+    }
+    if let Some(kafka_config) = config.events.kafka.clone() {
+        tokio::spawn(kafka::run(kafka_config, event_bus.subscribe()));
+    }
+    for webhook_config in config.events.webhooks.clone() {
+        let templates = Templates::new(&config.events.templates, &webhook_config.templates)?;
+        tokio::spawn(webhook::run(
+            webhook_config,
+            templates,
+            event_bus.subscribe(),
+        ));
+    }
+    for notifier_config in config.events.notifiers.clone() {
+        let templates = Templates::new(&config.events.templates, &notifier_config.templates)?;
+        let channel = notify::Channel::new(notifier_config, templates)?;
+        tokio::spawn(channel.run(event_bus.subscribe()));
+    }
+    // NICs whose traffic currently exceeds their estimate, so each overrun is reported once
+    let mut exceeded_nics: HashSet<NicName> = HashSet::new();
+    let mut alerts = Alerts::new(&config.events);
+
+    let mut remote_writer = config
+        .remote_write
+        .clone()
+        .map(|remote_write| RemoteWriter::new(&config, remote_write));
+
+    let metrics = Arc::new(Metrics::default());
+    let status_board = Arc::new(StatusBoard::new(clock.clone()));
+    let control = Arc::new(Control::new(&config));
+    // Runtime-selected policy that only logs its decisions until its warm-up has passed
+    let mut shadow: Option<(PendingPolicyChange, u64)> = None;
+    let dashboard = config
+        .server
+        .dashboard
+        .as_ref()
+        .map(|dashboard| Arc::new(Dashboard::new(dashboard)));
+    let hints = config
+        .capacity_hints
+        .clone()
+        .map(|hints| Arc::new(CapacityHints::new(hints)));
+    let grpc_view = config
+        .server
+        .grpc
+        .as_ref()
+        .map(|_| Arc::new(GrpcView::default()));
+    let status_page = &config.server.status_page;
+    let app_state = AppState {
+        metrics: metrics.clone(),
+        auth: Arc::new(Authenticator::new(&config.server.auth, clock.clone())),
+        status_board: status_board.clone(),
+        control: control.clone(),
+        diag: recorder.clone(),
+        event_stream: Arc::new(EventStream::new(
+            config.server.event_stream.clone(),
+            event_bus.clone(),
+            metrics.clone(),
+        )),
+        dashboard: dashboard.clone(),
+        hints: hints.clone(),
+        store: history_db.clone(),
+        status_limiter: status_page.enabled.then(|| {
+            Arc::new(RateLimiter::per_minute(
+                status_page.requests_per_minute,
+                clock.clone(),
+            ))
+        }),
+    };
+    if let Some(listen) = config.server.listen {
+        server::spawn(listen, app_state.clone()).await?;
+    }
+    if let Some(dashboard_config) = &config.server.dashboard {
+        server::spawn_dashboard(dashboard_config.listen, app_state.clone()).await?;
+    }
+    if let (Some(grpc_config), Some(view)) = (&config.server.grpc, &grpc_view) {
+        grpc::spawn(grpc_config.listen, app_state, view.clone()).await?;
+    }
+
+    // A handed-over state is newer than the snapshot, so it goes over it
+    let snapshot = config
+        .saved_state
+        .as_ref()
+        .and_then(|saved_state| saved_state::load(saved_state, clock.unix_secs()));
+    if let Some(snapshot) = &snapshot {
+        info!(
+            version = %snapshot.version,
+            age_secs = clock.unix_secs().saturating_sub(snapshot.saved_at),
+            switches = snapshot.switch_history.len(),
+            manual_pins = snapshot.manual_pins.len(),
+            "Restored the saved state"
+        );
+    }
+    let restored = match inherited {
+        Some(inherited) => {
+            info!(
+                pid = inherited.pid,
+                version = %inherited.version,
+                switches = inherited.switch_history.len(),
+                manual_pins = inherited.manual_pins.len(),
+                "Took over from the previous instance"
+            );
+            Some(inherited.over(snapshot, clock.unix_secs()))
+        }
+        None => snapshot,
+    };
+    if let Some(restored) = restored {
+        for record in restored.switch_history {
+            switch_history.record(record);
+        }
+        manual_pins.extend(restored.manual_pins.into_iter().map(|pin| (pin.ip, pin)));
+        pause = restored.pause;
+        if let (Some(failover), Some(state)) = (failover.as_mut(), restored.failover) {
+            failover.restore(state);
+        }
+        if let (Some(soft_start), Some(since)) = (soft_start.as_mut(), restored.soft_start_since) {
+            soft_start.restart(since);
+        }
+        if let (Some(smoother), Some(state)) = (smoother.as_mut(), restored.smoothing) {
+            smoother.restore(state);
+        }
+        switch_breaker.restore(restored.breaker);
+        if let Some((status, at)) = restored.last_status {
+            last_status.restore(status, at);
+        }
+    } else if let Some(history_db) = &history_db {
+        // A cold start: the switches still within a cooldown come from the store
+        let since = clock.unix_secs().saturating_sub(cooldowns.max_window());
+        for record in history_db.recent_switches(since)? {
+            switch_history.record(record);
+        }
+    }
+    if let Some(history_db) = &history_db {
+        restore_learned(
+            history_db.as_ref(),
+            clock.unix_secs(),
+            &mut cooldowns,
+            quotas.as_mut(),
+            anomaly_detector.as_mut(),
+        );
+    }
+    let mut control_socket = config
+        .handoff
+        .as_ref()
+        .map(ControlSocket::bind)
+        .transpose()?;
+
+    let mut systemd = Notifier::from_env();
+    let run_started = clock.unix_secs();
+    let mut state_saved_at = run_started;
+    let mut learned_stored_at = run_started;
+    let (mut cycles, mut switched, mut failed) = (0u64, 0u64, 0u64);
+    while !shutdown.is_requested() {
+        // A new instance takes over between cycles; this one stops switching and exits
+        if let Some(request) = control_socket
+            .as_mut()
+            .and_then(ControlSocket::take_request)
+        {
+            let state = HandoffState {
+                pid: std::process::id(),
+                version: env!("CARGO_PKG_VERSION").to_string(),
+                switch_history: switch_history.records().to_vec(),
+                manual_pins: manual_pins.values().cloned().collect(),
+                pause: pause.clone(),
+                failover: failover.as_ref().map(Failover::export),
+                soft_start_since: soft_start.as_ref().map(SoftStart::ramp_started_at),
+                smoothing: smoother.as_ref().map(Smoother::export),
+                breaker: Some(switch_breaker.export()),
+                last_status: last_status.latest().cloned(),
+            };
+            match request.complete(&state).await {
+                Ok(()) => {
+                    info!("Handed over to the new instance");
+                    break;
+                }
+                Err(e) => warn!("{:#}; continuing", e),
+            }
+        }
+        let cycle_started = clock.now();
+        cycles += 1;
+        if let Some(systemd) = &systemd {
+            systemd.watchdog();
+        }
+
+        // The routing service and Prometheus queries are independent of each other, so they
+        // run concurrently; at short scan intervals their latencies would otherwise add up
+        debug!(
+            "Fetching status mappings from {} and traffic data from {}",
+            routing.base_url(),
+            metric_source.name()
+        );
+        let pause_query = config
+            .probes
+            .as_ref()
+            .and_then(|probes| probes.pause_query.as_ref())
+            .filter(|_| prober.is_some());
+        let data_cap_queries = data_caps
+            .as_ref()
+            .map(|data_caps| data_caps.due_queries(&clock.local()))
+            .unwrap_or_default();
+        let (
+            status,
+            tcp_results,
+            timestamp_results,
+            pause_results,
+            network_results,
+            qos,
+            experience_results,
+            app_results,
+            loss_results,
+            jitter_results,
+            data_cap_results,
+        ) = tokio::join!(
+            routing.status(),
+            metric_source.tcp_bandwidth(clock.unix_secs()),
+            async {
+                if failover.is_some() {
+                    Some(
+                        metric_source
+                            .bandwidth_sample_times(clock.unix_secs())
+                            .await,
+                    )
+                } else {
+                    None
+                }
+            },
+            async {
+                match pause_query {
+                    Some(pause_query) => Some(
+                        retry::with_backoff(&config.retry, "Prometheus query", || {
+                            prometheus.query(pause_query)
+                        })
+                        .await,
+                    ),
+                    None => None,
+                }
+            },
+            metric_source.network_by_ip(clock.unix_secs()),
+            async {
+                match &queue_monitor {
+                    Some(queue_monitor) => Some(routing.qos(queue_monitor.path()).await),
+                    None => None,
+                }
+            },
+            async {
+                match &experience_query {
+                    Some(experience_query) => Some(
+                        retry::with_backoff(&config.retry, "Prometheus query", || {
+                            prometheus.query(experience_query)
+                        })
+                        .await,
+                    ),
+                    None => None,
+                }
+            },
+            async {
+                match &app_query {
+                    Some(app_query) => Some(
+                        retry::with_backoff(&config.retry, "Prometheus query", || {
+                            prometheus.query(app_query)
+                        })
+                        .await,
+                    ),
+                    None => None,
+                }
+            },
+            async {
+                match loss_query {
+                    Some(loss_query) => Some(
+                        retry::with_backoff(&config.retry, "Prometheus query", || {
+                            prometheus.query(loss_query)
+                        })
+                        .await,
+                    ),
+                    None => None,
+                }
+            },
+            async {
+                match jitter_query {
+                    Some(jitter_query) => Some(
+                        retry::with_backoff(&config.retry, "Prometheus query", || {
+                            prometheus.query(jitter_query)
+                        })
+                        .await,
+                    ),
+                    None => None,
+                }
+            },
+            futures_util::future::join_all(data_cap_queries.iter().map(|(wan, query)| async {
+                let result = retry::with_backoff(&config.retry, "Prometheus query", || {
+                    prometheus.query(query)
+                })
+                .await;
+                (wan.clone(), result)
+            })),
+        );
+
+        // A failed fetch falls back on its last good answer for a while; what is stale
+        // decides the mode
+        let now = clock.unix_secs();
+        let max_stale_secs = config.degradation.max_stale_secs;
+        let status = last_status.resolve(status, now, max_stale_secs);
+        let tcp_results = last_tcp_results.resolve(tcp_results, now, max_stale_secs);
+        // Read every cycle, so the rates are over one scan when they are needed
+        let local_rates = match local_stats.as_mut() {
+            Some(local_stats) => match local_stats.sample(clock.now()) {
+                Ok(rates) => Some(rates),
+                Err(e) => {
+                    warn!("{:#}; no local interface counters this scan", e);
+                    None
+                }
+            },
+            None => None,
+        };
+        let network_results = last_network_results.resolve(network_results, now, max_stale_secs);
+        let metrics_age_secs = tcp_results.age_secs().max(network_results.age_secs());
+        let status_fresh = status.is_fresh();
+        let backend_healthy =
+            status_fresh && !matches!(switch_breaker.state(now), BreakerState::Open { .. });
+        let mode = OperatingMode::from_health(
+            tcp_results.is_fresh() && network_results.is_fresh(),
+            backend_healthy,
+        );
+        control.set_mode(
+            mode_tracker
+                .update(
+                    mode,
+                    metrics_age_secs,
+                    status.age_secs(),
+                    status.is_failed() || tcp_results.is_failed() || network_results.is_failed(),
+                    now,
+                    &metrics,
+                )
+                .clone(),
+        );
+
+        // Only cycles that got every answer are recorded; a replay has its own fallbacks
+        if let (
+            Some(recording),
+            Fetched::Fresh(status),
+            Fetched::Fresh(tcp_results),
+            Fetched::Fresh(network_results),
+        ) = (recording.as_mut(), &status, &tcp_results, &network_results)
+        {
+            if let Err(e) = recording.record(now, status, tcp_results, network_results) {
+                warn!("Failed to record the cycle: {:#}", e);
+            }
+        }
+
+        // Step 1: Status mappings
+        let Some(status) = status.take(&metrics, "status") else {
+            wait_for_next_scan(clock.as_ref(), &mut shutdown, scan_interval.shortest()).await;
+            continue;
+        };
+
+        // Complete the journaled switches the mappings now confirm (or refute), including
+        // any left in flight by a crash; stale mappings would refute them all
+        if let Some(journal) = journal.as_mut().filter(|_| status_fresh) {
+            match journal.reconcile(&status.mappings) {
+                Ok(unrecorded) => {
+                    for intent in unrecorded {
+                        switch_history.record(SwitchRecord {
+                            ip: intent.ip,
+                            target_wan: intent.target_wan.clone(),
+                            timestamp: intent.timestamp,
+                        });
+                        if let Some(history_db) = &history_db {
+                            let stored = StoredSwitch {
+                                timestamp: intent.timestamp,
+                                ip: intent.ip.to_string(),
+                                from_wan: intent.from_wan.as_ref().map(ToString::to_string),
+                                to_wan: intent.target_wan.to_string(),
+                                reason: intent.reason,
+                                result: "success".to_string(),
+                                error: None,
+                                // Only switches found in effect are recovered
+                                verification: Some("verified".to_string()),
+                            };
+                            if let Err(e) = history_db.insert_switch(&stored) {
+                                error!("Failed to persist switch history: {:#}", e);
+                            }
+                        }
+                    }
+                }
+                Err(e) => error!("Failed to update the switch journal: {:#}", e),
+            }
+        }
+
+        let wan_to_nic = build_wan_to_nic_map(&status.config);
+        let ip_to_nic = build_ip_to_nic_map(&status, &wan_to_nic);
+
+        // Dual-stack devices are balanced under their primary address; the others follow it
+        if let Some(dual_stack) = &config.dual_stack {
+            let now = clock.unix_secs();
+            let due = neighbors_read_at
+                .is_none_or(|read_at| now.saturating_sub(read_at) >= dual_stack.refresh_secs);
+            if due {
+                neighbors_read_at = Some(now);
+                match tokio::task::spawn_blocking(neighbors::read_neighbors).await? {
+                    Ok(table) => neighbor_table = table,
+                    Err(e) => warn!("Failed to read the neighbour tables: {:#}", e),
+                }
+            }
+        }
+        if let Some(dhcp_leases) = dhcp_leases.as_mut() {
+            dhcp_leases.refresh(clock.unix_secs()).await;
+        }
+        let leases = dhcp_leases
+            .as_ref()
+            .map(|dhcp_leases| dhcp_leases.active(clock.unix_secs()))
+            .unwrap_or_default();
+        let controller_clients = controller
+            .as_ref()
+            .map(Controller::snapshot)
+            .unwrap_or_default();
+        let known = |ip| status.mappings.contains_key(&ip);
+        let devices = if config.dual_stack.is_some()
+            && (!controller_clients.is_empty() || !leases.is_empty())
+        {
+            // The controller and the DHCP server also know devices outside this host's
+            // neighbour tables
+            Devices::group(
+                &with_known_addresses(&neighbor_table, &controller_clients, &leases),
+                known,
+            )
+        } else {
+            Devices::group(&neighbor_table, known)
+        };
+        if devices.count() > 0 {
+            debug!(devices = devices.count(), "Grouped dual-stack addresses");
+        }
+        let device_mappings: HashMap<ClientIp, WanId> = status
+            .mappings
+            .iter()
+            .filter(|(ip, _)| devices.primary(**ip) == **ip)
+            .map(|(ip, wan)| (*ip, wan.clone()))
+            .collect();
+        let clients_per_wan = count_clients_per_wan(&device_mappings);
+        if let Some(prober) = &prober {
+            prober.set_interfaces(&wan_to_nic);
+        }
+        if let Some(calibrator) = &calibrator {
+            calibrator.set_interfaces(&wan_to_nic);
+        }
+        metric_source.set_interfaces(&wan_to_nic.values().cloned().collect::<Vec<_>>());
+        let wan_probes = prober.as_ref().map(Prober::snapshot).unwrap_or_default();
+        // Loss and jitter from Prometheus where queried, from the probes otherwise
+        let link_quality = match &link_quality_reader {
+            Some(reader) => {
+                let read =
+                    |results: Option<Result<Vec<PrometheusResult>, MetricsError>>| match results {
+                        Some(Ok(results)) => Some(results),
+                        Some(Err(e)) => {
+                            metrics.record_scrape_error("prometheus");
+                            warn!("{:#}; link quality from the probes only this scan", e);
+                            None
+                        }
+                        None => None,
+                    };
+                let loss_results = read(loss_results);
+                let jitter_results = read(jitter_results);
+                reader.read(
+                    loss_results.as_deref(),
+                    jitter_results.as_deref(),
+                    &wan_to_nic,
+                    &wan_probes,
+                )
+            }
+            None => HashMap::new(),
+        };
+
+        // Step 2: tcp_traffic_scan data
+        // With the metric source gone, the kernel's or the modems' counters keep the NIC
+        // totals (and the kernel's the WAN health) going; no client can be moved for traffic
+        // reasons meanwhile
+        let has_fallback = local_rates.is_some() || snmp.is_some();
+        let tcp_results = match tcp_results {
+            Fetched::Failed(e) if has_fallback => {
+                metrics.record_scrape_error(metric_source.name());
+                warn!("{:#}; falling back on local interface counters", e);
+                None
+            }
+            tcp_results => match tcp_results.take(&metrics, metric_source.name()) {
+                Some(tcp_results) => Some(tcp_results),
+                None => {
+                    wait_for_next_scan(clock.as_ref(), &mut shutdown, scan_interval.shortest())
+                        .await;
+                    continue;
+                }
+            },
+        };
+
+        let bandwidth_local = tcp_results.is_none();
+        let mut nic_stats: HashMap<NicName, NicStats> = HashMap::new();
+
+        // Process TCP bandwidth data (grouped by interface)
+        match tcp_results {
+            Some(tcp_results) => {
+                for result in tcp_results {
+                    if let Some(interface) = result.label::<NicName>("interface") {
+                        let value: f64 = result.value.1.parse().unwrap_or(0.0);
+                        nic_stats.entry(interface).or_default().tcp_bandwidth += value;
+                    }
+                }
+                known_bandwidth = nic_stats
+                    .iter()
+                    .map(|(nic, stats)| (nic.clone(), stats.tcp_bandwidth))
+                    .collect();
+            }
+            None => {
+                for (nic, tcp_bandwidth) in &known_bandwidth {
+                    nic_stats.entry(nic.clone()).or_default().tcp_bandwidth = *tcp_bandwidth;
+                }
+            }
+        }
+        // A recent throughput test beats the scanner's estimate
+        if let Some(calibrator) = &calibrator {
+            for (nic, capacity) in calibrator.capacities() {
+                nic_stats.entry(nic).or_default().tcp_bandwidth = capacity;
+            }
+        }
+
+        // Sample times reveal WANs whose scanner stopped reporting (Prometheus keeps
+        // answering with the last value for a while)
+        if let (Some(failover), Some(timestamp_results)) = (failover.as_mut(), timestamp_results) {
+            // Locally a link that is up counts as sampled now
+            let timestamp_results = match (timestamp_results, &local_rates) {
+                (Err(e), Some(local_rates)) if bandwidth_local => {
+                    debug!("{:#}; judging WAN health by the local link states", e);
+                    Ok(local_sample_times(local_rates, clock.unix_secs()))
+                }
+                (Ok(results), _) => Ok(results
+                    .iter()
+                    .filter_map(|result| {
+                        let interface = result.label("interface")?;
+                        Some((interface, result.value.1.parse().ok()?))
+                    })
+                    .collect()),
+                (Err(e), _) => Err(e),
+            };
+            match timestamp_results {
+                Ok(sample_times) => {
+                    let now = clock.unix_secs();
+                    for change in failover.update(&wan_to_nic, &sample_times, &wan_probes, now) {
+                        if change.up {
+                            info!(wan = %change.wan, nic = %change.nic, reason = %change.reason, "WAN is up");
+                            // Fail-backs to it should not all happen at once
+                            if let Some(soft_start) = soft_start.as_mut() {
+                                soft_start.restart(now);
+                            }
+                        } else {
+                            warn!(wan = %change.wan, nic = %change.nic, reason = %change.reason, "WAN is down");
+                        }
+                        event_bus.emit(Event::WanHealth {
+                            timestamp: now,
+                            wan: change.wan,
+                            nic: change.nic,
+                            up: change.up,
+                            reason: change.reason,
+                        });
+                    }
+                }
+                Err(e) => {
+                    metrics.record_scrape_error(metric_source.name());
+                    warn!("{:#}; WAN health unchanged", e);
+                }
+            }
+        }
+
+        if let Some(public_ip_watcher) = &public_ip_watcher {
+            public_ip_watcher.set_interfaces(&wan_to_nic);
+            let mut found: Vec<_> = public_ip_watcher.snapshot().into_iter().collect();
+            found.sort();
+            let now = clock.unix_secs();
+            for (wan, current) in found {
+                let previous = public_ips.insert(wan.clone(), current);
+                if previous == Some(current) {
+                    continue;
+                }
+                let Some(nic) = wan_to_nic.get(&wan) else {
+                    continue;
+                };
+                match previous {
+                    Some(previous) => {
+                        warn!(wan = %wan, nic = %nic, previous = %previous, current = %current, "Public IP changed")
+                    }
+                    None => info!(wan = %wan, nic = %nic, current = %current, "Public IP found"),
+                }
+                if let Some(history_db) = &history_db {
+                    let change = StoredPublicIpChange {
+                        timestamp: now,
+                        wan: wan.to_string(),
+                        previous: previous.map(|previous| previous.to_string()),
+                        current: current.to_string(),
+                    };
+                    if let Err(e) = history_db.insert_public_ip_change(&change) {
+                        error!("Failed to record public IP change: {:#}", e);
+                    }
+                }
+                event_bus.emit(Event::PublicIpChange {
+                    timestamp: now,
+                    wan,
+                    nic: nic.clone(),
+                    previous,
+                    current,
+                });
+            }
+        }
+
+        // Keep the prober off WANs whose bandwidth is being measured, so probe traffic does
+        // not skew the estimate
+        if let (Some(prober), Some(pause_results)) = (&prober, pause_results) {
+            match pause_results {
+                Ok(results) => prober.set_paused(
+                    results
+                        .iter()
+                        .filter_map(|result| result.label::<NicName>("interface"))
+                        .filter_map(|interface| {
+                            wan_to_nic
+                                .iter()
+                                .find(|(_, nic)| **nic == interface)
+                                .map(|(wan, _)| wan.clone())
+                        })
+                        .collect(),
+                ),
+                Err(e) => {
+                    metrics.record_scrape_error("prometheus");
+                    warn!("{:#}; probe pauses unchanged", e);
+                }
+            }
+        }
+
+        // Step 3: localpacketdump data
+        let mut traffic_local = false;
+        let network_results = match network_results {
+            Fetched::Failed(e) if has_fallback => {
+                metrics.record_scrape_error(metric_source.name());
+                warn!("{:#}; falling back on local interface counters", e);
+                traffic_local = true;
+                Vec::new()
+            }
+            network_results => match network_results.take(&metrics, metric_source.name()) {
+                Some(network_results) => network_results,
+                None => {
+                    wait_for_next_scan(clock.as_ref(), &mut shutdown, scan_interval.shortest())
+                        .await;
+                    continue;
+                }
+            },
+        };
+        // Both backends have answered
+        if let Some(systemd) = systemd.as_mut() {
+            systemd.ready();
+        }
+
+        // Process network data (aggregate by NIC using IP mappings)
+        let mut ip_traffic: HashMap<ClientIp, IpTraffic> = HashMap::new();
+        let mut destination_traffic: HashMap<(ClientIp, IpAddr), DestinationTraffic> =
+            HashMap::new();
+        for result in &network_results {
+            if let (Some(metric_name), Some(ip)) = (
+                result.metric.get("__name__"),
+                result.label::<ClientIp>("ip_address"),
+            ) {
+                if let Some(nic) = ip_to_nic.get(&ip) {
+                    let value: f64 = result.value.1.parse().unwrap_or(0.0);
+
+                    // The address's own NIC carries it, but it counts toward its device
+                    let device = devices.primary(ip);
+                    let stats = nic_stats.entry(nic.clone()).or_default();
+                    let traffic = ip_traffic.entry(device).or_insert_with(|| IpTraffic {
+                        ip: device,
+                        nic: ip_to_nic.get(&device).unwrap_or(nic).clone(),
+                        rx_bps: 0.0,
+                        tx_bps: 0.0,
+                    });
+
+                    if metric_name == "network_ip_tx_bps" {
+                        stats.tx_bps += value;
+                        traffic.tx_bps += value;
+                    } else if metric_name == "network_ip_rx_bps" {
+                        stats.rx_bps += value;
+                        traffic.rx_bps += value;
+                    }
+
+                    // Series split by remote address additionally feed destination attribution
+                    if let (Some(enricher), Some(destination)) = (
+                        destination_enricher.as_mut(),
+                        config.destinations.as_ref().and_then(|destinations| {
+                            result
+                                .metric
+                                .get(&destinations.label)?
+                                .parse::<IpAddr>()
+                                .ok()
+                        }),
+                    ) {
+                        let flow = destination_traffic
+                            .entry((device, destination))
+                            .or_insert_with(|| DestinationTraffic {
+                                client_ip: device,
+                                nic: nic.clone(),
+                                destination,
+                                info: enricher.lookup(destination),
+                                rx_bps: 0.0,
+                                tx_bps: 0.0,
+                            });
+                        if metric_name == "network_ip_tx_bps" {
+                            flow.tx_bps += value;
+                        } else if metric_name == "network_ip_rx_bps" {
+                            flow.rx_bps += value;
+                        }
+                    }
+                }
+            }
+        }
+        let mut ip_traffic: Vec<IpTraffic> = ip_traffic.into_values().collect();
+        if let (true, Some(local_rates)) = (traffic_local, &local_rates) {
+            // No client traffic to add up, but the NICs' own counters have the totals
+            for nic in wan_to_nic.values() {
+                if let Some(rates) = local_rates.get(nic) {
+                    let stats = nic_stats.entry(nic.clone()).or_default();
+                    stats.rx_bps = rates.rx_bps;
+                    stats.tx_bps = rates.tx_bps;
+                }
+            }
+        }
+        if let (Some(snmp), Some(snmp_config)) = (&snmp, &config.snmp) {
+            if traffic_local || snmp_config.mode == SnmpMode::Replace {
+                for (wan, totals) in snmp.snapshot() {
+                    if let Some(nic) = wan_to_nic.get(&wan) {
+                        let stats = nic_stats.entry(nic.clone()).or_default();
+                        stats.rx_bps = totals.rx_bps;
+                        stats.tx_bps = totals.tx_bps;
+                    }
+                }
+            }
+        }
+        if let Some(smoother) = smoother.as_mut() {
+            smoother.apply(clock.now(), &mut nic_stats, &mut ip_traffic);
+        }
+
+        // How the clients' own flows are doing, per client and as a median per WAN
+        let client_experience = match (&passive_rtt, experience_results) {
+            (Some(passive_rtt), Some(Ok(results))) => passive_rtt.parse(&results, &devices),
+            (_, Some(Err(e))) => {
+                metrics.record_scrape_error("prometheus");
+                warn!("{:#}; no passive RTT readings this scan", e);
+                HashMap::new()
+            }
+            _ => HashMap::new(),
+        };
+        let wan_rtts = PassiveRtt::wan_rtts(&client_experience, &device_mappings);
+
+        // How much of its cap each metered WAN has used this billing period
+        let data_cap_usage = match data_caps.as_mut() {
+            Some(data_caps) => {
+                let now = clock.local();
+                if let Err(e) = data_caps.update(&now, data_cap_results, &wan_to_nic, &nic_stats) {
+                    metrics.record_scrape_error("prometheus");
+                    warn!("{:#}; data cap usage not refreshed this scan", e);
+                }
+                data_caps.usage(&now)
+            }
+            None => HashMap::new(),
+        };
+
+        // What the clients' traffic is, by the ports it uses
+        let app_classes = match (&app_classifier, app_results) {
+            (Some(app_classifier), Some(Ok(results))) => app_classifier.classify(&results),
+            (_, Some(Err(e))) => {
+                metrics.record_scrape_error("prometheus");
+                warn!("{:#}; no application classes this scan", e);
+                HashMap::new()
+            }
+            _ => HashMap::new(),
+        };
+
+        // Queue buildup on the router marks a NIC congested even while its byte counters
+        // look acceptable
+        let mut queue_states: HashMap<NicName, QueueState> = HashMap::new();
+        if let (Some(queue_monitor), Some(qos)) = (queue_monitor.as_mut(), qos) {
+            match qos {
+                Ok(response) => queue_states = queue_monitor.update(clock.now(), response),
+                Err(e) => {
+                    metrics.record_scrape_error("qos");
+                    warn!("{:#}; treating queues as idle", e);
+                }
+            }
+        }
+        for (nic, stats) in nic_stats.iter_mut() {
+            let queue = queue_states.get(nic);
+            stats.queue_congested = queue.is_some_and(|queue| queue.congested);
+            if !stats.queue_congested {
+                if congested_nics.remove(nic) {
+                    info!(nic = %nic, "Queue drained");
+                }
+            } else if congested_nics.insert(nic.clone()) {
+                let queue = queue.unwrap();
+                warn!(
+                    nic = %nic,
+                    backlog_packets = queue.backlog_packets,
+                    drops_per_sec = queue.drops_per_sec,
+                    "Queue building up; treating WAN as full"
+                );
+            }
+        }
+        let destination_traffic: Vec<DestinationTraffic> =
+            destination_traffic.into_values().collect();
+
+        // Measure how evenly the WANs are loaded before any switches this cycle
+        let wan_samples: HashMap<WanId, (f64, f64)> = wan_to_nic
+            .iter()
+            .filter_map(|(wan, nic)| {
+                nic_stats.get(nic).map(|stats| {
+                    (
+                        wan.clone(),
+                        (stats.tx_bps + stats.rx_bps, stats.tcp_bandwidth),
+                    )
+                })
+            })
+            .collect();
+        let fairness = fairness::compute(&wan_samples);
+
+        let mut wan_statuses: Vec<WanStatus> = wan_to_nic
+            .iter()
+            .map(|(wan, nic)| {
+                let stats = nic_stats.get(nic).cloned().unwrap_or_default();
+                let traffic_bps = stats.tx_bps + stats.rx_bps;
+                let probe = wan_probes.get(wan);
+                WanStatus {
+                    wan: wan.clone(),
+                    nic: nic.clone(),
+                    health: if probe.is_some_and(WanProbeStats::is_down)
+                        || failover
+                            .as_ref()
+                            .is_some_and(|failover| failover.is_down(wan))
+                    {
+                        "down"
+                    } else if probe.is_some_and(WanProbeStats::is_degraded) {
+                        "degraded"
+                    } else if stats.tcp_bandwidth > 0.0 {
+                        "ok"
+                    } else {
+                        "no_data"
+                    },
+                    utilization: (stats.tcp_bandwidth > 0.0)
+                        .then(|| traffic_bps / stats.tcp_bandwidth),
+                    tcp_bandwidth_bps: stats.tcp_bandwidth,
+                    traffic_bps,
+                    clients: clients_per_wan.get(wan).copied().unwrap_or(0),
+                    rtt_ms: probe.and_then(|probe| probe.rtt_ms),
+                }
+            })
+            .collect();
+        wan_statuses.sort_by(|a, b| a.wan.cmp(&b.wan));
+        status_board.update(wan_statuses.clone());
+        if let Some(hints) = &hints {
+            if let Err(e) = hints.update(
+                clock.unix_secs(),
+                &wan_statuses,
+                &nic_stats,
+                &config.wan_client_caps,
+            ) {
+                warn!("{:#}", e);
+            }
+        }
+
+        let mut nics: Vec<_> = nic_stats.keys().collect();
+        nics.sort();
+        // As many per NIC as the top_rx policy may move
+        let top_ips: Vec<TopIpReport> = nics
+            .iter()
+            .flat_map(|nic| {
+                let mut clients: Vec<&IpTraffic> = ip_traffic
+                    .iter()
+                    .filter(|traffic| &traffic.nic == *nic)
+                    .collect();
+                clients.sort_by(|a, b| {
+                    b.rx_bps
+                        .partial_cmp(&a.rx_bps)
+                        .unwrap_or(std::cmp::Ordering::Equal)
+                });
+                clients
+                    .into_iter()
+                    .take(config.top_rx.moves_per_nic.max(1))
+                    .map(|traffic| {
+                        TopIpReport::new(
+                            traffic,
+                            client_experience.get(&traffic.ip),
+                            app_classes.get(&traffic.ip),
+                        )
+                    })
+            })
+            .collect();
+        let nics: Vec<BandwidthComparison> = nics
+            .into_iter()
+            .map(|nic| {
+                let quality = wan_to_nic
+                    .iter()
+                    .find(|(_, wan_nic)| *wan_nic == nic)
+                    .and_then(|(wan, _)| link_quality.get(wan));
+                BandwidthComparison::new(nic, &nic_stats[nic], queue_states.get(nic), quality)
+            })
+            .collect();
+
+        // A policy selected at runtime shadows the active one before it takes control
+        let now = clock.unix_secs();
+        if let Some(change) = control.take_policy_request() {
+            info!(
+                policy = change.policy.name(),
+                requested_by = %change.requested_by,
+                shadow_secs = control.shadow_secs,
+                "Shadowing requested policy"
+            );
+            event_bus.emit(Event::PolicyChange {
+                timestamp: now,
+                policy: change.policy.name().to_string(),
+                previous: switch_policy.name().to_string(),
+                requested_by: change.requested_by.clone(),
+                active: false,
+            });
+            shadow = Some((change, now));
+        }
+        if shadow
+            .as_ref()
+            .is_some_and(|(_, since)| now.saturating_sub(*since) >= control.shadow_secs)
+        {
+            let (change, _) = shadow.take().unwrap();
+            info!(
+                policy = change.policy.name(),
+                previous = switch_policy.name(),
+                requested_by = %change.requested_by,
+                "Policy took control"
+            );
+            event_bus.emit(Event::PolicyChange {
+                timestamp: now,
+                policy: change.policy.name().to_string(),
+                previous: switch_policy.name().to_string(),
+                requested_by: change.requested_by,
+                active: true,
+            });
+            switch_policy = change.policy;
+            if let Some(soft_start) = soft_start.as_mut() {
+                soft_start.restart(now);
+            }
+        }
+
+        for pending in control.take_pin_requests() {
+            let ip = pending.request.ip;
+            match pending.request.wan {
+                Some(wan) => {
+                    if !status.mappings.contains_key(&ip) {
+                        warn!(ip = %ip, "Manually pinned client has no mapping yet");
+                    }
+                    info!(
+                        ip = %ip,
+                        wan = %wan,
+                        requested_by = %pending.requested_by,
+                        duration_secs = pending.request.duration_secs,
+                        "Pinned client manually"
+                    );
+                    manual_pins.insert(
+                        ip,
+                        ManualPin {
+                            ip,
+                            wan,
+                            requested_by: pending.requested_by,
+                            expires_at: now.saturating_add(pending.request.duration_secs),
+                        },
+                    );
+                }
+                None => {
+                    if manual_pins.remove(&ip).is_some() {
+                        info!(ip = %ip, requested_by = %pending.requested_by, "Manual pin released");
+                    }
+                }
+            }
+        }
+        manual_pins.retain(|ip, pin| {
+            let active = pin.expires_at > now;
+            if !active {
+                info!(ip = %ip, wan = %pin.wan, "Manual pin expired");
+            }
+            active
+        });
+        client_rules.set_manual_pins(
+            manual_pins
+                .values()
+                .map(|pin| (pin.ip, pin.wan.clone(), pin.requested_by.clone())),
+        );
+        control.set_manual_pins(manual_pins.values().cloned().collect());
+        if let Some(pending) = control.take_pause_request() {
+            match pending.change {
+                PauseChange::Pause(request) => {
+                    info!(
+                        requested_by = %pending.requested_by,
+                        duration_secs = ?request.duration_secs,
+                        reason = ?request.reason,
+                        "Balancing paused"
+                    );
+                    pause = Some(PauseStatus {
+                        requested_by: pending.requested_by,
+                        reason: request.reason,
+                        since: now,
+                        until: request
+                            .duration_secs
+                            .map(|duration_secs| now.saturating_add(duration_secs)),
+                    });
+                }
+                PauseChange::Resume => {
+                    if pause.take().is_some() {
+                        info!(requested_by = %pending.requested_by, "Balancing resumed");
+                    }
+                }
+            }
+        }
+        if pause
+            .as_ref()
+            .and_then(|pause| pause.until)
+            .is_some_and(|until| until <= now)
+        {
+            info!("Pause expired; balancing resumes");
+            pause = None;
+        }
+        control.set_pause_status(pause.clone());
+        let maintenance_window = maintenance.update(&clock.local()).cloned();
+        control.set_maintenance(maintenance_window.clone());
+        for (rule, in_force) in client_rules.set_time(&clock.local()) {
+            if in_force {
+                info!(rule = %rule, "Scheduled client rule in force");
+            } else {
+                info!(rule = %rule, "Scheduled client rule no longer in force");
+            }
+        }
+        // Later sources win: the leases are the DHCP server's own record
+        client_rules.set_macs(
+            neighbor_table
+                .iter()
+                .flat_map(|(mac, addresses)| addresses.iter().map(move |ip| (*ip, mac.clone())))
+                .chain(controller_clients.values().flat_map(|client| {
+                    client
+                        .addresses
+                        .iter()
+                        .map(move |ip| (*ip, client.mac.clone()))
+                }))
+                .chain(
+                    leases
+                        .iter()
+                        .filter_map(|(ip, lease)| Some((*ip, lease.mac.clone()?))),
+                ),
+        );
+        client_rules.set_app_classes(app_classes);
+        client_rules.set_metadata(
+            controller_clients
+                .iter()
+                .map(|(ip, client)| (*ip, client.device_type.as_deref(), client.vendor.as_deref())),
+        );
+        control.set_policy_status(PolicyStatus {
+            active: switch_policy.name().to_string(),
+            shadow: shadow.as_ref().map(|(change, since)| ShadowStatus {
+                policy: change.policy.name().to_string(),
+                requested_by: change.requested_by.clone(),
+                remaining_secs: (since + control.shadow_secs).saturating_sub(now),
+            }),
+        });
+
+        let active_reservations =
+            reservations.evaluate(&ip_traffic, &status.mappings, &clock.local());
+        let now_open: HashSet<String> = active_reservations
+            .active
+            .iter()
+            .map(|reservation| reservation.config.name.clone())
+            .collect();
+        for reservation in &active_reservations.active {
+            if !open_reservations.contains(&reservation.config.name) {
+                info!(
+                    reservation = %reservation.config.name,
+                    wan = %reservation.config.wan,
+                    mbps = reservation.config.mbps,
+                    window = %reservation.config.schedule,
+                    "Reservation window opened"
+                );
+            }
+        }
+        for name in open_reservations.difference(&now_open) {
+            info!(reservation = %name, "Reservation window closed");
+        }
+        open_reservations = now_open;
+
+        // Step 4: Let the switching policy plan this cycle's moves
+        let decision_started = clock.now();
+        // Traffic trending past a NIC's capacity counts as already there
+        let projected_stats = forecaster
+            .as_mut()
+            .map(|forecaster| forecaster.project(clock.unix_secs(), &nic_stats));
+        let policy_input = PolicyInput {
+            nic_stats: projected_stats.as_ref().unwrap_or(&nic_stats),
+            ip_traffic: &ip_traffic,
+            wan_to_nic: &wan_to_nic,
+            mappings: &device_mappings,
+            clients_per_wan: &clients_per_wan,
+            destinations: &destination_traffic,
+            wan_probes: &wan_probes,
+            link_quality: &link_quality,
+            reservations: &active_reservations,
+            client_rules: &client_rules,
+            config: &config,
+        };
+        let mut plan = switch_policy.plan(&policy_input);
+        if let Some((change, _)) = shadow.as_mut() {
+            for decision in change.policy.plan(&policy_input).switches {
+                info!(
+                    policy = change.policy.name(),
+                    ip = %decision.ip,
+                    target_wan = %decision.target_wan,
+                    reason = %decision.reason,
+                    "Shadow policy would switch"
+                );
+            }
+        }
+        if let Some(hysteresis) = hysteresis.as_mut() {
+            plan = hysteresis.filter(plan, &policy_input);
+        }
+        if let Some(speedtest) = speedtest.as_mut() {
+            // The rebalancing waits for a running speed test; rules and failover do not
+            let burst = speedtest.check(&policy_input, clock.unix_secs());
+            match (burst, speedtest_client) {
+                (Some(burst), None) => {
+                    info!(ip = %burst.ip, nic = %burst.nic, reason = %burst.reason, "Speed test detected; holding back rebalancing")
+                }
+                (None, Some(ip)) => info!(ip = %ip, "Speed test over; rebalancing resumes"),
+                _ => {}
+            }
+            speedtest_client = burst.map(|burst| burst.ip);
+            if let Some(burst) = burst {
+                let reason = format!("speed test in progress ({})", burst.reason);
+                plan.skipped
+                    .extend(plan.switches.drain(..).map(|decision| SkippedCandidate {
+                        reason: reason.clone(),
+                        ip: decision.ip,
+                        nic: decision.from_nic,
+                    }));
+            }
+        }
+        if let Some(passive_rtt) = &passive_rtt {
+            plan = passive_rtt.filter(plan, &client_experience, &wan_rtts);
+        }
+        if let Some(data_caps) = &data_caps {
+            plan = data_caps.filter(plan, &data_cap_usage, &ip_traffic);
+        }
+        // Policy moves are watched and reverted if they do not help; the policies then leave
+        // the client alone for a while
+        let mut rolling_back = HashSet::new();
+        let mut policy_moves: HashSet<(ClientIp, WanId)> = HashSet::new();
+        if let Some(rollbacks) = rollbacks.as_mut() {
+            let now = clock.unix_secs();
+            plan = rollbacks.filter(plan, now);
+            policy_moves.extend(
+                plan.switches
+                    .iter()
+                    .map(|decision| (decision.ip, decision.target_wan.clone())),
+            );
+            let reverts = rollbacks.plan(&policy_input, now);
+            plan.switches
+                .retain(|decision| !reverts.iter().any(|revert| revert.ip == decision.ip));
+            rolling_back.extend(reverts.iter().map(|revert| revert.ip));
+            plan.switches.splice(0..0, reverts);
+        }
+        if let Some(initial_placement) = initial_placement.as_mut() {
+            // First-sight placements take precedence over reactive moves of the same IP
+            let placements = initial_placement.plan(&policy_input);
+            plan.switches
+                .retain(|decision| !placements.iter().any(|placed| placed.ip == decision.ip));
+            plan.switches.splice(0..0, placements);
+        }
+        if let Some(destination_rules) = &destination_rules {
+            // Clients pinned by a destination rule only ever move to their rule's WAN
+            let pinned = destination_rules.plan(&policy_input);
+            plan.switches.retain(|decision| {
+                pinned
+                    .pinned
+                    .get(&decision.ip)
+                    .is_none_or(|wan| *wan == decision.target_wan)
+                    && !pinned
+                        .switches
+                        .iter()
+                        .any(|forced| forced.ip == decision.ip)
+            });
+            plan.switches.splice(0..0, pinned.switches);
+        }
+        // Without client traffic every client would look under its quota
+        if let Some(quotas) = quotas.as_mut().filter(|_| !traffic_local) {
+            // Clients over a quota only ever move to its bulk WAN
+            let now = clock.unix_secs();
+            let steering = quotas.plan(&policy_input, now);
+            for change in steering.changes {
+                let subject = change
+                    .ip
+                    .map_or_else(|| "group".to_string(), |ip| ip.to_string());
+                if change.exceeded {
+                    warn!(quota = %change.quota, client = %subject, traffic_mbps = change.traffic_bps / 1_000_000.0, bulk_wan = %change.bulk_wan, "Routinely over quota; sending to the bulk WAN");
+                } else {
+                    info!(quota = %change.quota, client = %subject, "Back under quota");
+                }
+                event_bus.emit(Event::Quota {
+                    timestamp: now,
+                    quota: change.quota,
+                    ip: change.ip,
+                    exceeded: change.exceeded,
+                    traffic_bps: change.traffic_bps,
+                    quota_bps: change.quota_bps,
+                    bulk_wan: change.bulk_wan,
+                });
+            }
+            plan.switches.retain(|decision| {
+                steering
+                    .steered
+                    .get(&decision.ip)
+                    .is_none_or(|wan| *wan == decision.target_wan)
+                    && !steering
+                        .switches
+                        .iter()
+                        .any(|forced| forced.ip == decision.ip)
+            });
+            plan.switches.splice(0..0, steering.switches);
+        }
+        if let Some(anomaly_detector) = anomaly_detector.as_mut().filter(|_| !traffic_local) {
+            // Quarantined clients only ever move to the quarantine WAN
+            let now = clock.unix_secs();
+            let quarantine = anomaly_detector.plan(&policy_input, now);
+            let quarantine_wan = config
+                .anomaly
+                .as_ref()
+                .and_then(|anomaly| anomaly.quarantine_wan.clone());
+            for change in quarantine.changes {
+                if change.anomalous {
+                    warn!(client = %change.ip, traffic_mbps = change.traffic_bps / 1_000_000.0, baseline_mbps = change.baseline_bps / 1_000_000.0, z_score = change.z_score, "Anomalous client traffic");
+                } else {
+                    info!(client = %change.ip, "Client traffic back to normal");
+                }
+                event_bus.emit(Event::Anomaly {
+                    timestamp: now,
+                    ip: change.ip,
+                    anomalous: change.anomalous,
+                    traffic_bps: change.traffic_bps,
+                    baseline_bps: change.baseline_bps,
+                    z_score: change.z_score,
+                    quarantine_wan: quarantine_wan.clone(),
+                });
+            }
+            plan.switches.retain(|decision| {
+                quarantine
+                    .quarantined
+                    .get(&decision.ip)
+                    .is_none_or(|wan| *wan == decision.target_wan)
+                    && !quarantine
+                        .switches
+                        .iter()
+                        .any(|forced| forced.ip == decision.ip)
+            });
+            plan.switches.splice(0..0, quarantine.switches);
+        }
+        // Pinned clients that ended up elsewhere go back to their WAN, and realtime ones go to
+        // the WAN with the lowest RTT
+        let returns = client_rules.plan(&policy_input);
+        plan.switches
+            .retain(|decision| !returns.iter().any(|pinned| pinned.ip == decision.ip));
+        plan.switches.splice(0..0, returns);
+        // The other addresses of a device that were left behind when it moved, e.g. held
+        // back by the rate limit, catch up with it
+        let mut strays = Vec::new();
+        for (&ip, wan) in &status.mappings {
+            let primary = devices.primary(ip);
+            let (Some(primary_wan), Some(from_nic)) =
+                (status.mappings.get(&primary), wan_to_nic.get(wan))
+            else {
+                continue;
+            };
+            if primary == ip || primary_wan == wan {
+                continue;
+            }
+            strays.push(SwitchDecision {
+                ip,
+                from_nic: from_nic.clone(),
+                target_wan: primary_wan.clone(),
+                rx_bps: 0.0,
+                reason: format!("same device as {}", primary),
+            });
+        }
+        plan.switches
+            .retain(|decision| !strays.iter().any(|stray| stray.ip == decision.ip));
+        plan.switches.extend(strays);
+        let mut evacuating = HashSet::new();
+        if let Some(failover) = failover.as_mut() {
+            // Nothing moves onto a dead WAN, and clients on one leave it before anything else
+            let now = clock.unix_secs();
+            let moves = failover.plan(&policy_input, now);
+            let (dead_targets, switches): (Vec<_>, Vec<_>) = plan
+                .switches
+                .into_iter()
+                .filter(|decision| {
+                    !moves
+                        .evacuations
+                        .iter()
+                        .chain(&moves.failbacks)
+                        .any(|forced| forced.ip == decision.ip)
+                })
+                .partition(|decision| failover.is_down(&decision.target_wan));
+            plan.skipped
+                .extend(dead_targets.into_iter().map(|decision| SkippedCandidate {
+                    reason: format!("target {} is down", decision.target_wan),
+                    ip: decision.ip,
+                    nic: decision.from_nic,
+                }));
+            evacuating.extend(moves.evacuations.iter().map(|decision| decision.ip));
+            plan.switches = moves
+                .evacuations
+                .into_iter()
+                .chain(moves.failbacks)
+                .chain(switches)
+                .collect();
+        }
+        // Nothing else may move a pinned or excluded client
+        plan = client_rules.filter(plan, &policy_input, &evacuating);
+        if let Some(pause) = &pause {
+            // Failover included: the operator asked for nothing to move
+            let reason = match &pause.reason {
+                Some(reason) => format!("balancing paused by {} ({})", pause.requested_by, reason),
+                None => format!("balancing paused by {}", pause.requested_by),
+            };
+            plan.skipped
+                .extend(plan.switches.drain(..).map(|decision| SkippedCandidate {
+                    reason: reason.clone(),
+                    ip: decision.ip,
+                    nic: decision.from_nic,
+                }));
+        }
+        if let Some(window) = &maintenance_window {
+            let reason = format!("maintenance window {}", window.name);
+            plan.skipped
+                .extend(plan.switches.drain(..).map(|decision| SkippedCandidate {
+                    reason: reason.clone(),
+                    ip: decision.ip,
+                    nic: decision.from_nic,
+                }));
+        }
+        // One-off moves requested over the API go ahead even while paused or in maintenance
+        let requested: Vec<SwitchDecision> = control
+            .take_switch_requests()
+            .into_iter()
+            .filter_map(|pending| {
+                let ip = pending.request.ip;
+                let wan = pending.request.wan;
+                let Some(current_wan) = device_mappings.get(&ip) else {
+                    warn!(ip = %ip, "Requested switch of a client without a mapping; ignoring it");
+                    return None;
+                };
+                if !wan_to_nic.contains_key(&wan) {
+                    warn!(ip = %ip, wan = %wan, "Requested switch to an unknown WAN; ignoring it");
+                    return None;
+                }
+                if *current_wan == wan {
+                    info!(ip = %ip, wan = %wan, "Requested switch: the client is already there");
+                    return None;
+                }
+                Some(SwitchDecision {
+                    ip,
+                    from_nic: wan_to_nic.get(current_wan)?.clone(),
+                    target_wan: wan,
+                    rx_bps: ip_traffic
+                        .iter()
+                        .find(|traffic| traffic.ip == ip)
+                        .map_or(0.0, |traffic| traffic.rx_bps),
+                    reason: format!("manual switch requested by {}", pending.requested_by),
+                })
+            })
+            .collect();
+        let requested_ips: HashSet<ClientIp> =
+            requested.iter().map(|decision| decision.ip).collect();
+        plan.switches
+            .retain(|decision| !requested_ips.contains(&decision.ip));
+        plan.switches.splice(0..0, requested);
+        metrics.record_decision_latency(clock.since(decision_started));
+
+        if let Some(remote_writer) = remote_writer.as_mut() {
+            let timestamp_ms = clock.unix_millis();
+            remote_writer.push(&DerivedInput {
+                timestamp_ms,
+                nic_stats: &nic_stats,
+                wan_to_nic: &wan_to_nic,
+                ip_traffic: &ip_traffic,
+                policy_name: switch_policy.name(),
+                policy_scores: &switch_policy.wan_scores(&policy_input),
+            });
+        }
+
+        // Get current timestamp for checking recent switches
+        let now = clock.unix_secs();
+        cooldowns.observe(&ip_traffic, now);
+        let audit_snapshot = audit_log.as_ref().map(|audit_log| {
+            audit_log.snapshot(
+                switch_policy.as_ref(),
+                &policy_input,
+                plan.switches
+                    .iter()
+                    .map(|decision| decision.ip)
+                    .chain(plan.skipped.iter().map(|skipped| skipped.ip)),
+                &cooldowns,
+                &switch_history,
+                now,
+            )
+        });
+
+        let mut nic_summaries: Vec<NicSummary> = nic_stats
+            .iter()
+            .map(|(nic, stats)| NicSummary {
+                nic: nic.clone(),
+                wan: wan_to_nic
+                    .iter()
+                    .find(|(_, wan_nic)| *wan_nic == nic)
+                    .map(|(wan, _)| wan.clone()),
+                tcp_bandwidth_bps: stats.tcp_bandwidth,
+                tx_bps: stats.tx_bps,
+                rx_bps: stats.rx_bps,
+            })
+            .collect();
+        nic_summaries.sort_by(|a, b| a.nic.cmp(&b.nic));
+        for summary in &nic_summaries {
+            let traffic_bps = summary.tx_bps + summary.rx_bps;
+            let exceeded =
+                summary.tcp_bandwidth_bps > 0.0 && traffic_bps > summary.tcp_bandwidth_bps;
+            if !exceeded {
+                exceeded_nics.remove(&summary.nic);
+            } else if exceeded_nics.insert(summary.nic.clone()) {
+                warn!(
+                    nic = %summary.nic,
+                    traffic_mbps = traffic_bps / 1_000_000.0,
+                    tcp_bandwidth_mbps = summary.tcp_bandwidth_bps / 1_000_000.0,
+                    "Traffic exceeds TCP bandwidth estimate"
+                );
+                event_bus.emit(Event::BandwidthExceeded {
+                    timestamp: now,
+                    nic: summary.nic.clone(),
+                    wan: summary.wan.clone(),
+                    tcp_bandwidth_bps: summary.tcp_bandwidth_bps,
+                    traffic_bps,
+                });
+            }
+        }
+        for event in alerts.overloads(now, &nic_summaries) {
+            if let Event::Overload {
+                nic,
+                sustained: true,
+                since,
+                ..
+            } = &event
+            {
+                warn!(nic = %nic, secs = now - since, "NIC overloaded");
+            }
+            event_bus.emit(event);
+        }
+        event_bus.emit(Event::TrafficSummary {
+            timestamp: now,
+            nics: nic_summaries,
+            jain_index: fairness.as_ref().map(|metrics| metrics.jain_index),
+        });
+
+        let mut hostnames = reverse_dns
+            .as_ref()
+            .map(|reverse_dns| reverse_dns.names(status.mappings.keys().copied()))
+            .unwrap_or_default();
+        // The name a client gave the DHCP server goes before its PTR record
+        hostnames.extend(
+            leases
+                .iter()
+                .filter(|(ip, _)| status.mappings.contains_key(ip))
+                .filter_map(|(ip, lease)| Some((*ip, lease.hostname.clone()?))),
+        );
+        let mut decisions = Vec::new();
+        for skipped in &plan.skipped {
+            info!(
+                ip = %hostnames.label(skipped.ip),
+                nic = %skipped.nic,
+                reason = %skipped.reason,
+                "Skipping switch candidate"
+            );
+            metrics.record_skip("policy");
+            event_bus.emit(Event::SwitchSkipped {
+                timestamp: now,
+                ip: skipped.ip,
+                reason: skipped.reason.clone(),
+            });
+            decisions.push(DecisionReport {
+                ip: skipped.ip,
+                nic: skipped.nic.clone(),
+                target_wan: None,
+                rx_bps: None,
+                reason: skipped.reason.clone(),
+                outcome: DecisionOutcome::Skipped,
+            });
+        }
+
+        // Per-flow rates, only when there is something to move
+        if let Some(flow_steering) = flow_steering.as_mut().filter(|_| !plan.switches.is_empty()) {
+            let query = flow_steering.query();
+            match retry::with_backoff(&config.retry, "Prometheus query", || {
+                prometheus.query(&query)
+            })
+            .await
+            {
+                Ok(results) => flow_steering.update(Some(&results)),
+                Err(e) => {
+                    warn!("Failed to query flow rates: {}; moving whole clients", e);
+                    flow_steering.update(None);
+                }
+            }
+        }
+
+        // Evacuations from a dead WAN cannot wait, an operator's manual pin or switch
+        // applies at once, and an ineffective move is undone without delay
+        let is_urgent = |ip: &ClientIp| {
+            evacuating.contains(ip)
+                || client_rules.is_manually_pinned(*ip)
+                || requested_ips.contains(ip)
+                || rolling_back.contains(ip)
+        };
+        if switch_rate_limit.is_some() {
+            // The moves that cannot wait go first when the rate limit holds some back
+            plan.switches
+                .sort_by_key(|decision| !is_urgent(&decision.ip));
+        }
+
+        // Switches the routing service accepted this cycle, for verification
+        let mut accepted: Vec<AcceptedSwitch> = Vec::new();
+        // The other addresses of a device that switched follow it through the same checks,
+        // as urgently as it moved
+        let mut queue: VecDeque<SwitchDecision> = std::mem::take(&mut plan.switches).into();
+        let mut urgent_aliases: HashSet<ClientIp> = HashSet::new();
+        'decisions: while let Some(decision) = queue.pop_front() {
+            let decision = &decision;
+            let ip = decision.ip;
+            let target_wan = &decision.target_wan;
+            let urgent = is_urgent(&ip) || urgent_aliases.contains(&ip);
+
+            if let Some(skipped) = check_gates(
+                decision,
+                urgent,
+                requested_ips.contains(&ip),
+                now,
+                &cooldowns,
+                &switch_history,
+                connection_guard.as_mut(),
+                mode,
+                &mut switch_breaker,
+                soft_start.as_mut(),
+                switch_rate_limit.as_mut(),
+                &routing,
+                config.routing_service.validate_path.as_deref(),
+                &hostnames,
+            )
+            .await
+            {
+                skip(decision, skipped, now, &metrics, &event_bus, &mut decisions);
+                continue;
+            }
+
+            info!(
+                ip = %hostnames.label(ip),
+                from_nic = %decision.from_nic,
+                target_wan = %target_wan,
+                rx_mbps = decision.rx_bps / 1_000_000.0,
+                reason = %decision.reason,
+                "Switching"
+            );
+
+            // Moving the heaviest flows relieves the NIC and leaves the client's mapping, and
+            // the rest of its connections, alone; evacuations and failed flow moves take the
+            // whole client, and undoing a flow move moves those flows back
+            let flow_path = flow_steering
+                .as_ref()
+                .map(|flow_steering| flow_steering.switch_path().to_string());
+            let mut flows: Vec<Flow> = match (
+                &flow_path,
+                rollbacks
+                    .as_ref()
+                    .and_then(|rollbacks| rollbacks.reverted_flows(ip)),
+            ) {
+                (None, _) => Vec::new(),
+                (Some(_), Some(reverted)) => reverted.to_vec(),
+                (Some(_), None) => flow_steering
+                    .as_ref()
+                    .filter(|_| !urgent)
+                    .map(|flow_steering| flow_steering.heaviest(ip).to_vec())
+                    .unwrap_or_default(),
+            };
+
+            let mut counted = false;
+            let (journal_id, error, reason) = loop {
+                let moved: Vec<String> = flows.iter().map(ToString::to_string).collect();
+                let reason = if moved.is_empty() {
+                    decision.reason.clone()
+                } else {
+                    format!("{} (flows {})", decision.reason, moved.join(", "))
+                };
+
+                // Journal the switch before issuing it; without a durable record a crash could
+                // leave it half-done and unknown
+                let journal_id = match journal.as_mut().map(|journal| {
+                    journal.begin(
+                        now,
+                        ip,
+                        status.mappings.get(&ip).cloned(),
+                        target_wan.clone(),
+                        reason.clone(),
+                        moved.clone(),
+                    )
+                }) {
+                    None => None,
+                    Some(Ok(id)) => Some(id),
+                    Some(Err(e)) => {
+                        error!(ip = %ip, "Skipping switch, journaling failed: {:#}", e);
+                        metrics.record_skip("journal");
+                        decisions.push(DecisionReport {
+                            ip,
+                            nic: decision.from_nic.clone(),
+                            target_wan: Some(target_wan.clone()),
+                            rx_bps: Some(decision.rx_bps),
+                            reason: decision.reason.clone(),
+                            outcome: DecisionOutcome::Held {
+                                remaining_secs: 0,
+                                hold_reason: "switch journal unavailable".to_string(),
+                            },
+                        });
+                        continue 'decisions;
+                    }
+                };
+
+                if !counted {
+                    if let Some(soft_start) = soft_start.as_mut() {
+                        soft_start.record(now);
+                    }
+                    if let Some(rate_limit) = switch_rate_limit.as_mut() {
+                        rate_limit.record(now);
+                    }
+                    counted = true;
+                }
+
+                if let (false, Some(path)) = (flows.is_empty(), &flow_path) {
+                    match switch_flows(&routing, path, ip, &flows, target_wan).await {
+                        Ok(()) => {
+                            info!(
+                                ip = %hostnames.label(ip),
+                                target_wan = %target_wan,
+                                flows = %moved.join(", "),
+                                "Switched flows"
+                            );
+                            if switch_breaker.record_success() {
+                                info!("Switch API circuit closed");
+                            }
+                            switch_history.record(SwitchRecord {
+                                ip,
+                                target_wan: target_wan.clone(),
+                                timestamp: now,
+                            });
+                            break (journal_id, None, reason);
+                        }
+                        Err(e) => {
+                            warn!(
+                                ip = %ip,
+                                flows = %moved.join(", "),
+                                "Flow switch failed: {}; switching the whole client",
+                                e
+                            );
+                            if let (Some(journal), Some(id)) = (journal.as_mut(), journal_id) {
+                                if let Err(e) = journal.failed(id, e.to_string()) {
+                                    error!("Failed to update the switch journal: {:#}", e);
+                                }
+                            }
+                            flows.clear();
+                            continue;
+                        }
+                    }
+                }
+
+                debug!(ip = %ip, target_wan = %target_wan, "Calling routing service");
+                let error = match routing.switch(ip, target_wan).await {
+                    Ok(()) => {
+                        info!(ip = %hostnames.label(ip), target_wan = %target_wan, "Switched");
+                        if switch_breaker.record_success() {
+                            info!("Switch API circuit closed");
+                        }
+                        // Verified switches are flushed once the status confirms them
+                        if config.conntrack.flush_on_switch && config.verification.is_none() {
+                            flush_conntrack(ip);
+                        }
+
+                        // Record the switch with timestamp
+                        switch_history.record(SwitchRecord {
+                            ip,
+                            target_wan: target_wan.clone(),
+                            timestamp: now,
+                        });
+                        for &alias in devices.aliases(ip).iter().rev() {
+                            queue.retain(|queued| queued.ip != alias);
+                            if status.mappings.get(&alias) == Some(target_wan) {
+                                continue;
+                            }
+                            if urgent {
+                                urgent_aliases.insert(alias);
+                            }
+                            queue.push_front(SwitchDecision {
+                                ip: alias,
+                                from_nic: decision.from_nic.clone(),
+                                target_wan: target_wan.clone(),
+                                rx_bps: ip_traffic
+                                    .iter()
+                                    .find(|traffic| traffic.ip == alias)
+                                    .map_or(0.0, |traffic| traffic.rx_bps),
+                                reason: format!("same device as {}", ip),
+                            });
+                        }
+                        None
+                    }
+                    Err(e) => {
+                        error!(ip = %ip, "Switch failed: {}", e);
+                        if switch_breaker.record_failure(now) {
+                            warn!(
+                                consecutive_failures = switch_breaker.consecutive_failures(),
+                                open_secs = config.circuit_breaker.open_secs,
+                                "Switch API circuit opened; pausing switches"
+                            );
+                        }
+                        Some(e.to_string())
+                    }
+                };
+                break (journal_id, error, reason);
+            };
+
+            metrics.record_switch(if error.is_none() { "success" } else { "failed" });
+
+            let mut history_id = None;
+            if let Some(history_db) = &history_db {
+                let stored = StoredSwitch {
+                    timestamp: now,
+                    ip: ip.to_string(),
+                    from_wan: status.mappings.get(&ip).map(ToString::to_string),
+                    to_wan: target_wan.to_string(),
+                    reason: reason.clone(),
+                    result: if error.is_none() { "success" } else { "failed" }.to_string(),
+                    error: error.clone(),
+                    verification: None,
+                };
+                match history_db.insert_switch(&stored) {
+                    Ok(id) => history_id = Some(id),
+                    Err(e) => error!("Failed to persist switch history: {:#}", e),
+                }
+            }
+            if error.is_none() {
+                accepted.push(AcceptedSwitch {
+                    ip,
+                    target_wan: target_wan.clone(),
+                    history_id,
+                    flows_only: !flows.is_empty(),
+                });
+                // Judge the move later against how the source NIC's traffic changed
+                if let (Some(rollbacks), Some(from_wan)) =
+                    (rollbacks.as_mut(), status.mappings.get(&ip))
+                {
+                    if policy_moves.contains(&(ip, target_wan.clone())) {
+                        let moved_bps = if flows.is_empty() {
+                            ip_traffic
+                                .iter()
+                                .find(|traffic| traffic.ip == ip)
+                                .map_or(0.0, |traffic| traffic.rx_bps + traffic.tx_bps)
+                        } else {
+                            flows.iter().map(|flow| flow.rx_bps).sum()
+                        };
+                        rollbacks.watch(
+                            decision,
+                            from_wan.clone(),
+                            &nic_stats,
+                            moved_bps,
+                            &flows,
+                            now,
+                        );
+                    }
+                }
+            }
+            if let (Some(journal), Some(id)) = (journal.as_mut(), journal_id) {
+                let journaled = match &error {
+                    None => journal.applied(id),
+                    Some(error) => journal.failed(id, error.clone()),
+                };
+                if let Err(e) = journaled {
+                    error!("Failed to update the switch journal: {:#}", e);
+                }
+            }
+
+            decisions.push(DecisionReport {
+                ip,
+                nic: decision.from_nic.clone(),
+                target_wan: Some(target_wan.clone()),
+                rx_bps: Some(decision.rx_bps),
+                reason: reason.clone(),
+                outcome: match &error {
+                    None => {
+                        switched += 1;
+                        DecisionOutcome::Switched
+                    }
+                    Some(error) => {
+                        failed += 1;
+                        DecisionOutcome::Failed {
+                            error: error.clone(),
+                        }
+                    }
+                },
+            });
+            event_bus.emit(Event::Switch {
+                timestamp: now,
+                ip,
+                from_nic: decision.from_nic.clone(),
+                target_wan: target_wan.clone(),
+                reason,
+                success: error.is_none(),
+                error,
+            });
+        }
+
+        if let (Some(audit_log), Some(snapshot)) = (audit_log.as_mut(), &audit_snapshot) {
+            if let Err(e) = audit_log.record(
+                now,
+                snapshot,
+                &decisions,
+                &cooldowns,
+                &status.mappings,
+                &ip_traffic,
+                &hostnames,
+            ) {
+                warn!("Failed to write the audit log: {:#}", e);
+            }
+        }
+
+        if let (Some(verification), false) = (&config.verification, accepted.is_empty()) {
+            for (switch, result) in
+                verification::verify(&routing, verification, clock.as_ref(), accepted).await
+            {
+                metrics.record_verification(result.label());
+                if config.conntrack.flush_on_switch && result == Verification::Verified {
+                    flush_conntrack(switch.ip);
+                }
+                if let (Some(history_db), Some(id)) = (&history_db, switch.history_id) {
+                    if let Err(e) = history_db.set_verification(id, result.label()) {
+                        error!("Failed to persist switch verification: {:#}", e);
+                    }
+                }
+            }
+        }
+
+        if let Some(event) = alerts.switches(now, switched) {
+            if let Event::SwitchStorm {
+                switches,
+                window_secs,
+                active: true,
+                ..
+            } = &event
+            {
+                warn!(switches, window_secs, "Switch storm");
+            }
+            event_bus.emit(event);
+        }
+
+        let switch_circuit = CircuitReport {
+            state: switch_breaker.state(now),
+            consecutive_failures: switch_breaker.consecutive_failures(),
+        };
+        metrics.record_circuit(
+            switch_circuit.state.gauge(),
+            switch_circuit.consecutive_failures,
+        );
+
+        if let Some(mapping_gc) = mapping_gc
+            .as_mut()
+            .filter(|_| mode == OperatingMode::Full && maintenance_window.is_none())
+        {
+            collect_idle_mappings(&routing, mapping_gc, &device_mappings, &ip_traffic, now).await;
+        }
+
+        let now = clock.unix_secs();
+        let recent_switches = switch_history
+            .records()
+            .iter()
+            .map(|record| RecentSwitch {
+                ip: record.ip,
+                target_wan: record.target_wan.clone(),
+                age_secs: now.saturating_sub(record.timestamp),
+                hold: cooldowns
+                    .remaining(&switch_history, record.ip, now)
+                    .map(|hold| RecentHold {
+                        reason: hold.reason.to_string(),
+                        remaining_secs: hold.remaining_secs,
+                    }),
+            })
+            .collect();
+
+        let nic_to_wan: HashMap<NicName, WanId> = wan_to_nic
+            .iter()
+            .map(|(wan, nic)| (nic.clone(), wan.clone()))
+            .collect();
+        let mut destination_usage =
+            destinations::aggregate_by_network(&destination_traffic, &nic_to_wan);
+        destination_usage.truncate(config.destinations.as_ref().map_or(0, |d| d.top));
+
+        let mut wans: Vec<_> = wan_to_nic.iter().collect();
+        wans.sort();
+        let report = CycleReport {
+            timestamp: now,
+            site: config.site.clone(),
+            policy: switch_policy.name().to_string(),
+            lan: status.config.lan.clone(),
+            wans: wans
+                .into_iter()
+                .map(|(wan, nic)| WanReport {
+                    wan: wan.clone(),
+                    nic: nic.clone(),
+                    clients: clients_per_wan.get(wan).copied().unwrap_or(0),
+                    client_cap: config.client_cap(wan),
+                    probe: wan_probes.get(wan).cloned(),
+                    client_rtt_ms: wan_rtts.get(wan).copied(),
+                    public_ip: public_ips.get(wan).copied(),
+                    data_cap: data_cap_usage.get(wan).cloned(),
+                })
+                .collect(),
+            nics,
+            top_ips,
+            destination_usage,
+            decisions,
+            switch_circuit,
+            mode,
+            maintenance_window: maintenance_window.map(|window| window.name),
+            fairness: fairness.as_ref().map(Into::into),
+            recent_switches,
+            history_window_secs: cooldowns.max_window(),
+            hostnames,
+            leases: leases
+                .into_iter()
+                .filter(|(ip, _)| status.mappings.contains_key(ip))
+                .collect(),
+            clients: controller_clients
+                .into_iter()
+                .filter(|(ip, _)| status.mappings.contains_key(ip))
+                .collect(),
+        };
+        match output {
+            Some(OutputFormat::Text) => report.print_text(&report_template),
+            Some(OutputFormat::Json) => report.print_json(),
+            None => {}
+        }
+        recorder.record_cycle(&report);
+        control.record_cycle(&report);
+        if let Some(dashboard) = &dashboard {
+            dashboard.record(&report, &wan_statuses, &status.mappings, &ip_traffic);
+        }
+        if let (Some(history_db), Some(intervals)) = (&history_db, nic_stats_intervals.as_mut()) {
+            let now = clock.unix_secs();
+            if let Some(stats) =
+                intervals.add(now, &nic_stats, &ip_traffic, &wan_to_nic, &clients_per_wan)
+            {
+                let retain_since =
+                    now.saturating_sub(config.history.nic_stats_retention_days * 24 * 60 * 60);
+                if let Err(e) = history_db.insert_nic_stats(&stats.nics, retain_since) {
+                    warn!("Failed to persist NIC stats: {:#}", e);
+                }
+                if let Err(e) = history_db.insert_client_traffic(&stats.clients, retain_since) {
+                    warn!("Failed to persist client traffic: {:#}", e);
+                }
+            }
+        }
+        if let Some(view) = &grpc_view {
+            view.record(
+                report.timestamp,
+                &nic_stats,
+                &wan_to_nic,
+                &status.mappings,
+                &ip_traffic,
+            );
+        }
+
+        // Clean up records that no longer affect any cooldown
+        let now = clock.unix_secs();
+        switch_history.prune(now, cooldowns.max_window());
+
+        let nic_gauges: BTreeMap<String, NicGauges> = nic_stats
+            .iter()
+            .map(|(nic, stats)| {
+                (
+                    nic.to_string(),
+                    NicGauges {
+                        tcp_bandwidth_bps: stats.tcp_bandwidth,
+                        rx_bps: stats.rx_bps,
+                        tx_bps: stats.tx_bps,
+                    },
+                )
+            })
+            .collect();
+        metrics.record_data_caps(
+            data_cap_usage
+                .iter()
+                .map(|(wan, usage)| {
+                    (
+                        wan.to_string(),
+                        DataCapGauges {
+                            used_bytes: usage.used_bytes,
+                            cap_bytes: usage.cap_bytes,
+                        },
+                    )
+                })
+                .collect(),
+        );
+        metrics.record_cycle(
+            nic_gauges,
+            fairness.as_ref().map(|metrics| metrics.jain_index),
+            clock.since(cycle_started),
+        );
+
+        if let Some(saved_state) = &config.saved_state {
+            if now.saturating_sub(state_saved_at) >= saved_state.interval_secs {
+                save_state(
+                    &config,
+                    now,
+                    &switch_history,
+                    &manual_pins,
+                    &pause,
+                    failover.as_ref(),
+                    soft_start.as_ref(),
+                    smoother.as_ref(),
+                    &switch_breaker,
+                    &last_status,
+                );
+                state_saved_at = now;
+            }
+        }
+        if let Some(history_db) = &history_db {
+            if now.saturating_sub(learned_stored_at) >= config.history.state_interval_secs {
+                store_learned(
+                    history_db.as_ref(),
+                    &cooldowns,
+                    quotas.as_ref(),
+                    anomaly_detector.as_ref(),
+                );
+                learned_stored_at = now;
+            }
+        }
+
+        let interval = scan_interval.after(&nic_stats);
+        debug!("Waiting {:?} before next scan", interval);
+        wait_for_next_scan(clock.as_ref(), &mut shutdown, interval).await;
+    }
+
+    save_state(
+        &config,
+        clock.unix_secs(),
+        &switch_history,
+        &manual_pins,
+        &pause,
+        failover.as_ref(),
+        soft_start.as_ref(),
+        smoother.as_ref(),
+        &switch_breaker,
+        &last_status,
+    );
+
+    if let Some(systemd) = &systemd {
+        systemd.stopping();
+    }
+    // Applied switches still pending verification are checked against the mappings on the
+    // next start
+    if let Some(journal) = journal.as_mut() {
+        if let Err(e) = journal.sync() {
+            error!("Failed to flush the switch journal: {:#}", e);
+        }
+    }
+    if let Some(history_db) = history_db {
+        store_learned(
+            history_db.as_ref(),
+            &cooldowns,
+            quotas.as_ref(),
+            anomaly_detector.as_ref(),
+        );
+        if let Err(e) = history_db.close() {
+            error!("Failed to close the history database: {:#}", e);
+        }
+    }
+    info!(
+        cycles,
+        switched,
+        failed,
+        uptime_secs = clock.unix_secs().saturating_sub(run_started),
+        "Stopped"
+    );
+    Ok(())
+}
+
+/// Waits `interval` for the next scan, cutting the wait short when a stop is requested.
+async fn wait_for_next_scan(clock: &dyn Clock, shutdown: &mut Shutdown, interval: Duration) {
+    tokio::select! {
+        _ = clock.sleep(interval) => {}
+        _ = shutdown.requested() => {}
+    }
+}
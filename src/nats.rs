@@ -0,0 +1,97 @@
+use crate::config::NatsSinkConfig;
+use crate::events::Event;
+use anyhow::{bail, Context, Result};
+use serde_json::json;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::{error, warn};
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Publishes events to NATS subjects `<subject_prefix>.<event kind>`, reconnecting on failure.
+pub async fn run(config: NatsSinkConfig, mut events: broadcast::Receiver<Arc<Event>>) {
+    loop {
+        match publish_until_error(&config, &mut events).await {
+            Ok(()) => return,
+            Err(e) => {
+                error!("NATS sink error ({}): {:#}", config.address, e);
+                tokio::time::sleep(RECONNECT_DELAY).await;
+            }
+        }
+    }
+}
+
+/// Returns `Ok(())` only when the event bus is closed.
+async fn publish_until_error(
+    config: &NatsSinkConfig,
+    events: &mut broadcast::Receiver<Arc<Event>>,
+) -> Result<()> {
+    let stream = TcpStream::connect(&config.address)
+        .await
+        .with_context(|| format!("Failed to connect to NATS at {}", config.address))?;
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+
+    let mut line = String::new();
+    reader
+        .read_line(&mut line)
+        .await
+        .context("Failed to read NATS INFO")?;
+    if !line.starts_with("INFO") {
+        bail!("Unexpected NATS greeting: {}", line.trim());
+    }
+
+    let mut connect = json!({
+        "verbose": false,
+        "pedantic": false,
+        "name": "routingFlow",
+        "lang": "rust",
+    });
+    if let Some(token) = &config.token {
+        connect["auth_token"] = json!(token);
+    }
+    if let (Some(user), Some(pass)) = (&config.user, &config.password) {
+        connect["user"] = json!(user);
+        connect["pass"] = json!(pass);
+    }
+    writer
+        .write_all(format!("CONNECT {}\r\n", connect).as_bytes())
+        .await?;
+
+    loop {
+        line.clear();
+        tokio::select! {
+            read = reader.read_line(&mut line) => {
+                if read? == 0 {
+                    bail!("NATS server closed the connection");
+                }
+                if line.starts_with("PING") {
+                    writer.write_all(b"PONG\r\n").await?;
+                } else if line.starts_with("-ERR") {
+                    bail!("NATS server error: {}", line.trim());
+                }
+            }
+            event = events.recv() => {
+                let event = match event {
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(skipped)) => {
+                        warn!("NATS sink dropped {} events", skipped);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => return Ok(()),
+                };
+
+                let payload = serde_json::to_vec(event.as_ref())?;
+                let subject = format!("{}.{}", config.subject_prefix, event.kind());
+                writer
+                    .write_all(format!("PUB {} {}\r\n", subject, payload.len()).as_bytes())
+                    .await?;
+                writer.write_all(&payload).await?;
+                writer.write_all(b"\r\n").await?;
+            }
+        }
+    }
+}
@@ -0,0 +1,126 @@
+use crate::model::ClientIp;
+use crate::netlink::{self, NetlinkSocket};
+use anyhow::Result;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+const NETLINK_ROUTE: i32 = 0;
+const RTM_GETNEIGH: u16 = 30;
+
+const NDA_DST: u16 = 1;
+const NDA_LLADDR: u16 = 2;
+
+const NDMSG_LEN: usize = 12;
+const NUD_INCOMPLETE: u16 = 0x01;
+const NUD_FAILED: u16 = 0x20;
+const NUD_NOARP: u16 = 0x40;
+
+/// Link-layer address → every IPv4 and IPv6 address the kernel's neighbour tables
+/// currently resolve to it, read over rtnetlink.
+pub fn read_neighbors() -> Result<HashMap<String, Vec<ClientIp>>> {
+    let mut socket = NetlinkSocket::open(NETLINK_ROUTE)?;
+    // AF_UNSPEC: both address families in one dump
+    let replies = socket.dump(RTM_GETNEIGH, &[0; NDMSG_LEN])?;
+
+    let mut neighbors: HashMap<String, Vec<ClientIp>> = HashMap::new();
+    for payload in replies {
+        let Some((ip, mac)) = parse_neighbor(&payload) else {
+            continue;
+        };
+        neighbors.entry(mac).or_default().push(ip);
+    }
+    for addresses in neighbors.values_mut() {
+        addresses.sort();
+        addresses.dedup();
+    }
+    Ok(neighbors)
+}
+
+fn parse_neighbor(payload: &[u8]) -> Option<(ClientIp, String)> {
+    let header = payload.get(..NDMSG_LEN)?;
+    let state = u16::from_ne_bytes([header[8], header[9]]);
+    if state & (NUD_INCOMPLETE | NUD_FAILED | NUD_NOARP) != 0 {
+        return None;
+    }
+
+    let attributes = &payload[NDMSG_LEN..];
+    let ip = match netlink::find(attributes, NDA_DST)? {
+        dst if dst.len() == 4 => IpAddr::V4(Ipv4Addr::from(<[u8; 4]>::try_from(dst).ok()?)),
+        dst if dst.len() == 16 => IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(dst).ok()?)),
+        _ => return None,
+    };
+    let mac = netlink::find(attributes, NDA_LLADDR)?;
+    if mac.len() != 6 || mac.iter().all(|byte| *byte == 0) {
+        return None;
+    }
+    let mac = mac
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<Vec<_>>()
+        .join(":");
+    Some((ClientIp::from(ip), mac))
+}
+
+/// `aa:bb:cc:dd:ee:ff`, the form the neighbour tables give, for a MAC written with dashes
+/// or in upper case as controllers and DHCP servers may.
+pub fn normalize_mac(mac: &str) -> String {
+    mac.trim().to_lowercase().replace('-', ":")
+}
+
+/// Six octets of two hex digits, separated by colons or dashes.
+pub fn is_mac(s: &str) -> bool {
+    let octets: Vec<&str> = s.trim().split([':', '-']).collect();
+    octets.len() == 6
+        && octets
+            .iter()
+            .all(|octet| octet.len() == 2 && octet.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+/// Groups the addresses of dual-stack clients: every address is mapped to the device's
+/// primary address (its lowest IPv4 address, or its lowest address if it has none), so
+/// the balancing can treat the device as one client.
+#[derive(Debug, Default)]
+pub struct Devices {
+    primary: HashMap<ClientIp, ClientIp>,
+    aliases: HashMap<ClientIp, Vec<ClientIp>>,
+}
+
+impl Devices {
+    /// Devices among `known` addresses (those the routing service maps).
+    pub fn group(
+        neighbors: &HashMap<String, Vec<ClientIp>>,
+        known: impl Fn(ClientIp) -> bool,
+    ) -> Self {
+        let mut devices = Self::default();
+        for addresses in neighbors.values() {
+            let mut addresses: Vec<ClientIp> =
+                addresses.iter().copied().filter(|ip| known(*ip)).collect();
+            if addresses.len() < 2 {
+                continue;
+            }
+            // IPv4 sorts before IPv6
+            addresses.sort();
+            let primary = addresses.remove(0);
+            for alias in &addresses {
+                devices.primary.insert(*alias, primary);
+            }
+            devices.aliases.insert(primary, addresses);
+        }
+        devices
+    }
+
+    /// The address `ip`'s device is tracked under.
+    pub fn primary(&self, ip: ClientIp) -> ClientIp {
+        self.primary.get(&ip).copied().unwrap_or(ip)
+    }
+
+    /// The device's other addresses, which move together with `primary`.
+    pub fn aliases(&self, primary: ClientIp) -> &[ClientIp] {
+        self.aliases.get(&primary).map_or(&[], Vec::as_slice)
+    }
+
+    /// Number of devices with more than one address.
+    pub fn count(&self) -> usize {
+        self.aliases.len()
+    }
+}
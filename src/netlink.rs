@@ -0,0 +1,151 @@
+use anyhow::{Context, Result};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::io::{Read, Write};
+use std::time::Duration;
+
+const AF_NETLINK: i32 = 16;
+
+const NLMSG_ERROR: u16 = 2;
+const NLMSG_DONE: u16 = 3;
+const NLM_F_REQUEST: u16 = 0x1;
+const NLM_F_ACK: u16 = 0x4;
+const NLM_F_DUMP: u16 = 0x300;
+const NLA_TYPE_MASK: u16 = 0x3fff;
+
+const NLMSG_HEADER_LEN: usize = 16;
+
+pub enum NetlinkError {
+    Errno(i32),
+    Io(anyhow::Error),
+}
+
+/// Raw netlink socket of one protocol family (`NETLINK_ROUTE`, `NETLINK_NETFILTER`, ...).
+pub struct NetlinkSocket {
+    socket: Socket,
+    sequence: u32,
+}
+
+impl NetlinkSocket {
+    pub fn open(protocol: i32) -> Result<Self> {
+        let socket = Socket::new(
+            Domain::from(AF_NETLINK),
+            Type::RAW,
+            Some(Protocol::from(protocol)),
+        )
+        .context("Failed to open netlink socket")?;
+        socket.set_read_timeout(Some(Duration::from_secs(2)))?;
+        Ok(Self {
+            socket,
+            sequence: 0,
+        })
+    }
+
+    /// Sends one request; `payload` starts with the family-specific header.
+    fn send(&mut self, message_type: u16, flags: u16, payload: &[u8]) -> Result<()> {
+        self.sequence += 1;
+        let len = NLMSG_HEADER_LEN + payload.len();
+        let mut message = Vec::with_capacity(len);
+        message.extend_from_slice(&(len as u32).to_ne_bytes());
+        message.extend_from_slice(&message_type.to_ne_bytes());
+        message.extend_from_slice(&(NLM_F_REQUEST | flags).to_ne_bytes());
+        message.extend_from_slice(&self.sequence.to_ne_bytes());
+        message.extend_from_slice(&0u32.to_ne_bytes());
+        message.extend_from_slice(payload);
+        self.socket
+            .write_all(&message)
+            .context("Failed to send netlink request")
+    }
+
+    /// Raw `(type, payload)` of every message in the next datagram.
+    pub fn receive(&mut self) -> Result<Vec<(u16, Vec<u8>)>> {
+        let mut buffer = vec![0u8; 64 * 1024];
+        let received = self
+            .socket
+            .read(&mut buffer)
+            .context("Failed to read netlink reply")?;
+
+        let mut messages = Vec::new();
+        let mut offset = 0;
+        while offset + NLMSG_HEADER_LEN <= received {
+            let len = u32::from_ne_bytes(buffer[offset..offset + 4].try_into().unwrap()) as usize;
+            if len < NLMSG_HEADER_LEN || offset + len > received {
+                break;
+            }
+            let message_type = u16::from_ne_bytes([buffer[offset + 4], buffer[offset + 5]]);
+            messages.push((
+                message_type,
+                buffer[offset + NLMSG_HEADER_LEN..offset + len].to_vec(),
+            ));
+            offset += align(len);
+        }
+        Ok(messages)
+    }
+
+    /// Sends a dump request and collects the payloads of every reply message.
+    pub fn dump(&mut self, message_type: u16, payload: &[u8]) -> Result<Vec<Vec<u8>>> {
+        self.send(message_type, NLM_F_DUMP, payload)?;
+
+        let mut replies = Vec::new();
+        loop {
+            for (message_type, payload) in self.receive()? {
+                match message_type {
+                    NLMSG_DONE => return Ok(replies),
+                    NLMSG_ERROR => match errno(&payload) {
+                        0 => {}
+                        errno => anyhow::bail!("Netlink dump failed: errno {}", errno),
+                    },
+                    _ => replies.push(payload),
+                }
+            }
+        }
+    }
+
+    /// Sends a request and waits for its acknowledgement.
+    pub fn request(&mut self, message_type: u16, payload: &[u8]) -> Result<(), NetlinkError> {
+        self.send(message_type, NLM_F_ACK, payload)
+            .map_err(NetlinkError::Io)?;
+        loop {
+            for (message_type, payload) in self.receive().map_err(NetlinkError::Io)? {
+                if message_type == NLMSG_ERROR {
+                    return match errno(&payload) {
+                        0 => Ok(()),
+                        errno => Err(NetlinkError::Errno(errno)),
+                    };
+                }
+            }
+        }
+    }
+}
+
+/// Positive errno of an `NLMSG_ERROR` payload; 0 for an acknowledgement.
+fn errno(payload: &[u8]) -> i32 {
+    payload
+        .get(..4)
+        .map_or(0, |bytes| -i32::from_ne_bytes(bytes.try_into().unwrap()))
+}
+
+pub fn align(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+/// `(type, whole attribute, value)` of each attribute in `data`.
+pub fn attributes(data: &[u8]) -> impl Iterator<Item = (u16, &[u8], &[u8])> {
+    let mut offset = 0;
+    std::iter::from_fn(move || {
+        let header = data.get(offset..offset + 4)?;
+        let len = u16::from_ne_bytes([header[0], header[1]]) as usize;
+        let attribute_type = u16::from_ne_bytes([header[2], header[3]]) & NLA_TYPE_MASK;
+        if len < 4 {
+            return None;
+        }
+        let attribute = data.get(offset..offset + len)?;
+        offset += align(len);
+        Some((attribute_type, attribute, &attribute[4..]))
+    })
+}
+
+pub fn find(data: &[u8], wanted: u16) -> Option<&[u8]> {
+    attributes(data)
+        .find(|(attribute_type, _, _)| *attribute_type == wanted)
+        .map(|(_, _, value)| value)
+}
@@ -0,0 +1,169 @@
+use crate::config::{NotifierConfig, NotifierKind};
+use crate::error::ConfigError;
+use crate::events::Event;
+use crate::templates::Templates;
+use anyhow::{bail, Context, Result};
+use reqwest::Client;
+use std::collections::VecDeque;
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::warn;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// The window `max_per_hour` counts messages over.
+const RATE_WINDOW_SECS: u64 = 3600;
+
+/// Sends the events a person should see to Slack, Telegram or email, filtered by
+/// severity and limited to `max_per_hour` messages.
+pub struct Channel {
+    config: NotifierConfig,
+    templates: Templates,
+    client: Client,
+    /// Event times of the messages sent within the rate window.
+    sent: VecDeque<u64>,
+    /// Messages dropped by the rate limit since the last one sent.
+    suppressed: usize,
+}
+
+impl Channel {
+    pub fn new(config: NotifierConfig, templates: Templates) -> Result<Self, ConfigError> {
+        let missing = match config.kind {
+            NotifierKind::Slack if config.webhook_url.is_none() => Some("webhook_url"),
+            NotifierKind::Telegram if config.bot_token.is_none() => Some("bot_token"),
+            NotifierKind::Telegram if config.chat_id.is_none() => Some("chat_id"),
+            NotifierKind::Email if config.to.is_empty() => Some("to"),
+            _ => None,
+        };
+        if let Some(field) = missing {
+            return Err(ConfigError::Invalid(format!(
+                "{} notifier needs {}",
+                config.kind.name(),
+                field
+            )));
+        }
+        Ok(Self {
+            config,
+            templates,
+            client: Client::new(),
+            sent: VecDeque::new(),
+            suppressed: 0,
+        })
+    }
+
+    pub async fn run(mut self, mut events: broadcast::Receiver<Arc<Event>>) {
+        loop {
+            let event = match events.recv().await {
+                Ok(event) => event,
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!(
+                        "{} notifier dropped {} events",
+                        self.config.kind.name(),
+                        skipped
+                    );
+                    continue;
+                }
+                Err(RecvError::Closed) => return,
+            };
+            if event.severity() < self.config.min_severity {
+                continue;
+            }
+
+            let now = event.timestamp();
+            while self
+                .sent
+                .front()
+                .is_some_and(|at| now.saturating_sub(*at) >= RATE_WINDOW_SECS)
+            {
+                self.sent.pop_front();
+            }
+            if self.sent.len() >= self.config.max_per_hour {
+                self.suppressed += 1;
+                continue;
+            }
+            self.sent.push_back(now);
+
+            let mut message = self.templates.render(&event);
+            if self.suppressed > 0 {
+                message.push_str(&format!(
+                    " ({} earlier notifications suppressed)",
+                    self.suppressed
+                ));
+                self.suppressed = 0;
+            }
+            if let Err(e) = self.deliver(&event, &message).await {
+                warn!(
+                    "{} notifier failed to send {} event: {:#}",
+                    self.config.kind.name(),
+                    event.kind(),
+                    e
+                );
+            }
+        }
+    }
+
+    async fn deliver(&self, event: &Event, message: &str) -> Result<()> {
+        let request = match self.config.kind {
+            NotifierKind::Slack => self
+                .client
+                .post(self.config.webhook_url.as_deref().unwrap_or_default())
+                .json(&serde_json::json!({ "text": message })),
+            NotifierKind::Telegram => self
+                .client
+                .post(format!(
+                    "{}/bot{}/sendMessage",
+                    self.config.api_url.trim_end_matches('/'),
+                    self.config.bot_token.as_deref().unwrap_or_default()
+                ))
+                .json(&serde_json::json!({
+                    "chat_id": self.config.chat_id,
+                    "text": message,
+                })),
+            NotifierKind::Email => return self.send_mail(event, message).await,
+        };
+
+        let response = request
+            .timeout(REQUEST_TIMEOUT)
+            .send()
+            .await
+            .context("Failed to reach the notification service")?;
+        if !response.status().is_success() {
+            bail!("Notification service returned {}", response.status());
+        }
+        Ok(())
+    }
+
+    /// Hands the message to `sendmail`, which reads the recipients from the headers.
+    async fn send_mail(&self, event: &Event, message: &str) -> Result<()> {
+        let mut mail = String::new();
+        if let Some(from) = &self.config.from {
+            mail.push_str(&format!("From: {}\n", from));
+        }
+        mail.push_str(&format!(
+            "To: {}\nSubject: routingFlow {:?}: {}\nContent-Type: text/plain; charset=utf-8\n\n{}\n",
+            self.config.to.join(", "),
+            event.severity(),
+            event.kind(),
+            message
+        ));
+
+        let mut child = Command::new(&self.config.sendmail)
+            .arg("-t")
+            .stdin(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("Failed to run {}", self.config.sendmail.display()))?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(mail.as_bytes()).await?;
+        }
+        let status = tokio::time::timeout(REQUEST_TIMEOUT, child.wait())
+            .await
+            .context("sendmail timed out")??;
+        if !status.success() {
+            bail!("{} exited with {}", self.config.sendmail.display(), status);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,130 @@
+use crate::config::PassiveRttConfig;
+use crate::model::{ClientIp, WanId};
+use crate::neighbors::Devices;
+use crate::policy::{PolicyPlan, SkippedCandidate};
+use crate::prometheus::PrometheusResult;
+use serde::Serialize;
+use std::collections::HashMap;
+
+/// How a client's TCP connections are doing, as measured on its own flows.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ClientExperience {
+    pub rtt_ms: Option<f64>,
+    /// Share of segments retransmitted (0–1).
+    pub retransmit_ratio: Option<f64>,
+}
+
+/// Passive RTT and retransmit readings of client flows from the exporters. Each WAN's
+/// median client RTT stands for what a client moved there can expect, so policy moves
+/// onto a WAN that is that much slower than what the client sees now are held back.
+pub struct PassiveRtt {
+    config: PassiveRttConfig,
+}
+
+impl PassiveRtt {
+    pub fn new(config: PassiveRttConfig) -> Self {
+        Self { config }
+    }
+
+    /// Query for both metrics, by name.
+    pub fn query(&self) -> String {
+        let mut names = vec![self.config.rtt_metric.as_str()];
+        if let Some(retransmit_metric) = &self.config.retransmit_metric {
+            names.push(retransmit_metric);
+        }
+        format!(r#"{{__name__=~"{}"}}"#, names.join("|"))
+    }
+
+    /// Readings per device; a device with several addresses gets its worst.
+    pub fn parse(
+        &self,
+        results: &[PrometheusResult],
+        devices: &Devices,
+    ) -> HashMap<ClientIp, ClientExperience> {
+        let mut experience: HashMap<ClientIp, ClientExperience> = HashMap::new();
+        for result in results {
+            let (Some(name), Some(ip)) = (
+                result.metric.get("__name__"),
+                result.label::<ClientIp>(&self.config.ip_label),
+            ) else {
+                continue;
+            };
+            let Ok(value) = result.value.1.parse::<f64>() else {
+                continue;
+            };
+            if !value.is_finite() {
+                continue;
+            }
+            let entry = experience.entry(devices.primary(ip)).or_default();
+            let slot = if *name == self.config.rtt_metric {
+                &mut entry.rtt_ms
+            } else if self.config.retransmit_metric.as_ref() == Some(name) {
+                &mut entry.retransmit_ratio
+            } else {
+                continue;
+            };
+            *slot = Some(slot.map_or(value, |current| current.max(value)));
+        }
+        experience
+    }
+
+    /// Median client RTT of every WAN with readings.
+    pub fn wan_rtts(
+        experience: &HashMap<ClientIp, ClientExperience>,
+        mappings: &HashMap<ClientIp, WanId>,
+    ) -> HashMap<WanId, f64> {
+        let mut rtts: HashMap<WanId, Vec<f64>> = HashMap::new();
+        for (ip, client) in experience {
+            if let (Some(rtt_ms), Some(wan)) = (client.rtt_ms, mappings.get(ip)) {
+                rtts.entry(wan.clone()).or_default().push(rtt_ms);
+            }
+        }
+        rtts.into_iter()
+            .map(|(wan, mut rtts)| {
+                rtts.sort_by(f64::total_cmp);
+                let middle = rtts.len() / 2;
+                let median = if rtts.len() % 2 == 0 {
+                    (rtts[middle - 1] + rtts[middle]) / 2.0
+                } else {
+                    rtts[middle]
+                };
+                (wan, median)
+            })
+            .collect()
+    }
+
+    /// Drops policy moves onto a WAN whose median RTT exceeds the client's own by more
+    /// than `max_rtt_increase_ms`, reporting them as skipped.
+    pub fn filter(
+        &self,
+        mut plan: PolicyPlan,
+        experience: &HashMap<ClientIp, ClientExperience>,
+        wan_rtts: &HashMap<WanId, f64>,
+    ) -> PolicyPlan {
+        let (kept, worse): (Vec<_>, Vec<_>) =
+            plan.switches.into_iter().partition(|decision| {
+                match (
+                    experience
+                        .get(&decision.ip)
+                        .and_then(|client| client.rtt_ms),
+                    wan_rtts.get(&decision.target_wan),
+                ) {
+                    (Some(own), Some(target)) => target - own <= self.config.max_rtt_increase_ms,
+                    _ => true,
+                }
+            });
+        plan.switches = kept;
+        plan.skipped.extend(worse.into_iter().map(|decision| {
+            let own = experience[&decision.ip].rtt_ms.unwrap_or_default();
+            SkippedCandidate {
+                reason: format!(
+                    "RTT would worsen on {} ({:.1} ms now, {:.1} ms median there)",
+                    decision.target_wan, own, wan_rtts[&decision.target_wan]
+                ),
+                ip: decision.ip,
+                nic: decision.from_nic,
+            }
+        }));
+        plan
+    }
+}
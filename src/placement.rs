@@ -0,0 +1,163 @@
+use crate::config::InitialPlacementConfig;
+use crate::model::{ClientIp, WanId};
+use crate::policy::{PolicyInput, SwitchDecision};
+use std::collections::{HashMap, HashSet};
+
+/// Assigns newly-seen IPs to a WAN by weighted rendezvous hashing, so the initial spread
+/// of devices follows WAN capacity and is stable across restarts.
+pub struct InitialPlacement {
+    config: InitialPlacementConfig,
+    known: HashSet<ClientIp>,
+    seeded: bool,
+}
+
+impl InitialPlacement {
+    pub fn new(config: InitialPlacementConfig) -> Self {
+        Self {
+            config,
+            known: HashSet::new(),
+            seeded: false,
+        }
+    }
+
+    pub fn plan(&mut self, input: &PolicyInput) -> Vec<SwitchDecision> {
+        // Devices already mapped at startup keep their placement
+        if !self.seeded {
+            self.known.extend(input.mappings.keys().copied());
+            self.seeded = true;
+            return Vec::new();
+        }
+
+        let weights = self.wan_weights(input);
+        if weights.is_empty() {
+            return Vec::new();
+        }
+
+        let mut clients_per_wan = input.clients_per_wan.clone();
+        let mut new_ips: Vec<ClientIp> = input
+            .mappings
+            .keys()
+            .filter(|ip| !self.known.contains(*ip))
+            .copied()
+            .collect();
+        new_ips.sort();
+
+        let mut decisions = Vec::new();
+
+        for ip in new_ips {
+            self.known.insert(ip);
+
+            let current_wan = input.mappings.get(&ip);
+            let Some(target_wan) = rank_wans(ip, &weights).into_iter().find(|wan| {
+                match input.config.client_cap(wan) {
+                    Some(cap) => {
+                        current_wan == Some(wan)
+                            || clients_per_wan.get(wan).copied().unwrap_or(0) < cap
+                    }
+                    None => true,
+                }
+            }) else {
+                continue;
+            };
+
+            if current_wan == Some(&target_wan) {
+                continue;
+            }
+
+            if let Some(count) = current_wan.and_then(|wan| clients_per_wan.get_mut(wan)) {
+                *count = count.saturating_sub(1);
+            }
+            *clients_per_wan.entry(target_wan.clone()).or_insert(0) += 1;
+
+            let traffic = input.ip_traffic.iter().find(|traffic| traffic.ip == ip);
+            let Some(from_nic) = current_wan
+                .and_then(|wan| input.wan_to_nic.get(wan))
+                .cloned()
+            else {
+                continue;
+            };
+
+            decisions.push(SwitchDecision {
+                ip,
+                from_nic,
+                target_wan: target_wan.clone(),
+                rx_bps: traffic.map(|traffic| traffic.rx_bps).unwrap_or(0.0),
+                reason: format!("new device; weighted hash assigns it to {}", target_wan),
+            });
+        }
+
+        decisions
+    }
+
+    /// Configured weights, falling back to each WAN's TCP bandwidth estimate. WANs whose
+    /// probes fail or that intercept traffic get no new devices.
+    fn wan_weights(&self, input: &PolicyInput) -> HashMap<WanId, f64> {
+        let weights: HashMap<WanId, f64> = if self.config.weights.is_empty() {
+            input
+                .wan_to_nic
+                .iter()
+                .filter_map(|(wan, nic)| {
+                    input
+                        .nic_stats
+                        .get(nic)
+                        .map(|stats| (wan.clone(), stats.tcp_bandwidth))
+                })
+                .collect()
+        } else {
+            self.config.weights.clone()
+        };
+
+        weights
+            .into_iter()
+            .filter(|(wan, weight)| {
+                *weight > 0.0
+                    && !input
+                        .wan_probes
+                        .get(wan)
+                        .is_some_and(|probe| probe.is_down() || probe.is_degraded())
+            })
+            .collect()
+    }
+}
+
+/// Orders WANs by weighted rendezvous score for `ip` (best first).
+fn rank_wans(ip: ClientIp, weights: &HashMap<WanId, f64>) -> Vec<WanId> {
+    let mut scored: Vec<(f64, &WanId)> = weights
+        .iter()
+        .map(|(wan, weight)| {
+            let hash = mix(fnv1a(format!("{}/{}", ip, wan).as_bytes()));
+            // Map the hash into (0, 1) and apply the weighted HRW transform
+            let unit = (hash as f64 + 1.0) / (u64::MAX as f64 + 2.0);
+            (-weight / unit.ln(), wan)
+        })
+        .collect();
+
+    scored.sort_by(|a, b| {
+        b.0.partial_cmp(&a.0)
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then_with(|| a.1.cmp(b.1))
+    });
+    scored.into_iter().map(|(_, wan)| wan.clone()).collect()
+}
+
+/// FNV-1a, used instead of `DefaultHasher` so placements are stable across builds.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in bytes {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    hash
+}
+
+/// MurmurHash3's 64-bit finalizer. Keys that differ only in their last byte, as the same
+/// client's keys for different WANs do, leave FNV-1a hashes a small multiple of its prime
+/// apart; without mixing, every WAN would draw almost the same score and the heaviest
+/// weight would always win.
+fn mix(mut hash: u64) -> u64 {
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    hash ^ (hash >> 33)
+}
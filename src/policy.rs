@@ -0,0 +1,682 @@
+use crate::client_rules::ClientRules;
+use crate::config::{Config, RankBy, ShareBy, WeightedPolicyConfig};
+use crate::destinations::DestinationTraffic;
+use crate::error::ConfigError;
+use crate::link_quality::LinkQuality;
+use crate::model::{ClientIp, IpTraffic, NicName, NicStats, WanId};
+use crate::probe::WanProbeStats;
+use crate::reservations::ActiveReservations;
+use std::collections::HashMap;
+
+/// Minimum traffic (1 Mbps, by the ranking's measure) for an IP to be worth moving.
+const MIN_TRAFFIC_THRESHOLD: f64 = 1_000_000.0;
+
+/// Everything a policy may look at when planning switches for one scan cycle.
+pub struct PolicyInput<'a> {
+    pub nic_stats: &'a HashMap<NicName, NicStats>,
+    pub ip_traffic: &'a [IpTraffic],
+    pub wan_to_nic: &'a HashMap<WanId, NicName>,
+    /// Current IP → WAN mappings reported by the routing service.
+    pub mappings: &'a HashMap<ClientIp, WanId>,
+    pub clients_per_wan: &'a HashMap<WanId, usize>,
+    /// Per-destination breakdown of `ip_traffic`; empty unless destination attribution is enabled.
+    pub destinations: &'a [DestinationTraffic],
+    /// Latest latency probe results per WAN; empty unless probing is enabled.
+    pub wan_probes: &'a HashMap<WanId, WanProbeStats>,
+    /// Packet loss and jitter per WAN; empty unless link quality is enabled.
+    pub link_quality: &'a HashMap<WanId, LinkQuality>,
+    /// Bandwidth reservations open this cycle; empty unless configured.
+    pub reservations: &'a ActiveReservations,
+    /// Pins, exclusions, preferred WANs and share weights of clients.
+    pub client_rules: &'a ClientRules,
+    pub config: &'a Config,
+}
+
+#[derive(Debug, Clone)]
+pub struct SwitchDecision {
+    pub ip: ClientIp,
+    pub from_nic: NicName,
+    pub target_wan: WanId,
+    pub rx_bps: f64,
+    pub reason: String,
+}
+
+/// A candidate the policy looked at but decided not to move.
+#[derive(Debug, Clone)]
+pub struct SkippedCandidate {
+    pub ip: ClientIp,
+    pub nic: NicName,
+    pub reason: String,
+}
+
+#[derive(Debug, Default)]
+pub struct PolicyPlan {
+    pub switches: Vec<SwitchDecision>,
+    pub skipped: Vec<SkippedCandidate>,
+}
+
+pub trait SwitchPolicy: Send {
+    fn name(&self) -> &'static str;
+
+    fn plan(&mut self, input: &PolicyInput) -> PolicyPlan;
+
+    /// Per-WAN desirability as seen by this policy (higher is better), for reporting.
+    fn wan_scores(&self, _input: &PolicyInput) -> HashMap<WanId, f64> {
+        HashMap::new()
+    }
+}
+
+/// Builds the policy named by `config.policy`.
+pub fn from_config(config: &Config) -> Result<Box<dyn SwitchPolicy>, ConfigError> {
+    build(&config.policy, &config.weighted)
+}
+
+/// Builds the policy called `name` with the given parameters.
+pub fn build(
+    name: &str,
+    weighted: &WeightedPolicyConfig,
+) -> Result<Box<dyn SwitchPolicy>, ConfigError> {
+    match name {
+        "top_rx" => Ok(Box::new(TopRxPolicy)),
+        "weighted" => {
+            if !weighted.weights.values().any(|weight| *weight > 0.0) {
+                return Err(ConfigError::Invalid(
+                    "The weighted policy needs positive [weighted] weights".to_string(),
+                ));
+            }
+            Ok(Box::new(WeightedPolicy {
+                config: weighted.clone(),
+            }))
+        }
+        other => Err(ConfigError::Invalid(format!(
+            "Unknown switching policy: {}",
+            other
+        ))),
+    }
+}
+
+/// Moves the top RX IP (or `[top_rx] moves_per_nic` heaviest IPs) of every NIC to the
+/// WAN with the most free headroom.
+pub struct TopRxPolicy;
+
+impl SwitchPolicy for TopRxPolicy {
+    fn name(&self) -> &'static str {
+        "top_rx"
+    }
+
+    fn plan(&mut self, input: &PolicyInput) -> PolicyPlan {
+        let mut plan = PolicyPlan::default();
+        let mut clients_per_wan = input.clients_per_wan.clone();
+        let mut nic_stats = input.nic_stats.clone();
+
+        let mut nics: Vec<_> = input.nic_stats.keys().collect();
+        nics.sort();
+
+        let top_rx = &input.config.top_rx;
+        for nic in nics {
+            // Up to `moves_per_nic` of the heaviest clients are considered; clients held by
+            // their rule give way to the next ones on the NIC
+            let mut considered = 0;
+            for (index, top) in ranked_clients(input, nic).into_iter().enumerate() {
+                if considered >= top_rx.moves_per_nic {
+                    break;
+                }
+                if let Some(reason) = input
+                    .client_rules
+                    .policy_hold_reason(top.ip, input.mappings.get(&top.ip))
+                {
+                    plan.skipped.push(SkippedCandidate {
+                        ip: top.ip,
+                        nic: nic.clone(),
+                        reason,
+                    });
+                    continue;
+                }
+                considered += 1;
+
+                let ranked_bps = rank_bps(top, top_rx.rank_by);
+                if ranked_bps < MIN_TRAFFIC_THRESHOLD {
+                    plan.skipped.push(SkippedCandidate {
+                        ip: top.ip,
+                        nic: nic.clone(),
+                        reason: format!(
+                            "traffic ({:.2} Mbps) below threshold ({:.2} Mbps)",
+                            ranked_bps / 1_000_000.0,
+                            MIN_TRAFFIC_THRESHOLD / 1_000_000.0
+                        ),
+                    });
+                    // Everything ranked lower is lighter still
+                    break;
+                }
+                let rank = rank_label(index, top_rx.rank_by);
+
+                let moving_bps = top.rx_bps + top.tx_bps;
+                let selection = select_target_wan(
+                    top.ip,
+                    nic,
+                    moving_bps,
+                    &nic_stats,
+                    input.wan_to_nic,
+                    &clients_per_wan,
+                    input.wan_probes,
+                    input.link_quality,
+                    input.reservations,
+                    input.client_rules.preferred_wan(top.ip),
+                    input.config,
+                );
+
+                let Some(target_wan) = selection.target_wan else {
+                    let reason = if selection.candidates == 0 && selection.unhealthy > 0 {
+                        "every alternative WAN is failing its probes or intercepting traffic"
+                            .to_string()
+                    } else if selection.worse_link > 0 {
+                        "no alternative WAN beats the current one once loss and jitter count"
+                            .to_string()
+                    } else if selection.too_full > 0 {
+                        format!(
+                            "{:.2} Mbps would not fit into the headroom of any alternative WAN",
+                            moving_bps / 1_000_000.0
+                        )
+                    } else {
+                        "no alternative WAN has room under its client cap".to_string()
+                    };
+                    plan.skipped.push(SkippedCandidate {
+                        ip: top.ip,
+                        nic: nic.clone(),
+                        reason,
+                    });
+                    continue;
+                };
+
+                let target_headroom = input
+                    .wan_to_nic
+                    .get(&target_wan)
+                    .and_then(|target_nic| nic_stats.get(target_nic))
+                    .map_or(0.0, NicStats::headroom)
+                    - input.reservations.withheld_bps(&target_wan, top.ip);
+                let mut reason = match input
+                    .wan_probes
+                    .get(&target_wan)
+                    .and_then(|probe| probe.rtt_ms)
+                {
+                    Some(rtt_ms) if input.config.probes.is_some() => format!(
+                        "{} on {}; {} has the most RTT-adjusted headroom ({:.2} Mbps free, {:.1} ms RTT)",
+                        rank,
+                        nic,
+                        target_wan,
+                        target_headroom / 1_000_000.0,
+                        rtt_ms
+                    ),
+                    _ => format!(
+                        "{} on {}; {} has the most headroom ({:.2} Mbps free)",
+                        rank,
+                        nic,
+                        target_wan,
+                        target_headroom / 1_000_000.0
+                    ),
+                };
+                if input.client_rules.preferred_wan(top.ip) == Some(&target_wan) {
+                    reason = format!(
+                        "{} on {}; moving it to its preferred {} ({:.2} Mbps free)",
+                        rank,
+                        nic,
+                        target_wan,
+                        target_headroom / 1_000_000.0
+                    );
+                }
+                if let Some((capped_wan, cap)) = &selection.capped_preferred {
+                    reason = format!(
+                        "{} on {}; preferred target {} is at its client cap ({}), placement is not optimal",
+                        rank, nic, capped_wan, cap
+                    );
+                }
+
+                // Reserve the slot so later decisions this cycle see the new count
+                if let Some(previous_wan) = input.mappings.get(&top.ip) {
+                    if let Some(count) = clients_per_wan.get_mut(previous_wan) {
+                        *count = count.saturating_sub(1);
+                    }
+                }
+                *clients_per_wan.entry(target_wan.clone()).or_insert(0) += 1;
+                // Likewise move its traffic, so the next candidate sees the remaining headroom
+                if let Some(source) = nic_stats.get_mut(nic) {
+                    source.rx_bps -= top.rx_bps;
+                    source.tx_bps -= top.tx_bps;
+                }
+                if let Some(target) = input
+                    .wan_to_nic
+                    .get(&target_wan)
+                    .and_then(|target_nic| nic_stats.get_mut(target_nic))
+                {
+                    target.rx_bps += top.rx_bps;
+                    target.tx_bps += top.tx_bps;
+                }
+
+                plan.switches.push(SwitchDecision {
+                    ip: top.ip,
+                    from_nic: nic.clone(),
+                    target_wan,
+                    rx_bps: top.rx_bps,
+                    reason,
+                });
+            }
+        }
+
+        plan
+    }
+
+    fn wan_scores(&self, input: &PolicyInput) -> HashMap<WanId, f64> {
+        input
+            .wan_to_nic
+            .iter()
+            .filter_map(|(wan, nic)| {
+                let stats = input.nic_stats.get(nic)?;
+                let withheld_bps = input.reservations.unused_bps(wan);
+                let score = wan_score(
+                    wan,
+                    stats,
+                    withheld_bps,
+                    input.wan_probes,
+                    input.link_quality,
+                    input.config,
+                )
+                .unwrap_or(0.0);
+                Some((wan.clone(), score))
+            })
+            .collect()
+    }
+}
+
+/// Keeps each WAN's share of clients (or of their traffic) near its configured weight,
+/// moving one client per cycle off the most over-weight WAN once it leaves the tolerance.
+pub struct WeightedPolicy {
+    config: WeightedPolicyConfig,
+}
+
+impl WeightedPolicy {
+    fn weight(&self, wan: &WanId) -> f64 {
+        self.config
+            .weights
+            .get(wan)
+            .copied()
+            .unwrap_or(0.0)
+            .max(0.0)
+    }
+
+    fn client_load(&self, input: &PolicyInput, ip: ClientIp) -> f64 {
+        let load = match self.config.share_by {
+            ShareBy::Clients => 1.0,
+            ShareBy::Traffic => input
+                .ip_traffic
+                .iter()
+                .find(|traffic| traffic.ip == ip)
+                .map_or(0.0, |traffic| traffic.rx_bps + traffic.tx_bps),
+        };
+        load * input.client_rules.weight(ip)
+    }
+
+    /// Observed share minus target share per WAN, most over-weight first.
+    fn deviations<'a>(&self, input: &'a PolicyInput) -> Vec<(&'a WanId, f64)> {
+        let total_weight: f64 = input.wan_to_nic.keys().map(|wan| self.weight(wan)).sum();
+        let mut load: HashMap<&WanId, f64> =
+            input.wan_to_nic.keys().map(|wan| (wan, 0.0)).collect();
+        for (ip, wan) in input.mappings {
+            if let Some(wan_load) = load.get_mut(wan) {
+                *wan_load += self.client_load(input, *ip);
+            }
+        }
+        let total_load: f64 = load.values().sum();
+        if total_weight <= 0.0 || total_load <= 0.0 {
+            return Vec::new();
+        }
+
+        let mut deviations: Vec<(&WanId, f64)> = load
+            .into_iter()
+            .map(|(wan, wan_load)| (wan, wan_load / total_load - self.weight(wan) / total_weight))
+            .collect();
+        deviations.sort_by(|a, b| {
+            b.1.partial_cmp(&a.1)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then_with(|| a.0.cmp(b.0))
+        });
+        deviations
+    }
+}
+
+impl SwitchPolicy for WeightedPolicy {
+    fn name(&self) -> &'static str {
+        "weighted"
+    }
+
+    fn plan(&mut self, input: &PolicyInput) -> PolicyPlan {
+        let mut plan = PolicyPlan::default();
+        let deviations = self.deviations(input);
+        let Some((over_wan, over)) = deviations.first().copied() else {
+            return plan;
+        };
+        if over <= self.config.tolerance {
+            return plan;
+        }
+        let Some(from_nic) = input.wan_to_nic.get(over_wan) else {
+            return plan;
+        };
+
+        // Most under-weight WAN that can take another client
+        let Some((target_wan, under)) =
+            deviations.iter().rev().copied().find(|(wan, deviation)| {
+                let healthy = input.wan_to_nic.get(*wan).is_none_or(|nic| {
+                    input.nic_stats.get(nic).is_none_or(|stats| {
+                        wan_score(
+                            wan,
+                            stats,
+                            0.0,
+                            input.wan_probes,
+                            input.link_quality,
+                            input.config,
+                        )
+                        .is_some()
+                    })
+                });
+                let has_room = input
+                    .config
+                    .client_cap(wan)
+                    .is_none_or(|cap| input.clients_per_wan.get(*wan).copied().unwrap_or(0) < cap);
+                *deviation < 0.0 && healthy && has_room
+            })
+        else {
+            return plan;
+        };
+
+        // The client closest to the gap; moving one more than twice its size would only
+        // swap which WAN is over-weight
+        let total_load: f64 = input
+            .mappings
+            .keys()
+            .map(|ip| self.client_load(input, *ip))
+            .sum();
+        let gap = over.min(-under) * total_load;
+        let mut clients: Vec<(ClientIp, f64, f64)> = input
+            .mappings
+            .iter()
+            .filter(|(_, wan)| *wan == over_wan)
+            .map(|(ip, _)| {
+                let rx_bps = input
+                    .ip_traffic
+                    .iter()
+                    .find(|traffic| traffic.ip == *ip)
+                    .map_or(0.0, |traffic| traffic.rx_bps);
+                (*ip, self.client_load(input, *ip), rx_bps)
+            })
+            .filter(|(ip, _, _)| {
+                input
+                    .client_rules
+                    .policy_hold_reason(*ip, Some(over_wan))
+                    .is_none()
+            })
+            .filter(|(_, load, _)| *load > 0.0 && *load < 2.0 * gap)
+            .filter(|(ip, _, _)| fits_reservations(input, *ip, target_wan))
+            .collect();
+        clients.sort_by(|a, b| {
+            (a.1 - gap)
+                .abs()
+                .partial_cmp(&(b.1 - gap).abs())
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then(a.2.partial_cmp(&b.2).unwrap_or(std::cmp::Ordering::Equal))
+                .then_with(|| a.0.cmp(&b.0))
+        });
+        let Some((ip, _, rx_bps)) = clients.first() else {
+            return plan;
+        };
+
+        let total_weight: f64 = input.wan_to_nic.keys().map(|wan| self.weight(wan)).sum();
+        let unit = match self.config.share_by {
+            ShareBy::Clients => "clients",
+            ShareBy::Traffic => "traffic",
+        };
+        plan.switches.push(SwitchDecision {
+            ip: *ip,
+            from_nic: from_nic.clone(),
+            target_wan: target_wan.clone(),
+            rx_bps: *rx_bps,
+            reason: format!(
+                "{} has {:.0}% of {} (target {:.0}%); {} has {:.0}% (target {:.0}%)",
+                over_wan,
+                (over + self.weight(over_wan) / total_weight) * 100.0,
+                unit,
+                self.weight(over_wan) / total_weight * 100.0,
+                target_wan,
+                (under + self.weight(target_wan) / total_weight) * 100.0,
+                self.weight(target_wan) / total_weight * 100.0,
+            ),
+        });
+        plan
+    }
+
+    /// Share still missing to reach each WAN's target (negative when over-weight).
+    fn wan_scores(&self, input: &PolicyInput) -> HashMap<WanId, f64> {
+        self.deviations(input)
+            .into_iter()
+            .map(|(wan, deviation)| (wan.clone(), -deviation))
+            .collect()
+    }
+}
+
+/// Whether moving `ip` onto `wan` leaves the headroom other groups have reserved there intact.
+fn fits_reservations(input: &PolicyInput, ip: ClientIp, wan: &WanId) -> bool {
+    let withheld_bps = input.reservations.withheld_bps(wan, ip);
+    if withheld_bps <= 0.0 {
+        return true;
+    }
+    let headroom = input
+        .wan_to_nic
+        .get(wan)
+        .and_then(|nic| input.nic_stats.get(nic))
+        .map_or(0.0, NicStats::headroom);
+    let moving_bps = input
+        .ip_traffic
+        .iter()
+        .find(|traffic| traffic.ip == ip)
+        .map_or(0.0, |traffic| traffic.rx_bps + traffic.tx_bps);
+    moving_bps <= headroom - withheld_bps
+}
+
+/// Clients on `nic`, heaviest first by `[top_rx] rank_by`.
+fn ranked_clients<'a>(input: &PolicyInput<'a>, nic: &NicName) -> Vec<&'a IpTraffic> {
+    let rank_by = input.config.top_rx.rank_by;
+    let mut clients: Vec<&IpTraffic> = input
+        .ip_traffic
+        .iter()
+        .filter(|traffic| &traffic.nic == nic)
+        .collect();
+    clients.sort_by(|a, b| {
+        rank_bps(b, rank_by)
+            .partial_cmp(&rank_bps(a, rank_by))
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+    clients
+}
+
+fn rank_bps(traffic: &IpTraffic, rank_by: RankBy) -> f64 {
+    match rank_by {
+        RankBy::Rx => traffic.rx_bps,
+        RankBy::Total => traffic.rx_bps + traffic.tx_bps,
+    }
+}
+
+/// "top RX IP", "#2 RX IP", ... for the client at `index` of the ranking.
+fn rank_label(index: usize, rank_by: RankBy) -> String {
+    let measure = match rank_by {
+        RankBy::Rx => "RX",
+        RankBy::Total => "traffic",
+    };
+    match index {
+        0 => format!("top {} IP", measure),
+        _ => format!("#{} {} IP", index + 1, measure),
+    }
+}
+
+/// Outcome of choosing a target WAN while honouring per-WAN client caps and headroom.
+pub struct TargetSelection {
+    /// The WAN the IP should be moved to, if any WAN has room.
+    pub target_wan: Option<WanId>,
+    /// The best-headroom WAN that had to be passed over because it is at its cap.
+    pub capped_preferred: Option<(WanId, usize)>,
+    /// Alternative WANs that were ranked.
+    pub candidates: usize,
+    /// Alternative WANs left out because all of their latency probes failed or a captive
+    /// check marked them degraded.
+    pub unhealthy: usize,
+    /// Alternative WANs passed over because the moved traffic would exceed their headroom
+    /// (less what other groups have reserved).
+    pub too_full: usize,
+    /// Alternative WANs passed over because, with link quality enabled, their adjusted
+    /// headroom is no better than the current WAN's.
+    pub worse_link: usize,
+}
+
+/// Free headroom of a WAN (TCP bandwidth estimate minus observed traffic and
+/// `withheld_bps`), scaled down by its probed RTT beyond `rtt_reference_ms` and by its loss
+/// and jitter beyond their references; `None` when every probe of its last round failed or
+/// it is degraded.
+fn wan_score(
+    wan: &WanId,
+    stats: &NicStats,
+    withheld_bps: f64,
+    wan_probes: &HashMap<WanId, WanProbeStats>,
+    link_quality: &HashMap<WanId, LinkQuality>,
+    config: &Config,
+) -> Option<f64> {
+    let headroom = stats.headroom() - withheld_bps;
+    let mut factor = 1.0;
+    if let (Some(probes), Some(probe)) = (&config.probes, wan_probes.get(wan)) {
+        if probe.is_down() || probe.is_degraded() {
+            return None;
+        }
+        factor *= probe
+            .rtt_ms
+            .map_or(1.0, |rtt_ms| (probes.rtt_reference_ms / rtt_ms).min(1.0));
+    }
+    if let (Some(link_config), Some(quality)) = (&config.link_quality, link_quality.get(wan)) {
+        factor *= quality.factor(link_config);
+    }
+    // Scaling an overdraft down would make a slower, equally full WAN look better
+    Some(if headroom > 0.0 {
+        headroom * factor
+    } else {
+        headroom
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn select_target_wan(
+    ip: ClientIp,
+    current_nic: &NicName,
+    moving_bps: f64,
+    nic_stats: &HashMap<NicName, NicStats>,
+    wan_to_nic: &HashMap<WanId, NicName>,
+    clients_per_wan: &HashMap<WanId, usize>,
+    wan_probes: &HashMap<WanId, WanProbeStats>,
+    link_quality: &HashMap<WanId, LinkQuality>,
+    reservations: &ActiveReservations,
+    preferred_wan: Option<&WanId>,
+    config: &Config,
+) -> TargetSelection {
+    let mut unhealthy = 0;
+    let mut too_full = 0;
+    let mut worse_link = 0;
+
+    // With link quality in the scores, a fast but lossy WAN is only worth moving to when
+    // it still beats the current one
+    let current_score = wan_to_nic
+        .iter()
+        .filter(|_| config.link_quality.is_some())
+        .find(|(_, nic)| *nic == current_nic)
+        .and_then(|(wan, nic)| {
+            wan_score(
+                wan,
+                nic_stats.get(nic)?,
+                0.0,
+                wan_probes,
+                link_quality,
+                config,
+            )
+        });
+
+    // Rank the other WANs by (RTT- and quality-adjusted) headroom, most first
+    let mut candidates: Vec<(&WanId, f64)> = wan_to_nic
+        .iter()
+        .filter(|(_, nic)| *nic != current_nic)
+        .filter_map(|(wan, nic)| {
+            let withheld_bps = reservations.withheld_bps(wan, ip);
+            let score = wan_score(
+                wan,
+                nic_stats.get(nic)?,
+                withheld_bps,
+                wan_probes,
+                link_quality,
+                config,
+            );
+            if score.is_none() {
+                unhealthy += 1;
+            }
+            Some((wan, score?))
+        })
+        .collect();
+    candidates.sort_by(|a, b| {
+        b.1.partial_cmp(&a.1)
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then_with(|| a.0.cmp(b.0))
+    });
+    // A client rule's preferred WAN goes first whenever it is a healthy alternative
+    if let Some(index) = candidates
+        .iter()
+        .position(|(wan, _)| Some(*wan) == preferred_wan)
+    {
+        let preferred = candidates.remove(index);
+        candidates.insert(0, preferred);
+    }
+
+    let mut capped_preferred = None;
+
+    for (index, (wan, score)) in candidates.iter().enumerate() {
+        let mapped = clients_per_wan.get(*wan).copied().unwrap_or(0);
+        if let Some(cap) = config.client_cap(wan).filter(|cap| mapped >= *cap) {
+            if index == 0 {
+                capped_preferred = Some(((*wan).clone(), cap));
+            }
+            continue;
+        }
+
+        let headroom = wan_to_nic
+            .get(*wan)
+            .and_then(|nic| nic_stats.get(nic))
+            .map_or(0.0, NicStats::headroom)
+            - reservations.withheld_bps(wan, ip);
+        if moving_bps > headroom {
+            too_full += 1;
+            continue;
+        }
+        if current_score.is_some_and(|current| *score <= current) && Some(*wan) != preferred_wan {
+            worse_link += 1;
+            continue;
+        }
+
+        return TargetSelection {
+            target_wan: Some((*wan).clone()),
+            capped_preferred,
+            candidates: candidates.len(),
+            unhealthy,
+            too_full,
+            worse_link,
+        };
+    }
+
+    TargetSelection {
+        target_wan: None,
+        capped_preferred,
+        candidates: candidates.len(),
+        unhealthy,
+        too_full,
+        worse_link,
+    }
+}
@@ -0,0 +1,510 @@
+use crate::history_db::{
+    HistoryQuery, StoredClientTraffic, StoredNicStats, StoredProfile, StoredPublicIpChange,
+    StoredQuotaUsage, StoredResidency, StoredSwitch,
+};
+use crate::store::StateStore;
+use anyhow::{bail, Context, Result};
+use postgres::{Client, NoTls};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tokio::runtime::{Handle, RuntimeFlavor};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS switch_history (
+    id        BIGSERIAL PRIMARY KEY,
+    timestamp BIGINT NOT NULL,
+    ip        TEXT NOT NULL,
+    from_wan  TEXT,
+    to_wan    TEXT NOT NULL,
+    reason    TEXT NOT NULL,
+    result    TEXT NOT NULL,
+    error     TEXT,
+    verification TEXT
+);
+CREATE INDEX IF NOT EXISTS switch_history_ip_timestamp ON switch_history (ip, timestamp);
+CREATE INDEX IF NOT EXISTS switch_history_timestamp ON switch_history (timestamp);
+CREATE TABLE IF NOT EXISTS public_ip_history (
+    id        BIGSERIAL PRIMARY KEY,
+    timestamp BIGINT NOT NULL,
+    wan       TEXT NOT NULL,
+    previous  TEXT,
+    current   TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS public_ip_history_timestamp ON public_ip_history (timestamp);
+CREATE TABLE IF NOT EXISTS nic_stats_history (
+    timestamp BIGINT NOT NULL,
+    interval_secs BIGINT NOT NULL,
+    nic       TEXT NOT NULL,
+    wan       TEXT,
+    tcp_bandwidth_bps DOUBLE PRECISION NOT NULL,
+    tx_bps    DOUBLE PRECISION NOT NULL,
+    rx_bps    DOUBLE PRECISION NOT NULL,
+    clients   BIGINT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS nic_stats_history_timestamp ON nic_stats_history (timestamp);
+CREATE TABLE IF NOT EXISTS client_traffic_history (
+    timestamp BIGINT NOT NULL,
+    interval_secs BIGINT NOT NULL,
+    ip        TEXT NOT NULL,
+    rx_bps    DOUBLE PRECISION NOT NULL,
+    tx_bps    DOUBLE PRECISION NOT NULL
+);
+CREATE INDEX IF NOT EXISTS client_traffic_history_timestamp ON client_traffic_history (timestamp);
+CREATE TABLE IF NOT EXISTS cooldown_residencies (
+    ip        TEXT NOT NULL,
+    class     TEXT NOT NULL,
+    until     BIGINT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS quota_usage (
+    quota     TEXT NOT NULL,
+    ip        TEXT,
+    first_seen BIGINT NOT NULL,
+    samples   TEXT NOT NULL,
+    over      BOOLEAN NOT NULL
+);
+CREATE TABLE IF NOT EXISTS traffic_profiles (
+    ip        TEXT NOT NULL,
+    first_seen BIGINT NOT NULL,
+    samples   TEXT NOT NULL,
+    anomalous BOOLEAN NOT NULL
+);
+";
+
+/// The same tables as the SQLite history, in a Postgres schema, so several routers can keep
+/// their state on one server.
+pub struct PostgresStore {
+    /// Taken out by `close`.
+    client: Mutex<Option<Client>>,
+}
+
+impl PostgresStore {
+    pub fn connect(url: &str, schema: &str) -> Result<Self> {
+        let schema = format!("\"{}\"", schema.replace('"', "\"\""));
+        let client = blocking(|| -> Result<Client> {
+            let mut client =
+                Client::connect(url, NoTls).context("Failed to connect to Postgres")?;
+            client
+                .batch_execute(&format!(
+                    "CREATE SCHEMA IF NOT EXISTS {schema}; SET search_path TO {schema};"
+                ))
+                .with_context(|| format!("Failed to select Postgres schema {}", schema))?;
+            client
+                .batch_execute(SCHEMA)
+                .context("Failed to initialize Postgres schema")?;
+            Ok(client)
+        })?;
+
+        Ok(Self {
+            client: Mutex::new(Some(client)),
+        })
+    }
+
+    fn with_client<T: Send>(&self, f: impl FnOnce(&mut Client) -> Result<T> + Send) -> Result<T> {
+        blocking(|| match self.client.lock().unwrap().as_mut() {
+            Some(client) => f(client),
+            None => bail!("The Postgres store is closed"),
+        })
+    }
+}
+
+/// Runs `f`, which blocks on the synchronous client, off the async loop: the client drives
+/// its own runtime, which cannot start on a runtime thread.
+fn blocking<T: Send>(f: impl FnOnce() -> T + Send) -> T {
+    match Handle::try_current() {
+        Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => {
+            tokio::task::block_in_place(f)
+        }
+        Ok(_) => std::thread::scope(|scope| scope.spawn(f).join().unwrap()),
+        Err(_) => f(),
+    }
+}
+
+impl StateStore for PostgresStore {
+    fn insert_switch(&self, record: &StoredSwitch) -> Result<i64> {
+        self.with_client(|client| {
+            let row = client
+                .query_one(
+                    "INSERT INTO switch_history
+                         (timestamp, ip, from_wan, to_wan, reason, result, error, verification)
+                     VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+                     RETURNING id",
+                    &[
+                        &(record.timestamp as i64),
+                        &record.ip,
+                        &record.from_wan,
+                        &record.to_wan,
+                        &record.reason,
+                        &record.result,
+                        &record.error,
+                        &record.verification,
+                    ],
+                )
+                .context("Failed to insert switch history record")?;
+
+            Ok(row.get(0))
+        })
+    }
+
+    fn set_verification(&self, id: i64, verification: &str) -> Result<()> {
+        self.with_client(|client| {
+            client
+                .execute(
+                    "UPDATE switch_history SET verification = $1 WHERE id = $2",
+                    &[&verification, &id],
+                )
+                .context("Failed to update switch history record")?;
+
+            Ok(())
+        })
+    }
+
+    fn query_switches(&self, query: &HistoryQuery) -> Result<Vec<StoredSwitch>> {
+        self.with_client(|client| {
+            let rows = client
+                .query(
+                    "SELECT timestamp, ip, from_wan, to_wan, reason, result, error, verification
+                     FROM switch_history
+                     WHERE ($1::TEXT IS NULL OR ip = $1) AND ($2::BIGINT IS NULL OR timestamp >= $2)
+                     ORDER BY timestamp DESC, id DESC
+                     LIMIT $3",
+                    &[
+                        &query.ip,
+                        &query.since.map(|since| since as i64),
+                        &query.limit.map(|limit| limit as i64),
+                    ],
+                )
+                .context("Failed to read switch history")?;
+
+            Ok(rows
+                .iter()
+                .map(|row| StoredSwitch {
+                    timestamp: row.get::<_, i64>(0) as u64,
+                    ip: row.get(1),
+                    from_wan: row.get(2),
+                    to_wan: row.get(3),
+                    reason: row.get(4),
+                    result: row.get(5),
+                    error: row.get(6),
+                    verification: row.get(7),
+                })
+                .collect())
+        })
+    }
+
+    fn insert_public_ip_change(&self, change: &StoredPublicIpChange) -> Result<()> {
+        self.with_client(|client| {
+            client
+                .execute(
+                    "INSERT INTO public_ip_history (timestamp, wan, previous, current)
+                     VALUES ($1, $2, $3, $4)",
+                    &[
+                        &(change.timestamp as i64),
+                        &change.wan,
+                        &change.previous,
+                        &change.current,
+                    ],
+                )
+                .context("Failed to insert public IP change")?;
+
+            Ok(())
+        })
+    }
+
+    fn latest_public_ips(&self) -> Result<HashMap<String, String>> {
+        self.with_client(|client| {
+            let rows = client
+                .query(
+                    "SELECT DISTINCT ON (wan) wan, current FROM public_ip_history
+                     ORDER BY wan, id DESC",
+                    &[],
+                )
+                .context("Failed to read public IP history")?;
+
+            Ok(rows.iter().map(|row| (row.get(0), row.get(1))).collect())
+        })
+    }
+
+    fn query_public_ip_changes(&self, query: &HistoryQuery) -> Result<Vec<StoredPublicIpChange>> {
+        self.with_client(|client| {
+            let rows = client
+                .query(
+                    "SELECT timestamp, wan, previous, current
+                     FROM public_ip_history
+                     WHERE $1::BIGINT IS NULL OR timestamp >= $1
+                     ORDER BY timestamp DESC, id DESC
+                     LIMIT $2",
+                    &[
+                        &query.since.map(|since| since as i64),
+                        &query.limit.map(|limit| limit as i64),
+                    ],
+                )
+                .context("Failed to read public IP history")?;
+
+            Ok(rows
+                .iter()
+                .map(|row| StoredPublicIpChange {
+                    timestamp: row.get::<_, i64>(0) as u64,
+                    wan: row.get(1),
+                    previous: row.get(2),
+                    current: row.get(3),
+                })
+                .collect())
+        })
+    }
+
+    fn insert_nic_stats(&self, rows: &[StoredNicStats], retain_since: u64) -> Result<()> {
+        self.with_client(|client| {
+            let mut transaction = client.transaction()?;
+            for row in rows {
+                transaction
+                    .execute(
+                        "INSERT INTO nic_stats_history
+                             (timestamp, interval_secs, nic, wan, tcp_bandwidth_bps, tx_bps, rx_bps, clients)
+                         VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+                        &[
+                            &(row.timestamp as i64),
+                            &(row.interval_secs as i64),
+                            &row.nic,
+                            &row.wan,
+                            &row.tcp_bandwidth_bps,
+                            &row.tx_bps,
+                            &row.rx_bps,
+                            &(row.clients as i64),
+                        ],
+                    )
+                    .context("Failed to insert NIC stats")?;
+            }
+            transaction.execute(
+                "DELETE FROM nic_stats_history WHERE timestamp < $1",
+                &[&(retain_since as i64)],
+            )?;
+            transaction.commit().context("Failed to store NIC stats")
+        })
+    }
+
+    fn query_nic_stats(&self, since: Option<u64>) -> Result<Vec<StoredNicStats>> {
+        self.with_client(|client| {
+            let rows = client
+                .query(
+                    "SELECT timestamp, interval_secs, nic, wan, tcp_bandwidth_bps, tx_bps, rx_bps, clients
+                     FROM nic_stats_history
+                     WHERE $1::BIGINT IS NULL OR timestamp >= $1
+                     ORDER BY timestamp, nic",
+                    &[&since.map(|since| since as i64)],
+                )
+                .context("Failed to read NIC stats history")?;
+
+            Ok(rows
+                .iter()
+                .map(|row| StoredNicStats {
+                    timestamp: row.get::<_, i64>(0) as u64,
+                    interval_secs: row.get::<_, i64>(1) as u64,
+                    nic: row.get(2),
+                    wan: row.get(3),
+                    tcp_bandwidth_bps: row.get(4),
+                    tx_bps: row.get(5),
+                    rx_bps: row.get(6),
+                    clients: row.get::<_, i64>(7) as usize,
+                })
+                .collect())
+        })
+    }
+
+    fn insert_client_traffic(&self, rows: &[StoredClientTraffic], retain_since: u64) -> Result<()> {
+        self.with_client(|client| {
+            let mut transaction = client.transaction()?;
+            for row in rows {
+                transaction
+                    .execute(
+                        "INSERT INTO client_traffic_history (timestamp, interval_secs, ip, rx_bps, tx_bps)
+                         VALUES ($1, $2, $3, $4, $5)",
+                        &[
+                            &(row.timestamp as i64),
+                            &(row.interval_secs as i64),
+                            &row.ip,
+                            &row.rx_bps,
+                            &row.tx_bps,
+                        ],
+                    )
+                    .context("Failed to insert client traffic")?;
+            }
+            transaction.execute(
+                "DELETE FROM client_traffic_history WHERE timestamp < $1",
+                &[&(retain_since as i64)],
+            )?;
+            transaction
+                .commit()
+                .context("Failed to store client traffic")
+        })
+    }
+
+    fn query_client_traffic(&self, since: Option<u64>) -> Result<Vec<StoredClientTraffic>> {
+        self.with_client(|client| {
+            let rows = client
+                .query(
+                    "SELECT timestamp, interval_secs, ip, rx_bps, tx_bps
+                     FROM client_traffic_history
+                     WHERE $1::BIGINT IS NULL OR timestamp >= $1
+                     ORDER BY timestamp, ip",
+                    &[&since.map(|since| since as i64)],
+                )
+                .context("Failed to read client traffic history")?;
+
+            Ok(rows
+                .iter()
+                .map(|row| StoredClientTraffic {
+                    timestamp: row.get::<_, i64>(0) as u64,
+                    interval_secs: row.get::<_, i64>(1) as u64,
+                    ip: row.get(2),
+                    rx_bps: row.get(3),
+                    tx_bps: row.get(4),
+                })
+                .collect())
+        })
+    }
+
+    fn save_residencies(&self, rows: &[StoredResidency]) -> Result<()> {
+        self.with_client(|client| {
+            let mut transaction = client.transaction()?;
+            transaction.execute("DELETE FROM cooldown_residencies", &[])?;
+            for row in rows {
+                transaction
+                    .execute(
+                        "INSERT INTO cooldown_residencies (ip, class, until) VALUES ($1, $2, $3)",
+                        &[&row.ip, &row.class, &(row.until as i64)],
+                    )
+                    .context("Failed to insert cooldown residency")?;
+            }
+            transaction
+                .commit()
+                .context("Failed to store cooldown residencies")
+        })
+    }
+
+    fn load_residencies(&self) -> Result<Vec<StoredResidency>> {
+        self.with_client(|client| {
+            let rows = client
+                .query("SELECT ip, class, until FROM cooldown_residencies", &[])
+                .context("Failed to read cooldown residencies")?;
+
+            Ok(rows
+                .iter()
+                .map(|row| StoredResidency {
+                    ip: row.get(0),
+                    class: row.get(1),
+                    until: row.get::<_, i64>(2) as u64,
+                })
+                .collect())
+        })
+    }
+
+    fn save_quota_usage(&self, rows: &[StoredQuotaUsage]) -> Result<()> {
+        self.with_client(|client| {
+            let mut transaction = client.transaction()?;
+            transaction.execute("DELETE FROM quota_usage", &[])?;
+            for row in rows {
+                transaction
+                    .execute(
+                        "INSERT INTO quota_usage (quota, ip, first_seen, samples, over)
+                         VALUES ($1, $2, $3, $4, $5)",
+                        &[
+                            &row.quota,
+                            &row.ip,
+                            &(row.first_seen as i64),
+                            &serde_json::to_string(&row.samples)?,
+                            &row.over,
+                        ],
+                    )
+                    .context("Failed to insert quota usage")?;
+            }
+            transaction.commit().context("Failed to store quota usage")
+        })
+    }
+
+    fn load_quota_usage(&self) -> Result<Vec<StoredQuotaUsage>> {
+        self.with_client(|client| {
+            let rows = client
+                .query(
+                    "SELECT quota, ip, first_seen, samples, over FROM quota_usage",
+                    &[],
+                )
+                .context("Failed to read quota usage")?;
+
+            rows.iter()
+                .map(|row| {
+                    Ok(StoredQuotaUsage {
+                        quota: row.get(0),
+                        ip: row.get(1),
+                        first_seen: row.get::<_, i64>(2) as u64,
+                        samples: serde_json::from_str(row.get(3))
+                            .context("Unreadable quota usage samples")?,
+                        over: row.get(4),
+                    })
+                })
+                .collect()
+        })
+    }
+
+    fn save_profiles(&self, rows: &[StoredProfile]) -> Result<()> {
+        self.with_client(|client| {
+            let mut transaction = client.transaction()?;
+            transaction.execute("DELETE FROM traffic_profiles", &[])?;
+            for row in rows {
+                transaction
+                    .execute(
+                        "INSERT INTO traffic_profiles (ip, first_seen, samples, anomalous)
+                         VALUES ($1, $2, $3, $4)",
+                        &[
+                            &row.ip,
+                            &(row.first_seen as i64),
+                            &serde_json::to_string(&row.samples)?,
+                            &row.anomalous,
+                        ],
+                    )
+                    .context("Failed to insert traffic profile")?;
+            }
+            transaction
+                .commit()
+                .context("Failed to store traffic profiles")
+        })
+    }
+
+    fn load_profiles(&self) -> Result<Vec<StoredProfile>> {
+        self.with_client(|client| {
+            let rows = client
+                .query(
+                    "SELECT ip, first_seen, samples, anomalous FROM traffic_profiles",
+                    &[],
+                )
+                .context("Failed to read traffic profiles")?;
+
+            rows.iter()
+                .map(|row| {
+                    Ok(StoredProfile {
+                        ip: row.get(0),
+                        first_seen: row.get::<_, i64>(1) as u64,
+                        samples: serde_json::from_str(row.get(2))
+                            .context("Unreadable traffic profile samples")?,
+                        anomalous: row.get(3),
+                    })
+                })
+                .collect()
+        })
+    }
+
+    fn close(&self) -> Result<()> {
+        let Some(client) = self.client.lock().unwrap().take() else {
+            return Ok(());
+        };
+        blocking(|| client.close()).context("Failed to close the Postgres connection")
+    }
+}
+
+impl Drop for PostgresStore {
+    /// The client says goodbye to the server on drop, which blocks like any other call.
+    fn drop(&mut self) {
+        if let Some(client) = self.client.get_mut().unwrap().take() {
+            blocking(|| drop(client));
+        }
+    }
+}
@@ -0,0 +1,103 @@
+use crate::config::PredictionConfig;
+use crate::error::ConfigError;
+use crate::model::{NicName, NicStats};
+use std::collections::{HashMap, HashSet, VecDeque};
+use tracing::info;
+
+/// Fits a linear trend to each NIC's recent traffic and, where it reaches the NIC's capacity
+/// within `horizon_secs`, shows the policy the projected traffic instead of the current,
+/// so clients are moved off before the WAN saturates rather than after.
+pub struct Forecaster {
+    config: PredictionConfig,
+    /// NIC → `(timestamp, traffic_bps)` within the window, oldest first.
+    samples: HashMap<NicName, VecDeque<(u64, f64)>>,
+    /// NICs projected to exceed their capacity at the last cycle, to log each trend once.
+    projected: HashSet<NicName>,
+}
+
+impl Forecaster {
+    pub fn new(config: PredictionConfig) -> Result<Self, ConfigError> {
+        if config.min_samples < 2 || config.window_secs == 0 {
+            return Err(ConfigError::Invalid(
+                "[prediction] needs a positive window_secs and min_samples of at least 2"
+                    .to_string(),
+            ));
+        }
+        Ok(Self {
+            config,
+            samples: HashMap::new(),
+            projected: HashSet::new(),
+        })
+    }
+
+    /// Records `nic_stats` and returns them with the traffic of every NIC that trends past
+    /// its capacity within the horizon raised to the projection.
+    pub fn project(
+        &mut self,
+        now: u64,
+        nic_stats: &HashMap<NicName, NicStats>,
+    ) -> HashMap<NicName, NicStats> {
+        self.samples.retain(|nic, _| nic_stats.contains_key(nic));
+        let mut projected_stats = nic_stats.clone();
+        let mut projected = HashSet::new();
+        for (nic, stats) in projected_stats.iter_mut() {
+            let traffic_bps = stats.tx_bps + stats.rx_bps;
+            let samples = self.samples.entry(nic.clone()).or_default();
+            samples.push_back((now, traffic_bps));
+            while samples
+                .front()
+                .is_some_and(|(at, _)| at + self.config.window_secs <= now)
+            {
+                samples.pop_front();
+            }
+            if samples.len() < self.config.min_samples || stats.tcp_bandwidth <= 0.0 {
+                continue;
+            }
+            let Some(slope) = slope(samples).filter(|slope| *slope > 0.0) else {
+                continue;
+            };
+            let projected_bps = traffic_bps + slope * self.config.horizon_secs as f64;
+            // Already saturated: the current traffic says as much as a projection
+            if traffic_bps >= stats.tcp_bandwidth || projected_bps <= stats.tcp_bandwidth {
+                continue;
+            }
+
+            if !self.projected.contains(nic) {
+                info!(
+                    nic = %nic,
+                    traffic_mbps = traffic_bps / 1_000_000.0,
+                    capacity_mbps = stats.tcp_bandwidth / 1_000_000.0,
+                    secs_to_capacity = (stats.tcp_bandwidth - traffic_bps) / slope,
+                    "Traffic trending past capacity; balancing ahead of it"
+                );
+            }
+            projected.insert(nic.clone());
+            let scale = if traffic_bps > 0.0 {
+                projected_bps / traffic_bps
+            } else {
+                1.0
+            };
+            stats.tx_bps *= scale;
+            stats.rx_bps *= scale;
+        }
+        self.projected = projected;
+        projected_stats
+    }
+}
+
+/// Least-squares slope of `samples`, in bits per second per second.
+fn slope(samples: &VecDeque<(u64, f64)>) -> Option<f64> {
+    let count = samples.len() as f64;
+    let first = samples.front()?.0;
+    let mean_t = samples
+        .iter()
+        .map(|(at, _)| (at - first) as f64)
+        .sum::<f64>()
+        / count;
+    let mean_y = samples.iter().map(|(_, bps)| bps).sum::<f64>() / count;
+    let (covariance, variance) = samples.iter().fold((0.0, 0.0), |(cov, var), (at, bps)| {
+        let dt = (at - first) as f64 - mean_t;
+        (cov + dt * (bps - mean_y), var + dt * dt)
+    });
+    (variance > 0.0).then(|| covariance / variance)
+}
@@ -0,0 +1,604 @@
+use crate::clock::Clock;
+use crate::config::{CaptiveCheck, ProbeConfig, ProbeTarget};
+use crate::model::{NicName, WanId};
+use anyhow::{bail, Context, Result};
+use reqwest::Url;
+use serde::Serialize;
+use socket2::{Domain, Protocol, Socket, Type};
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpSocket, TcpStream, UdpSocket};
+use tokio::task::JoinSet;
+use tracing::{debug, info, warn};
+
+/// Weight of the latest round's RTT change in a WAN's jitter.
+const JITTER_GAIN: f64 = 0.25;
+
+/// Result of the latest probe round over one WAN.
+#[derive(Debug, Clone, Serialize)]
+pub struct WanProbeStats {
+    /// Mean RTT of the successful probes; `None` when every probe failed.
+    pub rtt_ms: Option<f64>,
+    /// Fraction of probes that failed or timed out.
+    pub loss: f64,
+    /// Smoothed change of the RTT from one round to the next; `None` until two rounds got
+    /// through.
+    pub jitter_ms: Option<f64>,
+    /// Captive portal or DNS hijacking found by a captive check; the WAN is then only used
+    /// for destination-rule traffic.
+    pub degraded: Option<String>,
+    pub probed_at: u64,
+    /// Probing is paused while the WAN's bandwidth is being measured; these are the
+    /// results of the last round before the pause.
+    pub paused: bool,
+}
+
+impl WanProbeStats {
+    /// Every probe of the last round failed.
+    pub fn is_down(&self) -> bool {
+        self.loss >= 1.0
+    }
+
+    pub fn is_degraded(&self) -> bool {
+        self.degraded.is_some()
+    }
+}
+
+/// Actively measures the RTT of every WAN by probing targets through its interface
+/// (`SO_BINDTODEVICE`), in the background.
+pub struct Prober {
+    interfaces: Arc<Mutex<HashMap<WanId, NicName>>>,
+    paused: Arc<Mutex<HashSet<WanId>>>,
+    results: Arc<Mutex<HashMap<WanId, WanProbeStats>>>,
+}
+
+impl Prober {
+    pub fn spawn(config: ProbeConfig, clock: Arc<dyn Clock>) -> Self {
+        let interfaces = Arc::new(Mutex::new(HashMap::new()));
+        let paused = Arc::new(Mutex::new(HashSet::new()));
+        let results = Arc::new(Mutex::new(HashMap::new()));
+        tokio::spawn(run(
+            config,
+            interfaces.clone(),
+            paused.clone(),
+            results.clone(),
+            clock,
+        ));
+        Self {
+            interfaces,
+            paused,
+            results,
+        }
+    }
+
+    /// WAN → interface assignment to probe; refreshed from the routing service each cycle.
+    pub fn set_interfaces(&self, wan_to_nic: &HashMap<WanId, NicName>) {
+        *self.interfaces.lock().unwrap() = wan_to_nic.clone();
+    }
+
+    /// WANs not to probe until further notice, e.g. while a speedtest runs over them.
+    pub fn set_paused(&self, wans: HashSet<WanId>) {
+        *self.paused.lock().unwrap() = wans;
+    }
+
+    pub fn snapshot(&self) -> HashMap<WanId, WanProbeStats> {
+        self.results.lock().unwrap().clone()
+    }
+}
+
+async fn run(
+    config: ProbeConfig,
+    interfaces: Arc<Mutex<HashMap<WanId, NicName>>>,
+    paused: Arc<Mutex<HashSet<WanId>>>,
+    results: Arc<Mutex<HashMap<WanId, WanProbeStats>>>,
+    clock: Arc<dyn Clock>,
+) {
+    let timeout = Duration::from_millis(config.timeout_ms);
+    let interval = Duration::from_secs(config.interval_secs.max(1));
+    let mut next_round = clock.now();
+
+    loop {
+        clock.sleep_until(next_round).await;
+        next_round = clock.now() + interval;
+        let paused = paused.lock().unwrap().clone();
+        let mut wans = interfaces.lock().unwrap().clone();
+        wans.retain(|wan, _| !paused.contains(wan));
+
+        let mut probes = JoinSet::new();
+        let mut checks = JoinSet::new();
+        for (wan, nic) in &wans {
+            let egress = Egress {
+                nic: nic.clone(),
+                fwmark: config.fwmark,
+            };
+            for target in config.targets.clone() {
+                let wan = wan.clone();
+                let egress = egress.clone();
+                probes.spawn(async move {
+                    let rtt = match tokio::time::timeout(timeout, probe(&target, &egress, timeout))
+                        .await
+                    {
+                        Ok(Ok(rtt)) => Some(rtt),
+                        Ok(Err(e)) => {
+                            debug!(wan = %wan, nic = %egress.nic, ?target, "Probe failed: {:#}", e);
+                            None
+                        }
+                        Err(_) => {
+                            debug!(wan = %wan, nic = %egress.nic, ?target, "Probe timed out");
+                            None
+                        }
+                    };
+                    (wan, rtt)
+                });
+            }
+            for check in config.captive_checks.clone() {
+                let wan = wan.clone();
+                let egress = egress.clone();
+                checks.spawn(async move {
+                    // An unreachable check target says nothing about interception
+                    let finding =
+                        match tokio::time::timeout(timeout, captive_check(&check, &egress)).await {
+                            Ok(Ok(finding)) => finding,
+                            Ok(Err(e)) => {
+                                debug!(
+                                    wan = %wan,
+                                    nic = %egress.nic,
+                                    ?check,
+                                    "Captive check failed: {:#}",
+                                    e
+                                );
+                                None
+                            }
+                            Err(_) => None,
+                        };
+                    (wan, finding)
+                });
+            }
+        }
+
+        let mut rounds: HashMap<WanId, Vec<Option<Duration>>> = HashMap::new();
+        while let Some(Ok((wan, rtt))) = probes.join_next().await {
+            rounds.entry(wan).or_default().push(rtt);
+        }
+        let mut findings: HashMap<WanId, String> = HashMap::new();
+        while let Some(Ok((wan, finding))) = checks.join_next().await {
+            if let Some(finding) = finding {
+                findings.entry(wan).or_insert(finding);
+            }
+        }
+
+        let probed_at = clock.unix_secs();
+        let mut round: HashMap<WanId, WanProbeStats> = rounds
+            .into_iter()
+            .map(|(wan, rtts)| {
+                let successes: Vec<f64> = rtts
+                    .iter()
+                    .flatten()
+                    .map(|rtt| rtt.as_secs_f64() * 1000.0)
+                    .collect();
+                let stats = WanProbeStats {
+                    rtt_ms: (!successes.is_empty())
+                        .then(|| successes.iter().sum::<f64>() / successes.len() as f64),
+                    loss: 1.0 - successes.len() as f64 / rtts.len() as f64,
+                    jitter_ms: None,
+                    degraded: None,
+                    probed_at,
+                    paused: false,
+                };
+                (wan, stats)
+            })
+            .collect();
+        // Without RTT targets, WANs are only known via their captive checks
+        for wan in wans.into_keys() {
+            let stats = round.entry(wan.clone()).or_insert(WanProbeStats {
+                rtt_ms: None,
+                loss: 0.0,
+                jitter_ms: None,
+                degraded: None,
+                probed_at,
+                paused: false,
+            });
+            stats.degraded = findings.remove(&wan);
+        }
+
+        let mut results = results.lock().unwrap();
+        // Paused WANs keep what they measured before the pause
+        for wan in &paused {
+            if let Some(previous) = results.get(wan) {
+                round.insert(
+                    wan.clone(),
+                    WanProbeStats {
+                        paused: true,
+                        ..previous.clone()
+                    },
+                );
+            }
+        }
+        for (wan, stats) in round.iter_mut().filter(|(_, stats)| !stats.paused) {
+            let Some(previous) = results.get(wan) else {
+                continue;
+            };
+            stats.jitter_ms = match (stats.rtt_ms, previous.rtt_ms) {
+                (Some(rtt_ms), Some(previous_rtt_ms)) => {
+                    let change = (rtt_ms - previous_rtt_ms).abs();
+                    Some(previous.jitter_ms.map_or(change, |jitter_ms| {
+                        jitter_ms + (change - jitter_ms) * JITTER_GAIN
+                    }))
+                }
+                _ => previous.jitter_ms,
+            };
+        }
+        for (wan, stats) in &round {
+            let was_degraded = results
+                .get(wan)
+                .is_some_and(|previous| previous.is_degraded());
+            match &stats.degraded {
+                Some(finding) if !was_degraded => {
+                    warn!(wan = %wan, finding = %finding, "WAN degraded: traffic is intercepted")
+                }
+                None if was_degraded => info!(wan = %wan, "WAN no longer intercepted"),
+                _ => {}
+            }
+        }
+        *results = round;
+    }
+}
+
+/// What a captive-portal / DNS-hijack check found wrong, if anything.
+async fn captive_check(check: &CaptiveCheck, egress: &Egress) -> Result<Option<String>> {
+    match check {
+        CaptiveCheck::Http { url, expect_status } => {
+            let (_, status) = http_head(url, egress).await?;
+            Ok((status != *expect_status).then(|| {
+                format!(
+                    "{} answered {} instead of {} (captive portal?)",
+                    url, status, expect_status
+                )
+            }))
+        }
+        CaptiveCheck::Dns {
+            server,
+            name,
+            expect,
+        } => {
+            let answers = dns_resolve(
+                *server,
+                name,
+                expect.first().is_some_and(IpAddr::is_ipv6),
+                egress,
+            )
+            .await?;
+            let hijacked = if expect.is_empty() {
+                !answers.is_empty()
+            } else {
+                !answers.iter().any(|answer| expect.contains(answer))
+            };
+            Ok(hijacked.then(|| {
+                let answers: Vec<String> = answers.iter().map(ToString::to_string).collect();
+                format!(
+                    "{} resolved {} to [{}] (DNS hijacking?)",
+                    server,
+                    name,
+                    answers.join(", ")
+                )
+            }))
+        }
+    }
+}
+
+/// How probe traffic leaves the router: through one WAN interface, optionally carrying a
+/// firewall mark so it can be told apart from client traffic (e.g. excluded from accounting).
+#[derive(Debug, Clone)]
+pub struct Egress {
+    nic: NicName,
+    fwmark: Option<u32>,
+}
+
+impl Egress {
+    pub fn new(nic: NicName, fwmark: Option<u32>) -> Self {
+        Self { nic, fwmark }
+    }
+
+    fn socket(&self, domain: Domain, ty: Type, protocol: Protocol) -> Result<Socket> {
+        let socket = Socket::new(domain, ty, Some(protocol))?;
+        socket
+            .bind_device(Some(self.nic.as_str().as_bytes()))
+            .with_context(|| format!("Failed to bind probe socket to {}", self.nic))?;
+        if let Some(fwmark) = self.fwmark {
+            socket
+                .set_mark(fwmark)
+                .context("Failed to set probe socket mark")?;
+        }
+        Ok(socket)
+    }
+}
+
+async fn probe(target: &ProbeTarget, egress: &Egress, timeout: Duration) -> Result<Duration> {
+    match target {
+        ProbeTarget::Icmp { host } => {
+            let host = *host;
+            let egress = egress.clone();
+            tokio::task::spawn_blocking(move || icmp_echo(host, &egress, timeout)).await?
+        }
+        ProbeTarget::Tcp { address } => {
+            let started = Instant::now();
+            connect(*address, egress).await?;
+            Ok(started.elapsed())
+        }
+        ProbeTarget::Http { url } => Ok(http_head(url, egress).await?.0),
+    }
+}
+
+async fn connect(address: SocketAddr, egress: &Egress) -> Result<TcpStream> {
+    let socket = egress.socket(Domain::for_address(address), Type::STREAM, Protocol::TCP)?;
+    socket.set_nonblocking(true)?;
+    TcpSocket::from_std_stream(socket.into())
+        .connect(address)
+        .await
+        .with_context(|| format!("Failed to connect to {}", address))
+}
+
+/// Time from sending a `HEAD` request until the status line arrives, and the status code
+/// (plain HTTP only).
+async fn http_head(url: &str, egress: &Egress) -> Result<(Duration, u16)> {
+    let url = Url::parse(url).with_context(|| format!("Invalid probe URL {}", url))?;
+    if url.scheme() != "http" {
+        bail!("Only http:// probe URLs are supported");
+    }
+    let host = url.host_str().context("Probe URL has no host")?;
+    let port = url.port_or_known_default().unwrap_or(80);
+    let address = tokio::net::lookup_host((host, port))
+        .await?
+        .next()
+        .with_context(|| format!("Failed to resolve {}", host))?;
+
+    let started = Instant::now();
+    let mut stream = connect(address, egress).await?;
+    let request = format!(
+        "HEAD {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: routingFlow\r\nConnection: close\r\n\r\n",
+        url.path(),
+        host
+    );
+    stream.write_all(request.as_bytes()).await?;
+
+    let mut status_line = [0u8; 12];
+    stream.read_exact(&mut status_line).await?;
+    let elapsed = started.elapsed();
+    let status = std::str::from_utf8(&status_line[9..12])
+        .ok()
+        .and_then(|status| status.parse().ok())
+        .filter(|_| status_line.starts_with(b"HTTP/"))
+        .with_context(|| format!("Unexpected response from {}", url))?;
+
+    Ok((elapsed, status))
+}
+
+/// Body of a plain-HTTP `GET` of `url` sent through `egress`.
+pub async fn http_get(url: &str, egress: &Egress) -> Result<String> {
+    let url = Url::parse(url).with_context(|| format!("Invalid URL {}", url))?;
+    if url.scheme() != "http" {
+        bail!("Only http:// URLs are supported");
+    }
+    let host = url.host_str().context("URL has no host")?;
+    let port = url.port_or_known_default().unwrap_or(80);
+    let address = tokio::net::lookup_host((host, port))
+        .await?
+        .next()
+        .with_context(|| format!("Failed to resolve {}", host))?;
+
+    let mut stream = connect(address, egress).await?;
+    // HTTP/1.0, so the body comes unchunked and ends with the connection
+    let request = format!(
+        "GET {} HTTP/1.0\r\nHost: {}\r\nUser-Agent: routingFlow\r\n\r\n",
+        url.path(),
+        host
+    );
+    stream.write_all(request.as_bytes()).await?;
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await?;
+
+    let response = String::from_utf8_lossy(&response);
+    let Some((head, body)) = response.split_once("\r\n\r\n") else {
+        bail!("Unexpected response from {}", url);
+    };
+    let status = head.split_whitespace().nth(1).unwrap_or_default();
+    if !head.starts_with("HTTP/") || !status.starts_with('2') {
+        bail!(
+            "{} answered {}",
+            url,
+            head.lines().next().unwrap_or_default()
+        );
+    }
+    Ok(body.to_string())
+}
+
+/// Downloads `url` through `egress` until it ends, `max_bytes` of body have arrived or
+/// `max_duration` has passed; the body bytes received and the time from the first of them
+/// to the last (plain HTTP only).
+pub async fn http_download(
+    url: &str,
+    egress: &Egress,
+    max_bytes: u64,
+    max_duration: Duration,
+) -> Result<(u64, Duration)> {
+    let url = Url::parse(url).with_context(|| format!("Invalid URL {}", url))?;
+    if url.scheme() != "http" {
+        bail!("Only http:// URLs are supported");
+    }
+    let host = url.host_str().context("URL has no host")?;
+    let port = url.port_or_known_default().unwrap_or(80);
+    let address = tokio::net::lookup_host((host, port))
+        .await?
+        .next()
+        .with_context(|| format!("Failed to resolve {}", host))?;
+
+    let mut stream = connect(address, egress).await?;
+    let request = format!(
+        "GET {} HTTP/1.0\r\nHost: {}\r\nUser-Agent: routingFlow\r\n\r\n",
+        url.path(),
+        host
+    );
+    stream.write_all(request.as_bytes()).await?;
+
+    let mut buffer = vec![0u8; 64 * 1024];
+    let mut head = Vec::new();
+    let mut received = 0u64;
+    let mut started: Option<Instant> = None;
+    let deadline = tokio::time::Instant::now() + max_duration;
+    while received < max_bytes {
+        let read = match tokio::time::timeout_at(deadline, stream.read(&mut buffer)).await {
+            Ok(read) => read?,
+            Err(_) => break,
+        };
+        if read == 0 {
+            break;
+        }
+        if started.is_some() {
+            received += read as u64;
+            continue;
+        }
+        head.extend_from_slice(&buffer[..read]);
+        let Some(end) = head.windows(4).position(|window| window == b"\r\n\r\n") else {
+            continue;
+        };
+        let status_line = String::from_utf8_lossy(&head[..end]);
+        let status_line = status_line.lines().next().unwrap_or_default();
+        let status = status_line.split_whitespace().nth(1).unwrap_or_default();
+        if !status_line.starts_with("HTTP/") || !status.starts_with('2') {
+            bail!("{} answered {}", url, status_line);
+        }
+        started = Some(Instant::now());
+        received = (head.len() - end - 4) as u64;
+    }
+    let Some(started) = started else {
+        bail!("No response body from {}", url);
+    };
+    Ok((received, started.elapsed()))
+}
+
+/// A/AAAA answers for `name` from `server`, queried through `egress`; empty for NXDOMAIN.
+async fn dns_resolve(
+    server: SocketAddr,
+    name: &str,
+    ipv6: bool,
+    egress: &Egress,
+) -> Result<Vec<IpAddr>> {
+    let socket = egress.socket(Domain::for_address(server), Type::DGRAM, Protocol::UDP)?;
+    socket.set_nonblocking(true)?;
+    let socket = UdpSocket::from_std(socket.into())?;
+    socket.connect(server).await?;
+
+    let id = std::process::id() as u16;
+    let query_type: u16 = if ipv6 { 28 } else { 1 };
+    let mut query = Vec::with_capacity(32 + name.len());
+    query.extend_from_slice(&id.to_be_bytes());
+    // Recursion desired, one question
+    query.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in name.trim_end_matches('.').split('.') {
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.push(0);
+    query.extend_from_slice(&query_type.to_be_bytes());
+    query.extend_from_slice(&1u16.to_be_bytes());
+    socket.send(&query).await?;
+
+    let mut response = [0u8; 1500];
+    loop {
+        let len = socket.recv(&mut response).await?;
+        let response = &response[..len];
+        if len >= 12 && response[..2] == id.to_be_bytes() {
+            return parse_dns_answers(response).context("Malformed DNS response");
+        }
+    }
+}
+
+fn parse_dns_answers(response: &[u8]) -> Option<Vec<IpAddr>> {
+    let rcode = response[3] & 0x0f;
+    // NXDOMAIN
+    if rcode == 3 {
+        return Some(Vec::new());
+    }
+    let questions = u16::from_be_bytes([response[4], response[5]]);
+    let answers = u16::from_be_bytes([response[6], response[7]]);
+
+    let mut offset = 12;
+    for _ in 0..questions {
+        offset = skip_dns_name(response, offset)? + 4;
+    }
+
+    let mut addresses = Vec::new();
+    for _ in 0..answers {
+        offset = skip_dns_name(response, offset)?;
+        let header = response.get(offset..offset + 10)?;
+        let record_type = u16::from_be_bytes([header[0], header[1]]);
+        let len = u16::from_be_bytes([header[8], header[9]]) as usize;
+        let data = response.get(offset + 10..offset + 10 + len)?;
+        match (record_type, len) {
+            (1, 4) => addresses.push(IpAddr::from(<[u8; 4]>::try_from(data).ok()?)),
+            (28, 16) => addresses.push(IpAddr::from(<[u8; 16]>::try_from(data).ok()?)),
+            _ => {}
+        }
+        offset += 10 + len;
+    }
+    Some(addresses)
+}
+
+/// Offset just past the (possibly compressed) name starting at `offset`.
+fn skip_dns_name(message: &[u8], mut offset: usize) -> Option<usize> {
+    loop {
+        let len = *message.get(offset)?;
+        match len {
+            0 => return Some(offset + 1),
+            len if len & 0xc0 == 0xc0 => return Some(offset + 2),
+            len => offset += 1 + len as usize,
+        }
+    }
+}
+
+/// One ICMP echo over an unprivileged ping socket (see `net.ipv4.ping_group_range`).
+fn icmp_echo(host: IpAddr, egress: &Egress, timeout: Duration) -> Result<Duration> {
+    let (domain, protocol, request_type, reply_type) = match host {
+        IpAddr::V4(_) => (Domain::IPV4, Protocol::ICMPV4, 8u8, 0u8),
+        IpAddr::V6(_) => (Domain::IPV6, Protocol::ICMPV6, 128u8, 129u8),
+    };
+    let socket = egress
+        .socket(domain, Type::DGRAM, protocol)
+        .context("Failed to open ICMP socket (is ping_group_range set?)")?;
+    // Datagram ping sockets behave like UDP sockets for send/recv
+    let socket = std::net::UdpSocket::from(socket);
+    socket.set_read_timeout(Some(timeout))?;
+
+    // The kernel fills in the identifier and, for IPv6, the checksum
+    let sequence = (std::process::id() as u16).to_be_bytes();
+    let mut packet = [request_type, 0, 0, 0, 0, 0, sequence[0], sequence[1]];
+    if host.is_ipv4() {
+        let checksum = icmp_checksum(&packet).to_be_bytes();
+        packet[2..4].copy_from_slice(&checksum);
+    }
+
+    let started = Instant::now();
+    socket.send_to(&packet, SocketAddr::new(host, 0))?;
+
+    let mut reply = [0u8; 1500];
+    loop {
+        let received = socket.recv(&mut reply).context("No ICMP echo reply")?;
+        if received >= 8 && reply[0] == reply_type && reply[6..8] == sequence {
+            return Ok(started.elapsed());
+        }
+    }
+}
+
+fn icmp_checksum(packet: &[u8]) -> u16 {
+    let mut sum: u32 = packet
+        .chunks(2)
+        .map(|pair| u32::from(u16::from_be_bytes([pair[0], *pair.get(1).unwrap_or(&0)])))
+        .sum();
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
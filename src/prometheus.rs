@@ -0,0 +1,317 @@
+use crate::config::{PrometheusConfig, QueryWindowConfig, TenantMode, WindowFunction};
+use crate::error::{ConfigError, MetricsError};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION};
+use reqwest::{Certificate, Client};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::str::FromStr;
+
+#[derive(Debug, Deserialize)]
+struct PrometheusResponse<T> {
+    data: PrometheusData<T>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PrometheusData<T> {
+    result: Vec<T>,
+}
+
+/// One series of an instant-vector query result.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PrometheusResult {
+    pub metric: HashMap<String, String>,
+    pub value: (f64, String),
+}
+
+impl PrometheusResult {
+    /// The value of label `name`, if present and valid as a `T`.
+    pub fn label<T: FromStr>(&self, name: &str) -> Option<T> {
+        self.metric.get(name)?.parse().ok()
+    }
+}
+
+/// One series of a range query result, oldest sample first.
+#[derive(Debug, Deserialize)]
+pub struct RangeResult {
+    pub metric: HashMap<String, String>,
+    pub values: Vec<(f64, String)>,
+}
+
+impl RangeResult {
+    fn samples(&self) -> impl Iterator<Item = (f64, f64)> + '_ {
+        self.values
+            .iter()
+            .filter_map(|(timestamp, value)| Some((*timestamp, value.parse::<f64>().ok()?)))
+            .filter(|(_, value)| value.is_finite())
+    }
+
+    /// Mean of the samples, like PromQL's `avg_over_time`.
+    pub fn avg_over_time(&self) -> Option<f64> {
+        let (count, sum) = self.samples().fold((0, 0.0), |(count, sum), (_, value)| {
+            (count + 1, sum + value)
+        });
+        (count > 0).then(|| sum / count as f64)
+    }
+
+    pub fn max_over_time(&self) -> Option<f64> {
+        self.samples().map(|(_, value)| value).reduce(f64::max)
+    }
+
+    /// Per-second increase of a counter, like PromQL's `rate` (a drop counts as a reset).
+    pub fn rate(&self) -> Option<f64> {
+        let mut samples = self.samples();
+        let (first_at, mut previous) = samples.next()?;
+        let (mut last_at, mut increase) = (first_at, 0.0);
+        for (timestamp, value) in samples {
+            increase += if value >= previous {
+                value - previous
+            } else {
+                value
+            };
+            previous = value;
+            last_at = timestamp;
+        }
+        (last_at > first_at).then(|| increase / (last_at - first_at))
+    }
+}
+
+/// How requests to Prometheus authenticate.
+enum Auth {
+    None,
+    Basic {
+        username: String,
+        password: Option<String>,
+    },
+    Bearer(String),
+}
+
+/// Client of the Prometheus HTTP API, with the configured TLS settings and credentials.
+pub struct PrometheusClient {
+    client: Client,
+    api_url: String,
+    auth: Auth,
+    /// `&name=value` of the configured extra query parameters, already encoded.
+    extra_params: String,
+}
+
+impl PrometheusClient {
+    pub fn new(config: &PrometheusConfig) -> Result<Self, ConfigError> {
+        let mut headers = HeaderMap::new();
+        for (name, value) in &config.headers {
+            let name = HeaderName::try_from(name.as_str()).map_err(|e| {
+                ConfigError::Invalid(format!("Invalid Prometheus header {}: {}", name, e))
+            })?;
+            let mut value = HeaderValue::try_from(value.as_str()).map_err(|e| {
+                ConfigError::Invalid(format!(
+                    "Invalid value of Prometheus header {}: {}",
+                    name, e
+                ))
+            })?;
+            value.set_sensitive(true);
+            headers.insert(name, value);
+        }
+        let mut base_url = config.url.trim_end_matches('/').to_string();
+        if let Some(tenant) = &config.tenant {
+            match config.tenant_mode {
+                TenantMode::Header => {
+                    let name =
+                        HeaderName::try_from(config.tenant_header.as_str()).map_err(|e| {
+                            ConfigError::Invalid(format!(
+                                "Invalid prometheus.tenant_header {}: {}",
+                                config.tenant_header, e
+                            ))
+                        })?;
+                    let value = HeaderValue::try_from(tenant.as_str()).map_err(|e| {
+                        ConfigError::Invalid(format!("Invalid prometheus.tenant: {}", e))
+                    })?;
+                    headers.insert(name, value);
+                }
+                TenantMode::Path => {
+                    // `accountID` or `accountID:projectID`, kept as is
+                    if tenant.contains(['/', '?', '#']) {
+                        return Err(ConfigError::Invalid(format!(
+                            "Invalid prometheus.tenant {} for the path mode",
+                            tenant
+                        )));
+                    }
+                    base_url = format!("{}/select/{}/prometheus", base_url, tenant);
+                }
+            }
+        }
+
+        let mut builder = Client::builder()
+            .default_headers(headers.clone())
+            .danger_accept_invalid_certs(config.insecure_skip_verify);
+        if let Some(ca_file) = &config.ca_file {
+            let pem = std::fs::read(ca_file).map_err(|e| {
+                ConfigError::Invalid(format!(
+                    "Failed to read Prometheus CA file {}: {}",
+                    ca_file.display(),
+                    e
+                ))
+            })?;
+            let certificate = Certificate::from_pem(&pem).map_err(|e| {
+                ConfigError::Invalid(format!(
+                    "Invalid Prometheus CA file {}: {}",
+                    ca_file.display(),
+                    e
+                ))
+            })?;
+            builder = builder.add_root_certificate(certificate);
+        }
+        let client = builder.build().map_err(|e| {
+            ConfigError::Invalid(format!("Failed to set up Prometheus client: {}", e))
+        })?;
+
+        let bearer_token =
+            match (&config.bearer_token, &config.bearer_token_file) {
+                (Some(_), Some(_)) => return Err(ConfigError::Invalid(
+                    "Set either prometheus.bearer_token or prometheus.bearer_token_file, not both"
+                        .to_string(),
+                )),
+                (Some(token), None) => Some(token.clone()),
+                (None, Some(path)) => Some(
+                    std::fs::read_to_string(path)
+                        .map_err(|e| {
+                            ConfigError::Invalid(format!(
+                                "Failed to read Prometheus bearer token file {}: {}",
+                                path.display(),
+                                e
+                            ))
+                        })?
+                        .trim()
+                        .to_string(),
+                ),
+                (None, None) => None,
+            };
+        let auth = match (&config.username, bearer_token) {
+            (Some(_), Some(_)) => {
+                return Err(ConfigError::Invalid(
+                    "Prometheus basic auth and bearer token are mutually exclusive".to_string(),
+                ))
+            }
+            (Some(username), None) => Auth::Basic {
+                username: username.clone(),
+                password: config.password.clone(),
+            },
+            (None, Some(token)) => Auth::Bearer(token),
+            (None, None) => Auth::None,
+        };
+        if !matches!(auth, Auth::None) && headers.contains_key(AUTHORIZATION) {
+            return Err(ConfigError::Invalid(
+                "Prometheus credentials and an Authorization header are mutually exclusive"
+                    .to_string(),
+            ));
+        }
+
+        Ok(Self {
+            client,
+            api_url: format!("{}/api/v1", base_url),
+            auth,
+            extra_params: config
+                .query_params
+                .iter()
+                .map(|(name, value)| {
+                    format!(
+                        "&{}={}",
+                        urlencoding::encode(name),
+                        urlencoding::encode(value)
+                    )
+                })
+                .collect(),
+        })
+    }
+
+    /// Runs an instant query.
+    pub async fn query(&self, query: &str) -> Result<Vec<PrometheusResult>, MetricsError> {
+        self.fetch(&format!(
+            "{}/query?query={}{}",
+            self.api_url,
+            urlencoding::encode(query),
+            self.extra_params
+        ))
+        .await
+    }
+
+    /// Runs a range query over `[start, end]` (Unix seconds) at `step_secs` resolution.
+    pub async fn query_range(
+        &self,
+        query: &str,
+        start: u64,
+        end: u64,
+        step_secs: u64,
+    ) -> Result<Vec<RangeResult>, MetricsError> {
+        self.fetch(&format!(
+            "{}/query_range?query={}&start={}&end={}&step={}{}",
+            self.api_url,
+            urlencoding::encode(query),
+            start,
+            end,
+            step_secs.max(1),
+            self.extra_params
+        ))
+        .await
+    }
+
+    /// Evaluates `query` over the window ending at `now` and reduces every series to one
+    /// value, shaped like an instant query result. Series without enough samples are dropped.
+    pub async fn query_window(
+        &self,
+        query: &str,
+        window: &QueryWindowConfig,
+        now: u64,
+    ) -> Result<Vec<PrometheusResult>, MetricsError> {
+        let start = now.saturating_sub(window.window_secs);
+        let series = self
+            .query_range(query, start, now, window.step_secs)
+            .await?;
+
+        Ok(series
+            .into_iter()
+            .filter_map(|series| {
+                let value = match window.function {
+                    WindowFunction::AvgOverTime => series.avg_over_time(),
+                    WindowFunction::MaxOverTime => series.max_over_time(),
+                    WindowFunction::Rate => series.rate(),
+                }?;
+                let sampled_at = series.values.last()?.0;
+                Some(PrometheusResult {
+                    metric: series.metric,
+                    value: (sampled_at, value.to_string()),
+                })
+            })
+            .collect())
+    }
+
+    async fn fetch<T: DeserializeOwned>(&self, url: &str) -> Result<Vec<T>, MetricsError> {
+        let request = self.client.get(url);
+        let request = match &self.auth {
+            Auth::None => request,
+            Auth::Basic { username, password } => request.basic_auth(username, password.as_ref()),
+            Auth::Bearer(token) => request.bearer_auth(token),
+        };
+        let response = request
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(MetricsError::Unreachable)?;
+
+        let prom_response: PrometheusResponse<T> = response
+            .json()
+            .await
+            .map_err(|e| MetricsError::Malformed(e.to_string()))?;
+
+        Ok(prom_response.data.result)
+    }
+}
+
+/// Fails with [`MetricsError::Stale`] when a sample taken at Unix time `sampled_at` is more
+/// than `max_age_secs` old at `now`.
+pub fn check_fresh(sampled_at: f64, now: u64, max_age_secs: u64) -> Result<(), MetricsError> {
+    let age_secs = now.saturating_sub(sampled_at as u64);
+    if age_secs > max_age_secs {
+        return Err(MetricsError::Stale { age_secs });
+    }
+    Ok(())
+}
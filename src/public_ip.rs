@@ -0,0 +1,108 @@
+use crate::config::{PublicIpConfig, PublicIpSource};
+use crate::model::{NicName, WanId};
+use crate::probe::{self, Egress};
+use crate::routing::RoutingService;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::JoinSet;
+use tracing::{debug, warn};
+
+/// Finds out the public address of every WAN in the background, from the routing service
+/// or from an echo service requested out of each WAN interface. A changed address (a
+/// CGNAT pool reassignment, a new DHCP lease upstream) often explains clients breaking
+/// after a switch.
+pub struct PublicIpWatcher {
+    interfaces: Arc<Mutex<HashMap<WanId, NicName>>>,
+    addresses: Arc<Mutex<HashMap<WanId, IpAddr>>>,
+}
+
+impl PublicIpWatcher {
+    pub fn spawn(config: PublicIpConfig, routing: RoutingService) -> Self {
+        let interfaces = Arc::new(Mutex::new(HashMap::new()));
+        let addresses = Arc::new(Mutex::new(HashMap::new()));
+        tokio::spawn(run(config, routing, interfaces.clone(), addresses.clone()));
+        Self {
+            interfaces,
+            addresses,
+        }
+    }
+
+    /// WAN → interface assignment to ask out of; refreshed from the routing service each cycle.
+    pub fn set_interfaces(&self, wan_to_nic: &HashMap<WanId, NicName>) {
+        *self.interfaces.lock().unwrap() = wan_to_nic.clone();
+    }
+
+    /// The latest address found per WAN.
+    pub fn snapshot(&self) -> HashMap<WanId, IpAddr> {
+        self.addresses.lock().unwrap().clone()
+    }
+}
+
+async fn run(
+    config: PublicIpConfig,
+    routing: RoutingService,
+    interfaces: Arc<Mutex<HashMap<WanId, NicName>>>,
+    addresses: Arc<Mutex<HashMap<WanId, IpAddr>>>,
+) {
+    let timeout = Duration::from_millis(config.timeout_ms);
+    let mut interval = tokio::time::interval(Duration::from_secs(config.interval_secs.max(1)));
+
+    loop {
+        interval.tick().await;
+        // The first tick fires at once, before the monitor has read the interfaces
+        let wans = interfaces.lock().unwrap().clone();
+        if wans.is_empty() {
+            interval.reset_after(Duration::from_secs(1));
+            continue;
+        }
+
+        let found = match &config.source {
+            PublicIpSource::Backend { path } => {
+                match tokio::time::timeout(timeout, routing.public_ips(path)).await {
+                    Ok(Ok(found)) => found,
+                    Ok(Err(e)) => {
+                        warn!("Failed to read public IPs from the routing service: {}", e);
+                        continue;
+                    }
+                    Err(_) => {
+                        warn!("Routing service did not answer with the public IPs in time");
+                        continue;
+                    }
+                }
+            }
+            PublicIpSource::Echo { url } => {
+                let mut requests = JoinSet::new();
+                for (wan, nic) in wans {
+                    let egress = Egress::new(nic.clone(), config.fwmark);
+                    let url = url.clone();
+                    requests.spawn(async move {
+                        let body = tokio::time::timeout(timeout, probe::http_get(&url, &egress))
+                            .await
+                            .unwrap_or_else(|_| Err(anyhow::anyhow!("timed out")));
+                        let address = body.and_then(|body| {
+                            body.trim().parse::<IpAddr>().map_err(|_| {
+                                anyhow::anyhow!("answered {:?}, not an address", body.trim())
+                            })
+                        });
+                        (wan, nic, address)
+                    });
+                }
+                let mut found = HashMap::new();
+                while let Some(Ok((wan, nic, address))) = requests.join_next().await {
+                    match address {
+                        Ok(address) => {
+                            found.insert(wan, address);
+                        }
+                        Err(e) => {
+                            debug!(wan = %wan, nic = %nic, "Public IP lookup failed: {:#}", e)
+                        }
+                    }
+                }
+                found
+            }
+        };
+        addresses.lock().unwrap().extend(found);
+    }
+}
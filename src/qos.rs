@@ -0,0 +1,91 @@
+use crate::config::QosConfig;
+use crate::model::NicName;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::SystemTime;
+
+/// Queue / shaper counters the routing service reports for one interface.
+#[derive(Debug, Clone, Deserialize)]
+pub struct QueueCounters {
+    /// Packets waiting in the interface's queues.
+    #[serde(default)]
+    pub backlog_packets: u64,
+    #[serde(default)]
+    pub backlog_bytes: u64,
+    /// Packets dropped since the queue was created.
+    #[serde(default)]
+    pub drops: u64,
+}
+
+/// Response of the routing service's QoS endpoint.
+#[derive(Debug, Deserialize)]
+pub struct QosResponse {
+    pub interfaces: HashMap<NicName, QueueCounters>,
+}
+
+/// Queue state of one NIC for this cycle.
+#[derive(Debug, Clone, Serialize)]
+pub struct QueueState {
+    pub backlog_packets: u64,
+    pub backlog_bytes: u64,
+    /// Drop rate since the previous reading; absent on the first one.
+    pub drops_per_sec: Option<f64>,
+    /// Backlog or drops over the configured thresholds.
+    pub congested: bool,
+}
+
+/// Turns the routing service's queue counters into per-NIC congestion verdicts.
+pub struct QueueMonitor {
+    config: QosConfig,
+    /// NIC → (drop counter, read at)
+    last_drops: HashMap<NicName, (u64, SystemTime)>,
+}
+
+impl QueueMonitor {
+    pub fn new(config: QosConfig) -> Self {
+        Self {
+            config,
+            last_drops: HashMap::new(),
+        }
+    }
+
+    pub fn path(&self) -> &str {
+        &self.config.path
+    }
+
+    pub fn update(
+        &mut self,
+        now: SystemTime,
+        response: QosResponse,
+    ) -> HashMap<NicName, QueueState> {
+        self.last_drops
+            .retain(|nic, _| response.interfaces.contains_key(nic));
+
+        response
+            .interfaces
+            .into_iter()
+            .map(|(nic, counters)| {
+                let drops_per_sec = self
+                    .last_drops
+                    .insert(nic.clone(), (counters.drops, now))
+                    .and_then(|(drops, at)| {
+                        let elapsed = now.duration_since(at).unwrap_or_default().as_secs_f64();
+                        // A counter that went backwards was reset; skip that interval
+                        (elapsed > 0.0 && counters.drops >= drops)
+                            .then(|| (counters.drops - drops) as f64 / elapsed)
+                    });
+                let congested = counters.backlog_packets >= self.config.backlog_packets
+                    || drops_per_sec.is_some_and(|rate| rate >= self.config.drops_per_sec);
+                (
+                    nic,
+                    QueueState {
+                        backlog_packets: counters.backlog_packets,
+                        backlog_bytes: counters.backlog_bytes,
+                        drops_per_sec,
+                        congested,
+                    },
+                )
+            })
+            .collect()
+    }
+}
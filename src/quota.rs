@@ -0,0 +1,242 @@
+use crate::config::{QuotaConfig, QuotaScope};
+use crate::error::ConfigError;
+use crate::history_db::StoredQuotaUsage;
+use crate::model::{ClientIp, WanId};
+use crate::policy::{PolicyInput, SwitchDecision};
+use std::collections::{HashMap, VecDeque};
+
+/// A client or group found routinely over its quota this cycle, or back under it.
+#[derive(Debug, Clone)]
+pub struct QuotaChange {
+    pub quota: String,
+    /// `None` for a group quota.
+    pub ip: Option<ClientIp>,
+    pub exceeded: bool,
+    /// Average over the quota's window.
+    pub traffic_bps: f64,
+    pub quota_bps: f64,
+    pub bulk_wan: WanId,
+}
+
+#[derive(Debug, Default)]
+pub struct QuotaPlan {
+    /// Client IP → bulk WAN it must stay on; other moves of these clients are dropped.
+    pub steered: HashMap<ClientIp, WanId>,
+    /// Moves of steered clients that are not on their bulk WAN yet.
+    pub switches: Vec<SwitchDecision>,
+    pub changes: Vec<QuotaChange>,
+}
+
+/// Recent cycles of one client, or of a whole group, against its quota.
+#[derive(Debug)]
+struct Usage {
+    first_seen: u64,
+    /// `(timestamp, traffic_bps)` within the window, oldest first.
+    samples: VecDeque<(u64, f64)>,
+    over: bool,
+}
+
+/// Sends clients that are routinely over their quota to the quota's bulk WAN, judging each
+/// on the cycles of the last `window_secs` rather than on a single busy one.
+pub struct Quotas {
+    quotas: Vec<(QuotaConfig, HashMap<Option<ClientIp>, Usage>)>,
+}
+
+impl Quotas {
+    pub fn new(quotas: &[QuotaConfig]) -> Result<Self, ConfigError> {
+        for quota in quotas {
+            if quota.prefixes.is_empty() || quota.mbps <= 0.0 {
+                return Err(ConfigError::Invalid(format!(
+                    "Quota {} needs at least one prefix and a positive mbps",
+                    quota.name
+                )));
+            }
+            if quota.window_secs == 0 || !(quota.over_fraction > 0.0 && quota.over_fraction <= 1.0)
+            {
+                return Err(ConfigError::Invalid(format!(
+                    "Quota {} needs a positive window_secs and an over_fraction in (0, 1]",
+                    quota.name
+                )));
+            }
+        }
+
+        Ok(Self {
+            quotas: quotas
+                .iter()
+                .map(|quota| (quota.clone(), HashMap::new()))
+                .collect(),
+        })
+    }
+
+    pub fn export(&self) -> Vec<StoredQuotaUsage> {
+        self.quotas
+            .iter()
+            .flat_map(|(config, usage)| {
+                usage.iter().map(|(subject, usage)| StoredQuotaUsage {
+                    quota: config.name.clone(),
+                    ip: subject.map(|ip| ip.to_string()),
+                    first_seen: usage.first_seen,
+                    samples: usage.samples.iter().copied().collect(),
+                    over: usage.over,
+                })
+            })
+            .collect()
+    }
+
+    /// Takes back the usage of the quotas still configured, without the samples that fell
+    /// out of their windows by `now`.
+    pub fn restore(&mut self, rows: Vec<StoredQuotaUsage>, now: u64) {
+        for row in rows {
+            let Some((config, usage)) = self
+                .quotas
+                .iter_mut()
+                .find(|(config, _)| config.name == row.quota)
+            else {
+                continue;
+            };
+            let subject = match (&row.ip, config.per) {
+                (Some(ip), QuotaScope::Client) => match ip.parse() {
+                    Ok(ip) => Some(ip),
+                    Err(_) => continue,
+                },
+                (None, QuotaScope::Group) => None,
+                _ => continue,
+            };
+            let samples: VecDeque<(u64, f64)> = row
+                .samples
+                .into_iter()
+                .filter(|(at, _)| at + config.window_secs > now)
+                .collect();
+            if samples.is_empty() {
+                continue;
+            }
+            usage.insert(
+                subject,
+                Usage {
+                    first_seen: row.first_seen,
+                    samples,
+                    over: row.over,
+                },
+            );
+        }
+    }
+
+    /// Records this cycle's traffic and plans the moves onto the bulk WANs. A client under
+    /// several quotas follows the first one it is over.
+    pub fn plan(&mut self, input: &PolicyInput, now: u64) -> QuotaPlan {
+        let traffic: HashMap<ClientIp, (f64, f64)> = input
+            .ip_traffic
+            .iter()
+            .map(|traffic| (traffic.ip, (traffic.rx_bps, traffic.tx_bps)))
+            .collect();
+        let total_bps = |ip: &ClientIp| traffic.get(ip).map_or(0.0, |(rx, tx)| rx + tx);
+
+        let mut plan = QuotaPlan::default();
+        for (config, usage) in &mut self.quotas {
+            let quota_bps = config.mbps * 1_000_000.0;
+            let mut members: Vec<ClientIp> = input
+                .mappings
+                .keys()
+                .copied()
+                .filter(|ip| {
+                    config
+                        .prefixes
+                        .iter()
+                        .any(|prefix| prefix.contains(&ip.addr()))
+                })
+                .collect();
+            members.sort();
+            let subjects: Vec<(Option<ClientIp>, f64)> = match config.per {
+                QuotaScope::Client => members
+                    .iter()
+                    .map(|ip| (Some(*ip), total_bps(ip)))
+                    .collect(),
+                QuotaScope::Group => vec![(None, members.iter().map(total_bps).sum())],
+            };
+            // Clients that went away start over when they come back
+            usage.retain(|subject, _| subject.is_none_or(|ip| members.binary_search(&ip).is_ok()));
+
+            for (subject, bps) in subjects {
+                let usage = usage.entry(subject).or_insert_with(|| Usage {
+                    first_seen: now,
+                    samples: VecDeque::new(),
+                    over: false,
+                });
+                usage.samples.push_back((now, bps));
+                while usage
+                    .samples
+                    .front()
+                    .is_some_and(|(at, _)| at + config.window_secs <= now)
+                {
+                    usage.samples.pop_front();
+                }
+                let samples = usage.samples.len() as f64;
+                let over_share = usage
+                    .samples
+                    .iter()
+                    .filter(|(_, bps)| *bps > quota_bps)
+                    .count() as f64
+                    / samples;
+                let average_bps = usage.samples.iter().map(|(_, bps)| bps).sum::<f64>() / samples;
+
+                // Not judged before a whole window has been seen
+                let was_over = usage.over;
+                usage.over = if was_over {
+                    over_share >= config.over_fraction / 2.0
+                } else {
+                    now.saturating_sub(usage.first_seen) >= config.window_secs
+                        && over_share >= config.over_fraction
+                };
+                if usage.over != was_over {
+                    plan.changes.push(QuotaChange {
+                        quota: config.name.clone(),
+                        ip: subject,
+                        exceeded: usage.over,
+                        traffic_bps: average_bps,
+                        quota_bps,
+                        bulk_wan: config.bulk_wan.clone(),
+                    });
+                }
+                if !usage.over {
+                    continue;
+                }
+
+                let steered = match subject {
+                    Some(ip) => vec![ip],
+                    None => members.clone(),
+                };
+                for ip in steered {
+                    if plan.steered.contains_key(&ip) {
+                        continue;
+                    }
+                    plan.steered.insert(ip, config.bulk_wan.clone());
+                    let Some(current_wan) = input.mappings.get(&ip) else {
+                        continue;
+                    };
+                    if *current_wan == config.bulk_wan
+                        || !input.wan_to_nic.contains_key(&config.bulk_wan)
+                    {
+                        continue;
+                    }
+                    let Some(from_nic) = input.wan_to_nic.get(current_wan) else {
+                        continue;
+                    };
+                    plan.switches.push(SwitchDecision {
+                        ip,
+                        from_nic: from_nic.clone(),
+                        target_wan: config.bulk_wan.clone(),
+                        rx_bps: traffic.get(&ip).map_or(0.0, |(rx, _)| *rx),
+                        reason: format!(
+                            "{} routinely over quota {} ({:.2} Mbps on average, quota {:.2} Mbps)",
+                            if subject.is_some() { "client" } else { "group" },
+                            config.name,
+                            average_bps / 1_000_000.0,
+                            config.mbps
+                        ),
+                    });
+                }
+            }
+        }
+        plan
+    }
+}
@@ -0,0 +1,53 @@
+use crate::config::SwitchRateLimitConfig;
+use crate::error::ConfigError;
+use std::collections::VecDeque;
+
+/// Length of the window switches are counted over.
+const WINDOW_SECS: u64 = 60;
+
+/// Caps the switches of all clients together at `max_per_minute`, on top of the per-client
+/// cooldowns. Switches over the cap are held back; the policies plan them again in a later
+/// cycle, most urgent first.
+pub struct SwitchRateLimit {
+    max_per_minute: usize,
+    /// Times of the switches within the last window, oldest first
+    recent: VecDeque<u64>,
+}
+
+impl SwitchRateLimit {
+    pub fn new(config: &SwitchRateLimitConfig) -> Result<Self, ConfigError> {
+        if config.max_per_minute == 0 {
+            return Err(ConfigError::Invalid(
+                "switch_rate_limit.max_per_minute must be positive".to_string(),
+            ));
+        }
+        Ok(Self {
+            max_per_minute: config.max_per_minute as usize,
+            recent: VecDeque::new(),
+        })
+    }
+
+    pub fn max_per_minute(&self) -> usize {
+        self.max_per_minute
+    }
+
+    /// Whether one more switch fits the cap; if not, the seconds until one does.
+    pub fn check(&mut self, now: u64) -> Result<(), u64> {
+        while self
+            .recent
+            .front()
+            .is_some_and(|at| now.saturating_sub(*at) >= WINDOW_SECS)
+        {
+            self.recent.pop_front();
+        }
+        if self.recent.len() < self.max_per_minute {
+            return Ok(());
+        }
+        let oldest = self.recent[self.recent.len() - self.max_per_minute];
+        Err((oldest + WINDOW_SECS).saturating_sub(now).max(1))
+    }
+
+    pub fn record(&mut self, now: u64) {
+        self.recent.push_back(now);
+    }
+}
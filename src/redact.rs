@@ -0,0 +1,109 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// Replaces IP (and optionally MAC) addresses with stable stand-ins, so the same client
+/// keeps the same pseudonym across every file it is applied to. IPv4 addresses map into
+/// 240.0.0.0/4 and IPv6 addresses into fd00::/8, which never appear on real networks; MACs
+/// become locally administered `02:00:00:…` addresses.
+#[derive(Debug, Default)]
+pub struct Redactor {
+    pseudonyms: HashMap<IpAddr, IpAddr>,
+    /// Also replace MAC addresses.
+    macs: bool,
+    mac_pseudonyms: HashMap<String, String>,
+}
+
+impl Redactor {
+    /// A redactor that also replaces MAC addresses.
+    pub fn with_macs() -> Self {
+        Self {
+            macs: true,
+            ..Self::default()
+        }
+    }
+
+    pub fn addr(&mut self, ip: IpAddr) -> IpAddr {
+        let next = self.pseudonyms.len() as u32 + 1;
+        *self.pseudonyms.entry(ip).or_insert_with(|| match ip {
+            IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::from(0xf000_0000 | next)),
+            IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::from(0xfd00_u128 << 112 | u128::from(next))),
+        })
+    }
+
+    /// `text` with every address in it replaced; prefixes keep their length.
+    pub fn text(&mut self, text: &str) -> String {
+        let mut out = String::with_capacity(text.len());
+        let mut token = String::new();
+        for c in text.chars().chain(std::iter::once('\0')) {
+            if c.is_ascii_hexdigit() || c == '.' || c == ':' {
+                token.push(c);
+                continue;
+            }
+            out.push_str(&self.token(&token));
+            token.clear();
+            if c != '\0' {
+                out.push(c);
+            }
+        }
+        out
+    }
+
+    fn token(&mut self, token: &str) -> String {
+        // Addresses at the end of a sentence still count
+        let trimmed = token.trim_end_matches(['.', ':']);
+        let suffix = &token[trimmed.len()..];
+        if let Ok(ip) = trimmed.parse::<IpAddr>() {
+            return format!("{}{}", self.addr(ip), suffix);
+        }
+        if self.macs && is_mac(trimmed) {
+            let next = self.mac_pseudonyms.len() as u32 + 1;
+            let pseudonym = self
+                .mac_pseudonyms
+                .entry(trimmed.to_ascii_lowercase())
+                .or_insert_with(|| {
+                    let [_, a, b, c] = next.to_be_bytes();
+                    format!("02:00:00:{:02x}:{:02x}:{:02x}", a, b, c)
+                });
+            return format!("{}{}", pseudonym, suffix);
+        }
+        token.to_string()
+    }
+
+    /// Redacts every string inside a TOML document.
+    pub fn toml(&mut self, value: &mut toml::Value) {
+        match value {
+            toml::Value::String(s) => *s = self.text(s),
+            toml::Value::Array(values) => values.iter_mut().for_each(|value| self.toml(value)),
+            toml::Value::Table(table) => table.iter_mut().for_each(|(_, value)| self.toml(value)),
+            _ => {}
+        }
+    }
+
+    /// Redacts every string and object key inside a JSON document.
+    pub fn json(&mut self, value: &mut serde_json::Value) {
+        match value {
+            serde_json::Value::String(s) => *s = self.text(s),
+            serde_json::Value::Array(values) => {
+                values.iter_mut().for_each(|value| self.json(value))
+            }
+            serde_json::Value::Object(object) => {
+                *object = std::mem::take(object)
+                    .into_iter()
+                    .map(|(key, mut value)| {
+                        self.json(&mut value);
+                        (self.text(&key), value)
+                    })
+                    .collect();
+            }
+            _ => {}
+        }
+    }
+}
+
+fn is_mac(token: &str) -> bool {
+    let groups: Vec<&str> = token.split(':').collect();
+    groups.len() == 6
+        && groups
+            .iter()
+            .all(|group| group.len() == 2 && group.chars().all(|c| c.is_ascii_hexdigit()))
+}
@@ -0,0 +1,198 @@
+use crate::classify::TrafficClassifier;
+use crate::config::{Config, RemoteWriteConfig};
+use crate::model::{IpTraffic, NicName, NicStats, WanId};
+use anyhow::{bail, Context, Result};
+use reqwest::Client;
+use std::collections::HashMap;
+use tracing::warn;
+
+/// Prometheus remote-write protobuf messages (prometheus/prompb/remote.proto, types.proto).
+mod prompb {
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct WriteRequest {
+        #[prost(message, repeated, tag = "1")]
+        pub timeseries: Vec<TimeSeries>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct TimeSeries {
+        #[prost(message, repeated, tag = "1")]
+        pub labels: Vec<Label>,
+        #[prost(message, repeated, tag = "2")]
+        pub samples: Vec<Sample>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Label {
+        #[prost(string, tag = "1")]
+        pub name: String,
+        #[prost(string, tag = "2")]
+        pub value: String,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Sample {
+        #[prost(double, tag = "1")]
+        pub value: f64,
+        #[prost(int64, tag = "2")]
+        pub timestamp: i64,
+    }
+}
+
+/// Everything the remote writer derives series from in one cycle.
+pub struct DerivedInput<'a> {
+    pub timestamp_ms: i64,
+    pub nic_stats: &'a HashMap<NicName, NicStats>,
+    pub wan_to_nic: &'a HashMap<WanId, NicName>,
+    pub ip_traffic: &'a [IpTraffic],
+    pub policy_name: &'a str,
+    pub policy_scores: &'a HashMap<WanId, f64>,
+}
+
+/// Pushes the controller's computed view (smoothed utilization, classified per-IP
+/// rates, policy scores) to a Prometheus remote-write endpoint.
+pub struct RemoteWriter {
+    client: Client,
+    config: RemoteWriteConfig,
+    classifier: TrafficClassifier,
+    smoothed_utilization: HashMap<WanId, f64>,
+}
+
+impl RemoteWriter {
+    pub fn new(config: &Config, remote_write: RemoteWriteConfig) -> Self {
+        Self {
+            client: Client::new(),
+            config: remote_write,
+            classifier: TrafficClassifier::new(&config.traffic_classes),
+            smoothed_utilization: HashMap::new(),
+        }
+    }
+
+    /// Builds this cycle's series and sends them in the background.
+    pub fn push(&mut self, input: &DerivedInput) {
+        let request = self.build_request(input);
+        if request.timeseries.is_empty() {
+            return;
+        }
+
+        let client = self.client.clone();
+        let url = self.config.url.clone();
+        tokio::spawn(async move {
+            if let Err(e) = send(&client, &url, &request).await {
+                warn!("Remote write failed: {:#}", e);
+            }
+        });
+    }
+
+    fn build_request(&mut self, input: &DerivedInput) -> prompb::WriteRequest {
+        let mut timeseries = Vec::new();
+        let alpha = self.config.smoothing_alpha;
+
+        for (wan, nic) in input.wan_to_nic {
+            let Some(stats) = input.nic_stats.get(nic) else {
+                continue;
+            };
+            if stats.tcp_bandwidth <= 0.0 {
+                continue;
+            }
+
+            let utilization = (stats.tx_bps + stats.rx_bps) / stats.tcp_bandwidth;
+            let smoothed = *self
+                .smoothed_utilization
+                .entry(wan.clone())
+                .and_modify(|value| *value = alpha * utilization + (1.0 - alpha) * *value)
+                .or_insert(utilization);
+
+            timeseries.push(self.series(
+                "routingflow_wan_utilization_smoothed",
+                &[("wan", wan.as_str()), ("nic", nic.as_str())],
+                smoothed,
+                input.timestamp_ms,
+            ));
+        }
+
+        for traffic in input.ip_traffic {
+            let class = self
+                .classifier
+                .classify(traffic)
+                .map(|class| class.name.as_str())
+                .unwrap_or("unclassified");
+
+            for (direction, value) in [("rx", traffic.rx_bps), ("tx", traffic.tx_bps)] {
+                timeseries.push(self.series(
+                    "routingflow_ip_classified_bps",
+                    &[
+                        ("ip", &traffic.ip.to_string()),
+                        ("nic", traffic.nic.as_str()),
+                        ("class", class),
+                        ("direction", direction),
+                    ],
+                    value,
+                    input.timestamp_ms,
+                ));
+            }
+        }
+
+        for (wan, score) in input.policy_scores {
+            timeseries.push(self.series(
+                "routingflow_policy_score",
+                &[("wan", wan.as_str()), ("policy", input.policy_name)],
+                *score,
+                input.timestamp_ms,
+            ));
+        }
+
+        prompb::WriteRequest { timeseries }
+    }
+
+    fn series(
+        &self,
+        name: &str,
+        labels: &[(&str, &str)],
+        value: f64,
+        timestamp: i64,
+    ) -> prompb::TimeSeries {
+        let mut labels: Vec<prompb::Label> = std::iter::once(("__name__", name))
+            .chain(labels.iter().copied())
+            .chain(
+                self.config
+                    .labels
+                    .iter()
+                    .map(|(name, value)| (name.as_str(), value.as_str())),
+            )
+            .map(|(name, value)| prompb::Label {
+                name: name.to_string(),
+                value: value.to_string(),
+            })
+            .collect();
+        // Remote write requires labels sorted by name
+        labels.sort_by(|a, b| a.name.cmp(&b.name));
+
+        prompb::TimeSeries {
+            labels,
+            samples: vec![prompb::Sample { value, timestamp }],
+        }
+    }
+}
+
+async fn send(client: &Client, url: &str, request: &prompb::WriteRequest) -> Result<()> {
+    let body = snap::raw::Encoder::new()
+        .compress_vec(&prost::Message::encode_to_vec(request))
+        .context("Failed to compress remote write payload")?;
+
+    let response = client
+        .post(url)
+        .header(reqwest::header::CONTENT_TYPE, "application/x-protobuf")
+        .header(reqwest::header::CONTENT_ENCODING, "snappy")
+        .header("X-Prometheus-Remote-Write-Version", "0.1.0")
+        .body(body)
+        .send()
+        .await
+        .with_context(|| format!("Failed to reach remote write endpoint {}", url))?;
+
+    if !response.status().is_success() {
+        bail!("Remote write endpoint returned {}", response.status());
+    }
+
+    Ok(())
+}
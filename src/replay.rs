@@ -0,0 +1,480 @@
+use crate::cli::ReplayArgs;
+use crate::clock::{Clock, ManualClock, SystemClock};
+use crate::config::{
+    Config, EventsConfig, HistoryBackend, MetricSchemaConfig, PrometheusConfig, RecordingConfig,
+    RoutingServiceConfig, ServerConfig,
+};
+use crate::diag::DiagRecorder;
+use crate::metric_source;
+use crate::model::{ClientIp, WanId};
+use crate::monitor::{self, OutputFormat};
+use crate::prometheus::{PrometheusClient, PrometheusResult};
+use crate::routing::{RoutingService, StatusResponse};
+use crate::shutdown::Shutdown;
+use anyhow::{bail, Context, Result};
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Json};
+use axum::routing::get;
+use axum::Router;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, UNIX_EPOCH};
+use tokio::sync::watch;
+use tracing::{info, warn};
+
+/// Timestamp of the recorded files' names; sorts in time order.
+const FILE_HOUR_FORMAT: &str = "%Y%m%dT%H";
+
+/// One cycle's answers, as a line of a recording.
+#[derive(Debug, Deserialize)]
+struct Snapshot {
+    timestamp: u64,
+    tcp_bandwidth: Vec<PrometheusResult>,
+    client_traffic: Vec<PrometheusResult>,
+    status: StatusResponse,
+}
+
+#[derive(Serialize)]
+struct RecordedCycle<'a> {
+    timestamp: u64,
+    tcp_bandwidth: &'a [PrometheusResult],
+    client_traffic: &'a [PrometheusResult],
+    status: &'a StatusResponse,
+}
+
+/// Appends every cycle's answers to an hourly file, deleting the files that have aged out
+/// whenever a new one is started.
+pub struct Recording {
+    config: RecordingConfig,
+    file: Option<(String, BufWriter<File>)>,
+}
+
+impl Recording {
+    pub fn new(config: RecordingConfig) -> Self {
+        Self { config, file: None }
+    }
+
+    pub fn record(
+        &mut self,
+        timestamp: u64,
+        status: &StatusResponse,
+        tcp_bandwidth: &[PrometheusResult],
+        client_traffic: &[PrometheusResult],
+    ) -> Result<()> {
+        let hour = file_hour(timestamp);
+        if self.file.as_ref().is_none_or(|(open, _)| *open != hour) {
+            fs::create_dir_all(&self.config.dir)
+                .with_context(|| format!("Failed to create {}", self.config.dir.display()))?;
+            let path = self.config.dir.join(format!("cycles-{}.jsonl", hour));
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .with_context(|| format!("Failed to open {}", path.display()))?;
+            self.file = Some((hour, BufWriter::new(file)));
+            self.prune(timestamp);
+        }
+        let Some((_, file)) = self.file.as_mut() else {
+            return Ok(());
+        };
+        let cycle = RecordedCycle {
+            timestamp,
+            tcp_bandwidth,
+            client_traffic,
+            status,
+        };
+        serde_json::to_writer(&mut *file, &cycle)?;
+        file.write_all(b"\n")?;
+        file.flush()?;
+        Ok(())
+    }
+
+    fn prune(&self, now: u64) {
+        let cutoff = format!(
+            "cycles-{}.jsonl",
+            file_hour(now.saturating_sub(self.config.retain_hours * 3600))
+        );
+        let Ok(entries) = fs::read_dir(&self.config.dir) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name.starts_with("cycles-") && name.ends_with(".jsonl") && name < cutoff {
+                if let Err(e) = fs::remove_file(entry.path()) {
+                    warn!("Failed to delete old recording {}: {}", name, e);
+                }
+            }
+        }
+    }
+}
+
+fn file_hour(timestamp: u64) -> String {
+    chrono::DateTime::from_timestamp(timestamp as i64, 0)
+        .unwrap_or_default()
+        .format(FILE_HOUR_FORMAT)
+        .to_string()
+}
+
+/// A switch the replayed balancing made.
+#[derive(Debug, Clone, Serialize)]
+struct ReplayedSwitch {
+    timestamp: u64,
+    ip: ClientIp,
+    from_wan: Option<WanId>,
+    to_wan: WanId,
+}
+
+/// Stands in for Prometheus and the routing service during a replay: answers with the
+/// snapshot current on the simulated clock and keeps the mappings the replayed switches
+/// produce.
+struct ReplayBackend {
+    snapshots: Vec<Snapshot>,
+    clock: Arc<ManualClock>,
+    /// Start from the first snapshot's; clients that appear later join with their recorded
+    /// WAN, and only the replay moves them after that.
+    mappings: Mutex<HashMap<ClientIp, WanId>>,
+    switches: Mutex<Vec<ReplayedSwitch>>,
+    stop: watch::Sender<bool>,
+}
+
+impl ReplayBackend {
+    fn current(&self) -> &Snapshot {
+        let now = self.clock.unix_secs();
+        let index = self
+            .snapshots
+            .partition_point(|snapshot| snapshot.timestamp <= now);
+        &self.snapshots[index.saturating_sub(1)]
+    }
+
+    fn end(&self) -> u64 {
+        self.snapshots
+            .last()
+            .map_or(0, |snapshot| snapshot.timestamp)
+    }
+}
+
+/// Implements the `replay` subcommand.
+pub async fn run_replay_command(
+    config: Config,
+    args: &ReplayArgs,
+    output: OutputFormat,
+    recorder: Arc<DiagRecorder>,
+) -> Result<()> {
+    let mut snapshots = match (&args.dir, args.since) {
+        (Some(dir), _) => read_recordings(dir)?,
+        (None, Some(since)) => query_history(&config, since, args.step).await?,
+        (None, None) => bail!("Give a --dir of recordings or a --since period"),
+    };
+    snapshots.sort_by_key(|snapshot| snapshot.timestamp);
+    let (Some(first), Some(last)) = (snapshots.first(), snapshots.last()) else {
+        bail!("Nothing recorded to replay");
+    };
+    let (start, end) = (first.timestamp, last.timestamp);
+    info!(
+        cycles = snapshots.len(),
+        duration_secs = end - start,
+        "Replaying recorded traffic"
+    );
+
+    let clock = Arc::new(ManualClock::new(UNIX_EPOCH + Duration::from_secs(start)));
+    let (stop, shutdown) = Shutdown::manual();
+    let backend = Arc::new(ReplayBackend {
+        mappings: Mutex::new(first.status.mappings.clone()),
+        snapshots,
+        clock: clock.clone(),
+        switches: Mutex::new(Vec::new()),
+        stop,
+    });
+    let app = Router::new()
+        .route("/api/v1/query", get(prometheus_query))
+        .route("/api/v1/query_range", get(prometheus_query_range))
+        .route("/status", get(status))
+        .route("/switch", get(switch))
+        .with_state(backend.clone());
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+    let url = format!("http://{}", listener.local_addr()?);
+    let server = tokio::spawn(async move { axum::serve(listener, app).await });
+
+    let replay_config = replay_config(config, &url);
+    let reports = args.reports.then_some(output);
+    let result =
+        monitor::run_monitor(replay_config, reports, recorder, clock, shutdown, None).await;
+    server.abort();
+    result?;
+
+    let switches = backend.switches.lock().unwrap().clone();
+    match output {
+        OutputFormat::Json => println!(
+            "{}",
+            json!({ "start": start, "end": end, "switches": switches })
+        ),
+        OutputFormat::Text => print_switches(start, end, &switches),
+    }
+    Ok(())
+}
+
+/// The config to replay with: the balancing as configured, with the replay standing in for
+/// the backends and everything that reaches outside the process turned off.
+fn replay_config(mut config: Config, url: &str) -> Config {
+    config.prometheus = PrometheusConfig {
+        url: url.to_string(),
+        ..PrometheusConfig::default()
+    };
+    // Cycles are recorded with the series already under the default names
+    config.metric_schema = MetricSchemaConfig::default();
+    config.influxdb = None;
+    config.routing_service = RoutingServiceConfig {
+        url: url.to_string(),
+        ..RoutingServiceConfig::default()
+    };
+    config.history.backend = HistoryBackend::Memory;
+    config.journal.enabled = false;
+    config.conntrack.flush_on_switch = false;
+    config.server = ServerConfig::default();
+    config.events = EventsConfig::default();
+    config.handoff = None;
+    config.saved_state = None;
+    config.remote_write = None;
+    config.probes = None;
+    config.reverse_dns = None;
+    config.destinations = None;
+    config.mapping_gc = None;
+    config.qos = None;
+    config.public_ip = None;
+    config.capacity_hints = None;
+    config.recording = None;
+    config.audit = None;
+    config.local_stats = None;
+    config.snmp = None;
+    config.calibration = None;
+    config.data_caps = None;
+    config
+}
+
+fn read_recordings(dir: &Path) -> Result<Vec<Snapshot>> {
+    let mut paths: Vec<PathBuf> = fs::read_dir(dir)
+        .with_context(|| format!("Failed to read {}", dir.display()))?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "jsonl")
+        })
+        .collect();
+    paths.sort();
+
+    let mut snapshots = Vec::new();
+    for path in paths {
+        let file =
+            File::open(&path).with_context(|| format!("Failed to open {}", path.display()))?;
+        for (index, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let snapshot = serde_json::from_str(&line).with_context(|| {
+                format!("{}:{}: not a recorded cycle", path.display(), index + 1)
+            })?;
+            snapshots.push(snapshot);
+        }
+    }
+    Ok(snapshots)
+}
+
+/// Snapshots every `step_secs` over the last `since_secs` from Prometheus's history. The
+/// routing service keeps no history, so every snapshot has the current mappings.
+async fn query_history(config: &Config, since_secs: u64, step_secs: u64) -> Result<Vec<Snapshot>> {
+    let prometheus = PrometheusClient::new(&config.prometheus)?;
+    let routing = RoutingService::new(&config.routing_service, config.retry.clone())?;
+    let status = routing
+        .status()
+        .await
+        .context("Failed to read the current mappings")?;
+    let end = SystemClock.unix_secs();
+    let start = end.saturating_sub(since_secs);
+    let schema = &config.metric_schema;
+    let (bandwidth_query, traffic_query) = (
+        metric_source::bandwidth_query(schema),
+        metric_source::traffic_query(schema),
+    );
+    let (tcp_bandwidth, client_traffic) = tokio::try_join!(
+        prometheus.query_range(&bandwidth_query, start, end, step_secs),
+        prometheus.query_range(&traffic_query, start, end, step_secs),
+    )
+    .context("Failed to query Prometheus's history")?;
+
+    let mut snapshots: BTreeMap<u64, Snapshot> = BTreeMap::new();
+    for (series, tcp) in tcp_bandwidth
+        .into_iter()
+        .map(|series| (series, true))
+        .chain(client_traffic.into_iter().map(|series| (series, false)))
+    {
+        for (timestamp, value) in series.values {
+            let snapshot = snapshots
+                .entry(timestamp as u64)
+                .or_insert_with(|| Snapshot {
+                    timestamp: timestamp as u64,
+                    tcp_bandwidth: Vec::new(),
+                    client_traffic: Vec::new(),
+                    status: status.clone(),
+                });
+            let sample = PrometheusResult {
+                metric: series.metric.clone(),
+                value: (timestamp, value),
+            };
+            if tcp {
+                snapshot.tcp_bandwidth.push(sample);
+            } else {
+                snapshot.client_traffic.push(sample);
+            }
+        }
+    }
+    Ok(snapshots
+        .into_values()
+        .map(|mut snapshot| {
+            snapshot.tcp_bandwidth =
+                metric_source::normalize_bandwidth(schema, snapshot.tcp_bandwidth);
+            snapshot.client_traffic =
+                metric_source::normalize_traffic(schema, snapshot.client_traffic);
+            snapshot
+        })
+        .collect())
+}
+
+fn print_switches(start: u64, end: u64, switches: &[ReplayedSwitch]) {
+    for switch in switches {
+        println!(
+            "{} (+{}s) - {} {} → {}",
+            switch.timestamp,
+            switch.timestamp - start,
+            switch.ip,
+            switch
+                .from_wan
+                .as_ref()
+                .map_or_else(|| "(new)".to_string(), ToString::to_string),
+            switch.to_wan
+        );
+    }
+    println!(
+        "{} switches over {}s of recorded traffic",
+        switches.len(),
+        end - start
+    );
+}
+
+/// A snapshot's answer to `query`: the two traffic queries and the failover's freshness
+/// check. Anything else has no recorded answer and gets an empty one.
+fn answer(snapshot: &Snapshot, query: &str) -> Vec<PrometheusResult> {
+    let schema = MetricSchemaConfig::default();
+    let bandwidth_query = metric_source::bandwidth_query(&schema);
+    if query == bandwidth_query {
+        snapshot.tcp_bandwidth.clone()
+    } else if query == metric_source::traffic_query(&schema) {
+        snapshot.client_traffic.clone()
+    } else if query == format!("timestamp({})", bandwidth_query) {
+        snapshot
+            .tcp_bandwidth
+            .iter()
+            .map(|result| PrometheusResult {
+                metric: result.metric.clone(),
+                value: (result.value.0, result.value.0.to_string()),
+            })
+            .collect()
+    } else {
+        Vec::new()
+    }
+}
+
+#[derive(Deserialize)]
+struct QueryParams {
+    query: String,
+    start: Option<u64>,
+    end: Option<u64>,
+}
+
+async fn prometheus_query(
+    State(backend): State<Arc<ReplayBackend>>,
+    Query(params): Query<QueryParams>,
+) -> Json<serde_json::Value> {
+    let result = answer(backend.current(), &params.query);
+    Json(json!({
+        "status": "success",
+        "data": { "resultType": "vector", "result": result },
+    }))
+}
+
+/// The snapshots between `start` and `end` as series, for `query_window`.
+async fn prometheus_query_range(
+    State(backend): State<Arc<ReplayBackend>>,
+    Query(params): Query<QueryParams>,
+) -> Json<serde_json::Value> {
+    let (start, end) = (params.start.unwrap_or(0), params.end.unwrap_or(u64::MAX));
+    // Keyed by the sorted labels, so each series gathers its samples
+    let mut series: BTreeMap<BTreeMap<String, String>, Vec<(f64, String)>> = BTreeMap::new();
+    for snapshot in &backend.snapshots {
+        if snapshot.timestamp < start || snapshot.timestamp > end {
+            continue;
+        }
+        for result in answer(snapshot, &params.query) {
+            series
+                .entry(result.metric.into_iter().collect())
+                .or_default()
+                .push(result.value);
+        }
+    }
+    let result: Vec<serde_json::Value> = series
+        .into_iter()
+        .map(|(metric, values)| json!({ "metric": metric, "values": values }))
+        .collect();
+    Json(json!({
+        "status": "success",
+        "data": { "resultType": "matrix", "result": result },
+    }))
+}
+
+/// The mappings as the replay has them; reaching the end of the recording stops the run.
+async fn status(State(backend): State<Arc<ReplayBackend>>) -> Json<StatusResponse> {
+    if backend.clock.unix_secs() >= backend.end() {
+        let _ = backend.stop.send(true);
+    }
+    let snapshot = backend.current();
+    let mut mappings = backend.mappings.lock().unwrap();
+    mappings.retain(|ip, _| snapshot.status.mappings.contains_key(ip));
+    for (ip, wan) in &snapshot.status.mappings {
+        mappings.entry(*ip).or_insert_with(|| wan.clone());
+    }
+    Json(StatusResponse {
+        config: snapshot.status.config.clone(),
+        mappings: mappings.clone(),
+    })
+}
+
+#[derive(Deserialize)]
+struct SwitchParams {
+    ip: ClientIp,
+    nic: WanId,
+}
+
+async fn switch(
+    State(backend): State<Arc<ReplayBackend>>,
+    Query(params): Query<SwitchParams>,
+) -> impl IntoResponse {
+    let from_wan = backend
+        .mappings
+        .lock()
+        .unwrap()
+        .insert(params.ip, params.nic.clone());
+    backend.switches.lock().unwrap().push(ReplayedSwitch {
+        timestamp: backend.clock.unix_secs(),
+        ip: params.ip,
+        from_wan,
+        to_wan: params.nic,
+    });
+    StatusCode::OK
+}
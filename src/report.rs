@@ -0,0 +1,237 @@
+use crate::breaker::BreakerState;
+use crate::controller::ClientInfo;
+use crate::data_caps::DataCapUsage;
+use crate::destinations::DestinationUsage;
+use crate::dhcp_leases::Lease;
+use crate::fairness::FairnessMetrics;
+use crate::link_quality::LinkQuality;
+use crate::mode::OperatingMode;
+use crate::model::{ClientIp, IpTraffic, NicName, NicStats, WanId};
+use crate::passive_rtt::ClientExperience;
+use crate::probe::WanProbeStats;
+use crate::qos::QueueState;
+use crate::reverse_dns::Hostnames;
+use crate::templates::ReportTemplate;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+
+/// Everything one scan cycle found and decided, printed as text or JSON.
+#[derive(Debug, Serialize)]
+pub struct CycleReport {
+    pub timestamp: u64,
+    /// Name of the site, when the instance balances several.
+    pub site: Option<String>,
+    pub policy: String,
+    pub lan: NicName,
+    pub wans: Vec<WanReport>,
+    pub nics: Vec<BandwidthComparison>,
+    pub top_ips: Vec<TopIpReport>,
+    /// Busiest destination networks per WAN; empty unless destination attribution is enabled.
+    pub destination_usage: Vec<DestinationUsage>,
+    pub decisions: Vec<DecisionReport>,
+    pub switch_circuit: CircuitReport,
+    pub mode: OperatingMode,
+    /// Name of the open maintenance window, during which no switches are made.
+    pub maintenance_window: Option<String>,
+    pub fairness: Option<FairnessReport>,
+    pub recent_switches: Vec<RecentSwitch>,
+    /// Longest cooldown window; switches older than this are no longer listed.
+    pub history_window_secs: u64,
+    /// What the network controller knows about the mapped clients; empty unless a
+    /// controller is configured.
+    pub clients: BTreeMap<ClientIp, ClientInfo>,
+    /// Names of the mapped clients from their DHCP leases or reverse DNS; empty unless
+    /// either is configured.
+    pub hostnames: Hostnames,
+    /// DHCP leases of the mapped clients; empty unless lease files are configured.
+    pub leases: BTreeMap<ClientIp, Lease>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct WanReport {
+    pub wan: WanId,
+    pub nic: NicName,
+    pub clients: usize,
+    pub client_cap: Option<usize>,
+    /// Latest latency probe round; absent unless probing is enabled.
+    pub probe: Option<WanProbeStats>,
+    /// Median passive RTT of the WAN's clients; absent without passive RTT readings.
+    pub client_rtt_ms: Option<f64>,
+    /// Absent unless public IP detection is enabled and has found one.
+    pub public_ip: Option<IpAddr>,
+    /// Use of the WAN's data cap this billing period; absent unless it has one.
+    pub data_cap: Option<DataCapUsage>,
+}
+
+/// Estimated TCP bandwidth of a NIC against the traffic actually observed on it.
+#[derive(Debug, Serialize)]
+pub struct BandwidthComparison {
+    pub nic: NicName,
+    pub tcp_bandwidth_bps: f64,
+    pub tx_bps: f64,
+    pub rx_bps: f64,
+    pub total_bps: f64,
+    pub headroom_bps: f64,
+    /// Router-side queue state; absent unless QoS statistics are enabled.
+    pub queue: Option<QueueState>,
+    /// Loss and jitter of the NIC's WAN; absent unless link quality is enabled.
+    pub link_quality: Option<LinkQuality>,
+}
+
+impl BandwidthComparison {
+    pub fn new(
+        nic: &NicName,
+        stats: &NicStats,
+        queue: Option<&QueueState>,
+        link_quality: Option<&LinkQuality>,
+    ) -> Self {
+        Self {
+            nic: nic.clone(),
+            tcp_bandwidth_bps: stats.tcp_bandwidth,
+            tx_bps: stats.tx_bps,
+            rx_bps: stats.rx_bps,
+            total_bps: stats.tx_bps + stats.rx_bps,
+            headroom_bps: stats.headroom(),
+            queue: queue.cloned(),
+            link_quality: link_quality.copied(),
+        }
+    }
+}
+
+/// The IP with the most RX traffic on a NIC.
+#[derive(Debug, Serialize)]
+pub struct TopIpReport {
+    pub nic: NicName,
+    pub ip: ClientIp,
+    pub rx_bps: f64,
+    pub tx_bps: f64,
+    /// Passive RTT and retransmits of the client's flows; absent without readings.
+    pub experience: Option<ClientExperience>,
+    /// Application classes of the client's traffic, busiest first.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub app_classes: Vec<String>,
+}
+
+impl TopIpReport {
+    pub fn new(
+        traffic: &IpTraffic,
+        experience: Option<&ClientExperience>,
+        app_classes: Option<&Vec<String>>,
+    ) -> Self {
+        Self {
+            nic: traffic.nic.clone(),
+            ip: traffic.ip,
+            rx_bps: traffic.rx_bps,
+            tx_bps: traffic.tx_bps,
+            experience: experience.cloned(),
+            app_classes: app_classes.cloned().unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct DecisionReport {
+    pub ip: ClientIp,
+    pub nic: NicName,
+    /// `None` for candidates the policy skipped without picking a target.
+    pub target_wan: Option<WanId>,
+    pub rx_bps: Option<f64>,
+    pub reason: String,
+    #[serde(flatten)]
+    pub outcome: DecisionOutcome,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "outcome", rename_all = "snake_case")]
+pub enum DecisionOutcome {
+    Switched,
+    Failed {
+        error: String,
+    },
+    /// Held back by a cooldown or class residency.
+    Held {
+        remaining_secs: u64,
+        hold_reason: String,
+    },
+    /// Rejected by the policy.
+    Skipped,
+}
+
+/// State of the circuit breaker in front of the switch API.
+#[derive(Debug, Serialize)]
+pub struct CircuitReport {
+    #[serde(flatten)]
+    pub state: BreakerState,
+    pub consecutive_failures: u32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FairnessReport {
+    pub utilizations: BTreeMap<WanId, f64>,
+    pub jain_index: f64,
+    pub max_min_ratio: Option<f64>,
+}
+
+impl From<&FairnessMetrics> for FairnessReport {
+    fn from(metrics: &FairnessMetrics) -> Self {
+        Self {
+            utilizations: metrics.utilizations.iter().cloned().collect(),
+            jain_index: metrics.jain_index,
+            max_min_ratio: metrics.max_min_ratio,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RecentSwitch {
+    pub ip: ClientIp,
+    pub target_wan: WanId,
+    pub age_secs: u64,
+    pub hold: Option<RecentHold>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RecentHold {
+    pub reason: String,
+    pub remaining_secs: u64,
+}
+
+impl CycleReport {
+    /// Prints the report as one JSON object per line.
+    pub fn print_json(&self) {
+        match serde_json::to_string(self) {
+            Ok(json) => println!("{}", json),
+            Err(e) => tracing::error!("Failed to serialize cycle report: {}", e),
+        }
+    }
+
+    /// `ip`, with its name and connection from the controller if it knows the client, or
+    /// else its lease or reverse DNS name.
+    fn client(&self, ip: ClientIp) -> String {
+        let Some(client) = self.clients.get(&ip) else {
+            return self.hostnames.label(ip);
+        };
+        let connection = if client.wired { "wired" } else { "wireless" };
+        match &client.name {
+            Some(name) => format!("{} ({}, {})", ip, name, connection),
+            None => format!("{} ({})", ip, connection),
+        }
+    }
+
+    /// The name shown for each client IP of the report, as `client` gives it.
+    pub fn labels(&self) -> BTreeMap<String, String> {
+        let ips = self
+            .top_ips
+            .iter()
+            .map(|top| top.ip)
+            .chain(self.decisions.iter().map(|decision| decision.ip))
+            .chain(self.recent_switches.iter().map(|switch| switch.ip));
+        ips.map(|ip| (ip.to_string(), self.client(ip))).collect()
+    }
+
+    /// Prints the report as text through `template`.
+    pub fn print_text(&self, template: &ReportTemplate) {
+        print!("{}", template.render(self));
+    }
+}
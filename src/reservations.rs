@@ -0,0 +1,103 @@
+use crate::config::ReservationConfig;
+use crate::error::ConfigError;
+use crate::model::{ClientIp, IpTraffic, WanId};
+use chrono::{DateTime, TimeZone};
+use std::collections::HashMap;
+
+/// A reservation whose window is open this cycle.
+#[derive(Debug, Clone)]
+pub struct ActiveReservation {
+    pub config: ReservationConfig,
+    /// Part of the reservation its group is not using right now.
+    pub unused_bps: f64,
+}
+
+impl ActiveReservation {
+    fn covers(&self, ip: ClientIp) -> bool {
+        self.config
+            .prefixes
+            .iter()
+            .any(|prefix| prefix.contains(&ip.addr()))
+    }
+}
+
+/// Reservations in force for one cycle. The unused part of each one is headroom that
+/// clients outside its group must not be planned into.
+#[derive(Debug, Default)]
+pub struct ActiveReservations {
+    pub active: Vec<ActiveReservation>,
+}
+
+/// The configured reservations.
+pub struct Reservations {
+    reservations: Vec<ReservationConfig>,
+}
+
+impl Reservations {
+    pub fn new(reservations: &[ReservationConfig]) -> Result<Self, ConfigError> {
+        if let Some(reservation) = reservations
+            .iter()
+            .find(|reservation| reservation.prefixes.is_empty() || reservation.mbps <= 0.0)
+        {
+            return Err(ConfigError::Invalid(format!(
+                "Reservation {} needs at least one prefix and a positive mbps",
+                reservation.name
+            )));
+        }
+
+        Ok(Self {
+            reservations: reservations.to_vec(),
+        })
+    }
+
+    /// Reservations open at `at`, less what their group already sends over the reserved WAN.
+    pub fn evaluate<Tz: TimeZone>(
+        &self,
+        ip_traffic: &[IpTraffic],
+        mappings: &HashMap<ClientIp, WanId>,
+        at: &DateTime<Tz>,
+    ) -> ActiveReservations {
+        let active = self
+            .reservations
+            .iter()
+            .filter(|reservation| reservation.schedule.contains(at))
+            .map(|reservation| {
+                let mut active = ActiveReservation {
+                    config: reservation.clone(),
+                    unused_bps: 0.0,
+                };
+                let used_bps: f64 = ip_traffic
+                    .iter()
+                    .filter(|traffic| {
+                        mappings.get(&traffic.ip) == Some(&reservation.wan)
+                            && active.covers(traffic.ip)
+                    })
+                    .map(|traffic| traffic.rx_bps + traffic.tx_bps)
+                    .sum();
+                active.unused_bps = (reservation.mbps * 1_000_000.0 - used_bps).max(0.0);
+                active
+            })
+            .collect();
+        ActiveReservations { active }
+    }
+}
+
+impl ActiveReservations {
+    /// Headroom on `wan` held back from `ip` by reservations of other groups.
+    pub fn withheld_bps(&self, wan: &WanId, ip: ClientIp) -> f64 {
+        self.active
+            .iter()
+            .filter(|reservation| reservation.config.wan == *wan && !reservation.covers(ip))
+            .map(|reservation| reservation.unused_bps)
+            .sum()
+    }
+
+    /// Unused reserved headroom on `wan` across all groups.
+    pub fn unused_bps(&self, wan: &WanId) -> f64 {
+        self.active
+            .iter()
+            .filter(|reservation| reservation.config.wan == *wan)
+            .map(|reservation| reservation.unused_bps)
+            .sum()
+    }
+}
@@ -0,0 +1,110 @@
+use crate::config::RetryConfig;
+use crate::error::{BackendError, MetricsError};
+use std::collections::hash_map::RandomState;
+use std::future::Future;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+use tracing::warn;
+
+/// Errors that may go away when the same call is simply made again.
+pub trait Transient {
+    fn is_transient(&self) -> bool;
+
+    /// Delay the other side asked for before the next attempt.
+    fn retry_after(&self) -> Option<Duration> {
+        None
+    }
+}
+
+impl Transient for MetricsError {
+    fn is_transient(&self) -> bool {
+        match self {
+            MetricsError::Unreachable(e) | MetricsError::InfluxDbUnreachable(e) => {
+                e.status().is_none_or(|status| {
+                    status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS
+                })
+            }
+            MetricsError::Stale { .. }
+            | MetricsError::Malformed(_)
+            | MetricsError::InfluxDbRejected { .. }
+            | MetricsError::InfluxDbMalformed(_) => false,
+        }
+    }
+}
+
+impl Transient for BackendError {
+    fn is_transient(&self) -> bool {
+        match self {
+            BackendError::Rejected { status } => status.is_server_error(),
+            BackendError::RateLimited { .. } | BackendError::Unreachable(_) => true,
+            BackendError::Malformed(_) => false,
+        }
+    }
+
+    fn retry_after(&self) -> Option<Duration> {
+        match self {
+            BackendError::RateLimited { retry_after } => *retry_after,
+            _ => None,
+        }
+    }
+}
+
+/// Runs `call` until it succeeds, fails permanently or `max_attempts` are used up, sleeping
+/// an exponentially growing, fully jittered backoff between attempts.
+pub async fn with_backoff<T, E, F, Fut>(
+    config: &RetryConfig,
+    what: &str,
+    mut call: F,
+) -> Result<T, E>
+where
+    E: Transient + std::fmt::Display,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    let mut attempt = 1;
+    loop {
+        let error = match call().await {
+            Ok(value) => return Ok(value),
+            Err(error) => error,
+        };
+        if attempt >= config.max_attempts || !error.is_transient() {
+            return Err(error);
+        }
+
+        // Waiting out a pause longer than max_backoff_ms would stall the scan; fail instead
+        let delay = match error.retry_after() {
+            Some(after) if after > Duration::from_millis(config.max_backoff_ms) => {
+                return Err(error)
+            }
+            Some(after) => after,
+            None => jittered(backoff(config, attempt)),
+        };
+        warn!(
+            attempt,
+            max_attempts = config.max_attempts,
+            delay_ms = delay.as_millis() as u64,
+            "{} failed ({}); retrying",
+            what,
+            error
+        );
+        tokio::time::sleep(delay).await;
+        attempt += 1;
+    }
+}
+
+/// Upper bound of the delay after the `attempt`-th failure.
+fn backoff(config: &RetryConfig, attempt: u32) -> Duration {
+    let factor = 2u64.saturating_pow(attempt.saturating_sub(1));
+    Duration::from_millis(
+        config
+            .initial_backoff_ms
+            .saturating_mul(factor)
+            .min(config.max_backoff_ms),
+    )
+}
+
+/// A uniformly random delay in `0..=max`, so clients retrying together spread out.
+fn jittered(max: Duration) -> Duration {
+    let random = RandomState::new().build_hasher().finish();
+    max.mul_f64(random as f64 / u64::MAX as f64)
+}
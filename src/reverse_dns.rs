@@ -0,0 +1,295 @@
+use crate::clock::Clock;
+use crate::config::ReverseDnsConfig;
+use crate::error::ConfigError;
+use crate::model::ClientIp;
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::hash::{BuildHasher, Hasher};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use tokio::net::UdpSocket;
+use tokio::sync::Semaphore;
+use tracing::debug;
+
+/// Lookups in flight at once, so a cold cache does not flood the resolver.
+const MAX_CONCURRENT_LOOKUPS: usize = 16;
+
+const TYPE_PTR: u16 = 12;
+const CLASS_IN: u16 = 1;
+
+/// Names of the clients, from their DHCP leases or as far as the reverse DNS has answered.
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(transparent)]
+pub struct Hostnames(BTreeMap<ClientIp, String>);
+
+impl Extend<(ClientIp, String)> for Hostnames {
+    fn extend<I: IntoIterator<Item = (ClientIp, String)>>(&mut self, names: I) {
+        self.0.extend(names);
+    }
+}
+
+impl Hostnames {
+    pub fn get(&self, ip: ClientIp) -> Option<&str> {
+        self.0.get(&ip).map(String::as_str)
+    }
+
+    /// `nas.lan (192.168.1.20)`, or the bare address without a name.
+    pub fn label(&self, ip: ClientIp) -> String {
+        match self.get(ip) {
+            Some(name) => format!("{} ({})", name, ip),
+            None => ip.to_string(),
+        }
+    }
+}
+
+struct Entry {
+    name: Option<String>,
+    expires: SystemTime,
+}
+
+/// PTR lookups of client addresses, cached. Lookups run in the background: an address seen
+/// for the first time goes by its bare address until the answer is in, so a slow resolver
+/// never holds up a cycle.
+pub struct ReverseDns {
+    config: ReverseDnsConfig,
+    server: SocketAddr,
+    cache: Arc<Mutex<HashMap<IpAddr, Entry>>>,
+    pending: Arc<Mutex<HashSet<IpAddr>>>,
+    lookups: Arc<Semaphore>,
+    clock: Arc<dyn Clock>,
+}
+
+impl ReverseDns {
+    pub fn new(config: ReverseDnsConfig, clock: Arc<dyn Clock>) -> Result<Self, ConfigError> {
+        let server = match config.server {
+            Some(server) => server,
+            None => system_resolver().map_err(|e| {
+                ConfigError::Invalid(format!(
+                    "No resolver for reverse DNS ({:#}); set reverse_dns.server",
+                    e
+                ))
+            })?,
+        };
+        Ok(Self {
+            config,
+            server,
+            cache: Arc::new(Mutex::new(HashMap::new())),
+            pending: Arc::new(Mutex::new(HashSet::new())),
+            lookups: Arc::new(Semaphore::new(MAX_CONCURRENT_LOOKUPS)),
+            clock,
+        })
+    }
+
+    /// The cached names of `ips`; addresses not cached, or cached too long ago, are looked
+    /// up for later cycles.
+    pub fn names(&self, ips: impl IntoIterator<Item = ClientIp>) -> Hostnames {
+        let now = self.clock.now();
+        let mut names = BTreeMap::new();
+        let cache = self.cache.lock().unwrap();
+        let mut pending = self.pending.lock().unwrap();
+        for ip in ips {
+            let entry = cache.get(&ip.addr());
+            if let Some(name) = entry.and_then(|entry| entry.name.clone()) {
+                names.insert(ip, name);
+            }
+            if entry.is_some_and(|entry| entry.expires > now) || !pending.insert(ip.addr()) {
+                continue;
+            }
+            tokio::spawn(resolve(
+                ip.addr(),
+                self.server,
+                self.config.clone(),
+                self.cache.clone(),
+                self.pending.clone(),
+                self.lookups.clone(),
+                self.clock.clone(),
+            ));
+        }
+        Hostnames(names)
+    }
+}
+
+async fn resolve(
+    addr: IpAddr,
+    server: SocketAddr,
+    config: ReverseDnsConfig,
+    cache: Arc<Mutex<HashMap<IpAddr, Entry>>>,
+    pending: Arc<Mutex<HashSet<IpAddr>>>,
+    lookups: Arc<Semaphore>,
+    clock: Arc<dyn Clock>,
+) {
+    let name = match lookups.acquire().await {
+        Ok(_permit) => {
+            let timeout = Duration::from_millis(config.timeout_ms);
+            match tokio::time::timeout(timeout, lookup(server, addr)).await {
+                Ok(Ok(name)) => name,
+                Ok(Err(e)) => {
+                    debug!(ip = %addr, "Reverse DNS lookup failed: {:#}", e);
+                    None
+                }
+                Err(_) => {
+                    debug!(ip = %addr, "Reverse DNS lookup timed out");
+                    None
+                }
+            }
+        }
+        Err(_) => None,
+    };
+    let keep_secs = if name.is_some() {
+        config.cache_secs
+    } else {
+        config.negative_cache_secs
+    };
+    cache.lock().unwrap().insert(
+        addr,
+        Entry {
+            name,
+            expires: clock.now() + Duration::from_secs(keep_secs),
+        },
+    );
+    pending.lock().unwrap().remove(&addr);
+}
+
+/// The first `nameserver` of `/etc/resolv.conf`.
+fn system_resolver() -> Result<SocketAddr> {
+    let resolv_conf =
+        std::fs::read_to_string("/etc/resolv.conf").context("Failed to read /etc/resolv.conf")?;
+    resolv_conf
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("nameserver"))
+        .filter_map(|server| {
+            let server = server.trim();
+            let address = server
+                .split_once('%')
+                .map_or(server, |(address, _zone)| address);
+            address.parse::<IpAddr>().ok()
+        })
+        .map(|address| SocketAddr::new(address, 53))
+        .next()
+        .context("/etc/resolv.conf lists no nameserver")
+}
+
+/// The PTR name of `addr`; `None` when it has none.
+async fn lookup(server: SocketAddr, addr: IpAddr) -> Result<Option<String>> {
+    let bind: SocketAddr = if server.is_ipv4() {
+        "0.0.0.0:0".parse()?
+    } else {
+        "[::]:0".parse()?
+    };
+    let socket = UdpSocket::bind(bind).await?;
+    socket.connect(server).await?;
+    let id = RandomState::new().build_hasher().finish() as u16;
+    socket.send(&query(id, &ptr_name(addr))).await?;
+
+    let mut buffer = [0u8; 1500];
+    loop {
+        let length = socket.recv(&mut buffer).await?;
+        // Stray answers to someone else's query are not ours to read
+        if length >= 2 && u16::from_be_bytes([buffer[0], buffer[1]]) == id {
+            return parse_answer(&buffer[..length]);
+        }
+    }
+}
+
+/// `20.1.168.192.in-addr.arpa` or the nibbles of an IPv6 address under `ip6.arpa`.
+fn ptr_name(addr: IpAddr) -> String {
+    match addr {
+        IpAddr::V4(addr) => {
+            let [a, b, c, d] = addr.octets();
+            format!("{}.{}.{}.{}.in-addr.arpa", d, c, b, a)
+        }
+        IpAddr::V6(addr) => {
+            let mut name = String::with_capacity(72);
+            for byte in addr.octets().iter().rev() {
+                name.push_str(&format!("{:x}.{:x}.", byte & 0x0F, byte >> 4));
+            }
+            name.push_str("ip6.arpa");
+            name
+        }
+    }
+}
+
+fn query(id: u16, name: &str) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(name.len() + 18);
+    packet.extend_from_slice(&id.to_be_bytes());
+    // Recursion desired; one question
+    packet.extend_from_slice(&[0x01, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0]);
+    for label in name.split('.') {
+        packet.push(label.len() as u8);
+        packet.extend_from_slice(label.as_bytes());
+    }
+    packet.push(0);
+    packet.extend_from_slice(&TYPE_PTR.to_be_bytes());
+    packet.extend_from_slice(&CLASS_IN.to_be_bytes());
+    packet
+}
+
+fn parse_answer(packet: &[u8]) -> Result<Option<String>> {
+    if packet.len() < 12 {
+        bail!("Truncated DNS response");
+    }
+    match packet[3] & 0x0F {
+        0 => {}
+        // NXDOMAIN: no name for the address
+        3 => return Ok(None),
+        rcode => bail!("DNS server answered with rcode {}", rcode),
+    }
+    let questions = u16::from_be_bytes([packet[4], packet[5]]);
+    let answers = u16::from_be_bytes([packet[6], packet[7]]);
+
+    let mut offset = 12;
+    for _ in 0..questions {
+        offset = read_name(packet, offset)?.1 + 4;
+    }
+    for _ in 0..answers {
+        offset = read_name(packet, offset)?.1;
+        let header = packet
+            .get(offset..offset + 10)
+            .context("Truncated DNS answer")?;
+        let record_type = u16::from_be_bytes([header[0], header[1]]);
+        let length = usize::from(u16::from_be_bytes([header[8], header[9]]));
+        offset += 10;
+        if record_type == TYPE_PTR {
+            let (name, _) = read_name(packet, offset)?;
+            return Ok((!name.is_empty()).then_some(name));
+        }
+        offset += length;
+    }
+    Ok(None)
+}
+
+/// The name at `offset`, following compression pointers, and the offset after it.
+fn read_name(packet: &[u8], mut offset: usize) -> Result<(String, usize)> {
+    let mut labels: Vec<String> = Vec::new();
+    let mut end = None;
+    // Each pointer must go backwards, so a loop of them cannot run forever
+    let mut limit = offset;
+    loop {
+        let length = *packet.get(offset).context("Truncated DNS name")?;
+        match length {
+            0 => {
+                return Ok((labels.join("."), end.unwrap_or(offset + 1)));
+            }
+            length if length & 0xC0 == 0xC0 => {
+                let low = *packet.get(offset + 1).context("Truncated DNS name")?;
+                let target = (usize::from(length & 0x3F) << 8) | usize::from(low);
+                if target >= limit {
+                    bail!("Invalid DNS name compression");
+                }
+                end.get_or_insert(offset + 2);
+                limit = target;
+                offset = target;
+            }
+            length => {
+                let label = packet
+                    .get(offset + 1..offset + 1 + usize::from(length))
+                    .context("Truncated DNS name")?;
+                labels.push(String::from_utf8_lossy(label).into_owned());
+                offset += 1 + usize::from(length);
+            }
+        }
+    }
+}
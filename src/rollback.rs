@@ -0,0 +1,163 @@
+use crate::config::RollbackConfig;
+use crate::flows::Flow;
+use crate::model::{ClientIp, NicName, NicStats, WanId};
+use crate::policy::{PolicyInput, PolicyPlan, SkippedCandidate, SwitchDecision};
+use std::collections::HashMap;
+
+/// A policy move whose effect is checked once its grace period is over.
+struct Watch {
+    ip: ClientIp,
+    from_wan: WanId,
+    from_nic: NicName,
+    target_wan: WanId,
+    switched_at: u64,
+    /// Traffic (RX + TX) of the source NIC and of the moved client at the time of the move.
+    source_bps: f64,
+    moved_bps: f64,
+    /// The flows moved instead of the client, whose mapping stayed on `from_wan`.
+    flows: Vec<Flow>,
+}
+
+/// Reverts policy moves that did not relieve the NIC they were meant to relieve, or that
+/// overloaded their target, and keeps the policies from re-attempting them for a while.
+pub struct Rollbacks {
+    config: RollbackConfig,
+    watching: Vec<Watch>,
+    /// Client → end of its backoff.
+    backoff: HashMap<ClientIp, u64>,
+    /// Client → the flows its revert this cycle moves back.
+    reverted_flows: HashMap<ClientIp, Vec<Flow>>,
+}
+
+impl Rollbacks {
+    pub fn new(config: RollbackConfig) -> Self {
+        Self {
+            config,
+            watching: Vec::new(),
+            backoff: HashMap::new(),
+            reverted_flows: HashMap::new(),
+        }
+    }
+
+    /// Starts watching a move the routing service accepted, of the client or, with `flows`,
+    /// of just those.
+    pub fn watch(
+        &mut self,
+        decision: &SwitchDecision,
+        from_wan: WanId,
+        nic_stats: &HashMap<NicName, NicStats>,
+        moved_bps: f64,
+        flows: &[Flow],
+        now: u64,
+    ) {
+        let Some(source) = nic_stats.get(&decision.from_nic) else {
+            return;
+        };
+        self.watching.retain(|watch| watch.ip != decision.ip);
+        self.watching.push(Watch {
+            ip: decision.ip,
+            from_wan,
+            from_nic: decision.from_nic.clone(),
+            target_wan: decision.target_wan.clone(),
+            switched_at: now,
+            source_bps: source.rx_bps + source.tx_bps,
+            moved_bps,
+            flows: flows.to_vec(),
+        });
+    }
+
+    /// Drops policy moves of clients in their backoff, reporting them as skipped.
+    pub fn filter(&mut self, mut plan: PolicyPlan, now: u64) -> PolicyPlan {
+        self.backoff.retain(|_, until| *until > now);
+        let (kept, held): (Vec<_>, Vec<_>) = plan
+            .switches
+            .into_iter()
+            .partition(|decision| !self.backoff.contains_key(&decision.ip));
+        plan.switches = kept;
+        plan.skipped
+            .extend(held.into_iter().map(|decision| SkippedCandidate {
+                reason: format!(
+                    "an earlier move was rolled back; not re-attempting for another {}s",
+                    self.backoff[&decision.ip].saturating_sub(now)
+                ),
+                ip: decision.ip,
+                nic: decision.from_nic,
+            }));
+        plan
+    }
+
+    /// Reverts of watched moves whose grace period is over and that did not help; the
+    /// clients are put into backoff.
+    pub fn plan(&mut self, input: &PolicyInput, now: u64) -> Vec<SwitchDecision> {
+        let (due, watching): (Vec<_>, Vec<_>) = std::mem::take(&mut self.watching)
+            .into_iter()
+            .partition(|watch| now.saturating_sub(watch.switched_at) >= self.config.grace_secs);
+        self.watching = watching;
+        self.reverted_flows.clear();
+
+        let mut reverts = Vec::new();
+        for watch in due {
+            // Moved again since, by something else
+            let mapped_wan = if watch.flows.is_empty() {
+                &watch.target_wan
+            } else {
+                &watch.from_wan
+            };
+            if input.mappings.get(&watch.ip) != Some(mapped_wan) {
+                continue;
+            }
+            let Some(target_nic) = input.wan_to_nic.get(&watch.target_wan) else {
+                continue;
+            };
+            let (Some(source), Some(target)) = (
+                input.nic_stats.get(&watch.from_nic),
+                input.nic_stats.get(target_nic),
+            ) else {
+                continue;
+            };
+
+            let source_bps = source.rx_bps + source.tx_bps;
+            let relieved =
+                watch.source_bps - source_bps >= watch.moved_bps * self.config.min_relief;
+            let reason = if target.headroom() <= 0.0 {
+                format!(
+                    "rollback: the move overloaded {} ({} has no headroom left)",
+                    watch.target_wan, target_nic
+                )
+            } else if !relieved {
+                format!(
+                    "rollback: moving to {} did not relieve {} ({:.2} → {:.2} Mbps)",
+                    watch.target_wan,
+                    watch.from_nic,
+                    watch.source_bps / 1_000_000.0,
+                    source_bps / 1_000_000.0
+                )
+            } else {
+                continue;
+            };
+
+            self.backoff
+                .insert(watch.ip, now + self.config.backoff_secs);
+            if !watch.flows.is_empty() {
+                self.reverted_flows.insert(watch.ip, watch.flows);
+            }
+            reverts.push(SwitchDecision {
+                ip: watch.ip,
+                from_nic: target_nic.clone(),
+                target_wan: watch.from_wan,
+                rx_bps: input
+                    .ip_traffic
+                    .iter()
+                    .find(|traffic| traffic.ip == watch.ip)
+                    .map_or(0.0, |traffic| traffic.rx_bps),
+                reason,
+            });
+        }
+        reverts
+    }
+
+    /// The flows the revert of `ip` planned this cycle moves back, if it moved only those.
+    pub fn reverted_flows(&self, ip: ClientIp) -> Option<&[Flow]> {
+        self.reverted_flows.get(&ip).map(Vec::as_slice)
+    }
+}
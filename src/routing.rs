@@ -0,0 +1,306 @@
+use crate::config::{RetryConfig, RoutingServiceConfig};
+use crate::error::{BackendError, ConfigError};
+use crate::model::{ClientIp, NicName, WanId};
+use crate::qos::QosResponse;
+use crate::retry;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION};
+use reqwest::{Client, Response, StatusCode};
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
+use std::time::Duration;
+use tracing::warn;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StatusResponse {
+    pub config: ConfigInfo,
+    #[serde(deserialize_with = "lenient_mappings")]
+    pub mappings: HashMap<ClientIp, WanId>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigInfo {
+    pub lan: NicName,
+    /// The `wan<N>` fields: a WAN (`wan0`, `wan1`, …) and its interface.
+    #[serde(flatten, deserialize_with = "lenient_wans")]
+    pub wans: BTreeMap<WanId, NicName>,
+}
+
+/// The `wan<N>` entries of the status config, leaving out every other field and the WANs
+/// whose interface is not a valid name rather than failing the whole status over them.
+fn lenient_wans<'de, D>(deserializer: D) -> Result<BTreeMap<WanId, NicName>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let fields = Map::<String, Value>::deserialize(deserializer)?;
+    Ok(fields
+        .into_iter()
+        .filter_map(|(key, value)| {
+            let is_wan = key
+                .strip_prefix("wan")
+                .is_some_and(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()));
+            let nic = value.as_str().and_then(|nic| nic.parse().ok());
+            match (is_wan, key.parse(), nic) {
+                (true, Ok(wan), Some(nic)) => Some((wan, nic)),
+                _ => {
+                    warn!(field = %key, value = %value, "Skipping non-WAN routing service config field");
+                    None
+                }
+            }
+        })
+        .collect())
+}
+
+/// The mappings as reported, leaving out the entries whose IP or WAN id does not parse
+/// rather than failing the whole status over one bad entry.
+fn lenient_mappings<'de, D>(deserializer: D) -> Result<HashMap<ClientIp, WanId>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let mappings = HashMap::<String, String>::deserialize(deserializer)?;
+    Ok(mappings
+        .into_iter()
+        .filter_map(|(ip, wan)| match (ip.parse(), wan.parse()) {
+            (Ok(ip), Ok(wan)) => Some((ip, wan)),
+            _ => {
+                warn!(ip = %ip, wan = %wan, "Skipping malformed routing service mapping");
+                None
+            }
+        })
+        .collect())
+}
+
+/// Client of the routing service that owns the IP → WAN mappings, sending the configured
+/// credentials with every request.
+pub struct RoutingService {
+    client: Client,
+    base_url: String,
+    retry: RetryConfig,
+}
+
+impl RoutingService {
+    pub fn new(config: &RoutingServiceConfig, retry: RetryConfig) -> Result<Self, ConfigError> {
+        let mut headers = HeaderMap::new();
+        for (name, value) in &config.headers {
+            let name = HeaderName::try_from(name.as_str()).map_err(|e| {
+                ConfigError::Invalid(format!("Invalid routing service header {}: {}", name, e))
+            })?;
+            let mut value = HeaderValue::try_from(value.as_str()).map_err(|e| {
+                ConfigError::Invalid(format!(
+                    "Invalid value of routing service header {}: {}",
+                    name, e
+                ))
+            })?;
+            value.set_sensitive(true);
+            headers.insert(name, value);
+        }
+
+        let bearer_token = match (&config.bearer_token, &config.bearer_token_file) {
+            (Some(_), Some(_)) => {
+                return Err(ConfigError::Invalid(
+                    "Set either routing_service.bearer_token or routing_service.bearer_token_file, not both"
+                        .to_string(),
+                ))
+            }
+            (Some(token), None) => Some(token.clone()),
+            (None, Some(path)) => Some(
+                std::fs::read_to_string(path)
+                    .map_err(|e| {
+                        ConfigError::Invalid(format!(
+                            "Failed to read routing service bearer token file {}: {}",
+                            path.display(),
+                            e
+                        ))
+                    })?
+                    .trim()
+                    .to_string(),
+            ),
+            (None, None) => None,
+        };
+        if let Some(token) = bearer_token {
+            if headers.contains_key(AUTHORIZATION) {
+                return Err(ConfigError::Invalid(
+                    "routing_service.bearer_token and an Authorization header are mutually exclusive"
+                        .to_string(),
+                ));
+            }
+            let mut value = HeaderValue::try_from(format!("Bearer {}", token)).map_err(|e| {
+                ConfigError::Invalid(format!("Invalid routing service bearer token: {}", e))
+            })?;
+            value.set_sensitive(true);
+            headers.insert(AUTHORIZATION, value);
+        }
+
+        let client = Client::builder()
+            .default_headers(headers)
+            .build()
+            .map_err(|e| {
+                ConfigError::Invalid(format!("Failed to set up routing service client: {}", e))
+            })?;
+
+        Ok(Self {
+            client,
+            base_url: config.url.trim_end_matches('/').to_string(),
+            retry,
+        })
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    /// Current interface configuration and client mappings.
+    pub async fn status(&self) -> Result<StatusResponse, BackendError> {
+        let response = self.get(&format!("{}/status", self.base_url)).await?;
+        response.json().await.map_err(BackendError::Malformed)
+    }
+
+    /// Queue / shaper counters per interface from the endpoint at `path`.
+    pub async fn qos(&self, path: &str) -> Result<QosResponse, BackendError> {
+        let response = self.get(&format!("{}{}", self.base_url, path)).await?;
+        response.json().await.map_err(BackendError::Malformed)
+    }
+
+    /// Public address of every WAN from the endpoint at `path`.
+    pub async fn public_ips(&self, path: &str) -> Result<HashMap<WanId, IpAddr>, BackendError> {
+        let response = self.get(&format!("{}{}", self.base_url, path)).await?;
+        response.json().await.map_err(BackendError::Malformed)
+    }
+
+    /// Moves `ip` onto `wan`.
+    pub async fn switch(&self, ip: ClientIp, wan: &WanId) -> Result<(), BackendError> {
+        self.get(&switch_url(&self.base_url, "/switch", ip, wan))
+            .await
+            .map(drop)
+    }
+
+    /// Moves one flow of `ip`, the one on its `port` over `proto`, onto `wan` through the
+    /// endpoint at `path`; the client's mapping stays as it is.
+    pub async fn switch_flow(
+        &self,
+        path: &str,
+        ip: ClientIp,
+        port: u16,
+        proto: &str,
+        wan: &WanId,
+    ) -> Result<(), BackendError> {
+        self.get(&format!(
+            "{}&port={}&proto={}",
+            switch_url(&self.base_url, path, ip, wan),
+            port,
+            urlencoding::encode(proto)
+        ))
+        .await
+        .map(drop)
+    }
+
+    /// Asks the dry-run endpoint at `path` whether moving `ip` onto `wan` would be
+    /// accepted. `Some` carries the reason the service gave for rejecting it.
+    pub async fn validate(
+        &self,
+        path: &str,
+        ip: ClientIp,
+        wan: &WanId,
+    ) -> Result<Option<String>, BackendError> {
+        let url = switch_url(&self.base_url, path, ip, wan);
+        let response = retry::with_backoff(&self.retry, "Routing service dry run", || {
+            self.send_once(&url)
+        })
+        .await?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(None);
+        }
+        if !status.is_client_error() {
+            return Err(BackendError::Rejected { status });
+        }
+        let body = response.text().await.unwrap_or_default();
+        Ok(Some(
+            rejection_reason(&body).unwrap_or_else(|| status.to_string()),
+        ))
+    }
+
+    /// Drops the mapping of `ip` through the endpoint at `path`.
+    pub async fn remove(&self, path: &str, ip: ClientIp) -> Result<(), BackendError> {
+        self.get(&format!(
+            "{}{}?ip={}",
+            self.base_url,
+            path,
+            urlencoding::encode(&ip.to_string())
+        ))
+        .await
+        .map(drop)
+    }
+
+    /// GETs `url`, retrying transient failures.
+    async fn get(&self, url: &str) -> Result<Response, BackendError> {
+        retry::with_backoff(&self.retry, "Routing service request", || {
+            self.get_once(url)
+        })
+        .await
+    }
+
+    async fn get_once(&self, url: &str) -> Result<Response, BackendError> {
+        let response = self.send_once(url).await?;
+        if !response.status().is_success() {
+            return Err(BackendError::Rejected {
+                status: response.status(),
+            });
+        }
+        Ok(response)
+    }
+
+    /// GETs `url`, failing only on errors worth retrying: the service being unreachable,
+    /// rate limiting and server errors.
+    async fn send_once(&self, url: &str) -> Result<Response, BackendError> {
+        let response = self
+            .client
+            .get(url)
+            .send()
+            .await
+            .map_err(BackendError::Unreachable)?;
+
+        let status = response.status();
+        if status == StatusCode::TOO_MANY_REQUESTS {
+            let retry_after = response
+                .headers()
+                .get(reqwest::header::RETRY_AFTER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse().ok())
+                .map(Duration::from_secs);
+            return Err(BackendError::RateLimited { retry_after });
+        }
+        if status.is_server_error() {
+            return Err(BackendError::Rejected { status });
+        }
+        Ok(response)
+    }
+}
+
+/// The service's switch endpoints name the WAN parameter `nic`, but take the same WAN ids
+/// as the `/status` mappings.
+fn switch_url(base_url: &str, path: &str, ip: ClientIp, wan: &WanId) -> String {
+    format!(
+        "{}{}?ip={}&nic={}",
+        base_url,
+        path,
+        urlencoding::encode(&ip.to_string()),
+        urlencoding::encode(wan.as_str())
+    )
+}
+
+/// The message of a rejection: the `error`, `reason` or `message` field of a JSON body,
+/// or else the body itself.
+fn rejection_reason(body: &str) -> Option<String> {
+    let body = body.trim();
+    if let Ok(serde_json::Value::Object(fields)) = serde_json::from_str(body) {
+        if let Some(message) = ["error", "reason", "message"]
+            .iter()
+            .find_map(|key| fields.get(*key).and_then(|value| value.as_str()))
+        {
+            return Some(message.to_string());
+        }
+    }
+    (!body.is_empty()).then(|| body.to_string())
+}
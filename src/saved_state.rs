@@ -0,0 +1,79 @@
+use crate::breaker::BreakerCounters;
+use crate::config::SavedStateConfig;
+use crate::control::{ManualPin, PauseStatus};
+use crate::failover::FailoverState;
+use crate::history::SwitchRecord;
+use crate::routing::StatusResponse;
+use crate::smoothing::SmootherState;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::io::ErrorKind;
+use tracing::warn;
+
+/// What the balancing loop holds in memory, written to disk so a restarted instance keeps
+/// its cooldowns, holds and averages instead of re-deciding every client at once.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SavedState {
+    /// Unix time the snapshot was taken.
+    pub saved_at: u64,
+    pub version: String,
+    pub switch_history: Vec<SwitchRecord>,
+    pub manual_pins: Vec<ManualPin>,
+    pub pause: Option<PauseStatus>,
+    pub failover: Option<FailoverState>,
+    pub soft_start_since: Option<u64>,
+    pub smoothing: Option<SmootherState>,
+    pub breaker: BreakerCounters,
+    /// The routing service's last good answer and when it came.
+    pub last_status: Option<(StatusResponse, u64)>,
+}
+
+/// Writes `state` next to the snapshot path and renames it into place, so a crash while
+/// writing leaves the previous snapshot intact.
+pub fn save(config: &SavedStateConfig, state: &SavedState) -> Result<()> {
+    let mut temporary = config.path.clone().into_os_string();
+    temporary.push(".tmp");
+    std::fs::write(&temporary, serde_json::to_vec(state)?)
+        .with_context(|| format!("Failed to write {}", temporary.to_string_lossy()))?;
+    std::fs::rename(&temporary, &config.path)
+        .with_context(|| format!("Failed to replace {}", config.path.display()))?;
+    Ok(())
+}
+
+/// The snapshot on disk, unless there is none or it is older than `max_age_secs` at `now`.
+/// A snapshot that cannot be read is ignored with a warning: starting afresh is safe.
+pub fn load(config: &SavedStateConfig, now: u64) -> Option<SavedState> {
+    let bytes = match std::fs::read(&config.path) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == ErrorKind::NotFound => return None,
+        Err(e) => {
+            warn!(
+                "Failed to read {}: {}; starting afresh",
+                config.path.display(),
+                e
+            );
+            return None;
+        }
+    };
+    let state: SavedState = match serde_json::from_slice(&bytes) {
+        Ok(state) => state,
+        Err(e) => {
+            warn!(
+                "Unreadable state in {}: {}; starting afresh",
+                config.path.display(),
+                e
+            );
+            return None;
+        }
+    };
+    let age_secs = now.saturating_sub(state.saved_at);
+    if age_secs > config.max_age_secs {
+        warn!(
+            age_secs,
+            "Saved state in {} is too old to apply; starting afresh",
+            config.path.display()
+        );
+        return None;
+    }
+    Some(state)
+}
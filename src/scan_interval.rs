@@ -0,0 +1,60 @@
+use crate::config::ScanConfig;
+use crate::error::ConfigError;
+use crate::model::{NicName, NicStats};
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Paces the loop: a fixed interval, or with `max_interval_ms` one that follows the busiest
+/// WAN, polling fast while a WAN nears capacity and backing off while all are quiet so
+/// Prometheus is not queried every second for nothing.
+pub struct ScanInterval {
+    config: ScanConfig,
+}
+
+impl ScanInterval {
+    pub fn new(config: ScanConfig) -> Result<Self, ConfigError> {
+        if config.interval_ms == 0 {
+            return Err(ConfigError::Invalid(
+                "scan.interval_ms must be positive".to_string(),
+            ));
+        }
+        if config
+            .max_interval_ms
+            .is_some_and(|max| max < config.interval_ms)
+        {
+            return Err(ConfigError::Invalid(
+                "scan.max_interval_ms cannot be shorter than interval_ms".to_string(),
+            ));
+        }
+        if config.idle_utilization >= config.busy_utilization {
+            return Err(ConfigError::Invalid(
+                "scan.idle_utilization must be below busy_utilization".to_string(),
+            ));
+        }
+        Ok(Self { config })
+    }
+
+    /// The shortest wait, for cycles that did not get as far as reading the NICs.
+    pub fn shortest(&self) -> Duration {
+        Duration::from_millis(self.config.interval_ms)
+    }
+
+    /// The wait after a cycle that read `nic_stats`: the shortest from `busy_utilization`
+    /// on, the longest up to `idle_utilization`, and in proportion between them.
+    pub fn after(&self, nic_stats: &HashMap<NicName, NicStats>) -> Duration {
+        let Some(max_interval_ms) = self.config.max_interval_ms else {
+            return self.shortest();
+        };
+        let utilization = nic_stats
+            .values()
+            .filter(|stats| stats.tcp_bandwidth > 0.0)
+            .map(|stats| (stats.tx_bps + stats.rx_bps) / stats.tcp_bandwidth)
+            .fold(0.0, f64::max);
+        let idleness = ((self.config.busy_utilization - utilization)
+            / (self.config.busy_utilization - self.config.idle_utilization))
+            .clamp(0.0, 1.0);
+        let interval_ms = self.config.interval_ms as f64
+            + (max_interval_ms - self.config.interval_ms) as f64 * idleness;
+        Duration::from_millis(interval_ms.round() as u64)
+    }
+}
@@ -0,0 +1,88 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Datelike, TimeZone, Timelike, Weekday};
+use serde::{Deserialize, Deserializer};
+use std::fmt;
+use std::str::FromStr;
+
+/// A recurring local-time window such as weekdays 09:00–17:00. A window whose end is
+/// before its start runs past midnight; equal ends cover the whole day.
+#[derive(Debug, Clone, Deserialize)]
+pub struct TimeWindow {
+    /// Days the window opens on (`mon` … `sun`); every day when empty.
+    #[serde(default)]
+    pub days: Vec<Day>,
+    pub start: TimeOfDay,
+    pub end: TimeOfDay,
+}
+
+impl TimeWindow {
+    pub fn contains<Tz: TimeZone>(&self, at: &DateTime<Tz>) -> bool {
+        let minute = TimeOfDay(at.hour() * 60 + at.minute());
+        let today = at.weekday();
+        let opens_on = |day: Weekday| self.days.is_empty() || self.days.iter().any(|d| d.0 == day);
+
+        if self.start < self.end {
+            opens_on(today) && self.start <= minute && minute < self.end
+        } else if self.start == self.end {
+            opens_on(today)
+        } else {
+            // The part after midnight belongs to the previous day's window
+            (opens_on(today) && minute >= self.start)
+                || (opens_on(today.pred()) && minute < self.end)
+        }
+    }
+}
+
+impl fmt::Display for TimeWindow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.start, self.end)
+    }
+}
+
+/// A day of the week, written as in `mon` or `monday`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Day(Weekday);
+
+impl<'de> Deserialize<'de> for Day {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse::<Weekday>()
+            .map(Day)
+            .map_err(|_| serde::de::Error::custom(format!("Invalid day of the week: {}", s)))
+    }
+}
+
+/// Minutes since local midnight, written as `HH:MM` (`24:00` is the end of the day).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TimeOfDay(u32);
+
+impl FromStr for TimeOfDay {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (hours, minutes) = s
+            .split_once(':')
+            .ok_or_else(|| anyhow!("Invalid time of day {} (expected HH:MM)", s))?;
+        let (Ok(hours), Ok(minutes)) = (hours.trim().parse::<u32>(), minutes.trim().parse::<u32>())
+        else {
+            return Err(anyhow!("Invalid time of day {} (expected HH:MM)", s));
+        };
+        if minutes >= 60 || hours * 60 + minutes > 24 * 60 {
+            return Err(anyhow!("Time of day {} out of range", s));
+        }
+        Ok(Self(hours * 60 + minutes))
+    }
+}
+
+impl fmt::Display for TimeOfDay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02}:{:02}", self.0 / 60, self.0 % 60)
+    }
+}
+
+impl<'de> Deserialize<'de> for TimeOfDay {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
@@ -0,0 +1,478 @@
+use crate::auth::{AuthError, Authenticator, Principal, Role};
+use crate::control::{
+    Control, PauseChange, PauseRequest, PinRequest, PolicyChangeRequest, SwitchRequest,
+    MAX_DURATION_SECS,
+};
+use crate::dashboard::{self, Dashboard};
+use crate::diag::DiagRecorder;
+use crate::event_stream::{Delivery, EventStream};
+use crate::events::Event;
+use crate::hints::CapacityHints;
+use crate::history_db::HistoryQuery;
+use crate::metrics::Metrics;
+use crate::model::ClientIp;
+use crate::status_page::{RateLimiter, StatusBoard};
+use crate::store::StateStore;
+use crate::websocket;
+use anyhow::{Context, Result};
+use axum::extract::ws::rejection::WebSocketUpgradeRejection;
+use axum::extract::ws::WebSocketUpgrade;
+use axum::extract::{ConnectInfo, Query, Request, State};
+use axum::http::{header, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::sse::{self, KeepAlive, Sse};
+use axum::response::{Html, IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Extension, Json, Router};
+use futures_util::stream::{self, Stream};
+use serde::Deserialize;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tracing::{error, info, warn};
+
+/// Shared state behind the HTTP endpoints.
+#[derive(Clone)]
+pub struct AppState {
+    pub metrics: Arc<Metrics>,
+    pub auth: Arc<Authenticator>,
+    pub status_board: Arc<StatusBoard>,
+    pub control: Arc<Control>,
+    pub diag: Arc<DiagRecorder>,
+    pub event_stream: Arc<EventStream>,
+    /// Set when the dashboard is enabled.
+    pub dashboard: Option<Arc<Dashboard>>,
+    /// Set when capacity hints are enabled.
+    pub hints: Option<Arc<CapacityHints>>,
+    /// Set when the switch history is enabled.
+    pub store: Option<Arc<dyn StateStore>>,
+    /// Set when the public status page is enabled.
+    pub status_limiter: Option<Arc<RateLimiter>>,
+}
+
+/// Binds the HTTP server and serves it in the background.
+pub async fn spawn(listen: SocketAddr, state: AppState) -> Result<()> {
+    let listener = tokio::net::TcpListener::bind(listen)
+        .await
+        .with_context(|| format!("Failed to bind HTTP server to {}", listen))?;
+    info!("Serving metrics on http://{}/metrics", listen);
+    if !state.auth.is_enabled() {
+        warn!("HTTP API authentication is disabled (no API keys or OIDC configured); the API is read-only");
+    }
+
+    let viewer = Router::new()
+        .route("/metrics", get(metrics))
+        .route("/policy", get(policy_status))
+        .route("/pins", get(manual_pins))
+        .route("/events", get(events))
+        .route("/state", get(balancer_state))
+        .route("/history", get(switch_history))
+        .route("/hints", get(capacity_hints));
+    let operator = Router::new()
+        .route("/pause", post(pause))
+        .route("/resume", post(resume))
+        .route("/switch", post(switch));
+    let admin = Router::new()
+        .route("/policy", post(change_policy))
+        .route("/pins", post(change_pin))
+        .route("/diag", get(diag_snapshot));
+
+    let mut app = Router::new()
+        // Authenticates by itself: browsers cannot set headers on a WebSocket
+        .route("/ws", get(events_websocket))
+        .merge(with_role(viewer, &state, Role::Viewer))
+        .merge(with_role(operator, &state, Role::Operator))
+        .merge(with_role(admin, &state, Role::Admin));
+    if state.status_limiter.is_some() {
+        info!("Serving public status page on http://{}/status", listen);
+        app = app
+            .route("/status", get(status_page))
+            .route("/status.json", get(status_json));
+    }
+    let app = app.with_state(state);
+
+    tokio::spawn(async move {
+        let service = app.into_make_service_with_connect_info::<SocketAddr>();
+        if let Err(e) = axum::serve(listener, service).await {
+            error!("HTTP server stopped: {}", e);
+        }
+    });
+
+    Ok(())
+}
+
+/// Binds the dashboard and serves it in the background. The page itself is public; the
+/// data behind it needs the viewer role like the rest of the API.
+pub async fn spawn_dashboard(listen: SocketAddr, state: AppState) -> Result<()> {
+    let listener = tokio::net::TcpListener::bind(listen)
+        .await
+        .with_context(|| format!("Failed to bind dashboard to {}", listen))?;
+    info!("Serving dashboard on http://{}/", listen);
+
+    let api = Router::new().route("/api/dashboard", get(dashboard_data));
+    let app = Router::new()
+        .route("/", get(|| async { Html(dashboard::PAGE) }))
+        .merge(with_role(api, &state, Role::Viewer))
+        .with_state(state);
+
+    tokio::spawn(async move {
+        if let Err(e) = axum::serve(listener, app).await {
+            error!("Dashboard stopped: {}", e);
+        }
+    });
+
+    Ok(())
+}
+
+async fn metrics(State(state): State<AppState>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        state.metrics.render(),
+    )
+}
+
+async fn policy_status(State(state): State<AppState>) -> impl IntoResponse {
+    Json(state.control.policy_status())
+}
+
+async fn change_policy(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    Json(request): Json<PolicyChangeRequest>,
+) -> Response {
+    match state.control.request_policy(&request, &principal.name) {
+        Ok(()) => {
+            info!(
+                policy = %request.policy,
+                requested_by = %principal.name,
+                "Policy change requested"
+            );
+            (
+                StatusCode::ACCEPTED,
+                format!(
+                    "policy {} accepted; shadowing for {}s before it takes control\n",
+                    request.policy, state.control.shadow_secs
+                ),
+            )
+                .into_response()
+        }
+        Err(e) => (StatusCode::BAD_REQUEST, format!("{}\n", e)).into_response(),
+    }
+}
+
+async fn manual_pins(State(state): State<AppState>) -> impl IntoResponse {
+    Json(state.control.manual_pins())
+}
+
+async fn change_pin(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    Json(request): Json<PinRequest>,
+) -> Response {
+    let ip = request.ip;
+    let message = match &request.wan {
+        Some(wan) => format!(
+            "{} pinned to {} for {}s; switching on the next cycle\n",
+            ip, wan, request.duration_secs
+        ),
+        None => format!("pin of {} released\n", ip),
+    };
+    match state.control.request_pin(request, &principal.name) {
+        Ok(()) => {
+            info!(ip = %ip, requested_by = %principal.name, "Manual pin requested");
+            (StatusCode::ACCEPTED, message).into_response()
+        }
+        Err(e) => (StatusCode::BAD_REQUEST, format!("{}\n", e)).into_response(),
+    }
+}
+
+async fn balancer_state(State(state): State<AppState>) -> impl IntoResponse {
+    Json(state.control.state())
+}
+
+#[derive(Debug, Deserialize)]
+struct HistoryParams {
+    ip: Option<ClientIp>,
+    /// Unix time of the oldest record to return.
+    since: Option<u64>,
+    #[serde(default = "default_history_limit")]
+    limit: usize,
+}
+
+fn default_history_limit() -> usize {
+    50
+}
+
+/// Switches from the history database, newest first.
+async fn switch_history(
+    State(state): State<AppState>,
+    Query(params): Query<HistoryParams>,
+) -> Response {
+    let Some(store) = state.store.clone() else {
+        return (StatusCode::NOT_FOUND, "switch history is disabled\n").into_response();
+    };
+    let records = tokio::task::spawn_blocking(move || {
+        store.query_switches(&HistoryQuery {
+            ip: params.ip.map(|ip| ip.to_string()),
+            since: params.since,
+            limit: Some(params.limit),
+        })
+    })
+    .await;
+    match records {
+        Ok(Ok(records)) => Json(records).into_response(),
+        Ok(Err(e)) => (StatusCode::INTERNAL_SERVER_ERROR, format!("{:#}\n", e)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("{}\n", e)).into_response(),
+    }
+}
+
+async fn pause(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    request: Option<Json<PauseRequest>>,
+) -> Response {
+    let request = request.map(|Json(request)| request).unwrap_or_default();
+    if request
+        .duration_secs
+        .is_some_and(|duration_secs| duration_secs > MAX_DURATION_SECS)
+    {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!(
+                "A pause lasts at most {}s; leave the duration out to pause until resumed\n",
+                MAX_DURATION_SECS
+            ),
+        )
+            .into_response();
+    }
+    let message = match request.duration_secs {
+        Some(duration_secs) => format!(
+            "balancing paused for {}s from the next cycle\n",
+            duration_secs
+        ),
+        None => "balancing paused from the next cycle until resumed\n".to_string(),
+    };
+    info!(requested_by = %principal.name, "Pause requested");
+    state
+        .control
+        .request_pause(PauseChange::Pause(request), &principal.name);
+    (StatusCode::ACCEPTED, message).into_response()
+}
+
+async fn resume(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+) -> Response {
+    info!(requested_by = %principal.name, "Resume requested");
+    state
+        .control
+        .request_pause(PauseChange::Resume, &principal.name);
+    (
+        StatusCode::ACCEPTED,
+        "balancing resumes on the next cycle\n",
+    )
+        .into_response()
+}
+
+async fn switch(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    Json(request): Json<SwitchRequest>,
+) -> Response {
+    let message = format!(
+        "{} switching to {} on the next cycle\n",
+        request.ip, request.wan
+    );
+    info!(ip = %request.ip, wan = %request.wan, requested_by = %principal.name, "Manual switch requested");
+    state.control.request_switch(request, &principal.name);
+    (StatusCode::ACCEPTED, message).into_response()
+}
+
+/// Live events as server-sent events named by their type; a `lagged` event tells the
+/// client how many it missed after falling behind.
+async fn events(
+    State(state): State<AppState>,
+) -> Sse<impl Stream<Item = Result<sse::Event, Infallible>>> {
+    let subscription = state.event_stream.subscribe();
+    let stream = stream::unfold(subscription, |mut subscription| async move {
+        let message = match subscription.next().await? {
+            Delivery::Event(event) => sse::Event::default()
+                .event(event.kind())
+                .json_data(event.as_ref())
+                .unwrap_or_default(),
+            Delivery::Lagged(dropped) => sse::Event::default()
+                .event("lagged")
+                .data(format!("{{\"dropped\":{}}}", dropped)),
+        };
+        Some((Ok(message), subscription))
+    });
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+#[derive(Debug, Deserialize)]
+struct WebSocketParams {
+    /// Comma-separated event types; all when absent.
+    events: Option<String>,
+    /// API key for clients that cannot send headers.
+    api_key: Option<String>,
+}
+
+/// The live events of `/events`, as JSON text messages over a WebSocket.
+async fn events_websocket(
+    State(state): State<AppState>,
+    Query(params): Query<WebSocketParams>,
+    mut credentials: header::HeaderMap,
+    upgrade: Result<WebSocketUpgrade, WebSocketUpgradeRejection>,
+) -> Response {
+    if let Some(key) = params
+        .api_key
+        .as_deref()
+        .and_then(|key| header::HeaderValue::from_str(key).ok())
+    {
+        credentials.insert("x-api-key", key);
+    }
+    if let Err(e) = state.auth.authorize(&credentials, Role::Viewer).await {
+        return auth_error(e);
+    }
+
+    let kinds = match params.events.as_deref() {
+        Some(events) => {
+            let mut kinds = std::collections::HashSet::new();
+            for kind in events
+                .split(',')
+                .map(str::trim)
+                .filter(|kind| !kind.is_empty())
+            {
+                if !Event::KINDS.contains(&kind) {
+                    return (
+                        StatusCode::BAD_REQUEST,
+                        format!(
+                            "unknown event type {:?} (expected one of {})\n",
+                            kind,
+                            Event::KINDS.join(", ")
+                        ),
+                    )
+                        .into_response();
+                }
+                kinds.insert(kind.to_string());
+            }
+            Some(kinds)
+        }
+        None => None,
+    };
+
+    let upgrade = match upgrade {
+        Ok(upgrade) => upgrade,
+        Err(rejection) => return rejection.into_response(),
+    };
+    let subscription = state.event_stream.subscribe();
+    upgrade
+        .max_message_size(websocket::MAX_CLIENT_MESSAGE)
+        .on_upgrade(move |socket| websocket::stream_events(socket, subscription, kinds))
+}
+
+async fn diag_snapshot(State(state): State<AppState>) -> impl IntoResponse {
+    Json(state.diag.snapshot())
+}
+
+async fn dashboard_data(State(state): State<AppState>) -> Response {
+    match &state.dashboard {
+        Some(dashboard) => Json(dashboard.snapshot()).into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+async fn capacity_hints(State(state): State<AppState>) -> Response {
+    let Some(hints) = &state.hints else {
+        return (StatusCode::NOT_FOUND, "capacity hints are disabled\n").into_response();
+    };
+    match hints.rendered() {
+        Some((content_type, body)) => {
+            ([(header::CONTENT_TYPE, content_type)], body).into_response()
+        }
+        None => (StatusCode::SERVICE_UNAVAILABLE, "no hints yet\n").into_response(),
+    }
+}
+
+async fn status_page(
+    State(state): State<AppState>,
+    ConnectInfo(client): ConnectInfo<SocketAddr>,
+) -> Response {
+    if let Some(rejected) = rate_limited(&state, client) {
+        return rejected;
+    }
+    match state.status_board.snapshot() {
+        Some(status) => Html(status.render_html()).into_response(),
+        None => (StatusCode::SERVICE_UNAVAILABLE, "no status yet\n").into_response(),
+    }
+}
+
+async fn status_json(
+    State(state): State<AppState>,
+    ConnectInfo(client): ConnectInfo<SocketAddr>,
+) -> Response {
+    if let Some(rejected) = rate_limited(&state, client) {
+        return rejected;
+    }
+    match state.status_board.snapshot() {
+        Some(status) => Json(status).into_response(),
+        None => (StatusCode::SERVICE_UNAVAILABLE, "no status yet\n").into_response(),
+    }
+}
+
+fn rate_limited(state: &AppState, client: SocketAddr) -> Option<Response> {
+    let limiter = state.status_limiter.as_ref()?;
+    if limiter.allow(client.ip()) {
+        return None;
+    }
+    Some(
+        (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(header::RETRY_AFTER, "60")],
+            "rate limit exceeded\n",
+        )
+            .into_response(),
+    )
+}
+
+/// Requires callers of every route in `router` to hold at least `role`.
+fn with_role(router: Router<AppState>, state: &AppState, role: Role) -> Router<AppState> {
+    router.route_layer(middleware::from_fn_with_state(
+        state.clone(),
+        move |State(state): State<AppState>, request: Request, next: Next| {
+            authorize(state, request, next, role)
+        },
+    ))
+}
+
+async fn authorize(state: AppState, mut request: Request, next: Next, role: Role) -> Response {
+    match state.auth.authorize(request.headers(), role).await {
+        Ok(principal) => {
+            request.extensions_mut().insert(principal);
+            next.run(request).await
+        }
+        Err(e) => auth_error(e),
+    }
+}
+
+fn auth_error(error: AuthError) -> Response {
+    match error {
+        AuthError::Unauthenticated => (
+            StatusCode::UNAUTHORIZED,
+            [(header::WWW_AUTHENTICATE, "Bearer")],
+            "authentication required\n",
+        )
+            .into_response(),
+        AuthError::Forbidden {
+            principal,
+            required,
+        } => (
+            StatusCode::FORBIDDEN,
+            format!(
+                "role {} required ({} has role {})\n",
+                required, principal.name, principal.role
+            ),
+        )
+            .into_response(),
+    }
+}
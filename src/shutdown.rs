@@ -0,0 +1,64 @@
+use anyhow::{Context, Result};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::watch;
+use tracing::{info, warn};
+
+/// Stop request from SIGINT or SIGTERM. The balancing loop only checks it between cycles,
+/// so a cycle that has started, and the switch calls it makes, always completes; a second
+/// signal exits at once.
+#[derive(Clone)]
+pub struct Shutdown {
+    requested: watch::Receiver<bool>,
+}
+
+impl Shutdown {
+    pub fn listen() -> Result<Self> {
+        let mut interrupt =
+            signal(SignalKind::interrupt()).context("Failed to install the SIGINT handler")?;
+        let mut terminate =
+            signal(SignalKind::terminate()).context("Failed to install the SIGTERM handler")?;
+        let (sender, requested) = watch::channel(false);
+        tokio::spawn(async move {
+            loop {
+                let name = tokio::select! {
+                    _ = interrupt.recv() => "SIGINT",
+                    _ = terminate.recv() => "SIGTERM",
+                };
+                if *sender.borrow() {
+                    warn!(
+                        signal = name,
+                        "Received a second stop signal; exiting immediately"
+                    );
+                    std::process::exit(130);
+                }
+                info!(signal = name, "Stopping after the current cycle");
+                let _ = sender.send(true);
+            }
+        });
+        Ok(Self { requested })
+    }
+
+    /// Stopped by sending `true` on the returned sender instead of by a signal, for runs
+    /// the daemon drives itself.
+    pub fn manual() -> (watch::Sender<bool>, Self) {
+        let (sender, requested) = watch::channel(false);
+        (sender, Self { requested })
+    }
+
+    pub fn is_requested(&self) -> bool {
+        *self.requested.borrow()
+    }
+
+    /// Waits until a stop is requested.
+    pub async fn requested(&mut self) {
+        // An error means the listener is gone, and with it any way to be stopped
+        if self
+            .requested
+            .wait_for(|requested| *requested)
+            .await
+            .is_err()
+        {
+            std::future::pending::<()>().await;
+        }
+    }
+}
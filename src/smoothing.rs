@@ -0,0 +1,132 @@
+use crate::config::SmoothingConfig;
+use crate::model::{ClientIp, IpTraffic, NicName, NicStats};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The smoothed values, as saved across restarts.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SmootherState {
+    /// NIC → (TCP bandwidth, tx, rx)
+    pub nics: HashMap<NicName, (f64, f64, f64)>,
+    /// Client → (rx, tx)
+    pub ips: HashMap<ClientIp, (f64, f64)>,
+    /// Unix time of the last sample.
+    pub last_sample: Option<u64>,
+}
+
+/// Exponentially weighted moving average over the per-NIC and per-IP readings, so single
+/// noisy instant-vector samples do not drive switch decisions.
+pub struct Smoother {
+    config: SmoothingConfig,
+    nics: HashMap<NicName, NicStats>,
+    ips: HashMap<ClientIp, (f64, f64)>,
+    last_sample: Option<SystemTime>,
+}
+
+impl Smoother {
+    pub fn new(config: SmoothingConfig) -> Self {
+        Self {
+            config,
+            nics: HashMap::new(),
+            ips: HashMap::new(),
+            last_sample: None,
+        }
+    }
+
+    pub fn export(&self) -> SmootherState {
+        SmootherState {
+            nics: self
+                .nics
+                .iter()
+                .map(|(nic, stats)| {
+                    (
+                        nic.clone(),
+                        (stats.tcp_bandwidth, stats.tx_bps, stats.rx_bps),
+                    )
+                })
+                .collect(),
+            ips: self.ips.clone(),
+            last_sample: self
+                .last_sample
+                .and_then(|last| Some(last.duration_since(UNIX_EPOCH).ok()?.as_secs())),
+        }
+    }
+
+    pub fn restore(&mut self, state: SmootherState) {
+        self.nics = state
+            .nics
+            .into_iter()
+            .map(|(nic, (tcp_bandwidth, tx_bps, rx_bps))| {
+                let stats = NicStats {
+                    tcp_bandwidth,
+                    tx_bps,
+                    rx_bps,
+                    ..NicStats::default()
+                };
+                (nic, stats)
+            })
+            .collect();
+        self.ips = state.ips;
+        self.last_sample = state
+            .last_sample
+            .map(|secs| UNIX_EPOCH + Duration::from_secs(secs));
+    }
+
+    /// Weight of the newest sample: `alpha`, or derived from the time since the previous
+    /// sample when a `window_secs` time constant is configured.
+    fn alpha(&mut self, now: SystemTime) -> f64 {
+        let elapsed = self
+            .last_sample
+            .replace(now)
+            .map(|last| now.duration_since(last).unwrap_or_default());
+        match (self.config.window_secs, elapsed) {
+            (Some(window_secs), Some(elapsed)) if window_secs > 0.0 => {
+                1.0 - (-elapsed.as_secs_f64() / window_secs).exp()
+            }
+            _ => self.config.alpha,
+        }
+        .clamp(0.0, 1.0)
+    }
+
+    /// Replaces the readings with their smoothed values. NICs and IPs seen for the first
+    /// time start from their raw reading; those no longer reported are forgotten.
+    pub fn apply(
+        &mut self,
+        now: SystemTime,
+        nic_stats: &mut HashMap<NicName, NicStats>,
+        ip_traffic: &mut [IpTraffic],
+    ) {
+        let alpha = self.alpha(now);
+        let ewma = |previous: f64, sample: f64| alpha * sample + (1.0 - alpha) * previous;
+
+        self.nics.retain(|nic, _| nic_stats.contains_key(nic));
+        for (nic, stats) in nic_stats.iter_mut() {
+            let smoothed = self
+                .nics
+                .entry(nic.clone())
+                .and_modify(|previous| {
+                    previous.tcp_bandwidth = ewma(previous.tcp_bandwidth, stats.tcp_bandwidth);
+                    previous.tx_bps = ewma(previous.tx_bps, stats.tx_bps);
+                    previous.rx_bps = ewma(previous.rx_bps, stats.rx_bps);
+                })
+                .or_insert_with(|| stats.clone());
+            *stats = smoothed.clone();
+        }
+
+        self.ips
+            .retain(|ip, _| ip_traffic.iter().any(|traffic| &traffic.ip == ip));
+        for traffic in ip_traffic {
+            let (rx_bps, tx_bps) = self
+                .ips
+                .entry(traffic.ip)
+                .and_modify(|(rx_bps, tx_bps)| {
+                    *rx_bps = ewma(*rx_bps, traffic.rx_bps);
+                    *tx_bps = ewma(*tx_bps, traffic.tx_bps);
+                })
+                .or_insert((traffic.rx_bps, traffic.tx_bps));
+            traffic.rx_bps = *rx_bps;
+            traffic.tx_bps = *tx_bps;
+        }
+    }
+}
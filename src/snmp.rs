@@ -0,0 +1,398 @@
+use crate::clock::Clock;
+use crate::config::{SnmpConfig, SnmpWanConfig};
+use crate::error::ConfigError;
+use crate::model::WanId;
+use anyhow::{bail, Context, Result};
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hasher};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use tokio::net::UdpSocket;
+use tokio::task::JoinSet;
+use tracing::{debug, warn};
+
+const TAG_INTEGER: u8 = 0x02;
+const TAG_OCTET_STRING: u8 = 0x04;
+const TAG_NULL: u8 = 0x05;
+const TAG_OID: u8 = 0x06;
+const TAG_SEQUENCE: u8 = 0x30;
+const TAG_COUNTER32: u8 = 0x41;
+const TAG_GAUGE32: u8 = 0x42;
+const TAG_COUNTER64: u8 = 0x46;
+const TAG_NO_SUCH_OBJECT: u8 = 0x80;
+const TAG_NO_SUCH_INSTANCE: u8 = 0x81;
+const PDU_GET_REQUEST: u8 = 0xA0;
+const PDU_RESPONSE: u8 = 0xA2;
+const SNMP_V2C: u8 = 1;
+
+/// A WAN's traffic as its modem counts it.
+#[derive(Debug, Clone, Copy)]
+pub struct WanTotals {
+    pub rx_bps: f64,
+    pub tx_bps: f64,
+    polled_at: SystemTime,
+}
+
+/// One WAN's agent with its OIDs parsed.
+struct Target {
+    wan: WanId,
+    host: String,
+    community: String,
+    in_octets: Vec<u32>,
+    out_octets: Vec<u32>,
+}
+
+/// Polls the modems' octet counters in the background and turns them into per-WAN rates.
+/// Modem counters include traffic no client exporter sees (the router's own, other LAN
+/// segments), so they can stand in for or replace the sum of the clients' traffic.
+pub struct SnmpPoller {
+    totals: Arc<Mutex<HashMap<WanId, WanTotals>>>,
+    max_age: Duration,
+    clock: Arc<dyn Clock>,
+}
+
+impl SnmpPoller {
+    pub fn spawn(config: SnmpConfig, clock: Arc<dyn Clock>) -> Result<Self, ConfigError> {
+        let targets = config
+            .wans
+            .iter()
+            .map(Target::new)
+            .collect::<Result<Vec<_>, _>>()?;
+        let totals = Arc::new(Mutex::new(HashMap::new()));
+        let interval = Duration::from_secs(config.interval_secs.max(1));
+        tokio::spawn(run(
+            targets,
+            interval,
+            Duration::from_millis(config.timeout_ms),
+            totals.clone(),
+            clock.clone(),
+        ));
+        Ok(Self {
+            totals,
+            // A missed poll or two is fine; beyond that the modem is not answering
+            max_age: interval * 3,
+            clock,
+        })
+    }
+
+    /// The latest rates of the WANs polled recently.
+    pub fn snapshot(&self) -> HashMap<WanId, WanTotals> {
+        self.totals
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, totals)| self.clock.since(totals.polled_at) <= self.max_age)
+            .map(|(wan, totals)| (wan.clone(), *totals))
+            .collect()
+    }
+}
+
+impl Target {
+    fn new(config: &SnmpWanConfig) -> Result<Self, ConfigError> {
+        let oid = |oid: &str| {
+            parse_oid(oid).ok_or_else(|| {
+                ConfigError::Invalid(format!("Invalid SNMP OID {} for {}", oid, config.wan))
+            })
+        };
+        let host = match config.host.trim_matches(['[', ']']).parse::<IpAddr>() {
+            Ok(address) => SocketAddr::new(address, 161).to_string(),
+            Err(_) if config.host.contains(':') => config.host.clone(),
+            Err(_) => format!("{}:161", config.host),
+        };
+        Ok(Self {
+            wan: config.wan.clone(),
+            host,
+            community: config.community.clone(),
+            in_octets: oid(&config.in_octets_oid)?,
+            out_octets: oid(&config.out_octets_oid)?,
+        })
+    }
+}
+
+async fn run(
+    targets: Vec<Target>,
+    interval: Duration,
+    timeout: Duration,
+    totals: Arc<Mutex<HashMap<WanId, WanTotals>>>,
+    clock: Arc<dyn Clock>,
+) {
+    let targets: Vec<Arc<Target>> = targets.into_iter().map(Arc::new).collect();
+    // Previous reading of each WAN: `(in, out, when)`
+    let mut previous: HashMap<WanId, (u64, u64, SystemTime)> = HashMap::new();
+    let mut next_poll = clock.now();
+
+    loop {
+        clock.sleep_until(next_poll).await;
+        next_poll = clock.now() + interval;
+        let mut polls = JoinSet::new();
+        for target in &targets {
+            let target = target.clone();
+            let clock = clock.clone();
+            polls.spawn(async move {
+                let counters = tokio::time::timeout(
+                    timeout,
+                    get(
+                        &target.host,
+                        &target.community,
+                        &[&target.in_octets, &target.out_octets],
+                    ),
+                )
+                .await
+                .unwrap_or_else(|_| Err(anyhow::anyhow!("timed out")));
+                (target, counters, clock.now())
+            });
+        }
+        while let Some(Ok((target, counters, at))) = polls.join_next().await {
+            let (in_octets, out_octets) = match counters.as_deref() {
+                Ok([in_octets, out_octets]) => (*in_octets, *out_octets),
+                Ok(_) => continue,
+                Err(e) => {
+                    warn!(wan = %target.wan, host = %target.host, "SNMP poll failed: {:#}", e);
+                    continue;
+                }
+            };
+            let Some((previous_in, previous_out, previous_at)) =
+                previous.insert(target.wan.clone(), (in_octets, out_octets, at))
+            else {
+                continue;
+            };
+            let elapsed = at
+                .duration_since(previous_at)
+                .unwrap_or_default()
+                .as_secs_f64();
+            let (Some(rx_octets), Some(tx_octets)) = (
+                increase(previous_in, in_octets),
+                increase(previous_out, out_octets),
+            ) else {
+                debug!(wan = %target.wan, "SNMP counters reset");
+                continue;
+            };
+            if elapsed <= 0.0 {
+                continue;
+            }
+            totals.lock().unwrap().insert(
+                target.wan.clone(),
+                WanTotals {
+                    rx_bps: rx_octets as f64 * 8.0 / elapsed,
+                    tx_bps: tx_octets as f64 * 8.0 / elapsed,
+                    polled_at: at,
+                },
+            );
+        }
+    }
+}
+
+/// Octets counted between two readings. A 32-bit counter that went backwards wrapped; a
+/// 64-bit one was reset, as was anything after an agent restart.
+fn increase(previous: u64, current: u64) -> Option<u64> {
+    if current >= previous {
+        Some(current - previous)
+    } else if previous <= u64::from(u32::MAX) {
+        Some(current + (1 << 32) - previous)
+    } else {
+        None
+    }
+}
+
+/// `1.3.6.1.2.1.31.1.1.1.6.2`, with or without a leading dot.
+fn parse_oid(oid: &str) -> Option<Vec<u32>> {
+    let arcs = oid
+        .trim_start_matches('.')
+        .split('.')
+        .map(|arc| arc.parse().ok())
+        .collect::<Option<Vec<u32>>>()?;
+    (arcs.len() >= 2 && arcs[0] <= 2 && arcs[1] < 40).then_some(arcs)
+}
+
+/// Reads the counters at `oids` with one SNMP v2c GET, in order.
+async fn get(host: &str, community: &str, oids: &[&[u32]]) -> Result<Vec<u64>> {
+    let server: SocketAddr = tokio::net::lookup_host(host)
+        .await
+        .with_context(|| format!("Failed to resolve {}", host))?
+        .next()
+        .with_context(|| format!("{} has no address", host))?;
+    let bind: SocketAddr = if server.is_ipv4() {
+        "0.0.0.0:0".parse()?
+    } else {
+        "[::]:0".parse()?
+    };
+    let socket = UdpSocket::bind(bind).await?;
+    socket.connect(server).await?;
+    let request_id = (RandomState::new().build_hasher().finish() as u32 & 0x7FFF_FFFF) as i64;
+    socket
+        .send(&get_request(community, request_id, oids))
+        .await?;
+
+    let mut buffer = [0u8; 1500];
+    loop {
+        let length = socket.recv(&mut buffer).await?;
+        // A late answer to an earlier poll is not this one's
+        if let Some(values) = parse_response(&buffer[..length], request_id, oids.len())? {
+            return Ok(values);
+        }
+    }
+}
+
+fn get_request(community: &str, request_id: i64, oids: &[&[u32]]) -> Vec<u8> {
+    let bindings: Vec<u8> = oids
+        .iter()
+        .flat_map(|oid| {
+            let mut binding = tlv(TAG_OID, &encode_oid(oid));
+            binding.extend(tlv(TAG_NULL, &[]));
+            tlv(TAG_SEQUENCE, &binding)
+        })
+        .collect();
+    let mut pdu = tlv(TAG_INTEGER, &encode_integer(request_id));
+    // Error status and index
+    pdu.extend(tlv(TAG_INTEGER, &[0]));
+    pdu.extend(tlv(TAG_INTEGER, &[0]));
+    pdu.extend(tlv(TAG_SEQUENCE, &bindings));
+
+    let mut message = tlv(TAG_INTEGER, &[SNMP_V2C]);
+    message.extend(tlv(TAG_OCTET_STRING, community.as_bytes()));
+    message.extend(tlv(PDU_GET_REQUEST, &pdu));
+    tlv(TAG_SEQUENCE, &message)
+}
+
+/// The values of a response to `request_id`; `None` for a response to another request.
+fn parse_response(packet: &[u8], request_id: i64, expected: usize) -> Result<Option<Vec<u64>>> {
+    let (_, message, _) = read_tlv(packet, 0, TAG_SEQUENCE)?;
+    let (_, _version, offset) = read_tlv(message, 0, TAG_INTEGER)?;
+    let (_, _community, offset) = read_tlv(message, offset, TAG_OCTET_STRING)?;
+    let (_, pdu, _) = read_tlv(message, offset, PDU_RESPONSE)?;
+
+    let (_, id, offset) = read_tlv(pdu, 0, TAG_INTEGER)?;
+    if decode_unsigned(id)? as i64 != request_id {
+        return Ok(None);
+    }
+    let (_, error_status, offset) = read_tlv(pdu, offset, TAG_INTEGER)?;
+    let (_, error_index, offset) = read_tlv(pdu, offset, TAG_INTEGER)?;
+    match decode_unsigned(error_status)? {
+        0 => {}
+        status => bail!(
+            "Agent answered with error status {} at binding {}",
+            status,
+            decode_unsigned(error_index)?
+        ),
+    }
+
+    let (_, bindings, _) = read_tlv(pdu, offset, TAG_SEQUENCE)?;
+    let mut values = Vec::with_capacity(expected);
+    let mut offset = 0;
+    while offset < bindings.len() {
+        let (_, binding, next) = read_tlv(bindings, offset, TAG_SEQUENCE)?;
+        offset = next;
+        let (_, _oid, value_offset) = read_tlv(binding, 0, TAG_OID)?;
+        let (tag, value, _) = read_any_tlv(binding, value_offset)?;
+        match tag {
+            TAG_COUNTER32 | TAG_COUNTER64 | TAG_GAUGE32 | TAG_INTEGER => {
+                values.push(decode_unsigned(value)?)
+            }
+            TAG_NO_SUCH_OBJECT | TAG_NO_SUCH_INSTANCE => {
+                bail!("Agent has no object at binding {}", values.len() + 1)
+            }
+            tag => bail!("Unexpected SNMP value type 0x{:02x}", tag),
+        }
+    }
+    if values.len() != expected {
+        bail!("Expected {} values, got {}", expected, values.len());
+    }
+    Ok(Some(values))
+}
+
+fn tlv(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut out = vec![tag];
+    let length = content.len();
+    if length < 0x80 {
+        out.push(length as u8);
+    } else {
+        let bytes: Vec<u8> = length
+            .to_be_bytes()
+            .into_iter()
+            .skip_while(|byte| *byte == 0)
+            .collect();
+        out.push(0x80 | bytes.len() as u8);
+        out.extend(bytes);
+    }
+    out.extend_from_slice(content);
+    out
+}
+
+/// Two's complement, big-endian, in as few bytes as possible.
+fn encode_integer(value: i64) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    let mut start = 0;
+    while start < 7
+        && ((bytes[start] == 0 && bytes[start + 1] & 0x80 == 0)
+            || (bytes[start] == 0xFF && bytes[start + 1] & 0x80 != 0))
+    {
+        start += 1;
+    }
+    bytes[start..].to_vec()
+}
+
+fn encode_oid(arcs: &[u32]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let first = arcs[0] * 40 + arcs[1];
+    for arc in std::iter::once(first).chain(arcs[2..].iter().copied()) {
+        let mut groups = vec![(arc & 0x7F) as u8];
+        let mut rest = arc >> 7;
+        while rest > 0 {
+            groups.push((rest & 0x7F) as u8 | 0x80);
+            rest >>= 7;
+        }
+        out.extend(groups.into_iter().rev());
+    }
+    out
+}
+
+/// An unsigned big-endian number of up to 64 bits, such as a counter or a status.
+fn decode_unsigned(bytes: &[u8]) -> Result<u64> {
+    // Counter64 values at or above 2^63 carry a leading zero byte
+    let bytes = match bytes {
+        [0, rest @ ..] if !rest.is_empty() => rest,
+        bytes => bytes,
+    };
+    if bytes.len() > 8 {
+        bail!("SNMP integer of {} bytes", bytes.len());
+    }
+    Ok(bytes
+        .iter()
+        .fold(0u64, |value, byte| (value << 8) | u64::from(*byte)))
+}
+
+/// The element at `offset`, which must have tag `expected`.
+fn read_tlv(packet: &[u8], offset: usize, expected: u8) -> Result<(u8, &[u8], usize)> {
+    let (tag, content, next) = read_any_tlv(packet, offset)?;
+    if tag != expected {
+        bail!("Expected SNMP tag 0x{:02x}, got 0x{:02x}", expected, tag);
+    }
+    Ok((tag, content, next))
+}
+
+/// `(tag, content, offset after it)` of the element at `offset`.
+fn read_any_tlv(packet: &[u8], offset: usize) -> Result<(u8, &[u8], usize)> {
+    let tag = *packet.get(offset).context("Truncated SNMP message")?;
+    let first = *packet.get(offset + 1).context("Truncated SNMP message")?;
+    let (length, start) = if first & 0x80 == 0 {
+        (usize::from(first), offset + 2)
+    } else {
+        let count = usize::from(first & 0x7F);
+        if count == 0 || count > 4 {
+            bail!("Unsupported SNMP length encoding");
+        }
+        let bytes = packet
+            .get(offset + 2..offset + 2 + count)
+            .context("Truncated SNMP message")?;
+        let length = bytes
+            .iter()
+            .fold(0usize, |length, byte| (length << 8) | usize::from(*byte));
+        (length, offset + 2 + count)
+    };
+    let content = packet
+        .get(start..start + length)
+        .context("Truncated SNMP message")?;
+    Ok((tag, content, start + length))
+}
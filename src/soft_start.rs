@@ -0,0 +1,68 @@
+use crate::config::SoftStartConfig;
+use std::collections::VecDeque;
+
+/// Length of the window moves are counted over.
+const WINDOW_SECS: u64 = 60;
+
+/// Limits switches per minute while the controller's view of the network settles: the
+/// budget starts at `initial_moves_per_min` and grows linearly to `moves_per_min` over
+/// `ramp_secs`, after which switching is unrestricted again. The ramp restarts on
+/// startup, when a WAN recovers and when a new policy takes control.
+pub struct SoftStart {
+    config: SoftStartConfig,
+    ramp_started_at: u64,
+    /// Times of the moves within the last window, oldest first
+    recent: VecDeque<u64>,
+}
+
+impl SoftStart {
+    pub fn new(config: SoftStartConfig, now: u64) -> Self {
+        Self {
+            config,
+            ramp_started_at: now,
+            recent: VecDeque::new(),
+        }
+    }
+
+    pub fn restart(&mut self, now: u64) {
+        self.ramp_started_at = now;
+    }
+
+    pub fn ramp_started_at(&self) -> u64 {
+        self.ramp_started_at
+    }
+
+    /// Moves allowed per minute at `now`; `None` once the ramp is over.
+    pub fn budget(&self, now: u64) -> Option<u32> {
+        let elapsed = now.saturating_sub(self.ramp_started_at);
+        if elapsed >= self.config.ramp_secs {
+            return None;
+        }
+        let progress = elapsed as f64 / self.config.ramp_secs as f64;
+        let initial = f64::from(self.config.initial_moves_per_min);
+        let full = f64::from(self.config.moves_per_min);
+        Some((initial + (full - initial) * progress).floor().max(1.0) as u32)
+    }
+
+    /// Whether one more move fits the budget; if not, the seconds until one does.
+    pub fn check(&mut self, now: u64) -> Result<(), u64> {
+        while self
+            .recent
+            .front()
+            .is_some_and(|at| now.saturating_sub(*at) >= WINDOW_SECS)
+        {
+            self.recent.pop_front();
+        }
+        match self.budget(now) {
+            Some(budget) if self.recent.len() >= budget as usize => {
+                let oldest = self.recent[self.recent.len() - budget as usize];
+                Err((oldest + WINDOW_SECS).saturating_sub(now).max(1))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn record(&mut self, now: u64) {
+        self.recent.push_back(now);
+    }
+}
@@ -0,0 +1,110 @@
+use crate::config::SpeedtestConfig;
+use crate::error::ConfigError;
+use crate::model::{ClientIp, IpTraffic, NicName};
+use crate::policy::PolicyInput;
+
+/// A speed test seen as the top traffic of a NIC.
+#[derive(Debug, Clone)]
+pub struct SpeedtestBurst {
+    pub ip: ClientIp,
+    pub nic: NicName,
+    pub reason: String,
+}
+
+/// Recognizes speed tests among the heaviest clients: a flagged measurement device, or a
+/// client whose traffic goes to known speed test networks. While one runs (and for
+/// `hold_secs` after it was last seen) the balancing should leave the WANs alone, since
+/// the saturation it causes is over within seconds.
+pub struct SpeedtestGuard {
+    config: SpeedtestConfig,
+    last_seen: Option<(u64, SpeedtestBurst)>,
+}
+
+impl SpeedtestGuard {
+    pub fn new(config: SpeedtestConfig, destinations_enabled: bool) -> Result<Self, ConfigError> {
+        let by_destination = !config.prefixes.is_empty() || !config.asns.is_empty();
+        if !by_destination && config.devices.is_empty() {
+            return Err(ConfigError::Invalid(
+                "[speedtest] needs at least one prefix, ASN or measurement device".to_string(),
+            ));
+        }
+        if by_destination && !destinations_enabled {
+            return Err(ConfigError::Invalid(
+                "[speedtest] prefixes and ASNs need destination attribution ([destinations])"
+                    .to_string(),
+            ));
+        }
+        Ok(Self {
+            config,
+            last_seen: None,
+        })
+    }
+
+    /// The speed test holding back rebalancing at `now`, if any.
+    pub fn check(&mut self, input: &PolicyInput, now: u64) -> Option<&SpeedtestBurst> {
+        let mut nics: Vec<_> = input.nic_stats.keys().collect();
+        nics.sort();
+        if let Some(burst) = nics.into_iter().find_map(|nic| self.detect(input, nic)) {
+            self.last_seen = Some((now, burst));
+        }
+        self.last_seen
+            .as_ref()
+            .filter(|(seen_at, _)| now.saturating_sub(*seen_at) <= self.config.hold_secs)
+            .map(|(_, burst)| burst)
+    }
+
+    fn detect(&self, input: &PolicyInput, nic: &NicName) -> Option<SpeedtestBurst> {
+        let top = input
+            .ip_traffic
+            .iter()
+            .filter(|traffic| &traffic.nic == nic)
+            .max_by(|a, b| total_bps(a).total_cmp(&total_bps(b)))?;
+        let min_bps = self.config.min_mbps * 1_000_000.0;
+
+        let addr = top.ip.addr();
+        if self
+            .config
+            .devices
+            .iter()
+            .any(|device| device.contains(&addr))
+            && total_bps(top) >= min_bps
+        {
+            return Some(SpeedtestBurst {
+                ip: top.ip,
+                nic: nic.clone(),
+                reason: format!("measurement device {}", top.ip),
+            });
+        }
+
+        let flows = input.destinations.iter().filter(|flow| {
+            flow.client_ip == top.ip
+                && (self
+                    .config
+                    .prefixes
+                    .iter()
+                    .any(|prefix| prefix.contains(&flow.destination))
+                    || flow
+                        .info
+                        .asn
+                        .is_some_and(|asn| self.config.asns.contains(&asn)))
+        });
+        let busiest = flows
+            .clone()
+            .max_by(|a, b| (a.rx_bps + a.tx_bps).total_cmp(&(b.rx_bps + b.tx_bps)))?;
+        let test_bps: f64 = flows.map(|flow| flow.rx_bps + flow.tx_bps).sum();
+        (test_bps >= min_bps).then(|| SpeedtestBurst {
+            ip: top.ip,
+            nic: nic.clone(),
+            reason: format!(
+                "{} is testing against {} ({:.2} Mbps)",
+                top.ip,
+                busiest.destination,
+                test_bps / 1_000_000.0
+            ),
+        })
+    }
+}
+
+fn total_bps(traffic: &IpTraffic) -> f64 {
+    traffic.rx_bps + traffic.tx_bps
+}
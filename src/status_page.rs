@@ -0,0 +1,146 @@
+use crate::clock::Clock;
+use crate::model::{NicName, WanId};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+/// Age after which the published status is reported as stale.
+const STALE_AFTER_SECS: u64 = 10;
+
+/// Per-WAN health as shown on the public status page. Never contains client IPs.
+#[derive(Debug, Clone, Serialize)]
+pub struct WanStatus {
+    pub wan: WanId,
+    pub nic: NicName,
+    /// `ok` with a bandwidth estimate, `no_data` without one, `down` when every latency probe
+    /// failed and `degraded` behind a captive portal or hijacking resolver.
+    pub health: &'static str,
+    pub utilization: Option<f64>,
+    pub tcp_bandwidth_bps: f64,
+    pub traffic_bps: f64,
+    pub clients: usize,
+    pub rtt_ms: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PublicStatus {
+    pub updated_at: u64,
+    pub stale: bool,
+    pub wans: Vec<WanStatus>,
+}
+
+/// Latest per-WAN status published by the monitor loop.
+pub struct StatusBoard {
+    latest: Mutex<Option<(u64, Vec<WanStatus>)>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl StatusBoard {
+    pub fn new(clock: Arc<dyn Clock>) -> Self {
+        Self {
+            latest: Mutex::new(None),
+            clock,
+        }
+    }
+
+    pub fn update(&self, wans: Vec<WanStatus>) {
+        let now = self.clock.unix_secs();
+        *self.latest.lock().unwrap() = Some((now, wans));
+    }
+
+    pub fn snapshot(&self) -> Option<PublicStatus> {
+        let latest = self.latest.lock().unwrap();
+        let (updated_at, wans) = latest.as_ref()?;
+        Some(PublicStatus {
+            updated_at: *updated_at,
+            stale: self.clock.unix_secs().saturating_sub(*updated_at) > STALE_AFTER_SECS,
+            wans: wans.clone(),
+        })
+    }
+}
+
+impl PublicStatus {
+    /// Self-contained HTML page that refreshes itself, for embedding in an intranet page.
+    pub fn render_html(&self) -> String {
+        let mut out = String::new();
+        out.push_str(
+            "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\">\
+             <meta http-equiv=\"refresh\" content=\"10\">\
+             <title>WAN status</title></head><body>\n",
+        );
+        if self.stale {
+            out.push_str("<p><strong>Status is stale: the balancer has not reported recently.</strong></p>\n");
+        }
+        out.push_str(
+            "<table>\n<tr><th>WAN</th><th>Interface</th><th>Health</th>\
+             <th>Utilization</th><th>Bandwidth</th><th>Traffic</th><th>Clients</th><th>RTT</th></tr>\n",
+        );
+        for wan in &self.wans {
+            let utilization = wan
+                .utilization
+                .map(|utilization| format!("{:.1}%", utilization * 100.0))
+                .unwrap_or_else(|| "-".to_string());
+            let rtt = wan
+                .rtt_ms
+                .map(|rtt_ms| format!("{:.1} ms", rtt_ms))
+                .unwrap_or_else(|| "-".to_string());
+            let _ = writeln!(
+                out,
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td>\
+                 <td>{:.2} Mbps</td><td>{:.2} Mbps</td><td>{}</td><td>{}</td></tr>",
+                escape_html(wan.wan.as_str()),
+                escape_html(wan.nic.as_str()),
+                wan.health,
+                utilization,
+                wan.tcp_bandwidth_bps / 1_000_000.0,
+                wan.traffic_bps / 1_000_000.0,
+                wan.clients,
+                rtt
+            );
+        }
+        out.push_str("</table>\n</body></html>\n");
+        out
+    }
+}
+
+/// Fixed-window request limiter keyed by client address.
+pub struct RateLimiter {
+    per_window: u32,
+    window: Duration,
+    clients: Mutex<HashMap<IpAddr, (SystemTime, u32)>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl RateLimiter {
+    pub fn per_minute(per_window: u32, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            per_window,
+            window: Duration::from_secs(60),
+            clients: Mutex::new(HashMap::new()),
+            clock,
+        }
+    }
+
+    /// Counts a request from `client`; `false` once it exceeded its allowance.
+    pub fn allow(&self, client: IpAddr) -> bool {
+        let now = self.clock.now();
+        let mut clients = self.clients.lock().unwrap();
+        clients.retain(|_, (started, _)| {
+            now.duration_since(*started).unwrap_or_default() < self.window
+        });
+
+        let (_, count) = clients.entry(client).or_insert((now, 0));
+        *count += 1;
+        *count <= self.per_window
+    }
+}
+
+fn escape_html(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
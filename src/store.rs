@@ -0,0 +1,268 @@
+use crate::config::{HistoryBackend, HistoryConfig};
+use crate::history::SwitchRecord;
+use crate::history_db::{
+    HistoryDb, HistoryQuery, StoredClientTraffic, StoredNicStats, StoredProfile,
+    StoredPublicIpChange, StoredQuotaUsage, StoredResidency, StoredSwitch,
+};
+#[cfg(feature = "postgres")]
+use crate::postgres_store::PostgresStore;
+use anyhow::{bail, Result};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Where the daemon keeps what it wants back after a restart: the switch history (which
+/// cooldowns are computed from), public IP changes, NIC stats, and what the loop learns as it
+/// runs (class residencies, quota usage, traffic profiles). SQLite by default, or Postgres;
+/// kept in memory for tests and for embedders that bring their own storage.
+pub trait StateStore: Send + Sync {
+    /// Id of the inserted record, for `set_verification`.
+    fn insert_switch(&self, record: &StoredSwitch) -> Result<i64>;
+    fn set_verification(&self, id: i64, verification: &str) -> Result<()>;
+    /// Matching switches, newest first.
+    fn query_switches(&self, query: &HistoryQuery) -> Result<Vec<StoredSwitch>>;
+
+    fn insert_public_ip_change(&self, change: &StoredPublicIpChange) -> Result<()>;
+    /// The last recorded public address of every WAN.
+    fn latest_public_ips(&self) -> Result<HashMap<String, String>>;
+    /// Changes matching `query`'s time and limit, newest first.
+    fn query_public_ip_changes(&self, query: &HistoryQuery) -> Result<Vec<StoredPublicIpChange>>;
+
+    /// Stores one interval of NIC stats and drops intervals that started before
+    /// `retain_since`.
+    fn insert_nic_stats(&self, rows: &[StoredNicStats], retain_since: u64) -> Result<()>;
+    /// Stored NIC stats since `since`, oldest first.
+    fn query_nic_stats(&self, since: Option<u64>) -> Result<Vec<StoredNicStats>>;
+
+    /// Stores one interval of client traffic, like `insert_nic_stats`.
+    fn insert_client_traffic(&self, rows: &[StoredClientTraffic], retain_since: u64) -> Result<()>;
+    /// Stored client traffic since `since`, oldest first.
+    fn query_client_traffic(&self, since: Option<u64>) -> Result<Vec<StoredClientTraffic>>;
+
+    /// Replaces the stored class residencies.
+    fn save_residencies(&self, rows: &[StoredResidency]) -> Result<()>;
+    fn load_residencies(&self) -> Result<Vec<StoredResidency>>;
+
+    /// Replaces the stored quota usage.
+    fn save_quota_usage(&self, rows: &[StoredQuotaUsage]) -> Result<()>;
+    fn load_quota_usage(&self) -> Result<Vec<StoredQuotaUsage>>;
+
+    /// Replaces the stored traffic profiles.
+    fn save_profiles(&self, rows: &[StoredProfile]) -> Result<()>;
+    fn load_profiles(&self) -> Result<Vec<StoredProfile>>;
+
+    /// Writes out anything pending; the store is not used afterwards.
+    fn close(&self) -> Result<()>;
+
+    /// Successful switches since `since`, oldest first, to restore the cooldowns with.
+    fn recent_switches(&self, since: u64) -> Result<Vec<SwitchRecord>> {
+        let mut records: Vec<SwitchRecord> = self
+            .query_switches(&HistoryQuery {
+                since: Some(since),
+                ..HistoryQuery::default()
+            })?
+            .into_iter()
+            .filter(|record| record.result == "success")
+            .filter_map(|record| {
+                Some(SwitchRecord {
+                    ip: record.ip.parse().ok()?,
+                    target_wan: record.to_wan.parse().ok()?,
+                    timestamp: record.timestamp,
+                })
+            })
+            .collect();
+        records.reverse();
+        Ok(records)
+    }
+}
+
+/// Opens the configured store; `None` when history is disabled.
+pub fn open(config: &HistoryConfig) -> Result<Option<Arc<dyn StateStore>>> {
+    if !config.enabled {
+        return Ok(None);
+    }
+    Ok(Some(match config.backend {
+        HistoryBackend::Sqlite => Arc::new(HistoryDb::open(&config.db_path)?),
+        HistoryBackend::Postgres => open_postgres(config)?,
+        HistoryBackend::Memory => Arc::new(MemoryStore::default()),
+    }))
+}
+
+#[cfg(feature = "postgres")]
+fn open_postgres(config: &HistoryConfig) -> Result<Arc<dyn StateStore>> {
+    let Some(url) = &config.postgres_url else {
+        bail!("[history] backend = \"postgres\" needs a postgres_url");
+    };
+    Ok(Arc::new(PostgresStore::connect(
+        url,
+        &config.postgres_schema,
+    )?))
+}
+
+#[cfg(not(feature = "postgres"))]
+fn open_postgres(_config: &HistoryConfig) -> Result<Arc<dyn StateStore>> {
+    bail!("This build has no Postgres support; rebuild with the postgres feature")
+}
+
+/// Opens the stored history for the offline commands (`history`, `export`, ...), which
+/// cannot see a store that lives in the running instance's memory. `None` when there is no
+/// SQLite database yet.
+pub fn open_database(config: &HistoryConfig) -> Result<Option<Arc<dyn StateStore>>> {
+    match config.backend {
+        HistoryBackend::Memory => bail!(
+            "The history is kept in the running instance's memory; query it with GET /history"
+        ),
+        HistoryBackend::Sqlite if !config.db_path.exists() => Ok(None),
+        HistoryBackend::Sqlite => Ok(Some(Arc::new(HistoryDb::open(&config.db_path)?))),
+        HistoryBackend::Postgres => open_postgres(config).map(Some),
+    }
+}
+
+/// Everything in memory, gone with the process.
+#[derive(Default)]
+pub struct MemoryStore {
+    state: Mutex<MemoryState>,
+}
+
+#[derive(Default)]
+struct MemoryState {
+    /// Oldest first; a record's id is its index.
+    switches: Vec<StoredSwitch>,
+    public_ip_changes: Vec<StoredPublicIpChange>,
+    nic_stats: Vec<StoredNicStats>,
+    client_traffic: Vec<StoredClientTraffic>,
+    residencies: Vec<StoredResidency>,
+    quota_usage: Vec<StoredQuotaUsage>,
+    profiles: Vec<StoredProfile>,
+}
+
+impl StateStore for MemoryStore {
+    fn insert_switch(&self, record: &StoredSwitch) -> Result<i64> {
+        let mut state = self.state.lock().unwrap();
+        state.switches.push(record.clone());
+        Ok(state.switches.len() as i64 - 1)
+    }
+
+    fn set_verification(&self, id: i64, verification: &str) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(record) = usize::try_from(id)
+            .ok()
+            .and_then(|id| state.switches.get_mut(id))
+        {
+            record.verification = Some(verification.to_string());
+        }
+        Ok(())
+    }
+
+    fn query_switches(&self, query: &HistoryQuery) -> Result<Vec<StoredSwitch>> {
+        let state = self.state.lock().unwrap();
+        let mut records: Vec<StoredSwitch> = state
+            .switches
+            .iter()
+            .rev()
+            .filter(|record| query.ip.as_ref().is_none_or(|ip| &record.ip == ip))
+            .filter(|record| query.since.is_none_or(|since| record.timestamp >= since))
+            .cloned()
+            .collect();
+        // Newest first even for records inserted out of order (journal recovery)
+        records.sort_by_key(|record| std::cmp::Reverse(record.timestamp));
+        records.truncate(query.limit.unwrap_or(usize::MAX));
+        Ok(records)
+    }
+
+    fn insert_public_ip_change(&self, change: &StoredPublicIpChange) -> Result<()> {
+        self.state
+            .lock()
+            .unwrap()
+            .public_ip_changes
+            .push(change.clone());
+        Ok(())
+    }
+
+    fn latest_public_ips(&self) -> Result<HashMap<String, String>> {
+        let state = self.state.lock().unwrap();
+        Ok(state
+            .public_ip_changes
+            .iter()
+            .map(|change| (change.wan.clone(), change.current.clone()))
+            .collect())
+    }
+
+    fn query_public_ip_changes(&self, query: &HistoryQuery) -> Result<Vec<StoredPublicIpChange>> {
+        let state = self.state.lock().unwrap();
+        Ok(state
+            .public_ip_changes
+            .iter()
+            .rev()
+            .filter(|change| query.since.is_none_or(|since| change.timestamp >= since))
+            .take(query.limit.unwrap_or(usize::MAX))
+            .cloned()
+            .collect())
+    }
+
+    fn insert_nic_stats(&self, rows: &[StoredNicStats], retain_since: u64) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.nic_stats.extend_from_slice(rows);
+        state.nic_stats.retain(|row| row.timestamp >= retain_since);
+        Ok(())
+    }
+
+    fn query_nic_stats(&self, since: Option<u64>) -> Result<Vec<StoredNicStats>> {
+        let state = self.state.lock().unwrap();
+        Ok(state
+            .nic_stats
+            .iter()
+            .filter(|row| since.is_none_or(|since| row.timestamp >= since))
+            .cloned()
+            .collect())
+    }
+
+    fn insert_client_traffic(&self, rows: &[StoredClientTraffic], retain_since: u64) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.client_traffic.extend_from_slice(rows);
+        state
+            .client_traffic
+            .retain(|row| row.timestamp >= retain_since);
+        Ok(())
+    }
+
+    fn query_client_traffic(&self, since: Option<u64>) -> Result<Vec<StoredClientTraffic>> {
+        let state = self.state.lock().unwrap();
+        Ok(state
+            .client_traffic
+            .iter()
+            .filter(|row| since.is_none_or(|since| row.timestamp >= since))
+            .cloned()
+            .collect())
+    }
+
+    fn save_residencies(&self, rows: &[StoredResidency]) -> Result<()> {
+        self.state.lock().unwrap().residencies = rows.to_vec();
+        Ok(())
+    }
+
+    fn load_residencies(&self) -> Result<Vec<StoredResidency>> {
+        Ok(self.state.lock().unwrap().residencies.clone())
+    }
+
+    fn save_quota_usage(&self, rows: &[StoredQuotaUsage]) -> Result<()> {
+        self.state.lock().unwrap().quota_usage = rows.to_vec();
+        Ok(())
+    }
+
+    fn load_quota_usage(&self) -> Result<Vec<StoredQuotaUsage>> {
+        Ok(self.state.lock().unwrap().quota_usage.clone())
+    }
+
+    fn save_profiles(&self, rows: &[StoredProfile]) -> Result<()> {
+        self.state.lock().unwrap().profiles = rows.to_vec();
+        Ok(())
+    }
+
+    fn load_profiles(&self) -> Result<Vec<StoredProfile>> {
+        Ok(self.state.lock().unwrap().profiles.clone())
+    }
+
+    fn close(&self) -> Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,281 @@
+use crate::cli::ReportArgs;
+use crate::config::Config;
+use crate::history_db::{HistoryQuery, StoredClientTraffic, StoredNicStats, StoredSwitch};
+use crate::monitor::OutputFormat;
+use crate::store;
+use anyhow::{bail, Result};
+use clap::ValueEnum;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// An interval counts as saturated from this share of the TCP bandwidth estimate on;
+/// averaged over an interval, a WAN that is full most of the time rarely reaches all of it.
+const SATURATED_UTILIZATION: f64 = 0.9;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportPeriod {
+    /// The 24 hours before --until
+    Daily,
+    /// The 7 days before --until
+    Weekly,
+}
+
+impl ReportPeriod {
+    fn secs(self) -> u64 {
+        match self {
+            ReportPeriod::Daily => 24 * 60 * 60,
+            ReportPeriod::Weekly => 7 * 24 * 60 * 60,
+        }
+    }
+}
+
+/// Stored history of one period, summed up.
+#[derive(Debug, Serialize)]
+pub struct Summary {
+    pub period: ReportPeriod,
+    pub since: u64,
+    pub until: u64,
+    pub wans: Vec<WanSummary>,
+    /// Most switched first.
+    pub switches: Vec<ClientSwitches>,
+    /// Most traffic first.
+    pub top_talkers: Vec<TopTalker>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct WanSummary {
+    /// The WAN, or the NIC for one no WAN was mapped to.
+    pub wan: String,
+    pub nic: String,
+    /// Seconds of stored stats.
+    pub recorded_secs: u64,
+    /// Traffic over the TCP bandwidth estimate, per stored interval.
+    pub utilization_p50: f64,
+    pub utilization_p95: f64,
+    pub utilization_max: f64,
+    pub saturated_secs: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ClientSwitches {
+    pub ip: String,
+    pub switches: usize,
+    pub failed: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TopTalker {
+    pub ip: String,
+    /// Average over the whole period, idle time included.
+    pub rx_bps: f64,
+    pub tx_bps: f64,
+    pub bytes: f64,
+}
+
+impl Summary {
+    pub fn new(
+        period: ReportPeriod,
+        until: u64,
+        nic_stats: &[StoredNicStats],
+        switches: &[StoredSwitch],
+        client_traffic: &[StoredClientTraffic],
+        top: usize,
+    ) -> Self {
+        let since = until.saturating_sub(period.secs());
+        let in_period = |timestamp: u64| timestamp >= since && timestamp < until;
+
+        let mut per_wan: BTreeMap<String, (String, Vec<&StoredNicStats>)> = BTreeMap::new();
+        for stats in nic_stats.iter().filter(|stats| in_period(stats.timestamp)) {
+            let wan = stats.wan.clone().unwrap_or_else(|| stats.nic.clone());
+            per_wan
+                .entry(wan)
+                .or_insert_with(|| (stats.nic.clone(), Vec::new()))
+                .1
+                .push(stats);
+        }
+        let wans = per_wan
+            .into_iter()
+            .map(|(wan, (nic, intervals))| {
+                let mut utilization: Vec<f64> = intervals
+                    .iter()
+                    .filter(|stats| stats.tcp_bandwidth_bps > 0.0)
+                    .map(|stats| (stats.tx_bps + stats.rx_bps) / stats.tcp_bandwidth_bps)
+                    .collect();
+                utilization.sort_by(f64::total_cmp);
+                WanSummary {
+                    wan,
+                    nic,
+                    recorded_secs: intervals.iter().map(|stats| stats.interval_secs).sum(),
+                    utilization_p50: percentile(&utilization, 0.5),
+                    utilization_p95: percentile(&utilization, 0.95),
+                    utilization_max: utilization.last().copied().unwrap_or(0.0),
+                    saturated_secs: intervals
+                        .iter()
+                        .filter(|stats| {
+                            stats.tcp_bandwidth_bps > 0.0
+                                && stats.tx_bps + stats.rx_bps
+                                    >= stats.tcp_bandwidth_bps * SATURATED_UTILIZATION
+                        })
+                        .map(|stats| stats.interval_secs)
+                        .sum(),
+                }
+            })
+            .collect();
+
+        let mut per_ip: BTreeMap<&str, ClientSwitches> = BTreeMap::new();
+        for switch in switches.iter().filter(|switch| in_period(switch.timestamp)) {
+            let entry = per_ip.entry(&switch.ip).or_insert_with(|| ClientSwitches {
+                ip: switch.ip.clone(),
+                switches: 0,
+                failed: 0,
+            });
+            entry.switches += 1;
+            if switch.result != "success" {
+                entry.failed += 1;
+            }
+        }
+        let mut switches: Vec<ClientSwitches> = per_ip.into_values().collect();
+        // Stable, so ties stay in address order
+        switches.sort_by_key(|client| std::cmp::Reverse(client.switches));
+
+        let period_secs = period.secs() as f64;
+        let mut per_client: BTreeMap<&str, TopTalker> = BTreeMap::new();
+        for traffic in client_traffic
+            .iter()
+            .filter(|traffic| in_period(traffic.timestamp))
+        {
+            let secs = traffic.interval_secs as f64;
+            let talker = per_client.entry(&traffic.ip).or_insert_with(|| TopTalker {
+                ip: traffic.ip.clone(),
+                rx_bps: 0.0,
+                tx_bps: 0.0,
+                bytes: 0.0,
+            });
+            talker.rx_bps += traffic.rx_bps * secs / period_secs;
+            talker.tx_bps += traffic.tx_bps * secs / period_secs;
+            talker.bytes += (traffic.rx_bps + traffic.tx_bps) * secs / 8.0;
+        }
+        let mut top_talkers: Vec<TopTalker> = per_client.into_values().collect();
+        top_talkers.sort_by(|a, b| b.bytes.total_cmp(&a.bytes));
+        top_talkers.truncate(top);
+
+        Self {
+            period,
+            since,
+            until,
+            wans,
+            switches,
+            top_talkers,
+        }
+    }
+
+    pub fn print_json(&self) -> Result<()> {
+        println!("{}", serde_json::to_string_pretty(self)?);
+        Ok(())
+    }
+
+    pub fn print_text(&self) {
+        let time = |timestamp: u64| {
+            chrono::DateTime::from_timestamp(timestamp as i64, 0)
+                .map(|time| time.to_rfc3339())
+                .unwrap_or_default()
+        };
+        println!(
+            "{:?} report, {} to {}",
+            self.period,
+            time(self.since),
+            time(self.until)
+        );
+
+        println!("\nWAN utilization:");
+        if self.wans.is_empty() {
+            println!("  (No NIC stats recorded in that period)");
+        }
+        for wan in &self.wans {
+            println!(
+                "  {} ({}): p50 {:.0}%, p95 {:.0}%, max {:.0}%; saturated {} of {}",
+                wan.wan,
+                wan.nic,
+                wan.utilization_p50 * 100.0,
+                wan.utilization_p95 * 100.0,
+                wan.utilization_max * 100.0,
+                format_secs(wan.saturated_secs),
+                format_secs(wan.recorded_secs)
+            );
+        }
+
+        println!("\nSwitches per client:");
+        if self.switches.is_empty() {
+            println!("  (No switches in that period)");
+        }
+        for client in &self.switches {
+            let failed = if client.failed > 0 {
+                format!(" ({} failed)", client.failed)
+            } else {
+                String::new()
+            };
+            println!("  {}: {}{}", client.ip, client.switches, failed);
+        }
+
+        println!("\nTop talkers:");
+        if self.top_talkers.is_empty() {
+            println!("  (No client traffic recorded in that period)");
+        }
+        for talker in &self.top_talkers {
+            println!(
+                "  {}: {:.1} MB (avg RX {:.2} Mbps, TX {:.2} Mbps)",
+                talker.ip,
+                talker.bytes / 1_000_000.0,
+                talker.rx_bps / 1_000_000.0,
+                talker.tx_bps / 1_000_000.0
+            );
+        }
+    }
+}
+
+/// Nearest-rank percentile of `sorted`; 0 when empty.
+fn percentile(sorted: &[f64], quantile: f64) -> f64 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+    let rank = (quantile * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+fn format_secs(secs: u64) -> String {
+    format!("{}h{:02}m", secs / 3600, secs % 3600 / 60)
+}
+
+/// Implements the `report` subcommand: the stored NIC stats, switches and client traffic of
+/// the day or week up to `--until` or `now`, summed up.
+pub fn run_report_command(config: &Config, args: &ReportArgs, now: u64) -> Result<()> {
+    let Some(db) = store::open_database(&config.history)? else {
+        bail!(
+            "No switch history database at {}",
+            config.history.db_path.display()
+        );
+    };
+    let until = match args.until {
+        Some(until) => until.timestamp().max(0) as u64,
+        None => now,
+    };
+    let since = Some(until.saturating_sub(args.period.secs()));
+
+    let summary = Summary::new(
+        args.period,
+        until,
+        &db.query_nic_stats(since)?,
+        &db.query_switches(&HistoryQuery {
+            since,
+            ..HistoryQuery::default()
+        })?,
+        &db.query_client_traffic(since)?,
+        args.top,
+    );
+    match args.format {
+        OutputFormat::Text => summary.print_text(),
+        OutputFormat::Json => summary.print_json()?,
+    }
+    Ok(())
+}
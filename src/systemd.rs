@@ -0,0 +1,81 @@
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::time::Duration;
+use tracing::{debug, warn};
+
+/// Notifications to systemd for a `Type=notify` service: readiness once the backends have
+/// answered, and a watchdog ping every cycle so systemd restarts an instance whose loop
+/// hangs, e.g. on an HTTP call that never returns.
+pub struct Notifier {
+    socket: UnixDatagram,
+    address: SocketAddr,
+    watchdog: Option<Duration>,
+    ready: bool,
+}
+
+impl Notifier {
+    /// Connects to `$NOTIFY_SOCKET`; `None` when not started by systemd.
+    pub fn from_env() -> Option<Self> {
+        let path = std::env::var_os("NOTIFY_SOCKET")?;
+        let path = path.to_string_lossy();
+        // `@` marks a socket in the abstract namespace
+        let address = match path.strip_prefix('@') {
+            Some(name) => SocketAddr::from_abstract_name(name.as_bytes()),
+            None => SocketAddr::from_pathname(path.as_ref()),
+        };
+        let notifier = address
+            .and_then(|address| Ok((UnixDatagram::unbound()?, address)))
+            .map(|(socket, address)| Self {
+                socket,
+                address,
+                watchdog: watchdog_interval(),
+                ready: false,
+            });
+        match notifier {
+            Ok(notifier) => {
+                debug!(socket = %path, watchdog = ?notifier.watchdog, "Notifying systemd");
+                Some(notifier)
+            }
+            Err(e) => {
+                warn!("Cannot notify systemd at {}: {}", path, e);
+                None
+            }
+        }
+    }
+
+    /// Reports the service as started; only the first call sends anything.
+    pub fn ready(&mut self) {
+        if !self.ready {
+            self.ready = true;
+            self.send("READY=1");
+        }
+    }
+
+    /// Tells the watchdog the loop is alive, if systemd runs one for this service.
+    pub fn watchdog(&self) {
+        if self.watchdog.is_some() {
+            self.send("WATCHDOG=1");
+        }
+    }
+
+    pub fn stopping(&self) {
+        self.send("STOPPING=1");
+    }
+
+    fn send(&self, state: &str) {
+        if let Err(e) = self.socket.send_to_addr(state.as_bytes(), &self.address) {
+            warn!(state, "Failed to notify systemd: {}", e);
+        }
+    }
+}
+
+/// The watchdog timeout from `$WATCHDOG_USEC`, if it is meant for this process.
+fn watchdog_interval() -> Option<Duration> {
+    let usec: u64 = std::env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    if let Ok(pid) = std::env::var("WATCHDOG_PID") {
+        if pid.parse::<u32>().ok() != Some(std::process::id()) {
+            return None;
+        }
+    }
+    (usec > 0).then(|| Duration::from_micros(usec))
+}
@@ -0,0 +1,300 @@
+use crate::error::ConfigError;
+use crate::events::Event;
+use crate::report::CycleReport;
+use chrono::{Local, TimeZone};
+use minijinja::{context, Environment, UndefinedBehavior, Value};
+use std::collections::HashMap;
+use tracing::warn;
+
+/// Messages used for events without a configured template.
+const BUILTIN_TEMPLATES: [(&str, &str); 11] = [
+    (
+        "switch",
+        "{% if success %}Moved {{ ip }} from {{ from_nic }} to {{ target_wan }}{% else %}Failed to move {{ ip }} to {{ target_wan }}: {{ error }}{% endif %} ({{ reason }})",
+    ),
+    ("switch_skipped", "Skipped {{ ip }}: {{ reason }}"),
+    (
+        "bandwidth_exceeded",
+        "{{ nic }} carries {{ traffic_bps | mbps }} Mbps, above its estimate of {{ tcp_bandwidth_bps | mbps }} Mbps",
+    ),
+    (
+        "wan_health",
+        "{{ wan }} ({{ nic }}) is {% if up %}up{% else %}down{% endif %}: {{ reason }}",
+    ),
+    (
+        "public_ip_change",
+        "{{ wan }} ({{ nic }}) public IP {% if previous %}changed from {{ previous }} to {{ current }}{% else %}is {{ current }}{% endif %}",
+    ),
+    (
+        "quota",
+        "{% if ip %}{{ ip }}{% else %}Group of quota {{ quota }}{% endif %} {% if exceeded %}is routinely over quota {{ quota }} ({{ traffic_bps | mbps }} of {{ quota_bps | mbps }} Mbps); sending it to {{ bulk_wan }}{% else %}is back under quota {{ quota }}{% endif %}",
+    ),
+    (
+        "anomaly",
+        "{% if anomalous %}{{ ip }} carries {{ traffic_bps | mbps }} Mbps against a baseline of {{ baseline_bps | mbps }} Mbps (z-score {{ z_score | fixed }}){% if quarantine_wan %}; quarantined on {{ quarantine_wan }}{% endif %}{% else %}{{ ip }} is back to normal ({{ traffic_bps | mbps }} Mbps){% endif %}",
+    ),
+    (
+        "policy_change",
+        "{% if active %}Policy {{ policy }} took over from {{ previous }}{% else %}Policy {{ policy }} is shadowing {{ previous }}{% endif %} (requested by {{ requested_by }})",
+    ),
+    (
+        "overload",
+        "{% if sustained %}{{ nic }} has been above its estimate of {{ tcp_bandwidth_bps | mbps }} Mbps since {{ since | time }} ({{ traffic_bps | mbps }} Mbps now){% else %}{{ nic }} is back below its estimate of {{ tcp_bandwidth_bps | mbps }} Mbps{% endif %}",
+    ),
+    (
+        "switch_storm",
+        "{% if active %}Switch storm: {{ switches }} switches in the last {{ window_secs }} s{% else %}Switching has calmed down ({{ switches }} switches in the last {{ window_secs }} s){% endif %}",
+    ),
+    (
+        "traffic_summary",
+        "Traffic summary at {{ timestamp | time }}{% if jain_index %}, fairness {{ jain_index | fixed }}{% endif %}",
+    ),
+];
+
+/// The text report printed every cycle unless `[report] template` replaces it.
+const BUILTIN_REPORT_TEMPLATE: &str = r#"{% if site %}
+
+Site: {{ site }}
+{% endif %}
+
+NIC Configuration:
+  LAN: {{ lan }}
+{% for wan in wans %}
+  {{ wan.wan | upper }}: {{ wan.nic }} ({{ wan.wan }}) - {{ wan.clients }}
+  {%- if wan.client_cap is not none %}/{{ wan.client_cap }}{% endif %} clients
+  {%- if wan.probe and wan.probe.rtt_ms is not none %}, RTT {{ wan.probe.rtt_ms | fixed(1) }} ms ({{ (wan.probe.loss * 100) | fixed(0) }}% loss)
+  {%- elif wan.probe and wan.probe.loss >= 1 %}, probes failing{% endif %}
+  {%- if wan.probe and wan.probe.paused %}, probes paused{% endif %}
+  {%- if wan.probe and wan.probe.degraded %}, degraded: {{ wan.probe.degraded }}{% endif %}
+  {%- if wan.client_rtt_ms is not none %}, client RTT {{ wan.client_rtt_ms | fixed(1) }} ms (median){% endif %}
+  {%- if wan.public_ip %}, public IP {{ wan.public_ip }}{% endif %}
+  {%- if wan.data_cap %}, {{ (wan.data_cap.used_bytes / 1e9) | fixed(1) }} of {{ (wan.data_cap.cap_bytes / 1e9) | fixed(0) }} GB data cap used ({{ (wan.data_cap.remaining_bytes / 1e9) | fixed(1) }} GB left){% endif +%}
+{% endfor %}
+
+=== NIC Statistics ===
+
+{% for nic in nics %}
+Interface: {{ nic.nic }}
+  TCP Bandwidth (avg): {{ nic.tcp_bandwidth_bps | fixed }} bps ({{ nic.tcp_bandwidth_bps | mbps }} Mbps)
+  TX (total): {{ nic.tx_bps | fixed }} bps ({{ nic.tx_bps | mbps }} Mbps)
+  RX (total): {{ nic.rx_bps | fixed }} bps ({{ nic.rx_bps | mbps }} Mbps)
+  Total Traffic: {{ nic.total_bps | fixed }} bps ({{ nic.total_bps | mbps }} Mbps)
+{% if nic.queue %}
+  Queue: {{ nic.queue.backlog_packets }} packets / {{ nic.queue.backlog_bytes }} bytes backlog, {{ nic.queue.drops_per_sec | fixed(1) if nic.queue.drops_per_sec is not none else "-" }} drops/s{{ " (congested)" if nic.queue.congested else "" }}
+{% endif %}
+{% if nic.link_quality %}
+  Link Quality: {{ (nic.link_quality.loss * 100) | fixed(1) ~ "%" if nic.link_quality.loss is not none else "-" }} loss, {{ nic.link_quality.jitter_ms | fixed(1) ~ " ms" if nic.link_quality.jitter_ms is not none else "-" }} jitter
+{% endif %}
+  Top IPs by RX traffic:
+{% for top in top_ips if top.nic == nic.nic %}
+    {{ labels[top.ip] }} - {{ top.rx_bps | fixed }} bps ({{ top.rx_bps | mbps }} Mbps)
+    {%- if top.app_classes %} ({{ top.app_classes | join(", ") }}){% endif %}
+    {%- if top.experience %} [
+      {%- if top.experience.rtt_ms is not none %}RTT {{ top.experience.rtt_ms | fixed(1) }} ms{% endif %}
+      {%- if top.experience.rtt_ms is not none and top.experience.retransmit_ratio is not none %}, {% endif %}
+      {%- if top.experience.retransmit_ratio is not none %}{{ (top.experience.retransmit_ratio * 100) | fixed(1) }}% retransmits{% endif -%}
+    ]{% endif +%}
+{% endfor %}
+
+{% endfor %}
+{% if destination_usage %}
+=== Traffic by Destination Network ===
+{% for usage in destination_usage %}
+  {{ usage.wan }}: {{ "AS" ~ usage.asn if usage.asn is not none else "unknown AS" }}{{ " " ~ usage.as_org if usage.as_org is not none else "" }}{{ " (" ~ usage.country ~ ")" if usage.country is not none else "" }} - RX {{ usage.rx_bps | mbps }} Mbps, TX {{ usage.tx_bps | mbps }} Mbps
+{% endfor %}
+
+{% endif %}
+=== Switch Decisions ({{ policy }}) ===
+{% if mode == "metrics_degraded" %}
+  ⚠ Traffic data is stale; only urgent switches are made
+{% elif mode == "backend_degraded" %}
+  ⚠ Routing service unavailable; switching is suspended
+{% elif mode == "observe_only" %}
+  ⚠ Prometheus and the routing service unavailable; observing only
+{% endif %}
+{% if maintenance_window %}
+  ⚠ Maintenance window {{ maintenance_window }} open; switching is frozen
+{% endif %}
+{% if switch_circuit.state == "open" %}
+  ⚠ Switch API circuit open after {{ switch_circuit.consecutive_failures }} consecutive failures; switching resumes in {{ switch_circuit.remaining_secs }}s
+{% elif switch_circuit.state == "half_open" %}
+  ⚠ Switch API circuit half-open; trying one switch
+{% endif %}
+{% for decision in decisions %}
+{% if decision.outcome == "switched" %}
+  ✓ {{ labels[decision.ip] }} on {{ decision.nic }} → {{ decision.target_wan or "-" }}: {{ decision.reason }}
+{% elif decision.outcome == "failed" %}
+  ✗ {{ labels[decision.ip] }} on {{ decision.nic }} → {{ decision.target_wan or "-" }}: {{ decision.error }}
+{% elif decision.outcome == "held" %}
+  ⏭ Skipping {{ labels[decision.ip] }} - held for another {{ decision.remaining_secs }}s ({{ decision.hold_reason }})
+{% else %}
+  ⏭ Skipping {{ labels[decision.ip] }} on {{ decision.nic }} - {{ decision.reason }}
+{% endif %}
+{% endfor %}
+
+=== Load Balancing Fairness ===
+{% if fairness %}
+{% for wan, utilization in fairness.utilizations | items %}
+  {{ wan }} utilization: {{ (utilization * 100) | fixed(1) }}%
+{% endfor %}
+  Jain's index: {{ fairness.jain_index | fixed(3) }}
+{% if fairness.max_min_ratio is not none %}
+  Max/min utilization ratio: {{ fairness.max_min_ratio | fixed }}
+{% else %}
+  Max/min utilization ratio: n/a (idle WAN)
+{% endif %}
+{% else %}
+  (No WAN capacity estimates available)
+{% endif %}
+
+History of IPs switched:
+{% for switch in recent_switches %}
+{% if switch.hold %}
+  {{ labels[switch.ip] }} → {{ switch.target_wan }} - {{ switch.age_secs }}s ago ({{ switch.hold.reason }}, {{ switch.hold.remaining_secs }}s remaining)
+{% else %}
+  {{ labels[switch.ip] }} → {{ switch.target_wan }} - {{ switch.age_secs }}s ago
+{% endif %}
+{% else %}
+  (No recent switches in the last {{ history_window_secs }} seconds)
+{% endfor %}
+
+"#;
+
+/// Name under which the built-in template of a kind is kept next to a configured one.
+fn builtin(kind: &str) -> String {
+    format!("builtin/{}", kind)
+}
+
+/// Message text per event kind: the channel's own templates, then the shared ones, then
+/// the built-in English ones.
+pub struct Templates {
+    env: Environment<'static>,
+}
+
+impl Templates {
+    pub fn new(
+        shared: &HashMap<String, String>,
+        channel: &HashMap<String, String>,
+    ) -> Result<Self, ConfigError> {
+        let mut env = environment();
+        for (kind, source) in BUILTIN_TEMPLATES {
+            env.add_template_owned(builtin(kind), source)
+                .expect("built-in template");
+        }
+        for (kind, source) in shared.iter().chain(channel) {
+            let Some(&kind) = Event::KINDS.iter().find(|known| **known == kind.as_str()) else {
+                return Err(ConfigError::Invalid(format!(
+                    "Template for unknown event type {:?} (one of {})",
+                    kind,
+                    Event::KINDS.join(", ")
+                )));
+            };
+            env.add_template_owned(kind, source.clone()).map_err(|e| {
+                ConfigError::Invalid(format!("Invalid template for {} events: {}", kind, e))
+            })?;
+        }
+        Ok(Self { env })
+    }
+
+    /// The event through its configured template, or the built-in one when there is none
+    /// or it fails, e.g. on a misspelt field.
+    pub fn render(&self, event: &Event) -> String {
+        let kind = event.kind();
+        let fields = Value::from_serialize(event);
+        if let Ok(template) = self.env.get_template(kind) {
+            match template.render(&fields) {
+                Ok(message) => return message,
+                Err(e) => warn!(
+                    "Template for {} events failed, using the built-in one: {:#}",
+                    kind, e
+                ),
+            }
+        }
+        self.env
+            .get_template(&builtin(kind))
+            .and_then(|template| template.render(&fields))
+            .unwrap_or_default()
+    }
+}
+
+/// The text report of every cycle, from `[report] template` or the built-in one.
+pub struct ReportTemplate {
+    env: Environment<'static>,
+}
+
+impl ReportTemplate {
+    pub fn new(source: Option<&str>) -> Result<Self, ConfigError> {
+        let mut env = environment();
+        env.add_template_owned(builtin("report"), BUILTIN_REPORT_TEMPLATE)
+            .expect("built-in template");
+        if let Some(source) = source {
+            env.add_template_owned("report", source.to_string())
+                .map_err(|e| ConfigError::Invalid(format!("Invalid [report] template: {}", e)))?;
+        }
+        Ok(Self { env })
+    }
+
+    /// The report through the configured template, falling back on the built-in one. Next to
+    /// the report's own fields the template gets `labels`, the name shown for each client
+    /// IP.
+    pub fn render(&self, report: &CycleReport) -> String {
+        let fields = context! {
+            labels => report.labels(),
+            ..Value::from_serialize(report)
+        };
+        if let Ok(template) = self.env.get_template("report") {
+            match template.render(&fields) {
+                Ok(text) => return text,
+                Err(e) => warn!("Report template failed, using the built-in one: {:#}", e),
+            }
+        }
+        self.env
+            .get_template(&builtin("report"))
+            .and_then(|template| template.render(&fields))
+            .unwrap_or_default()
+    }
+}
+
+/// Template environment with the filters the messages use: `mbps` (bits per second as
+/// Mbps), `fixed(digits)` (two decimals unless given) and `time` (Unix seconds as local
+/// time). Missing fields fail the template; empty ones print as nothing.
+fn environment() -> Environment<'static> {
+    let mut env = Environment::new();
+    env.set_undefined_behavior(UndefinedBehavior::SemiStrict);
+    env.set_trim_blocks(true);
+    env.set_lstrip_blocks(true);
+    env.set_keep_trailing_newline(true);
+    env.set_formatter(|out, state, value| {
+        if value.is_none() {
+            return Ok(());
+        }
+        minijinja::escape_formatter(out, state, value)
+    });
+    env.add_filter("mbps", |value: Value| {
+        number(&value).map_or(value, |bps| {
+            Value::from(format!("{:.2}", bps / 1_000_000.0))
+        })
+    });
+    env.add_filter("fixed", |value: Value, digits: Option<usize>| {
+        number(&value).map_or(value, |number| {
+            Value::from(format!("{:.*}", digits.unwrap_or(2), number))
+        })
+    });
+    env.add_filter("time", |value: Value| {
+        number(&value).map_or(value, |secs| {
+            Value::from(
+                Local
+                    .timestamp_opt(secs as i64, 0)
+                    .single()
+                    .map(|time| time.to_rfc3339())
+                    .unwrap_or_default(),
+            )
+        })
+    });
+    env
+}
+
+/// Non-numbers go through the filters unchanged.
+fn number(value: &Value) -> Option<f64> {
+    f64::try_from(value.clone()).ok()
+}
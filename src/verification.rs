@@ -0,0 +1,100 @@
+use crate::clock::Clock;
+use crate::config::VerificationConfig;
+use crate::model::{ClientIp, WanId};
+use crate::routing::RoutingService;
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+/// A switch the routing service accepted, to be checked against its status.
+#[derive(Debug, Clone)]
+pub struct AcceptedSwitch {
+    pub ip: ClientIp,
+    pub target_wan: WanId,
+    /// Its record in the history database, if it was persisted.
+    pub history_id: Option<i64>,
+    /// Only some of the client's flows moved, which the status does not show.
+    pub flows_only: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verification {
+    /// The status maps the client to its target.
+    Verified,
+    /// Still mapped elsewhere after every re-issue.
+    NotInEffect,
+    /// The status could not be fetched, or cannot show the switch.
+    Unverified,
+}
+
+impl Verification {
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Verified => "verified",
+            Self::NotInEffect => "not_in_effect",
+            Self::Unverified => "unverified",
+        }
+    }
+}
+
+/// Re-reads the status after `delay_ms` and re-issues switches that are not in effect yet,
+/// up to `retries` times, before flagging them.
+pub async fn verify(
+    routing: &RoutingService,
+    config: &VerificationConfig,
+    clock: &dyn Clock,
+    pending: Vec<AcceptedSwitch>,
+) -> Vec<(AcceptedSwitch, Verification)> {
+    let (flow_switches, mut pending): (Vec<_>, Vec<_>) =
+        pending.into_iter().partition(|switch| switch.flows_only);
+    let mut results: Vec<_> = flow_switches
+        .into_iter()
+        .map(|switch| (switch, Verification::Unverified))
+        .collect();
+    let mut attempt = 0;
+    while !pending.is_empty() {
+        clock.sleep(Duration::from_millis(config.delay_ms)).await;
+        let status = match routing.status().await {
+            Ok(status) => status,
+            Err(e) => {
+                warn!("Failed to verify switches: {}", e);
+                results.extend(
+                    pending
+                        .into_iter()
+                        .map(|switch| (switch, Verification::Unverified)),
+                );
+                break;
+            }
+        };
+
+        let (verified, not_in_effect): (Vec<_>, Vec<_>) = pending
+            .into_iter()
+            .partition(|switch| status.mappings.get(&switch.ip) == Some(&switch.target_wan));
+        results.extend(
+            verified
+                .into_iter()
+                .map(|switch| (switch, Verification::Verified)),
+        );
+
+        if attempt >= config.retries {
+            for switch in not_in_effect {
+                error!(
+                    ip = %switch.ip,
+                    target_wan = %switch.target_wan,
+                    current_wan = status.mappings.get(&switch.ip).map_or("-", WanId::as_str),
+                    "Switch accepted but not in effect"
+                );
+                results.push((switch, Verification::NotInEffect));
+            }
+            break;
+        }
+        attempt += 1;
+        for switch in &not_in_effect {
+            info!(ip = %switch.ip, target_wan = %switch.target_wan, attempt, "Switch not in effect yet; re-issuing");
+            if let Err(e) = routing.switch(switch.ip, &switch.target_wan).await {
+                warn!(ip = %switch.ip, "Re-issued switch failed: {}", e);
+            }
+        }
+        pending = not_in_effect;
+    }
+    results
+}
@@ -0,0 +1,89 @@
+use crate::config::{WebhookConfig, WebhookFormat};
+use crate::events::Event;
+use crate::templates::Templates;
+use anyhow::{bail, Context, Result};
+use reqwest::Client;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::{error, warn};
+
+/// Delay before the first retry; doubled for every further attempt.
+const INITIAL_RETRY_DELAY: Duration = Duration::from_secs(1);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// POSTs each configured event type to a webhook URL, as JSON or as a message rendered
+/// through `templates`, retrying failed deliveries.
+pub async fn run(
+    config: WebhookConfig,
+    templates: Templates,
+    mut events: broadcast::Receiver<Arc<Event>>,
+) {
+    let client = Client::new();
+
+    loop {
+        let event = match events.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(skipped)) => {
+                warn!("Webhook {} dropped {} events", config.url, skipped);
+                continue;
+            }
+            Err(RecvError::Closed) => return,
+        };
+        if !config.events.iter().any(|kind| kind == event.kind()) {
+            continue;
+        }
+
+        let mut delay = INITIAL_RETRY_DELAY;
+        for attempt in 0..=config.max_retries {
+            match deliver(&client, &config, &templates, &event).await {
+                Ok(()) => break,
+                Err(e) if attempt < config.max_retries => {
+                    warn!(
+                        "Webhook {} failed (attempt {}), retrying in {:?}: {:#}",
+                        config.url,
+                        attempt + 1,
+                        delay,
+                        e
+                    );
+                    tokio::time::sleep(delay).await;
+                    delay *= 2;
+                }
+                Err(e) => error!(
+                    "Webhook {} gave up on {} event: {:#}",
+                    config.url,
+                    event.kind(),
+                    e
+                ),
+            }
+        }
+    }
+}
+
+async fn deliver(
+    client: &Client,
+    config: &WebhookConfig,
+    templates: &Templates,
+    event: &Event,
+) -> Result<()> {
+    let request = client.post(&config.url).timeout(REQUEST_TIMEOUT);
+    let mut request = match config.format {
+        WebhookFormat::Json => request.json(event),
+        WebhookFormat::Text => request.json(&serde_json::json!({
+            config.text_field.as_str(): templates.render(event),
+        })),
+    };
+    for (name, value) in &config.headers {
+        request = request.header(name, value);
+    }
+
+    let response = request
+        .send()
+        .await
+        .with_context(|| format!("Failed to reach webhook {}", config.url))?;
+    if !response.status().is_success() {
+        bail!("Webhook returned {}", response.status());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,63 @@
+use crate::event_stream::{Delivery, Subscription};
+use axum::extract::ws::{CloseFrame, Message, WebSocket};
+use std::collections::HashSet;
+use std::time::Duration;
+use tracing::debug;
+
+/// Client messages are only ever pings and closes; anything bigger ends the connection.
+pub const MAX_CLIENT_MESSAGE: usize = 64 * 1024;
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+/// Close status sent when the stream ends on the daemon's side (going away).
+const GOING_AWAY: u16 = 1001;
+
+/// Sends every event of the subscription as a JSON text message (the same JSON as the
+/// other sinks), limited to `kinds` when given, until either side closes.
+pub async fn stream_events(
+    mut socket: WebSocket,
+    mut subscription: Subscription,
+    kinds: Option<HashSet<String>>,
+) {
+    let mut keep_alive = tokio::time::interval(KEEP_ALIVE);
+    keep_alive.reset();
+    loop {
+        let message = tokio::select! {
+            delivery = subscription.next() => match delivery {
+                Some(Delivery::Event(event)) => {
+                    if kinds.as_ref().is_some_and(|kinds| !kinds.contains(event.kind())) {
+                        continue;
+                    }
+                    match serde_json::to_string(event.as_ref()) {
+                        Ok(json) => Message::Text(json),
+                        Err(_) => continue,
+                    }
+                }
+                Some(Delivery::Lagged(dropped)) => {
+                    Message::Text(format!("{{\"type\":\"lagged\",\"dropped\":{}}}", dropped))
+                }
+                None => {
+                    let _ = socket
+                        .send(Message::Close(Some(CloseFrame {
+                            code: GOING_AWAY,
+                            reason: "".into(),
+                        })))
+                        .await;
+                    break;
+                }
+            },
+            // Pings are answered and closes echoed by the socket itself
+            received = socket.recv() => match received {
+                Some(Ok(Message::Close(_))) | None => break,
+                Some(Ok(_)) => continue,
+                Some(Err(e)) => {
+                    debug!("WebSocket client went away: {}", e);
+                    break;
+                }
+            },
+            _ = keep_alive.tick() => Message::Ping(Vec::new()),
+        };
+        if socket.send(message).await.is_err() {
+            break;
+        }
+    }
+}
@@ -0,0 +1,33 @@
+mod common;
+
+use common::{Instance, MockBackends, Script};
+
+#[tokio::test]
+async fn quarantines_a_client_whose_traffic_jumps() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let instance =
+        Instance::start(&backends.config(
+            "[anomaly]\nwindow_secs = 60\nmin_baseline_secs = 10\nquarantine_wan = \"wan0\"",
+        ));
+
+    backends
+        .wait_for("15 cycles", |log| log.count("/status") >= 15)
+        .await;
+    backends.update(|script| {
+        script
+            .traffic_bps
+            .insert("192.168.1.12".to_string(), (30e6, 1e6));
+    });
+    let log = backends
+        .wait_for("the quarantine", |log| {
+            log.moves().contains(&("192.168.1.12", "wan0"))
+        })
+        .await;
+    assert!(instance.stop().await.success());
+    let quarantine = log
+        .switches
+        .iter()
+        .find(|switch| switch.ip == "192.168.1.12")
+        .unwrap();
+    assert!(quarantine.cycle >= 15);
+}
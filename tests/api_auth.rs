@@ -0,0 +1,102 @@
+mod common;
+
+use axum::response::Json;
+use axum::routing::post;
+use axum::Router;
+use common::{free_addr, Api, Instance, MockBackends, Script, SAMPLE_TIME};
+use serde_json::json;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+const ROLES: &str = "[[server.auth.api_keys]]\nname = \"grafana\"\nkey = \"viewer-key\"\nrole = \"viewer\"\n\n\
+                     [[server.auth.api_keys]]\nname = \"noc\"\nkey = \"operator-key\"\nrole = \"operator\"\n\n\
+                     [[server.auth.api_keys]]\nname = \"netadmin\"\nkey = \"admin-key\"\nrole = \"admin\"\n";
+
+#[tokio::test]
+async fn gates_every_endpoint_on_the_callers_role() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let addr = free_addr();
+    let config = format!("[server]\nlisten = \"{}\"\n\n{}", addr, ROLES);
+    let instance = Instance::start(&backends.config(&config));
+    let viewer = Api::connect(addr, "viewer-key").await;
+    let operator = Api::connect(addr, "operator-key").await;
+    let admin = Api::connect(addr, "admin-key").await;
+    let stranger = Api::connect(addr, "viewer-kez").await;
+
+    let pin = json!({"ip": "192.168.1.12", "wan": "wan0", "duration_secs": 60});
+    assert_eq!(stranger.get("/state").await.0, 401);
+    assert_eq!(viewer.get("/state").await.0, 200);
+    assert_eq!(viewer.post("/pause", json!({})).await.0, 403);
+    assert_eq!(operator.post("/pause", json!({})).await.0, 202);
+    assert_eq!(operator.post("/pins", pin.clone()).await.0, 403);
+    assert_eq!(admin.post("/pins", pin).await.0, 202);
+    assert!(instance.stop().await.success());
+}
+
+#[tokio::test]
+async fn is_read_only_without_credentials_configured() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let addr = free_addr();
+    let config = format!("[server]\nlisten = \"{}\"\n", addr);
+    let instance = Instance::start(&backends.config(&config));
+    let anonymous = Api::connect(addr, "").await;
+
+    assert_eq!(anonymous.get("/state").await.0, 200);
+    let (status, body) = anonymous.post("/pause", json!({})).await;
+    assert_eq!(status, 403, "{}", body);
+    assert_eq!(
+        anonymous
+            .post("/switch", json!({"ip": "192.168.1.10", "wan": "wan1"}))
+            .await
+            .0,
+        403
+    );
+    assert!(instance.stop().await.success());
+}
+
+/// A token introspection endpoint that finds every token active, for an operator, until
+/// `exp`. Returns its URL and the number of introspections it has answered.
+async fn introspection(exp: u64) -> (String, Arc<AtomicUsize>) {
+    let answered = Arc::new(AtomicUsize::new(0));
+    let counter = answered.clone();
+    let app = Router::new().route(
+        "/introspect",
+        post(move || {
+            counter.fetch_add(1, Ordering::Relaxed);
+            async move {
+                Json(json!({"active": true, "sub": "alice", "roles": ["operator"], "exp": exp}))
+            }
+        }),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/introspect", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.unwrap();
+    });
+    (url, answered)
+}
+
+#[tokio::test]
+async fn trusts_an_introspected_token_no_longer_than_it_lives() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let (url, introspections) = introspection(SAMPLE_TIME as u64 + 10).await;
+    let addr = free_addr();
+    let instance = Instance::start(&backends.config(&format!(
+        "[server]\nlisten = \"{}\"\n\n[server.auth.oidc]\nintrospection_url = \"{}\"\nclient_id = \"routingflow\"",
+        addr, url
+    )));
+    let api = Api::connect(addr, "opaque-token").await;
+
+    assert_eq!(api.get("/state").await.0, 200);
+    let cached = introspections.load(Ordering::Relaxed);
+    assert_eq!(api.get("/state").await.0, 200);
+    assert_eq!(introspections.load(Ordering::Relaxed), cached);
+
+    // Well within a minute of the first check, but past the token's expiry
+    backends
+        .wait_for("20 cycles", |log| log.count("/status") >= 20)
+        .await;
+    assert_eq!(api.get("/state").await.0, 200);
+    assert!(instance.stop().await.success());
+    assert_eq!(introspections.load(Ordering::Relaxed), cached + 1);
+}
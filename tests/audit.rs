@@ -0,0 +1,45 @@
+mod common;
+
+use common::{Instance, MockBackends, Script};
+use serde_json::Value;
+
+#[tokio::test]
+async fn writes_each_switch_with_what_it_was_decided_on() {
+    let mut script = Script::two_wans();
+    script
+        .traffic_bps
+        .insert("192.168.1.10".to_string(), (60e6, 5e6));
+    let backends = MockBackends::start(script).await;
+    let instance = Instance::start(&backends.config("[audit]\ndir = \"audit\""));
+
+    backends
+        .wait_for("a switch", |log| !log.switches.is_empty())
+        .await;
+    let audit = std::fs::read_to_string(instance.path("audit/decisions-20261014.jsonl")).unwrap();
+    assert!(instance.stop().await.success());
+    let records: Vec<Value> = audit
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+
+    let switch = records
+        .iter()
+        .find(|record| record["outcome"] == "switched")
+        .unwrap_or_else(|| panic!("no switch in {}", audit));
+    assert_eq!(switch["ip"], "192.168.1.10");
+    assert_eq!(switch["from_wan"], "wan0");
+    assert_eq!(switch["target_wan"], "wan1");
+    assert_eq!(switch["policy"], "top_rx");
+    assert_eq!(switch["rx_bps"], 60e6);
+    assert_eq!(switch["thresholds"]["cooldown_secs"], 30);
+    assert!(switch["cooldown"].is_null(), "{}", switch);
+    let candidates = switch["candidates"].as_array().unwrap();
+    assert_eq!(candidates.len(), 2);
+    assert_eq!(candidates[0]["wan"], "wan0");
+    assert_eq!(candidates[0]["tcp_bandwidth_bps"], 50e6);
+    assert!(
+        candidates[0]["headroom_bps"].as_f64().unwrap() < 0.0,
+        "{}",
+        switch
+    );
+}
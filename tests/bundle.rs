@@ -0,0 +1,103 @@
+mod common;
+
+use common::{unpack, Instance, MockBackends, Script};
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use std::process::Output;
+
+/// An empty directory to import into, named after `name`.
+fn scratch(name: &str) -> PathBuf {
+    let dir =
+        std::env::temp_dir().join(format!("routingflow-test-{}-{}", std::process::id(), name));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Runs the binary with `args` in `dir`.
+fn run(dir: &Path, args: &[&str]) -> Output {
+    std::process::Command::new(env!("CARGO_BIN_EXE_routingFlow"))
+        .args(args)
+        .current_dir(dir)
+        .env_remove("ROUTINGFLOW_CONFIG")
+        .output()
+        .unwrap()
+}
+
+/// Exports the bundle of an instance that made a switch into `dir`.
+async fn export(config: &str, dir: &Path, args: &[&str]) -> (String, Output) {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let config = backends.config(config);
+    let instance = Instance::start(&config);
+    // The switch is recorded once the service has answered
+    backends
+        .wait_for("the cycle after a switch", |log| {
+            log.switches
+                .first()
+                .is_some_and(|switch| log.count("/status") > switch.cycle)
+        })
+        .await;
+    let bundle = dir.join("bundle.tar.gz");
+    let mut command = vec!["export-bundle", bundle.to_str().unwrap()];
+    command.extend(args);
+    let output = instance.command(&command).await;
+    assert!(instance.stop().await.success());
+    (config, output)
+}
+
+#[tokio::test]
+async fn moves_the_config_and_history_to_another_machine() {
+    let dir = scratch("bundle-move");
+    let (config, output) = export("", &dir, &[]).await;
+    assert!(output.status.success(), "{:?}", output);
+
+    let target = dir.join("target");
+    std::fs::create_dir_all(&target).unwrap();
+    let imported = run(&target, &["import-bundle", "../bundle.tar.gz"]);
+    assert!(imported.status.success(), "{:?}", imported);
+    assert_eq!(
+        std::fs::read_to_string(target.join("routingflow.toml")).unwrap(),
+        config
+    );
+    let history = run(&target, &["history"]);
+    let history = String::from_utf8_lossy(&history.stdout);
+    assert!(
+        history.contains("192.168.1.10 wan0 → wan1 [success"),
+        "{}",
+        history
+    );
+
+    // Not over a config that is already there
+    let again = run(&target, &["import-bundle", "../bundle.tar.gz"]);
+    assert!(!again.status.success());
+    assert!(String::from_utf8_lossy(&again.stderr).contains("pass --force to overwrite it"));
+    let forced = run(&target, &["import-bundle", "../bundle.tar.gz", "--force"]);
+    assert!(forced.status.success(), "{:?}", forced);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn redacts_the_ips_for_a_bug_report() {
+    let dir = scratch("bundle-redact");
+    let (_, output) = export(
+        "[cooldown]\noverrides = [{ prefix = \"192.168.1.10\", secs = 600 }]",
+        &dir,
+        &["--redact-ips"],
+    )
+    .await;
+    assert!(output.status.success(), "{:?}", output);
+
+    let files = unpack(&dir.join("bundle.tar.gz"));
+    let _ = std::fs::remove_dir_all(&dir);
+    let manifest: Value = serde_json::from_str(&files["manifest.json"]).unwrap();
+    assert_eq!(manifest["redacted_ips"], true);
+    assert_eq!(manifest["history_records"], 1);
+    for name in ["routingflow.toml", "history.json"] {
+        assert!(!files[name].contains("192.168.1.10"), "{}", files[name]);
+    }
+    // The same pseudonym in both
+    let history: Value = serde_json::from_str(&files["history.json"]).unwrap();
+    let pseudonym = history[0]["ip"].as_str().unwrap();
+    assert!(pseudonym.starts_with("240."), "{}", pseudonym);
+    assert!(files["routingflow.toml"].contains(pseudonym));
+}
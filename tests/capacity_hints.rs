@@ -0,0 +1,64 @@
+mod common;
+
+use common::{api_config, free_addr, Api, Instance, MockBackends, Script};
+use serde_json::Value;
+use std::collections::HashMap;
+
+#[tokio::test]
+async fn writes_env_hints_for_dhcp_hooks() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let instance = Instance::start(
+        &backends.config("[capacity_hints]\npath = \"hints.env\"\nformat = \"env\""),
+    );
+    let log = backends
+        .wait_for("a switch", |log| !log.switches.is_empty())
+        .await;
+    let cycles = log.count("/status");
+    backends
+        .wait_for("2 more cycles", |log| log.count("/status") >= cycles + 2)
+        .await;
+
+    let hints = std::fs::read_to_string(instance.path("hints.env")).unwrap();
+    assert!(instance.stop().await.success());
+    let values: HashMap<&str, &str> = hints
+        .lines()
+        .map(|line| line.split_once('=').unwrap())
+        .collect();
+    // 177.45 Mbps free on wan1 with the busy client, 49.45 on wan0 without it
+    assert_eq!(values["ROUTINGFLOW_PREFERRED_WAN"], "wan1", "{}", hints);
+    assert_eq!(values["ROUTINGFLOW_PREFERRED_NIC"], "eth1");
+    // The names the per-WAN variables are prefixed with
+    assert_eq!(values["ROUTINGFLOW_WANS"], "WAN0,WAN1");
+    assert_eq!(values["ROUTINGFLOW_WAN0_NIC"], "eth0");
+    assert_eq!(values["ROUTINGFLOW_WAN0_HEALTH"], "ok");
+    assert_eq!(values["ROUTINGFLOW_WAN0_HEADROOM_BPS"], "49450000");
+    assert_eq!(values["ROUTINGFLOW_WAN0_CLIENTS"], "1");
+    assert_eq!(values["ROUTINGFLOW_WAN1_HEADROOM_BPS"], "177450000");
+    assert_eq!(values["ROUTINGFLOW_WAN1_CLIENTS"], "2");
+}
+
+#[tokio::test]
+async fn prefers_no_wan_at_its_client_cap() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let addr = free_addr();
+    let instance = Instance::start(&backends.config(&format!(
+        "{}\n[capacity_hints]\n\n[wan_client_caps]\nwan1 = 1",
+        api_config(addr)
+    )));
+    let api = Api::connect(addr, "admin-key").await;
+    backends
+        .wait_for("2 cycles", |log| log.count("/status") >= 2)
+        .await;
+
+    let (status, body) = api.get("/hints").await;
+    assert!(instance.stop().await.success());
+    assert_eq!(status, 200, "{}", body);
+    let hints: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(hints["preferred_wan"], "wan0");
+    assert_eq!(hints["preferred_nic"], "eth0");
+    let wan1 = &hints["wans"][1];
+    assert_eq!(wan1["wan"], "wan1");
+    assert_eq!(wan1["clients"], 1);
+    assert_eq!(wan1["client_cap"], 1);
+    assert_eq!(wan1["accepting"], false);
+}
@@ -0,0 +1,55 @@
+mod common;
+
+use common::{Instance, MockBackends, Script};
+use serde_json::Value;
+
+const BREAKER: &str = "[circuit_breaker]\nfailure_threshold = 2\nopen_secs = 10";
+
+#[tokio::test]
+async fn pauses_switching_after_consecutive_failures() {
+    let mut script = Script::two_wans();
+    script.fail_switch = true;
+    let backends = MockBackends::start(script).await;
+    let instance = Instance::start_with(&backends.config(BREAKER), &["--output", "json"]);
+
+    let log = backends
+        .wait_for("a trial switch", |log| log.switches.len() >= 3)
+        .await;
+    let (status, output) = instance.stop_with_report().await;
+    assert!(status.success());
+    let cycles: Vec<usize> = log.switches.iter().map(|switch| switch.cycle).collect();
+    assert!(cycles[2] >= cycles[1] + 10, "{:?}", cycles);
+    let circuit = output
+        .lines()
+        .map(|line| serde_json::from_str::<Value>(line).unwrap())
+        .map(|report| report["switch_circuit"].clone())
+        .find(|circuit| circuit["state"] == "open")
+        .unwrap();
+    assert_eq!(circuit["consecutive_failures"], 2);
+    assert_eq!(circuit["remaining_secs"], 10);
+}
+
+#[tokio::test]
+async fn closes_again_after_a_successful_trial() {
+    let mut script = Script::two_wans();
+    script.fail_switch = true;
+    let backends = MockBackends::start(script).await;
+    let instance = Instance::start_with(&backends.config(BREAKER), &["--output", "json"]);
+
+    backends
+        .wait_for("two failed switches", |log| log.switches.len() >= 2)
+        .await;
+    backends.update(|script| script.fail_switch = false);
+    let log = backends
+        .wait_for("a trial switch", |log| log.switches.len() >= 3)
+        .await;
+    let cycles = log.count("/status");
+    backends
+        .wait_for("2 more cycles", |log| log.count("/status") >= cycles + 2)
+        .await;
+    let (status, output) = instance.stop_with_report().await;
+    assert!(status.success());
+    let last: Value = serde_json::from_str(output.lines().last().unwrap()).unwrap();
+    assert_eq!(last["switch_circuit"]["state"], "closed");
+    assert_eq!(last["switch_circuit"]["consecutive_failures"], 0);
+}
@@ -0,0 +1,37 @@
+mod common;
+
+use common::{Instance, MockBackends, Script};
+
+/// `wan1` has the most headroom but already has its one client, so the busy client goes to
+/// the next best WAN.
+#[tokio::test]
+async fn skips_a_wan_at_its_client_cap() {
+    let mut script = Script::two_wans();
+    script.wans.insert("wan2".to_string(), "eth3".to_string());
+    script.bandwidth_bps.insert("eth3".to_string(), 100e6);
+    let backends = MockBackends::start(script).await;
+    let instance = Instance::start(&backends.config("[wan_client_caps]\nwan1 = 1"));
+
+    let log = backends
+        .wait_for("a switch", |log| !log.switches.is_empty())
+        .await;
+    assert!(instance.stop().await.success());
+    assert_eq!(log.moves()[0], ("192.168.1.10", "wan2"));
+}
+
+#[tokio::test]
+async fn never_fills_a_wan_past_its_cap() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let instance = Instance::start(&backends.config("[wan_client_caps]\nwan1 = 1"));
+
+    let log = backends
+        .wait_for("30 cycles", |log| log.count("/status") >= 30)
+        .await;
+    assert!(instance.stop().await.success());
+    let mut mappings = Script::two_wans().mappings;
+    for (ip, wan) in log.moves() {
+        mappings.insert(ip.to_string(), wan.to_string());
+        let on_wan1 = mappings.values().filter(|wan| *wan == "wan1").count();
+        assert!(on_wan1 <= 1, "{:?}", log.moves());
+    }
+}
@@ -0,0 +1,139 @@
+mod common;
+
+use common::{Instance, MockBackends, Script};
+use serde_json::Value;
+
+/// Reasons of the skipped decisions about `ip` in any report.
+fn skipped(output: &str, ip: &str) -> Vec<String> {
+    output
+        .lines()
+        .map(|line| serde_json::from_str::<Value>(line).unwrap())
+        .flat_map(|report| report["decisions"].as_array().unwrap().clone())
+        .filter(|decision| decision["ip"] == ip && decision["outcome"] == "skipped")
+        .map(|decision| decision["reason"].as_str().unwrap().to_string())
+        .collect()
+}
+
+#[tokio::test]
+async fn follows_the_longest_matching_prefix() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let instance = Instance::start_with(
+        &backends.config(
+            "[[client_rules]]\nprefix = \"192.168.1.0/24\"\naction = \"exclude\"\n\n\
+             [[client_rules]]\nprefix = \"192.168.1.11/32\"\naction = \"pin\"\nwan = \"wan1\"",
+        ),
+        &["--output", "json"],
+    );
+
+    let log = backends
+        .wait_for("10 cycles", |log| log.count("/status") >= 10)
+        .await;
+    let (status, output) = instance.stop_with_report().await;
+    assert!(status.success());
+    // The host rule wins over its subnet's; the rest of the subnet stays put
+    assert_eq!(log.moves(), [("192.168.1.11", "wan1")]);
+    assert!(skipped(&output, "192.168.1.10").contains(&"excluded from switching".to_string()));
+}
+
+#[tokio::test]
+async fn leaves_a_client_on_its_preferred_wan() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let instance = Instance::start_with(
+        &backends.config(
+            "[[client_rules]]\nprefix = \"192.168.1.8/29\"\naction = \"prefer_wan\"\nwan = \"wan0\"",
+        ),
+        &["--output", "json"],
+    );
+
+    let log = backends
+        .wait_for("10 cycles", |log| log.count("/status") >= 10)
+        .await;
+    let (status, output) = instance.stop_with_report().await;
+    assert!(status.success());
+    assert!(log.switches.is_empty(), "{:?}", log.moves());
+    assert!(skipped(&output, "192.168.1.10").contains(&"on its preferred wan0".to_string()));
+}
+
+#[tokio::test]
+async fn weighs_clients_in_the_weighted_shares() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    // Without the rule, wan0 has 67% of the clients against a target of 50%
+    let instance = Instance::start(&backends.config(
+        "policy = \"weighted\"\n\n[weighted]\nweights = { wan0 = 1, wan1 = 1 }\n\n\
+         [[client_rules]]\nprefix = \"192.168.1.10/32\"\naction = \"weight\"\nweight = 0",
+    ));
+
+    let log = backends
+        .wait_for("10 cycles", |log| log.count("/status") >= 10)
+        .await;
+    assert!(instance.stop().await.success());
+    assert!(log.switches.is_empty(), "{:?}", log.moves());
+}
+
+#[tokio::test]
+async fn refuses_two_unscheduled_rules_for_one_prefix() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let instance = Instance::start(&backends.config(
+        "[[client_rules]]\nprefix = \"192.168.1.0/24\"\naction = \"exclude\"\n\n\
+         [[client_rules]]\nprefix = \"192.168.1.0/24\"\naction = \"pin\"\nwan = \"wan1\"",
+    ));
+
+    assert!(!instance.wait().await.success());
+    assert_eq!(backends.log().count("/status"), 0);
+}
+
+#[tokio::test]
+async fn applies_a_scheduled_rule_while_its_window_is_open() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    // The simulated clock starts at 10:00
+    let instance = Instance::start(&backends.config(
+        "[[client_rules]]\nprefix = \"192.168.1.11/32\"\naction = \"exclude\"\n\n\
+         [[client_rules]]\nprefix = \"192.168.1.11/32\"\naction = \"pin\"\nwan = \"wan1\"\n\
+         schedule = { start = \"10:01\", end = \"10:02\" }\n\n\
+         [logging]\nformat = \"json\"",
+    ));
+
+    let log = backends
+        .wait_for("a switch of .11", |log| {
+            log.switches
+                .iter()
+                .any(|switch| switch.ip == "192.168.1.11")
+        })
+        .await;
+    let (status, output) = instance.stop_with_log().await;
+    assert!(status.success());
+    // The scheduled rule goes before the unscheduled one of the same prefix
+    let switch = log
+        .switches
+        .iter()
+        .find(|switch| switch.ip == "192.168.1.11")
+        .unwrap();
+    assert_eq!(switch.wan, "wan1");
+    let cycle = switch.cycle;
+    assert!((60..=62).contains(&cycle), "switched after {}s", cycle);
+    let opened = output
+        .lines()
+        .map(|line| serde_json::from_str::<Value>(line).unwrap())
+        .find(|line| line["message"] == "Scheduled client rule in force")
+        .unwrap();
+    assert_eq!(
+        opened["rule"],
+        "192.168.1.11/32 pin to wan1 during 10:01-10:02"
+    );
+}
+
+#[tokio::test]
+async fn ignores_a_scheduled_rule_on_other_days() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    // The simulated clock starts on a Wednesday
+    let instance = Instance::start(&backends.config(
+        "[[client_rules]]\nprefix = \"192.168.1.10/32\"\naction = \"exclude\"\n\
+         schedule = { days = [\"thu\"], start = \"00:00\", end = \"00:00\" }",
+    ));
+
+    let log = backends
+        .wait_for("a switch", |log| !log.switches.is_empty())
+        .await;
+    assert!(instance.stop().await.success());
+    assert_eq!(log.moves()[0], ("192.168.1.10", "wan1"));
+}
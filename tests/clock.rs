@@ -0,0 +1,50 @@
+mod common;
+
+use common::{Instance, MockBackends, Script, SAMPLE_TIME};
+use serde_json::Value;
+
+#[tokio::test]
+async fn advances_a_second_per_cycle_from_the_simulated_start() {
+    let mut script = Script::two_wans();
+    script
+        .traffic_bps
+        .insert("192.168.1.10".to_string(), (5e5, 5e4));
+    let backends = MockBackends::start(script).await;
+    let instance = Instance::start_with(&backends.config(""), &["--output", "json"]);
+
+    backends
+        .wait_for("5 cycles", |log| log.count("/status") >= 5)
+        .await;
+    let (status, output) = instance.stop_with_report().await;
+    assert!(status.success());
+    let timestamps: Vec<u64> = output
+        .lines()
+        .map(|line| serde_json::from_str::<Value>(line).unwrap())
+        .map(|report| report["timestamp"].as_u64().unwrap())
+        .collect();
+    assert_eq!(timestamps[0], SAMPLE_TIME as u64);
+    for pair in timestamps.windows(2) {
+        assert_eq!(pair[1], pair[0] + 1, "{:?}", timestamps);
+    }
+}
+
+/// Two minutes of the simulated clock pass in a fraction of a real second.
+#[tokio::test]
+async fn closes_a_schedule_window_on_simulated_time() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    // wan1 is all but reserved for the quiet client until 10:02
+    let instance = Instance::start(&backends.config(
+        "[[reservations]]\nname = \"office\"\nwan = \"wan1\"\nmbps = 195\nprefixes = [\"192.168.1.12/32\"]\nschedule = { start = \"10:00\", end = \"10:02\" }",
+    ));
+
+    let log = backends
+        .wait_for("a switch", |log| !log.switches.is_empty())
+        .await;
+    assert!(instance.stop().await.success());
+    assert_eq!(log.moves()[0], ("192.168.1.10", "wan1"));
+    assert!(
+        (120..=125).contains(&log.switches[0].cycle),
+        "{:?}",
+        log.switches
+    );
+}
@@ -0,0 +1,892 @@
+//! Fake Prometheus and routing-service backends with scripted answers, and a way to run the
+//! binary against them on a simulated clock.
+#![allow(dead_code)]
+
+use axum::extract::{Query, Request, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Json, Response};
+use axum::routing::{get, post};
+use axum::Router;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::process::{ExitStatus, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::process::{Child, Command};
+
+/// Start of the simulated clock the binary runs on.
+pub const SIMULATED_START: &str = "2026-10-14T10:00:00+00:00";
+/// [`SIMULATED_START`] as the Unix time of the scripted samples.
+pub const SAMPLE_TIME: f64 = 1_791_972_000.0;
+
+/// What the binary running `run` writes to stderr, in its directory.
+const LOG_FILE: &str = "stderr.log";
+
+/// What it writes to stdout, the per-cycle reports unless it runs with `--quiet`.
+const REPORT_FILE: &str = "stdout.log";
+
+/// How long a test waits for the binary to do what it expects.
+const WAIT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// What the fake backends answer with.
+#[derive(Debug, Clone)]
+pub struct Script {
+    pub lan: String,
+    /// WAN → interface, as the routing service reports it.
+    pub wans: BTreeMap<String, String>,
+    /// Interface → TCP bandwidth estimate.
+    pub bandwidth_bps: BTreeMap<String, f64>,
+    /// Client → `(rx, tx)`.
+    pub traffic_bps: BTreeMap<String, (f64, f64)>,
+    /// Client → RX added to its traffic at every `/status` request, so once per cycle.
+    pub ramp_bps: BTreeMap<String, f64>,
+    /// `(client, port, rx)` of the clients' TCP flows.
+    pub flows: Vec<(String, u16, f64)>,
+    /// `(client, remote address, rx)` of traffic series split by `dst_address`, which add
+    /// to the clients' traffic.
+    pub destinations: Vec<(String, String, f64)>,
+    /// Client → (RTT in ms, retransmit ratio) of its flows, as passively measured.
+    pub rtt: BTreeMap<String, (f64, f64)>,
+    /// Interface → packet loss (0–1), as ping_exporter's `ping_loss_ratio`.
+    pub loss: BTreeMap<String, f64>,
+    /// Interface → bytes moved, the answer to any `increase` of node_exporter's counters.
+    pub used_bytes: BTreeMap<String, f64>,
+    /// Client → WAN; switches the fake accepts are applied to it.
+    pub mappings: BTreeMap<String, String>,
+    /// `/status` answers 500.
+    pub fail_status: bool,
+    /// `/switch` answers with `switch_error`.
+    pub fail_switch: bool,
+    /// Status of a failing `/switch` and, for a 429, its `Retry-After` in seconds; 500 by
+    /// default.
+    pub switch_error: (u16, Option<u64>),
+    /// `/switch` answers 200 but leaves the mapping as it was.
+    pub ignore_switches: bool,
+    /// Client → why the dry run at `/switch/validate` refuses to move it, answered with a
+    /// 409; other moves it accepts.
+    pub rejections: BTreeMap<String, String>,
+    /// Range-query answers climb linearly from 0 at the range's start to the instant value
+    /// at its end, instead of holding the instant value.
+    pub range_ramps: bool,
+    /// The next this many notifications answer 500 and are not logged.
+    pub fail_notifications: usize,
+    /// How long every answer takes.
+    pub latency: Duration,
+    /// WAN → public address, as the routing service's `/public_ip` reports it.
+    pub public_ips: BTreeMap<String, String>,
+    /// NIC → (queued packets, packets dropped between two readings) the QoS endpoint
+    /// reports; NICs without an entry are not listed.
+    pub queues: BTreeMap<String, (u64, u64)>,
+    /// Fields `/status` reports in its config besides the LAN and the WANs.
+    pub extra_config: BTreeMap<String, Value>,
+}
+
+impl Script {
+    /// `wan0` on a 50 Mbps `eth0` carrying one busy client, `wan1` on a 200 Mbps `eth1`
+    /// with a quiet one, and a quiet client on `wan0` too.
+    pub fn two_wans() -> Self {
+        Self {
+            lan: "eth2".to_string(),
+            wans: [("wan0", "eth0"), ("wan1", "eth1")]
+                .into_iter()
+                .map(|(wan, nic)| (wan.to_string(), nic.to_string()))
+                .collect(),
+            bandwidth_bps: [("eth0", 50e6), ("eth1", 200e6)]
+                .into_iter()
+                .map(|(nic, bps)| (nic.to_string(), bps))
+                .collect(),
+            traffic_bps: [
+                ("192.168.1.10", (20e6, 2e6)),
+                ("192.168.1.11", (5e5, 5e4)),
+                ("192.168.1.12", (5e5, 5e4)),
+            ]
+            .into_iter()
+            .map(|(ip, traffic)| (ip.to_string(), traffic))
+            .collect(),
+            mappings: [
+                ("192.168.1.10", "wan0"),
+                ("192.168.1.11", "wan0"),
+                ("192.168.1.12", "wan1"),
+            ]
+            .into_iter()
+            .map(|(ip, wan)| (ip.to_string(), wan.to_string()))
+            .collect(),
+            ramp_bps: BTreeMap::new(),
+            flows: Vec::new(),
+            destinations: Vec::new(),
+            rtt: BTreeMap::new(),
+            loss: BTreeMap::new(),
+            used_bytes: BTreeMap::new(),
+            fail_status: false,
+            fail_switch: false,
+            switch_error: (500, None),
+            ignore_switches: false,
+            rejections: BTreeMap::new(),
+            range_ramps: false,
+            fail_notifications: 0,
+            latency: Duration::ZERO,
+            public_ips: BTreeMap::new(),
+            queues: BTreeMap::new(),
+            extra_config: BTreeMap::new(),
+        }
+    }
+
+    /// The instant-query answer to `query`: the bandwidth, client traffic, RTT, loss and
+    /// byte counter series, and the sample time of the bandwidth series. Anything else has
+    /// no series.
+    fn series(&self, query: &str) -> Vec<(HashMap<String, String>, f64)> {
+        let labels = |pairs: &[(&str, &str)]| -> HashMap<String, String> {
+            pairs
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect()
+        };
+        if query.contains("tcp_traffic_scan_tcp_bandwidth_avg_bps") {
+            let timestamp = query.starts_with("timestamp(");
+            // A query for one interface only has that interface's series
+            self.bandwidth_bps
+                .iter()
+                .filter(|(nic, _)| {
+                    !query.contains("interface=\"")
+                        || query.contains(&format!("interface=\"{}\"", nic))
+                })
+                .map(|(nic, bps)| {
+                    let value = if timestamp { SAMPLE_TIME } else { *bps };
+                    (labels(&[("interface", nic)]), value)
+                })
+                .collect()
+        } else if query.contains("tcp_traffic_scan_flow_rx_bps") {
+            self.flows
+                .iter()
+                .map(|(ip, port, rx)| {
+                    let port = port.to_string();
+                    (
+                        labels(&[("ip_address", ip), ("port", &port), ("proto", "tcp")]),
+                        *rx,
+                    )
+                })
+                .collect()
+        } else if query.contains("increase(") {
+            self.used_bytes
+                .iter()
+                .map(|(nic, bytes)| (labels(&[("device", nic)]), *bytes))
+                .collect()
+        } else if query.contains("tcp_traffic_scan_ip_rtt_ms") {
+            self.rtt
+                .iter()
+                .flat_map(|(ip, (rtt_ms, retransmits))| {
+                    [
+                        (
+                            labels(&[
+                                ("__name__", "tcp_traffic_scan_ip_rtt_ms"),
+                                ("ip_address", ip),
+                            ]),
+                            *rtt_ms,
+                        ),
+                        (
+                            labels(&[
+                                ("__name__", "tcp_traffic_scan_ip_retransmit_ratio"),
+                                ("ip_address", ip),
+                            ]),
+                            *retransmits,
+                        ),
+                    ]
+                })
+                .collect()
+        } else if query.contains("ping_loss_ratio") {
+            self.loss
+                .iter()
+                .map(|(nic, loss)| (labels(&[("interface", nic)]), *loss))
+                .collect()
+        } else if query.contains("network_ip_rx_bps") {
+            self.traffic_bps
+                .iter()
+                .flat_map(|(ip, (rx, tx))| {
+                    [
+                        (
+                            labels(&[("__name__", "network_ip_rx_bps"), ("ip_address", ip)]),
+                            *rx,
+                        ),
+                        (
+                            labels(&[("__name__", "network_ip_tx_bps"), ("ip_address", ip)]),
+                            *tx,
+                        ),
+                    ]
+                })
+                .chain(self.destinations.iter().map(|(ip, destination, rx)| {
+                    (
+                        labels(&[
+                            ("__name__", "network_ip_rx_bps"),
+                            ("ip_address", ip),
+                            ("dst_address", destination),
+                        ]),
+                        *rx,
+                    )
+                }))
+                .collect()
+        } else {
+            Vec::new()
+        }
+    }
+}
+
+/// A request one of the fakes received.
+#[derive(Debug, Clone)]
+pub struct Received {
+    pub path: String,
+    pub query: HashMap<String, String>,
+    pub authorization: Option<String>,
+    /// `X-Scope-OrgID`, the tenant of multi-tenant stores.
+    pub tenant: Option<String>,
+    /// Every header, by lowercase name.
+    pub headers: HashMap<String, String>,
+}
+
+/// A switch request, accepted or not.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Switch {
+    pub ip: String,
+    pub wan: String,
+    /// Of a single flow's move.
+    pub port: Option<u16>,
+    /// `/status` requests before it: the cycle it was made in, and so the seconds of
+    /// simulated time since the start.
+    pub cycle: usize,
+}
+
+/// A message posted to a webhook, the fake Slack or Telegram, or a Kafka topic.
+#[derive(Debug, Clone)]
+pub struct Notification {
+    pub path: String,
+    pub body: Value,
+    /// `/status` requests before it.
+    pub cycle: usize,
+}
+
+/// What the fakes have seen so far.
+#[derive(Debug, Default, Clone)]
+pub struct Log {
+    pub requests: Vec<Received>,
+    pub switches: Vec<Switch>,
+    pub notifications: Vec<Notification>,
+    /// The most requests the fakes were answering at once.
+    pub max_in_flight: usize,
+    in_flight: usize,
+}
+
+impl Log {
+    /// `(ip, wan)` of every switch request.
+    pub fn moves(&self) -> Vec<(&str, &str)> {
+        self.switches
+            .iter()
+            .map(|switch| (switch.ip.as_str(), switch.wan.as_str()))
+            .collect()
+    }
+
+    pub fn count(&self, path: &str) -> usize {
+        self.requests
+            .iter()
+            .filter(|request| request.path == path)
+            .count()
+    }
+}
+
+struct Backend {
+    script: Script,
+    log: Log,
+    /// Drop counters of the QoS endpoint per NIC.
+    drops: HashMap<String, u64>,
+}
+
+type Shared = Arc<Mutex<Backend>>;
+
+/// Prometheus (`/api/v1/...`, or vmselect-style under `/select/<tenant>/prometheus`), the
+/// routing service (`/status`, `/switch`, `/remove`, `/qos`), and Slack (`/slack`), Telegram
+/// (`/bot<token>/sendMessage`) and a Kafka REST proxy (`/topics/<topic>`) to notify on one
+/// port.
+pub struct MockBackends {
+    pub url: String,
+    backend: Shared,
+    server: tokio::task::JoinHandle<()>,
+}
+
+impl MockBackends {
+    pub async fn start(script: Script) -> Self {
+        let backend = Arc::new(Mutex::new(Backend {
+            script,
+            log: Log::default(),
+            drops: HashMap::new(),
+        }));
+        let app = Router::new()
+            .route("/api/v1/query", get(instant_query))
+            .route("/api/v1/query_range", get(range_query))
+            .route(
+                "/select/:tenant/prometheus/api/v1/query",
+                get(instant_query),
+            )
+            .route(
+                "/select/:tenant/prometheus/api/v1/query_range",
+                get(range_query),
+            )
+            .route("/status", get(status))
+            .route("/switch", get(switch))
+            .route("/switch/validate", get(validate))
+            .route("/remove", get(remove))
+            .route("/qos", get(qos))
+            .route("/public_ip", get(public_ip))
+            .route("/hook", post(notify))
+            .route("/slack", post(notify))
+            .route("/topics/:topic", post(notify))
+            .route("/:bot/sendMessage", post(notify))
+            .layer(axum::middleware::from_fn_with_state(backend.clone(), log))
+            .with_state(backend.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            axum::serve(listener, app).await.unwrap();
+        });
+        Self {
+            url,
+            backend,
+            server,
+        }
+    }
+
+    /// A config pointing at the fakes, with `extra` (top-level keys and further tables) in
+    /// front; failed calls are not retried so failures show up at once.
+    pub fn config(&self, extra: &str) -> String {
+        format!(
+            "{}\n\n[prometheus]\nurl = \"{}\"\n\n[routing_service]\nurl = \"{}\"\n\n[retry]\nmax_attempts = 1\n",
+            extra, self.url, self.url
+        )
+    }
+
+    pub fn log(&self) -> Log {
+        self.backend.lock().unwrap().log.clone()
+    }
+
+    /// Counts the cycles so far, one `/status` request each, from another task: the
+    /// simulated seconds since the instance started.
+    pub fn cycles(&self) -> impl Fn() -> usize + Send + 'static {
+        let backend = self.backend.clone();
+        move || backend.lock().unwrap().log.count("/status")
+    }
+
+    /// Changes the answers from now on.
+    pub fn update(&self, change: impl FnOnce(&mut Script)) {
+        change(&mut self.backend.lock().unwrap().script);
+    }
+
+    /// Waits until `done` holds for what the fakes have seen, failing the test after
+    /// [`WAIT_TIMEOUT`].
+    pub async fn wait_for(&self, what: &str, done: impl Fn(&Log) -> bool) -> Log {
+        let deadline = tokio::time::Instant::now() + WAIT_TIMEOUT;
+        loop {
+            let log = self.log();
+            if done(&log) {
+                return log;
+            }
+            if tokio::time::Instant::now() > deadline {
+                panic!("Timed out waiting for {}; saw {:?}", what, log.moves());
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    }
+}
+
+impl Drop for MockBackends {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
+
+async fn log(
+    State(backend): State<Shared>,
+    request: Request,
+    next: axum::middleware::Next,
+) -> Response {
+    let received = Received {
+        path: request.uri().path().to_string(),
+        query: Query::<HashMap<String, String>>::try_from_uri(request.uri())
+            .map(|Query(query)| query)
+            .unwrap_or_default(),
+        authorization: request
+            .headers()
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string),
+        tenant: request
+            .headers()
+            .get("x-scope-orgid")
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string),
+        headers: request
+            .headers()
+            .iter()
+            .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+            .collect(),
+    };
+    let latency = {
+        let mut backend = backend.lock().unwrap();
+        let log = &mut backend.log;
+        log.requests.push(received);
+        log.in_flight += 1;
+        log.max_in_flight = log.max_in_flight.max(log.in_flight);
+        backend.script.latency
+    };
+    tokio::time::sleep(latency).await;
+    let response = next.run(request).await;
+    backend.lock().unwrap().log.in_flight -= 1;
+    response
+}
+
+async fn instant_query(
+    State(backend): State<Shared>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Json<Value> {
+    let query = params.get("query").map_or("", String::as_str);
+    let result: Vec<Value> = backend
+        .lock()
+        .unwrap()
+        .script
+        .series(query)
+        .into_iter()
+        .map(|(metric, value)| json!({ "metric": metric, "value": [SAMPLE_TIME, value.to_string()] }))
+        .collect();
+    Json(json!({
+        "status": "success",
+        "data": { "resultType": "vector", "result": result },
+    }))
+}
+
+/// The instant answer held constant over the requested range, or climbing towards it with
+/// [`Script::range_ramps`].
+async fn range_query(
+    State(backend): State<Shared>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Json<Value> {
+    let query = params.get("query").map_or("", String::as_str);
+    let number = |name: &str| params.get(name).and_then(|value| value.parse::<f64>().ok());
+    let (Some(start), Some(end), Some(step)) = (number("start"), number("end"), number("step"))
+    else {
+        return Json(json!({ "status": "error", "error": "start, end and step are required" }));
+    };
+    let mut timestamps = Vec::new();
+    let mut timestamp = start;
+    while timestamp <= end {
+        timestamps.push(timestamp);
+        timestamp += step.max(1.0);
+    }
+    let script = &backend.lock().unwrap().script;
+    let result: Vec<Value> = script
+        .series(query)
+        .into_iter()
+        .map(|(metric, value)| {
+            let values: Vec<Value> = timestamps
+                .iter()
+                .map(|timestamp| {
+                    let value = if script.range_ramps && end > start {
+                        value * (timestamp - start) / (end - start)
+                    } else {
+                        value
+                    };
+                    json!([timestamp, value.to_string()])
+                })
+                .collect();
+            json!({ "metric": metric, "values": values })
+        })
+        .collect();
+    Json(json!({
+        "status": "success",
+        "data": { "resultType": "matrix", "result": result },
+    }))
+}
+
+async fn status(State(backend): State<Shared>) -> Response {
+    let mut backend = backend.lock().unwrap();
+    let script = &mut backend.script;
+    for (ip, step) in &script.ramp_bps {
+        if let Some((rx, _)) = script.traffic_bps.get_mut(ip) {
+            *rx += step;
+        }
+    }
+    if script.fail_status {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+    let mut config = json!({ "lan": script.lan });
+    for (wan, nic) in &script.wans {
+        config[wan] = json!(nic);
+    }
+    for (field, value) in &script.extra_config {
+        config[field] = value.clone();
+    }
+    Json(json!({ "config": config, "mappings": script.mappings })).into_response()
+}
+
+async fn switch(
+    State(backend): State<Shared>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Response {
+    let (Some(ip), Some(wan)) = (params.get("ip"), params.get("nic")) else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+    let mut backend = backend.lock().unwrap();
+    let cycle = backend.log.count("/status");
+    let port = params.get("port").and_then(|port| port.parse().ok());
+    backend.log.switches.push(Switch {
+        ip: ip.clone(),
+        wan: wan.clone(),
+        port,
+        cycle,
+    });
+    if backend.script.fail_switch {
+        let (status, retry_after) = backend.script.switch_error;
+        let status = StatusCode::from_u16(status).unwrap();
+        return match retry_after {
+            Some(secs) => (status, [("retry-after", secs.to_string())]).into_response(),
+            None => status.into_response(),
+        };
+    }
+    // A flow moves without the client's mapping
+    if port.is_some() || backend.script.ignore_switches {
+        return StatusCode::OK.into_response();
+    }
+    backend.script.mappings.insert(ip.clone(), wan.clone());
+    StatusCode::OK.into_response()
+}
+
+/// Dry run of a switch: rejects the moves of the clients in `Script::rejections`.
+async fn validate(
+    State(backend): State<Shared>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Response {
+    let Some(ip) = params.get("ip") else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+    match backend.lock().unwrap().script.rejections.get(ip) {
+        Some(reason) => (StatusCode::CONFLICT, Json(json!({ "error": reason }))).into_response(),
+        None => StatusCode::OK.into_response(),
+    }
+}
+
+/// Drops the mapping of `ip`, as the mapping collector's `remove_path`.
+async fn remove(
+    State(backend): State<Shared>,
+    Query(params): Query<HashMap<String, String>>,
+) -> StatusCode {
+    let Some(ip) = params.get("ip") else {
+        return StatusCode::BAD_REQUEST;
+    };
+    backend.lock().unwrap().script.mappings.remove(ip);
+    StatusCode::OK
+}
+
+async fn public_ip(State(backend): State<Shared>) -> Json<Value> {
+    Json(json!(backend.lock().unwrap().script.public_ips))
+}
+
+async fn qos(State(backend): State<Shared>) -> Json<Value> {
+    let backend = &mut *backend.lock().unwrap();
+    let mut interfaces = json!({});
+    for (nic, (backlog_packets, drops)) in &backend.script.queues {
+        let total = backend.drops.entry(nic.clone()).or_insert(0);
+        *total += drops;
+        interfaces[nic] = json!({ "backlog_packets": backlog_packets, "drops": *total });
+    }
+    Json(json!({ "interfaces": interfaces }))
+}
+
+async fn notify(State(backend): State<Shared>, request: Request) -> StatusCode {
+    let path = request.uri().path().to_string();
+    let Ok(body) = axum::body::to_bytes(request.into_body(), usize::MAX).await else {
+        return StatusCode::BAD_REQUEST;
+    };
+    let Ok(body) = serde_json::from_slice(&body) else {
+        return StatusCode::BAD_REQUEST;
+    };
+    let mut backend = backend.lock().unwrap();
+    if backend.script.fail_notifications > 0 {
+        backend.script.fail_notifications -= 1;
+        return StatusCode::INTERNAL_SERVER_ERROR;
+    }
+    let cycle = backend.log.count("/status");
+    backend
+        .log
+        .notifications
+        .push(Notification { path, body, cycle });
+    StatusCode::OK
+}
+
+/// The binary running `run` on the simulated clock in a directory of its own, so its history
+/// database and journal start empty.
+pub struct Instance {
+    child: Child,
+    dir: PathBuf,
+    args: Vec<String>,
+}
+
+impl Instance {
+    pub fn start(config: &str) -> Self {
+        Self::start_with(config, &["--quiet"])
+    }
+
+    /// Starts it with `args` before the `run` subcommand instead of `--quiet`.
+    pub fn start_with(config: &str, args: &[&str]) -> Self {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let dir = std::env::temp_dir().join(format!(
+            "routingflow-test-{}-{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("routingflow.toml"), config).unwrap();
+        let args: Vec<String> = args.iter().map(ToString::to_string).collect();
+        let child = spawn(&dir, &args, SIMULATED_START, &[]);
+        Self { child, dir, args }
+    }
+
+    /// Stops the instance and starts it again in the same directory, its clock `secs` past
+    /// [`SIMULATED_START`], as a restart some time later would.
+    pub async fn restart(mut self, secs: i64) -> Self {
+        assert!(self.stop_child().await.success());
+        let start = chrono::DateTime::parse_from_rfc3339(SIMULATED_START).unwrap()
+            + chrono::Duration::seconds(secs);
+        self.child = spawn(&self.dir, &self.args, &start.to_rfc3339(), &[]);
+        self
+    }
+
+    /// Starts a new instance with `run --take-over` in the same directory and waits for
+    /// this one to hand over to it and exit, returning how it exited and the new instance.
+    pub async fn hand_over(mut self) -> (ExitStatus, Self) {
+        let successor = spawn(&self.dir, &self.args, SIMULATED_START, &["--take-over"]);
+        let status = tokio::time::timeout(WAIT_TIMEOUT, self.child.wait())
+            .await
+            .expect("routingFlow did not hand over")
+            .unwrap();
+        self.child = successor;
+        (status, self)
+    }
+
+    /// A file the instance writes, relative to its directory.
+    pub fn path(&self, name: &str) -> PathBuf {
+        self.dir.join(name)
+    }
+
+    /// Runs another subcommand against the instance's config and history, e.g. `report`.
+    pub async fn command(&self, args: &[&str]) -> std::process::Output {
+        Command::new(env!("CARGO_BIN_EXE_routingFlow"))
+            .args(args)
+            .current_dir(&self.dir)
+            .env_remove("ROUTINGFLOW_CONFIG")
+            .stdin(Stdio::null())
+            .output()
+            .await
+            .expect("Failed to run routingFlow")
+    }
+
+    /// Waits for it to exit on its own, e.g. after refusing its config.
+    pub async fn wait(mut self) -> ExitStatus {
+        tokio::time::timeout(WAIT_TIMEOUT, self.child.wait())
+            .await
+            .expect("routingFlow did not exit")
+            .unwrap()
+    }
+
+    /// Waits for it to exit like [`Instance::wait`] and returns everything it logged.
+    pub async fn wait_with_log(mut self) -> (ExitStatus, String) {
+        let status = tokio::time::timeout(WAIT_TIMEOUT, self.child.wait())
+            .await
+            .expect("routingFlow did not exit")
+            .unwrap();
+        (
+            status,
+            std::fs::read_to_string(self.path(LOG_FILE)).unwrap(),
+        )
+    }
+
+    /// Its process ID.
+    pub fn pid(&self) -> u32 {
+        self.child.id().expect("routingFlow already exited")
+    }
+
+    /// Sends it `signal`, e.g. `libc::SIGTERM`.
+    pub fn signal(&self, signal: libc::c_int) {
+        if let Some(pid) = self.child.id() {
+            unsafe {
+                libc::kill(pid as libc::pid_t, signal);
+            }
+        }
+    }
+
+    /// Stops it the way Ctrl+C would and waits for it to exit.
+    pub async fn stop(mut self) -> ExitStatus {
+        self.stop_child().await
+    }
+
+    /// Stops it like [`Instance::stop`] and returns everything it logged, restarts included.
+    pub async fn stop_with_log(mut self) -> (ExitStatus, String) {
+        let status = self.stop_child().await;
+        (
+            status,
+            std::fs::read_to_string(self.path(LOG_FILE)).unwrap(),
+        )
+    }
+
+    /// Stops it like [`Instance::stop`] and returns everything it wrote to stdout.
+    pub async fn stop_with_report(mut self) -> (ExitStatus, String) {
+        let status = self.stop_child().await;
+        (
+            status,
+            std::fs::read_to_string(self.path(REPORT_FILE)).unwrap(),
+        )
+    }
+
+    async fn stop_child(&mut self) -> ExitStatus {
+        self.signal(libc::SIGINT);
+        tokio::time::timeout(WAIT_TIMEOUT, self.child.wait())
+            .await
+            .expect("routingFlow did not stop")
+            .unwrap()
+    }
+}
+
+/// Runs the binary in `dir`, appending its output to [`REPORT_FILE`] and [`LOG_FILE`] there.
+/// `run_args` follow the `run` subcommand's own.
+fn spawn(dir: &Path, args: &[String], start: &str, run_args: &[&str]) -> Child {
+    let append = |name| {
+        std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(dir.join(name))
+            .unwrap()
+    };
+    Command::new(env!("CARGO_BIN_EXE_routingFlow"))
+        .args(args)
+        .args(["run", "--simulated-start", start])
+        .args(run_args)
+        .current_dir(dir)
+        .env_remove("ROUTINGFLOW_CONFIG")
+        .env_remove("RUST_LOG")
+        // Schedules see SIMULATED_START as 10:00 on a Wednesday
+        .env("TZ", "UTC")
+        .stdin(Stdio::null())
+        .stdout(append(REPORT_FILE))
+        .stderr(append(LOG_FILE))
+        .kill_on_drop(true)
+        .spawn()
+        .expect("Failed to start routingFlow")
+}
+
+impl Drop for Instance {
+    fn drop(&mut self) {
+        let _ = self.child.start_kill();
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// A local address nothing listens on, for the instance's HTTP API.
+pub fn free_addr() -> SocketAddr {
+    std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+}
+
+/// The HTTP API of an instance, called with an API key.
+pub struct Api {
+    client: reqwest::Client,
+    url: String,
+    key: String,
+}
+
+impl Api {
+    /// Waits until the API at `addr` answers.
+    pub async fn connect(addr: SocketAddr, key: &str) -> Self {
+        let api = Self {
+            client: reqwest::Client::new(),
+            url: format!("http://{}", addr),
+            key: key.to_string(),
+        };
+        let deadline = tokio::time::Instant::now() + WAIT_TIMEOUT;
+        while api
+            .client
+            .get(format!("{}/metrics", api.url))
+            .send()
+            .await
+            .is_err()
+        {
+            if tokio::time::Instant::now() > deadline {
+                panic!("Timed out waiting for the API on {}", addr);
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        api
+    }
+
+    /// Status and body of `GET path`.
+    pub async fn get(&self, path: &str) -> (u16, String) {
+        let request = self.client.get(format!("{}{}", self.url, path));
+        self.send(request).await
+    }
+
+    /// Status and body of `POST path` with `body` as JSON.
+    pub async fn post(&self, path: &str, body: Value) -> (u16, String) {
+        let request = self
+            .client
+            .post(format!("{}{}", self.url, path))
+            .json(&body);
+        self.send(request).await
+    }
+
+    /// Waits until `done` holds for the body of `GET /state`, failing the test after
+    /// [`WAIT_TIMEOUT`].
+    pub async fn wait_for_state(&self, what: &str, done: impl Fn(&Value) -> bool) -> Value {
+        let deadline = tokio::time::Instant::now() + WAIT_TIMEOUT;
+        loop {
+            let (_, state) = self.get("/state").await;
+            let state: Value = serde_json::from_str(&state).unwrap();
+            if done(&state) {
+                return state;
+            }
+            if tokio::time::Instant::now() > deadline {
+                panic!("Timed out waiting for {}; state {}", what, state);
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    }
+
+    async fn send(&self, request: reqwest::RequestBuilder) -> (u16, String) {
+        let response = request.header("x-api-key", &self.key).send().await.unwrap();
+        let status = response.status().as_u16();
+        (status, response.text().await.unwrap())
+    }
+}
+
+/// Files in a `.tar.gz` the binary wrote, by name.
+pub fn unpack(archive: &Path) -> HashMap<String, String> {
+    let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(
+        std::fs::File::open(archive).unwrap(),
+    ));
+    archive
+        .entries()
+        .unwrap()
+        .map(|entry| {
+            let mut entry = entry.unwrap();
+            let name = entry.path().unwrap().to_string_lossy().into_owned();
+            let mut contents = String::new();
+            std::io::Read::read_to_string(&mut entry, &mut contents).unwrap();
+            (name, contents)
+        })
+        .collect()
+}
+
+/// Config of an HTTP API on `addr` with one admin key, `admin-key`.
+pub fn api_config(addr: SocketAddr) -> String {
+    format!(
+        "[server]\nlisten = \"{}\"\n\n[[server.auth.api_keys]]\nname = \"test\"\nkey = \"admin-key\"\nrole = \"admin\"\n",
+        addr
+    )
+}
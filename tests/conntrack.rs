@@ -0,0 +1,122 @@
+mod common;
+
+use common::{Instance, MockBackends, Script};
+use serde_json::Value;
+
+/// A 50 MB download of the busy client keeps it where it is until the deferral runs out.
+#[tokio::test]
+async fn defers_switching_a_client_with_a_busy_connection() {
+    let table =
+        std::env::temp_dir().join(format!("routingflow-test-{}.conntrack", std::process::id()));
+    std::fs::write(
+        &table,
+        "ipv4     2 tcp      6 431999 ESTABLISHED src=192.168.1.10 dst=198.51.100.7 sport=51000 dport=443 packets=9000 bytes=400000 src=198.51.100.7 dst=203.0.113.2 sport=443 dport=51000 packets=36000 bytes=50000000 [ASSURED] mark=0 zone=0 use=2\n\
+         ipv4     2 tcp      6 431999 ESTABLISHED src=192.168.1.11 dst=198.51.100.7 sport=51001 dport=443 packets=10 bytes=4000 src=198.51.100.7 dst=203.0.113.2 sport=443 dport=51001 packets=10 bytes=9000 [ASSURED] mark=0 zone=0 use=2\n",
+    )
+    .unwrap();
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let instance = Instance::start(&backends.config(&format!(
+        "[conntrack.protect]\npath = {:?}\nmax_defer_secs = 30",
+        table
+    )));
+
+    let log = backends
+        .wait_for("a switch", |log| !log.switches.is_empty())
+        .await;
+    assert!(instance.stop().await.success());
+    let _ = std::fs::remove_file(&table);
+    assert_eq!(log.moves()[0], ("192.168.1.10", "wan1"));
+    let cycle = log.switches[0].cycle;
+    assert!((30..=35).contains(&cycle), "switched after {}s", cycle);
+}
+
+/// IPs of the clients whose conntrack entries the instance flushed, or tried to: without
+/// `CAP_NET_ADMIN` the flush fails, and is logged as such.
+fn flushed(log: &str) -> Vec<String> {
+    log.lines()
+        .map(|line| serde_json::from_str::<Value>(line).unwrap())
+        .filter(|line| {
+            let message = line["message"].as_str().unwrap();
+            message == "Flushed conntrack entries"
+                || message.starts_with("Failed to flush conntrack entries")
+        })
+        .map(|line| line["ip"].as_str().unwrap().to_string())
+        .collect()
+}
+
+#[tokio::test]
+async fn flushes_a_switched_clients_entries() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let instance = Instance::start(
+        &backends.config("[conntrack]\nflush_on_switch = true\n\n[logging]\nformat = \"json\""),
+    );
+    let log = backends
+        .wait_for("a switch", |log| !log.switches.is_empty())
+        .await;
+    // The flush runs in the background
+    let cycles = log.count("/status");
+    backends
+        .wait_for("2 more cycles", |log| log.count("/status") >= cycles + 2)
+        .await;
+
+    let (status, log) = instance.stop_with_log().await;
+    assert!(status.success());
+    assert_eq!(flushed(&log)[0], "192.168.1.10", "{}", log);
+}
+
+#[tokio::test]
+async fn leaves_the_entries_of_a_failed_switch() {
+    let mut script = Script::two_wans();
+    script.fail_switch = true;
+    let backends = MockBackends::start(script).await;
+    let instance = Instance::start(
+        &backends.config("[conntrack]\nflush_on_switch = true\n\n[logging]\nformat = \"json\""),
+    );
+    backends
+        .wait_for("a failed switch", |log| !log.switches.is_empty())
+        .await;
+
+    let (status, log) = instance.stop_with_log().await;
+    assert!(status.success());
+    assert!(flushed(&log).is_empty(), "{}", log);
+}
+
+#[tokio::test]
+async fn flushes_a_verified_switch_once_it_is_confirmed() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let instance = Instance::start(&backends.config(
+        "[conntrack]\nflush_on_switch = true\n\n[verification]\ndelay_ms = 10\nretries = 1\n\n[logging]\nformat = \"json\"",
+    ));
+    let log = backends
+        .wait_for("a switch", |log| !log.switches.is_empty())
+        .await;
+    let cycles = log.count("/status");
+    backends
+        .wait_for("2 more cycles", |log| log.count("/status") >= cycles + 2)
+        .await;
+
+    let (status, log) = instance.stop_with_log().await;
+    assert!(status.success());
+    assert_eq!(flushed(&log), ["192.168.1.10"], "{}", log);
+}
+
+#[tokio::test]
+async fn leaves_the_entries_of_a_switch_that_did_not_take_effect() {
+    let mut script = Script::two_wans();
+    script.ignore_switches = true;
+    let backends = MockBackends::start(script).await;
+    let instance = Instance::start(&backends.config(
+        "[conntrack]\nflush_on_switch = true\n\n[verification]\ndelay_ms = 10\nretries = 1\n\n[logging]\nformat = \"json\"",
+    ));
+    let log = backends
+        .wait_for("a re-issued switch", |log| log.switches.len() >= 2)
+        .await;
+    let cycles = log.count("/status");
+    backends
+        .wait_for("2 more cycles", |log| log.count("/status") >= cycles + 2)
+        .await;
+
+    let (status, log) = instance.stop_with_log().await;
+    assert!(status.success());
+    assert!(flushed(&log).is_empty(), "{}", log);
+}
@@ -0,0 +1,219 @@
+mod common;
+
+use axum::body::Body;
+use axum::http::{header, HeaderMap, Request, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::{Json, Router};
+use common::{api_config, free_addr, Api, Instance, MockBackends, Script};
+use serde_json::{json, Value};
+use std::sync::{Arc, Mutex};
+
+/// A request the fake controller received: path and query, `Cookie` and `Csrf-Token`.
+type Seen = Arc<Mutex<Vec<(String, Option<String>, Option<String>)>>>;
+
+/// Answers like a UniFi Network application or an Omada controller with one client at
+/// 192.168.1.10 and one at an address nothing maps.
+async fn answer(headers: HeaderMap, request: Request<Body>) -> Response {
+    let path = request.uri().path().to_string();
+    let logged_in = |cookie: &str| {
+        headers
+            .get(header::COOKIE)
+            .is_some_and(|value| value == cookie)
+    };
+    match path.as_str() {
+        "/api/login" => (
+            [(header::SET_COOKIE, "unifises=s3ss10n; Path=/; HttpOnly")],
+            Json(json!({ "meta": { "rc": "ok" }, "data": [] })),
+        )
+            .into_response(),
+        "/api/s/default/stat/sta" if logged_in("unifises=s3ss10n") => Json(json!({
+            "meta": { "rc": "ok" },
+            "data": [
+                {
+                    "mac": "AA:BB:CC:00:00:10",
+                    "ip": "192.168.1.10",
+                    "name": "Garage camera",
+                    "hostname": "ipc-4f2a",
+                    "oui": "Hikvision Digital",
+                    "is_wired": true
+                },
+                { "mac": "aa:bb:cc:00:00:99", "ip": "192.168.1.99", "hostname": "guest" }
+            ]
+        }))
+        .into_response(),
+        "/api/info" => {
+            Json(json!({ "errorCode": 0, "result": { "omadacId": "c0ffee" } })).into_response()
+        }
+        "/c0ffee/api/v2/login" => (
+            [(header::SET_COOKIE, "TPOMADA_SESSIONID=0m4d4; Path=/")],
+            Json(json!({ "errorCode": 0, "result": { "token": "csrf-1" } })),
+        )
+            .into_response(),
+        "/c0ffee/api/v2/sites" if logged_in("TPOMADA_SESSIONID=0m4d4") => Json(json!({
+            "errorCode": 0,
+            "result": {
+                "totalRows": 2,
+                "data": [{ "id": "s-lab", "name": "Lab" }, { "id": "s-home", "name": "Default" }]
+            }
+        }))
+        .into_response(),
+        "/c0ffee/api/v2/sites/s-home/clients"
+            if logged_in("TPOMADA_SESSIONID=0m4d4")
+                && headers
+                    .get("csrf-token")
+                    .is_some_and(|token| token == "csrf-1") =>
+        {
+            Json(json!({
+                "errorCode": 0,
+                "result": {
+                    "totalRows": 2,
+                    "data": [
+                        {
+                            "mac": "AA-BB-CC-00-00-10",
+                            "ip": "192.168.1.10",
+                            "name": "AA-BB-CC-00-00-10",
+                            "hostName": "ipc-4f2a",
+                            "deviceType": "camera",
+                            "vendor": "TP-Link",
+                            "wireless": true
+                        },
+                        { "mac": "AA-BB-CC-00-00-99", "ip": "192.168.1.99", "name": "guest" }
+                    ]
+                }
+            }))
+            .into_response()
+        }
+        _ => StatusCode::UNAUTHORIZED.into_response(),
+    }
+}
+
+/// The fake controller's URL and the requests it received.
+async fn controller() -> (String, Seen) {
+    let seen = Seen::default();
+    let record = seen.clone();
+    let app = Router::new().fallback(move |headers: HeaderMap, request: Request<Body>| {
+        let header = |name: &str| {
+            headers
+                .get(name)
+                .map(|value| value.to_str().unwrap().to_string())
+        };
+        record.lock().unwrap().push((
+            request.uri().to_string(),
+            header("cookie"),
+            header("csrf-token"),
+        ));
+        answer(headers, request)
+    });
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    (url, seen)
+}
+
+/// [`Script::two_wans`] with the busy client quiet until the controller has been read.
+fn quiet() -> Script {
+    let mut script = Script::two_wans();
+    script
+        .traffic_bps
+        .insert("192.168.1.10".to_string(), (5e5, 5e4));
+    script
+}
+
+/// Waits for the controller's client at 192.168.1.10 in the state, then makes it busy and
+/// returns the decision about it.
+async fn decision_once_busy(backends: &MockBackends, api: &Api) -> (Value, Value) {
+    let state = api
+        .wait_for_state("the controller's clients", |state| {
+            state["cycle"]["clients"]["192.168.1.10"].is_object()
+        })
+        .await;
+    let client = state["cycle"]["clients"]["192.168.1.10"].clone();
+    backends.update(|script| {
+        script
+            .traffic_bps
+            .insert("192.168.1.10".to_string(), (20e6, 2e6));
+    });
+    let decision = |state: &Value| {
+        state["cycle"]["decisions"]
+            .as_array()
+            .and_then(|decisions| {
+                decisions
+                    .iter()
+                    .find(|decision| decision["ip"] == "192.168.1.10")
+            })
+            .cloned()
+    };
+    let state = api
+        .wait_for_state("a decision", |state| decision(state).is_some())
+        .await;
+    (client, decision(&state).unwrap())
+}
+
+#[tokio::test]
+async fn pins_clients_by_the_vendor_a_unifi_controller_reports() {
+    let (url, seen) = controller().await;
+    let backends = MockBackends::start(quiet()).await;
+    let addr = free_addr();
+    let instance = Instance::start(&backends.config(&format!(
+        "[controller]\nkind = \"unifi\"\nurl = \"{}\"\nusername = \"routingflow\"\npassword = \"secret\"\n\n\
+         [[client_rules]]\nvendor = \"hikvision\"\naction = \"pin\"\nwan = \"wan0\"\n\n{}",
+        url,
+        api_config(addr)
+    )));
+    let api = Api::connect(addr, "admin-key").await;
+
+    let (client, decision) = decision_once_busy(&backends, &api).await;
+    let log = backends.log();
+    assert!(instance.stop().await.success());
+    assert!(log.switches.is_empty(), "{:?}", log.moves());
+    assert_eq!(decision["outcome"], "skipped");
+    assert_eq!(decision["reason"], "pinned to wan0");
+    assert_eq!(client["mac"], "aa:bb:cc:00:00:10");
+    assert_eq!(client["name"], "Garage camera");
+    assert_eq!(client["vendor"], "Hikvision Digital");
+    assert_eq!(client["wired"], true);
+    let seen = seen.lock().unwrap().clone();
+    assert_eq!(seen[0].0, "/api/login");
+    assert_eq!(seen[1].0, "/api/s/default/stat/sta");
+    assert_eq!(seen[1].1.as_deref(), Some("unifises=s3ss10n"));
+}
+
+#[tokio::test]
+async fn pins_clients_by_the_device_type_an_omada_controller_reports() {
+    let (url, seen) = controller().await;
+    let backends = MockBackends::start(quiet()).await;
+    let addr = free_addr();
+    let instance = Instance::start(&backends.config(&format!(
+        "[controller]\nkind = \"omada\"\nurl = \"{}\"\nusername = \"routingflow\"\npassword = \"secret\"\n\n\
+         [[client_rules]]\ndevice_type = \"camera\"\naction = \"pin\"\nwan = \"wan0\"\n\n{}",
+        url,
+        api_config(addr)
+    )));
+    let api = Api::connect(addr, "admin-key").await;
+
+    let (client, decision) = decision_once_busy(&backends, &api).await;
+    let log = backends.log();
+    assert!(instance.stop().await.success());
+    assert!(log.switches.is_empty(), "{:?}", log.moves());
+    assert_eq!(decision["reason"], "pinned to wan0");
+    assert_eq!(client["mac"], "aa:bb:cc:00:00:10");
+    // Omada names unnamed clients by their MAC
+    assert_eq!(client["name"], "ipc-4f2a");
+    assert_eq!(client["device_type"], "camera");
+    assert_eq!(client["wired"], false);
+    let seen = seen.lock().unwrap().clone();
+    assert_eq!(seen[3].2.as_deref(), Some("csrf-1"));
+    let paths: Vec<String> = seen
+        .iter()
+        .map(|(path, _, _)| path.split('?').next().unwrap().to_string())
+        .collect();
+    assert_eq!(
+        paths[..4],
+        [
+            "/api/info",
+            "/c0ffee/api/v2/login",
+            "/c0ffee/api/v2/sites",
+            "/c0ffee/api/v2/sites/s-home/clients"
+        ]
+    );
+}
@@ -0,0 +1,122 @@
+mod common;
+
+use common::{api_config, free_addr, Api, Instance, MockBackends, Script};
+
+/// The busy client is the heaviest on whichever WAN it is on, so `top_rx` moves it back and
+/// forth; only the cooldown spaces the moves out.
+#[tokio::test]
+async fn holds_a_switched_client_for_its_cooldown() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let instance = Instance::start(&backends.config("[cooldown]\ndefault_secs = 120"));
+
+    let busy = |log: &common::Log| {
+        log.switches
+            .iter()
+            .filter(|switch| switch.ip == "192.168.1.10")
+            .map(|switch| switch.cycle)
+            .collect::<Vec<_>>()
+    };
+    let log = backends
+        .wait_for("three moves of the busy client", |log| busy(log).len() >= 3)
+        .await;
+    assert!(instance.stop().await.success());
+    // One cycle per simulated second
+    for moves in busy(&log).windows(2) {
+        let gap = moves[1] - moves[0];
+        assert!((120..=125).contains(&gap), "moved again after {}s", gap);
+    }
+}
+
+#[tokio::test]
+async fn a_per_prefix_cooldown_overrides_the_default() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let instance = Instance::start(&backends.config(
+        "[cooldown]\ndefault_secs = 120\noverrides = [{ prefix = \"192.168.1.0/28\", secs = 10 }]",
+    ));
+
+    let log = backends
+        .wait_for("two moves", |log| log.switches.len() >= 2)
+        .await;
+    assert!(instance.stop().await.success());
+    let gap = log.switches[1].cycle - log.switches[0].cycle;
+    assert!((10..=15).contains(&gap), "moved again after {}s", gap);
+}
+
+#[tokio::test]
+async fn a_restart_keeps_the_cooldown() {
+    let state = std::env::temp_dir().join(format!("routingflow-test-{}.state", std::process::id()));
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let config = backends.config(&format!(
+        "[cooldown]\ndefault_secs = 120\n\n[saved_state]\npath = {:?}",
+        state
+    ));
+    let first = Instance::start(&config);
+    backends
+        .wait_for("a switch", |log| !log.switches.is_empty())
+        .await;
+    assert!(first.stop().await.success());
+
+    // Without the saved switch the busy client would be moved straight back
+    let cycles = backends.log().count("/status");
+    let second = Instance::start(&config);
+    let log = backends
+        .wait_for("30 more cycles", |log| log.count("/status") >= cycles + 30)
+        .await;
+    assert!(second.stop().await.success());
+    let _ = std::fs::remove_file(&state);
+    assert_eq!(log.moves(), [("192.168.1.10", "wan1")]);
+}
+
+#[tokio::test]
+async fn the_longest_matching_prefix_sets_the_cooldown() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let instance = Instance::start(&backends.config(
+        "[cooldown]\ndefault_secs = 120\noverrides = [\n  { prefix = \"192.168.1.0/24\", secs = 60 },\n  { prefix = \"192.168.1.10\", secs = 10 },\n]",
+    ));
+
+    let log = backends
+        .wait_for("two moves", |log| log.switches.len() >= 2)
+        .await;
+    assert!(instance.stop().await.success());
+    let gap = log.switches[1].cycle - log.switches[0].cycle;
+    assert!((10..=15).contains(&gap), "moved again after {}s", gap);
+}
+
+#[tokio::test]
+async fn holds_a_client_in_a_sticky_class_for_its_residency() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let addr = free_addr();
+    let instance = Instance::start(&backends.config(&format!(
+        "[[traffic_classes]]\nname = \"bulk\"\nmin_rx_mbps = 10.0\nmin_residency_secs = 60\n\n{}",
+        api_config(addr)
+    )));
+    let api = Api::connect(addr, "admin-key").await;
+
+    let state = api
+        .wait_for_state("a held decision", |state| {
+            state["cycle"]["decisions"]
+                .as_array()
+                .is_some_and(|decisions| decisions.iter().any(|d| d["outcome"] == "held"))
+        })
+        .await;
+    let held = state["cycle"]["decisions"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|decision| decision["outcome"] == "held")
+        .unwrap();
+    assert_eq!(held["ip"], "192.168.1.10");
+    assert_eq!(held["hold_reason"], "bulk residency");
+
+    // In the class from the first cycle on
+    let log = backends
+        .wait_for("a switch", |log| !log.switches.is_empty())
+        .await;
+    assert!(instance.stop().await.success());
+    assert_eq!(log.moves()[0], ("192.168.1.10", "wan1"));
+    assert!(
+        (60..=65).contains(&log.switches[0].cycle),
+        "{:?}",
+        log.switches
+    );
+}
@@ -0,0 +1,118 @@
+mod common;
+
+use common::{MockBackends, Script, SIMULATED_START};
+use std::path::{Path, PathBuf};
+use std::process::Output;
+use std::time::Duration;
+
+/// A directory holding `config`, named after `name`.
+fn scratch(name: &str, config: &str) -> PathBuf {
+    let dir =
+        std::env::temp_dir().join(format!("routingflow-test-{}-{}", std::process::id(), name));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("routingflow.toml"), config).unwrap();
+    dir
+}
+
+/// Runs `run` with `args` on the simulated clock in `dir`; with `--daemon`, only until it
+/// has detached.
+async fn run(dir: &Path, args: &[&str]) -> Output {
+    tokio::process::Command::new(env!("CARGO_BIN_EXE_routingFlow"))
+        .args(["--quiet", "run", "--simulated-start", SIMULATED_START])
+        .args(args)
+        .current_dir(dir)
+        .env_remove("ROUTINGFLOW_CONFIG")
+        .output()
+        .await
+        .unwrap()
+}
+
+/// Waits for `done` to hold, polling every 20 ms for up to 10 seconds.
+async fn eventually(what: &str, done: impl Fn() -> bool) {
+    for _ in 0..500 {
+        if done() {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("Timed out waiting for {}", what);
+}
+
+/// Waits for the daemon to be balancing, and so to handle signals, and returns its PID.
+async fn running(backends: &MockBackends, pid_file: &Path) -> i32 {
+    backends
+        .wait_for("2 cycles", |log| log.count("/status") >= 2)
+        .await;
+    std::fs::read_to_string(pid_file)
+        .unwrap()
+        .trim()
+        .parse()
+        .unwrap()
+}
+
+/// Stops the daemon and waits for it to remove its PID file.
+async fn terminate(pid: i32, pid_file: &Path) {
+    unsafe {
+        libc::kill(pid, libc::SIGTERM);
+    }
+    eventually("the PID file to go", || !pid_file.exists()).await;
+}
+
+#[tokio::test]
+async fn detaches_and_holds_its_pid_file_against_a_second_instance() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let dir = scratch("daemon-lock", &backends.config(""));
+    let pid_file = dir.join("routingflow.pid");
+    let pid_arg = pid_file.to_str().unwrap();
+
+    let detached = run(
+        &dir,
+        &[
+            "--daemon",
+            "--pid-file",
+            pid_arg,
+            "--log-file",
+            "daemon.log",
+        ],
+    )
+    .await;
+    assert!(detached.status.success(), "{:?}", detached);
+    let pid = running(&backends, &pid_file).await;
+    assert_ne!(pid as u32, std::process::id());
+
+    let second = run(&dir, &["--pid-file", pid_arg]).await;
+    assert!(!second.status.success());
+    let stderr = String::from_utf8_lossy(&second.stderr);
+    assert!(
+        stderr.contains(&format!(
+            "Another routingFlow instance is running (PID {}, holding {})",
+            pid, pid_arg
+        )),
+        "{}",
+        stderr
+    );
+
+    terminate(pid, &pid_file).await;
+    let log = std::fs::read_to_string(dir.join("daemon.log")).unwrap();
+    assert!(log.contains("Stopping after the current cycle"), "{}", log);
+}
+
+#[tokio::test]
+async fn takes_over_a_pid_file_left_by_a_crash() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let dir = scratch("daemon-stale", &backends.config(""));
+    let pid_file = dir.join("routingflow.pid");
+    // Nobody holds the lock on it
+    std::fs::write(&pid_file, "999999\n").unwrap();
+
+    let detached = run(
+        &dir,
+        &["--daemon", "--pid-file", pid_file.to_str().unwrap()],
+    )
+    .await;
+    assert!(detached.status.success(), "{:?}", detached);
+    let pid = running(&backends, &pid_file).await;
+    assert_ne!(pid, 999999);
+    terminate(pid, &pid_file).await;
+}
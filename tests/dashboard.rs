@@ -0,0 +1,64 @@
+mod common;
+
+use common::{api_config, free_addr, Api, Instance, MockBackends, Script};
+use serde_json::Value;
+
+#[tokio::test]
+async fn serves_the_page_openly_and_its_data_to_viewers() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let addr = free_addr();
+    let dashboard = free_addr();
+    let instance = Instance::start(&backends.config(&format!(
+        "{}\n[server.dashboard]\nlisten = \"{}\"\nsamples = 5",
+        api_config(addr),
+        dashboard
+    )));
+    Api::connect(addr, "admin-key").await;
+    let log = backends
+        .wait_for("a switch", |log| !log.switches.is_empty())
+        .await;
+    let cycles = log.count("/status");
+    backends
+        .wait_for("10 more cycles", |log| log.count("/status") >= cycles + 10)
+        .await;
+
+    let client = reqwest::Client::new();
+    let page = client
+        .get(format!("http://{}/", dashboard))
+        .send()
+        .await
+        .unwrap();
+    let anonymous = client
+        .get(format!("http://{}/api/dashboard", dashboard))
+        .send()
+        .await
+        .unwrap();
+    let data: Value = client
+        .get(format!("http://{}/api/dashboard", dashboard))
+        .header("x-api-key", "admin-key")
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert!(instance.stop().await.success());
+
+    assert_eq!(page.status(), 200);
+    assert!(page.text().await.unwrap().contains("<h1>routingFlow</h1>"));
+    assert_eq!(anonymous.status(), 401);
+    // Only the configured number of cycles is kept
+    let samples = data["samples"].as_array().unwrap();
+    assert_eq!(samples.len(), 5);
+    assert_eq!(samples[4]["wans"]["wan1"]["tcp_bandwidth_bps"], 200e6);
+    let mapping = data["mappings"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|row| row["ip"] == "192.168.1.10")
+        .unwrap();
+    assert_eq!(mapping["wan"], "wan1");
+    assert_eq!(mapping["rx_bps"], 20e6);
+    assert_eq!(data["recent_switches"][0]["ip"], "192.168.1.10");
+    assert_eq!(data["recent_switches"][0]["target_wan"], "wan1");
+}
@@ -0,0 +1,54 @@
+mod common;
+
+use common::{Instance, MockBackends, Script};
+
+/// `wan1` capped at 200 GB, with `refresh_secs` short enough to see new readings.
+const DATA_CAPS: &str =
+    "[data_caps]\nrefresh_secs = 5\n\n[[data_caps.wans]]\nwan = \"wan1\"\ncap_gb = 200";
+
+/// The two WANs with `wan1` having moved `used_gb` this billing period.
+fn metered(used_gb: f64) -> Script {
+    let mut script = Script::two_wans();
+    script.used_bytes.insert("eth1".to_string(), used_gb * 1e9);
+    script
+}
+
+#[tokio::test]
+async fn stops_steering_onto_a_wan_at_its_cap_until_usage_drops() {
+    let backends = MockBackends::start(metered(195.0)).await;
+    let instance = Instance::start(&backends.config(DATA_CAPS));
+
+    let log = backends
+        .wait_for("five cycles", |log| log.count("/status") >= 5)
+        .await;
+    assert!(log.switches.is_empty(), "{:?}", log.switches);
+
+    // As after the counters were found to be off, or the period reset
+    backends.update(|script| {
+        script.used_bytes.insert("eth1".to_string(), 10e9);
+    });
+    let log = backends
+        .wait_for("a switch", |log| !log.switches.is_empty())
+        .await;
+    assert!(instance.stop().await.success());
+    assert_eq!(log.moves(), vec![("192.168.1.10", "wan1")]);
+}
+
+#[tokio::test]
+async fn moves_only_light_clients_onto_a_wan_near_its_cap() {
+    let mut script = metered(170.0);
+    script
+        .traffic_bps
+        .insert("192.168.1.11".to_string(), (3e6, 5e5));
+    let backends = MockBackends::start(script).await;
+    let instance =
+        Instance::start(&backends.config(&format!("[top_rx]\nmoves_per_nic = 2\n\n{}", DATA_CAPS)));
+
+    // The busy client is bulk traffic; the next one on the NIC is not
+    let log = backends
+        .wait_for("a switch", |log| !log.switches.is_empty())
+        .await;
+    assert!(instance.stop().await.success());
+    assert_eq!(log.moves()[0], ("192.168.1.11", "wan1"));
+    assert!(!log.moves().contains(&("192.168.1.10", "wan1")));
+}
@@ -0,0 +1,71 @@
+mod common;
+
+use common::{api_config, free_addr, Api, Instance, MockBackends, Script};
+
+#[tokio::test]
+async fn holds_switches_while_the_routing_service_is_down() {
+    let mut script = Script::two_wans();
+    script
+        .traffic_bps
+        .insert("192.168.1.10".to_string(), (5e5, 5e4));
+    let backends = MockBackends::start(script).await;
+    let addr = free_addr();
+    let instance = Instance::start(&backends.config(&api_config(addr)));
+    let api = Api::connect(addr, "admin-key").await;
+    api.wait_for_state("full mode", |state| state["mode"]["mode"] == "full")
+        .await;
+
+    // Busy again, with only the last good mappings to go on
+    backends.update(|script| script.fail_status = true);
+    api.wait_for_state("backend_degraded mode", |state| {
+        state["mode"]["mode"] == "backend_degraded"
+    })
+    .await;
+    backends.update(|script| {
+        script
+            .traffic_bps
+            .insert("192.168.1.10".to_string(), (20e6, 2e6));
+    });
+    let state = api
+        .wait_for_state("backend_degraded mode", |state| {
+            state["mode"]["mappings_age_secs"].as_u64() >= Some(5)
+        })
+        .await;
+    assert_eq!(state["mode"]["mode"], "backend_degraded");
+    assert_eq!(state["mode"]["skipping_cycles"], false);
+    assert!(state["mode"]["metrics_age_secs"].is_null(), "{}", state);
+    assert!(backends.log().switches.is_empty());
+
+    backends.update(|script| script.fail_status = false);
+    let log = backends
+        .wait_for("a switch", |log| !log.switches.is_empty())
+        .await;
+    let state = api
+        .wait_for_state("full mode", |state| state["mode"]["mode"] == "full")
+        .await;
+    assert!(instance.stop().await.success());
+    assert_eq!(log.moves()[0], ("192.168.1.10", "wan1"));
+    assert!(state["mode"]["mappings_age_secs"].is_null(), "{}", state);
+}
+
+#[tokio::test]
+async fn skips_cycles_once_the_last_mappings_are_too_old() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let addr = free_addr();
+    let instance = Instance::start(&backends.config(&format!(
+        "[degradation]\nmax_stale_secs = 3\n\n{}",
+        api_config(addr)
+    )));
+    let api = Api::connect(addr, "admin-key").await;
+    api.wait_for_state("full mode", |state| state["mode"]["mode"] == "full")
+        .await;
+
+    backends.update(|script| script.fail_status = true);
+    let state = api
+        .wait_for_state("skipped cycles", |state| {
+            state["mode"]["skipping_cycles"] == true
+        })
+        .await;
+    assert!(instance.stop().await.success());
+    assert_eq!(state["mode"]["mode"], "backend_degraded");
+}
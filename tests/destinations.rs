@@ -0,0 +1,200 @@
+mod common;
+
+use common::{Instance, MockBackends, Script};
+use serde_json::Value;
+
+/// A MaxMind DB string: type 2, its length in the control byte or the byte after.
+fn mmdb_string(value: &str) -> Vec<u8> {
+    let mut out = if value.len() < 29 {
+        vec![0x40 | value.len() as u8]
+    } else {
+        vec![0x40 | 29, (value.len() - 29) as u8]
+    };
+    out.extend_from_slice(value.as_bytes());
+    out
+}
+
+/// A MaxMind DB unsigned integer of `type_` (5 uint16, 6 uint32, 9 uint64), big-endian
+/// without leading zero bytes.
+fn mmdb_uint(type_: u8, value: u64) -> Vec<u8> {
+    let bytes: Vec<u8> = value
+        .to_be_bytes()
+        .into_iter()
+        .skip_while(|byte| *byte == 0)
+        .collect();
+    let mut out = if type_ <= 7 {
+        vec![type_ << 5 | bytes.len() as u8]
+    } else {
+        vec![bytes.len() as u8, type_ - 7]
+    };
+    out.extend(bytes);
+    out
+}
+
+/// A MaxMind ASN database, IPv4 only, that knows nothing but `network`/24.
+fn asn_database(network: [u8; 3], asn: u32, org: &str) -> Vec<u8> {
+    const NODES: u32 = 24;
+    let mut out = Vec::new();
+    // One node per bit of the prefix; leaving it means "not found", node count, and the
+    // last points at the record at the start of the data section
+    for bit in 0..NODES {
+        let set = network[bit as usize / 8] & (0x80 >> (bit % 8)) != 0;
+        let next = if bit + 1 < NODES { bit + 1 } else { NODES + 16 };
+        let (left, right) = if set { (NODES, next) } else { (next, NODES) };
+        out.extend_from_slice(&left.to_be_bytes()[1..]);
+        out.extend_from_slice(&right.to_be_bytes()[1..]);
+    }
+    out.extend_from_slice(&[0; 16]);
+
+    out.push(0xE0 | 2);
+    out.extend(mmdb_string("autonomous_system_number"));
+    out.extend(mmdb_uint(6, asn.into()));
+    out.extend(mmdb_string("autonomous_system_organization"));
+    out.extend(mmdb_string(org));
+
+    out.extend_from_slice(b"\xAB\xCD\xEFMaxMind.com");
+    out.push(0xE0 | 9);
+    for (key, value) in [
+        ("binary_format_major_version", mmdb_uint(5, 2)),
+        ("binary_format_minor_version", mmdb_uint(5, 0)),
+        ("build_epoch", mmdb_uint(9, 1_791_972_000)),
+        ("database_type", mmdb_string("GeoLite2-ASN")),
+        ("description", vec![0xE0]),
+        ("ip_version", mmdb_uint(5, 4)),
+        ("languages", vec![0x00, 0x04]),
+        ("node_count", mmdb_uint(6, NODES.into())),
+        ("record_size", mmdb_uint(5, 24)),
+    ] {
+        out.extend(mmdb_string(key));
+        out.extend(value);
+    }
+    out
+}
+
+#[tokio::test]
+async fn keeps_clients_of_a_destination_asn_on_its_wan() {
+    let mut script = Script::two_wans();
+    // The quiet client on wan1 talks to the corporate VPN
+    script
+        .destinations
+        .push(("192.168.1.12".to_string(), "203.0.113.7".to_string(), 2e6));
+    let backends = MockBackends::start(script).await;
+    let db = std::env::temp_dir().join(format!("routingflow-asn-{}.mmdb", std::process::id()));
+    std::fs::write(&db, asn_database([203, 0, 113], 64500, "Corp VPN")).unwrap();
+    let instance = Instance::start_with(
+        &backends.config(&format!(
+            "[destinations]\nasn_db = \"{}\"\n\n\
+             [[destinations.rules]]\nname = \"corp-vpn\"\nasns = [64500]\nwan = \"wan0\"\nmin_mbps = 1.0",
+            db.display()
+        )),
+        &["--output", "json"],
+    );
+
+    let log = backends
+        .wait_for("the VPN client's switch", |log| {
+            log.moves().contains(&("192.168.1.12", "wan0"))
+        })
+        .await;
+    let (status, output) = instance.stop_with_report().await;
+    std::fs::remove_file(db).unwrap();
+    assert!(status.success());
+    // Pinned to wan0 from then on
+    assert_eq!(
+        log.moves()
+            .iter()
+            .filter(|(ip, _)| *ip == "192.168.1.12")
+            .count(),
+        1
+    );
+    let reports: Vec<Value> = output
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let usage = &reports[0]["destination_usage"][0];
+    assert_eq!(usage["wan"], "wan1");
+    assert_eq!(usage["asn"], 64500);
+    assert_eq!(usage["as_org"], "Corp VPN");
+    assert_eq!(usage["rx_bps"], 2e6);
+    let switch = reports
+        .iter()
+        .flat_map(|report| report["decisions"].as_array().unwrap())
+        .find(|decision| decision["ip"] == "192.168.1.12" && decision["outcome"] == "switched")
+        .unwrap();
+    assert_eq!(
+        switch["reason"],
+        "traffic to 203.0.113.7 (AS64500) matches destination rule corp-vpn"
+    );
+}
+
+#[tokio::test]
+async fn lets_traffic_below_the_rule_minimum_be() {
+    let mut script = Script::two_wans();
+    // Too little, too, for the policy to pick the client
+    script
+        .destinations
+        .push(("192.168.1.12".to_string(), "203.0.113.7".to_string(), 4e5));
+    let backends = MockBackends::start(script).await;
+    let db = std::env::temp_dir().join(format!(
+        "routingflow-asn-minimum-{}.mmdb",
+        std::process::id()
+    ));
+    std::fs::write(&db, asn_database([203, 0, 113], 64500, "Corp VPN")).unwrap();
+    let instance = Instance::start(&backends.config(&format!(
+        "[destinations]\nasn_db = \"{}\"\n\n\
+         [[destinations.rules]]\nname = \"corp-vpn\"\nasns = [64500]\nwan = \"wan0\"\nmin_mbps = 1.0",
+        db.display()
+    )));
+
+    let log = backends
+        .wait_for("10 cycles", |log| log.count("/status") >= 10)
+        .await;
+    assert!(instance.stop().await.success());
+    std::fs::remove_file(db).unwrap();
+    assert!(
+        log.moves().iter().all(|(ip, _)| *ip != "192.168.1.12"),
+        "{:?}",
+        log.moves()
+    );
+}
+
+#[tokio::test]
+async fn pins_every_client_of_a_destination_prefix_by_the_first_matching_rule() {
+    let mut script = Script::two_wans();
+    for client in ["192.168.1.11", "192.168.1.12"] {
+        script
+            .destinations
+            .push((client.to_string(), "198.51.100.20".to_string(), 3e5));
+    }
+    let backends = MockBackends::start(script).await;
+    let instance = Instance::start_with(
+        &backends.config(
+            "[destinations]\n\n\
+             [[destinations.rules]]\nname = \"streaming\"\nprefixes = [\"198.51.100.0/24\"]\nwan = \"wan1\"\n\n\
+             [[destinations.rules]]\nname = \"everything\"\nprefixes = [\"0.0.0.0/0\"]\nwan = \"wan0\"",
+        ),
+        &["--output", "json"],
+    );
+
+    backends
+        .wait_for("the streaming client's switch", |log| {
+            log.moves().contains(&("192.168.1.11", "wan1"))
+        })
+        .await;
+    let cycles = backends.log().count("/status");
+    let log = backends
+        .wait_for("5 more cycles", |log| log.count("/status") >= cycles + 5)
+        .await;
+    let (status, output) = instance.stop_with_report().await;
+    assert!(status.success());
+    assert!(log.moves().iter().all(|(ip, _)| *ip != "192.168.1.12"));
+    let switch = output
+        .lines()
+        .map(|line| serde_json::from_str::<Value>(line).unwrap())
+        .flat_map(|report| report["decisions"].as_array().unwrap().clone())
+        .find(|decision| decision["ip"] == "192.168.1.11" && decision["outcome"] == "switched")
+        .unwrap();
+    assert_eq!(
+        switch["reason"],
+        "traffic to 198.51.100.20 matches destination rule streaming"
+    );
+}
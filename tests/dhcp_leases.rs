@@ -0,0 +1,95 @@
+mod common;
+
+use common::{Instance, MockBackends, Script};
+use serde_json::{json, Value};
+use std::path::PathBuf;
+
+/// Writes `contents` to a lease file of this test run.
+fn lease_file(name: &str, contents: &str) -> PathBuf {
+    let path =
+        std::env::temp_dir().join(format!("routingflow-test-{}.{}", std::process::id(), name));
+    std::fs::write(&path, contents).unwrap();
+    path
+}
+
+#[tokio::test]
+async fn names_and_pins_clients_by_their_leases() {
+    // .11's dnsmasq lease ran out before the simulated clock starts; its Kea lease has not
+    let dnsmasq = lease_file(
+        "dnsmasq.leases",
+        "0 aa:bb:cc:dd:ee:01 192.168.1.10 workstation 01:aa:bb:cc:dd:ee:01\n\
+         1000 aa:bb:cc:dd:ee:99 192.168.1.11 stale *\n",
+    );
+    // .12 was declined after it was leased
+    let kea = lease_file(
+        "kea.csv",
+        "address,hwaddr,client_id,valid_lifetime,expire,subnet_id,fqdn_fwd,fqdn_rev,hostname,state,user_context\n\
+         192.168.1.11,AA-BB-CC-DD-EE-02,,3600,2000000000,1,0,0,laptop.lan.,0,\n\
+         192.168.1.12,aa:bb:cc:dd:ee:03,,3600,2000000000,1,0,0,printer,0,\n\
+         192.168.1.12,aa:bb:cc:dd:ee:03,,3600,2000000000,1,0,0,printer,1,\n",
+    );
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let instance = Instance::start_with(
+        &backends.config(&format!(
+            "[[dhcp_leases]]\npath = {:?}\nformat = \"dnsmasq\"\n\n\
+             [[dhcp_leases]]\npath = {:?}\nformat = \"kea\"\n\n\
+             [[client_rules]]\nmac = \"aa:bb:cc:dd:ee:02\"\naction = \"pin\"\nwan = \"wan1\"",
+            dnsmasq, kea
+        )),
+        &["--output", "json"],
+    );
+
+    let log = backends
+        .wait_for("a switch of .11", |log| {
+            log.switches
+                .iter()
+                .any(|switch| switch.ip == "192.168.1.11")
+        })
+        .await;
+    let (status, output) = instance.stop_with_report().await;
+    let _ = std::fs::remove_file(&dnsmasq);
+    let _ = std::fs::remove_file(&kea);
+    assert!(status.success());
+    assert!(
+        log.moves().contains(&("192.168.1.11", "wan1")),
+        "{:?}",
+        log.moves()
+    );
+    let report: Value = serde_json::from_str(output.lines().last().unwrap()).unwrap();
+    assert_eq!(
+        report["hostnames"],
+        json!({"192.168.1.10": "workstation", "192.168.1.11": "laptop.lan"})
+    );
+}
+
+#[tokio::test]
+async fn runs_without_a_missing_lease_file_that_doctor_flags() {
+    let missing = std::env::temp_dir().join("routingflow-test-missing.leases");
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let instance = Instance::start(&backends.config(&format!(
+        "[[dhcp_leases]]\npath = {:?}\nformat = \"dnsmasq\"",
+        missing
+    )));
+
+    let log = backends
+        .wait_for("a switch", |log| !log.switches.is_empty())
+        .await;
+    let output = instance.command(&["doctor"]).await;
+    assert!(instance.stop().await.success());
+    assert_eq!(log.moves()[0], ("192.168.1.10", "wan1"));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(!output.status.success());
+    assert!(
+        stdout.contains(&format!(
+            "✗ Failed to read DHCP leases from {}",
+            missing.display()
+        )),
+        "{}",
+        stdout
+    );
+    assert!(
+        stdout.contains("→ Check dhcp_leases.path and that routingFlow may read it"),
+        "{}",
+        stdout
+    );
+}
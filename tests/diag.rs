@@ -0,0 +1,67 @@
+mod common;
+
+use common::{api_config, free_addr, unpack, Api, Instance, MockBackends, Script};
+use serde_json::Value;
+
+#[tokio::test]
+async fn bundles_the_recent_cycles_without_secrets_or_addresses() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let addr = free_addr();
+    let instance = Instance::start(&backends.config(&api_config(addr)));
+    Api::connect(addr, "admin-key").await;
+    backends
+        .wait_for("a switch", |log| !log.switches.is_empty())
+        .await;
+
+    let archive = instance.path("diag.tar.gz");
+    let output = instance
+        .command(&[
+            "diag",
+            archive.to_str().unwrap(),
+            "--anonymize",
+            "--cycles",
+            "2",
+            "--api-key",
+            "admin-key",
+        ])
+        .await;
+    let files = unpack(&archive);
+    assert!(instance.stop().await.success());
+    assert!(output.status.success(), "{:?}", output);
+    let version: Value = serde_json::from_str(&files["version.json"]).unwrap();
+    assert_eq!(version["anonymized"], true);
+    assert_eq!(version["instance_error"], Value::Null);
+    let cycles: Value = serde_json::from_str(&files["cycles.json"]).unwrap();
+    assert_eq!(cycles.as_array().unwrap().len(), 2);
+    assert!(
+        files["logs.txt"].contains("Switched"),
+        "{}",
+        files["logs.txt"]
+    );
+    for name in ["cycles.json", "logs.txt", "routingflow.toml"] {
+        assert!(!files[name].contains("192.168.1."), "{}", files[name]);
+    }
+    let config = &files["routingflow.toml"];
+    assert!(!config.contains("admin-key"), "{}", config);
+    assert!(config.contains("key = \"<redacted>\""), "{}", config);
+}
+
+#[tokio::test]
+async fn bundles_the_config_without_a_running_instance() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let instance = Instance::start(&backends.config(""));
+
+    let archive = instance.path("diag.tar.gz");
+    let output = instance.command(&["diag", archive.to_str().unwrap()]).await;
+    let files = unpack(&archive);
+    assert!(instance.stop().await.success());
+    assert!(output.status.success(), "{:?}", output);
+    let version: Value = serde_json::from_str(&files["version.json"]).unwrap();
+    assert_eq!(version["anonymized"], false);
+    assert_eq!(
+        version["instance_error"],
+        "The HTTP API is disabled ([server] listen is not set)"
+    );
+    assert_eq!(files["cycles.json"].trim(), "[]");
+    assert!(files["routingflow.toml"].contains(&backends.url));
+}
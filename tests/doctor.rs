@@ -0,0 +1,60 @@
+mod common;
+
+use common::{free_addr, Instance, MockBackends, Script};
+
+/// [`Script::two_wans`] reduced to interfaces every Linux host has.
+fn loopback_only() -> Script {
+    let mut script = Script::two_wans();
+    script.lan = "lo".to_string();
+    script.wans = [("wan0".to_string(), "lo".to_string())].into();
+    script.bandwidth_bps = [("lo".to_string(), 50e6)].into();
+    script
+}
+
+#[tokio::test]
+async fn passes_against_healthy_backends() {
+    let backends = MockBackends::start(loopback_only()).await;
+    let instance = Instance::start(&backends.config(""));
+
+    let output = instance.command(&["doctor"]).await;
+    assert!(instance.stop().await.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{}", stdout);
+    assert!(
+        stdout.contains("✓ /status answers: LAN lo, wan0 lo, 3 mapped clients"),
+        "{}",
+        stdout
+    );
+    assert!(stdout.contains("✓ LAN interface lo exists"), "{}", stdout);
+    assert!(stdout.contains("✓ wan0 interface lo exists"), "{}", stdout);
+    assert!(!stdout.contains('✗'), "{}", stdout);
+}
+
+#[tokio::test]
+async fn suggests_a_fix_for_every_problem() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    // Nothing listens where the routing service should be
+    let config = backends.config("").replace(
+        &format!("[routing_service]\nurl = \"{}\"", backends.url),
+        &format!("[routing_service]\nurl = \"http://{}\"", free_addr()),
+    );
+    let instance = Instance::start(&config);
+
+    let output = instance.command(&["doctor"]).await;
+    assert!(instance.stop().await.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(stdout.contains("✗ /status failed"), "{}", stdout);
+    assert!(
+        stdout.contains("→ Check routing_service.url and that the routing service is running"),
+        "{}",
+        stdout
+    );
+    assert!(
+        stdout.contains("(skipped: interface names come from the routing service)"),
+        "{}",
+        stdout
+    );
+    assert!(stderr.contains("problem(s) and"), "{}", stderr);
+}
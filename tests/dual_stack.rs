@@ -0,0 +1,61 @@
+mod common;
+
+use common::{Instance, MockBackends, Script};
+
+/// `192.168.1.10` and `fd00::10` as one device in the dnsmasq lease file `name`.
+fn dual_stack(name: &str) -> (Script, String) {
+    let leases = std::env::temp_dir().join(format!(
+        "routingflow-test-{}-{}.leases",
+        std::process::id(),
+        name
+    ));
+    std::fs::write(
+        &leases,
+        "0 aa:bb:cc:00:00:10 192.168.1.10 laptop *\n0 aa:bb:cc:00:00:10 fd00::10 laptop *\n",
+    )
+    .unwrap();
+    let mut script = Script::two_wans();
+    script
+        .mappings
+        .insert("fd00::10".to_string(), "wan0".to_string());
+    let config = format!(
+        "[dual_stack]\n\n[[dhcp_leases]]\npath = {:?}\nformat = \"dnsmasq\"\n\n",
+        leases
+    );
+    (script, config)
+}
+
+#[tokio::test]
+async fn moves_the_other_addresses_of_a_device_with_it() {
+    let (script, config) = dual_stack("follow");
+    let backends = MockBackends::start(script).await;
+    let instance = Instance::start(&backends.config(&config));
+
+    let log = backends
+        .wait_for("two switches", |log| log.switches.len() >= 2)
+        .await;
+    assert!(instance.stop().await.success());
+    assert_eq!(
+        log.moves()[..2],
+        [("192.168.1.10", "wan1"), ("fd00::10", "wan1")]
+    );
+    assert_eq!(log.switches[0].cycle, log.switches[1].cycle);
+}
+
+#[tokio::test]
+async fn counts_the_other_addresses_against_the_switch_rate_limit() {
+    let (script, config) = dual_stack("rate-limit");
+    let backends = MockBackends::start(script).await;
+    let instance = Instance::start(&backends.config(&format!(
+        "{}[cooldown]\ndefault_secs = 600\n\n[switch_rate_limit]\nmax_per_minute = 1\n",
+        config
+    )));
+
+    let log = backends
+        .wait_for("two switches", |log| log.switches.len() >= 2)
+        .await;
+    assert!(instance.stop().await.success());
+    assert_eq!(log.moves()[1], ("fd00::10", "wan1"));
+    let (first, second) = (log.switches[0].cycle, log.switches[1].cycle);
+    assert!(second >= first + 60, "{} then {}", first, second);
+}
@@ -0,0 +1,115 @@
+mod common;
+
+use common::{Instance, MockBackends, Script};
+use serde_json::Value;
+
+/// The harness's config with three attempts per call and next to no backoff.
+fn retrying(backends: &MockBackends) -> String {
+    backends.config("").replace(
+        "max_attempts = 1",
+        "max_attempts = 3\ninitial_backoff_ms = 1",
+    )
+}
+
+/// Switch requests in the first cycle that made any, once the next cycle has started.
+async fn attempts(backends: &MockBackends) -> usize {
+    let log = backends
+        .wait_for("the cycle after a switch", |log| {
+            log.switches
+                .first()
+                .is_some_and(|switch| log.count("/status") > switch.cycle)
+        })
+        .await;
+    let cycle = log.switches[0].cycle;
+    log.switches
+        .iter()
+        .filter(|switch| switch.cycle == cycle)
+        .count()
+}
+
+#[tokio::test]
+async fn retries_a_switch_the_service_failed_to_make() {
+    let mut script = Script::two_wans();
+    script.fail_switch = true;
+    let backends = MockBackends::start(script).await;
+    let instance = Instance::start(&retrying(&backends));
+
+    let attempts = attempts(&backends).await;
+    assert!(instance.stop().await.success());
+    assert_eq!(attempts, 3);
+}
+
+#[tokio::test]
+async fn gives_up_on_a_switch_the_service_rejected() {
+    let mut script = Script::two_wans();
+    script.fail_switch = true;
+    script.switch_error = (400, None);
+    let backends = MockBackends::start(script).await;
+    let instance = Instance::start(&retrying(&backends));
+
+    let attempts = attempts(&backends).await;
+    assert!(instance.stop().await.success());
+    assert_eq!(attempts, 1);
+}
+
+/// Delays of the retries the instance logged, in milliseconds.
+fn delays(log: &str) -> Vec<u64> {
+    log.lines()
+        .map(|line| serde_json::from_str::<Value>(line).unwrap())
+        .filter_map(|line| line["delay_ms"].as_u64())
+        .collect()
+}
+
+#[tokio::test]
+async fn waits_as_long_as_a_rate_limited_service_asks() {
+    let mut script = Script::two_wans();
+    script.fail_switch = true;
+    script.switch_error = (429, Some(1));
+    let backends = MockBackends::start(script).await;
+    let instance = Instance::start(&format!(
+        "[logging]\nformat = \"json\"\n\n{}",
+        retrying(&backends)
+    ));
+
+    let attempts = attempts(&backends).await;
+    let (status, log) = instance.stop_with_log().await;
+    assert!(status.success());
+    assert_eq!(attempts, 3);
+    assert_eq!(delays(&log)[..2], [1000, 1000], "{}", log);
+}
+
+#[tokio::test]
+async fn gives_up_on_a_pause_longer_than_the_longest_backoff() {
+    let mut script = Script::two_wans();
+    script.fail_switch = true;
+    script.switch_error = (429, Some(60));
+    let backends = MockBackends::start(script).await;
+    let instance = Instance::start(&format!(
+        "[logging]\nformat = \"json\"\n\n{}",
+        retrying(&backends)
+    ));
+
+    let attempts = attempts(&backends).await;
+    let (status, log) = instance.stop_with_log().await;
+    assert!(status.success());
+    assert_eq!(attempts, 1);
+    assert!(delays(&log).is_empty(), "{}", log);
+}
+
+#[tokio::test]
+async fn keeps_running_through_a_failing_status_endpoint() {
+    let mut script = Script::two_wans();
+    script.fail_status = true;
+    let backends = MockBackends::start(script).await;
+    let instance = Instance::start(&retrying(&backends));
+
+    backends
+        .wait_for("3 failed attempts", |log| log.count("/status") >= 3)
+        .await;
+    backends.update(|script| script.fail_status = false);
+    let log = backends
+        .wait_for("a switch", |log| !log.switches.is_empty())
+        .await;
+    assert!(instance.stop().await.success());
+    assert_eq!(log.moves()[0], ("192.168.1.10", "wan1"));
+}
@@ -0,0 +1,95 @@
+mod common;
+
+use common::{Instance, MockBackends, Script};
+use serde_json::Value;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+
+#[tokio::test]
+async fn posts_events_to_a_kafka_topic() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let instance = Instance::start(&backends.config(&format!(
+        "[events.kafka]\nrest_proxy_url = \"{}/\"\ntopic = \"routingflow-events\"",
+        backends.url
+    )));
+
+    let log = backends
+        .wait_for("a switch record", |log| {
+            log.notifications
+                .iter()
+                .any(|message| message.body["records"][0]["key"] == "switch")
+        })
+        .await;
+    assert!(instance.stop().await.success());
+    let message = log
+        .notifications
+        .iter()
+        .find(|message| message.body["records"][0]["key"] == "switch")
+        .unwrap();
+    assert_eq!(message.path, "/topics/routingflow-events");
+    let event = &message.body["records"][0]["value"];
+    assert_eq!(event["type"], "switch");
+    assert_eq!(event["ip"], "192.168.1.10");
+    assert_eq!(event["target_wan"], "wan1");
+}
+
+/// Subjects and payloads of the messages published to a NATS server on the returned
+/// address, which greets the first client and then only reads.
+async fn nats_server() -> (String, Arc<Mutex<Vec<(String, Value)>>>) {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    let published = Arc::new(Mutex::new(Vec::new()));
+    let sink = published.clone();
+    tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let (reader, mut writer) = stream.into_split();
+        writer.write_all(b"INFO {}\r\n").await.unwrap();
+        let mut reader = BufReader::new(reader);
+        let mut line = String::new();
+        while reader.read_line(&mut line).await.unwrap_or(0) > 0 {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            if let ["PUB", subject, len] = words[..] {
+                let mut payload = vec![0; len.parse::<usize>().unwrap() + 2];
+                reader.read_exact(&mut payload).await.unwrap();
+                let payload = serde_json::from_slice(&payload[..payload.len() - 2]).unwrap();
+                sink.lock().unwrap().push((subject.to_string(), payload));
+            }
+            line.clear();
+        }
+    });
+    (address, published)
+}
+
+#[tokio::test]
+async fn publishes_events_to_nats_subjects() {
+    let (address, published) = nats_server().await;
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let instance = Instance::start(&backends.config(&format!(
+        "[events.nats]\naddress = \"{}\"\nsubject_prefix = \"edge\"",
+        address
+    )));
+
+    backends
+        .wait_for("a switch", |log| !log.switches.is_empty())
+        .await;
+    let deadline = tokio::time::Instant::now() + std::time::Duration::from_secs(30);
+    let event = loop {
+        let switch = published
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|(subject, _)| subject == "edge.switch")
+            .map(|(_, payload)| payload.clone());
+        if let Some(event) = switch {
+            break event;
+        }
+        assert!(
+            tokio::time::Instant::now() < deadline,
+            "no switch published"
+        );
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    };
+    assert!(instance.stop().await.success());
+    assert_eq!(event["ip"], "192.168.1.10");
+    assert_eq!(event["target_wan"], "wan1");
+}
@@ -0,0 +1,89 @@
+mod common;
+
+use common::{api_config, free_addr, Api, Instance, MockBackends, Script};
+use serde_json::Value;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+/// [`Script::two_wans`] with the busy client quiet until a subscriber is listening.
+fn quiet() -> Script {
+    let mut script = Script::two_wans();
+    script
+        .traffic_bps
+        .insert("192.168.1.10".to_string(), (5e5, 5e4));
+    script
+}
+
+/// `/events` of the instance at `addr`, opened with `key`.
+async fn subscribe(addr: SocketAddr, key: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .get(format!("http://{}/events", addr))
+        .header("x-api-key", key)
+        .send()
+        .await
+        .unwrap()
+}
+
+/// Reads `response` until an event named `name` has arrived and returns its data.
+async fn next_event(response: &mut reqwest::Response, name: &str) -> Value {
+    let mut received = String::new();
+    let wanted = format!("event: {}\n", name);
+    let read = async {
+        loop {
+            if let Some(start) = received.find(&wanted) {
+                let rest = &received[start + wanted.len()..];
+                if let Some(end) = rest.find("\n\n") {
+                    let data = rest[..end].strip_prefix("data: ").unwrap();
+                    return serde_json::from_str(data).unwrap();
+                }
+            }
+            let chunk = response.chunk().await.unwrap().expect("stream ended");
+            received.push_str(&String::from_utf8_lossy(&chunk));
+        }
+    };
+    tokio::time::timeout(Duration::from_secs(30), read)
+        .await
+        .expect("no such event")
+}
+
+#[tokio::test]
+async fn streams_switch_events_to_a_subscriber() {
+    let backends = MockBackends::start(quiet()).await;
+    let addr = free_addr();
+    let instance = Instance::start(&backends.config(&api_config(addr)));
+    let api = Api::connect(addr, "admin-key").await;
+
+    let mut response = subscribe(addr, "admin-key").await;
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.headers()["content-type"], "text/event-stream");
+    let (_, metrics) = api.get("/metrics").await;
+    assert!(
+        metrics.contains("routingflow_event_stream_clients 1"),
+        "{}",
+        metrics
+    );
+    backends.update(|script| {
+        script
+            .traffic_bps
+            .insert("192.168.1.10".to_string(), (20e6, 2e6));
+    });
+
+    let event = next_event(&mut response, "switch").await;
+    assert!(instance.stop().await.success());
+    assert_eq!(event["type"], "switch");
+    assert_eq!(event["ip"], "192.168.1.10");
+    assert_eq!(event["target_wan"], "wan1");
+    assert_eq!(event["success"], true);
+}
+
+#[tokio::test]
+async fn refuses_a_subscriber_without_a_key() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let addr = free_addr();
+    let instance = Instance::start(&backends.config(&api_config(addr)));
+    Api::connect(addr, "admin-key").await;
+
+    let response = subscribe(addr, "wrong-key").await;
+    assert!(instance.stop().await.success());
+    assert_eq!(response.status(), 401);
+}
@@ -0,0 +1,58 @@
+mod common;
+
+use common::{Instance, MockBackends, Script};
+
+#[tokio::test]
+async fn exports_nic_stats_and_switches_as_csv() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let instance = Instance::start(&backends.config("[history]\nnic_stats_interval_secs = 5"));
+    backends
+        .wait_for("12 cycles", |log| log.count("/status") >= 12)
+        .await;
+
+    let output = instance
+        .command(&["export", "--format", "csv", "-o", "export.csv"])
+        .await;
+    let csv = std::fs::read_to_string(instance.path("export.csv")).unwrap();
+    assert!(instance.stop().await.success());
+    assert!(output.status.success(), "{:?}", output);
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(
+        lines[0],
+        "timestamp,time,kind,nic,wan,interval_secs,tcp_bandwidth_bps,tx_bps,rx_bps,clients,ip,from_wan,to_wan,result,reason,error"
+    );
+    // The switch stays after the NIC stats of its interval
+    assert_eq!(
+        lines[3],
+        "1791972000,2026-10-14T10:00:00+00:00,switch,,,,,,,,192.168.1.10,wan0,wan1,success,top RX IP on eth0; wan1 has the most headroom (199.45 Mbps free),"
+    );
+    // Averages over the interval after the switch, .10 on wan1 throughout
+    assert!(
+        lines.contains(&"1791972005,2026-10-14T10:00:05+00:00,nic_stats,eth0,wan0,5,50000000,50000,500000,1,,,,,,"),
+        "{}",
+        csv
+    );
+    assert!(
+        lines.contains(&"1791972005,2026-10-14T10:00:05+00:00,nic_stats,eth1,wan1,5,200000000,2050000,20500000,2,,,,,,"),
+        "{}",
+        csv
+    );
+}
+
+#[tokio::test]
+async fn fails_without_a_database() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let instance = Instance::start(&backends.config("[history]\nenabled = false"));
+    backends
+        .wait_for("a switch", |log| !log.switches.is_empty())
+        .await;
+
+    let output = instance.command(&["export"]).await;
+    assert!(instance.stop().await.success());
+    assert!(!output.status.success());
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("No switch history database"),
+        "{:?}",
+        output
+    );
+}
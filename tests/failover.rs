@@ -0,0 +1,75 @@
+mod common;
+
+use common::{Instance, MockBackends, Script};
+
+/// [`Script::two_wans`] without the busy client, so that only failover moves anyone.
+fn quiet() -> Script {
+    let mut script = Script::two_wans();
+    script
+        .traffic_bps
+        .insert("192.168.1.10".to_string(), (5e5, 5e4));
+    script
+}
+
+/// Takes eth1's bandwidth series away until wan1's client has been evacuated, and returns
+/// the cycle they come back in.
+async fn fail_wan1(backends: &MockBackends) -> usize {
+    backends
+        .wait_for("3 cycles", |log| log.count("/status") >= 3)
+        .await;
+    backends.update(|script| {
+        script.bandwidth_bps.remove("eth1");
+    });
+    let log = backends
+        .wait_for("an evacuation", |log| !log.switches.is_empty())
+        .await;
+    assert_eq!(log.moves(), [("192.168.1.12", "wan0")]);
+    backends.update(|script| {
+        script.bandwidth_bps.insert("eth1".to_string(), 200e6);
+    });
+    backends.log().count("/status")
+}
+
+#[tokio::test]
+async fn evacuates_a_dead_wan_and_fails_back_after_recovery() {
+    let backends = MockBackends::start(quiet()).await;
+    // Fail-backs wait out cooldowns too; only the recovery time is measured here
+    let instance = Instance::start(&backends.config(
+        "[cooldown]\ndefault_secs = 1\n\n[failover]\nstale_after_secs = 100000\nrecovery_secs = 5",
+    ));
+
+    let recovered = fail_wan1(&backends).await;
+    let log = backends
+        .wait_for("a failback", |log| log.switches.len() >= 2)
+        .await;
+    assert!(instance.stop().await.success());
+    assert_eq!(
+        log.moves(),
+        [("192.168.1.12", "wan0"), ("192.168.1.12", "wan1")]
+    );
+    let failback = log.switches[1].cycle;
+    assert!(
+        (recovered + 5..=recovered + 8).contains(&failback),
+        "back in cycle {}, failed back in {}",
+        recovered,
+        failback
+    );
+}
+
+#[tokio::test]
+async fn leaves_evacuated_clients_without_failback() {
+    let backends = MockBackends::start(quiet()).await;
+    let instance = Instance::start(
+        &backends
+            .config("[failover]\nstale_after_secs = 100000\nrecovery_secs = 0\nfailback = false"),
+    );
+
+    let recovered = fail_wan1(&backends).await;
+    let log = backends
+        .wait_for("10 more cycles", |log| {
+            log.count("/status") >= recovered + 10
+        })
+        .await;
+    assert!(instance.stop().await.success());
+    assert_eq!(log.moves(), [("192.168.1.12", "wan0")]);
+}
@@ -0,0 +1,63 @@
+mod common;
+
+use common::{api_config, free_addr, Api, Instance, MockBackends, Script};
+
+/// `wan0` at 25 of 50 Mbps and `wan1` at 50 of 200 Mbps, with every client excluded so
+/// nothing moves: utilizations 0.5 and 0.25, Jain's index 0.75² / (2 × 0.3125) = 0.9.
+#[tokio::test]
+async fn reports_jains_index_and_the_max_min_ratio() {
+    let mut script = Script::two_wans();
+    script.traffic_bps = [
+        ("192.168.1.10", (20e6, 0.0)),
+        ("192.168.1.11", (5e6, 0.0)),
+        ("192.168.1.12", (50e6, 0.0)),
+    ]
+    .into_iter()
+    .map(|(ip, traffic)| (ip.to_string(), traffic))
+    .collect();
+    let backends = MockBackends::start(script).await;
+    let addr = free_addr();
+    let instance = Instance::start(&backends.config(&format!(
+        "excluded_ips = [\"192.168.1.10\", \"192.168.1.11\", \"192.168.1.12\"]\n\n{}",
+        api_config(addr)
+    )));
+    let api = Api::connect(addr, "admin-key").await;
+
+    let state = api
+        .wait_for_state("a cycle", |state| !state["cycle"].is_null())
+        .await;
+    let (_, metrics) = api.get("/metrics").await;
+    assert!(instance.stop().await.success());
+    let fairness = &state["cycle"]["fairness"];
+    assert_eq!(fairness["utilizations"]["wan0"], 0.5);
+    assert_eq!(fairness["utilizations"]["wan1"], 0.25);
+    assert!((fairness["jain_index"].as_f64().unwrap() - 0.9).abs() < 1e-9);
+    assert_eq!(fairness["max_min_ratio"], 2.0);
+    assert!(metrics
+        .lines()
+        .any(|line| line.starts_with("routingflow_fairness_jain_index 0.9")));
+}
+
+#[tokio::test]
+async fn an_idle_wan_has_no_max_min_ratio() {
+    let mut script = Script::two_wans();
+    script.traffic_bps.remove("192.168.1.12");
+    script.mappings.remove("192.168.1.12");
+    let backends = MockBackends::start(script).await;
+    let addr = free_addr();
+    let instance = Instance::start(&backends.config(&format!(
+        "excluded_ips = [\"192.168.1.10\", \"192.168.1.11\"]\n\n{}",
+        api_config(addr)
+    )));
+    let api = Api::connect(addr, "admin-key").await;
+
+    let state = api
+        .wait_for_state("a cycle", |state| !state["cycle"].is_null())
+        .await;
+    assert!(instance.stop().await.success());
+    let fairness = &state["cycle"]["fairness"];
+    assert_eq!(fairness["utilizations"]["wan1"], 0.0);
+    // The worst case for two WANs
+    assert_eq!(fairness["jain_index"], 0.5);
+    assert!(fairness["max_min_ratio"].is_null());
+}
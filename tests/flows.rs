@@ -0,0 +1,113 @@
+mod common;
+
+use common::{api_config, free_addr, Api, Instance, MockBackends, Script};
+use serde_json::Value;
+
+#[tokio::test]
+async fn moves_the_heaviest_flows_instead_of_the_client() {
+    let mut script = Script::two_wans();
+    script.flows = vec![
+        ("192.168.1.10".to_string(), 51000, 15e6),
+        ("192.168.1.10".to_string(), 51001, 3e6),
+        ("192.168.1.10".to_string(), 51002, 2e5),
+    ];
+    let backends = MockBackends::start(script).await;
+    let instance = Instance::start(&backends.config("[flows]"));
+
+    let log = backends
+        .wait_for("two flow switches", |log| log.switches.len() >= 2)
+        .await;
+    assert!(instance.stop().await.success());
+    let flows: Vec<_> = log.switches[..2]
+        .iter()
+        .map(|switch| (switch.ip.as_str(), switch.wan.as_str(), switch.port))
+        .collect();
+    assert_eq!(
+        flows,
+        [
+            ("192.168.1.10", "wan1", Some(51000)),
+            ("192.168.1.10", "wan1", Some(51001)),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn reports_and_records_flow_moves_like_client_moves() {
+    let mut script = Script::two_wans();
+    script.flows = vec![("192.168.1.10".to_string(), 51000, 15e6)];
+    let backends = MockBackends::start(script).await;
+    let addr = free_addr();
+    let instance = Instance::start(&backends.config(&format!(
+        "[flows]\n\n[verification]\ndelay_ms = 0\n\n[[events.webhooks]]\nurl = \"{}/hook\"\n\n{}",
+        backends.url,
+        api_config(addr)
+    )));
+    let api = Api::connect(addr, "admin-key").await;
+
+    let log = backends
+        .wait_for("a switch event", |log| {
+            log.notifications
+                .iter()
+                .any(|event| event.body["type"] == "switch")
+        })
+        .await;
+    let event = &log
+        .notifications
+        .iter()
+        .find(|event| event.body["type"] == "switch")
+        .unwrap()
+        .body;
+    assert_eq!(event["ip"], "192.168.1.10");
+    assert_eq!(event["target_wan"], "wan1");
+    assert!(event["reason"]
+        .as_str()
+        .unwrap()
+        .ends_with("(flows 51000/tcp)"));
+
+    // Verified after the cycle's switches
+    let record = loop {
+        let (_, history) = api.get("/history").await;
+        let history: Vec<Value> = serde_json::from_str(&history).unwrap();
+        match history.last() {
+            Some(record) if !record["verification"].is_null() => break record.clone(),
+            _ => tokio::time::sleep(std::time::Duration::from_millis(20)).await,
+        }
+    };
+    assert!(instance.stop().await.success());
+    assert_eq!(record["result"], "success");
+    assert_eq!(record["verification"], "unverified");
+    assert_eq!(log.switches[0].port, Some(51000));
+}
+
+#[tokio::test]
+async fn moves_a_client_without_flow_rates_whole() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let instance = Instance::start(&backends.config("[flows]"));
+
+    let log = backends
+        .wait_for("a switch", |log| !log.switches.is_empty())
+        .await;
+    assert!(instance.stop().await.success());
+    assert_eq!(log.switches[0].port, None);
+    assert_eq!(log.moves()[0], ("192.168.1.10", "wan1"));
+}
+
+/// The fakes' NIC totals follow the mappings, so moving a flow never relieves `eth0`.
+#[tokio::test]
+async fn moves_the_flows_back_when_moving_them_did_not_help() {
+    let mut script = Script::two_wans();
+    script.flows = vec![("192.168.1.10".to_string(), 51000, 15e6)];
+    let backends = MockBackends::start(script).await;
+    let instance = Instance::start(&backends.config("[flows]\n\n[rollback]\ngrace_secs = 10"));
+
+    let log = backends
+        .wait_for("two flow switches", |log| log.switches.len() >= 2)
+        .await;
+    assert!(instance.stop().await.success());
+    let flows: Vec<_> = log.switches[..2]
+        .iter()
+        .map(|switch| (switch.wan.as_str(), switch.port))
+        .collect();
+    assert_eq!(flows, [("wan1", Some(51000)), ("wan0", Some(51000))]);
+    assert!(log.switches[1].cycle >= log.switches[0].cycle + 10);
+}
@@ -0,0 +1,77 @@
+mod common;
+
+use common::{api_config, free_addr, Api, Instance, MockBackends, Script};
+use prost::Message;
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct GetNicStatsResponse {
+    #[prost(uint64, tag = "1")]
+    timestamp: u64,
+    #[prost(message, repeated, tag = "2")]
+    nics: Vec<NicStats>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct NicStats {
+    #[prost(string, tag = "1")]
+    nic: String,
+    #[prost(string, tag = "2")]
+    wan: String,
+}
+
+/// Calls a unary RPC with an empty request over prior-knowledge HTTP/2.
+async fn call(addr: std::net::SocketAddr, method: &str, key: &str) -> reqwest::Response {
+    reqwest::Client::builder()
+        .http2_prior_knowledge()
+        .build()
+        .unwrap()
+        .post(format!(
+            "http://{}/routingflow.v1.RoutingFlow/{}",
+            addr, method
+        ))
+        .header("content-type", "application/grpc")
+        .header("te", "trailers")
+        .header("x-api-key", key)
+        .body(vec![0u8, 0, 0, 0, 0])
+        .send()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn serves_grpc_over_http2() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let (addr, grpc) = (free_addr(), free_addr());
+    let config = format!(
+        "{}\n[server.grpc]\nlisten = \"{}\"\n",
+        api_config(addr),
+        grpc
+    );
+    let instance = Instance::start(&backends.config(&config));
+    Api::connect(addr, "admin-key").await;
+    backends
+        .wait_for("two cycles", |log| log.count("/status") >= 2)
+        .await;
+
+    let response = call(grpc, "GetNicStats", "admin-key").await;
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.headers()["content-type"], "application/grpc");
+    let body = response.bytes().await.unwrap();
+    assert_eq!(body[0], 0, "uncompressed");
+    let length = u32::from_be_bytes([body[1], body[2], body[3], body[4]]) as usize;
+    let stats = GetNicStatsResponse::decode(&body[5..5 + length]).unwrap();
+    let mut wans: Vec<(&str, &str)> = stats
+        .nics
+        .iter()
+        .map(|nic| (nic.wan.as_str(), nic.nic.as_str()))
+        .collect();
+    wans.sort();
+    assert_eq!(wans, [("wan0", "eth0"), ("wan1", "eth1")]);
+
+    // Errors before any message are trailers-only, in the headers
+    let response = call(grpc, "GetNicStats", "wrong-key").await;
+    assert_eq!(response.headers()["grpc-status"], "16");
+    let response = call(grpc, "Reboot", "admin-key").await;
+    assert_eq!(response.headers()["grpc-status"], "12");
+    assert!(instance.stop().await.success());
+}
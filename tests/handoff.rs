@@ -0,0 +1,206 @@
+mod common;
+
+use common::{api_config, free_addr, Api, Instance, MockBackends, Script};
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::UnixListener;
+
+/// The lines the instances logged with `message`.
+fn logged(log: &str, message: &str) -> Vec<Value> {
+    log.lines()
+        .map(|line| serde_json::from_str::<Value>(line).unwrap())
+        .filter(|line| line["message"] == message)
+        .collect()
+}
+
+#[tokio::test]
+async fn hands_its_history_and_pause_to_the_new_instance() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let addr = free_addr();
+    let instance = Instance::start(&backends.config(&format!(
+        "{}\n[handoff]\nsocket = \"control.sock\"\ntimeout_secs = 10\n\n[logging]\nformat = \"json\"",
+        api_config(addr)
+    )));
+    let api = Api::connect(addr, "admin-key").await;
+    backends
+        .wait_for("a switch", |log| !log.switches.is_empty())
+        .await;
+    let (status, body) = api.post("/pause", json!({"duration_secs": 3600})).await;
+    assert!(status < 300, "{} {}", status, body);
+    api.wait_for_state("the pause", |state| !state["paused"].is_null())
+        .await;
+
+    let (status, instance) = instance.hand_over().await;
+    assert!(status.success());
+    // The new instance serves the API on the same address, still paused
+    let api = Api::connect(addr, "admin-key").await;
+    let state = api
+        .wait_for_state("a cycle of the new instance", |state| {
+            !state["cycle"].is_null()
+        })
+        .await;
+    let cycles = backends.log().count("/status");
+    let log = backends
+        .wait_for("10 more cycles", |log| log.count("/status") >= cycles + 10)
+        .await;
+    let (status, output) = instance.stop_with_log().await;
+    assert!(status.success());
+
+    assert!(!state["paused"].is_null(), "{}", state);
+    assert_eq!(log.moves(), [("192.168.1.10", "wan1")]);
+    assert_eq!(logged(&output, "Handed over to the new instance").len(), 1);
+    let took_over = &logged(&output, "Took over from the previous instance")[0];
+    assert_eq!(took_over["switches"], 1);
+    assert_eq!(took_over["version"], env!("CARGO_PKG_VERSION"));
+}
+
+#[tokio::test]
+async fn refuses_to_take_over_when_nothing_runs() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let instance = Instance::start(&backends.config(""));
+    // Nothing listens on this socket; the running instance has no [handoff]
+    let config = std::fs::read_to_string(instance.path("routingflow.toml")).unwrap();
+    std::fs::write(
+        instance.path("successor.toml"),
+        format!("[handoff]\nsocket = \"control.sock\"\n\n{}", config),
+    )
+    .unwrap();
+
+    let output = instance
+        .command(&["--config", "successor.toml", "run", "--take-over"])
+        .await;
+    assert!(instance.stop().await.success());
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("No running instance to take over from at control.sock"),
+        "{}",
+        stderr
+    );
+}
+
+#[tokio::test]
+async fn hands_its_averages_breaker_and_last_mappings_to_the_new_instance() {
+    let mut script = Script::two_wans();
+    script.fail_switch = true;
+    let backends = MockBackends::start(script).await;
+    let addr = free_addr();
+    let instance = Instance::start_with(
+        &backends.config(&format!(
+            "[handoff]\nsocket = \"control.sock\"\ntimeout_secs = 10\n\n[smoothing]\nalpha = 0.1\n\n[circuit_breaker]\nfailure_threshold = 2\nopen_secs = 600\n\n{}",
+            api_config(addr)
+        )),
+        &["--output", "json"],
+    );
+    let api = Api::connect(addr, "admin-key").await;
+    backends
+        .wait_for("two failed switches", |log| log.switches.len() >= 2)
+        .await;
+    // Only the last good mappings to go on, and a jump the average has yet to follow
+    backends.update(|script| {
+        script.fail_status = true;
+        script
+            .traffic_bps
+            .insert("192.168.1.12".to_string(), (4e6, 5e4));
+    });
+    api.wait_for_state("backend_degraded mode", |state| {
+        state["mode"]["mode"] == "backend_degraded"
+    })
+    .await;
+
+    let (status, instance) = instance.hand_over().await;
+    assert!(status.success());
+    let api = Api::connect(addr, "admin-key").await;
+    let state = api
+        .wait_for_state("a cycle of the new instance", |state| {
+            !state["cycle"].is_null()
+        })
+        .await;
+    let (status, output) = instance.stop_with_report().await;
+    assert!(status.success());
+
+    assert_eq!(state["mode"]["mode"], "backend_degraded", "{}", state);
+    assert_eq!(state["mode"]["skipping_cycles"], false, "{}", state);
+    // Both instances report to the same file; the new one's clock starts over
+    let reports: Vec<Value> = output
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let first = reports
+        .windows(2)
+        .find(|pair| pair[1]["timestamp"].as_u64() <= pair[0]["timestamp"].as_u64())
+        .map(|pair| &pair[1])
+        .unwrap();
+    assert_eq!(first["switch_circuit"]["state"], "open", "{}", first);
+    assert_eq!(first["switch_circuit"]["consecutive_failures"], 2);
+    let rx = first["top_ips"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|top| top["ip"] == "192.168.1.12")
+        .map(|top| top["rx_bps"].as_f64().unwrap())
+        .unwrap();
+    assert!(rx > 5e5 && rx < 4e6, "{}", rx);
+    assert_eq!(backends.log().switches.len(), 2);
+}
+
+#[tokio::test]
+async fn keeps_from_the_snapshot_what_an_older_version_does_not_hand_over() {
+    let mut script = Script::two_wans();
+    script.fail_switch = true;
+    let backends = MockBackends::start(script).await;
+    let instance = Instance::start_with(
+        &backends.config("[handoff]\nsocket = \"control.sock\"\ntimeout_secs = 10\n\n[saved_state]\npath = \"state.json\"\n\n[circuit_breaker]\nfailure_threshold = 2\nopen_secs = 600"),
+        &["--output", "json"],
+    );
+    backends
+        .wait_for("two failed switches", |log| log.switches.len() >= 2)
+        .await;
+    // Only the open breaker holds the switch back now
+    backends.update(|script| script.fail_switch = false);
+
+    // Stand in for a version that hands over neither averages nor breaker; the instance
+    // snapshots them as it stops
+    let socket = instance.path("control.sock");
+    std::fs::remove_file(&socket).unwrap();
+    let listener = UnixListener::bind(&socket).unwrap();
+    let pid = instance.pid();
+    tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = BufReader::new(stream);
+        let mut line = String::new();
+        stream.read_line(&mut line).await.unwrap();
+        let state = json!({
+            "pid": pid,
+            "version": "0.0.1",
+            "switch_history": [],
+            "manual_pins": [],
+            "pause": null,
+            "failover": null,
+            "soft_start_since": null,
+        });
+        let mut stream = stream.into_inner();
+        stream
+            .write_all(format!("{}\n", state).as_bytes())
+            .await
+            .unwrap();
+        unsafe {
+            libc::kill(pid as libc::pid_t, libc::SIGINT);
+        }
+    });
+
+    let (status, instance) = instance.hand_over().await;
+    assert!(status.success());
+    let cycles = backends.log().count("/status");
+    let log = backends
+        .wait_for("5 cycles of the new instance", |log| {
+            log.count("/status") >= cycles + 5
+        })
+        .await;
+    let (status, output) = instance.stop_with_report().await;
+    assert!(status.success());
+    assert_eq!(log.switches.len(), 2);
+    let last: Value = serde_json::from_str(output.lines().last().unwrap()).unwrap();
+    assert_eq!(last["switch_circuit"]["state"], "open", "{}", last);
+    assert_eq!(last["switch_circuit"]["consecutive_failures"], 2);
+}
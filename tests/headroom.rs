@@ -0,0 +1,66 @@
+mod common;
+
+use common::{api_config, free_addr, Api, Instance, MockBackends, Script};
+use serde_json::Value;
+
+/// [`Script::two_wans`] with a 190 Mbps download on wan1, which leaves it less than
+/// 10 Mbps of its 200.
+fn busy_wan1() -> Script {
+    let mut script = Script::two_wans();
+    script
+        .traffic_bps
+        .insert("192.168.1.13".to_string(), (190e6, 0.0));
+    script
+        .mappings
+        .insert("192.168.1.13".to_string(), "wan1".to_string());
+    script
+}
+
+#[tokio::test]
+async fn prefers_free_headroom_over_raw_bandwidth() {
+    let mut script = busy_wan1();
+    script.wans.insert("wan2".to_string(), "eth3".to_string());
+    script.bandwidth_bps.insert("eth3".to_string(), 100e6);
+    let backends = MockBackends::start(script).await;
+    let instance = Instance::start(&backends.config(""));
+
+    let log = backends
+        .wait_for("a switch", |log| !log.switches.is_empty())
+        .await;
+    assert!(instance.stop().await.success());
+    assert_eq!(log.moves()[0], ("192.168.1.10", "wan2"));
+}
+
+#[tokio::test]
+async fn refuses_a_move_that_would_not_fit() {
+    let backends = MockBackends::start(busy_wan1()).await;
+    let addr = free_addr();
+    let instance = Instance::start(&backends.config(&api_config(addr)));
+    let api = Api::connect(addr, "admin-key").await;
+
+    let skipped = |state: &Value| {
+        state["cycle"]["decisions"]
+            .as_array()
+            .and_then(|decisions| {
+                decisions
+                    .iter()
+                    .find(|decision| decision["ip"] == "192.168.1.10")
+            })
+            .cloned()
+    };
+    let state = api
+        .wait_for_state("a decision", |state| skipped(state).is_some())
+        .await;
+    let cycles = backends.log().count("/status");
+    let log = backends
+        .wait_for("10 more cycles", |log| log.count("/status") >= cycles + 10)
+        .await;
+    assert!(instance.stop().await.success());
+    assert!(log.switches.is_empty(), "{:?}", log.moves());
+    let decision = skipped(&state).unwrap();
+    assert_eq!(decision["outcome"], "skipped");
+    assert_eq!(
+        decision["reason"],
+        "22.00 Mbps would not fit into the headroom of any alternative WAN"
+    );
+}
@@ -0,0 +1,63 @@
+mod common;
+
+use common::{Instance, MockBackends, Script};
+
+#[tokio::test]
+async fn lists_the_recorded_switches_newest_first() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let instance = Instance::start(&backends.config("[cooldown]\ndefault_secs = 5"));
+    backends
+        .wait_for("two switches", |log| log.switches.len() >= 2)
+        .await;
+    // Too quiet to be moved again
+    backends.update(|script| {
+        script
+            .traffic_bps
+            .insert("192.168.1.10".to_string(), (5e5, 5e4));
+    });
+    let cycles = backends.log().count("/status");
+    let log = backends
+        .wait_for("a quiet cycle", |log| log.count("/status") > cycles + 1)
+        .await;
+
+    let output = instance.command(&["history"]).await;
+    let limited = instance.command(&["history", "--limit", "1"]).await;
+    let quiet = instance.command(&["history", "--ip", "192.168.1.11"]).await;
+    assert!(instance.stop().await.success());
+    assert!(output.status.success(), "{:?}", output);
+    let output = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<&str> = output.lines().collect();
+    assert_eq!(lines.len(), log.switches.len(), "{}", output);
+    for (line, (ip, wan)) in lines.iter().zip(log.moves().into_iter().rev()) {
+        let from = if wan == "wan1" { "wan0" } else { "wan1" };
+        assert!(
+            line.contains(&format!("{} {} → {} [success", ip, from, wan)),
+            "{}",
+            output
+        );
+    }
+    assert!(
+        lines.last().unwrap().contains("top RX IP on eth0"),
+        "{}",
+        output
+    );
+    assert_eq!(String::from_utf8_lossy(&limited.stdout).lines().count(), 1);
+    assert_eq!(
+        String::from_utf8_lossy(&quiet.stdout).trim(),
+        "(No switch history recorded)"
+    );
+}
+
+#[tokio::test]
+async fn fails_without_a_database() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let instance = Instance::start(&backends.config("[history]\nenabled = false"));
+    backends
+        .wait_for("a switch", |log| !log.switches.is_empty())
+        .await;
+
+    let output = instance.command(&["history"]).await;
+    assert!(instance.stop().await.success());
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("No switch history database"));
+}
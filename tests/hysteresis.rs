@@ -0,0 +1,51 @@
+mod common;
+
+use common::{Instance, MockBackends, Script};
+
+/// Moving the busy client gains `wan1`'s 199.45 Mbps of headroom over `wan0`'s 27.45: about
+/// 172 Mbps, or 628%.
+#[tokio::test]
+async fn waits_for_the_advantage_to_hold_for_the_consecutive_scans() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let instance = Instance::start(&backends.config("[hysteresis]\nconsecutive_scans = 5"));
+
+    let log = backends
+        .wait_for("a switch", |log| !log.switches.is_empty())
+        .await;
+    assert!(instance.stop().await.success());
+    assert_eq!(log.moves()[0], ("192.168.1.10", "wan1"));
+    // Held on the first four scans
+    assert!(
+        (5..=7).contains(&log.switches[0].cycle),
+        "{:?}",
+        log.switches
+    );
+}
+
+#[tokio::test]
+async fn never_switches_for_a_gain_below_both_thresholds() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let instance = Instance::start(
+        &backends.config("[hysteresis]\nmin_delta_percent = 1000.0\nmin_delta_mbps = 500.0"),
+    );
+
+    let log = backends
+        .wait_for("30 cycles", |log| log.count("/status") >= 30)
+        .await;
+    assert!(instance.stop().await.success());
+    assert_eq!(log.moves(), []);
+}
+
+#[tokio::test]
+async fn either_threshold_is_enough() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let instance = Instance::start(
+        &backends.config("[hysteresis]\nmin_delta_percent = 1000.0\nmin_delta_mbps = 100.0"),
+    );
+
+    let log = backends
+        .wait_for("a switch", |log| !log.switches.is_empty())
+        .await;
+    assert!(instance.stop().await.success());
+    assert_eq!(log.moves()[0], ("192.168.1.10", "wan1"));
+}
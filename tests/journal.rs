@@ -0,0 +1,115 @@
+mod common;
+
+use common::{Instance, MockBackends, Script, SAMPLE_TIME};
+use std::path::PathBuf;
+
+/// A journal a crash left behind with one switch of `ip` to `wan` in flight, and the
+/// config pointing at it.
+fn crashed(
+    backends: &MockBackends,
+    name: &str,
+    ip: &str,
+    from: &str,
+    wan: &str,
+) -> (PathBuf, String) {
+    let path = std::env::temp_dir().join(format!(
+        "routingflow-test-{}-{}.journal",
+        std::process::id(),
+        name
+    ));
+    std::fs::write(
+        &path,
+        format!(
+            "{{\"op\":\"intent\",\"id\":7,\"timestamp\":{},\"ip\":\"{}\",\"from_wan\":\"{}\",\"target_wan\":\"{}\",\"reason\":\"before the crash\"}}\n{{\"op\":\"inte",
+            SAMPLE_TIME as u64 - 10,
+            ip,
+            from,
+            wan
+        ),
+    )
+    .unwrap();
+    let config = backends.config(&format!("[journal]\npath = {:?}", path));
+    (path, config)
+}
+
+/// [`Script::two_wans`] without a client busy enough to move.
+fn quiet() -> Script {
+    let mut script = Script::two_wans();
+    script
+        .traffic_bps
+        .insert("192.168.1.10".to_string(), (5e5, 5e4));
+    script
+}
+
+#[tokio::test]
+async fn records_a_switch_that_took_effect_before_a_crash() {
+    let mut script = quiet();
+    script
+        .mappings
+        .insert("192.168.1.12".to_string(), "wan0".to_string());
+    let backends = MockBackends::start(script).await;
+    let (journal, config) = crashed(&backends, "applied", "192.168.1.12", "wan1", "wan0");
+    let instance = Instance::start(&config);
+
+    backends
+        .wait_for("2 cycles", |log| log.count("/status") >= 2)
+        .await;
+    let history = instance.command(&["history"]).await;
+    assert!(instance.stop().await.success());
+    let left = std::fs::read_to_string(&journal).unwrap();
+    let _ = std::fs::remove_file(&journal);
+    let history = String::from_utf8_lossy(&history.stdout);
+    assert_eq!(history.lines().count(), 1, "{}", history);
+    assert!(
+        history.contains("192.168.1.12 wan1 → wan0 [success"),
+        "{}",
+        history
+    );
+    // Nothing is in flight any more
+    assert_eq!(left, "");
+}
+
+#[tokio::test]
+async fn drops_a_switch_the_crash_interrupted() {
+    let backends = MockBackends::start(quiet()).await;
+    let (journal, config) = crashed(&backends, "interrupted", "192.168.1.11", "wan0", "wan1");
+    let instance = Instance::start(&config);
+
+    let log = backends
+        .wait_for("2 cycles", |log| log.count("/status") >= 2)
+        .await;
+    let history = instance.command(&["history"]).await;
+    assert!(instance.stop().await.success());
+    let left = std::fs::read_to_string(&journal).unwrap();
+    let _ = std::fs::remove_file(&journal);
+    // Neither repeated nor recorded
+    assert!(log.switches.is_empty(), "{:?}", log.moves());
+    assert_eq!(
+        String::from_utf8_lossy(&history.stdout).trim(),
+        "(No switch history recorded)"
+    );
+    assert_eq!(left, "");
+}
+
+#[tokio::test]
+async fn completes_the_switches_it_makes() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let path = std::env::temp_dir().join(format!(
+        "routingflow-test-{}-live.journal",
+        std::process::id()
+    ));
+    let instance = Instance::start(&backends.config(&format!("[journal]\npath = {:?}", path)));
+
+    let log = backends
+        .wait_for("the cycle after a switch", |log| {
+            log.switches
+                .first()
+                .is_some_and(|switch| log.count("/status") > switch.cycle + 1)
+        })
+        .await;
+    assert!(instance.stop().await.success());
+    let left = std::fs::read_to_string(&path).unwrap();
+    let _ = std::fs::remove_file(&path);
+    assert_eq!(log.moves()[0], ("192.168.1.10", "wan1"));
+    assert_eq!(left, "");
+}
@@ -0,0 +1,45 @@
+mod common;
+
+use common::{Instance, MockBackends, Script};
+
+/// `wan1` (200 Mbps) losing 10% of its packets, which leaves less of its headroom than
+/// `wan0` has left for the busy client.
+fn lossy_wan1() -> Script {
+    let mut script = Script::two_wans();
+    script.loss.insert("eth0".to_string(), 0.0);
+    script.loss.insert("eth1".to_string(), 0.1);
+    script
+}
+
+#[tokio::test]
+async fn keeps_clients_off_a_lossy_wan_until_it_recovers() {
+    let backends = MockBackends::start(lossy_wan1()).await;
+    let instance =
+        Instance::start(&backends.config("[link_quality]\nloss_query = \"ping_loss_ratio\""));
+
+    let log = backends
+        .wait_for("five cycles", |log| log.count("/status") >= 5)
+        .await;
+    assert!(log.switches.is_empty(), "{:?}", log.switches);
+
+    backends.update(|script| {
+        script.loss.insert("eth1".to_string(), 0.0);
+    });
+    let log = backends
+        .wait_for("a switch", |log| !log.switches.is_empty())
+        .await;
+    assert!(instance.stop().await.success());
+    assert_eq!(log.moves(), vec![("192.168.1.10", "wan1")]);
+}
+
+#[tokio::test]
+async fn ignores_loss_without_link_quality() {
+    let backends = MockBackends::start(lossy_wan1()).await;
+    let instance = Instance::start(&backends.config(""));
+
+    let log = backends
+        .wait_for("a switch", |log| !log.switches.is_empty())
+        .await;
+    assert!(instance.stop().await.success());
+    assert_eq!(log.moves()[0], ("192.168.1.10", "wan1"));
+}
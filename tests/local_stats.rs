@@ -0,0 +1,37 @@
+mod common;
+
+use common::{Instance, MockBackends, Script};
+
+/// `/proc/net/dev` with `eth0` and `eth1`.
+const PROC_NET_DEV: &str = "\
+Inter-|   Receive                                                |  Transmit
+ face |bytes    packets errs drop fifo frame compressed multicast|bytes    packets errs drop fifo colls carrier compressed
+  eth0: 1000000 1000 0 0 0 0 0 0 500000 800 0 0 0 0 0 0
+  eth1: 2000000 2000 0 0 0 0 0 0 900000 1200 0 0 0 0 0 0
+";
+
+#[tokio::test]
+async fn fails_over_on_local_link_states_without_prometheus() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let dir = std::env::temp_dir().join(format!("routingflow-local-stats-{}", std::process::id()));
+    for (nic, state) in [("eth0", "up"), ("eth1", "down")] {
+        std::fs::create_dir_all(dir.join(nic)).unwrap();
+        std::fs::write(dir.join(nic).join("operstate"), state).unwrap();
+    }
+    std::fs::write(dir.join("dev"), PROC_NET_DEV).unwrap();
+    // Nothing listens on the discard port, so every query fails at once
+    let config = format!(
+        "[failover]\n\n[local_stats]\nproc_net_dev = \"{}\"\nsys_class_net = \"{}\"\n\n[prometheus]\nurl = \"http://127.0.0.1:9\"\n\n[routing_service]\nurl = \"{}\"\n\n[retry]\nmax_attempts = 1\n",
+        dir.join("dev").display(),
+        dir.display(),
+        backends.url
+    );
+    let instance = Instance::start(&config);
+
+    let log = backends
+        .wait_for("a switch", |log| !log.switches.is_empty())
+        .await;
+    assert!(instance.stop().await.success());
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(log.moves(), [("192.168.1.12", "wan0")]);
+}
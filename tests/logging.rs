@@ -0,0 +1,52 @@
+mod common;
+
+use common::{Instance, MockBackends, Script};
+use serde_json::Value;
+
+#[tokio::test]
+async fn writes_json_lines_with_the_fields_flattened() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let instance = Instance::start(&backends.config("[logging]\nformat = \"json\""));
+    backends
+        .wait_for("a switch", |log| !log.switches.is_empty())
+        .await;
+
+    let (status, log) = instance.stop_with_log().await;
+    assert!(status.success());
+    let lines: Vec<Value> = log
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap_or_else(|e| panic!("{}: {}", e, line)))
+        .collect();
+    let switching = lines
+        .iter()
+        .find(|line| line["message"] == "Switching")
+        .unwrap_or_else(|| panic!("no switch logged in {}", log));
+    assert_eq!(switching["level"], "INFO");
+    assert_eq!(switching["target"], "routingFlow::monitor");
+    assert_eq!(switching["ip"], "192.168.1.10");
+    assert_eq!(switching["target_wan"], "wan1");
+    assert!(lines.iter().any(
+        |line| line["message"] == "Running against a simulated clock" && line["level"] == "WARN"
+    ));
+    assert!(lines
+        .iter()
+        .any(|line| line["message"] == "Stopping after the current cycle"));
+}
+
+#[tokio::test]
+async fn filters_by_level_and_module() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let instance = Instance::start(&backends.config(
+        "[logging]\nlevel = \"warn\"\n\n[logging.filters]\n\"routingFlow::monitor\" = \"info\"",
+    ));
+    backends
+        .wait_for("a switch", |log| !log.switches.is_empty())
+        .await;
+
+    let (status, log) = instance.stop_with_log().await;
+    assert!(status.success());
+    // The monitor's info lines, and only the warnings elsewhere
+    assert!(log.contains("Switching"), "{}", log);
+    assert!(log.contains("Running against a simulated clock"), "{}", log);
+    assert!(!log.contains("Stopping after the current cycle"), "{}", log);
+}
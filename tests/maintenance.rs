@@ -0,0 +1,64 @@
+mod common;
+
+use common::{api_config, free_addr, Api, Instance, MockBackends, Script};
+use serde_json::{json, Value};
+
+/// The simulated clock starts at 10:00, a minute into the window.
+const WINDOW: &str =
+    "[[maintenance_windows]]\nname = \"isp-wan1\"\nschedule = { start = \"09:59\", end = \"10:01\" }";
+
+#[tokio::test]
+async fn freezes_switching_until_the_window_closes() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let addr = free_addr();
+    let instance =
+        Instance::start(&backends.config(&format!("{}\n\n{}", WINDOW, api_config(addr))));
+    let api = Api::connect(addr, "admin-key").await;
+
+    let skipped = |state: &Value| {
+        state["cycle"]["decisions"]
+            .as_array()
+            .is_some_and(|decisions| {
+                decisions.iter().any(|decision| {
+                    decision["ip"] == "192.168.1.10"
+                        && decision["outcome"] == "skipped"
+                        && decision["reason"] == "maintenance window isp-wan1"
+                })
+            })
+    };
+    let state = api.wait_for_state("a frozen switch", skipped).await;
+    assert_eq!(state["maintenance"]["name"], "isp-wan1");
+    assert_eq!(state["maintenance"]["window"], "09:59-10:01");
+
+    let log = backends
+        .wait_for("a switch", |log| !log.switches.is_empty())
+        .await;
+    let state = api
+        .wait_for_state("the window closed", |state| state["maintenance"].is_null())
+        .await;
+    assert!(instance.stop().await.success());
+    assert_eq!(log.moves()[0], ("192.168.1.10", "wan1"));
+    let cycle = log.switches[0].cycle;
+    assert!((60..=62).contains(&cycle), "switched after {}s", cycle);
+    assert!(state["maintenance"].is_null());
+}
+
+#[tokio::test]
+async fn makes_requested_switches_in_a_window() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let addr = free_addr();
+    let instance =
+        Instance::start(&backends.config(&format!("{}\n\n{}", WINDOW, api_config(addr))));
+    let api = Api::connect(addr, "admin-key").await;
+
+    let (status, body) = api
+        .post("/switch", json!({"ip": "192.168.1.11", "wan": "wan1"}))
+        .await;
+    assert_eq!(status, 202, "{}", body);
+    let log = backends
+        .wait_for("a switch", |log| !log.switches.is_empty())
+        .await;
+    assert!(instance.stop().await.success());
+    assert_eq!(log.moves()[0], ("192.168.1.11", "wan1"));
+    assert!(log.switches[0].cycle < 60, "{}", log.switches[0].cycle);
+}
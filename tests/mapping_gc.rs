@@ -0,0 +1,62 @@
+mod common;
+
+use common::{Instance, MockBackends, Script};
+
+/// A mapped client without traffic; the host's ARP table has none of the test addresses.
+fn with_idle_client() -> Script {
+    let mut script = Script::two_wans();
+    script
+        .mappings
+        .insert("192.168.1.13".to_string(), "wan0".to_string());
+    script
+}
+
+#[tokio::test]
+async fn removes_a_mapping_idle_for_too_long() {
+    let backends = MockBackends::start(with_idle_client()).await;
+    let instance = Instance::start(
+        &backends
+            .config("[mapping_gc]\nidle_secs = 20\naction = \"remove\"\nremove_path = \"/remove\""),
+    );
+
+    let log = backends
+        .wait_for("a removal", |log| log.count("/remove") > 0)
+        .await;
+    // Removed once, and gone from the mappings for good
+    let cycles = log.count("/status");
+    let log = backends
+        .wait_for("10 more cycles", |log| log.count("/status") >= cycles + 10)
+        .await;
+    assert!(instance.stop().await.success());
+    let removals: Vec<_> = log
+        .requests
+        .iter()
+        .filter(|request| request.path == "/remove")
+        .map(|request| request.query["ip"].as_str())
+        .collect();
+    assert_eq!(removals, ["192.168.1.13"]);
+    // Idle from the first cycle on
+    let removed_at = log
+        .requests
+        .iter()
+        .take_while(|request| request.path != "/remove")
+        .filter(|request| request.path == "/status")
+        .count();
+    assert!(
+        (20..=23).contains(&removed_at),
+        "removed after {} cycles",
+        removed_at
+    );
+}
+
+#[tokio::test]
+async fn only_reports_idle_mappings_by_default() {
+    let backends = MockBackends::start(with_idle_client()).await;
+    let instance = Instance::start(&backends.config("[mapping_gc]\nidle_secs = 5"));
+
+    let log = backends
+        .wait_for("30 cycles", |log| log.count("/status") >= 30)
+        .await;
+    assert!(instance.stop().await.success());
+    assert_eq!(log.count("/remove"), 0);
+}
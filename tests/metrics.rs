@@ -0,0 +1,70 @@
+mod common;
+
+use common::{api_config, free_addr, Api, Instance, MockBackends, Script};
+
+/// The value of the sample `series` (name and labels, as written) in an exposition.
+fn sample(metrics: &str, series: &str) -> Option<f64> {
+    metrics.lines().find_map(|line| {
+        line.strip_prefix(series)?
+            .strip_prefix(' ')?
+            .trim()
+            .parse()
+            .ok()
+    })
+}
+
+#[tokio::test]
+async fn counts_cycles_and_switches_and_gauges_the_nics() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let addr = free_addr();
+    let instance = Instance::start(&backends.config(&api_config(addr)));
+    let api = Api::connect(addr, "admin-key").await;
+    backends
+        .wait_for("a switch", |log| !log.switches.is_empty())
+        .await;
+
+    let (status, metrics) = api.get("/metrics").await;
+    assert!(instance.stop().await.success());
+    assert_eq!(status, 200);
+    assert!(metrics.contains("# TYPE routingflow_switches_total counter"));
+    assert!(sample(&metrics, "routingflow_cycles_total").unwrap() >= 1.0);
+    assert!(sample(&metrics, "routingflow_switches_total{result=\"success\"}").unwrap() >= 1.0);
+    assert_eq!(
+        sample(&metrics, "routingflow_nic_tcp_bandwidth_bps{nic=\"eth1\"}"),
+        Some(200e6)
+    );
+    assert!(sample(&metrics, "routingflow_decision_latency_seconds_count").unwrap() >= 1.0);
+}
+
+#[tokio::test]
+async fn counts_failed_switches_and_scrapes() {
+    let mut script = Script::two_wans();
+    script.fail_switch = true;
+    let backends = MockBackends::start(script).await;
+    let addr = free_addr();
+    let instance = Instance::start(&backends.config(&format!(
+        "[degradation]\nmax_stale_secs = 0\n\n{}",
+        api_config(addr)
+    )));
+    let api = Api::connect(addr, "admin-key").await;
+    backends
+        .wait_for("a switch", |log| !log.switches.is_empty())
+        .await;
+    backends.update(|script| script.fail_status = true);
+    let cycles = backends.log().count("/status");
+    backends
+        .wait_for("failed scans", |log| log.count("/status") > cycles + 2)
+        .await;
+
+    let (_, metrics) = api.get("/metrics").await;
+    assert!(instance.stop().await.success());
+    assert!(sample(&metrics, "routingflow_switches_total{result=\"failed\"}").unwrap() >= 1.0);
+    assert!(
+        sample(
+            &metrics,
+            "routingflow_scrape_errors_total{source=\"status\"}"
+        )
+        .unwrap()
+            >= 1.0
+    );
+}
@@ -0,0 +1,64 @@
+mod common;
+
+use common::{Instance, MockBackends, Script};
+
+/// `wan0` with too small an estimate for the traffic on it.
+fn overloaded() -> Script {
+    let mut script = Script::two_wans();
+    script.bandwidth_bps.insert("eth0".to_string(), 10e6);
+    script
+}
+
+#[tokio::test]
+async fn tells_slack_about_a_sustained_overload() {
+    let backends = MockBackends::start(overloaded()).await;
+    let instance = Instance::start(&backends.config(&format!(
+        "excluded_ips = [\"192.168.1.10\"]\n\n[events]\noverload_secs = 5\n\n\
+         [[events.notifiers]]\nkind = \"slack\"\nwebhook_url = \"{}/slack\"",
+        backends.url
+    )));
+
+    let log = backends
+        .wait_for("an overload message", |log| {
+            log.notifications
+                .iter()
+                .any(|message| message.body["text"].as_str().unwrap().contains("since"))
+        })
+        .await;
+    assert!(instance.stop().await.success());
+    let texts: Vec<_> = log
+        .notifications
+        .iter()
+        .map(|message| message.body["text"].as_str().unwrap())
+        .collect();
+    assert!(texts[0].starts_with("eth0 carries"), "{:?}", texts);
+    let overload = &log.notifications[1];
+    assert!(
+        overload.body["text"]
+            .as_str()
+            .unwrap()
+            .starts_with("eth0 has been above its estimate of 10.00 Mbps"),
+        "{:?}",
+        texts
+    );
+    assert!(overload.cycle >= 5);
+}
+
+#[tokio::test]
+async fn rate_limits_telegram_messages() {
+    let backends = MockBackends::start(overloaded()).await;
+    let instance = Instance::start(&backends.config(&format!(
+        "excluded_ips = [\"192.168.1.10\"]\n\n[events]\noverload_secs = 5\n\n\
+         [[events.notifiers]]\nkind = \"telegram\"\nbot_token = \"123:abc\"\nchat_id = \"42\"\n\
+         api_url = \"{}\"\nmax_per_hour = 1",
+        backends.url
+    )));
+
+    let log = backends
+        .wait_for("20 cycles", |log| log.count("/status") >= 20)
+        .await;
+    assert!(instance.stop().await.success());
+    assert_eq!(log.notifications.len(), 1);
+    assert_eq!(log.notifications[0].path, "/bot123:abc/sendMessage");
+    assert_eq!(log.notifications[0].body["chat_id"], "42");
+}
@@ -0,0 +1,83 @@
+mod common;
+
+use common::{Instance, MockBackends, Script};
+use serde_json::Value;
+
+#[tokio::test]
+async fn prints_one_json_report_per_cycle() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let instance = Instance::start_with(&backends.config(""), &["--output", "json"]);
+    backends
+        .wait_for("a switch", |log| !log.switches.is_empty())
+        .await;
+
+    let (status, output) = instance.stop_with_report().await;
+    assert!(status.success());
+    let reports: Vec<Value> = output
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap_or_else(|e| panic!("{}: {}", e, line)))
+        .collect();
+    let first = &reports[0];
+    let eth0 = first["nics"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|nic| nic["nic"] == "eth0")
+        .unwrap();
+    assert_eq!(eth0["tcp_bandwidth_bps"], 50e6);
+    assert_eq!(eth0["rx_bps"], 20.5e6);
+    let top = &first["top_ips"][0];
+    assert_eq!(top["ip"], "192.168.1.10");
+    assert_eq!(top["rx_bps"], 20e6);
+
+    let switch = reports
+        .iter()
+        .flat_map(|report| report["decisions"].as_array().unwrap())
+        .find(|decision| decision["outcome"] == "switched")
+        .unwrap_or_else(|| panic!("no switch reported in {}", output));
+    assert_eq!(switch["ip"], "192.168.1.10");
+    assert_eq!(switch["nic"], "eth0");
+    assert_eq!(switch["target_wan"], "wan1");
+}
+
+#[tokio::test]
+async fn prints_the_text_report_unless_quiet() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let text = Instance::start_with(&backends.config(""), &[]);
+    let quiet = Instance::start(&backends.config(""));
+    backends
+        .wait_for("a switch", |log| !log.switches.is_empty())
+        .await;
+
+    let (status, output) = text.stop_with_report().await;
+    assert!(status.success());
+    assert!(output.contains("NIC Configuration:"), "{}", output);
+    let (status, output) = quiet.stop_with_report().await;
+    assert!(status.success());
+    assert_eq!(output, "");
+}
+
+#[tokio::test]
+async fn prints_the_text_report_through_a_configured_template() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let instance = Instance::start_with(
+        &backends.config(
+            "[report]\ntemplate = '''\n\
+             {% for decision in decisions if decision.outcome == \"switched\" %}\n\
+             {{ labels[decision.ip] }} → {{ decision.target_wan }} ({{ decision.rx_bps | mbps }} Mbps)\n\
+             {% endfor %}\n'''",
+        ),
+        &[],
+    );
+    backends
+        .wait_for("a switch", |log| !log.switches.is_empty())
+        .await;
+
+    let (status, output) = instance.stop_with_report().await;
+    assert!(status.success());
+    assert_eq!(
+        output.lines().next(),
+        Some("192.168.1.10 → wan1 (20.00 Mbps)")
+    );
+    assert!(!output.contains("NIC Configuration:"), "{}", output);
+}
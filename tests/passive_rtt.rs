@@ -0,0 +1,76 @@
+mod common;
+
+use common::{Instance, MockBackends, Script};
+use serde_json::Value;
+
+/// [`Script::two_wans`] with the busy client seeing 20 ms and the client on wan1 `wan1_ms`.
+fn measured(wan1_ms: f64) -> Script {
+    let mut script = Script::two_wans();
+    script.rtt.insert("192.168.1.10".to_string(), (20.0, 0.01));
+    script
+        .rtt
+        .insert("192.168.1.12".to_string(), (wan1_ms, 0.0));
+    script
+}
+
+fn reports(output: &str) -> Vec<Value> {
+    output
+        .lines()
+        .map(|line| serde_json::from_str::<Value>(line).unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn keeps_a_client_off_a_slower_wan() {
+    let backends = MockBackends::start(measured(80.0)).await;
+    let instance = Instance::start_with(
+        &backends.config("[passive_rtt]\nmax_rtt_increase_ms = 30.0"),
+        &["--output", "json"],
+    );
+
+    let log = backends
+        .wait_for("10 cycles", |log| log.count("/status") >= 10)
+        .await;
+    let (status, output) = instance.stop_with_report().await;
+    assert!(status.success());
+    assert!(log.switches.is_empty(), "{:?}", log.moves());
+    let report = &reports(&output)[0];
+    let decision = report["decisions"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|decision| decision["ip"] == "192.168.1.10")
+        .unwrap();
+    assert_eq!(decision["outcome"], "skipped");
+    assert_eq!(
+        decision["reason"],
+        "RTT would worsen on wan1 (20.0 ms now, 80.0 ms median there)"
+    );
+    let top = report["top_ips"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|top| top["ip"] == "192.168.1.10")
+        .unwrap();
+    assert_eq!(top["experience"]["rtt_ms"], 20.0);
+    assert_eq!(top["experience"]["retransmit_ratio"], 0.01);
+    let wan1 = report["wans"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|wan| wan["wan"] == "wan1")
+        .unwrap();
+    assert_eq!(wan1["client_rtt_ms"], 80.0);
+}
+
+#[tokio::test]
+async fn moves_a_client_onto_a_wan_within_the_allowed_increase() {
+    let backends = MockBackends::start(measured(45.0)).await;
+    let instance = Instance::start(&backends.config("[passive_rtt]\nmax_rtt_increase_ms = 30.0"));
+
+    let log = backends
+        .wait_for("a switch", |log| !log.switches.is_empty())
+        .await;
+    assert!(instance.stop().await.success());
+    assert_eq!(log.moves()[0], ("192.168.1.10", "wan1"));
+}
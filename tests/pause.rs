@@ -0,0 +1,33 @@
+mod common;
+
+use common::{api_config, free_addr, Api, Instance, MockBackends, Script};
+use serde_json::{json, Value};
+use std::time::Duration;
+
+#[tokio::test]
+async fn refuses_pauses_longer_than_a_year() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let addr = free_addr();
+    let instance = Instance::start(&backends.config(&api_config(addr)));
+    let api = Api::connect(addr, "admin-key").await;
+
+    let (status, body) = api.post("/pause", json!({"duration_secs": u64::MAX})).await;
+    assert_eq!(status, 400, "{}", body);
+    let (status, body) = api.post("/pause", json!({"duration_secs": 3600})).await;
+    assert!(status < 300, "{} {}", status, body);
+
+    // Picked up by the next cycle
+    let paused = loop {
+        let (_, state) = api.get("/state").await;
+        let state: Value = serde_json::from_str(&state).unwrap();
+        if !state["paused"].is_null() {
+            break state["paused"].clone();
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    };
+    assert!(instance.stop().await.success());
+    assert_eq!(
+        paused["until"].as_u64().unwrap(),
+        paused["since"].as_u64().unwrap() + 3600
+    );
+}
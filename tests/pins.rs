@@ -0,0 +1,74 @@
+mod common;
+
+use common::{api_config, free_addr, Api, Instance, MockBackends, Script};
+use serde_json::{json, Value};
+
+#[tokio::test]
+async fn refuses_pins_longer_than_a_year_and_honours_the_longest_allowed() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let addr = free_addr();
+    let instance = Instance::start(&backends.config(&api_config(addr)));
+    let api = Api::connect(addr, "admin-key").await;
+
+    let pin = |duration_secs: u64| json!({"ip": "192.168.1.12", "wan": "wan0", "duration_secs": duration_secs});
+    let (status, body) = api.post("/pins", pin(u64::MAX)).await;
+    assert_eq!(status, 400, "{}", body);
+    let (status, body) = api.post("/pins", pin(365 * 24 * 60 * 60)).await;
+    assert_eq!(status, 202, "{}", body);
+
+    backends
+        .wait_for("the pinned switch", |log| {
+            log.moves().contains(&("192.168.1.12", "wan0"))
+        })
+        .await;
+    let (_, pins) = api.get("/pins").await;
+    assert!(instance.stop().await.success());
+    let pins: serde_json::Value = serde_json::from_str(&pins).unwrap();
+    assert_eq!(pins[0]["ip"], "192.168.1.12");
+}
+
+/// Reasons of the skipped decisions about `ip` in any report.
+fn skipped(output: &str, ip: &str) -> Vec<String> {
+    output
+        .lines()
+        .map(|line| serde_json::from_str::<Value>(line).unwrap())
+        .flat_map(|report| report["decisions"].as_array().unwrap().clone())
+        .filter(|decision| decision["ip"] == ip && decision["outcome"] == "skipped")
+        .map(|decision| decision["reason"].as_str().unwrap().to_string())
+        .collect()
+}
+
+#[tokio::test]
+async fn never_moves_an_excluded_client() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let instance = Instance::start_with(
+        &backends.config("excluded_ips = [\"192.168.1.10\"]"),
+        &["--output", "json"],
+    );
+
+    let log = backends
+        .wait_for("10 cycles", |log| log.count("/status") >= 10)
+        .await;
+    let (status, output) = instance.stop_with_report().await;
+    assert!(status.success());
+    assert!(log.switches.is_empty(), "{:?}", log.moves());
+    assert!(skipped(&output, "192.168.1.10").contains(&"excluded from switching".to_string()));
+}
+
+#[tokio::test]
+async fn keeps_a_pinned_client_on_its_wan() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let instance = Instance::start_with(
+        &backends.config("[pinned_ips]\n\"192.168.1.10\" = \"wan0\"\n\"192.168.1.12\" = \"wan0\""),
+        &["--output", "json"],
+    );
+
+    let log = backends
+        .wait_for("10 cycles", |log| log.count("/status") >= 10)
+        .await;
+    let (status, output) = instance.stop_with_report().await;
+    assert!(status.success());
+    // The one pinned elsewhere is brought back, the busy one stays
+    assert_eq!(log.moves(), [("192.168.1.12", "wan0")]);
+    assert!(skipped(&output, "192.168.1.10").contains(&"pinned to wan0".to_string()));
+}
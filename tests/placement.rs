@@ -0,0 +1,66 @@
+mod common;
+
+use common::{Instance, MockBackends, Script};
+
+#[tokio::test]
+async fn places_a_new_device_and_leaves_known_ones_where_they_are() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let instance = Instance::start(
+        &backends.config("[initial_placement]\nweights = { wan0 = 0.0, wan1 = 1.0 }"),
+    );
+    backends
+        .wait_for("5 cycles", |log| log.count("/status") >= 5)
+        .await;
+
+    backends.update(|script| {
+        script
+            .mappings
+            .insert("192.168.1.20".to_string(), "wan0".to_string());
+    });
+    let log = backends
+        .wait_for("the new device's placement", |log| {
+            log.moves().contains(&("192.168.1.20", "wan1"))
+        })
+        .await;
+    assert!(instance.stop().await.success());
+    // Mapped at startup, and too quiet for the policy
+    assert!(!log.moves().iter().any(|(ip, _)| *ip == "192.168.1.11"));
+}
+
+/// Hashing is deterministic, so the same 100 addresses always split the same way; with
+/// weights of 3:1 about a quarter of them belong on `wan1`.
+#[tokio::test]
+async fn spreads_new_devices_by_weight() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let instance = Instance::start(
+        &backends.config("[initial_placement]\nweights = { wan0 = 3.0, wan1 = 1.0 }"),
+    );
+    backends
+        .wait_for("5 cycles", |log| log.count("/status") >= 5)
+        .await;
+
+    backends.update(|script| {
+        for host in 1..=100 {
+            script
+                .mappings
+                .insert(format!("10.0.0.{}", host), "wan0".to_string());
+        }
+    });
+    let placed = |log: &common::Log| {
+        log.moves()
+            .iter()
+            .filter(|(ip, wan)| ip.starts_with("10.0.0.") && *wan == "wan1")
+            .count()
+    };
+    let cycles = backends.log().count("/status");
+    let log = backends
+        .wait_for("3 more cycles", |log| log.count("/status") >= cycles + 3)
+        .await;
+    assert!(instance.stop().await.success());
+    assert!((15..=35).contains(&placed(&log)), "{}", placed(&log));
+    // Placed once, on first sight
+    assert!(!log
+        .moves()
+        .iter()
+        .any(|(ip, wan)| ip.starts_with("10.0.0.") && *wan == "wan0"));
+}
@@ -0,0 +1,181 @@
+mod common;
+
+use common::{api_config, free_addr, Api, Instance, MockBackends, Script};
+use serde_json::Value;
+
+#[tokio::test]
+async fn refuses_to_start_with_an_unknown_policy() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let instance = Instance::start(&backends.config("policy = \"round_robin\""));
+
+    assert!(!instance.wait().await.success());
+    assert_eq!(backends.log().count("/status"), 0);
+}
+
+#[tokio::test]
+async fn reports_the_plan_of_the_configured_policy() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let addr = free_addr();
+    let instance = Instance::start(&backends.config(&api_config(addr)));
+    let api = Api::connect(addr, "admin-key").await;
+
+    let switched = |state: &Value| {
+        state["cycle"]["decisions"]
+            .as_array()
+            .and_then(|decisions| {
+                decisions
+                    .iter()
+                    .find(|decision| decision["outcome"] == "switched")
+            })
+            .cloned()
+    };
+    let state = api
+        .wait_for_state("a switch", |state| switched(state).is_some())
+        .await;
+    assert!(instance.stop().await.success());
+    assert_eq!(state["cycle"]["policy"], "top_rx");
+    let decision = switched(&state).unwrap();
+    assert_eq!(decision["ip"], "192.168.1.10");
+    // By the time the state is read the client may have been moved back again
+    let target_wan = if decision["nic"] == "eth0" {
+        "wan1"
+    } else {
+        "wan0"
+    };
+    assert_eq!(decision["target_wan"], target_wan);
+}
+
+#[tokio::test]
+async fn shadows_a_policy_selected_at_runtime_before_it_takes_control() {
+    let mut script = Script::two_wans();
+    // Nothing for top_rx to move
+    script
+        .traffic_bps
+        .insert("192.168.1.10".to_string(), (5e5, 5e4));
+    let backends = MockBackends::start(script).await;
+    let addr = free_addr();
+    let instance = Instance::start(&backends.config(&format!(
+        "policy_shadow_secs = 10\n\n[[events.webhooks]]\nurl = \"{}/hook\"\nevents = [\"policy_change\"]\n\n{}",
+        backends.url,
+        api_config(addr)
+    )));
+    let api = Api::connect(addr, "admin-key").await;
+
+    let (status, _) = api
+        .post("/policy", serde_json::json!({ "policy": "round_robin" }))
+        .await;
+    assert_eq!(status, 400);
+    let (status, body) = api
+        .post(
+            "/policy",
+            serde_json::json!({ "policy": "weighted", "weighted": { "weights": { "wan0": 1, "wan1": 2 } } }),
+        )
+        .await;
+    assert_eq!(status, 202, "{}", body);
+    let requested = backends.log().count("/status");
+    let (_, policy) = api.get("/policy").await;
+    let policy: Value = serde_json::from_str(&policy).unwrap();
+    assert_eq!(policy["active"], "top_rx");
+
+    let log = backends
+        .wait_for("a switch", |log| !log.switches.is_empty())
+        .await;
+    let (_, policy) = api.get("/policy").await;
+    assert!(instance.stop().await.success());
+    // 67% of the clients on wan0 against a target of 33%
+    assert_eq!(log.moves(), [("192.168.1.10", "wan1")]);
+    assert!(log.switches[0].cycle >= requested + 10);
+    let policy: Value = serde_json::from_str(&policy).unwrap();
+    assert_eq!(policy["active"], "weighted");
+    assert_eq!(policy["shadow"], Value::Null);
+    let changes: Vec<&Value> = log
+        .notifications
+        .iter()
+        .map(|message| &message.body)
+        .collect();
+    assert_eq!(changes.len(), 2, "{:?}", changes);
+    for (change, active) in changes.iter().zip([false, true]) {
+        assert_eq!(change["policy"], "weighted");
+        assert_eq!(change["previous"], "top_rx");
+        assert_eq!(change["requested_by"], "test");
+        assert_eq!(change["active"], active);
+    }
+}
+
+/// Client and reason of every switch in the reports, in order.
+fn switch_reasons(output: &str) -> Vec<(String, String)> {
+    output
+        .lines()
+        .map(|line| serde_json::from_str::<Value>(line).unwrap())
+        .flat_map(|report| report["decisions"].as_array().unwrap().clone())
+        .filter(|decision| decision["outcome"] == "switched")
+        .map(|decision| {
+            (
+                decision["ip"].as_str().unwrap().to_string(),
+                decision["reason"].as_str().unwrap().to_string(),
+            )
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn moves_the_heaviest_clients_of_a_nic_in_one_cycle() {
+    let mut script = Script::two_wans();
+    script
+        .traffic_bps
+        .insert("192.168.1.11".to_string(), (5e6, 0.0));
+    let backends = MockBackends::start(script).await;
+    let instance = Instance::start_with(
+        &backends.config("[top_rx]\nmoves_per_nic = 2"),
+        &["--output", "json"],
+    );
+
+    let log = backends
+        .wait_for("two switches", |log| log.switches.len() >= 2)
+        .await;
+    let (status, output) = instance.stop_with_report().await;
+    assert!(status.success());
+    assert_eq!(
+        log.moves()[..2],
+        [("192.168.1.10", "wan1"), ("192.168.1.11", "wan1")]
+    );
+    assert_eq!(log.switches[0].cycle, log.switches[1].cycle);
+    let reasons = switch_reasons(&output);
+    assert!(
+        reasons[0].1.starts_with("top RX IP on eth0"),
+        "{:?}",
+        reasons
+    );
+    assert!(
+        reasons[1].1.starts_with("#2 RX IP on eth0"),
+        "{:?}",
+        reasons
+    );
+}
+
+#[tokio::test]
+async fn ranks_clients_by_their_total_traffic() {
+    // Uploading more than .10 downloads
+    let mut script = Script::two_wans();
+    script
+        .traffic_bps
+        .insert("192.168.1.11".to_string(), (1e6, 25e6));
+    let backends = MockBackends::start(script).await;
+    let instance = Instance::start_with(
+        &backends.config("[top_rx]\nrank_by = \"total\""),
+        &["--output", "json"],
+    );
+
+    let log = backends
+        .wait_for("a switch", |log| !log.switches.is_empty())
+        .await;
+    let (status, output) = instance.stop_with_report().await;
+    assert!(status.success());
+    assert_eq!(log.moves()[0], ("192.168.1.11", "wan1"));
+    let reasons = switch_reasons(&output);
+    assert!(
+        reasons[0].1.starts_with("top traffic IP on eth0"),
+        "{:?}",
+        reasons
+    );
+}
@@ -0,0 +1,45 @@
+mod common;
+
+use common::{Instance, MockBackends, Script};
+
+/// The busy client's traffic grows by 1 Mbps a cycle from 10 Mbps, so `wan0` (50 Mbps)
+/// saturates around cycle 38; the hysteresis only lets a switch through once `wan0` has no
+/// headroom left.
+fn rising() -> Script {
+    let mut script = Script::two_wans();
+    script
+        .traffic_bps
+        .insert("192.168.1.10".to_string(), (10e6, 2e6));
+    script.ramp_bps.insert("192.168.1.10".to_string(), 1e6);
+    script
+}
+
+const HYSTERESIS: &str = "[hysteresis]\nmin_delta_mbps = 200.0\nconsecutive_scans = 1";
+
+#[tokio::test]
+async fn moves_a_client_before_its_wan_saturates() {
+    let backends = MockBackends::start(rising()).await;
+    let instance = Instance::start(&backends.config(&format!(
+        "{}\n\n[prediction]\nhorizon_secs = 15\nwindow_secs = 10",
+        HYSTERESIS
+    )));
+
+    let log = backends
+        .wait_for("a switch", |log| !log.switches.is_empty())
+        .await;
+    assert!(instance.stop().await.success());
+    assert_eq!(log.moves()[0], ("192.168.1.10", "wan1"));
+    assert!(log.switches[0].cycle < 33, "{:?}", log.switches[0]);
+}
+
+#[tokio::test]
+async fn waits_for_saturation_without_prediction() {
+    let backends = MockBackends::start(rising()).await;
+    let instance = Instance::start(&backends.config(HYSTERESIS));
+
+    let log = backends
+        .wait_for("a switch", |log| !log.switches.is_empty())
+        .await;
+    assert!(instance.stop().await.success());
+    assert!(log.switches[0].cycle >= 36, "{:?}", log.switches[0]);
+}
@@ -0,0 +1,146 @@
+mod common;
+
+use common::{api_config, free_addr, Api, Instance, MockBackends, Script};
+use serde_json::Value;
+
+/// [`Script::two_wans`] with its WANs on the given interfaces: the loopback interface,
+/// which probes can reach 127.0.0.1 through, and one that is down. The busy client is
+/// quiet until the test has the first probe round in.
+fn on_interfaces(wan0: &str, wan1: &str) -> Script {
+    let mut script = Script::two_wans();
+    for (wan, nic, bps) in [("wan0", wan0, 50e6), ("wan1", wan1, 200e6)] {
+        let old = script
+            .wans
+            .insert(wan.to_string(), nic.to_string())
+            .unwrap();
+        script.bandwidth_bps.remove(&old);
+        script.bandwidth_bps.insert(nic.to_string(), bps);
+    }
+    script
+        .traffic_bps
+        .insert("192.168.1.10".to_string(), (5e5, 5e4));
+    script
+}
+
+/// Makes the busy client busy.
+fn busy(script: &mut Script) {
+    script
+        .traffic_bps
+        .insert("192.168.1.10".to_string(), (20e6, 2e6));
+}
+
+/// A WAN's probe results in the state's latest cycle, null before the first.
+fn probe<'a>(state: &'a Value, wan: &str) -> &'a Value {
+    state["cycle"]["wans"]
+        .as_array()
+        .and_then(|wans| wans.iter().find(|report| report["wan"] == wan))
+        .map_or(&Value::Null, |report| &report["probe"])
+}
+
+#[tokio::test]
+async fn measures_rtt_and_keeps_clients_off_a_wan_whose_probes_fail() {
+    let target = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let backends = MockBackends::start(on_interfaces("lo", "ifb0")).await;
+    let addr = free_addr();
+    let instance = Instance::start(&backends.config(&format!(
+        "[probes]\ninterval_secs = 1\ntimeout_ms = 200\n\n\
+         [[probes.targets]]\nkind = \"tcp\"\naddress = \"{}\"\n\n{}",
+        target.local_addr().unwrap(),
+        api_config(addr)
+    )));
+    let api = Api::connect(addr, "admin-key").await;
+    let state = api
+        .wait_for_state("a probe round", |state| {
+            !probe(state, "wan1").is_null() && !probe(state, "wan0").is_null()
+        })
+        .await;
+    assert_eq!(probe(&state, "wan0")["loss"], 0.0);
+    assert!(probe(&state, "wan0")["rtt_ms"].as_f64().unwrap() < 200.0);
+    assert_eq!(probe(&state, "wan1")["loss"], 1.0);
+    assert_eq!(probe(&state, "wan1")["rtt_ms"], Value::Null);
+
+    backends.update(busy);
+    let cycles = backends.log().count("/status");
+    let log = backends
+        .wait_for("10 busy cycles", |log| log.count("/status") >= cycles + 10)
+        .await;
+    assert!(instance.stop().await.success());
+    // wan1 has all the headroom, but cannot be reached
+    assert!(log.switches.is_empty(), "{:?}", log.moves());
+}
+
+#[tokio::test]
+async fn keeps_general_traffic_off_a_wan_behind_a_captive_portal() {
+    let mut script = on_interfaces("ifb0", "lo");
+    // Not enough traffic for the policy, but a destination rule's
+    script
+        .destinations
+        .push(("192.168.1.11".to_string(), "198.51.100.20".to_string(), 1e5));
+    let backends = MockBackends::start(script).await;
+    let addr = free_addr();
+    // The fake routing service has no such page
+    let instance = Instance::start(&backends.config(&format!(
+        "[probes]\ninterval_secs = 1\ntimeout_ms = 200\n\n\
+         [[probes.captive_checks]]\nkind = \"http\"\nurl = \"{}/generate_204\"\n\n\
+         [destinations]\n\n[[destinations.rules]]\nname = \"portal\"\n\
+         prefixes = [\"198.51.100.0/24\"]\nwan = \"wan1\"\n\n{}",
+        backends.url,
+        api_config(addr)
+    )));
+    let api = Api::connect(addr, "admin-key").await;
+    let state = api
+        .wait_for_state("a probe round", |state| {
+            !probe(state, "wan1").is_null() && !probe(state, "wan0").is_null()
+        })
+        .await;
+    assert_eq!(
+        probe(&state, "wan1")["degraded"],
+        format!(
+            "{}/generate_204 answered 404 instead of 204 (captive portal?)",
+            backends.url
+        )
+    );
+    // Unreachable says nothing about interception
+    assert_eq!(probe(&state, "wan0")["degraded"], Value::Null);
+
+    backends.update(busy);
+    let cycles = backends.log().count("/status");
+    let log = backends
+        .wait_for("10 busy cycles", |log| log.count("/status") >= cycles + 10)
+        .await;
+    assert!(instance.stop().await.success());
+    assert_eq!(log.moves(), [("192.168.1.11", "wan1")]);
+}
+
+#[tokio::test]
+async fn pauses_probing_a_wan_while_it_is_measured() {
+    let target = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let backends = MockBackends::start(on_interfaces("lo", "ifb0")).await;
+    let addr = free_addr();
+    // Any series with an `interface` label does; the fake has loss series at hand
+    let instance = Instance::start(&backends.config(&format!(
+        "[probes]\ninterval_secs = 1\ntimeout_ms = 200\npause_query = \"ping_loss_ratio\"\n\n\
+         [[probes.targets]]\nkind = \"tcp\"\naddress = \"{}\"\n\n{}",
+        target.local_addr().unwrap(),
+        api_config(addr)
+    )));
+    let api = Api::connect(addr, "admin-key").await;
+    let state = api
+        .wait_for_state("a probe round", |state| !probe(state, "wan0").is_null())
+        .await;
+    assert_eq!(probe(&state, "wan0")["paused"], false);
+
+    backends.update(|script| {
+        script.loss.insert("lo".to_string(), 0.0);
+    });
+    let state = api
+        .wait_for_state("a paused probe", |state| {
+            probe(state, "wan0")["paused"] == true
+        })
+        .await;
+    assert!(instance.stop().await.success());
+    // What was measured before the pause stands
+    assert_eq!(probe(&state, "wan0")["loss"], 0.0);
+    assert!(probe(&state, "wan0")["rtt_ms"].is_number());
+    assert_eq!(probe(&state, "wan1")["paused"], false);
+}
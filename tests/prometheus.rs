@@ -0,0 +1,260 @@
+mod common;
+
+use common::{Instance, MockBackends, Script};
+
+#[tokio::test]
+async fn sends_the_bearer_token_with_every_query() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let config = format!(
+        "[prometheus]\nurl = \"{}\"\nbearer_token = \"secret\"\n\n[routing_service]\nurl = \"{}\"\n",
+        backends.url, backends.url
+    );
+    let instance = Instance::start(&config);
+
+    let log = backends
+        .wait_for("a switch", |log| !log.switches.is_empty())
+        .await;
+    assert!(instance.stop().await.success());
+    let queries: Vec<_> = log
+        .requests
+        .iter()
+        .filter(|request| request.path.starts_with("/api/v1/"))
+        .collect();
+    assert!(!queries.is_empty());
+    assert!(queries
+        .iter()
+        .all(|request| request.authorization.as_deref() == Some("Bearer secret")));
+}
+
+#[tokio::test]
+async fn queries_a_vmselect_tenant_with_extra_parameters() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let config = format!(
+        "[prometheus]\nurl = \"{}\"\ntenant = \"42:7\"\ntenant_mode = \"path\"\nquery_params = {{ dedup = \"true\" }}\n\n[routing_service]\nurl = \"{}\"\n",
+        backends.url, backends.url
+    );
+    let instance = Instance::start(&config);
+
+    let log = backends
+        .wait_for("a switch", |log| !log.switches.is_empty())
+        .await;
+    assert!(instance.stop().await.success());
+    let queries: Vec<_> = log
+        .requests
+        .iter()
+        .filter(|request| request.path.contains("/api/v1/"))
+        .collect();
+    assert!(!queries.is_empty());
+    assert!(queries.iter().all(|request| {
+        request.path == "/select/42:7/prometheus/api/v1/query" && request.query["dedup"] == "true"
+    }));
+}
+
+#[tokio::test]
+async fn sends_the_tenant_header() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let config = format!(
+        "[prometheus]\nurl = \"{}\"\ntenant = \"edge\"\n\n[routing_service]\nurl = \"{}\"\n",
+        backends.url, backends.url
+    );
+    let instance = Instance::start(&config);
+
+    let log = backends
+        .wait_for("a switch", |log| !log.switches.is_empty())
+        .await;
+    assert!(instance.stop().await.success());
+    assert!(log
+        .requests
+        .iter()
+        .filter(|request| request.path.starts_with("/api/v1/"))
+        .all(|request| request.tenant.as_deref() == Some("edge")));
+}
+
+#[tokio::test]
+async fn evaluates_a_query_window_with_range_queries() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let instance =
+        Instance::start(&backends.config(
+            "[query_window]\nwindow_secs = 60\nstep_secs = 15\nfunction = \"max_over_time\"",
+        ));
+
+    // The window's constant samples reduce to the instant values, so the same switch follows
+    let log = backends
+        .wait_for("a switch", |log| !log.switches.is_empty())
+        .await;
+    assert!(instance.stop().await.success());
+    assert_eq!(log.moves()[0], ("192.168.1.10", "wan1"));
+    let ranges: Vec<_> = log
+        .requests
+        .iter()
+        .filter(|request| request.path == "/api/v1/query_range")
+        .collect();
+    assert!(!ranges.is_empty());
+    for range in ranges {
+        let bound = |name: &str| range.query[name].parse::<u64>().unwrap();
+        assert_eq!(bound("end") - bound("start"), 60);
+        assert_eq!(range.query["step"], "15");
+    }
+}
+
+#[tokio::test]
+async fn fills_in_query_templates_per_interface() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let instance = Instance::start(&backends.config(
+        "[metric_schema]\nbandwidth_template = 'max_over_time(tcp_traffic_scan_tcp_bandwidth_avg_bps{interface=\"{nic}\"}[{window}])'\ntraffic_template = 'sum by (__name__, ip_address) ({__name__=~\"network_ip_rx_bps|network_ip_tx_bps\"})'",
+    ));
+
+    let log = backends
+        .wait_for("a switch", |log| !log.switches.is_empty())
+        .await;
+    assert!(instance.stop().await.success());
+    assert_eq!(log.moves()[0], ("192.168.1.10", "wan1"));
+    let queries: Vec<_> = log
+        .requests
+        .iter()
+        .filter(|request| request.path == "/api/v1/query")
+        .map(|request| request.query["query"].as_str())
+        .collect();
+    for nic in ["eth0", "eth1"] {
+        assert!(queries.contains(
+            &format!(
+                "max_over_time(tcp_traffic_scan_tcp_bandwidth_avg_bps{{interface=\"{}\"}}[30s])",
+                nic
+            )
+            .as_str()
+        ));
+    }
+}
+
+#[tokio::test]
+async fn skips_cycles_without_traffic_figures() {
+    let mut script = Script::two_wans();
+    script.traffic_bps.clear();
+    script.bandwidth_bps.clear();
+    let backends = MockBackends::start(script).await;
+    let instance = Instance::start(&backends.config(""));
+
+    let log = backends
+        .wait_for("20 cycles", |log| log.count("/status") >= 20)
+        .await;
+    assert!(instance.stop().await.success());
+    assert!(log.switches.is_empty());
+}
+
+/// RX of the busy client in the first report with `function` over a window of samples
+/// climbing from 0 to 20 Mbps.
+async fn reduced_window(function: &str) -> f64 {
+    let mut script = Script::two_wans();
+    script.range_ramps = true;
+    let backends = MockBackends::start(script).await;
+    let instance = Instance::start_with(
+        &backends.config(&format!(
+            "[query_window]\nwindow_secs = 60\nstep_secs = 15\nfunction = \"{}\"",
+            function
+        )),
+        &["--output", "json"],
+    );
+    backends
+        .wait_for("2 cycles", |log| log.count("/status") >= 2)
+        .await;
+
+    let (status, output) = instance.stop_with_report().await;
+    assert!(status.success());
+    let report: serde_json::Value = serde_json::from_str(output.lines().next().unwrap()).unwrap();
+    report["top_ips"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|top| top["ip"] == "192.168.1.10")
+        .unwrap()["rx_bps"]
+        .as_f64()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn reduces_the_window_by_the_configured_function() {
+    // 0, 5, 10, 15 and 20 Mbps
+    assert_eq!(reduced_window("avg_over_time").await, 10e6);
+    assert_eq!(reduced_window("max_over_time").await, 20e6);
+    let rate = reduced_window("rate").await;
+    assert!((rate - 20e6 / 60.0).abs() < 1e-6, "{}", rate);
+}
+
+#[tokio::test]
+async fn sends_basic_auth_credentials() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let config = format!(
+        "[prometheus]\nurl = \"{}\"\nusername = \"user\"\npassword = \"pass\"\n\n[routing_service]\nurl = \"{}\"\n",
+        backends.url, backends.url
+    );
+    let instance = Instance::start(&config);
+
+    let log = backends
+        .wait_for("a switch", |log| !log.switches.is_empty())
+        .await;
+    assert!(instance.stop().await.success());
+    assert!(log
+        .requests
+        .iter()
+        .filter(|request| request.path.starts_with("/api/v1/"))
+        .all(|request| request.authorization.as_deref() == Some("Basic dXNlcjpwYXNz")));
+}
+
+#[tokio::test]
+async fn reads_the_bearer_token_from_a_file() {
+    let token = std::env::temp_dir().join(format!("routingflow-test-{}.token", std::process::id()));
+    std::fs::write(&token, "from-file\n").unwrap();
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let config = format!(
+        "[prometheus]\nurl = \"{}\"\nbearer_token_file = {:?}\n\n[routing_service]\nurl = \"{}\"\n",
+        backends.url, token, backends.url
+    );
+    let instance = Instance::start(&config);
+
+    let log = backends
+        .wait_for("a switch", |log| !log.switches.is_empty())
+        .await;
+    assert!(instance.stop().await.success());
+    let _ = std::fs::remove_file(&token);
+    assert!(log
+        .requests
+        .iter()
+        .filter(|request| request.path.starts_with("/api/v1/"))
+        .all(|request| request.authorization.as_deref() == Some("Bearer from-file")));
+}
+
+#[tokio::test]
+async fn refuses_to_start_with_an_unreadable_ca_file() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let config = format!(
+        "[prometheus]\nurl = \"{}\"\nca_file = \"missing.pem\"\n\n[routing_service]\nurl = \"{}\"\n",
+        backends.url.replace("http://", "https://"),
+        backends.url
+    );
+    let instance = Instance::start(&config);
+
+    assert!(!instance.wait().await.success());
+    assert_eq!(backends.log().count("/status"), 0);
+}
+
+#[tokio::test]
+async fn speaks_tls_to_an_https_url() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let config = format!(
+        "[prometheus]\nurl = \"{}\"\ninsecure_skip_verify = true\n\n[routing_service]\nurl = \"{}\"\n\n[retry]\nmax_attempts = 1\n",
+        backends.url.replace("http://", "https://"),
+        backends.url
+    );
+    let instance = Instance::start(&config);
+
+    // The fake only speaks plain HTTP, so no query gets through the handshake
+    let log = backends
+        .wait_for("3 cycles", |log| log.count("/status") >= 3)
+        .await;
+    assert!(instance.stop().await.success());
+    assert!(log.switches.is_empty(), "{:?}", log.moves());
+    assert!(!log
+        .requests
+        .iter()
+        .any(|request| request.path.contains("/api/v1/")));
+}
@@ -0,0 +1,72 @@
+mod common;
+
+use common::{Instance, MockBackends, Script};
+
+#[tokio::test]
+async fn records_and_announces_a_changed_public_ip() {
+    let mut script = Script::two_wans();
+    script
+        .public_ips
+        .insert("wan0".to_string(), "203.0.113.7".to_string());
+    script
+        .public_ips
+        .insert("wan1".to_string(), "198.51.100.4".to_string());
+    let backends = MockBackends::start(script).await;
+    let instance = Instance::start(&backends.config(&format!(
+        "[public_ip]\ninterval_secs = 1\nsource = {{ kind = \"backend\", path = \"/public_ip\" }}\n\n\
+         [[events.webhooks]]\nurl = \"{}/hook\"\nevents = [\"public_ip_change\"]",
+        backends.url
+    )));
+
+    let log = backends
+        .wait_for("both addresses", |log| log.notifications.len() >= 2)
+        .await;
+    let mut found: Vec<_> = log
+        .notifications
+        .iter()
+        .map(|notification| {
+            let event = &notification.body;
+            (
+                event["wan"].as_str().unwrap().to_string(),
+                event["previous"].is_null(),
+                event["current"].as_str().unwrap().to_string(),
+            )
+        })
+        .collect();
+    found.sort();
+    assert_eq!(
+        found,
+        [
+            ("wan0".to_string(), true, "203.0.113.7".to_string()),
+            ("wan1".to_string(), true, "198.51.100.4".to_string())
+        ]
+    );
+
+    backends.update(|script| {
+        script
+            .public_ips
+            .insert("wan0".to_string(), "203.0.113.8".to_string());
+    });
+    let log = backends
+        .wait_for("the change", |log| log.notifications.len() >= 3)
+        .await;
+    let history = instance.command(&["history"]).await;
+    assert!(instance.stop().await.success());
+    let change = &log.notifications[2].body;
+    assert_eq!(change["type"], "public_ip_change");
+    assert_eq!(change["wan"], "wan0");
+    assert_eq!(change["nic"], "eth0");
+    assert_eq!(change["previous"], "203.0.113.7");
+    assert_eq!(change["current"], "203.0.113.8");
+    let history = String::from_utf8_lossy(&history.stdout);
+    assert!(
+        history.contains("wan0 public IP 203.0.113.7 → 203.0.113.8"),
+        "{}",
+        history
+    );
+    assert!(
+        history.contains("wan1 public IP ? → 198.51.100.4"),
+        "{}",
+        history
+    );
+}
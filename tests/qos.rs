@@ -0,0 +1,70 @@
+mod common;
+
+use common::{Instance, MockBackends, Script};
+use serde_json::Value;
+
+/// Queue state of `nic` in the last report.
+fn queue(output: &str, nic: &str) -> Value {
+    let last: Value = serde_json::from_str(output.lines().last().unwrap()).unwrap();
+    last["nics"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|stats| stats["nic"] == nic)
+        .unwrap()["queue"]
+        .clone()
+}
+
+#[tokio::test]
+async fn treats_a_wan_with_a_backlog_as_full() {
+    let mut script = Script::two_wans();
+    script.queues.insert("eth0".to_string(), (0, 0));
+    script.queues.insert("eth1".to_string(), (500, 0));
+    let backends = MockBackends::start(script).await;
+    let instance = Instance::start_with(&backends.config("[qos]"), &["--output", "json"]);
+
+    let log = backends
+        .wait_for("10 cycles", |log| log.count("/status") >= 10)
+        .await;
+    assert!(log.switches.is_empty(), "{:?}", log.moves());
+    backends.update(|script| {
+        script.queues.insert("eth1".to_string(), (0, 0));
+    });
+    let log = backends
+        .wait_for("a switch", |log| !log.switches.is_empty())
+        .await;
+    let (status, output) = instance.stop_with_report().await;
+    assert!(status.success());
+    assert_eq!(log.moves()[0], ("192.168.1.10", "wan1"));
+    assert_eq!(queue(&output, "eth1")["congested"], false);
+}
+
+#[tokio::test]
+async fn treats_a_wan_dropping_packets_as_full() {
+    let mut script = Script::two_wans();
+    script.queues.insert("eth1".to_string(), (0, 50));
+    // Quiet until the drop rate is known, which takes a second reading
+    script
+        .traffic_bps
+        .insert("192.168.1.10".to_string(), (5e5, 5e4));
+    let backends = MockBackends::start(script).await;
+    let instance = Instance::start_with(&backends.config("[qos]"), &["--output", "json"]);
+
+    backends
+        .wait_for("3 cycles", |log| log.count("/status") >= 3)
+        .await;
+    backends.update(|script| {
+        script
+            .traffic_bps
+            .insert("192.168.1.10".to_string(), (20e6, 2e6));
+    });
+    let log = backends
+        .wait_for("10 more cycles", |log| log.count("/status") >= 13)
+        .await;
+    let (status, output) = instance.stop_with_report().await;
+    assert!(status.success());
+    assert!(log.switches.is_empty(), "{:?}", log.moves());
+    let queue = queue(&output, "eth1");
+    assert_eq!(queue["drops_per_sec"], 50.0);
+    assert_eq!(queue["congested"], true);
+}
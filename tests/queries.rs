@@ -0,0 +1,19 @@
+mod common;
+
+use common::{Instance, MockBackends, Script};
+use std::time::Duration;
+
+#[tokio::test]
+async fn fetches_the_status_and_traffic_of_a_scan_at_once() {
+    let mut script = Script::two_wans();
+    script.latency = Duration::from_millis(200);
+    let backends = MockBackends::start(script).await;
+    let instance = Instance::start(&backends.config(""));
+
+    let log = backends
+        .wait_for("3 cycles", |log| log.count("/status") >= 3)
+        .await;
+    assert!(instance.stop().await.success());
+    // The status, and the TCP bandwidth and client traffic queries
+    assert!(log.max_in_flight >= 3, "{}", log.max_in_flight);
+}
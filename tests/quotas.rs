@@ -0,0 +1,66 @@
+mod common;
+
+use common::{Instance, MockBackends, Script};
+
+/// [`Script::two_wans`] with the busy .10 in `prefix` under a 10 Mbps quota, which sends it
+/// back to wan0 after a 10-second window.
+async fn over_quota(prefix: &str, per: &str) -> (MockBackends, Instance) {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let instance = Instance::start(&backends.config(&format!(
+        "[[quotas]]\nname = \"bulk\"\nprefixes = [\"{}\"]\nmbps = 10\nper = \"{}\"\n\
+         bulk_wan = \"wan0\"\nwindow_secs = 10\n\n\
+         [[events.webhooks]]\nurl = \"{}/hook\"\nevents = [\"quota\"]\n",
+        prefix, per, backends.url
+    )));
+    (backends, instance)
+}
+
+#[tokio::test]
+async fn steers_a_client_routinely_over_its_quota_to_the_bulk_wan() {
+    let (backends, instance) = over_quota("192.168.1.10/32", "client").await;
+    let log = backends
+        .wait_for("a quota event", |log| !log.notifications.is_empty())
+        .await;
+    let exceeded = log.notifications[0].clone();
+    let log = backends
+        .wait_for("the switch to the bulk WAN", |log| log.switches.len() >= 2)
+        .await;
+
+    // Back under once fewer than a quarter of the window's cycles are over
+    backends.update(|script| {
+        script
+            .traffic_bps
+            .insert("192.168.1.10".to_string(), (5e5, 5e4));
+    });
+    let released = backends
+        .wait_for("a second quota event", |log| log.notifications.len() >= 2)
+        .await;
+    assert!(instance.stop().await.success());
+    assert_eq!(
+        log.moves(),
+        [("192.168.1.10", "wan1"), ("192.168.1.10", "wan0")]
+    );
+    // Not judged before a whole window
+    assert!(log.switches[1].cycle >= 10, "{}", log.switches[1].cycle);
+    assert_eq!(exceeded.body["ip"], "192.168.1.10");
+    assert_eq!(exceeded.body["exceeded"], true);
+    assert_eq!(exceeded.body["bulk_wan"], "wan0");
+    let released = &released.notifications[1].body;
+    assert_eq!(released["ip"], "192.168.1.10");
+    assert_eq!(released["exceeded"], false);
+}
+
+#[tokio::test]
+async fn steers_a_whole_group_over_its_quota() {
+    // .10 and .11, 22.55 Mbps between them
+    let (backends, instance) = over_quota("192.168.1.10/31", "group").await;
+    let log = backends
+        .wait_for("the switch and the quota event", |log| {
+            log.switches.len() >= 2 && !log.notifications.is_empty()
+        })
+        .await;
+    assert!(instance.stop().await.success());
+    assert_eq!(log.moves()[1], ("192.168.1.10", "wan0"));
+    assert!(log.notifications[0].body["ip"].is_null());
+    assert_eq!(log.notifications[0].body["exceeded"], true);
+}
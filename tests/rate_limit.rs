@@ -0,0 +1,44 @@
+mod common;
+
+use common::{Instance, MockBackends, Script};
+
+/// Six clients of 15 Mbps on `wan0` (50 Mbps): three have to move to `wan1`.
+fn crowded() -> Script {
+    let mut script = Script::two_wans();
+    for host in 20..26 {
+        let ip = format!("192.168.1.{}", host);
+        script.traffic_bps.insert(ip.clone(), (15e6, 1e6));
+        script.mappings.insert(ip, "wan0".to_string());
+    }
+    script
+}
+
+#[tokio::test]
+async fn holds_switches_over_the_rate_limit_for_a_minute() {
+    let backends = MockBackends::start(crowded()).await;
+    let instance = Instance::start(&backends.config("[switch_rate_limit]\nmax_per_minute = 2"));
+
+    let log = backends
+        .wait_for("three switches", |log| log.switches.len() >= 3)
+        .await;
+    assert!(instance.stop().await.success());
+    let cycles: Vec<usize> = log.switches.iter().map(|switch| switch.cycle).collect();
+    assert!(cycles[1] < cycles[0] + 60, "{:?}", cycles);
+    assert!(cycles[2] >= cycles[0] + 60, "{:?}", cycles);
+}
+
+#[tokio::test]
+async fn moves_clients_as_fast_as_the_policy_plans_without_a_limit() {
+    let backends = MockBackends::start(crowded()).await;
+    let instance = Instance::start(&backends.config(""));
+
+    let log = backends
+        .wait_for("three switches", |log| log.switches.len() >= 3)
+        .await;
+    assert!(instance.stop().await.success());
+    assert!(
+        log.switches[2].cycle < log.switches[0].cycle + 10,
+        "{:?}",
+        log.switches
+    );
+}
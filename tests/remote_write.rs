@@ -0,0 +1,132 @@
+mod common;
+
+use axum::body::Bytes;
+use axum::extract::State;
+use axum::http::{HeaderMap, StatusCode};
+use axum::routing::post;
+use axum::Router;
+use common::{Instance, MockBackends, Script};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// The remote-write messages, as in prometheus/prompb.
+#[derive(Clone, PartialEq, prost::Message)]
+struct WriteRequest {
+    #[prost(message, repeated, tag = "1")]
+    timeseries: Vec<TimeSeries>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct TimeSeries {
+    #[prost(message, repeated, tag = "1")]
+    labels: Vec<Label>,
+    #[prost(message, repeated, tag = "2")]
+    samples: Vec<Sample>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct Label {
+    #[prost(string, tag = "1")]
+    name: String,
+    #[prost(string, tag = "2")]
+    value: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct Sample {
+    #[prost(double, tag = "1")]
+    value: f64,
+    #[prost(int64, tag = "2")]
+    timestamp: i64,
+}
+
+type Received = Arc<Mutex<Vec<WriteRequest>>>;
+
+async fn write(State(received): State<Received>, headers: HeaderMap, body: Bytes) -> StatusCode {
+    if headers["content-encoding"] != "snappy" {
+        return StatusCode::BAD_REQUEST;
+    }
+    let Ok(body) = snap::raw::Decoder::new().decompress_vec(&body) else {
+        return StatusCode::BAD_REQUEST;
+    };
+    let Ok(request) = prost::Message::decode(body.as_slice()) else {
+        return StatusCode::BAD_REQUEST;
+    };
+    received.lock().unwrap().push(request);
+    StatusCode::NO_CONTENT
+}
+
+/// A remote-write receiver and the requests it decoded.
+async fn receiver() -> (String, Received) {
+    let received = Received::default();
+    let app = Router::new()
+        .route("/api/v1/write", post(write))
+        .with_state(received.clone());
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/api/v1/write", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    (url, received)
+}
+
+#[tokio::test]
+async fn pushes_the_derived_series() {
+    let (url, received) = receiver().await;
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let instance = Instance::start(&backends.config(&format!(
+        "[remote_write]\nurl = \"{}\"\nlabels = {{ instance = \"edge-1\" }}\n\n\
+         [[traffic_classes]]\nname = \"heavy\"\nmin_rx_mbps = 10.0",
+        url
+    )));
+
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(30);
+    while received.lock().unwrap().is_empty() {
+        assert!(tokio::time::Instant::now() < deadline, "nothing written");
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert!(instance.stop().await.success());
+    let request = received.lock().unwrap()[0].clone();
+    let series: Vec<(BTreeMap<String, String>, f64)> = request
+        .timeseries
+        .iter()
+        .map(|series| {
+            let names: Vec<&str> = series
+                .labels
+                .iter()
+                .map(|label| label.name.as_str())
+                .collect();
+            let mut sorted = names.clone();
+            sorted.sort();
+            assert_eq!(names, sorted, "labels out of order");
+            let labels = series
+                .labels
+                .iter()
+                .map(|label| (label.name.clone(), label.value.clone()))
+                .collect();
+            (labels, series.samples[0].value)
+        })
+        .collect();
+    let find = |name: &str, matching: &[(&str, &str)]| {
+        series
+            .iter()
+            .find(|(labels, _)| {
+                labels["__name__"] == name
+                    && matching
+                        .iter()
+                        .all(|(label, value)| labels.get(*label).map(String::as_str) == Some(value))
+            })
+            .unwrap_or_else(|| panic!("no {} {:?} in {:?}", name, matching, series))
+    };
+
+    // 22.55 Mbps on the 50 Mbps eth0 before the first switch
+    let (labels, utilization) = find("routingflow_wan_utilization_smoothed", &[("wan", "wan0")]);
+    assert_eq!(labels["nic"], "eth0");
+    assert_eq!(labels["instance"], "edge-1");
+    assert!((utilization - 0.451).abs() < 1e-9, "{}", utilization);
+    let heavy = [("ip", "192.168.1.10"), ("direction", "rx")];
+    let (labels, rx) = find("routingflow_ip_classified_bps", &heavy);
+    assert_eq!(labels["class"], "heavy");
+    assert_eq!(*rx, 20e6);
+    let (labels, _) = find("routingflow_ip_classified_bps", &[("ip", "192.168.1.11")]);
+    assert_eq!(labels["class"], "unclassified");
+}
@@ -0,0 +1,68 @@
+mod common;
+
+use common::{Instance, MockBackends, Script};
+use serde_json::{json, Value};
+
+/// The JSON summary of replaying the instance's recordings with `config`.
+async fn replay(instance: &Instance, config: &str) -> Value {
+    let dir = instance.path("recordings");
+    let output = instance
+        .command(&[
+            "--config",
+            config,
+            "--output",
+            "json",
+            "replay",
+            "--dir",
+            dir.to_str().unwrap(),
+        ])
+        .await;
+    assert!(output.status.success(), "{:?}", output);
+    serde_json::from_slice(&output.stdout).unwrap()
+}
+
+#[tokio::test]
+async fn replays_recorded_cycles_through_another_policy() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let instance = Instance::start(&backends.config("[recording]\ndir = \"recordings\""));
+    backends
+        .wait_for("10 cycles", |log| log.count("/status") >= 10)
+        .await;
+
+    let recorded = replay(&instance, "routingflow.toml").await;
+    std::fs::write(
+        instance.path("weighted.toml"),
+        backends.config("policy = \"weighted\"\n\n[weighted]\nweights = { wan0 = 1, wan1 = 2 }"),
+    )
+    .unwrap();
+    let weighted = replay(&instance, "weighted.toml").await;
+    assert!(instance.stop().await.success());
+
+    assert_eq!(recorded["start"], 1_791_972_000);
+    assert_eq!(
+        recorded["switches"],
+        json!([{"timestamp": 1_791_972_000, "ip": "192.168.1.10", "from_wan": "wan0", "to_wan": "wan1"}])
+    );
+    // The quieter client of the two on wan0 makes up the weighted gap
+    assert_eq!(weighted["switches"][0]["ip"], "192.168.1.11");
+    assert_eq!(weighted["switches"][0]["to_wan"], "wan1");
+}
+
+#[tokio::test]
+async fn fails_with_nothing_recorded() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let instance = Instance::start(&backends.config(""));
+    backends
+        .wait_for("2 cycles", |log| log.count("/status") >= 2)
+        .await;
+    std::fs::create_dir(instance.path("recordings")).unwrap();
+
+    let output = instance.command(&["replay", "--dir", "recordings"]).await;
+    assert!(instance.stop().await.success());
+    assert!(!output.status.success());
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("Nothing recorded to replay"),
+        "{:?}",
+        output
+    );
+}
@@ -0,0 +1,64 @@
+mod common;
+
+use common::{Instance, MockBackends, Script};
+use serde_json::Value;
+
+/// The busy client is moved from `wan0` (50 Mbps) to `wan1` (200 Mbps), which it keeps
+/// above 90% busy.
+fn overloaded() -> Script {
+    let mut script = Script::two_wans();
+    script
+        .traffic_bps
+        .insert("192.168.1.10".to_string(), (180e6, 5e6));
+    script
+}
+
+#[tokio::test]
+async fn sums_up_the_stored_history_of_a_day() {
+    let backends = MockBackends::start(overloaded()).await;
+    let instance = Instance::start(&backends.config("[history]\nnic_stats_interval_secs = 1"));
+
+    backends
+        .wait_for("a switch and 20 cycles", |log| {
+            !log.switches.is_empty() && log.count("/status") >= 20
+        })
+        .await;
+    let output = instance
+        .command(&[
+            "report",
+            "--period",
+            "daily",
+            "--format",
+            "json",
+            "--until",
+            "2026-10-15T10:00:00+00:00",
+        ])
+        .await;
+    assert!(instance.stop().await.success());
+    assert!(output.status.success(), "{:?}", output);
+    let report: Value = serde_json::from_slice(&output.stdout).unwrap();
+
+    let wans = report["wans"].as_array().unwrap();
+    assert_eq!(wans.len(), 2, "{}", report);
+    assert_eq!(wans[1]["wan"], "wan1");
+    assert!(
+        wans[1]["utilization_p50"].as_f64().unwrap() > 0.9,
+        "{}",
+        report
+    );
+    assert!(
+        wans[1]["saturated_secs"].as_u64().unwrap() >= 15,
+        "{}",
+        report
+    );
+    assert!(
+        wans[0]["utilization_p50"].as_f64().unwrap() < 0.1,
+        "{}",
+        report
+    );
+
+    assert_eq!(report["switches"][0]["ip"], "192.168.1.10", "{}", report);
+    assert_eq!(report["switches"][0]["switches"], 1, "{}", report);
+    assert_eq!(report["top_talkers"][0]["ip"], "192.168.1.10", "{}", report);
+    assert_eq!(report["top_talkers"].as_array().unwrap().len(), 3);
+}
@@ -0,0 +1,61 @@
+mod common;
+
+use common::{Instance, MockBackends, Script};
+
+/// 195 of wan1's 200 Mbps reserved for `prefix` during `schedule`.
+fn reserving(backends: &MockBackends, prefix: &str, schedule: &str) -> String {
+    backends.config(&format!(
+        "[[reservations]]\nname = \"office\"\nwan = \"wan1\"\nmbps = 195\nprefixes = [\"{}\"]\nschedule = {}",
+        prefix, schedule
+    ))
+}
+
+#[tokio::test]
+async fn keeps_other_clients_out_of_reserved_headroom() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let instance = Instance::start(&reserving(
+        &backends,
+        "192.168.1.12/32",
+        "{ start = \"00:00\", end = \"00:00\" }",
+    ));
+
+    // 22 Mbps does not fit into the 5 Mbps left over
+    let log = backends
+        .wait_for("10 cycles", |log| log.count("/status") >= 10)
+        .await;
+    assert!(instance.stop().await.success());
+    assert!(log.switches.is_empty(), "{:?}", log.moves());
+}
+
+#[tokio::test]
+async fn lets_the_group_use_its_reservation() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let instance = Instance::start(&reserving(
+        &backends,
+        "192.168.1.0/24",
+        "{ start = \"00:00\", end = \"00:00\" }",
+    ));
+
+    let log = backends
+        .wait_for("a switch", |log| !log.switches.is_empty())
+        .await;
+    assert!(instance.stop().await.success());
+    assert_eq!(log.moves()[0], ("192.168.1.10", "wan1"));
+}
+
+#[tokio::test]
+async fn releases_the_reservation_outside_its_window() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let instance = Instance::start(&reserving(
+        &backends,
+        "192.168.1.12/32",
+        // The simulated clock starts on a Wednesday
+        "{ days = [\"thu\"], start = \"00:00\", end = \"00:00\" }",
+    ));
+
+    let log = backends
+        .wait_for("a switch", |log| !log.switches.is_empty())
+        .await;
+    assert!(instance.stop().await.success());
+    assert_eq!(log.moves()[0], ("192.168.1.10", "wan1"));
+}
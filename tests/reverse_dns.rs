@@ -0,0 +1,58 @@
+mod common;
+
+use common::{Instance, MockBackends, Script};
+use serde_json::Value;
+use std::net::SocketAddr;
+use tokio::net::UdpSocket;
+
+/// A resolver answering the PTR query for 192.168.1.10 with `workstation.lan` and every
+/// other one with NXDOMAIN.
+async fn resolver() -> SocketAddr {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = socket.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut buffer = [0u8; 512];
+        loop {
+            let (length, peer) = socket.recv_from(&mut buffer).await.unwrap();
+            let query = &buffer[..length];
+            let mut answer = query.to_vec();
+            // A response, recursion available
+            answer[2] = 0x81;
+            answer[3] = 0x80;
+            let wanted = b"\x0210\x011\x03168\x03192\x07in-addr";
+            if query.windows(wanted.len()).any(|name| name == wanted) {
+                answer[7] = 1;
+                // The name of the question, then PTR IN, TTL 60
+                answer.extend_from_slice(&[0xC0, 0x0C, 0, 12, 0, 1, 0, 0, 0, 60]);
+                let name = b"\x0bworkstation\x03lan\x00";
+                answer.extend_from_slice(&(name.len() as u16).to_be_bytes());
+                answer.extend_from_slice(name);
+            } else {
+                answer[3] |= 3;
+            }
+            socket.send_to(&answer, peer).await.unwrap();
+        }
+    });
+    addr
+}
+
+#[tokio::test]
+async fn names_clients_by_their_ptr_records() {
+    let server = resolver().await;
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let instance = Instance::start_with(
+        &backends.config(&format!("[reverse_dns]\nserver = \"{}\"", server)),
+        &["--output", "json"],
+    );
+    backends
+        .wait_for("20 cycles", |log| log.count("/status") >= 20)
+        .await;
+
+    let (status, output) = instance.stop_with_report().await;
+    assert!(status.success());
+    let report: Value = serde_json::from_str(output.lines().last().unwrap()).unwrap();
+    assert_eq!(
+        report["hostnames"],
+        serde_json::json!({"192.168.1.10": "workstation.lan"})
+    );
+}
@@ -0,0 +1,75 @@
+mod common;
+
+use common::{Instance, MockBackends, Script};
+use serde_json::Value;
+
+const ROLLBACK: &str = "[rollback]\ngrace_secs = 10\nbackoff_secs = 600";
+
+#[tokio::test]
+async fn reverts_a_move_that_overloaded_its_target() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let instance = Instance::start_with(&backends.config(ROLLBACK), &["--output", "json"]);
+
+    let log = backends
+        .wait_for("a switch", |log| !log.switches.is_empty())
+        .await;
+    let moved = log.switches[0].cycle;
+    // A 199 Mbps download on wan1 leaves it no headroom next to the moved client
+    backends.update(|script| {
+        script
+            .traffic_bps
+            .insert("192.168.1.12".to_string(), (199e6, 0.0));
+    });
+    let log = backends
+        .wait_for("the revert", |log| log.switches.len() >= 2)
+        .await;
+    let reverted = log.switches[1].cycle;
+    let log = backends
+        .wait_for("10 more cycles", |log| {
+            log.count("/status") >= reverted + 10
+        })
+        .await;
+
+    let (status, output) = instance.stop_with_report().await;
+    assert!(status.success());
+    assert_eq!(
+        log.moves(),
+        [("192.168.1.10", "wan1"), ("192.168.1.10", "wan0")]
+    );
+    assert!(
+        (moved + 10..=moved + 12).contains(&reverted),
+        "{}",
+        reverted
+    );
+    let decisions: Vec<Value> = output
+        .lines()
+        .map(|line| serde_json::from_str::<Value>(line).unwrap())
+        .flat_map(|report| report["decisions"].as_array().unwrap().clone())
+        .filter(|decision| decision["ip"] == "192.168.1.10")
+        .collect();
+    assert!(
+        decisions
+            .iter()
+            .any(|decision| decision["outcome"] == "switched"
+                && decision["reason"]
+                    == "rollback: the move overloaded wan1 (eth1 has no headroom left)"),
+        "{:?}",
+        decisions
+    );
+}
+
+#[tokio::test]
+async fn keeps_a_move_that_relieved_its_nic() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let instance = Instance::start(&backends.config(ROLLBACK));
+
+    let log = backends
+        .wait_for("a switch", |log| !log.switches.is_empty())
+        .await;
+    let moved = log.switches[0].cycle;
+    let log = backends
+        .wait_for("20 more cycles", |log| log.count("/status") >= moved + 20)
+        .await;
+    assert!(instance.stop().await.success());
+    assert_eq!(log.moves(), [("192.168.1.10", "wan1")]);
+}
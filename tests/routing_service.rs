@@ -0,0 +1,155 @@
+mod common;
+
+use common::{Instance, MockBackends, Script};
+use serde_json::{json, Value};
+
+#[tokio::test]
+async fn balances_around_malformed_mappings() {
+    let mut script = Script::two_wans();
+    script
+        .mappings
+        .insert("not-an-ip".to_string(), "wan0".to_string());
+    script
+        .mappings
+        .insert("192.168.1.13".to_string(), "wan 1".to_string());
+    let backends = MockBackends::start(script).await;
+    let instance = Instance::start(&backends.config(""));
+
+    let log = backends
+        .wait_for("a switch", |log| !log.switches.is_empty())
+        .await;
+    assert!(instance.stop().await.success());
+    assert_eq!(log.moves()[0], ("192.168.1.10", "wan1"));
+}
+
+#[tokio::test]
+async fn ignores_config_fields_other_than_wans() {
+    let mut script = Script::two_wans();
+    script.extra_config.insert("dns".to_string(), json!("eth3"));
+    script.extra_config.insert("version".to_string(), json!(3));
+    script
+        .extra_config
+        .insert("wan2".to_string(), json!({ "nic": "eth4" }));
+    let backends = MockBackends::start(script).await;
+    let instance = Instance::start(&backends.config(""));
+
+    let log = backends
+        .wait_for("a switch", |log| !log.switches.is_empty())
+        .await;
+    assert!(instance.stop().await.success());
+    assert_eq!(log.moves()[0], ("192.168.1.10", "wan1"));
+    assert!(log
+        .moves()
+        .iter()
+        .all(|(_, wan)| *wan == "wan0" || *wan == "wan1"));
+}
+
+#[tokio::test]
+async fn authenticates_every_call_to_the_service() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let config = format!(
+        "[prometheus]\nurl = \"{}\"\n\n[routing_service]\nurl = \"{}\"\nbearer_token = \"service-token\"\nheaders = {{ X-API-Key = \"api-key\" }}\n",
+        backends.url, backends.url
+    );
+    let instance = Instance::start(&config);
+
+    let log = backends
+        .wait_for("a switch", |log| !log.switches.is_empty())
+        .await;
+    assert!(instance.stop().await.success());
+    let (service, prometheus): (Vec<_>, Vec<_>) = log
+        .requests
+        .iter()
+        .partition(|request| request.path == "/status" || request.path == "/switch");
+    assert!(service.iter().any(|request| request.path == "/switch"));
+    assert!(service.iter().all(|request| {
+        request.authorization.as_deref() == Some("Bearer service-token")
+            && request.headers.get("x-api-key").map(String::as_str) == Some("api-key")
+    }));
+    // The service's credentials stay with the service
+    assert!(prometheus.iter().all(|request| {
+        request.authorization.is_none() && !request.headers.contains_key("x-api-key")
+    }));
+}
+
+#[tokio::test]
+async fn refuses_to_start_without_its_token_file() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let config = format!(
+        "[prometheus]\nurl = \"{}\"\n\n[routing_service]\nurl = \"{}\"\nbearer_token_file = \"missing.token\"\n",
+        backends.url, backends.url
+    );
+    let instance = Instance::start(&config);
+
+    assert!(!instance.wait().await.success());
+    assert_eq!(backends.log().count("/status"), 0);
+}
+
+/// The harness's config with the dry run at `/switch/validate`.
+fn validating(backends: &MockBackends) -> String {
+    backends.config("").replace(
+        "[routing_service]\n",
+        "[routing_service]\nvalidate_path = \"/switch/validate\"\n",
+    )
+}
+
+#[tokio::test]
+async fn skips_a_switch_the_dry_run_rejects() {
+    let mut script = Script::two_wans();
+    script.rejections.insert(
+        "192.168.1.10".to_string(),
+        "conflicts with a static route".to_string(),
+    );
+    let backends = MockBackends::start(script).await;
+    let instance = Instance::start_with(&validating(&backends), &["--output", "json"]);
+
+    let log = backends
+        .wait_for("5 cycles", |log| log.count("/status") >= 5)
+        .await;
+    let (status, output) = instance.stop_with_report().await;
+    assert!(status.success());
+    assert!(log.switches.is_empty(), "{:?}", log.moves());
+    assert!(log.count("/switch/validate") >= 1);
+    let decision = output
+        .lines()
+        .map(|line| serde_json::from_str::<Value>(line).unwrap())
+        .flat_map(|report| report["decisions"].as_array().unwrap().clone())
+        .find(|decision| decision["ip"] == "192.168.1.10")
+        .unwrap();
+    assert_eq!(decision["outcome"], "skipped");
+    assert_eq!(
+        decision["reason"],
+        "the routing service's dry run rejected the move to wan1: conflicts with a static route"
+    );
+}
+
+#[tokio::test]
+async fn applies_a_switch_the_dry_run_accepts() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let instance = Instance::start(&validating(&backends));
+
+    let log = backends
+        .wait_for("a switch", |log| !log.switches.is_empty())
+        .await;
+    assert!(instance.stop().await.success());
+    assert_eq!(log.moves()[0], ("192.168.1.10", "wan1"));
+    let calls: Vec<_> = log
+        .requests
+        .iter()
+        .filter(|request| request.path.starts_with("/switch"))
+        .map(|request| {
+            (
+                request.path.as_str(),
+                request.query["ip"].as_str(),
+                request.query["nic"].as_str(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        calls[..2],
+        [
+            ("/switch/validate", "192.168.1.10", "wan1"),
+            ("/switch", "192.168.1.10", "wan1")
+        ]
+    );
+}
@@ -0,0 +1,52 @@
+mod common;
+
+use common::{Instance, MockBackends, Script, SAMPLE_TIME};
+use serde_json::Value;
+
+/// Every client barely busy, so the adaptive interval backs off to its longest.
+fn quiet() -> Script {
+    let mut script = Script::two_wans();
+    for traffic in script.traffic_bps.values_mut() {
+        *traffic = (5e5, 5e4);
+    }
+    script
+}
+
+#[tokio::test]
+async fn polls_slowly_while_the_wans_are_quiet() {
+    let backends = MockBackends::start(quiet()).await;
+    let instance =
+        Instance::start(&backends.config(
+            "[scan]\ninterval_ms = 1000\nmax_interval_ms = 10000\n\n[audit]\ndir = \"audit\"",
+        ));
+
+    backends
+        .wait_for("five cycles", |log| log.count("/status") >= 5)
+        .await;
+    backends.update(|script| {
+        script
+            .traffic_bps
+            .insert("192.168.1.10".to_string(), (60e6, 5e6));
+    });
+    let log = backends
+        .wait_for("a switch", |log| !log.switches.is_empty())
+        .await;
+    let audit = std::fs::read_to_string(instance.path("audit/decisions-20261014.jsonl")).unwrap();
+    assert!(instance.stop().await.success());
+
+    let switch = audit
+        .lines()
+        .map(|line| serde_json::from_str::<Value>(line).unwrap())
+        .find(|record| record["outcome"] == "switched")
+        .unwrap();
+    let elapsed = switch["timestamp"].as_u64().unwrap() - SAMPLE_TIME as u64;
+    // Ten simulated seconds between the quiet cycles, where a fixed interval takes one
+    let cycle = log.switches[0].cycle as u64;
+    assert!(cycle >= 6, "{:?}", log.switches);
+    assert!(
+        elapsed >= 10 * (cycle - 2),
+        "{}s by cycle {}",
+        elapsed,
+        cycle
+    );
+}
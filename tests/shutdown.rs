@@ -0,0 +1,60 @@
+mod common;
+
+use common::{Instance, MockBackends, Script};
+use serde_json::Value;
+use std::time::{Duration, Instant};
+
+/// The lines the instance logged with `message`.
+fn logged(log: &str, message: &str) -> Vec<Value> {
+    log.lines()
+        .map(|line| serde_json::from_str::<Value>(line).unwrap())
+        .filter(|line| line["message"] == message)
+        .collect()
+}
+
+#[tokio::test]
+async fn finishes_the_switch_in_flight_before_stopping() {
+    let mut script = Script::two_wans();
+    script.latency = Duration::from_millis(300);
+    let backends = MockBackends::start(script).await;
+    let instance = Instance::start(&backends.config("[logging]\nformat = \"json\""));
+
+    // Made, and not yet answered
+    backends
+        .wait_for("a switch request", |log| log.count("/switch") >= 1)
+        .await;
+    instance.signal(libc::SIGTERM);
+    let (status, output) = instance.wait_with_log().await;
+    let log = backends.log();
+    assert!(status.success());
+    assert_eq!(log.moves(), [("192.168.1.10", "wan1")]);
+    assert_eq!(
+        logged(&output, "Stopping after the current cycle")[0]["signal"],
+        "SIGTERM"
+    );
+    // The answer to the switch arrived after the signal and was still counted
+    let stopped = &logged(&output, "Stopped")[0];
+    assert_eq!(stopped["switched"], 1, "{}", output);
+    assert_eq!(stopped["failed"], 0);
+    assert_eq!(stopped["cycles"], 1);
+}
+
+#[tokio::test]
+async fn exits_at_once_on_a_second_signal() {
+    let mut script = Script::two_wans();
+    script.latency = Duration::from_secs(10);
+    let backends = MockBackends::start(script).await;
+    let instance = Instance::start(&backends.config("[logging]\nformat = \"json\""));
+
+    backends
+        .wait_for("a status request", |log| log.count("/status") >= 1)
+        .await;
+    let started = Instant::now();
+    instance.signal(libc::SIGINT);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    instance.signal(libc::SIGINT);
+    let (status, output) = instance.wait_with_log().await;
+    assert!(started.elapsed() < Duration::from_secs(5));
+    assert_eq!(status.code(), Some(130));
+    assert!(logged(&output, "Stopped").is_empty(), "{}", output);
+}
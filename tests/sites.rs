@@ -0,0 +1,52 @@
+mod common;
+
+use common::{Instance, MockBackends, Script};
+
+#[tokio::test]
+async fn balances_every_site_against_its_own_routing_service() {
+    let edge_a = MockBackends::start(Script::two_wans()).await;
+    let edge_b = MockBackends::start(Script::two_wans()).await;
+    let config = format!(
+        "[prometheus]\nurl = \"{}\"\n\n[retry]\nmax_attempts = 1\n\n\
+         [[sites]]\nname = \"edge-a\"\n[sites.routing_service]\nurl = \"{}\"\n\n\
+         [[sites]]\nname = \"edge-b\"\n[sites.routing_service]\nurl = \"{}\"\n\
+         [sites.prometheus]\nurl = \"{}\"\n[sites.metric_schema]\nmatchers = 'instance=\"edge-b\"'\n",
+        edge_a.url, edge_a.url, edge_b.url, edge_b.url
+    );
+    let instance = Instance::start(&config);
+
+    let a = edge_a
+        .wait_for("a switch at edge-a", |log| !log.switches.is_empty())
+        .await;
+    let b = edge_b
+        .wait_for("a switch at edge-b", |log| !log.switches.is_empty())
+        .await;
+    assert!(instance.stop().await.success());
+    assert_eq!(a.moves()[0], ("192.168.1.10", "wan1"));
+    assert_eq!(b.moves()[0], ("192.168.1.10", "wan1"));
+    let b_queries: Vec<_> = b
+        .requests
+        .iter()
+        .filter(|request| request.path == "/api/v1/query")
+        .collect();
+    assert!(!b_queries.is_empty());
+    assert!(b_queries
+        .iter()
+        .all(|request| request.query["query"].contains("instance=\"edge-b\"")));
+}
+
+#[tokio::test]
+async fn balances_onto_every_wan_the_routing_service_reports() {
+    let mut script = Script::two_wans();
+    script.wans.insert("wan2".to_string(), "eth3".to_string());
+    script.bandwidth_bps.insert("eth1".to_string(), 20e6);
+    script.bandwidth_bps.insert("eth3".to_string(), 500e6);
+    let backends = MockBackends::start(script).await;
+    let instance = Instance::start(&backends.config(""));
+
+    let log = backends
+        .wait_for("a switch", |log| !log.switches.is_empty())
+        .await;
+    assert!(instance.stop().await.success());
+    assert_eq!(log.moves()[0], ("192.168.1.10", "wan2"));
+}
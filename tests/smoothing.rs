@@ -0,0 +1,84 @@
+mod common;
+
+use common::{Instance, MockBackends, Script};
+use serde_json::Value;
+
+/// RX of `ip` in every report, in order.
+fn rx_of(output: &str, ip: &str) -> Vec<f64> {
+    output
+        .lines()
+        .map(|line| serde_json::from_str::<Value>(line).unwrap())
+        .filter_map(|report| {
+            report["top_ips"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .find(|top| top["ip"] == ip)
+                .map(|top| top["rx_bps"].as_f64().unwrap())
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn follows_a_step_in_traffic_by_the_configured_weight() {
+    let mut script = Script::two_wans();
+    script
+        .traffic_bps
+        .insert("192.168.1.10".to_string(), (5e5, 5e4));
+    let backends = MockBackends::start(script).await;
+    let instance = Instance::start_with(
+        &backends.config("[smoothing]\nalpha = 0.5"),
+        &["--output", "json"],
+    );
+    backends
+        .wait_for("3 cycles", |log| log.count("/status") >= 3)
+        .await;
+    backends.update(|script| {
+        script
+            .traffic_bps
+            .insert("192.168.1.10".to_string(), (20.5e6, 5e4));
+    });
+    let cycles = backends.log().count("/status");
+    backends
+        .wait_for("10 more cycles", |log| log.count("/status") >= cycles + 10)
+        .await;
+
+    let (status, output) = instance.stop_with_report().await;
+    assert!(status.success());
+    let rx = rx_of(&output, "192.168.1.10");
+    let step = rx.iter().position(|rx| *rx != 5e5).unwrap();
+    assert!(step > 0, "{:?}", rx);
+    // Half of the remaining distance per cycle
+    for (k, rx) in rx[step..step + 8].iter().enumerate() {
+        let expected = 20.5e6 - 20e6 * 0.5f64.powi(k as i32 + 1);
+        assert!((rx - expected).abs() < 1.0, "{:?}", rx);
+    }
+}
+
+#[tokio::test]
+async fn passes_raw_readings_without_smoothing() {
+    let mut script = Script::two_wans();
+    script
+        .traffic_bps
+        .insert("192.168.1.10".to_string(), (5e5, 5e4));
+    let backends = MockBackends::start(script).await;
+    let instance = Instance::start_with(&backends.config(""), &["--output", "json"]);
+    backends
+        .wait_for("3 cycles", |log| log.count("/status") >= 3)
+        .await;
+    backends.update(|script| {
+        script
+            .traffic_bps
+            .insert("192.168.1.10".to_string(), (20.5e6, 5e4));
+    });
+    let cycles = backends.log().count("/status");
+    backends
+        .wait_for("3 more cycles", |log| log.count("/status") >= cycles + 3)
+        .await;
+
+    let (status, output) = instance.stop_with_report().await;
+    assert!(status.success());
+    let rx = rx_of(&output, "192.168.1.10");
+    assert!(rx.iter().all(|rx| *rx == 5e5 || *rx == 20.5e6), "{:?}", rx);
+    assert_eq!(*rx.last().unwrap(), 20.5e6);
+}
@@ -0,0 +1,172 @@
+mod common;
+
+use common::{Instance, MockBackends, Script};
+use serde_json::Value;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+
+const COUNTER32: u8 = 0x41;
+const COUNTER64: u8 = 0x46;
+
+/// A BER TLV with a short-form length, all these small messages need.
+fn tlv(tag: u8, content: &[u8]) -> Vec<u8> {
+    assert!(content.len() < 0x80);
+    let mut out = vec![tag, content.len() as u8];
+    out.extend_from_slice(content);
+    out
+}
+
+/// The content of the TLV at `offset` and the offset after it.
+fn read(packet: &[u8], offset: usize) -> (&[u8], usize) {
+    let length = usize::from(packet[offset + 1]);
+    (
+        &packet[offset + 2..offset + 2 + length],
+        offset + 2 + length,
+    )
+}
+
+/// `value` as a BER unsigned integer: big-endian, minimal, with a leading zero when the top
+/// bit is set.
+fn unsigned(value: u64) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    let first = bytes.iter().position(|byte| *byte != 0).unwrap_or(7);
+    let mut out = bytes[first..].to_vec();
+    if out[0] & 0x80 != 0 {
+        out.insert(0, 0);
+    }
+    out
+}
+
+/// An SNMP v2c agent whose two counters, in and out, count `rx_octets` and `tx_octets` a
+/// simulated second from `start`, as counters of type `tag` (wrapping at 32 bits for
+/// Counter32); `elapsed` tells the simulated seconds. Returns its address and the number
+/// of requests it has answered.
+async fn agent(
+    tag: u8,
+    start: u64,
+    rx_octets: u64,
+    tx_octets: u64,
+    elapsed: impl Fn() -> usize + Send + 'static,
+) -> (SocketAddr, Arc<AtomicUsize>) {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = socket.local_addr().unwrap();
+    let answered = Arc::new(AtomicUsize::new(0));
+    let counter = answered.clone();
+    tokio::spawn(async move {
+        let mut buffer = [0u8; 512];
+        loop {
+            let (length, peer) = socket.recv_from(&mut buffer).await.unwrap();
+            let (message, _) = read(&buffer[..length], 0);
+            let (_, offset) = read(message, 0);
+            let (community, offset) = read(message, offset);
+            let (pdu, _) = read(message, offset);
+            let (request_id, offset) = read(pdu, 0);
+            let (_, offset) = read(pdu, offset);
+            let (_, offset) = read(pdu, offset);
+            let (bindings, _) = read(pdu, offset);
+
+            let elapsed = elapsed() as f64;
+            let mut values = Vec::new();
+            let mut offset = 0;
+            for octets in [rx_octets, tx_octets] {
+                let (binding, next) = read(bindings, offset);
+                offset = next;
+                let (oid, _) = read(binding, 0);
+                let mut value = start + (elapsed * octets as f64) as u64;
+                if tag == COUNTER32 {
+                    value &= u64::from(u32::MAX);
+                }
+                let mut binding = tlv(0x06, oid);
+                binding.extend(tlv(tag, &unsigned(value)));
+                values.extend(tlv(0x30, &binding));
+            }
+            let mut response = tlv(0x02, request_id);
+            response.extend(tlv(0x02, &[0]));
+            response.extend(tlv(0x02, &[0]));
+            response.extend(tlv(0x30, &values));
+            let mut reply = tlv(0x02, &[1]);
+            reply.extend(tlv(0x04, community));
+            reply.extend(tlv(0xA2, &response));
+            socket.send_to(&tlv(0x30, &reply), peer).await.unwrap();
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+    });
+    (addr, answered)
+}
+
+/// RX and TX of eth1 in the last report of a run against an [`agent`] for wan1 counting
+/// from `start` with counters of type `tag`, once it has been polled 3 times.
+async fn polled_rates(tag: u8, start: u64, rx_octets: u64, tx_octets: u64) -> (f64, f64) {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let (agent, answered) = agent(tag, start, rx_octets, tx_octets, backends.cycles()).await;
+    // Long enough between polls that a cycle more or less does not show in the rates
+    let instance = Instance::start_with(
+        &backends.config(&format!(
+            "[snmp]\nmode = \"replace\"\ninterval_secs = 60\n\n\
+             [[snmp.wans]]\nwan = \"wan1\"\nhost = \"{}\"\n\
+             in_octets_oid = \"1.3.6.1.2.1.31.1.1.1.6.2\"\nout_octets_oid = \".1.3.6.1.2.1.31.1.1.1.10.2\"",
+            agent
+        )),
+        &["--output", "json"],
+    );
+    tokio::time::timeout(Duration::from_secs(30), async {
+        while answered.load(Ordering::Relaxed) < 3 {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await
+    .expect("3 SNMP polls");
+    let cycles = backends.log().count("/status");
+    backends
+        .wait_for("2 more cycles", |log| log.count("/status") >= cycles + 2)
+        .await;
+
+    let (status, output) = instance.stop_with_report().await;
+    assert!(status.success());
+    let last: Value = serde_json::from_str(output.lines().last().unwrap()).unwrap();
+    let eth1 = last["nics"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|stats| stats["nic"] == "eth1")
+        .unwrap()
+        .clone();
+    (
+        eth1["rx_bps"].as_f64().unwrap(),
+        eth1["tx_bps"].as_f64().unwrap(),
+    )
+}
+
+fn assert_close(actual: f64, expected: f64) {
+    assert!(
+        (actual - expected).abs() < expected * 0.05,
+        "{} against {}",
+        actual,
+        expected
+    );
+}
+
+#[tokio::test]
+async fn replaces_the_wan_totals_with_the_modem_counters() {
+    // Past 32 bits from the start
+    let (rx_bps, tx_bps) = polled_rates(COUNTER64, 1 << 40, 10_000_000, 1_000_000).await;
+    assert_close(rx_bps, 80e6);
+    assert_close(tx_bps, 8e6);
+}
+
+#[tokio::test]
+async fn follows_a_32_bit_counter_through_its_wrap() {
+    // Wraps within the first simulated second
+    let (rx_bps, tx_bps) = polled_rates(
+        COUNTER32,
+        u64::from(u32::MAX) - 5_000_000,
+        10_000_000,
+        1_000_000,
+    )
+    .await;
+    assert_close(rx_bps, 80e6);
+    assert_close(tx_bps, 8e6);
+}
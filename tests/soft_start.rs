@@ -0,0 +1,75 @@
+mod common;
+
+use common::{Instance, MockBackends, Script};
+use serde_json::Value;
+
+/// [`Script::two_wans`] with four quiet clients on wan1 pinned to wan0, which all want to
+/// move at once.
+fn crowd(backends: &MockBackends, soft_start: &str) -> String {
+    let pins: Vec<String> = (13..17)
+        .map(|host| format!("\"192.168.1.{}\" = \"wan0\"", host))
+        .collect();
+    backends.config(&format!(
+        "[soft_start]\n{}\n\n[pinned_ips]\n{}",
+        soft_start,
+        pins.join("\n")
+    ))
+}
+
+async fn start() -> MockBackends {
+    let mut script = Script::two_wans();
+    script
+        .traffic_bps
+        .insert("192.168.1.10".to_string(), (5e5, 5e4));
+    for host in 13..17 {
+        let ip = format!("192.168.1.{}", host);
+        script.traffic_bps.insert(ip.clone(), (5e5, 5e4));
+        script.mappings.insert(ip, "wan1".to_string());
+    }
+    MockBackends::start(script).await
+}
+
+#[tokio::test]
+async fn makes_one_move_a_minute_at_the_start_of_the_ramp() {
+    let backends = start().await;
+    let instance = Instance::start_with(
+        &crowd(
+            &backends,
+            "ramp_secs = 600\ninitial_moves_per_min = 1\nmoves_per_min = 2",
+        ),
+        &["--output", "json"],
+    );
+
+    let log = backends
+        .wait_for("three moves", |log| log.switches.len() >= 3)
+        .await;
+    let (status, output) = instance.stop_with_report().await;
+    assert!(status.success());
+    let cycles: Vec<usize> = log.switches.iter().map(|switch| switch.cycle).collect();
+    assert!(cycles[1] >= cycles[0] + 60, "{:?}", cycles);
+    assert!(cycles[2] >= cycles[1] + 60, "{:?}", cycles);
+    let held = output
+        .lines()
+        .map(|line| serde_json::from_str::<Value>(line).unwrap())
+        .flat_map(|report| report["decisions"].as_array().unwrap().clone())
+        .find(|decision| decision["outcome"] == "held")
+        .unwrap();
+    assert_eq!(held["hold_reason"], "soft start (1 moves/min)");
+}
+
+#[tokio::test]
+async fn lifts_the_limit_at_the_end_of_the_ramp() {
+    let backends = start().await;
+    let instance = Instance::start(&crowd(
+        &backends,
+        "ramp_secs = 30\ninitial_moves_per_min = 1\nmoves_per_min = 2",
+    ));
+
+    let log = backends
+        .wait_for("every move", |log| log.switches.len() >= 4)
+        .await;
+    assert!(instance.stop().await.success());
+    let cycles: Vec<usize> = log.switches.iter().map(|switch| switch.cycle).collect();
+    assert!(cycles[1] >= cycles[0] + 29, "{:?}", cycles);
+    assert_eq!(cycles[1], cycles[3], "{:?}", cycles);
+}
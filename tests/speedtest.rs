@@ -0,0 +1,83 @@
+mod common;
+
+use common::{Instance, MockBackends, Script};
+use serde_json::Value;
+
+/// Reasons of the skipped decisions about `ip` in any report.
+fn skipped(output: &str, ip: &str) -> Vec<String> {
+    output
+        .lines()
+        .map(|line| serde_json::from_str::<Value>(line).unwrap())
+        .flat_map(|report| report["decisions"].as_array().unwrap().clone())
+        .filter(|decision| decision["ip"] == ip && decision["outcome"] == "skipped")
+        .map(|decision| decision["reason"].as_str().unwrap().to_string())
+        .collect()
+}
+
+#[tokio::test]
+async fn holds_back_rebalancing_while_a_measurement_device_tests() {
+    let mut script = Script::two_wans();
+    script
+        .traffic_bps
+        .insert("192.168.1.11".to_string(), (10e6, 0.0));
+    let backends = MockBackends::start(script).await;
+    let instance = Instance::start(
+        &backends.config("[speedtest]\ndevices = [\"192.168.1.10/32\"]\nhold_secs = 10"),
+    );
+
+    let log = backends
+        .wait_for("10 cycles", |log| log.count("/status") >= 10)
+        .await;
+    assert!(log.switches.is_empty(), "{:?}", log.moves());
+    backends.update(|script| {
+        script
+            .traffic_bps
+            .insert("192.168.1.10".to_string(), (5e5, 5e4));
+    });
+    let over = backends.log().count("/status");
+    let log = backends
+        .wait_for("a switch", |log| !log.switches.is_empty())
+        .await;
+    assert!(instance.stop().await.success());
+    // The next busiest client, once the hold after the test has run out
+    assert_eq!(log.moves()[0], ("192.168.1.11", "wan1"));
+    assert!(log.switches[0].cycle >= over + 10, "{:?}", log.switches);
+}
+
+#[tokio::test]
+async fn recognizes_traffic_to_a_speed_test_server() {
+    let mut script = Script::two_wans();
+    script
+        .destinations
+        .push(("192.168.1.10".to_string(), "203.0.113.5".to_string(), 20e6));
+    let backends = MockBackends::start(script).await;
+    let instance = Instance::start_with(
+        &backends.config("[destinations]\n\n[speedtest]\nprefixes = [\"203.0.113.0/28\"]"),
+        &["--output", "json"],
+    );
+
+    let log = backends
+        .wait_for("10 cycles", |log| log.count("/status") >= 10)
+        .await;
+    let (status, output) = instance.stop_with_report().await;
+    assert!(status.success());
+    assert!(log.switches.is_empty(), "{:?}", log.moves());
+    assert!(
+        skipped(&output, "192.168.1.10").contains(
+            &"speed test in progress (192.168.1.10 is testing against 203.0.113.5 (20.00 Mbps))"
+                .to_string()
+        ),
+        "{:?}",
+        skipped(&output, "192.168.1.10")
+    );
+}
+
+#[tokio::test]
+async fn refuses_server_prefixes_without_destination_attribution() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let instance =
+        Instance::start(&backends.config("[speedtest]\nprefixes = [\"203.0.113.0/28\"]"));
+
+    assert!(!instance.wait().await.success());
+    assert_eq!(backends.log().count("/status"), 0);
+}
@@ -0,0 +1,137 @@
+mod common;
+
+use common::{Instance, MockBackends, Script};
+
+/// `[history]` on the Postgres server in `ROUTINGFLOW_TEST_POSTGRES` (a connection string,
+/// e.g. `host=127.0.0.1 user=postgres`), in a schema of the test's own. The Postgres tests
+/// are skipped without it.
+fn postgres(test: &str) -> Option<String> {
+    let url = std::env::var("ROUTINGFLOW_TEST_POSTGRES").ok()?;
+    Some(format!(
+        "[history]\nbackend = \"postgres\"\npostgres_url = \"{}\"\npostgres_schema = \"test_{}_{}\"\n",
+        url,
+        test,
+        std::process::id()
+    ))
+}
+
+/// Cycle of the first webhook event of `kind`, once there is one.
+async fn first_event(backends: &MockBackends, kind: &str) -> usize {
+    backends
+        .wait_for(kind, |log| {
+            log.notifications
+                .iter()
+                .any(|event| event.body["type"] == kind)
+        })
+        .await
+        .notifications
+        .iter()
+        .find(|event| event.body["type"] == kind)
+        .unwrap()
+        .cycle
+}
+
+async fn keeps_traffic_profiles_across_restarts(history: &str) {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let instance = Instance::start(&backends.config(&format!(
+        "{}\n[anomaly]\nwindow_secs = 3600\nmin_baseline_secs = 300\n\n\
+         [[events.webhooks]]\nurl = \"{}/hook\"\nevents = [\"anomaly\"]\n",
+        history, backends.url
+    )));
+    backends
+        .wait_for("30 cycles", |log| log.count("/status") >= 30)
+        .await;
+
+    let instance = instance.restart(400).await;
+    let restarted_at = backends.log().count("/status");
+    backends.update(|script| {
+        script
+            .traffic_bps
+            .insert("192.168.1.12".to_string(), (30e6, 1e6));
+    });
+    // Judged on the baseline learned before the restart, not 300 cycles after it
+    let flagged_at = first_event(&backends, "anomaly").await;
+    assert!(instance.stop().await.success());
+    assert!(
+        flagged_at < restarted_at + 30,
+        "{} {}",
+        flagged_at,
+        restarted_at
+    );
+}
+
+async fn keeps_quota_usage_across_restarts(history: &str) {
+    let mut script = Script::two_wans();
+    script
+        .traffic_bps
+        .insert("192.168.1.12".to_string(), (30e6, 1e6));
+    let backends = MockBackends::start(script).await;
+    let instance = Instance::start(&backends.config(&format!(
+        "{}\n[[quotas]]\nname = \"bulk\"\nprefixes = [\"192.168.1.12/32\"]\nmbps = 10\n\
+         bulk_wan = \"wan0\"\nwindow_secs = 600\n\n\
+         [[events.webhooks]]\nurl = \"{}/hook\"\nevents = [\"quota\"]\n",
+        history, backends.url
+    )));
+    backends
+        .wait_for("30 cycles", |log| log.count("/status") >= 30)
+        .await;
+
+    // The cycles after the first 10 are still within the window
+    let instance = instance.restart(610).await;
+    let restarted_at = backends.log().count("/status");
+    let exceeded_at = first_event(&backends, "quota").await;
+    assert!(instance.stop().await.success());
+    assert!(
+        exceeded_at >= restarted_at,
+        "over quota before a whole window"
+    );
+    assert!(
+        exceeded_at < restarted_at + 30,
+        "{} {}",
+        exceeded_at,
+        restarted_at
+    );
+}
+
+#[tokio::test]
+async fn keeps_traffic_profiles_in_sqlite() {
+    keeps_traffic_profiles_across_restarts("").await;
+}
+
+#[tokio::test]
+async fn keeps_quota_usage_in_sqlite() {
+    keeps_quota_usage_across_restarts("").await;
+}
+
+#[tokio::test]
+async fn keeps_traffic_profiles_in_postgres() {
+    if let Some(history) = postgres("profiles") {
+        keeps_traffic_profiles_across_restarts(&history).await;
+    }
+}
+
+#[tokio::test]
+async fn keeps_quota_usage_in_postgres() {
+    if let Some(history) = postgres("quotas") {
+        keeps_quota_usage_across_restarts(&history).await;
+    }
+}
+
+#[tokio::test]
+async fn records_switches_in_postgres() {
+    let Some(history) = postgres("switches") else {
+        return;
+    };
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let instance = Instance::start(&backends.config(&history));
+    backends
+        .wait_for("a move", |log| !log.moves().is_empty())
+        .await;
+
+    // Unlike SQLite, the server can be read while the instance writes to it
+    let output = instance.command(&["history"]).await;
+    assert!(instance.stop().await.success());
+    assert!(output.status.success(), "{:?}", output);
+    let output = String::from_utf8_lossy(&output.stdout);
+    assert!(output.contains("192.168.1.10"), "{}", output);
+}
@@ -0,0 +1,58 @@
+mod common;
+
+use common::{api_config, free_addr, Api, Instance, MockBackends, Script};
+use serde_json::Value;
+
+#[tokio::test]
+async fn shows_wan_health_without_a_key_until_rate_limited() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let addr = free_addr();
+    let instance = Instance::start(&backends.config(&format!(
+        "{}\n[server.status_page]\nenabled = true\nrequests_per_minute = 4",
+        api_config(addr)
+    )));
+    Api::connect(addr, "admin-key").await;
+    backends
+        .wait_for("a switch", |log| !log.switches.is_empty())
+        .await;
+
+    let client = reqwest::Client::new();
+    let get = |path: &str| client.get(format!("http://{}{}", addr, path)).send();
+    let response = get("/status.json").await.unwrap();
+    assert_eq!(response.status(), 200);
+    let body = response.text().await.unwrap();
+    assert!(!body.contains("192.168.1."), "{}", body);
+    let status: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(status["stale"], false);
+    let wan1 = &status["wans"][1];
+    assert_eq!(wan1["wan"], "wan1");
+    assert_eq!(wan1["nic"], "eth1");
+    assert_eq!(wan1["health"], "ok");
+    assert_eq!(wan1["tcp_bandwidth_bps"], 200e6);
+
+    let page = get("/status").await.unwrap();
+    assert_eq!(page.status(), 200);
+    assert!(page.text().await.unwrap().contains("<td>eth1</td>"));
+    // Four requests a minute, the two above included
+    for _ in 0..2 {
+        assert_eq!(get("/status.json").await.unwrap().status(), 200);
+    }
+    let limited = get("/status.json").await.unwrap();
+    assert!(instance.stop().await.success());
+    assert_eq!(limited.status(), 429);
+    assert_eq!(limited.headers()["retry-after"], "60");
+}
+
+#[tokio::test]
+async fn is_off_by_default() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let addr = free_addr();
+    let instance = Instance::start(&backends.config(&api_config(addr)));
+    Api::connect(addr, "admin-key").await;
+
+    let response = reqwest::get(format!("http://{}/status.json", addr))
+        .await
+        .unwrap();
+    assert!(instance.stop().await.success());
+    assert_eq!(response.status(), 404);
+}
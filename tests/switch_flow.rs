@@ -0,0 +1,98 @@
+mod common;
+
+use common::{Instance, MockBackends, Script};
+
+#[tokio::test]
+async fn moves_the_busiest_client_to_the_wan_with_most_headroom() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let instance = Instance::start(&backends.config(""));
+
+    let log = backends
+        .wait_for("a switch", |log| !log.switches.is_empty())
+        .await;
+    assert!(instance.stop().await.success());
+    assert_eq!(log.moves()[0], ("192.168.1.10", "wan1"));
+}
+
+#[tokio::test]
+async fn leaves_excluded_clients_where_they_are() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let instance = Instance::start(&backends.config("excluded_ips = [\"192.168.1.10\"]"));
+
+    let log = backends
+        .wait_for("20 cycles", |log| log.count("/status") >= 20)
+        .await;
+    assert!(instance.stop().await.success());
+    assert!(log.switches.is_empty());
+}
+
+#[tokio::test]
+async fn never_moves_a_realtime_client_for_bandwidth() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let instance = Instance::start(&backends.config(
+        "[[client_rules]]\nprefix = \"192.168.1.10/32\"\naction = \"realtime\"\n\n[probes]\ntargets = []",
+    ));
+
+    let log = backends
+        .wait_for("20 cycles", |log| log.count("/status") >= 20)
+        .await;
+    assert!(instance.stop().await.success());
+    assert!(log.switches.is_empty());
+}
+
+#[tokio::test]
+async fn pins_clients_by_the_application_class_of_their_traffic() {
+    let mut script = Script::two_wans();
+    script.flows = vec![("192.168.1.12".to_string(), 873, 4e5)];
+    let backends = MockBackends::start(script).await;
+    let instance = Instance::start(&backends.config(
+        "[[client_rules]]\napp_class = \"backup\"\naction = \"pin\"\nwan = \"wan0\"\n\n\
+         [app_classes]\n[[app_classes.classes]]\nname = \"backup\"\nports = [873, \"8000-8010\"]",
+    ));
+
+    backends
+        .wait_for("the backup client on wan0", |log| {
+            log.moves().contains(&("192.168.1.12", "wan0"))
+        })
+        .await;
+    assert!(instance.stop().await.success());
+}
+
+#[tokio::test]
+async fn does_not_switch_without_mappings() {
+    let mut script = Script::two_wans();
+    script.fail_status = true;
+    let backends = MockBackends::start(script).await;
+    let instance = Instance::start(&backends.config(""));
+
+    let log = backends
+        .wait_for("20 cycles", |log| log.count("/status") >= 20)
+        .await;
+    assert!(log.switches.is_empty());
+
+    // Switching starts once the routing service answers again
+    backends.update(|script| script.fail_status = false);
+    let log = backends
+        .wait_for("a switch", |log| !log.switches.is_empty())
+        .await;
+    assert!(instance.stop().await.success());
+    assert_eq!(log.moves()[0], ("192.168.1.10", "wan1"));
+}
+
+#[tokio::test]
+async fn moves_nobody_else_after_a_rejected_switch() {
+    let mut script = Script::two_wans();
+    script.fail_switch = true;
+    let backends = MockBackends::start(script).await;
+    let instance = Instance::start(&backends.config(""));
+
+    let log = backends
+        .wait_for("20 cycles", |log| log.count("/status") >= 20)
+        .await;
+    assert!(instance.stop().await.success());
+    assert!(!log.switches.is_empty());
+    assert!(log
+        .moves()
+        .iter()
+        .all(|moved| *moved == ("192.168.1.10", "wan1")));
+}
@@ -0,0 +1,98 @@
+mod common;
+
+use common::{MockBackends, Script, SIMULATED_START};
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// A directory holding `config` and a bound notification socket, named after `name`.
+fn scratch(name: &str, config: &str) -> (PathBuf, UnixDatagram) {
+    let dir =
+        std::env::temp_dir().join(format!("routingflow-test-{}-{}", std::process::id(), name));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("routingflow.toml"), config).unwrap();
+    let socket = UnixDatagram::bind(dir.join("notify.sock")).unwrap();
+    socket.set_nonblocking(true).unwrap();
+    (dir, socket)
+}
+
+/// Runs the instance in `dir` as systemd would, with `env`, for a few cycles, and returns
+/// what it sent to the notification socket.
+async fn notifications(
+    backends: &MockBackends,
+    dir: &Path,
+    socket: &UnixDatagram,
+    env: &[(&str, &str)],
+) -> Vec<String> {
+    let mut child = tokio::process::Command::new(env!("CARGO_BIN_EXE_routingFlow"))
+        .args(["--quiet", "run", "--simulated-start", SIMULATED_START])
+        .current_dir(dir)
+        .env_remove("ROUTINGFLOW_CONFIG")
+        .env("NOTIFY_SOCKET", dir.join("notify.sock"))
+        .envs(env.iter().copied())
+        .kill_on_drop(true)
+        .spawn()
+        .unwrap();
+    backends
+        .wait_for("3 cycles", |log| log.count("/status") >= 3)
+        .await;
+    unsafe {
+        libc::kill(child.id().unwrap() as libc::pid_t, libc::SIGTERM);
+    }
+    let status = tokio::time::timeout(Duration::from_secs(30), child.wait())
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(status.success());
+
+    let mut received = Vec::new();
+    let mut buffer = [0; 256];
+    while let Ok(len) = socket.recv(&mut buffer) {
+        received.push(String::from_utf8_lossy(&buffer[..len]).into_owned());
+    }
+    received
+}
+
+#[tokio::test]
+async fn reports_readiness_and_pings_the_watchdog_every_cycle() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let (dir, socket) = scratch("systemd-watchdog", &backends.config(""));
+
+    let received = notifications(&backends, &dir, &socket, &[("WATCHDOG_USEC", "60000000")]).await;
+    let cycles = backends.log().count("/status");
+    assert_eq!(received.last().unwrap(), "STOPPING=1", "{:?}", received);
+    assert_eq!(
+        received.iter().filter(|state| *state == "READY=1").count(),
+        1,
+        "{:?}",
+        received
+    );
+    // Pinged at the start of every cycle, ready once the first one has its readings
+    assert_eq!(received[..2], ["WATCHDOG=1", "READY=1"]);
+    let pings = received
+        .iter()
+        .filter(|state| *state == "WATCHDOG=1")
+        .count();
+    assert!(
+        pings >= 3 && pings <= cycles,
+        "{} pings in {} cycles",
+        pings,
+        cycles
+    );
+}
+
+#[tokio::test]
+async fn leaves_a_watchdog_meant_for_another_process_alone() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let (dir, socket) = scratch("systemd-other-pid", &backends.config(""));
+
+    let received = notifications(
+        &backends,
+        &dir,
+        &socket,
+        &[("WATCHDOG_USEC", "60000000"), ("WATCHDOG_PID", "1")],
+    )
+    .await;
+    assert_eq!(received, ["READY=1", "STOPPING=1"]);
+}
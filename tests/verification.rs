@@ -0,0 +1,60 @@
+mod common;
+
+use common::{Instance, MockBackends, Script};
+
+const VERIFICATION: &str = "[verification]\ndelay_ms = 10\nretries = 1";
+
+/// The `history` of the busy client once the cycles after its first switch have begun, with
+/// the switch requests made for it within its cooldown. Verifying re-reads `/status`, which
+/// the fakes count as a cycle of its own.
+async fn history(script: Script) -> (usize, String) {
+    let backends = MockBackends::start(script).await;
+    let instance = Instance::start(&backends.config(VERIFICATION));
+    let log = backends
+        .wait_for("the cycle after a switch", |log| {
+            log.switches
+                .first()
+                .is_some_and(|switch| log.count("/status") > switch.cycle + 5)
+        })
+        .await;
+    let output = instance.command(&["history", "--ip", "192.168.1.10"]).await;
+    assert!(instance.stop().await.success());
+    let cycle = log.switches[0].cycle;
+    let attempts = log
+        .switches
+        .iter()
+        .filter(|switch| switch.cycle < cycle + 5)
+        .count();
+    (
+        attempts,
+        String::from_utf8_lossy(&output.stdout).into_owned(),
+    )
+}
+
+#[tokio::test]
+async fn records_a_switch_the_status_confirms() {
+    let (attempts, history) = history(Script::two_wans()).await;
+    assert_eq!(attempts, 1);
+    assert!(
+        history.contains("192.168.1.10 wan0 → wan1 [success, verified]"),
+        "{}",
+        history
+    );
+}
+
+#[tokio::test]
+async fn reissues_and_flags_a_switch_that_did_not_take_effect() {
+    let mut script = Script::two_wans();
+    script.ignore_switches = true;
+    let (attempts, history) = history(script).await;
+    assert_eq!(attempts, 2);
+    assert!(
+        history
+            .lines()
+            .last()
+            .unwrap()
+            .contains("192.168.1.10 wan0 → wan1 [success, not_in_effect]"),
+        "{}",
+        history
+    );
+}
@@ -0,0 +1,141 @@
+mod common;
+
+use common::{Instance, MockBackends, Script};
+use std::time::{Duration, Instant};
+
+#[tokio::test]
+async fn retries_a_failed_delivery_with_backoff() {
+    let mut script = Script::two_wans();
+    script.fail_notifications = 2;
+    let backends = MockBackends::start(script).await;
+    let started = Instant::now();
+    let instance = Instance::start(&backends.config(&format!(
+        "[[events.webhooks]]\nurl = \"{}/hook\"",
+        backends.url
+    )));
+
+    let log = backends
+        .wait_for("a delivered event", |log| !log.notifications.is_empty())
+        .await;
+    assert!(instance.stop().await.success());
+    // Retried after 1 s and then 2 s
+    assert!(started.elapsed() >= Duration::from_secs(3));
+    let event = &log.notifications[0].body;
+    assert_eq!(event["type"], "switch");
+    assert_eq!(event["ip"], "192.168.1.10");
+    assert_eq!(event["target_wan"], "wan1");
+}
+
+#[tokio::test]
+async fn gives_up_after_the_last_retry() {
+    let mut script = Script::two_wans();
+    script.fail_notifications = 1;
+    let backends = MockBackends::start(script).await;
+    let instance = Instance::start(&backends.config(&format!(
+        "[[events.webhooks]]\nurl = \"{}/hook\"\nmax_retries = 0",
+        backends.url
+    )));
+
+    let log = backends
+        .wait_for("a delivered event", |log| !log.notifications.is_empty())
+        .await;
+    assert!(instance.stop().await.success());
+    // The first switch is lost; the next, after the cooldown, moves the client back
+    let event = &log.notifications[0].body;
+    assert_eq!(event["type"], "switch");
+    assert_eq!(event["target_wan"], "wan0");
+}
+
+#[tokio::test]
+async fn posts_bandwidth_exceeded_events() {
+    let mut script = Script::two_wans();
+    script.bandwidth_bps.insert("eth0".to_string(), 10e6);
+    let backends = MockBackends::start(script).await;
+    let instance = Instance::start(&backends.config(&format!(
+        "excluded_ips = [\"192.168.1.10\"]\n\n\
+         [[events.webhooks]]\nurl = \"{}/hook\"\nevents = [\"bandwidth_exceeded\"]",
+        backends.url
+    )));
+
+    let log = backends
+        .wait_for("an event", |log| !log.notifications.is_empty())
+        .await;
+    assert!(instance.stop().await.success());
+    let event = &log.notifications[0].body;
+    assert_eq!(event["type"], "bandwidth_exceeded", "{}", event);
+    assert_eq!(event["nic"], "eth0");
+    assert_eq!(event["wan"], "wan0");
+    assert_eq!(event["tcp_bandwidth_bps"], 10e6);
+    assert_eq!(event["traffic_bps"], 22.55e6);
+}
+
+#[tokio::test]
+async fn posts_the_built_in_text_of_an_event() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let instance = Instance::start(&backends.config(&format!(
+        "[[events.webhooks]]\nurl = \"{}/hook\"\nformat = \"text\"\nevents = [\"switch\"]",
+        backends.url
+    )));
+
+    let log = backends
+        .wait_for("a delivered event", |log| !log.notifications.is_empty())
+        .await;
+    assert!(instance.stop().await.success());
+    assert_eq!(
+        log.notifications[0].body,
+        serde_json::json!({
+            "text": "Moved 192.168.1.10 from eth0 to wan1 \
+                     (top RX IP on eth0; wan1 has the most headroom (199.45 Mbps free))"
+        })
+    );
+}
+
+#[tokio::test]
+async fn prefers_a_webhooks_own_template_to_the_shared_one() {
+    let mut script = Script::two_wans();
+    script.bandwidth_bps.insert("eth0".to_string(), 10e6);
+    let backends = MockBackends::start(script).await;
+    let instance = Instance::start(&backends.config(&format!(
+        "excluded_ips = [\"192.168.1.10\"]\n\n\
+         [[events.webhooks]]\nurl = \"{url}/hook\"\nformat = \"text\"\ntext_field = \"content\"\n\
+         events = [\"bandwidth_exceeded\"]\n\
+         templates = {{ bandwidth_exceeded = \"{{{{ nic }}}} über {{{{ tcp_bandwidth_bps | mbps }}}} Mbps\" }}\n\n\
+         [[events.webhooks]]\nurl = \"{url}/slack\"\nformat = \"text\"\nevents = [\"bandwidth_exceeded\"]\n\n\
+         [events.templates]\nbandwidth_exceeded = \"{{% if wan %}}{{{{ wan }}}}{{% else %}}?{{% endif %}}: {{{{ traffic_bps | mbps }}}} Mbps\"",
+        url = backends.url
+    )));
+
+    let log = backends
+        .wait_for("both deliveries", |log| log.notifications.len() >= 2)
+        .await;
+    assert!(instance.stop().await.success());
+    let body = |path: &str| {
+        log.notifications
+            .iter()
+            .find(|notification| notification.path == path)
+            .unwrap()
+            .body
+            .clone()
+    };
+    assert_eq!(
+        body("/hook"),
+        serde_json::json!({ "content": "eth0 über 10.00 Mbps" })
+    );
+    assert_eq!(
+        body("/slack"),
+        serde_json::json!({ "text": "wan0: 22.55 Mbps" })
+    );
+}
+
+#[tokio::test]
+async fn refuses_a_template_for_an_unknown_event() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let instance = Instance::start(&backends.config(&format!(
+        "[[events.webhooks]]\nurl = \"{}/hook\"\nformat = \"text\"\n\n\
+         [events.templates]\nswitched = \"{{{{ ip }}}} moved\"",
+        backends.url
+    )));
+
+    assert!(!instance.wait().await.success());
+    assert_eq!(backends.log().count("/status"), 0);
+}
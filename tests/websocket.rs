@@ -0,0 +1,90 @@
+mod common;
+
+use common::{api_config, free_addr, Api, Instance, MockBackends, Script};
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// Reads one server frame (servers never mask) as opcode and payload.
+async fn read_frame(socket: &mut TcpStream) -> (u8, Vec<u8>) {
+    let mut header = [0u8; 2];
+    socket.read_exact(&mut header).await.unwrap();
+    let length = match header[1] & 0x7f {
+        126 => socket.read_u16().await.unwrap() as usize,
+        127 => socket.read_u64().await.unwrap() as usize,
+        length => length as usize,
+    };
+    let mut payload = vec![0u8; length];
+    socket.read_exact(&mut payload).await.unwrap();
+    (header[0] & 0x0f, payload)
+}
+
+/// Opens a WebSocket on `/ws?{query}`, returning the socket and the response head.
+async fn upgrade(addr: SocketAddr, query: &str) -> (TcpStream, String) {
+    let mut socket = TcpStream::connect(addr).await.unwrap();
+    socket
+        .write_all(
+            format!(
+                "GET /ws?{} HTTP/1.1\r\n\
+                 Host: localhost\r\n\
+                 Connection: Upgrade\r\n\
+                 Upgrade: websocket\r\n\
+                 Sec-WebSocket-Version: 13\r\n\
+                 Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n",
+                query
+            )
+            .as_bytes(),
+        )
+        .await
+        .unwrap();
+    let mut handshake = Vec::new();
+    while !handshake.ends_with(b"\r\n\r\n") {
+        handshake.push(socket.read_u8().await.unwrap());
+    }
+    (socket, String::from_utf8(handshake).unwrap())
+}
+
+#[tokio::test]
+async fn pushes_events_over_a_websocket() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let addr = free_addr();
+    let instance = Instance::start(&backends.config(&api_config(addr)));
+    let api = Api::connect(addr, "admin-key").await;
+    assert_eq!(api.get("/ws").await.0, 400, "not an upgrade");
+
+    let (mut socket, handshake) = upgrade(addr, "events=switch&api_key=admin-key").await;
+    assert!(handshake.starts_with("HTTP/1.1 101"), "{}", handshake);
+    assert!(
+        handshake.contains("s3pPLMBiTxaQ9kYGzzhZRbK+xOo="),
+        "{}",
+        handshake
+    );
+
+    let event = tokio::time::timeout(Duration::from_secs(30), async {
+        loop {
+            match read_frame(&mut socket).await {
+                (0x1, payload) => break payload,
+                (0x9, _) => continue,
+                (opcode, _) => panic!("unexpected opcode {:#x}", opcode),
+            }
+        }
+    })
+    .await
+    .expect("a switch event");
+    let event: serde_json::Value = serde_json::from_slice(&event).unwrap();
+    assert_eq!(event["type"], "switch");
+    assert!(instance.stop().await.success());
+}
+
+#[tokio::test]
+async fn refuses_a_websocket_without_a_key() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let addr = free_addr();
+    let instance = Instance::start(&backends.config(&api_config(addr)));
+    Api::connect(addr, "admin-key").await;
+
+    let (_, handshake) = upgrade(addr, "events=switch").await;
+    assert!(instance.stop().await.success());
+    assert!(handshake.starts_with("HTTP/1.1 401"), "{}", handshake);
+}
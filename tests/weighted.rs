@@ -0,0 +1,74 @@
+mod common;
+
+use common::{Instance, MockBackends, Script};
+use serde_json::Value;
+
+#[tokio::test]
+async fn moves_clients_until_the_shares_match_the_weights() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let instance = Instance::start_with(
+        &backends.config("policy = \"weighted\"\n\n[weighted]\nweights = { wan0 = 1, wan1 = 2 }"),
+        &["--output", "json"],
+    );
+
+    let log = backends
+        .wait_for("a switch", |log| !log.switches.is_empty())
+        .await;
+    let cycles = log.count("/status");
+    let log = backends
+        .wait_for("10 more cycles", |log| log.count("/status") >= cycles + 10)
+        .await;
+    let (status, output) = instance.stop_with_report().await;
+    assert!(status.success());
+    // One client of two on wan0 makes up the gap; the quieter of the two goes
+    assert_eq!(log.moves(), [("192.168.1.11", "wan1")]);
+    let switch = output
+        .lines()
+        .map(|line| serde_json::from_str::<Value>(line).unwrap())
+        .flat_map(|report| report["decisions"].as_array().unwrap().clone())
+        .find(|decision| decision["outcome"] == "switched")
+        .unwrap();
+    assert_eq!(
+        switch["reason"],
+        "wan0 has 67% of clients (target 33%); wan1 has 33% (target 67%)"
+    );
+}
+
+#[tokio::test]
+async fn tolerates_a_deviation_within_the_tolerance() {
+    let backends = MockBackends::start(Script::two_wans()).await;
+    let instance = Instance::start(&backends.config(
+        "policy = \"weighted\"\n\n[weighted]\nweights = { wan0 = 1, wan1 = 1 }\ntolerance = 0.2",
+    ));
+
+    // 67% against 50% of the clients
+    let log = backends
+        .wait_for("10 cycles", |log| log.count("/status") >= 10)
+        .await;
+    assert!(instance.stop().await.success());
+    assert!(log.switches.is_empty(), "{:?}", log.moves());
+}
+
+#[tokio::test]
+async fn balances_traffic_shares() {
+    let mut script = Script::two_wans();
+    script
+        .traffic_bps
+        .insert("192.168.1.11".to_string(), (4e6, 0.0));
+    let backends = MockBackends::start(script).await;
+    let instance = Instance::start(&backends.config(
+        "policy = \"weighted\"\n\n[weighted]\nweights = { wan0 = 1, wan1 = 1 }\nshare_by = \"traffic\"",
+    ));
+
+    let log = backends
+        .wait_for("a switch", |log| !log.switches.is_empty())
+        .await;
+    let cycles = log.count("/status");
+    let log = backends
+        .wait_for("10 more cycles", |log| log.count("/status") >= cycles + 10)
+        .await;
+    assert!(instance.stop().await.success());
+    // 26 against 0.55 Mbps: the 4 Mbps client is closer to the gap than the 22 Mbps one.
+    // 22 against 4.55 Mbps then: moving the busy client would make wan1 the over-weight WAN
+    assert_eq!(log.moves(), [("192.168.1.11", "wan1")]);
+}